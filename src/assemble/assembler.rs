@@ -0,0 +1,727 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::decode::{find_opcode, is_mnemonic, AddressingMode};
+
+use super::{
+    conditional::ConditionalStack,
+    expression::Expr,
+    object::{ObjectFile, ObjectSegment, Relocation, RelocationKind, SymbolValue, Verification},
+    parser::{parse_line, AddressSize, DataItem, Line, Operand, Statement},
+    AssembleError,
+};
+
+/// segment used until the first `.segment` directive, same as ca65
+const DEFAULT_SEGMENT: &str = "CODE";
+
+/// labels in this segment are assumed to fit in a byte, same as ca65
+const ZERO_PAGE_SEGMENT: &str = "ZEROPAGE";
+
+/// inverted branch over a `jmp`, see [`Assembler::with_long_branches`]
+const LONG_BRANCH_LEN: i64 = 5;
+const JMP_ABS: u8 = 0x4c;
+
+/// The branch taken when `mnemonic` isn't.
+fn inverted_branch(mnemonic: &str) -> Option<&'static str> {
+    return match mnemonic {
+        "bpl" => Option::Some("bmi"),
+        "bmi" => Option::Some("bpl"),
+        "bvc" => Option::Some("bvs"),
+        "bvs" => Option::Some("bvc"),
+        "bcc" => Option::Some("bcs"),
+        "bcs" => Option::Some("bcc"),
+        "bne" => Option::Some("beq"),
+        "beq" => Option::Some("bne"),
+        _ => Option::None,
+    };
+}
+
+/// Runs `build`, which assembles with [`Assembler::with_long_branches`], again
+/// with every branch it reports as out of range made long, until every branch
+/// reaches. Without `long_branches` the first error is returned.
+pub fn relax_branches<T>(
+    long_branches: bool,
+    mut build: impl FnMut(&HashSet<usize>) -> Result<T, AssembleError>,
+) -> Result<T, AssembleError> {
+    let mut lines = HashSet::new();
+    loop {
+        match build(&lines) {
+            Result::Err(AssembleError::BranchOutOfRange(line_no, _, _))
+                if long_branches && lines.insert(line_no) => {}
+            result => return result,
+        }
+    }
+}
+
+struct PlannedLine<'a> {
+    line_no: usize,
+    line: &'a Line,
+    mode: Option<AddressingMode>,
+}
+
+#[derive(Default)]
+struct SegmentState {
+    offset: i64,
+    /// set by `.org`, the absolute address of offset 0 in the segment
+    absolute_base: Option<i64>,
+}
+
+/// Tracks the active segment and the position within every segment seen so far.
+struct Location {
+    current: String,
+    order: Vec<String>,
+    segments: HashMap<String, SegmentState>,
+}
+
+impl Location {
+    fn new() -> Location {
+        let mut location = Location {
+            current: String::new(),
+            order: Vec::new(),
+            segments: HashMap::new(),
+        };
+        location.switch_to(DEFAULT_SEGMENT);
+        return location;
+    }
+
+    fn switch_to(&mut self, segment: &str) {
+        if !self.segments.contains_key(segment) {
+            self.order.push(segment.to_string());
+            self.segments
+                .insert(segment.to_string(), SegmentState::default());
+        }
+        self.current = segment.to_string();
+    }
+
+    fn state(&mut self) -> &mut SegmentState {
+        return self.segments.get_mut(&self.current).unwrap();
+    }
+
+    fn pc(&self) -> SymbolValue {
+        let state = &self.segments[&self.current];
+        return match state.absolute_base {
+            Option::Some(base) => SymbolValue::Absolute(base + state.offset),
+            Option::None => SymbolValue::Relative(self.current.clone(), state.offset),
+        };
+    }
+
+    fn org(&mut self, addr: i64) {
+        let state = self.state();
+        state.absolute_base = Option::Some(addr - state.offset);
+    }
+
+    fn advance(&mut self, len: i64) {
+        self.state().offset += len;
+    }
+}
+
+pub struct Assembler {
+    constants: HashMap<String, i64>,
+    labels: HashMap<String, SymbolValue>,
+    /// `.org` addresses to use instead of the ones in the source, by segment
+    org_overrides: HashMap<String, i64>,
+    /// lines whose branch is assembled as the inverted branch over a `jmp`
+    long_branches: HashSet<usize>,
+    /// bytes strings are written with instead of their characters, see `.charmap`
+    charmap: HashMap<u8, u8>,
+}
+
+impl Assembler {
+    pub fn new(defines: HashMap<String, i64>) -> Assembler {
+        return Assembler {
+            constants: defines,
+            labels: HashMap::new(),
+            org_overrides: HashMap::new(),
+            long_branches: HashSet::new(),
+            charmap: HashMap::new(),
+        };
+    }
+
+    /// Moves the code of `segment` to `addr`, used to relocate patches that outgrow
+    /// the place they were written for.
+    pub fn with_org_override(mut self, segment: &str, addr: i64) -> Assembler {
+        self.org_overrides.insert(segment.to_string(), addr);
+        return self;
+    }
+
+    /// Assembles the branches on `lines` as the inverted branch over a `jmp` to the
+    /// target so they reach any address, see [`relax_branches`].
+    pub fn with_long_branches(mut self, lines: &HashSet<usize>) -> Assembler {
+        self.long_branches = lines.clone();
+        return self;
+    }
+
+    pub fn assemble(&mut self, source: &str) -> Result<ObjectFile, AssembleError> {
+        let mut lines = Vec::new();
+        for (i, text) in source.lines().enumerate() {
+            let line = parse_line(text).map_err(|err| AssembleError::SyntaxError(i + 1, err))?;
+            lines.push((i + 1, line));
+        }
+
+        let planned = self.pass1(&lines)?;
+        return self.pass2(&planned);
+    }
+
+    /// Resolves conditional blocks, assigns label addresses and picks the addressing
+    /// mode (and therefore the size) of every instruction.
+    fn pass1<'a>(
+        &mut self,
+        lines: &'a [(usize, Line)],
+    ) -> Result<Vec<PlannedLine<'a>>, AssembleError> {
+        let mut planned = Vec::new();
+        let mut conditionals = ConditionalStack::new();
+        let mut location = Location::new();
+
+        for (line_no, line) in lines {
+            let line_no = *line_no;
+            match &line.statement {
+                Option::Some(Statement::If(e)) => {
+                    let condition =
+                        conditionals.is_active() && self.eval(line_no, e, &location.pc())? != 0;
+                    conditionals.push_if(line_no, condition);
+                    continue;
+                }
+                Option::Some(Statement::IfDef(name)) => {
+                    conditionals.push_if(line_no, self.is_defined(name));
+                    continue;
+                }
+                Option::Some(Statement::IfNDef(name)) => {
+                    conditionals.push_if(line_no, !self.is_defined(name));
+                    continue;
+                }
+                Option::Some(Statement::ElseIf(e)) => {
+                    let condition = conditionals.needs_else_if_condition()
+                        && self.eval(line_no, e, &location.pc())? != 0;
+                    conditionals
+                        .else_if(condition)
+                        .map_err(|err| AssembleError::ConditionalError(line_no, err))?;
+                    continue;
+                }
+                Option::Some(Statement::Else) => {
+                    conditionals
+                        .else_()
+                        .map_err(|err| AssembleError::ConditionalError(line_no, err))?;
+                    continue;
+                }
+                Option::Some(Statement::EndIf) => {
+                    conditionals
+                        .end_if()
+                        .map_err(|err| AssembleError::ConditionalError(line_no, err))?;
+                    continue;
+                }
+                _ => {}
+            }
+
+            if !conditionals.is_active() {
+                continue;
+            }
+
+            if let Option::Some(Statement::Segment(name)) = &line.statement {
+                location.switch_to(name);
+            }
+
+            let label = match &line.statement {
+                Option::Some(Statement::Proc(name)) => Option::Some(name),
+                _ => line.label.as_ref(),
+            };
+            if let Option::Some(label) = label {
+                if self.is_defined(label) {
+                    return Result::Err(AssembleError::DuplicateSymbol(line_no, label.clone()));
+                }
+                self.labels.insert(label.clone(), location.pc());
+            }
+
+            let mut mode = Option::None;
+            match &line.statement {
+                Option::Some(Statement::Define(name, e)) => {
+                    // forward references are resolved in the second pass
+                    if let Result::Ok(v) = self.eval(line_no, e, &location.pc()) {
+                        if self.is_defined(name) {
+                            return Result::Err(AssembleError::DuplicateSymbol(
+                                line_no,
+                                name.clone(),
+                            ));
+                        }
+                        self.constants.insert(name.clone(), v);
+                    }
+                }
+                Option::Some(Statement::Label(name, e)) => {
+                    if self.is_defined(name) {
+                        return Result::Err(AssembleError::DuplicateSymbol(line_no, name.clone()));
+                    }
+                    let v = self.label_value(line_no, e, &location.pc())?;
+                    self.labels.insert(name.clone(), v);
+                }
+                Option::Some(Statement::Org(e)) => {
+                    let addr = self.org_addr(line_no, e, &location)?;
+                    location.org(addr);
+                }
+                Option::Some(Statement::Byte(items)) => {
+                    for item in items {
+                        location.advance(match item {
+                            DataItem::String(s) => s.len() as i64,
+                            DataItem::Expr(_) => 1,
+                        });
+                    }
+                }
+                Option::Some(Statement::Word(items)) => location.advance(2 * items.len() as i64),
+                Option::Some(Statement::Res(count, _)) => {
+                    let count = self.eval(line_no, count, &location.pc())?;
+                    if count < 0 {
+                        return Result::Err(AssembleError::ValueOutOfRange(line_no, count));
+                    }
+                    location.advance(count);
+                }
+                Option::Some(Statement::Instruction(mnemonic, operand)) => {
+                    let m = self.select_mode(line_no, mnemonic, operand, &location.pc())?;
+                    if m == AddressingMode::Relative && self.long_branches.contains(&line_no) {
+                        location.advance(LONG_BRANCH_LEN);
+                    } else {
+                        location.advance(1 + m.operand_len() as i64);
+                    }
+                    mode = Option::Some(m);
+                }
+                _ => {}
+            }
+
+            planned.push(PlannedLine {
+                line_no,
+                line,
+                mode,
+            });
+        }
+
+        if let Option::Some(line_no) = conditionals.open_block_line() {
+            return Result::Err(AssembleError::UnterminatedConditional(line_no));
+        }
+
+        return Result::Ok(planned);
+    }
+
+    fn pass2(&mut self, planned: &[PlannedLine]) -> Result<ObjectFile, AssembleError> {
+        let mut location = Location::new();
+        let mut segments: HashMap<String, ObjectSegment> = HashMap::new();
+        let mut free_blocks = Vec::new();
+        // segment and offset of the last `.verify`
+        let mut last_verify = Option::None;
+
+        for p in planned {
+            let line_no = p.line_no;
+            if let Option::Some(Statement::Segment(name)) = &p.line.statement {
+                location.switch_to(name);
+            }
+
+            let pc = location.pc();
+            let segment =
+                segments
+                    .entry(location.current.clone())
+                    .or_insert_with(|| ObjectSegment {
+                        name: location.current.clone(),
+                        org: Option::None,
+                        data: Vec::new(),
+                        relocations: Vec::new(),
+                        verifications: Vec::new(),
+                    });
+            let start = segment.data.len();
+
+            match &p.line.statement {
+                Option::Some(Statement::Define(name, e)) => {
+                    let v = self.eval(line_no, e, &pc)?;
+                    self.constants.insert(name.clone(), v);
+                }
+                Option::Some(Statement::Org(e)) => {
+                    let addr = self.org_addr(line_no, e, &location)?;
+                    location.org(addr);
+                }
+                Option::Some(Statement::Free(start, end)) => {
+                    let start = self.eval(line_no, start, &pc)?;
+                    let end = self.eval(line_no, end, &pc)?;
+                    if end < start {
+                        return Result::Err(AssembleError::ValueOutOfRange(line_no, end));
+                    }
+                    free_blocks.push((start, end));
+                }
+                Option::Some(Statement::Verify(items)) => {
+                    let mut bytes = Vec::new();
+                    for e in items {
+                        let v = self.eval(line_no, e, &pc)?;
+                        bytes.push(
+                            RelocationKind::Byte
+                                .encode(v, 0)
+                                .map_err(|v| AssembleError::ValueOutOfRange(line_no, v))?[0],
+                        );
+                    }
+                    // one right after another carries on where it ends, one further
+                    // on, e.g. at a label inside a routine that is verified as a
+                    // whole, starts where it is
+                    let here = (location.current.clone(), start);
+                    let continues = last_verify.as_ref() == Option::Some(&here);
+                    let offset = segment
+                        .verifications
+                        .last()
+                        .map(|v| v.offset + v.bytes.len())
+                        .filter(|_| continues)
+                        .unwrap_or(start);
+                    last_verify = Option::Some(here);
+                    segment.verifications.push(Verification {
+                        offset,
+                        bytes,
+                        line_no,
+                    });
+                }
+                Option::Some(Statement::Charmap(c, byte)) => {
+                    let c = self.eval(line_no, c, &pc)?;
+                    let byte = self.eval(line_no, byte, &pc)?;
+                    for v in [c, byte] {
+                        if !(0..=0xff).contains(&v) {
+                            return Result::Err(AssembleError::ValueOutOfRange(line_no, v));
+                        }
+                    }
+                    self.charmap.insert(c as u8, byte as u8);
+                }
+                Option::Some(Statement::Byte(items)) => {
+                    for item in items {
+                        match item {
+                            DataItem::String(s) => segment.data.extend(
+                                s.bytes()
+                                    .map(|b| self.charmap.get(&b).copied().unwrap_or(b)),
+                            ),
+                            DataItem::Expr(e) => {
+                                self.emit(segment, line_no, e, RelocationKind::Byte, &pc)?
+                            }
+                        }
+                    }
+                }
+                Option::Some(Statement::Word(items)) => {
+                    for e in items {
+                        self.emit(segment, line_no, e, RelocationKind::Word, &pc)?;
+                    }
+                }
+                Option::Some(Statement::Res(count, fill)) => {
+                    let count = self.eval(line_no, count, &pc)?;
+                    let fill = match fill {
+                        Option::Some(fill) => {
+                            let v = self.eval(line_no, fill, &pc)?;
+                            RelocationKind::Byte
+                                .encode(v, 0)
+                                .map_err(|v| AssembleError::ValueOutOfRange(line_no, v))?[0]
+                        }
+                        Option::None => 0,
+                    };
+                    segment
+                        .data
+                        .resize(segment.data.len() + count as usize, fill);
+                }
+                Option::Some(Statement::Instruction(mnemonic, operand)) => {
+                    if let Option::Some(mode) = p.mode {
+                        self.encode(segment, line_no, mnemonic, operand, mode, &pc)?;
+                    }
+                }
+                _ => {}
+            }
+
+            let len = (segment.data.len() - start) as i64;
+            location.advance(len);
+        }
+
+        let mut symbols = HashMap::new();
+        for (name, v) in &self.constants {
+            symbols.insert(name.clone(), SymbolValue::Absolute(*v));
+        }
+        for (name, v) in &self.labels {
+            symbols.insert(name.clone(), v.clone());
+        }
+
+        for segment in segments.values_mut() {
+            segment.org = location.segments[&segment.name].absolute_base;
+        }
+
+        return Result::Ok(ObjectFile {
+            segments: location
+                .order
+                .iter()
+                .filter_map(|name| segments.remove(name))
+                .collect(),
+            symbols,
+            free_blocks,
+        });
+    }
+
+    fn org_addr(
+        &self,
+        line_no: usize,
+        e: &Expr,
+        location: &Location,
+    ) -> Result<i64, AssembleError> {
+        if let Option::Some(addr) = self.org_overrides.get(&location.current) {
+            return Result::Ok(*addr);
+        }
+        return self.eval(line_no, e, &location.pc());
+    }
+
+    fn is_defined(&self, name: &str) -> bool {
+        return self.constants.contains_key(name) || self.labels.contains_key(name);
+    }
+
+    fn absolute_value(&self, name: &str) -> Option<i64> {
+        if let Option::Some(v) = self.constants.get(name) {
+            return Option::Some(*v);
+        }
+        if let Option::Some(SymbolValue::Absolute(v)) = self.labels.get(name) {
+            return Option::Some(*v);
+        }
+        return Option::None;
+    }
+
+    /// true if the value of `e` depends on where the linker places a segment
+    fn is_relocatable(&self, e: &Expr, pc: &SymbolValue) -> bool {
+        return e.any(&|e| match e {
+            Expr::Symbol(name) => matches!(
+                self.labels.get(name),
+                Option::Some(SymbolValue::Relative(_, _))
+            ),
+            Expr::CurrentAddress => matches!(pc, SymbolValue::Relative(_, _)),
+            _ => false,
+        });
+    }
+
+    /// Evaluates an expression that has to be known at assembly time.
+    fn eval(&self, line_no: usize, e: &Expr, pc: &SymbolValue) -> Result<i64, AssembleError> {
+        if self.is_relocatable(e, pc) {
+            return Result::Err(AssembleError::NotConstant(line_no));
+        }
+        let pc = match pc {
+            SymbolValue::Absolute(v) => *v,
+            SymbolValue::Relative(_, v) => *v,
+        };
+        return e
+            .eval_with(&|name| self.absolute_value(name), pc)
+            .map_err(|err| AssembleError::ExprError(line_no, err));
+    }
+
+    /// The address `name := e` gives a label, relative to the segment like `*` if
+    /// `e` only uses `*` and constants.
+    fn label_value(
+        &self,
+        line_no: usize,
+        e: &Expr,
+        pc: &SymbolValue,
+    ) -> Result<SymbolValue, AssembleError> {
+        let uses_pc = e.any(&|e| matches!(e, Expr::CurrentAddress));
+        return match pc {
+            SymbolValue::Relative(segment, offset) if uses_pc => {
+                let fixed = SymbolValue::Absolute(*offset);
+                let v = self.eval(line_no, e, &fixed)?;
+                Result::Ok(SymbolValue::Relative(segment.clone(), v))
+            }
+            _ => self.eval(line_no, e, pc).map(SymbolValue::Absolute),
+        };
+    }
+
+    /// Appends the encoded value of `e`, leaving a relocation for the linker if the
+    /// value is not known until segments are placed. Undefined symbols are left for
+    /// the linker too since it defines symbols such as `__DATA_LOAD__`.
+    fn emit(
+        &self,
+        segment: &mut ObjectSegment,
+        line_no: usize,
+        e: &Expr,
+        kind: RelocationKind,
+        pc: &SymbolValue,
+    ) -> Result<(), AssembleError> {
+        let undefined = e.any(&|e| matches!(e, Expr::Symbol(name) if !self.is_defined(name)));
+        if undefined || self.is_relocatable(e, pc) {
+            segment.relocations.push(Relocation {
+                offset: segment.data.len(),
+                kind,
+                expr: e.clone(),
+                pc: pc.clone(),
+                line_no,
+            });
+            segment.data.resize(segment.data.len() + kind.len(), 0);
+            return Result::Ok(());
+        }
+
+        let v = self.eval(line_no, e, pc)?;
+        let pc = match pc {
+            SymbolValue::Absolute(v) => *v,
+            SymbolValue::Relative(_, v) => *v,
+        };
+        let bytes = kind.encode_expr(e, v, pc, line_no)?;
+        segment.data.extend(bytes);
+        return Result::Ok(());
+    }
+
+    fn select_mode(
+        &self,
+        line_no: usize,
+        mnemonic: &str,
+        operand: &Operand,
+        pc: &SymbolValue,
+    ) -> Result<AddressingMode, AssembleError> {
+        if !is_mnemonic(mnemonic) {
+            return Result::Err(AssembleError::InvalidInstruction(
+                line_no,
+                format!("unknown instruction \"{}\"", mnemonic),
+            ));
+        }
+
+        let mode = match operand {
+            Operand::Implied => {
+                if find_opcode(mnemonic, AddressingMode::Implied).is_some() {
+                    AddressingMode::Implied
+                } else {
+                    AddressingMode::Accumulator
+                }
+            }
+            Operand::Accumulator => AddressingMode::Accumulator,
+            Operand::Immediate(_) => AddressingMode::Immediate,
+            Operand::Direct(e, size) => {
+                if find_opcode(mnemonic, AddressingMode::Relative).is_some() {
+                    AddressingMode::Relative
+                } else {
+                    self.select_size(
+                        mnemonic,
+                        e,
+                        size,
+                        pc,
+                        AddressingMode::ZeroPage,
+                        AddressingMode::Absolute,
+                    )
+                }
+            }
+            Operand::DirectX(e, size) => self.select_size(
+                mnemonic,
+                e,
+                size,
+                pc,
+                AddressingMode::ZeroPageX,
+                AddressingMode::AbsoluteX,
+            ),
+            Operand::DirectY(e, size) => self.select_size(
+                mnemonic,
+                e,
+                size,
+                pc,
+                AddressingMode::ZeroPageY,
+                AddressingMode::AbsoluteY,
+            ),
+            Operand::Indirect(_) => AddressingMode::Indirect,
+            Operand::IndirectX(_) => AddressingMode::IndirectX,
+            Operand::IndirectY(_) => AddressingMode::IndirectY,
+        };
+
+        if find_opcode(mnemonic, mode).is_none() {
+            return Result::Err(AssembleError::InvalidInstruction(
+                line_no,
+                format!(
+                    "addressing mode {:?} not supported by \"{}\"",
+                    mode, mnemonic
+                ),
+            ));
+        }
+        return Result::Ok(mode);
+    }
+
+    /// Zero page is only chosen if the operand is already known (no forward
+    /// reference) and fits, so the size picked here is stable across both passes.
+    fn select_size(
+        &self,
+        mnemonic: &str,
+        e: &Expr,
+        size: &Option<AddressSize>,
+        pc: &SymbolValue,
+        zp_mode: AddressingMode,
+        abs_mode: AddressingMode,
+    ) -> AddressingMode {
+        let zp = match size {
+            Option::Some(AddressSize::ZeroPage) => true,
+            Option::Some(AddressSize::Absolute) => false,
+            Option::None => {
+                let pc = match pc {
+                    SymbolValue::Absolute(v) => *v,
+                    SymbolValue::Relative(_, _) => 0x10000,
+                };
+                // zero page labels are not placed yet but will always fit
+                let lookup = |name: &str| match self.labels.get(name) {
+                    Option::Some(SymbolValue::Relative(segment, _))
+                        if segment == ZERO_PAGE_SEGMENT =>
+                    {
+                        Option::Some(0)
+                    }
+                    _ => self.absolute_value(name),
+                };
+                matches!(e.eval_with(&lookup, pc), Result::Ok(v) if (0..=0xff).contains(&v))
+            }
+        };
+        if zp && find_opcode(mnemonic, zp_mode).is_some() {
+            return zp_mode;
+        }
+        return abs_mode;
+    }
+
+    fn encode(
+        &self,
+        segment: &mut ObjectSegment,
+        line_no: usize,
+        mnemonic: &str,
+        operand: &Operand,
+        mode: AddressingMode,
+        pc: &SymbolValue,
+    ) -> Result<(), AssembleError> {
+        if mode == AddressingMode::Relative && self.long_branches.contains(&line_no) {
+            return self.encode_long_branch(segment, line_no, mnemonic, operand, pc);
+        }
+        let opcode = find_opcode(mnemonic, mode).ok_or_else(|| {
+            AssembleError::InvalidInstruction(
+                line_no,
+                format!("unknown instruction \"{}\"", mnemonic),
+            )
+        })?;
+        segment.data.push(opcode);
+
+        let e = match operand {
+            Operand::Implied | Operand::Accumulator => return Result::Ok(()),
+            Operand::Immediate(e)
+            | Operand::Direct(e, _)
+            | Operand::DirectX(e, _)
+            | Operand::DirectY(e, _)
+            | Operand::Indirect(e)
+            | Operand::IndirectX(e)
+            | Operand::IndirectY(e) => e,
+        };
+
+        let kind = match mode {
+            AddressingMode::Relative => RelocationKind::Relative,
+            _ if mode.operand_len() == 1 => RelocationKind::Byte,
+            _ => RelocationKind::Word,
+        };
+        return self.emit(segment, line_no, e, kind, pc);
+    }
+
+    /// `beq target` as `bne *+5; jmp target`.
+    fn encode_long_branch(
+        &self,
+        segment: &mut ObjectSegment,
+        line_no: usize,
+        mnemonic: &str,
+        operand: &Operand,
+        pc: &SymbolValue,
+    ) -> Result<(), AssembleError> {
+        let opcode = inverted_branch(mnemonic)
+            .and_then(|inverted| find_opcode(inverted, AddressingMode::Relative))
+            .ok_or_else(|| {
+                AssembleError::InvalidInstruction(
+                    line_no,
+                    format!("\"{}\" can't be made a long branch", mnemonic),
+                )
+            })?;
+        let e = match operand {
+            Operand::Direct(e, _) => e,
+            _ => unreachable!("branches only take a direct operand"),
+        };
+        segment
+            .data
+            .extend([opcode, (LONG_BRANCH_LEN - 2) as u8, JMP_ABS]);
+        return self.emit(segment, line_no, e, RelocationKind::Word, pc);
+    }
+}
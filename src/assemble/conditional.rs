@@ -0,0 +1,106 @@
+use std::fmt;
+
+#[derive(Debug, PartialEq)]
+pub enum ConditionalError {
+    ElseWithoutIf,
+    ElseIfWithoutIf,
+    EndIfWithoutIf,
+    ElseAfterElse,
+    ElseIfAfterElse,
+}
+
+impl fmt::Display for ConditionalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConditionalError::ElseWithoutIf => write!(f, ".else without .if"),
+            ConditionalError::ElseIfWithoutIf => write!(f, ".elseif without .if"),
+            ConditionalError::EndIfWithoutIf => write!(f, ".endif without .if"),
+            ConditionalError::ElseAfterElse => write!(f, "duplicate .else"),
+            ConditionalError::ElseIfAfterElse => write!(f, ".elseif after .else"),
+        }
+    }
+}
+
+struct Frame {
+    line: usize,
+    parent_active: bool,
+    branch_taken: bool,
+    active: bool,
+    seen_else: bool,
+}
+
+/// Tracks nested `.if`/`.ifdef`/`.else`/`.endif` blocks while walking the source.
+pub struct ConditionalStack {
+    frames: Vec<Frame>,
+}
+
+impl ConditionalStack {
+    pub fn new() -> ConditionalStack {
+        return ConditionalStack { frames: Vec::new() };
+    }
+
+    /// true if lines at the current position should be assembled
+    pub fn is_active(&self) -> bool {
+        return self.frames.last().map(|f| f.active).unwrap_or(true);
+    }
+
+    /// line number of the innermost open block, used to report a missing `.endif`
+    pub fn open_block_line(&self) -> Option<usize> {
+        return self.frames.last().map(|f| f.line);
+    }
+
+    pub fn push_if(&mut self, line: usize, condition: bool) {
+        let parent_active = self.is_active();
+        self.frames.push(Frame {
+            line,
+            parent_active,
+            branch_taken: parent_active && condition,
+            active: parent_active && condition,
+            seen_else: false,
+        });
+    }
+
+    /// true if an `.elseif` at this point has to evaluate its condition, i.e. the
+    /// enclosing block is active and no earlier branch was taken
+    pub fn needs_else_if_condition(&self) -> bool {
+        return self
+            .frames
+            .last()
+            .map(|f| f.parent_active && !f.branch_taken)
+            .unwrap_or(false);
+    }
+
+    pub fn else_if(&mut self, condition: bool) -> Result<(), ConditionalError> {
+        let frame = match self.frames.last_mut() {
+            Option::Some(frame) => frame,
+            Option::None => return Result::Err(ConditionalError::ElseIfWithoutIf),
+        };
+        if frame.seen_else {
+            return Result::Err(ConditionalError::ElseIfAfterElse);
+        }
+        frame.active = frame.parent_active && !frame.branch_taken && condition;
+        frame.branch_taken = frame.branch_taken || frame.active;
+        return Result::Ok(());
+    }
+
+    pub fn else_(&mut self) -> Result<(), ConditionalError> {
+        let frame = match self.frames.last_mut() {
+            Option::Some(frame) => frame,
+            Option::None => return Result::Err(ConditionalError::ElseWithoutIf),
+        };
+        if frame.seen_else {
+            return Result::Err(ConditionalError::ElseAfterElse);
+        }
+        frame.seen_else = true;
+        frame.active = frame.parent_active && !frame.branch_taken;
+        frame.branch_taken = true;
+        return Result::Ok(());
+    }
+
+    pub fn end_if(&mut self) -> Result<(), ConditionalError> {
+        return match self.frames.pop() {
+            Option::Some(_) => Result::Ok(()),
+            Option::None => Result::Err(ConditionalError::EndIfWithoutIf),
+        };
+    }
+}
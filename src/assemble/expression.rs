@@ -0,0 +1,338 @@
+use std::{collections::HashMap, fmt};
+
+use nom::{
+    branch::alt,
+    bytes::complete::{tag, tag_no_case},
+    character::complete::{
+        alpha1, alphanumeric1, anychar, char, digit1, hex_digit1, one_of, space0,
+    },
+    combinator::{map, map_res, not, recognize, value},
+    error::{context, VerboseError},
+    multi::{many0, many1},
+    sequence::{delimited, pair, preceded, terminated},
+    IResult,
+};
+use nom_supreme::ParserExt;
+
+pub type Res<T, U> = IResult<T, U, VerboseError<T>>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Number(i64),
+    Symbol(String),
+    CurrentAddress,
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnaryOp {
+    Negate,
+    BitNot,
+    Not,
+    LowByte,
+    HighByte,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOp {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Shl,
+    Shr,
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ExprError {
+    UndefinedSymbol(String),
+    DivideByZero,
+}
+
+impl fmt::Display for ExprError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExprError::UndefinedSymbol(name) => write!(f, "undefined symbol \"{}\"", name),
+            ExprError::DivideByZero => write!(f, "divide by zero"),
+        }
+    }
+}
+
+impl Expr {
+    pub fn eval(&self, symbols: &HashMap<String, i64>, pc: i64) -> Result<i64, ExprError> {
+        return self.eval_with(&|name| symbols.get(name).copied(), pc);
+    }
+
+    pub fn eval_with<F: Fn(&str) -> Option<i64>>(
+        &self,
+        lookup: &F,
+        pc: i64,
+    ) -> Result<i64, ExprError> {
+        return match self {
+            Expr::Number(v) => Result::Ok(*v),
+            Expr::Symbol(name) => {
+                lookup(name).ok_or_else(|| ExprError::UndefinedSymbol(name.clone()))
+            }
+            Expr::CurrentAddress => Result::Ok(pc),
+            Expr::Unary(op, v) => {
+                let v = v.eval_with(lookup, pc)?;
+                Result::Ok(match op {
+                    UnaryOp::Negate => -v,
+                    UnaryOp::BitNot => !v,
+                    UnaryOp::Not => (v == 0) as i64,
+                    UnaryOp::LowByte => v & 0xff,
+                    UnaryOp::HighByte => (v >> 8) & 0xff,
+                })
+            }
+            Expr::Binary(op, left, right) => {
+                let l = left.eval_with(lookup, pc)?;
+                let r = right.eval_with(lookup, pc)?;
+                Result::Ok(match op {
+                    BinaryOp::Or => (l != 0 || r != 0) as i64,
+                    BinaryOp::And => (l != 0 && r != 0) as i64,
+                    BinaryOp::Eq => (l == r) as i64,
+                    BinaryOp::Ne => (l != r) as i64,
+                    BinaryOp::Lt => (l < r) as i64,
+                    BinaryOp::Gt => (l > r) as i64,
+                    BinaryOp::Le => (l <= r) as i64,
+                    BinaryOp::Ge => (l >= r) as i64,
+                    BinaryOp::BitOr => l | r,
+                    BinaryOp::BitXor => l ^ r,
+                    BinaryOp::BitAnd => l & r,
+                    BinaryOp::Shl => l << (r & 63),
+                    BinaryOp::Shr => l >> (r & 63),
+                    BinaryOp::Add => l + r,
+                    BinaryOp::Sub => l - r,
+                    BinaryOp::Mul => l * r,
+                    BinaryOp::Div => {
+                        if r == 0 {
+                            return Result::Err(ExprError::DivideByZero);
+                        }
+                        l / r
+                    }
+                })
+            }
+        };
+    }
+
+    /// true if `pred` holds for this expression or any of its sub-expressions
+    pub fn any(&self, pred: &dyn Fn(&Expr) -> bool) -> bool {
+        if pred(self) {
+            return true;
+        }
+        return match self {
+            Expr::Unary(_, v) => v.any(pred),
+            Expr::Binary(_, l, r) => l.any(pred) || r.any(pred),
+            _ => false,
+        };
+    }
+}
+
+pub fn identifier(input: &str) -> Res<&str, &str> {
+    return recognize(pair(
+        alt((alpha1, tag("_"))),
+        many0(alt((alphanumeric1, tag("_")))),
+    ))(input);
+}
+
+/// A name, or one in a ca65 `.scope` like `prgrom1::reset`. Labels are kept in one
+/// namespace, scopes or not, so only the name counts.
+fn symbol(input: &str) -> Res<&str, &str> {
+    return preceded(many0(terminated(identifier, tag("::"))), identifier)(input);
+}
+
+pub fn expr(input: &str) -> Res<&str, Expr> {
+    return context("expression", logical_or)(input);
+}
+
+fn binary_level<'a>(
+    input: &'a str,
+    operand: fn(&'a str) -> Res<&'a str, Expr>,
+    operator: fn(&'a str) -> Res<&'a str, BinaryOp>,
+) -> Res<&'a str, Expr> {
+    let (mut input, mut left) = operand(input)?;
+    loop {
+        match preceded(space0, operator)(input) {
+            Result::Ok((next_input, op)) => {
+                let (next_input, right) = preceded(space0, operand)(next_input)?;
+                left = Expr::Binary(op, Box::new(left), Box::new(right));
+                input = next_input;
+            }
+            Result::Err(nom::Err::Error(_)) => return Result::Ok((input, left)),
+            Result::Err(err) => return Result::Err(err),
+        }
+    }
+}
+
+fn logical_or(input: &str) -> Res<&str, Expr> {
+    return binary_level(input, logical_and, |i| {
+        value(BinaryOp::Or, alt((tag("||"), tag_no_case(".or"))))(i)
+    });
+}
+
+fn logical_and(input: &str) -> Res<&str, Expr> {
+    return binary_level(input, comparison, |i| {
+        value(BinaryOp::And, alt((tag("&&"), tag_no_case(".and"))))(i)
+    });
+}
+
+fn comparison(input: &str) -> Res<&str, Expr> {
+    return binary_level(input, bit_or, |i| {
+        alt((
+            value(BinaryOp::Le, tag("<=")),
+            value(BinaryOp::Ge, tag(">=")),
+            value(BinaryOp::Ne, tag("<>")),
+            value(BinaryOp::Ne, tag("!=")),
+            value(BinaryOp::Eq, tag("==")),
+            value(BinaryOp::Eq, tag("=")),
+            value(BinaryOp::Lt, tag("<")),
+            value(BinaryOp::Gt, tag(">")),
+        ))(i)
+    });
+}
+
+fn bit_or(input: &str) -> Res<&str, Expr> {
+    return binary_level(input, bit_xor, |i| {
+        value(BinaryOp::BitOr, terminated(char('|'), not(char('|'))))(i)
+    });
+}
+
+fn bit_xor(input: &str) -> Res<&str, Expr> {
+    return binary_level(input, bit_and, |i| value(BinaryOp::BitXor, char('^'))(i));
+}
+
+fn bit_and(input: &str) -> Res<&str, Expr> {
+    return binary_level(input, shift, |i| {
+        value(BinaryOp::BitAnd, terminated(char('&'), not(char('&'))))(i)
+    });
+}
+
+fn shift(input: &str) -> Res<&str, Expr> {
+    return binary_level(input, additive, |i| {
+        alt((
+            value(BinaryOp::Shl, tag("<<")),
+            value(BinaryOp::Shr, tag(">>")),
+        ))(i)
+    });
+}
+
+fn additive(input: &str) -> Res<&str, Expr> {
+    return binary_level(input, multiplicative, |i| {
+        alt((
+            value(BinaryOp::Add, char('+')),
+            value(BinaryOp::Sub, char('-')),
+        ))(i)
+    });
+}
+
+fn multiplicative(input: &str) -> Res<&str, Expr> {
+    return binary_level(input, unary, |i| {
+        alt((
+            value(BinaryOp::Mul, char('*')),
+            value(BinaryOp::Div, char('/')),
+        ))(i)
+    });
+}
+
+fn unary(input: &str) -> Res<&str, Expr> {
+    return alt((
+        map(
+            pair(
+                alt((
+                    value(UnaryOp::Negate, char('-')),
+                    value(UnaryOp::BitNot, char('~')),
+                    value(UnaryOp::Not, char('!')),
+                    value(UnaryOp::LowByte, char('<')),
+                    value(UnaryOp::HighByte, char('>')),
+                )),
+                preceded(space0, unary),
+            ),
+            |(op, v)| Expr::Unary(op, Box::new(v)),
+        ),
+        primary,
+    ))(input);
+}
+
+#[rustfmt::skip]
+fn primary(input: &str) -> Res<&str, Expr> {
+    return context(
+        "value",
+        alt((
+            map(number, Expr::Number),
+            map(symbol, |name| Expr::Symbol(name.to_string())),
+            value(Expr::CurrentAddress, char('*')),
+            delimited(
+                char('(').terminated(space0),
+                expr,
+                char(')').preceded_by(space0)
+            ),
+        ))
+    )(input);
+}
+
+pub fn number(input: &str) -> Res<&str, i64> {
+    return alt((
+        map_res(preceded(char('$'), hex_digit1), |s| {
+            i64::from_str_radix(s, 16)
+        }),
+        map_res(preceded(char('%'), recognize(many1(one_of("01")))), |s| {
+            i64::from_str_radix(s, 2)
+        }),
+        map_res(digit1, |s: &str| s.parse::<i64>()),
+        map(delimited(char('\''), anychar, char('\'')), |c| c as i64),
+    ))(input);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(input: &str) -> i64 {
+        let symbols = HashMap::from([("REGION".to_string(), 2)]);
+        let (rest, e) = expr(input).unwrap();
+        assert_eq!(rest, "");
+        return e.eval(&symbols, 0x8000).unwrap();
+    }
+
+    #[test]
+    fn test_number() {
+        assert_eq!(number("$ff"), Ok(("", 255)));
+        assert_eq!(number("%0101"), Ok(("", 5)));
+        assert_eq!(number("42,x"), Ok((",x", 42)));
+        assert_eq!(number("'A'"), Ok(("", 65)));
+    }
+
+    #[test]
+    fn test_expr() {
+        assert_eq!(eval("1 + 2 * 3"), 7);
+        assert_eq!(eval("(1 + 2) * 3"), 9);
+        assert_eq!(eval("<$1234"), 0x34);
+        assert_eq!(eval(">$1234"), 0x12);
+        assert_eq!(eval("1 << 4 | 1"), 0x11);
+        assert_eq!(eval("REGION = 2"), 1);
+        assert_eq!(eval("REGION <> 2 || REGION > 1"), 1);
+        assert_eq!(eval("!REGION"), 0);
+        assert_eq!(eval("* + 2"), 0x8002);
+    }
+
+    #[test]
+    fn test_undefined_symbol() {
+        let (_, e) = expr("MISSING + 1").unwrap();
+        assert_eq!(
+            e.eval(&HashMap::new(), 0),
+            Err(ExprError::UndefinedSymbol("MISSING".to_string()))
+        );
+    }
+}
@@ -0,0 +1,171 @@
+use std::{collections::HashMap, io::Write, path::PathBuf};
+
+use super::{
+    expression::expr,
+    open_out_file, parse_defines,
+    patch::{checksum_warnings, displaced_len, patch_rom, RomLayout},
+    read_file_or_stdin, AssembleError,
+};
+
+/// size of the `jmp` written over the hooked code
+const JMP_ABS_LEN: usize = 3;
+const NOP: u8 = 0xea;
+
+#[derive(Debug)]
+pub struct HookOptions {
+    pub rom_file: PathBuf,
+    /// routine to call, a subroutine ending in `rts`
+    pub routine_file: Option<PathBuf>,
+    pub out_file: Option<PathBuf>,
+    /// address to hook, e.g. `$8123`
+    pub at: String,
+    /// inclusive range the trampoline and routine are placed in, e.g. `$9f00..$9fff`
+    pub free: String,
+    pub defines: Vec<String>,
+}
+
+/// Hooks the routine into the ROM: the code at `at` is replaced with a `jmp` to a
+/// trampoline that calls the routine, runs the instructions that were displaced by
+/// the `jmp` and jumps back.
+pub fn hook(opts: HookOptions) -> Result<(), AssembleError> {
+    let routine = read_file_or_stdin(opts.routine_file)?;
+    let defines = parse_defines(&opts.defines)?;
+    if !opts.rom_file.as_path().exists() {
+        return Result::Err(AssembleError::MissingFile(opts.rom_file));
+    }
+    let rom = std::fs::read(opts.rom_file.as_path())?;
+
+    let at = parse_addr(&opts.at)?;
+    let (free_start, free_end) = opts
+        .free
+        .split_once("..")
+        .ok_or_else(|| AssembleError::Patch(format!("invalid range \"{}\"", opts.free)))?;
+    let source = hook_source(
+        &rom,
+        at,
+        parse_addr(free_start)?,
+        parse_addr(free_end)?,
+        &routine,
+    )?;
+
+    let patched = patch_rom(&rom, &source, defines, false, false)?;
+    for warning in checksum_warnings(&rom, &patched)? {
+        eprintln!("warning: {}", warning);
+    }
+    open_out_file(opts.out_file)?.write_all(&patched)?;
+    return Result::Ok(());
+}
+
+/// Patch source for [`hook`], assembled with [`patch_rom`] so the usual size checks
+/// apply to both the trampoline and the routine.
+pub fn hook_source(
+    rom: &[u8],
+    at: i64,
+    free_start: i64,
+    free_end: i64,
+    routine: &str,
+) -> Result<String, AssembleError> {
+    let layout = RomLayout::new(rom)?;
+    let len = displaced_len(rom, &layout, at, JMP_ABS_LEN)?;
+    let offset = layout.to_offset(at)?;
+    let displaced = rom[offset..offset + len]
+        .iter()
+        .map(|b| format!("${:02x}", b))
+        .collect::<Vec<String>>()
+        .join(", ");
+
+    let mut source = format!(
+        ".free ${:04x}, ${:04x}
+.segment \"HOOK\"
+.org ${:04x}
+__hook_trampoline:
+    jsr __hook_routine
+    .byte {} ; displaced from ${:04x}
+    jmp ${:04x}
+__hook_routine:
+{}
+.segment \"HOOK_JMP\"
+.org ${:04x}
+    jmp __hook_trampoline
+",
+        free_start,
+        free_end,
+        free_start,
+        displaced,
+        at,
+        at + len as i64,
+        routine.trim_end(),
+        at
+    );
+    if len > JMP_ABS_LEN {
+        source.push_str(format!("    .res {}, ${:02x}\n", len - JMP_ABS_LEN, NOP).as_str());
+    }
+    return Result::Ok(source);
+}
+
+fn parse_addr(s: &str) -> Result<i64, AssembleError> {
+    return match expr(s.trim()) {
+        Result::Ok(("", e)) => e
+            .eval(&HashMap::new(), 0)
+            .map_err(|err| AssembleError::Patch(format!("invalid address \"{}\": {}", s, err))),
+        _ => Result::Err(AssembleError::Patch(format!("invalid address \"{}\"", s))),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 16k NROM starting with `lda #1; sta $10; rts`
+    fn rom() -> Vec<u8> {
+        let mut rom = vec![b'N', b'E', b'S', 0x1a, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        rom.resize(16 + 0x4000, 0xff);
+        rom[16..21].copy_from_slice(&[0xa9, 0x01, 0x85, 0x10, 0x60]);
+        return rom;
+    }
+
+    #[test]
+    fn test_hook() {
+        let source = hook_source(&rom(), 0x8000, 0x9000, 0x90ff, "inc $20\nrts").unwrap();
+        let patched = patch_rom(&rom(), &source, HashMap::new(), false, false).unwrap();
+        // jmp __hook_trampoline, nop
+        assert_eq!(patched[16..21], [0x4c, 0x00, 0x90, 0xea, 0x60]);
+        assert_eq!(
+            patched[16 + 0x1000..16 + 0x1000 + 13],
+            [
+                0x20, 0x0a, 0x90, // jsr __hook_routine
+                0xa9, 0x01, 0x85, 0x10, // displaced
+                0x4c, 0x04, 0x80, // jmp back
+                0xe6, 0x20, 0x60, // routine
+            ]
+        );
+    }
+
+    #[test]
+    fn test_hook_errors() {
+        let mut rom = rom();
+        rom[16..18].copy_from_slice(&[0xd0, 0x02]);
+        assert!(matches!(
+            hook_source(&rom, 0x8000, 0x9000, 0x90ff, "rts"),
+            Err(AssembleError::Patch(msg)) if msg == "the branch at $8000 can't be moved"
+        ));
+
+        // inx, rts: the jmp would run over what follows the rts
+        rom[16..18].copy_from_slice(&[0xe8, 0x60]);
+        let err = hook_source(&rom, 0x8000, 0x9000, 0x90ff, "rts").unwrap_err();
+        assert!(matches!(err, AssembleError::HookTooShort(0x8000, 2, 3)));
+        assert_eq!(
+            err.to_string(),
+            "routine too short to hook: the code at $8000 ends after 2 bytes but the jmp over \
+             it takes 3"
+        );
+        // a jmp right away is moved whole, inx, rti is too short like inx, rts
+        rom[16..19].copy_from_slice(&[0x4c, 0x00, 0x81]);
+        assert!(hook_source(&rom, 0x8000, 0x9000, 0x90ff, "rts").is_ok());
+        rom[16..19].copy_from_slice(&[0xe8, 0x40, 0xff]);
+        assert!(matches!(
+            hook_source(&rom, 0x8000, 0x9000, 0x90ff, "rts"),
+            Err(AssembleError::HookTooShort(0x8000, 2, 3))
+        ));
+    }
+}
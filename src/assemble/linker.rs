@@ -0,0 +1,491 @@
+use std::collections::HashMap;
+
+use crate::linker_file::{Item, LinkerFile};
+
+use super::{
+    object::{ObjectFile, ObjectSegment, SymbolValue},
+    AssembleError,
+};
+
+/// An output file produced by the linker. `name` is `None` for the main output
+/// (`%O` in a linker config).
+#[derive(Debug, PartialEq)]
+pub struct LinkedFile {
+    pub name: Option<String>,
+    pub data: Vec<u8>,
+}
+
+struct MemoryArea {
+    name: String,
+    /// `None` if the area is not written to any file
+    file: Option<Option<String>>,
+    start: i64,
+    size: i64,
+    fill: bool,
+    fill_value: u8,
+    define: bool,
+    data: Vec<u8>,
+}
+
+struct Placement {
+    load: usize,
+    load_offset: usize,
+    run_addr: i64,
+    size: i64,
+}
+
+/// Links a single object file. Without a config every segment is placed one after
+/// another starting at address 0, in the order the segments first appear.
+pub fn link(
+    object: &ObjectFile,
+    config: Option<&LinkerFile>,
+) -> Result<Vec<LinkedFile>, AssembleError> {
+    return match config {
+        Option::Some(config) => link_with_config(object, config),
+        Option::None => link_flat(object),
+    };
+}
+
+fn link_flat(object: &ObjectFile) -> Result<Vec<LinkedFile>, AssembleError> {
+    let mut run_addrs = HashMap::new();
+    let mut data = Vec::new();
+    for segment in &object.segments {
+        run_addrs.insert(segment.name.clone(), data.len() as i64);
+        data.extend_from_slice(&segment.data);
+    }
+
+    let symbols = resolve_symbols(object, HashMap::new(), &run_addrs);
+    let mut offset = 0;
+    for segment in &object.segments {
+        let len = segment.data.len();
+        patch(
+            segment,
+            &mut data[offset..offset + len],
+            &symbols,
+            &run_addrs,
+        )?;
+        offset += len;
+    }
+
+    return Result::Ok(vec![LinkedFile {
+        name: Option::None,
+        data,
+    }]);
+}
+
+/// Contents of a single segment with its relocations applied, for objects where
+/// every segment was placed with `.org` so nothing depends on the linker.
+pub fn link_absolute_segment(
+    object: &ObjectFile,
+    segment: &ObjectSegment,
+) -> Result<Vec<u8>, AssembleError> {
+    let run_addrs = HashMap::new();
+    let symbols = resolve_symbols(object, HashMap::new(), &run_addrs);
+    let mut data = segment.data.clone();
+    patch(segment, &mut data, &symbols, &run_addrs)?;
+    return Result::Ok(data);
+}
+
+fn link_with_config(
+    object: &ObjectFile,
+    config: &LinkerFile,
+) -> Result<Vec<LinkedFile>, AssembleError> {
+    let mut config_symbols = HashMap::new();
+    if let Option::Some(symbols) = config.category("SYMBOLS") {
+        for (name, item) in symbols.items() {
+            let value = config_value(item, "value", &config_symbols)?.ok_or_else(|| {
+                AssembleError::LinkerConfig(format!("symbol {} has no value", name))
+            })?;
+            config_symbols.insert(name.clone(), value);
+        }
+    }
+    // symbols from the source override the (weak) config symbols
+    for (name, value) in &object.symbols {
+        if let SymbolValue::Absolute(v) = value {
+            config_symbols.insert(name.clone(), *v);
+        }
+    }
+
+    let mut memory = Vec::new();
+    if let Option::Some(areas) = config.category("MEMORY") {
+        for (name, item) in areas.items() {
+            memory.push(memory_area(name, item, &config_symbols)?);
+        }
+    }
+
+    let mut placements: HashMap<String, Placement> = HashMap::new();
+    let mut run_positions: Vec<i64> = memory.iter().map(|m| m.start).collect();
+    if let Option::Some(segments) = config.category("SEGMENTS") {
+        for (name, item) in segments.items() {
+            let segment = match object.segment(name) {
+                Option::Some(segment) => segment,
+                Option::None => continue,
+            };
+            let placement = place_segment(
+                segment,
+                item,
+                &mut memory,
+                &mut run_positions,
+                &config_symbols,
+            )?;
+            if item.argument("define") == Option::Some("yes") {
+                let load_addr = memory[placement.load].start + placement.load_offset as i64;
+                config_symbols.insert(format!("__{}_LOAD__", name), load_addr);
+                config_symbols.insert(format!("__{}_RUN__", name), placement.run_addr);
+                config_symbols.insert(format!("__{}_SIZE__", name), placement.size);
+            }
+            placements.insert(name.clone(), placement);
+        }
+    }
+
+    // like ld65, segments left empty, such as the default one before the first
+    // `.segment`, don't need a place
+    for segment in &object.segments {
+        if !placements.contains_key(&segment.name) && !segment.data.is_empty() {
+            return Result::Err(AssembleError::UnplacedSegment(segment.name.clone()));
+        }
+    }
+
+    for (i, area) in memory.iter().enumerate() {
+        let used = (run_positions[i] - area.start).max(area.data.len() as i64);
+        if used > area.size {
+            return Result::Err(AssembleError::MemoryOverflow(
+                area.name.clone(),
+                used - area.size,
+            ));
+        }
+        if area.define {
+            config_symbols.insert(format!("__{}_START__", area.name), area.start);
+            config_symbols.insert(format!("__{}_SIZE__", area.name), area.size);
+            config_symbols.insert(format!("__{}_LAST__", area.name), area.start + used);
+        }
+    }
+
+    let run_addrs = placements
+        .iter()
+        .map(|(name, p)| (name.clone(), p.run_addr))
+        .collect();
+    let symbols = resolve_symbols(object, config_symbols, &run_addrs);
+    for segment in &object.segments {
+        let placement = match placements.get(&segment.name) {
+            Option::Some(placement) => placement,
+            Option::None => continue,
+        };
+        let area = &mut memory[placement.load];
+        if placement.load_offset + segment.data.len() <= area.data.len() {
+            let data =
+                &mut area.data[placement.load_offset..placement.load_offset + segment.data.len()];
+            patch(segment, data, &symbols, &run_addrs)?;
+        }
+    }
+
+    let mut files: Vec<LinkedFile> = Vec::new();
+    for mut area in memory {
+        let name = match area.file {
+            Option::Some(name) => name,
+            Option::None => continue,
+        };
+        if area.fill {
+            area.data.resize(area.size as usize, area.fill_value);
+        }
+        match files.iter_mut().find(|f| f.name == name) {
+            Option::Some(file) => file.data.extend(area.data),
+            Option::None => files.push(LinkedFile {
+                name,
+                data: area.data,
+            }),
+        }
+    }
+    return Result::Ok(files);
+}
+
+fn memory_area(
+    name: &str,
+    item: &Item,
+    symbols: &HashMap<String, i64>,
+) -> Result<MemoryArea, AssembleError> {
+    let file = match item.argument("file") {
+        Option::Some("%O") => Option::Some(Option::None),
+        Option::Some(file) => {
+            let file = file.trim_matches('"');
+            if file.is_empty() {
+                Option::None
+            } else {
+                Option::Some(Option::Some(file.to_string()))
+            }
+        }
+        Option::None => Option::Some(Option::None),
+    };
+    let required = |arg: &str| -> Result<i64, AssembleError> {
+        return config_value(item, arg, symbols)?.ok_or_else(|| {
+            AssembleError::LinkerConfig(format!("memory area {} has no {}", name, arg))
+        });
+    };
+    let fill_value = config_value(item, "fillval", symbols)?.unwrap_or(0);
+    return Result::Ok(MemoryArea {
+        name: name.to_string(),
+        file,
+        start: required("start")?,
+        size: required("size")?,
+        fill: item.argument("fill") == Option::Some("yes"),
+        fill_value: fill_value as u8,
+        define: item.argument("define") == Option::Some("yes"),
+        data: Vec::new(),
+    });
+}
+
+fn place_segment(
+    segment: &ObjectSegment,
+    item: &Item,
+    memory: &mut [MemoryArea],
+    run_positions: &mut [i64],
+    symbols: &HashMap<String, i64>,
+) -> Result<Placement, AssembleError> {
+    let find_area = |arg: &str| -> Result<Option<usize>, AssembleError> {
+        return match item.argument(arg) {
+            Option::Some(area) => match memory.iter().position(|m| m.name == area) {
+                Option::Some(i) => Result::Ok(Option::Some(i)),
+                Option::None => Result::Err(AssembleError::LinkerConfig(format!(
+                    "segment {} references unknown memory area {}",
+                    segment.name, area
+                ))),
+            },
+            Option::None => Result::Ok(Option::None),
+        };
+    };
+    let load = find_area("load")?.ok_or_else(|| {
+        AssembleError::LinkerConfig(format!("segment {} has no load area", segment.name))
+    })?;
+    let run = find_area("run")?.unwrap_or(load);
+    let size = segment.data.len() as i64;
+
+    let mut run_addr = run_positions[run];
+    if let Option::Some(align) = config_value(item, "align", symbols)? {
+        if align > 0 {
+            run_addr = (run_addr + align - 1) / align * align;
+        }
+    }
+    if let Option::Some(offset) = config_value(item, "offset", symbols)? {
+        run_addr = memory[run].start + offset;
+    }
+    if let Option::Some(start) = config_value(item, "start", symbols)? {
+        run_addr = start;
+    }
+    if run_addr < run_positions[run] {
+        return Result::Err(AssembleError::LinkerConfig(format!(
+            "segment {} would overlap the preceding segment in {}",
+            segment.name, memory[run].name
+        )));
+    }
+    run_positions[run] = run_addr + size;
+
+    let is_bss = matches!(
+        item.argument("type"),
+        Option::Some("bss") | Option::Some("zp")
+    );
+    let load_offset = if run == load {
+        (run_addr - memory[load].start) as usize
+    } else {
+        let offset = (run_positions[load] - memory[load].start) as usize;
+        if !is_bss {
+            run_positions[load] += size;
+        }
+        offset
+    };
+    if !is_bss {
+        let area = &mut memory[load];
+        let fill_value = area.fill_value;
+        if area.data.len() < load_offset {
+            area.data.resize(load_offset, fill_value);
+        }
+        area.data.extend_from_slice(&segment.data);
+    }
+
+    return Result::Ok(Placement {
+        load,
+        load_offset,
+        run_addr,
+        size,
+    });
+}
+
+/// A numeric config argument, see [`Item::value`].
+fn config_value(
+    item: &Item,
+    arg: &str,
+    symbols: &HashMap<String, i64>,
+) -> Result<Option<i64>, AssembleError> {
+    return item
+        .value(arg, symbols)
+        .map_err(AssembleError::LinkerConfig);
+}
+
+fn resolve_symbols(
+    object: &ObjectFile,
+    mut symbols: HashMap<String, i64>,
+    run_addrs: &HashMap<String, i64>,
+) -> HashMap<String, i64> {
+    for (name, value) in &object.symbols {
+        if let Option::Some(v) = resolve(value, run_addrs) {
+            symbols.insert(name.clone(), v);
+        }
+    }
+    return symbols;
+}
+
+fn resolve(value: &SymbolValue, run_addrs: &HashMap<String, i64>) -> Option<i64> {
+    return match value {
+        SymbolValue::Absolute(v) => Option::Some(*v),
+        SymbolValue::Relative(segment, offset) => run_addrs.get(segment).map(|a| a + offset),
+    };
+}
+
+fn patch(
+    segment: &ObjectSegment,
+    data: &mut [u8],
+    symbols: &HashMap<String, i64>,
+    run_addrs: &HashMap<String, i64>,
+) -> Result<(), AssembleError> {
+    for relocation in &segment.relocations {
+        let pc = resolve(&relocation.pc, run_addrs).unwrap_or(0);
+        let value = relocation
+            .expr
+            .eval(symbols, pc)
+            .map_err(|err| AssembleError::ExprError(relocation.line_no, err))?;
+        let bytes = relocation
+            .kind
+            .encode_expr(&relocation.expr, value, pc, relocation.line_no)?;
+        data[relocation.offset..relocation.offset + bytes.len()].copy_from_slice(&bytes);
+    }
+    for verification in &segment.verifications {
+        let found = data.get(verification.offset..).unwrap_or_default();
+        let mismatch = verification
+            .bytes
+            .iter()
+            .enumerate()
+            .find(|(i, b)| found.get(*i) != Option::Some(b));
+        if let Option::Some((i, expected)) = mismatch {
+            return Result::Err(AssembleError::VerifyFailed(
+                verification.line_no,
+                i,
+                *expected,
+                found.get(i).copied(),
+            ));
+        }
+    }
+    return Result::Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assemble::assembler::Assembler, linker_file::parse_linker_file};
+
+    const CONFIG: &str = "
+        MEMORY {
+            ZP:   file = \"\", start = $0010, size = $0010, type = rw;
+            ROM:  file = %O, start = $8000, size = $0010, fill = yes, fillval = $ff;
+            RAM:  file = \"\", start = $0300, size = $0100, define = yes;
+            CHR:  file = \"chr.bin\", start = $0000, size = $0002;
+        }
+        SEGMENTS {
+            ZEROPAGE: load = ZP, type = zp;
+            CODE:     load = ROM, type = ro;
+            DATA:     load = ROM, run = RAM, type = rw, define = yes;
+            VECTORS:  load = ROM, type = ro, start = $800e;
+            CHARS:    load = CHR, type = ro;
+        }
+    ";
+
+    fn link_str(source: &str, config: &str) -> Result<Vec<LinkedFile>, AssembleError> {
+        let object = Assembler::new(HashMap::new()).assemble(source)?;
+        return link(&object, Option::Some(&parse_linker_file(config).unwrap()));
+    }
+
+    #[test]
+    fn test_link_with_config() {
+        let source = "
+            .segment \"VECTORS\"
+                .word reset
+            .segment \"ZEROPAGE\"
+            ptr: .res 2
+            .segment \"DATA\"
+            counter: .byte 7
+            .segment \"CODE\"
+            reset:
+                lda ptr
+                sta counter
+                lda #<__DATA_LOAD__
+                bne reset
+            .segment \"CHARS\"
+                .byte 1, 2
+        ";
+        assert_eq!(
+            link_str(source, CONFIG).unwrap(),
+            vec![
+                LinkedFile {
+                    name: Option::None,
+                    data: vec![
+                        0xa5, 0x10, 0x8d, 0x00, 0x03, 0xa9, 0x09, 0xd0, 0xf7, 0x07, 0xff, 0xff,
+                        0xff, 0xff, 0x00, 0x80
+                    ],
+                },
+                LinkedFile {
+                    name: Option::Some("chr.bin".to_string()),
+                    data: vec![1, 2],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_link_errors() {
+        assert!(matches!(
+            link_str(".segment \"CODE\"\n.res 17", CONFIG),
+            Err(AssembleError::MemoryOverflow(name, 1)) if name == "ROM"
+        ));
+        assert!(matches!(
+            link_str(".segment \"BOGUS\"\n.byte 1", CONFIG),
+            Err(AssembleError::UnplacedSegment(name)) if name == "BOGUS"
+        ));
+        assert!(matches!(
+            link_str("lda #undefined", CONFIG),
+            Err(AssembleError::ExprError(1, _))
+        ));
+    }
+
+    #[test]
+    fn test_verify() {
+        let source = "
+            .segment \"CODE\"
+            .verify $a9, $00, $4c
+            .verify $00, $80
+            reset:
+                lda #0
+                jmp reset
+        ";
+        assert!(link_str(source, CONFIG).is_ok());
+        assert!(matches!(
+            link_str(&source.replace("jmp reset", "jmp $8001"), CONFIG),
+            Err(AssembleError::VerifyFailed(4, 0, 0x00, Option::Some(0x01)))
+        ));
+        assert!(matches!(
+            link_str(&source.replace("jmp reset", "rts"), CONFIG),
+            Err(AssembleError::VerifyFailed(3, 2, 0x4c, Option::Some(0x60)))
+        ));
+        // one at a label inside verified code checks from the label
+        let nested = "
+            .segment \"CODE\"
+            .verify $a9, $00, $60
+                lda #0
+            done:
+            .verify $60
+                rts
+        ";
+        assert!(link_str(nested, CONFIG).is_ok());
+        assert!(matches!(
+            link_str(".segment \"CODE\"\n.verify $ea\n", CONFIG),
+            Err(AssembleError::VerifyFailed(2, 0, 0xea, Option::None))
+        ));
+    }
+}
@@ -0,0 +1,515 @@
+mod assembler;
+mod conditional;
+pub(crate) mod expression;
+mod hook;
+mod linker;
+mod object;
+mod parser;
+mod patch;
+#[cfg(test)]
+mod roundtrip;
+
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::File,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::disassemble::{Manifest, MANIFEST_FILE};
+use crate::linker_file::{read_linker_file, ReadLinkerFileError};
+
+pub use self::hook::{hook, HookOptions};
+
+use self::{
+    assembler::{relax_branches, Assembler},
+    conditional::ConditionalError,
+    expression::{expr, identifier, ExprError},
+    linker::link,
+    patch::{checksum_warnings, patch_rom},
+};
+
+#[derive(Debug)]
+pub struct AssembleOptions {
+    /// the source, or a project directory `d` wrote, assembled with the source
+    /// and linker config its manifest lists
+    pub in_file: Option<PathBuf>,
+    pub out_file: Option<PathBuf>,
+    /// `NAME=value` or `NAME` (defined as 1) entries from the command line
+    pub defines: Vec<String>,
+    /// linker config, either a built-in name such as `nes` or a path to an ld65
+    /// style config file
+    pub config: Option<String>,
+    /// ROM to assemble the source over instead of linking a new file
+    pub patch: Option<PathBuf>,
+    /// move patches that don't fit to a `.free` block instead of failing
+    pub allow_grow: bool,
+    /// assemble branches that can't reach their target as the inverted branch
+    /// over a `jmp`
+    pub long_branches: bool,
+}
+
+#[derive(Debug)]
+pub enum AssembleError {
+    MissingFile(PathBuf),
+    IoError(std::io::Error),
+    InvalidDefine(String),
+    SyntaxError(usize, String),
+    ExprError(usize, ExprError),
+    ConditionalError(usize, ConditionalError),
+    UnterminatedConditional(usize),
+    InvalidInstruction(usize, String),
+    DuplicateSymbol(usize, String),
+    ValueOutOfRange(usize, i64),
+    /// line, branch target and distance to the target
+    BranchOutOfRange(usize, String, i64),
+    NotConstant(usize),
+    LinkerFileError(ReadLinkerFileError),
+    LinkerConfig(String),
+    UnplacedSegment(String),
+    MemoryOverflow(String, i64),
+    Patch(String),
+    /// segment, address, size and the number of bytes available there
+    PatchTooLarge(String, i64, usize, usize),
+    /// line of the `.verify`, how far into its bytes, the byte expected and the
+    /// one assembled, if the segment goes on that far
+    VerifyFailed(usize, usize, u8, Option<u8>),
+    /// address hooked, length of the code there up to its `rts`, `rti` or `jmp`
+    /// and the bytes the hook needs
+    HookTooShort(i64, usize, usize),
+    Project(String),
+}
+
+impl From<std::io::Error> for AssembleError {
+    fn from(err: std::io::Error) -> Self {
+        return AssembleError::IoError(err);
+    }
+}
+
+impl From<ReadLinkerFileError> for AssembleError {
+    fn from(err: ReadLinkerFileError) -> Self {
+        return AssembleError::LinkerFileError(err);
+    }
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AssembleError::MissingFile(path) => write!(f, "Missing file {}", path.display()),
+            AssembleError::IoError(err) => write!(f, "io error: {}", err),
+            AssembleError::InvalidDefine(define) => write!(f, "invalid define: {}", define),
+            AssembleError::SyntaxError(line, err) => {
+                write!(f, "line {}: syntax error: {}", line, err)
+            }
+            AssembleError::ExprError(line, err) => write!(f, "line {}: {}", line, err),
+            AssembleError::ConditionalError(line, err) => write!(f, "line {}: {}", line, err),
+            AssembleError::UnterminatedConditional(line) => {
+                write!(f, "line {}: .if without .endif", line)
+            }
+            AssembleError::InvalidInstruction(line, msg) => write!(f, "line {}: {}", line, msg),
+            AssembleError::DuplicateSymbol(line, name) => {
+                write!(f, "line {}: duplicate symbol \"{}\"", line, name)
+            }
+            AssembleError::ValueOutOfRange(line, v) => {
+                write!(f, "line {}: value out of range: {}", line, v)
+            }
+            AssembleError::BranchOutOfRange(line, target, distance) => write!(
+                f,
+                "line {}: branch to {} out of range by {} bytes (offset {}, must be -128..127)",
+                line,
+                target,
+                if *distance < 0 {
+                    -128 - distance
+                } else {
+                    distance - 127
+                },
+                distance
+            ),
+            AssembleError::NotConstant(line) => {
+                write!(f, "line {}: expression must be constant", line)
+            }
+            AssembleError::LinkerFileError(err) => write!(f, "{}", err),
+            AssembleError::LinkerConfig(msg) => write!(f, "linker config: {}", msg),
+            AssembleError::UnplacedSegment(name) => {
+                write!(f, "segment \"{}\" is not in the linker config", name)
+            }
+            AssembleError::MemoryOverflow(name, overflow) => {
+                write!(f, "memory area {} overflows by {} bytes", name, overflow)
+            }
+            AssembleError::Patch(msg) => write!(f, "patch: {}", msg),
+            AssembleError::PatchTooLarge(name, addr, size, available) => write!(
+                f,
+                "segment \"{}\" at ${:04x} is {} bytes but only {} bytes are available there, \
+                 declare a .free block or use --allow-grow to move it",
+                name, addr, size, available
+            ),
+            AssembleError::VerifyFailed(line, index, expected, Option::Some(found)) => write!(
+                f,
+                "line {}: .verify byte {} is ${:02x} but the code assembles to ${:02x}",
+                line, index, expected, found
+            ),
+            AssembleError::VerifyFailed(line, index, expected, Option::None) => write!(
+                f,
+                "line {}: .verify byte {} is ${:02x} but the code ends before it",
+                line, index, expected
+            ),
+            AssembleError::HookTooShort(addr, len, needed) => write!(
+                f,
+                "routine too short to hook: the code at ${:04x} ends after {} bytes but the jmp \
+                 over it takes {}",
+                addr, len, needed
+            ),
+            AssembleError::Project(msg) => write!(f, "project: {}", msg),
+        }
+    }
+}
+
+pub fn assemble(mut opts: AssembleOptions) -> Result<(), AssembleError> {
+    // a project directory builds the ROM next to its source, listed in the manifest
+    let mut project_dir = Option::None;
+    if let Option::Some(dir) = opts.in_file.clone().filter(|file| file.is_dir()) {
+        if opts.patch.is_some() {
+            return Result::Err(AssembleError::Project(
+                "a project directory is linked with its config, not patched over a ROM".to_string(),
+            ));
+        }
+        let (source, config) = project_files(&dir)?;
+        opts.config
+            .get_or_insert_with(|| config.to_string_lossy().to_string());
+        opts.out_file
+            .get_or_insert_with(|| source.with_extension("nes"));
+        opts.in_file = Option::Some(source);
+        project_dir = Option::Some(dir);
+    }
+    let source = read_file_or_stdin(opts.in_file)?;
+    let defines = parse_defines(&opts.defines)?;
+
+    if let Option::Some(rom_file) = opts.patch {
+        if opts.config.is_some() {
+            return Result::Err(AssembleError::Patch(
+                "a linker config can't be used when patching".to_string(),
+            ));
+        }
+        if !rom_file.as_path().exists() {
+            return Result::Err(AssembleError::MissingFile(rom_file));
+        }
+        let rom = std::fs::read(rom_file.as_path())?;
+        let patched = patch_rom(&rom, &source, defines, opts.allow_grow, opts.long_branches)?;
+        for warning in checksum_warnings(&rom, &patched)? {
+            eprintln!("warning: {}", warning);
+        }
+        open_out_file(opts.out_file)?.write_all(&patched)?;
+        return Result::Ok(());
+    }
+
+    let config = match opts.config {
+        Option::Some(config) => Option::Some(read_linker_file(config)?),
+        Option::None => Option::None,
+    };
+
+    let files = relax_branches(opts.long_branches, |long_branches| {
+        let object = Assembler::new(defines.clone())
+            .with_long_branches(long_branches)
+            .assemble(&source)?;
+        return link(&object, config.as_ref());
+    })?;
+
+    let Option::Some(dir) = project_dir else {
+        for file in files {
+            match file.name {
+                Option::Some(name) => std::fs::write(name, &file.data)?,
+                Option::None => {
+                    let mut out = open_out_file(opts.out_file.clone())?;
+                    out.write_all(&file.data)?;
+                }
+            }
+        }
+        return Result::Ok(());
+    };
+
+    let out_files = files
+        .iter()
+        .map(|file| match &file.name {
+            Option::Some(name) => dir.join(name),
+            Option::None => opts.out_file.clone().unwrap_or_default(),
+        })
+        .collect::<Vec<PathBuf>>();
+    let project_err = |err: crate::disassemble::DisassembleError| {
+        return AssembleError::Project(err.to_string());
+    };
+    let mut manifest = Manifest::read(&dir).map_err(project_err)?;
+    let changed = manifest.changed(&dir, &out_files).map_err(project_err)?;
+    if !changed.is_empty() {
+        return Result::Err(AssembleError::Project(format!(
+            "{} wasn't built here or changed since, move it away to build",
+            changed
+                .iter()
+                .map(|file| file.display().to_string())
+                .collect::<Vec<String>>()
+                .join(", ")
+        )));
+    }
+    for (file, out_file) in files.iter().zip(&out_files) {
+        std::fs::write(out_file, &file.data)?;
+        manifest.record(&dir, out_file).map_err(project_err)?;
+    }
+    manifest.write(&dir).map_err(project_err)?;
+    return Result::Ok(());
+}
+
+/// The source and linker config `d` wrote into the project directory `dir`, as
+/// listed in its manifest.
+fn project_files(dir: &Path) -> Result<(PathBuf, PathBuf), AssembleError> {
+    let manifest = Manifest::read(dir).map_err(|err| AssembleError::Project(err.to_string()))?;
+    for file in manifest.files.keys() {
+        let config = Path::new(file);
+        if config.extension().and_then(|ext| ext.to_str()) != Option::Some("cfg") {
+            continue;
+        }
+        let source = config.with_extension("s");
+        if manifest
+            .files
+            .contains_key(source.to_string_lossy().as_ref())
+        {
+            return Result::Ok((dir.join(source), dir.join(config)));
+        }
+    }
+    return Result::Err(AssembleError::Project(format!(
+        "{} in {} lists no source with a linker config, disassemble a ROM into it first",
+        MANIFEST_FILE,
+        dir.display()
+    )));
+}
+
+fn parse_defines(defines: &[String]) -> Result<HashMap<String, i64>, AssembleError> {
+    let mut result = HashMap::new();
+    for define in defines {
+        let (name, value) = define.split_once('=').unwrap_or((define, "1"));
+        let name = name.trim();
+        if !matches!(identifier(name), Result::Ok(("", _))) {
+            return Result::Err(AssembleError::InvalidDefine(define.clone()));
+        }
+        let value = match expr(value.trim()) {
+            Result::Ok(("", e)) => e
+                .eval(&result, 0)
+                .map_err(|err| AssembleError::InvalidDefine(format!("{} ({})", define, err)))?,
+            _ => return Result::Err(AssembleError::InvalidDefine(define.clone())),
+        };
+        result.insert(name.to_string(), value);
+    }
+    return Result::Ok(result);
+}
+
+fn open_out_file(f: Option<PathBuf>) -> Result<Box<dyn Write>, AssembleError> {
+    if let Option::Some(out_file) = f {
+        let f = File::create(out_file.as_path())?;
+        return Result::Ok(Box::new(f) as Box<dyn Write>);
+    }
+
+    return Result::Ok(Box::new(std::io::stdout()) as Box<dyn Write>);
+}
+
+fn read_file_or_stdin(f: Option<PathBuf>) -> Result<String, AssembleError> {
+    if let Option::Some(in_file) = f {
+        if !in_file.as_path().exists() {
+            return Result::Err(AssembleError::MissingFile(in_file));
+        }
+        return Result::Ok(std::fs::read_to_string(in_file.as_path())?);
+    }
+
+    let mut buffer = String::new();
+    std::io::stdin().read_to_string(&mut buffer)?;
+    return Result::Ok(buffer);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn assemble_str(source: &str, defines: &[&str]) -> Result<Vec<u8>, AssembleError> {
+        let defines = parse_defines(
+            &defines
+                .iter()
+                .map(|d| d.to_string())
+                .collect::<Vec<String>>(),
+        )?;
+        let object = Assembler::new(defines).assemble(source)?;
+        return link(&object, Option::None).map(|mut files| files.remove(0).data);
+    }
+
+    #[test]
+    fn test_assemble_instructions() {
+        let source = "
+            .org $8000
+            reset:
+                lda #$00
+                sta $10
+                sta $0200,x
+                ldx ZP_PTR
+            loop:
+                dex
+                bne loop
+                jmp reset
+            ZP_PTR = $20
+        ";
+        assert_eq!(
+            assemble_str(source, &[]).unwrap(),
+            vec![
+                0xa9, 0x00, 0x85, 0x10, 0x9d, 0x00, 0x02, 0xae, 0x20, 0x00, 0xca, 0xd0, 0xfd, 0x4c,
+                0x00, 0x80
+            ]
+        );
+    }
+
+    #[test]
+    fn test_branch_out_of_range() {
+        let source = "
+            .org $8000
+            loop:
+                .res 126
+                bne loop
+                bne loop
+        ";
+        let err = assemble_str(source, &[]).unwrap_err();
+        assert!(matches!(
+            &err,
+            AssembleError::BranchOutOfRange(6, target, -130) if target == "loop"
+        ));
+        assert_eq!(
+            err.to_string(),
+            "line 6: branch to loop out of range by 2 bytes (offset -130, must be -128..127)"
+        );
+    }
+
+    #[test]
+    fn test_long_branches() {
+        let source = "
+            .org $8000
+            loop:
+                .res 127
+                bne loop
+                bcc $9000
+        ";
+        let object = |long_branches: &HashSet<usize>| {
+            return Assembler::new(HashMap::new())
+                .with_long_branches(long_branches)
+                .assemble(source);
+        };
+        assert!(relax_branches(false, object).is_err());
+        let data = relax_branches(true, |long_branches| {
+            return link(&object(long_branches)?, Option::None);
+        })
+        .unwrap()
+        .remove(0)
+        .data;
+        assert_eq!(
+            data[127..],
+            [0xf0, 0x03, 0x4c, 0x00, 0x80, 0xb0, 0x03, 0x4c, 0x00, 0x90]
+        );
+    }
+
+    #[test]
+    fn test_conditionals() {
+        let source = "
+            .ifdef DEBUG
+                .byte 1
+            .else
+                .byte 2
+            .endif
+            .if REGION = 1
+                .byte $10
+            .elseif REGION = 2
+                .byte $20
+                .ifndef DEBUG
+                    .byte $21
+                .endif
+            .else
+                .byte $30
+            .endif
+        ";
+        assert_eq!(assemble_str(source, &["REGION=1"]).unwrap(), vec![2, 0x10]);
+        assert_eq!(
+            assemble_str(source, &["REGION=2"]).unwrap(),
+            vec![2, 0x20, 0x21]
+        );
+        assert_eq!(
+            assemble_str(source, &["DEBUG", "REGION=3"]).unwrap(),
+            vec![1, 0x30]
+        );
+    }
+
+    #[test]
+    fn test_conditional_errors() {
+        assert!(matches!(
+            assemble_str(".if 1\n.byte 1", &[]),
+            Err(AssembleError::UnterminatedConditional(1))
+        ));
+        assert!(matches!(
+            assemble_str(".endif", &[]),
+            Err(AssembleError::ConditionalError(
+                1,
+                ConditionalError::EndIfWithoutIf
+            ))
+        ));
+        assert!(matches!(
+            assemble_str(".if 1\n.else\n.else\n.endif", &[]),
+            Err(AssembleError::ConditionalError(
+                3,
+                ConditionalError::ElseAfterElse
+            ))
+        ));
+        assert!(matches!(
+            parse_defines(&["1BAD=2".to_string()]),
+            Err(AssembleError::InvalidDefine(_))
+        ));
+    }
+
+    #[test]
+    fn test_assemble_project_dir() {
+        let dir = std::env::temp_dir().join(format!("sixtyfive-build-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let opts = || AssembleOptions {
+            in_file: Option::Some(dir.clone()),
+            out_file: Option::None,
+            defines: Vec::new(),
+            config: Option::None,
+            patch: Option::None,
+            allow_grow: false,
+            long_branches: false,
+        };
+        assert!(matches!(assemble(opts()), Err(AssembleError::Project(_))));
+
+        let mut manifest = Manifest::default();
+        for (name, contents) in [
+            (
+                "game.cfg",
+                "MEMORY { ROM: file = %O, start = $8000, size = $0004, fill = yes; }\n\
+                 SEGMENTS { CODE: load = ROM, type = ro; }\n",
+            ),
+            ("game.s", ".segment \"CODE\"\nlda #$01\nrts\n"),
+            ("REPORT.md", "# game\n"),
+        ] {
+            std::fs::write(dir.join(name), contents).unwrap();
+            manifest.record(&dir, &dir.join(name)).unwrap();
+        }
+        manifest.write(&dir).unwrap();
+
+        // the ROM is written next to the source and listed with it
+        assemble(opts()).unwrap();
+        assert_eq!(
+            std::fs::read(dir.join("game.nes")).unwrap(),
+            [0xa9, 0x01, 0x60, 0x00]
+        );
+        let manifest = Manifest::read(&dir).unwrap();
+        assert!(manifest.files.contains_key("game.nes"));
+
+        // and built again unless changed by hand
+        assemble(opts()).unwrap();
+        std::fs::write(dir.join("game.nes"), "edited").unwrap();
+        assert!(matches!(assemble(opts()), Err(AssembleError::Project(_))));
+        assert_eq!(std::fs::read(dir.join("game.nes")).unwrap(), b"edited");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
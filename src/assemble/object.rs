@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+
+use super::{expression::Expr, AssembleError};
+
+/// Relocatable output of the assembler. Segment contents are position independent;
+/// anything depending on where a segment ends up is recorded as a [`Relocation`]
+/// and patched by the linker.
+#[derive(Debug)]
+pub struct ObjectFile {
+    pub segments: Vec<ObjectSegment>,
+    pub symbols: HashMap<String, SymbolValue>,
+    /// inclusive address ranges declared with `.free`
+    pub free_blocks: Vec<(i64, i64)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SymbolValue {
+    Absolute(i64),
+    /// offset from the start of the named segment
+    Relative(String, i64),
+}
+
+#[derive(Debug)]
+pub struct ObjectSegment {
+    pub name: String,
+    /// address of the start of the segment if it was fixed with `.org`
+    pub org: Option<i64>,
+    pub data: Vec<u8>,
+    pub relocations: Vec<Relocation>,
+    pub verifications: Vec<Verification>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RelocationKind {
+    Byte,
+    Word,
+    /// branch displacement relative to the instruction following the branch
+    Relative,
+}
+
+impl RelocationKind {
+    pub fn len(&self) -> usize {
+        return match self {
+            RelocationKind::Byte | RelocationKind::Relative => 1,
+            RelocationKind::Word => 2,
+        };
+    }
+
+    /// Encodes `value` (the target address for [`RelocationKind::Relative`]) for a
+    /// statement at `pc`. The offending value is returned if it does not fit.
+    pub fn encode(&self, value: i64, pc: i64) -> Result<Vec<u8>, i64> {
+        return match self {
+            RelocationKind::Byte => {
+                if !(-128..=0xff).contains(&value) {
+                    return Result::Err(value);
+                }
+                Result::Ok(vec![value as u8])
+            }
+            RelocationKind::Word => {
+                if !(-32768..=0xffff).contains(&value) {
+                    return Result::Err(value);
+                }
+                Result::Ok((value as u16).to_le_bytes().to_vec())
+            }
+            RelocationKind::Relative => {
+                let rel = value - (pc + 2);
+                if !(-128..=127).contains(&rel) {
+                    return Result::Err(rel);
+                }
+                Result::Ok(vec![rel as i8 as u8])
+            }
+        };
+    }
+
+    /// Encodes the value of `e` for the statement on `line_no`. A branch that can't
+    /// reach its target is reported with the label it was trying to reach.
+    pub fn encode_expr(
+        &self,
+        e: &Expr,
+        value: i64,
+        pc: i64,
+        line_no: usize,
+    ) -> Result<Vec<u8>, AssembleError> {
+        return self.encode(value, pc).map_err(|v| match self {
+            RelocationKind::Relative => {
+                let target = match e {
+                    Expr::Symbol(name) => name.clone(),
+                    _ => format!("${:04X}", value),
+                };
+                AssembleError::BranchOutOfRange(line_no, target, v)
+            }
+            _ => AssembleError::ValueOutOfRange(line_no, v),
+        });
+    }
+}
+
+#[derive(Debug)]
+pub struct Relocation {
+    /// position in the segment data to patch
+    pub offset: usize,
+    pub kind: RelocationKind,
+    pub expr: Expr,
+    /// value of `*` for the statement containing the expression
+    pub pc: SymbolValue,
+    pub line_no: usize,
+}
+
+/// Bytes a `.verify` expects the segment to hold once it is linked.
+#[derive(Debug)]
+pub struct Verification {
+    /// position in the segment data of the first byte
+    pub offset: usize,
+    pub bytes: Vec<u8>,
+    pub line_no: usize,
+}
+
+impl ObjectFile {
+    pub fn segment(&self, name: &str) -> Option<&ObjectSegment> {
+        return self.segments.iter().find(|s| s.name == name);
+    }
+}
@@ -0,0 +1,456 @@
+use nom::{
+    branch::alt,
+    bytes::complete::{is_not, tag, tag_no_case},
+    character::complete::{alpha1, char, space0, space1},
+    combinator::{eof, map, not, opt, value},
+    error::{context, convert_error},
+    multi::separated_list1,
+    sequence::{delimited, pair, preceded, terminated, tuple},
+};
+use nom_supreme::ParserExt;
+
+use super::expression::{expr, identifier, Expr, Res};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Line {
+    pub label: Option<String>,
+    pub statement: Option<Statement>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Statement {
+    Instruction(String, Operand),
+    Define(String, Expr),
+    /// `name := expr`, a label at an address relative to `*` or fixed
+    Label(String, Expr),
+    Segment(String),
+    Org(Expr),
+    Byte(Vec<DataItem>),
+    Word(Vec<Expr>),
+    Res(Expr, Option<Expr>),
+    /// inclusive address range a patch may use, see [`super::patch`]
+    Free(Expr, Expr),
+    /// bytes the code that follows must assemble to, checked once it is linked
+    Verify(Vec<Expr>),
+    If(Expr),
+    IfDef(String),
+    IfNDef(String),
+    ElseIf(Expr),
+    Else,
+    EndIf,
+    /// symbols linked in from other object files, all labels are visible to the
+    /// linker already
+    Export(Vec<String>),
+    /// the CPU instructions are assembled for, only the 6502 is supported
+    SetCpu(String),
+    /// character code and the byte strings write for it from here on
+    Charmap(Expr, Expr),
+    /// start of a `.proc`, a label for the code up to `.endproc`
+    Proc(String),
+    EndProc,
+    /// start of a `.scope` up to `.endscope`, its labels visible everywhere since
+    /// there is only one namespace
+    Scope(String),
+    EndScope,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DataItem {
+    Expr(Expr),
+    String(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AddressSize {
+    ZeroPage,
+    Absolute,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+    Implied,
+    Accumulator,
+    Immediate(Expr),
+    Direct(Expr, Option<AddressSize>),
+    DirectX(Expr, Option<AddressSize>),
+    DirectY(Expr, Option<AddressSize>),
+    Indirect(Expr),
+    IndirectX(Expr),
+    IndirectY(Expr),
+}
+
+pub fn parse_line(input: &str) -> Result<Line, String> {
+    let input = strip_comment(input).trim();
+    return match line(input) {
+        Result::Ok((_, line)) => Result::Ok(line),
+        Result::Err(nom::Err::Error(err)) | Result::Err(nom::Err::Failure(err)) => {
+            Result::Err(convert_error(input, err).trim_end().to_string())
+        }
+        Result::Err(nom::Err::Incomplete(needed)) => {
+            Result::Err(format!("incomplete input {:?}", needed))
+        }
+    };
+}
+
+fn strip_comment(input: &str) -> &str {
+    let mut in_string = false;
+    for (i, c) in input.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            ';' if !in_string => return &input[..i],
+            _ => {}
+        }
+    }
+    return input;
+}
+
+#[rustfmt::skip]
+fn line(input: &str) -> Res<&str, Line> {
+    return context(
+        "line",
+        tuple((
+            opt(terminated(identifier, pair(char(':'), not(char('='))))),
+            space0,
+            opt(statement),
+            space0,
+            eof,
+        ))
+    )(input).map(|(next_input, res)| {
+        (
+            next_input,
+            Line {
+                label: res.0.map(|l| l.to_string()),
+                statement: res.2,
+            },
+        )
+    });
+}
+
+fn statement(input: &str) -> Res<&str, Statement> {
+    return alt((directive, label_assignment, equate, instruction))(input);
+}
+
+#[rustfmt::skip]
+fn equate(input: &str) -> Res<&str, Statement> {
+    return context(
+        "equate",
+        tuple((
+            identifier,
+            char('=').delimited_by(space0),
+            expr
+        ))
+    )(input).map(|(next_input, res)| {
+        (next_input, Statement::Define(res.0.to_string(), res.2))
+    });
+}
+
+#[rustfmt::skip]
+fn label_assignment(input: &str) -> Res<&str, Statement> {
+    return context(
+        "label assignment",
+        tuple((
+            identifier,
+            tag(":=").delimited_by(space0),
+            expr
+        ))
+    )(input).map(|(next_input, res)| {
+        (next_input, Statement::Label(res.0.to_string(), res.2))
+    });
+}
+
+fn directive(input: &str) -> Res<&str, Statement> {
+    let (next_input, name) = context("directive", preceded(char('.'), alpha1))(input)?;
+    let (next_input, _) = space0(next_input)?;
+    return match name.to_lowercase().as_str() {
+        "define" | "set" => define_args(next_input),
+        "segment" => map(string, Statement::Segment)(next_input),
+        "org" => map(expr, Statement::Org)(next_input),
+        "byte" | "byt" | "db" => map(
+            separated_list1(char(',').delimited_by(space0), data_item),
+            Statement::Byte,
+        )(next_input),
+        "word" | "addr" | "dw" => map(
+            separated_list1(char(',').delimited_by(space0), expr),
+            Statement::Word,
+        )(next_input),
+        "res" | "ds" => map(
+            pair(expr, opt(preceded(char(',').delimited_by(space0), expr))),
+            |(count, fill)| Statement::Res(count, fill),
+        )(next_input),
+        "free" => map(
+            pair(expr, preceded(char(',').delimited_by(space0), expr)),
+            |(start, end)| Statement::Free(start, end),
+        )(next_input),
+        "verify" => map(
+            separated_list1(char(',').delimited_by(space0), expr),
+            Statement::Verify,
+        )(next_input),
+        "if" => map(expr, Statement::If)(next_input),
+        "ifdef" => map(identifier, |n| Statement::IfDef(n.to_string()))(next_input),
+        "ifndef" => map(identifier, |n| Statement::IfNDef(n.to_string()))(next_input),
+        "elseif" => map(expr, Statement::ElseIf)(next_input),
+        "else" => Result::Ok((next_input, Statement::Else)),
+        "endif" => Result::Ok((next_input, Statement::EndIf)),
+        "export" => map(
+            separated_list1(char(',').delimited_by(space0), identifier),
+            |names| Statement::Export(names.iter().map(|n| n.to_string()).collect()),
+        )(next_input),
+        "setcpu" => map(string, Statement::SetCpu)(next_input),
+        "charmap" => map(
+            pair(expr, preceded(char(',').delimited_by(space0), expr)),
+            |(c, byte)| Statement::Charmap(c, byte),
+        )(next_input),
+        "proc" => map(identifier, |n| Statement::Proc(n.to_string()))(next_input),
+        "endproc" => Result::Ok((next_input, Statement::EndProc)),
+        "scope" => map(identifier, |n| Statement::Scope(n.to_string()))(next_input),
+        "endscope" => Result::Ok((next_input, Statement::EndScope)),
+        _ => context("unknown directive", nom::combinator::fail)(input),
+    };
+}
+
+#[rustfmt::skip]
+fn define_args(input: &str) -> Res<&str, Statement> {
+    return tuple((
+        identifier,
+        alt((value((), char('=').delimited_by(space0)), value((), space1))),
+        expr,
+    ))(input).map(|(next_input, res)| {
+        (next_input, Statement::Define(res.0.to_string(), res.2))
+    });
+}
+
+fn string(input: &str) -> Res<&str, String> {
+    return map(
+        delimited(char('"'), opt(is_not("\"")), char('"')),
+        |s: Option<&str>| s.unwrap_or("").to_string(),
+    )(input);
+}
+
+fn data_item(input: &str) -> Res<&str, DataItem> {
+    return alt((map(string, DataItem::String), map(expr, DataItem::Expr)))(input);
+}
+
+#[rustfmt::skip]
+fn instruction(input: &str) -> Res<&str, Statement> {
+    return context(
+        "instruction",
+        pair(
+            alpha1,
+            alt((
+                preceded(space1, operand),
+                value(Operand::Implied, space0),
+            )),
+        )
+    )(input).map(|(next_input, res)| {
+        (next_input, Statement::Instruction(res.0.to_lowercase(), res.1))
+    });
+}
+
+fn index_register(reg: char) -> impl FnMut(&str) -> Res<&str, char> {
+    return move |input| {
+        preceded(
+            char(',').delimited_by(space0),
+            alt((char(reg), char(reg.to_ascii_uppercase()))),
+        )(input)
+    };
+}
+
+fn address_size(input: &str) -> Res<&str, AddressSize> {
+    return alt((
+        value(AddressSize::ZeroPage, tag_no_case("z:")),
+        value(AddressSize::Absolute, tag_no_case("a:")),
+    ))(input);
+}
+
+#[rustfmt::skip]
+fn operand(input: &str) -> Res<&str, Operand> {
+    return context(
+        "operand",
+        alt((
+            map(preceded(char('#'), expr.preceded_by(space0)), Operand::Immediate),
+            map(
+                terminated(
+                    delimited(char('(').terminated(space0), expr, index_register('x')),
+                    char(')').preceded_by(space0),
+                ),
+                Operand::IndirectX,
+            ),
+            map(
+                terminated(
+                    delimited(char('(').terminated(space0), expr, char(')').preceded_by(space0)),
+                    index_register('y'),
+                ),
+                Operand::IndirectY,
+            ),
+            map(
+                terminated(
+                    delimited(char('(').terminated(space0), expr, char(')').preceded_by(space0)),
+                    pair(space0, eof),
+                ),
+                Operand::Indirect,
+            ),
+            map(
+                terminated(pair(opt(address_size), expr), index_register('x')),
+                |(size, e)| Operand::DirectX(e, size),
+            ),
+            map(
+                terminated(pair(opt(address_size), expr), index_register('y')),
+                |(size, e)| Operand::DirectY(e, size),
+            ),
+            value(
+                Operand::Accumulator,
+                terminated(alt((char('a'), char('A'))), pair(space0, eof)),
+            ),
+            map(pair(opt(address_size), expr), |(size, e)| Operand::Direct(e, size)),
+        ))
+    )(input);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assemble::expression::BinaryOp;
+
+    fn instr(mnemonic: &str, operand: Operand) -> Line {
+        return Line {
+            label: Option::None,
+            statement: Option::Some(Statement::Instruction(mnemonic.to_string(), operand)),
+        };
+    }
+
+    #[test]
+    fn test_parse_instruction() {
+        assert_eq!(parse_line("    rts"), Ok(instr("rts", Operand::Implied)));
+        assert_eq!(parse_line("asl a"), Ok(instr("asl", Operand::Accumulator)));
+        assert_eq!(
+            parse_line("lda #$00 ; comment"),
+            Ok(instr("lda", Operand::Immediate(Expr::Number(0))))
+        );
+        assert_eq!(
+            parse_line("sta ($10),y"),
+            Ok(instr("sta", Operand::IndirectY(Expr::Number(0x10))))
+        );
+        assert_eq!(
+            parse_line("lda ($10,x)"),
+            Ok(instr("lda", Operand::IndirectX(Expr::Number(0x10))))
+        );
+        assert_eq!(
+            parse_line("jmp ($fffc)"),
+            Ok(instr("jmp", Operand::Indirect(Expr::Number(0xfffc))))
+        );
+        assert_eq!(
+            parse_line("    jmp prgrom1::reset"),
+            Ok(instr(
+                "jmp",
+                Operand::Direct(Expr::Symbol("reset".to_string()), Option::None)
+            ))
+        );
+        assert_eq!(
+            parse_line("lda a:$0010,x"),
+            Ok(instr(
+                "lda",
+                Operand::DirectX(Expr::Number(0x10), Option::Some(AddressSize::Absolute))
+            ))
+        );
+        assert_eq!(
+            parse_line("LDX table, Y"),
+            Ok(instr(
+                "ldx",
+                Operand::DirectY(Expr::Symbol("table".to_string()), Option::None)
+            ))
+        );
+    }
+
+    #[test]
+    fn test_parse_label_and_directives() {
+        assert_eq!(
+            parse_line("reset: sei"),
+            Ok(Line {
+                label: Option::Some("reset".to_string()),
+                statement: Option::Some(Statement::Instruction(
+                    "sei".to_string(),
+                    Operand::Implied
+                )),
+            })
+        );
+        assert_eq!(
+            parse_line(".define PPU_CTRL                  = $2000"),
+            Ok(Line {
+                label: Option::None,
+                statement: Option::Some(Statement::Define(
+                    "PPU_CTRL".to_string(),
+                    Expr::Number(0x2000)
+                )),
+            })
+        );
+        assert_eq!(
+            parse_line(".byte \"NES;\", $1A"),
+            Ok(Line {
+                label: Option::None,
+                statement: Option::Some(Statement::Byte(vec![
+                    DataItem::String("NES;".to_string()),
+                    DataItem::Expr(Expr::Number(0x1a)),
+                ])),
+            })
+        );
+        assert_eq!(
+            parse_line(".ifdef DEBUG"),
+            Ok(Line {
+                label: Option::None,
+                statement: Option::Some(Statement::IfDef("DEBUG".to_string())),
+            })
+        );
+        assert_eq!(
+            parse_line(".free $9f00, $9fff"),
+            Ok(Line {
+                label: Option::None,
+                statement: Option::Some(Statement::Free(
+                    Expr::Number(0x9f00),
+                    Expr::Number(0x9fff)
+                )),
+            })
+        );
+        assert_eq!(
+            parse_line(".verify $A9, $00"),
+            Ok(Line {
+                label: Option::None,
+                statement: Option::Some(Statement::Verify(vec![
+                    Expr::Number(0xa9),
+                    Expr::Number(0x00)
+                ])),
+            })
+        );
+        assert_eq!(
+            parse_line("table_middle := * + 2"),
+            Ok(Line {
+                label: Option::None,
+                statement: Option::Some(Statement::Label(
+                    "table_middle".to_string(),
+                    Expr::Binary(
+                        BinaryOp::Add,
+                        Box::new(Expr::CurrentAddress),
+                        Box::new(Expr::Number(2))
+                    )
+                )),
+            })
+        );
+        assert_eq!(
+            parse_line(".export reset, nmi"),
+            Ok(Line {
+                label: Option::None,
+                statement: Option::Some(Statement::Export(vec![
+                    "reset".to_string(),
+                    "nmi".to_string()
+                ])),
+            })
+        );
+        assert_eq!(
+            parse_line(".proc reset"),
+            Ok(Line {
+                label: Option::None,
+                statement: Option::Some(Statement::Proc("reset".to_string())),
+            })
+        );
+        assert!(parse_line(".bogus 1").is_err());
+    }
+}
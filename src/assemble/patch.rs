@@ -0,0 +1,387 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    decode::{decode_opcode, AddressingMode},
+    disassemble::{find_checksum_routines, NesHeader},
+};
+
+use super::{
+    assembler::{relax_branches, Assembler},
+    linker::link_absolute_segment,
+    AssembleError,
+};
+
+const NES_HEADER_LENGTH: usize = 16;
+const NES_TRAINER_LENGTH: usize = 512;
+const PRG_ROM_START_ADDRESS: i64 = 0x8000;
+const JMP_ABS: u8 = 0x4c;
+const JMP_ABS_LEN: usize = 3;
+
+/// Where CPU addresses of a ROM with at most 32k of PRG ROM are in the file, a
+/// single 16k page being mirrored at $c000.
+pub struct RomLayout {
+    prg_rom_start: usize,
+    prg_rom_size: usize,
+}
+
+impl RomLayout {
+    pub fn new(rom: &[u8]) -> Result<RomLayout, AssembleError> {
+        let header = NesHeader::parse(rom).map_err(|err| AssembleError::Patch(err.to_string()))?;
+        if header.prg_rom_size == 0 || header.prg_rom_size > 0x8000 {
+            return Result::Err(AssembleError::Patch(format!(
+                "only ROMs with 16k or 32k of PRG ROM can be patched, this one has {} bytes",
+                header.prg_rom_size
+            )));
+        }
+        let trainer_len = if header.trainer {
+            NES_TRAINER_LENGTH
+        } else {
+            0
+        };
+        let prg_rom_start = NES_HEADER_LENGTH + trainer_len;
+        if rom.len() < prg_rom_start + header.prg_rom_size {
+            return Result::Err(AssembleError::Patch(
+                "ROM is shorter than its header says".to_string(),
+            ));
+        }
+        return Result::Ok(RomLayout {
+            prg_rom_start,
+            prg_rom_size: header.prg_rom_size,
+        });
+    }
+
+    pub fn to_offset(&self, addr: i64) -> Result<usize, AssembleError> {
+        if !(PRG_ROM_START_ADDRESS..=0xffff).contains(&addr) {
+            return Result::Err(AssembleError::Patch(format!(
+                "${:04x} is not in PRG ROM",
+                addr
+            )));
+        }
+        let prg_offset = (addr - PRG_ROM_START_ADDRESS) as usize % self.prg_rom_size;
+        return Result::Ok(self.prg_rom_start + prg_offset);
+    }
+
+    /// Address of `offset` in PRG ROM, the one at $c000 for a mirrored 16k page.
+    pub fn to_addr(&self, offset: usize) -> i64 {
+        return 0x10000 - self.prg_rom_size as i64 + (offset - self.prg_rom_start) as i64;
+    }
+
+    /// offset just past the PRG ROM
+    pub fn prg_rom_end(&self) -> usize {
+        return self.prg_rom_start + self.prg_rom_size;
+    }
+}
+
+/// Length of the code at `offset` up to and including the first `rts`, `rti` or
+/// `jmp`. Stops early at bytes that are not opcodes.
+pub fn routine_len(rom: &[u8], offset: usize, end: usize) -> usize {
+    let mut len = 0;
+    while offset + len < end {
+        let (mnemonic, mode) = match decode_opcode(rom[offset + len]) {
+            Option::Some(op) => op,
+            Option::None => break,
+        };
+        len += 1 + mode.operand_len();
+        if matches!(mnemonic, "rts" | "rti" | "jmp") {
+            break;
+        }
+    }
+    return len.min(end - offset);
+}
+
+/// Length of the whole instructions at `addr` that cover at least `min_len` bytes,
+/// i.e. the code displaced by writing `min_len` bytes there. They can't go past an
+/// `rts`, `rti` or `jmp`, what follows one isn't part of the same code.
+pub fn displaced_len(
+    rom: &[u8],
+    layout: &RomLayout,
+    addr: i64,
+    min_len: usize,
+) -> Result<usize, AssembleError> {
+    let offset = layout.to_offset(addr)?;
+    let mut len = 0;
+    while len < min_len {
+        let instr_addr = addr + len as i64;
+        let op = if offset + len < layout.prg_rom_end() {
+            decode_opcode(rom[offset + len])
+        } else {
+            Option::None
+        };
+        match op {
+            Option::Some((_, AddressingMode::Relative)) => {
+                return Result::Err(AssembleError::Patch(format!(
+                    "the branch at ${:04x} can't be moved",
+                    instr_addr
+                )));
+            }
+            Option::Some((mnemonic, mode)) => {
+                len += 1 + mode.operand_len();
+                if len < min_len && matches!(mnemonic, "rts" | "rti" | "jmp") {
+                    return Result::Err(AssembleError::HookTooShort(addr, len, min_len));
+                }
+            }
+            Option::None => {
+                return Result::Err(AssembleError::Patch(format!(
+                    "no instruction at ${:04x}",
+                    instr_addr
+                )));
+            }
+        }
+    }
+    return Result::Ok(len);
+}
+
+struct FreeBlock {
+    end: i64,
+    /// first address not used by a patch yet
+    next: i64,
+}
+
+/// Assembles `source` over a copy of `rom`. Every segment must be placed with
+/// `.org` and has to fit either in the routine it overwrites or in a `.free`
+/// block. With `allow_grow` segments that don't fit are moved to a free block
+/// and a `jmp` to them is left at the original address. With `long_branches`
+/// branches that can't reach their target, say after being moved, become the
+/// inverted branch over a `jmp`.
+pub fn patch_rom(
+    rom: &[u8],
+    source: &str,
+    defines: HashMap<String, i64>,
+    allow_grow: bool,
+    long_branches: bool,
+) -> Result<Vec<u8>, AssembleError> {
+    return relax_branches(long_branches, |lines| {
+        return patch_rom_with(rom, source, defines.clone(), allow_grow, lines);
+    });
+}
+
+/// [`patch_rom`] with the branches on `long_branches` made long.
+fn patch_rom_with(
+    rom: &[u8],
+    source: &str,
+    defines: HashMap<String, i64>,
+    allow_grow: bool,
+    long_branches: &HashSet<usize>,
+) -> Result<Vec<u8>, AssembleError> {
+    let layout = RomLayout::new(rom)?;
+    let object = Assembler::new(defines.clone())
+        .with_long_branches(long_branches)
+        .assemble(source)?;
+
+    let mut free_blocks = object
+        .free_blocks
+        .iter()
+        .map(|(start, end)| FreeBlock {
+            end: *end,
+            next: *start,
+        })
+        .collect::<Vec<FreeBlock>>();
+    let mut too_large = Vec::new();
+    for segment in &object.segments {
+        let len = segment.data.len();
+        if len == 0 {
+            continue;
+        }
+        let org = segment.org.ok_or_else(|| {
+            AssembleError::Patch(format!(
+                "segment \"{}\" has no .org, patches must say where they go",
+                segment.name
+            ))
+        })?;
+        let capacity = match free_blocks
+            .iter_mut()
+            .find(|b| (b.next..=b.end).contains(&org))
+        {
+            Option::Some(block) => {
+                let capacity = (block.end - org + 1) as usize;
+                if len <= capacity {
+                    block.next = org + len as i64;
+                }
+                capacity
+            }
+            Option::None => {
+                let offset = layout.to_offset(org)?;
+                routine_len(rom, offset, layout.prg_rom_end())
+            }
+        };
+        if len > capacity {
+            if !allow_grow || capacity < JMP_ABS_LEN {
+                return Result::Err(AssembleError::PatchTooLarge(
+                    segment.name.clone(),
+                    org,
+                    len,
+                    capacity,
+                ));
+            }
+            too_large.push((segment.name.clone(), org, len));
+        }
+    }
+
+    let mut trampolines = Vec::new();
+    let mut assembler = Assembler::new(defines).with_long_branches(long_branches);
+    for (name, org, len) in &too_large {
+        let block = free_blocks
+            .iter_mut()
+            .find(|b| b.end - b.next + 1 >= *len as i64)
+            .ok_or_else(|| {
+                AssembleError::Patch(format!(
+                    "no .free block has room for the {} bytes of segment \"{}\"",
+                    len, name
+                ))
+            })?;
+        assembler = assembler.with_org_override(name, block.next);
+        trampolines.push((*org, block.next));
+        block.next += *len as i64;
+    }
+    let object = if too_large.is_empty() {
+        object
+    } else {
+        assembler.assemble(source)?
+    };
+
+    let mut result = rom.to_vec();
+    for segment in &object.segments {
+        if let Option::Some(org) = segment.org {
+            write(
+                &mut result,
+                &layout,
+                org,
+                &link_absolute_segment(&object, segment)?,
+            )?;
+        }
+    }
+    for (from, to) in trampolines {
+        write(&mut result, &layout, from, &jmp_abs(to))?;
+    }
+    return Result::Ok(result);
+}
+
+/// Warnings for each checksum routine of `rom` that sums bytes `patched` changes,
+/// since the check fails unless the checksum or the routine is patched as well.
+pub fn checksum_warnings(rom: &[u8], patched: &[u8]) -> Result<Vec<String>, AssembleError> {
+    let layout = RomLayout::new(rom)?;
+    let changed = (layout.prg_rom_start..layout.prg_rom_end())
+        .filter(|offset| rom[*offset] != patched[*offset])
+        .map(|offset| layout.to_addr(offset))
+        .collect::<Vec<i64>>();
+    let mut warnings = Vec::new();
+    for routine in find_checksum_routines(rom, layout.prg_rom_start, layout.prg_rom_end()) {
+        let checked = changed
+            .iter()
+            .filter(|addr| **addr >= routine.start as i64)
+            .count();
+        if checked > 0 {
+            warnings.push(format!(
+                "the patch changes {} bytes {} at ${:04x} checksums (${:04x}-$ffff), the check \
+                 will fail unless it is patched too",
+                checked,
+                routine.name,
+                layout.to_addr(routine.offset),
+                routine.start
+            ));
+        }
+    }
+    return Result::Ok(warnings);
+}
+
+fn jmp_abs(addr: i64) -> Vec<u8> {
+    return vec![JMP_ABS, (addr & 0xff) as u8, ((addr >> 8) & 0xff) as u8];
+}
+
+fn write(rom: &mut [u8], layout: &RomLayout, addr: i64, data: &[u8]) -> Result<(), AssembleError> {
+    let offset = layout.to_offset(addr)?;
+    if offset + data.len() > layout.prg_rom_end() {
+        return Result::Err(AssembleError::Patch(format!(
+            "patch at ${:04x} runs past the end of PRG ROM",
+            addr
+        )));
+    }
+    rom[offset..offset + data.len()].copy_from_slice(data);
+    return Result::Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 16k NROM with a 3 byte routine at $8000
+    fn rom() -> Vec<u8> {
+        let mut rom = vec![b'N', b'E', b'S', 0x1a, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        rom.resize(NES_HEADER_LENGTH + 0x4000, 0xff);
+        rom[16..19].copy_from_slice(&[0xa9, 0x01, 0x60]);
+        return rom;
+    }
+
+    #[test]
+    fn test_patch_in_place() {
+        let patched = patch_rom(
+            &rom(),
+            ".org $8000\nlda #2\nrts",
+            HashMap::new(),
+            false,
+            false,
+        )
+        .unwrap();
+        assert_eq!(patched[16..19], [0xa9, 0x02, 0x60]);
+        assert_eq!(patched.len(), rom().len());
+    }
+
+    #[test]
+    fn test_patch_too_large() {
+        let source = "
+            .free $9000, $90ff
+            .org $8000
+                lda #2
+                sta $10
+                rts
+        ";
+        let err = patch_rom(&rom(), source, HashMap::new(), false, false).unwrap_err();
+        assert!(matches!(err, AssembleError::PatchTooLarge(_, 0x8000, 5, 3)));
+
+        let patched = patch_rom(&rom(), source, HashMap::new(), true, false).unwrap();
+        assert_eq!(patched[16..19], [0x4c, 0x00, 0x90]);
+        assert_eq!(
+            patched[16 + 0x1000..16 + 0x1005],
+            [0xa9, 0x02, 0x85, 0x10, 0x60]
+        );
+    }
+
+    #[test]
+    fn test_patch_free_block() {
+        let source = "
+            .free $9000, $9003
+            .segment \"HOOK\"
+            .org $9000
+                jsr $8000
+                rts
+        ";
+        let patched = patch_rom(&rom(), source, HashMap::new(), false, false).unwrap();
+        assert_eq!(patched[16 + 0x1000..16 + 0x1004], [0x20, 0x00, 0x80, 0x60]);
+
+        let source = source.replace("$9003", "$9002");
+        assert!(matches!(
+            patch_rom(&rom(), &source, HashMap::new(), false, false),
+            Err(AssembleError::PatchTooLarge(_, 0x9000, 4, 3))
+        ));
+    }
+
+    #[test]
+    fn test_checksum_warnings() {
+        let mut rom = rom();
+        // lda #$e0 / sta $11 then the clc / adc loop, at $8100
+        rom[16 + 0x100..16 + 0x114].copy_from_slice(&[
+            0xa9, 0xe0, 0x85, 0x11, 0xb1, 0x10, 0x18, 0x65, 0x12, 0x85, 0x12, 0xc8, 0xd0, 0xf6,
+            0xe6, 0x11, 0xd0, 0xf2, 0x60, 0x60,
+        ]);
+        let source = ".org $8000\nlda #2\nrts";
+        let patched = patch_rom(&rom, source, HashMap::new(), false, false).unwrap();
+        // $8000 is mirrored at $c000, before what is checked
+        assert!(checksum_warnings(&rom, &patched).unwrap().is_empty());
+
+        rom[16 + 0x101] = 0xc0;
+        let patched = patch_rom(&rom, source, HashMap::new(), false, false).unwrap();
+        let warnings = checksum_warnings(&rom, &patched).unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].starts_with("the patch changes 1 bytes checksum_add at $c104"));
+    }
+}
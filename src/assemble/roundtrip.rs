@@ -0,0 +1,133 @@
+//! Property test assembling random instruction sequences, disassembling the
+//! bytes linearly and assembling the result again, which has to give the same
+//! bytes. Covers every documented opcode, operand widths and branches in both
+//! directions, written both the way `list` and the way `d` write them. A whole
+//! ROM disassembled by `d` has to link back to the same file with the linker config
+//! written with it.
+
+use std::collections::HashMap;
+
+use proptest::prelude::*;
+
+use super::{assembler::Assembler, linker::link, AssembleError};
+use crate::{
+    decode::{decode, AddressingMode},
+    disassemble::{Instruction, NesDisassembler},
+    linker_file::parse_linker_file,
+    opcodes::{Opcode, OPCODES},
+};
+
+const ORG: u16 = 0x8000;
+
+fn assemble_str(source: &str) -> Result<Vec<u8>, AssembleError> {
+    let object = Assembler::new(HashMap::new()).assemble(source)?;
+    return link(&object, Option::None).map(|mut files| files.remove(0).data);
+}
+
+/// Source for `opcode` with `operand`, written the way a person would rather than
+/// the way the disassembler does: forced widths and branches relative to `*`.
+fn source_line(opcode: &Opcode, operand: u16) -> String {
+    let m = opcode.mnemonic;
+    let zp = operand & 0xff;
+    return match opcode.mode {
+        AddressingMode::Implied => m.to_string(),
+        AddressingMode::Accumulator => format!("{} a", m),
+        AddressingMode::Immediate => format!("{} #${:02x}", m, zp),
+        AddressingMode::ZeroPage => format!("{} z:${:02x}", m, zp),
+        AddressingMode::ZeroPageX => format!("{} z:${:02x},x", m, zp),
+        AddressingMode::ZeroPageY => format!("{} z:${:02x},y", m, zp),
+        AddressingMode::Absolute => format!("{} a:${:04x}", m, operand),
+        AddressingMode::AbsoluteX => format!("{} a:${:04x},x", m, operand),
+        AddressingMode::AbsoluteY => format!("{} a:${:04x},y", m, operand),
+        AddressingMode::Indirect => format!("{} (${:04x})", m, operand),
+        AddressingMode::IndirectX => format!("{} (${:02x},x)", m, zp),
+        AddressingMode::IndirectY => format!("{} (${:02x}),y", m, zp),
+        AddressingMode::Relative => format!("{} *{:+}", m, 2 + zp as u8 as i8 as i32),
+    };
+}
+
+fn encode(opcode: &Opcode, operand: u16) -> Vec<u8> {
+    let [low, high] = operand.to_le_bytes();
+    return [opcode.opcode, low, high][..opcode.length()].to_vec();
+}
+
+fn instructions() -> impl Strategy<Value = Vec<(&'static Opcode, u16)>> {
+    let documented = OPCODES
+        .iter()
+        .filter(|o| !o.illegal)
+        .collect::<Vec<&Opcode>>();
+    return prop::collection::vec((prop::sample::select(documented), any::<u16>()), 1..64);
+}
+
+proptest! {
+    #[test]
+    fn test_roundtrip(instrs in instructions()) {
+        let mut source = format!(".org ${:04x}\n", ORG);
+        let mut expected = Vec::new();
+        for (opcode, operand) in &instrs {
+            source += &format!("{}\n", source_line(opcode, *operand));
+            expected.extend(encode(opcode, *operand));
+        }
+        let assembled = assemble_str(&source).map_err(|err| {
+            TestCaseError::fail(format!("{}\n{}", err, source))
+        })?;
+        prop_assert_eq!(&assembled, &expected, "{}", source);
+
+        for nes in [false, true] {
+            let mut disassembled = format!(".org ${:04x}\n", ORG);
+            let mut offset = 0;
+            while offset < assembled.len() {
+                let addr = ORG + offset as u16;
+                let instr = decode(&assembled[offset..], addr)
+                    .ok_or_else(|| TestCaseError::fail(format!("can't decode at {}", offset)))?;
+                // `d` writes the byte after `brk` separately, it isn't part of the
+                // instruction here
+                let text = match Instruction::decode(&assembled[offset..], addr) {
+                    Option::Some((nes_instr, len)) if nes && len == instr.size() => {
+                        nes_instr.to_string()
+                    }
+                    _ => instr.to_string(),
+                };
+                disassembled += &format!("{}\n", text);
+                offset += instr.size();
+            }
+            let reassembled = assemble_str(&disassembled).map_err(|err| {
+                TestCaseError::fail(format!("{}\n{}", err, disassembled))
+            })?;
+            prop_assert_eq!(&reassembled, &assembled, "{}", disassembled);
+        }
+    }
+}
+
+#[test]
+fn test_rom_roundtrip() {
+    let mut rom = b"NES\x1a\x01\x00".to_vec();
+    rom.resize(16, 0);
+    let mut prg = vec![0xff; 0x4000];
+    #[rustfmt::skip]
+    let code = [
+        // ldx #$00 / lda $8100,x / sta $0200,x / inx / cpx #$04 / bne -11
+        0xa2, 0x00, 0xbd, 0x00, 0x81, 0x9d, 0x00, 0x02, 0xe8, 0xe0, 0x04, 0xd0, 0xf5,
+        // a pointer to $c000, the mirror of $8000
+        0xa9, 0x00, 0x85, 0x10, 0xa9, 0xc0, 0x85, 0x11,
+        // jsr $8020 / jmp $c018, itself through the mirror
+        0x20, 0x20, 0x80, 0x4c, 0x18, 0xc0,
+    ];
+    prg[..code.len()].copy_from_slice(&code);
+    prg[0x20] = 0x60;
+    prg[0x100..0x104].copy_from_slice(&[1, 2, 3, 4]);
+    prg[0x3ffa..].copy_from_slice(&[0x20, 0x80, 0x00, 0x80, 0x20, 0x80]);
+    rom.extend(prg);
+
+    let d = NesDisassembler::disassemble(rom.clone()).unwrap();
+    let mut source = Vec::new();
+    d.write(&mut source).unwrap();
+    let mut config = Vec::new();
+    d.write_linker_config(&mut config).unwrap();
+    let source = String::from_utf8(source).unwrap();
+    let config = parse_linker_file(&String::from_utf8(config).unwrap()).unwrap();
+
+    let object = Assembler::new(HashMap::new()).assemble(&source).unwrap();
+    let files = link(&object, Option::Some(&config)).unwrap();
+    assert_eq!(files[0].data, rom, "{}", source);
+}
@@ -0,0 +1,456 @@
+//! Decoder from bytes to instructions, using the table in [`crate::opcodes`].
+//! Doesn't need `std` or an allocator, so it can be used without the rest of the
+//! crate by building with `default-features = false`.
+
+use core::fmt;
+
+use crate::opcodes::{lookup, Opcode, OPCODES};
+
+// https://www.masswerk.at/6502/6502_instruction_set.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AddressingMode {
+    Implied,
+    Accumulator,
+    Immediate,
+    ZeroPage,
+    ZeroPageX,
+    ZeroPageY,
+    Absolute,
+    AbsoluteX,
+    AbsoluteY,
+    Indirect,
+    IndirectX,
+    IndirectY,
+    Relative,
+}
+
+impl AddressingMode {
+    pub fn operand_len(&self) -> usize {
+        return match self {
+            AddressingMode::Implied | AddressingMode::Accumulator => 0,
+            AddressingMode::Immediate
+            | AddressingMode::ZeroPage
+            | AddressingMode::ZeroPageX
+            | AddressingMode::ZeroPageY
+            | AddressingMode::IndirectX
+            | AddressingMode::IndirectY
+            | AddressingMode::Relative => 1,
+            AddressingMode::Absolute
+            | AddressingMode::AbsoluteX
+            | AddressingMode::AbsoluteY
+            | AddressingMode::Indirect => 2,
+        };
+    }
+
+    /// The zero page mode an assembler picks instead of this one for an operand
+    /// that fits in a byte.
+    pub fn zero_page(&self) -> Option<AddressingMode> {
+        return match self {
+            AddressingMode::Absolute => Option::Some(AddressingMode::ZeroPage),
+            AddressingMode::AbsoluteX => Option::Some(AddressingMode::ZeroPageX),
+            AddressingMode::AbsoluteY => Option::Some(AddressingMode::ZeroPageY),
+            _ => Option::None,
+        };
+    }
+}
+
+pub fn find_opcode(mnemonic: &str, mode: AddressingMode) -> Option<u8> {
+    return OPCODES
+        .iter()
+        .find(|o| !o.illegal && o.mnemonic == mnemonic && o.mode == mode)
+        .map(|o| o.opcode);
+}
+
+pub fn is_mnemonic(mnemonic: &str) -> bool {
+    return OPCODES.iter().any(|o| !o.illegal && o.mnemonic == mnemonic);
+}
+
+/// Mnemonic and addressing mode of an opcode byte, for reading existing code.
+pub fn decode_opcode(op: u8) -> Option<(&'static str, AddressingMode)> {
+    return lookup(op)
+        .filter(|o| !o.illegal)
+        .map(|o| (o.mnemonic, o.mode));
+}
+
+/// Mnemonic and addressing mode of an undocumented opcode, see [`decode_illegal`].
+pub fn decode_illegal_opcode(op: u8) -> Option<(&'static str, AddressingMode)> {
+    return lookup(op)
+        .filter(|o| o.illegal)
+        .map(|o| (o.mnemonic, o.mode));
+}
+
+/// An instruction decoded from bytes, see [`decode`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodedInstruction {
+    pub addr: u16,
+    pub opcode: u8,
+    pub mnemonic: &'static str,
+    pub mode: AddressingMode,
+    /// the operand bytes as a little endian value, 0 if there are none
+    pub operand: u16,
+}
+
+impl DecodedInstruction {
+    /// length in bytes including the opcode
+    pub fn size(&self) -> usize {
+        return 1 + self.mode.operand_len();
+    }
+
+    /// Whether assembling the mnemonic and operand gives back the same opcode. Not
+    /// so for undocumented opcodes that duplicate another, e.g. `nop` as $1a.
+    pub fn reassembles(&self) -> bool {
+        // documented opcodes are listed first
+        let canonical = OPCODES
+            .iter()
+            .find(|o| o.mnemonic == self.mnemonic && o.mode == self.mode)
+            .map(|o| o.opcode);
+        return canonical == Option::Some(self.opcode);
+    }
+
+    /// The opcode's entry in [`crate::opcodes::OPCODES`], with its timing.
+    pub fn info(&self) -> &'static Opcode {
+        return lookup(self.opcode).expect("decoded opcodes are in the table");
+    }
+
+    /// Fewest and most cycles the instruction takes. Indexing may or may not cross
+    /// a page, branches take a cycle more when taken and one more again when the
+    /// target is on another page than the next instruction.
+    pub fn cycles(&self) -> (u8, u8) {
+        let info = self.info();
+        if self.mode == AddressingMode::Relative {
+            let next = self.addr.wrapping_add(2);
+            let crosses = self.target().is_some_and(|target| target >> 8 != next >> 8);
+            return (info.cycles, info.cycles + 1 + crosses as u8);
+        }
+        return (info.cycles, info.cycles + info.page_cross_penalty as u8);
+    }
+
+    /// Address a branch goes to, or the operand address of a jump.
+    pub fn target(&self) -> Option<u16> {
+        return match self.mode {
+            AddressingMode::Relative => Option::Some(
+                self.addr
+                    .wrapping_add(2)
+                    .wrapping_add(self.operand as u8 as i8 as u16),
+            ),
+            AddressingMode::Absolute if matches!(self.mnemonic, "jmp" | "jsr") => {
+                Option::Some(self.operand)
+            }
+            _ => Option::None,
+        };
+    }
+
+    /// The instruction's bytes, of which the first [`DecodedInstruction::size`] are
+    /// used.
+    pub fn encode(&self) -> [u8; 3] {
+        let [low, high] = self.operand.to_le_bytes();
+        return [self.opcode, low, high];
+    }
+
+    /// The same instruction placed at `addr`. Branches are re-encoded to still go to
+    /// the same target.
+    pub fn relocate(&self, addr: u16) -> Result<DecodedInstruction, RelocateError> {
+        let mut result = DecodedInstruction { addr, ..*self };
+        if let Option::Some(target) = self.target() {
+            if self.mode == AddressingMode::Relative {
+                result.operand = branch_operand(addr, target)?;
+            }
+        }
+        return Result::Ok(result);
+    }
+
+    /// The same instruction with `operand`, the target for branches. Absolute
+    /// operands in the zero page use the shorter zero page form if the instruction
+    /// has one, zero page ones above it the absolute form.
+    pub fn with_operand(&self, operand: u16) -> Result<DecodedInstruction, RelocateError> {
+        let out_of_range = RelocateError::OperandOutOfRange {
+            addr: self.addr,
+            operand,
+        };
+        let mode = match self.mode {
+            AddressingMode::Implied | AddressingMode::Accumulator => return Result::Ok(*self),
+            AddressingMode::Relative => {
+                return Result::Ok(DecodedInstruction {
+                    operand: branch_operand(self.addr, operand)?,
+                    ..*self
+                });
+            }
+            AddressingMode::Immediate | AddressingMode::IndirectX | AddressingMode::IndirectY
+                if operand > 0xff =>
+            {
+                return Result::Err(out_of_range);
+            }
+            mode if operand <= 0xff => zero_page_mode(mode)
+                .filter(|m| find_opcode(self.mnemonic, *m).is_some())
+                .unwrap_or(mode),
+            mode => absolute_mode(mode),
+        };
+        let opcode = find_opcode(self.mnemonic, mode).ok_or(out_of_range)?;
+        return Result::Ok(DecodedInstruction {
+            opcode,
+            mode,
+            operand,
+            ..*self
+        });
+    }
+}
+
+/// Why an instruction can't be moved or given another operand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelocateError {
+    /// no documented opcode at the address
+    InvalidOpcode(u16),
+    /// the branch at `addr` can't reach `target`
+    BranchOutOfRange { addr: u16, target: u16 },
+    /// the instruction at `addr` has no addressing mode taking `operand`
+    OperandOutOfRange { addr: u16, operand: u16 },
+    /// the output is shorter than the code
+    OutputTooSmall,
+}
+
+impl fmt::Display for RelocateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            RelocateError::InvalidOpcode(addr) => write!(f, "no instruction at ${:04x}", addr),
+            RelocateError::BranchOutOfRange { addr, target } => {
+                write!(f, "the branch at ${:04x} can't reach ${:04x}", addr, target)
+            }
+            RelocateError::OperandOutOfRange { addr, operand } => write!(
+                f,
+                "the instruction at ${:04x} can't take ${:04x} as operand",
+                addr, operand
+            ),
+            RelocateError::OutputTooSmall => write!(f, "output too small for the code"),
+        };
+    }
+}
+
+fn branch_operand(addr: u16, target: u16) -> Result<u16, RelocateError> {
+    let distance = target as i32 - (addr as i32 + 2);
+    if !(i8::MIN as i32..=i8::MAX as i32).contains(&distance) {
+        return Result::Err(RelocateError::BranchOutOfRange { addr, target });
+    }
+    return Result::Ok(distance as i8 as u8 as u16);
+}
+
+fn zero_page_mode(mode: AddressingMode) -> Option<AddressingMode> {
+    return match mode {
+        AddressingMode::Absolute => Option::Some(AddressingMode::ZeroPage),
+        AddressingMode::AbsoluteX => Option::Some(AddressingMode::ZeroPageX),
+        AddressingMode::AbsoluteY => Option::Some(AddressingMode::ZeroPageY),
+        _ => Option::None,
+    };
+}
+
+fn absolute_mode(mode: AddressingMode) -> AddressingMode {
+    return match mode {
+        AddressingMode::ZeroPage => AddressingMode::Absolute,
+        AddressingMode::ZeroPageX => AddressingMode::AbsoluteX,
+        AddressingMode::ZeroPageY => AddressingMode::AbsoluteY,
+        mode => mode,
+    };
+}
+
+/// Copies the code in `bytes`, which is at `from`, into `out` so it runs at `to`.
+/// Branches and absolute operands pointing into the code itself are moved along,
+/// everything else keeps pointing where it did. Instructions keep their size.
+/// Returns the number of bytes written.
+pub fn relocate_code(
+    bytes: &[u8],
+    from: u16,
+    to: u16,
+    out: &mut [u8],
+) -> Result<usize, RelocateError> {
+    if out.len() < bytes.len() {
+        return Result::Err(RelocateError::OutputTooSmall);
+    }
+    let end = from as usize + bytes.len();
+    let moved = |addr: u16| -> u16 {
+        if (from as usize..end).contains(&(addr as usize)) {
+            return addr.wrapping_sub(from).wrapping_add(to);
+        }
+        return addr;
+    };
+
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let addr = from.wrapping_add(offset as u16);
+        let instr = decode(&bytes[offset..], addr).ok_or(RelocateError::InvalidOpcode(addr))?;
+        let mut relocated = DecodedInstruction {
+            addr: moved(addr),
+            ..instr
+        };
+        match instr.mode {
+            AddressingMode::Relative => {
+                let target = moved(instr.target().unwrap_or(addr));
+                relocated.operand = branch_operand(relocated.addr, target)?;
+            }
+            AddressingMode::Absolute
+            | AddressingMode::AbsoluteX
+            | AddressingMode::AbsoluteY
+            | AddressingMode::Indirect => relocated.operand = moved(instr.operand),
+            _ => {}
+        }
+        let size = instr.size();
+        out[offset..offset + size].copy_from_slice(&relocated.encode()[..size]);
+        offset += size;
+    }
+    return Result::Ok(offset);
+}
+
+impl fmt::Display for DecodedInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let m = self.mnemonic;
+        let v = self.operand;
+        let size = absolute_prefix(m, self.mode, v);
+        return match self.mode {
+            AddressingMode::Implied => write!(f, "{}", m),
+            AddressingMode::Accumulator => write!(f, "{} a", m),
+            AddressingMode::Immediate => write!(f, "{} #${:02x}", m, v),
+            AddressingMode::ZeroPage => write!(f, "{} ${:02x}", m, v),
+            AddressingMode::ZeroPageX => write!(f, "{} ${:02x},x", m, v),
+            AddressingMode::ZeroPageY => write!(f, "{} ${:02x},y", m, v),
+            AddressingMode::Absolute => write!(f, "{} {}${:04x}", m, size, v),
+            AddressingMode::AbsoluteX => write!(f, "{} {}${:04x},x", m, size, v),
+            AddressingMode::AbsoluteY => write!(f, "{} {}${:04x},y", m, size, v),
+            AddressingMode::Indirect => write!(f, "{} (${:04x})", m, v),
+            AddressingMode::IndirectX => write!(f, "{} (${:02x},x)", m, v),
+            AddressingMode::IndirectY => write!(f, "{} (${:02x}),y", m, v),
+            AddressingMode::Relative => write!(f, "{} ${:04x}", m, self.target().unwrap_or(0)),
+        };
+    }
+}
+
+/// `a:` if an assembler would shorten the operand of `mnemonic` in `mode` to zero
+/// page, which has to be written before it to keep the instruction's length.
+pub fn absolute_prefix(mnemonic: &str, mode: AddressingMode, operand: u16) -> &'static str {
+    return match mode.zero_page() {
+        Option::Some(zp_mode)
+            if operand <= 0xff
+                && OPCODES
+                    .iter()
+                    .any(|o| o.mnemonic == mnemonic && o.mode == zp_mode) =>
+        {
+            "a:"
+        }
+        _ => "",
+    };
+}
+
+/// Decodes the instruction at the start of `bytes`, which is at `addr`. `None` if
+/// the first byte isn't a documented opcode or the operand is cut off.
+pub fn decode(bytes: &[u8], addr: u16) -> Option<DecodedInstruction> {
+    let opcode = *bytes.first()?;
+    return decode_with(bytes, addr, decode_opcode(opcode)?);
+}
+
+/// Same as [`decode`] for the undocumented opcodes, the JAMs aside.
+pub fn decode_illegal(bytes: &[u8], addr: u16) -> Option<DecodedInstruction> {
+    let opcode = *bytes.first()?;
+    return decode_with(bytes, addr, decode_illegal_opcode(opcode)?);
+}
+
+fn decode_with(
+    bytes: &[u8],
+    addr: u16,
+    (mnemonic, mode): (&'static str, AddressingMode),
+) -> Option<DecodedInstruction> {
+    let opcode = bytes[0];
+    let operand = match mode.operand_len() {
+        0 => 0,
+        1 => *bytes.get(1)? as u16,
+        _ => u16::from_le_bytes([*bytes.get(1)?, *bytes.get(2)?]),
+    };
+    return Option::Some(DecodedInstruction {
+        addr,
+        opcode,
+        mnemonic,
+        mode,
+        operand,
+    });
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        let i = decode(&[0xbd, 0x00, 0x90, 0xff], 0x8000).unwrap();
+        assert_eq!(i.mnemonic, "lda");
+        assert_eq!(i.mode, AddressingMode::AbsoluteX);
+        assert_eq!(i.size(), 3);
+        assert_eq!(i.to_string(), "lda $9000,x");
+
+        let i = decode(&[0xd0, 0xfe], 0x8010).unwrap();
+        assert_eq!(i.target(), Option::Some(0x8010));
+        assert_eq!(i.to_string(), "bne $8010");
+
+        assert!(decode(&[0x20, 0x00], 0x8000).is_none());
+        assert!(decode(&[0x02], 0x8000).is_none());
+        assert!(decode(&[], 0x8000).is_none());
+    }
+
+    #[test]
+    fn test_decode_illegal() {
+        let i = decode_illegal(&[0xa7, 0x10], 0x8000).unwrap();
+        assert_eq!(i.to_string(), "lax $10");
+        assert!(i.reassembles());
+        assert!(!decode_illegal(&[0x1a], 0x8000).unwrap().reassembles());
+        assert!(!decode_illegal(&[0xeb, 0x01], 0x8000).unwrap().reassembles());
+        assert!(decode_illegal(&[0xa9, 0x01], 0x8000).is_none());
+
+        // every opcode but the 12 JAMs decodes one way or the other
+        let decoded = (0..=255u8)
+            .filter(|op| decode_opcode(*op).is_some() || decode_illegal_opcode(*op).is_some())
+            .count();
+        assert_eq!(decoded, 256 - 12);
+    }
+
+    #[test]
+    fn test_relocate() {
+        let bne = decode(&[0xd0, 0xfe], 0x8010).unwrap();
+        let moved = bne.relocate(0x8000).unwrap();
+        assert_eq!(moved.encode()[..2], [0xd0, 0x0e]);
+        assert_eq!(moved.target(), Option::Some(0x8010));
+        assert_eq!(
+            bne.relocate(0x9000),
+            Err(RelocateError::BranchOutOfRange {
+                addr: 0x9000,
+                target: 0x8010
+            })
+        );
+
+        let lda = decode(&[0xbd, 0x00, 0x90], 0x8000).unwrap();
+        assert_eq!(lda.with_operand(0x0010).unwrap().to_string(), "lda $10,x");
+        let ldx = decode(&[0xb6, 0x10], 0x8000).unwrap();
+        assert_eq!(ldx.with_operand(0x0300).unwrap().to_string(), "ldx $0300,y");
+        let jsr = decode(&[0x20, 0x00, 0x90], 0x8000).unwrap();
+        assert_eq!(jsr.with_operand(0x0010).unwrap().size(), 3);
+        let lda = decode(&[0xb1, 0x10], 0x8000).unwrap();
+        assert!(lda.with_operand(0x0300).is_err());
+    }
+
+    #[test]
+    fn test_cycles() {
+        let cycles = |bytes: &[u8], addr| decode(bytes, addr).unwrap().cycles();
+        assert_eq!(cycles(&[0xea], 0x8000), (2, 2));
+        assert_eq!(cycles(&[0xbd, 0x00, 0x90], 0x8000), (4, 5));
+        assert_eq!(cycles(&[0x9d, 0x00, 0x90], 0x8000), (5, 5));
+        assert_eq!(cycles(&[0xd0, 0x10], 0x8000), (2, 3));
+        assert_eq!(cycles(&[0xd0, 0xfc], 0x8000), (2, 4));
+    }
+
+    #[test]
+    fn test_relocate_code() {
+        // loop: dex; bne loop; jmp loop; jsr $c000
+        let code = [0xca, 0xd0, 0xfd, 0x4c, 0x00, 0x80, 0x20, 0x00, 0xc0];
+        let mut out = [0; 9];
+        assert_eq!(relocate_code(&code, 0x8000, 0x9000, &mut out), Ok(9));
+        assert_eq!(out, [0xca, 0xd0, 0xfd, 0x4c, 0x00, 0x90, 0x20, 0x00, 0xc0]);
+
+        // the branch leaves the code
+        let code = [0xd0, 0x10];
+        assert!(relocate_code(&code, 0x8000, 0x9000, &mut out).is_err());
+    }
+}
@@ -0,0 +1,432 @@
+//! Decoder for the 65816 of the SNES and Apple IIgs. Unlike the 6502 the length
+//! of immediate operands depends on the M and X flags, so [`Decoder65816`] keeps
+//! track of them through `rep`, `sep` and `xce` while decoding. Like
+//! [`crate::decode`] it doesn't need `std` or an allocator.
+
+use core::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Mode65816 {
+    Implied,
+    Accumulator,
+    /// one byte, e.g. `rep`, `sep`, `brk` and `cop`
+    Immediate8,
+    /// the size of the accumulator, 8 bits when M is set
+    ImmediateM,
+    /// the size of the index registers, 8 bits when X is set
+    ImmediateX,
+    /// `pea`, always 16 bits
+    Immediate16,
+    Direct,
+    DirectX,
+    DirectY,
+    DirectIndirect,
+    DirectIndirectLong,
+    DirectIndirectX,
+    DirectIndirectY,
+    DirectIndirectLongY,
+    Absolute,
+    AbsoluteX,
+    AbsoluteY,
+    AbsoluteLong,
+    AbsoluteLongX,
+    AbsoluteIndirect,
+    AbsoluteIndirectLong,
+    AbsoluteIndirectX,
+    StackRelative,
+    StackRelativeIndirectY,
+    Relative,
+    RelativeLong,
+    /// `mvn`/`mvp`, the destination bank is the first operand byte, the source
+    /// bank the second
+    BlockMove,
+}
+
+impl Mode65816 {
+    pub fn operand_len(&self, flags: Flags) -> usize {
+        return match self {
+            Mode65816::Implied | Mode65816::Accumulator => 0,
+            Mode65816::ImmediateM => {
+                if flags.m8 {
+                    1
+                } else {
+                    2
+                }
+            }
+            Mode65816::ImmediateX => {
+                if flags.x8 {
+                    1
+                } else {
+                    2
+                }
+            }
+            Mode65816::Immediate8
+            | Mode65816::Direct
+            | Mode65816::DirectX
+            | Mode65816::DirectY
+            | Mode65816::DirectIndirect
+            | Mode65816::DirectIndirectLong
+            | Mode65816::DirectIndirectX
+            | Mode65816::DirectIndirectY
+            | Mode65816::DirectIndirectLongY
+            | Mode65816::StackRelative
+            | Mode65816::StackRelativeIndirectY
+            | Mode65816::Relative => 1,
+            Mode65816::Immediate16
+            | Mode65816::Absolute
+            | Mode65816::AbsoluteX
+            | Mode65816::AbsoluteY
+            | Mode65816::AbsoluteIndirect
+            | Mode65816::AbsoluteIndirectX
+            | Mode65816::AbsoluteIndirectLong
+            | Mode65816::RelativeLong
+            | Mode65816::BlockMove => 2,
+            Mode65816::AbsoluteLong | Mode65816::AbsoluteLongX => 3,
+        };
+    }
+}
+
+/// Register widths and mode, which decide the length of immediate operands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Flags {
+    /// 8-bit accumulator and memory
+    pub m8: bool,
+    /// 8-bit index registers
+    pub x8: bool,
+    /// 6502 emulation mode, where M and X are always set
+    pub emulation: bool,
+}
+
+impl Flags {
+    /// State after reset: emulation mode with 8-bit registers.
+    pub const RESET: Flags = Flags {
+        m8: true,
+        x8: true,
+        emulation: true,
+    };
+
+    /// Native mode with the widths given.
+    pub fn native(m8: bool, x8: bool) -> Flags {
+        return Flags {
+            m8,
+            x8,
+            emulation: false,
+        };
+    }
+
+    /// The flags after `instr` runs. `carry` is the carry flag if known, which
+    /// `xce` swaps with the emulation flag.
+    pub fn after(&self, instr: &Decoded65816, carry: Option<bool>) -> Flags {
+        let mut result = *self;
+        match instr.mnemonic {
+            // in emulation mode M and X can't be cleared
+            "rep" if !self.emulation => {
+                result.m8 &= instr.operand & 0x20 == 0;
+                result.x8 &= instr.operand & 0x10 == 0;
+            }
+            "sep" => {
+                result.m8 |= instr.operand & 0x20 != 0;
+                result.x8 |= instr.operand & 0x10 != 0;
+            }
+            "xce" => {
+                // entering native mode leaves M and X set, which they are in
+                // emulation mode
+                if let Option::Some(carry) = carry {
+                    result.emulation = carry;
+                    if carry {
+                        result.m8 = true;
+                        result.x8 = true;
+                    }
+                }
+            }
+            _ => {}
+        }
+        return result;
+    }
+}
+
+/// An instruction decoded from bytes, see [`decode_65816`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Decoded65816 {
+    /// 24-bit address, bank in the high byte
+    pub addr: u32,
+    pub opcode: u8,
+    pub mnemonic: &'static str,
+    pub mode: Mode65816,
+    /// the operand bytes as a little endian value, 0 if there are none
+    pub operand: u32,
+    /// length in bytes including the opcode
+    pub size: usize,
+}
+
+impl Decoded65816 {
+    /// Address a branch goes to, in the same bank.
+    pub fn target(&self) -> Option<u32> {
+        let bank = self.addr & 0xff0000;
+        let next = self.addr.wrapping_add(self.size as u32) as u16;
+        return match self.mode {
+            Mode65816::Relative => {
+                Option::Some(bank | next.wrapping_add(self.operand as u8 as i8 as u16) as u32)
+            }
+            Mode65816::RelativeLong => {
+                Option::Some(bank | next.wrapping_add(self.operand as u16) as u32)
+            }
+            _ => Option::None,
+        };
+    }
+}
+
+impl fmt::Display for Decoded65816 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let m = self.mnemonic;
+        let v = self.operand;
+        return match self.mode {
+            Mode65816::Implied => write!(f, "{}", m),
+            Mode65816::Accumulator => write!(f, "{} a", m),
+            Mode65816::Immediate8 => write!(f, "{} #${:02x}", m, v),
+            Mode65816::ImmediateM | Mode65816::ImmediateX if self.size == 2 => {
+                write!(f, "{} #${:02x}", m, v)
+            }
+            Mode65816::ImmediateM | Mode65816::ImmediateX | Mode65816::Immediate16 => {
+                write!(f, "{} #${:04x}", m, v)
+            }
+            Mode65816::Direct => write!(f, "{} ${:02x}", m, v),
+            Mode65816::DirectX => write!(f, "{} ${:02x},x", m, v),
+            Mode65816::DirectY => write!(f, "{} ${:02x},y", m, v),
+            Mode65816::DirectIndirect => write!(f, "{} (${:02x})", m, v),
+            Mode65816::DirectIndirectLong => write!(f, "{} [${:02x}]", m, v),
+            Mode65816::DirectIndirectX => write!(f, "{} (${:02x},x)", m, v),
+            Mode65816::DirectIndirectY => write!(f, "{} (${:02x}),y", m, v),
+            Mode65816::DirectIndirectLongY => write!(f, "{} [${:02x}],y", m, v),
+            Mode65816::Absolute => write!(f, "{} ${:04x}", m, v),
+            Mode65816::AbsoluteX => write!(f, "{} ${:04x},x", m, v),
+            Mode65816::AbsoluteY => write!(f, "{} ${:04x},y", m, v),
+            Mode65816::AbsoluteLong => write!(f, "{} ${:06x}", m, v),
+            Mode65816::AbsoluteLongX => write!(f, "{} ${:06x},x", m, v),
+            Mode65816::AbsoluteIndirect => write!(f, "{} (${:04x})", m, v),
+            Mode65816::AbsoluteIndirectLong => write!(f, "{} [${:04x}]", m, v),
+            Mode65816::AbsoluteIndirectX => write!(f, "{} (${:04x},x)", m, v),
+            Mode65816::StackRelative => write!(f, "{} ${:02x},s", m, v),
+            Mode65816::StackRelativeIndirectY => write!(f, "{} (${:02x},s),y", m, v),
+            Mode65816::Relative | Mode65816::RelativeLong => {
+                write!(f, "{} ${:06x}", m, self.target().unwrap_or(0))
+            }
+            Mode65816::BlockMove => write!(f, "{} ${:02x},${:02x}", m, v >> 8, v & 0xff),
+        };
+    }
+}
+
+/// Mnemonic and addressing mode of a 65816 opcode. Every byte is an opcode.
+pub fn decode_opcode_65816(op: u8) -> (&'static str, Mode65816) {
+    return OPCODES_65816[op as usize];
+}
+
+/// Decodes the instruction at the start of `bytes`, which is at `addr`, with the
+/// register widths in `flags`. `None` if the operand is cut off.
+pub fn decode_65816(bytes: &[u8], addr: u32, flags: Flags) -> Option<Decoded65816> {
+    let opcode = *bytes.first()?;
+    let (mnemonic, mode) = decode_opcode_65816(opcode);
+    let len = mode.operand_len(flags);
+    let operand = bytes.get(1..1 + len)?;
+    let operand = operand
+        .iter()
+        .rev()
+        .fold(0u32, |value, b| (value << 8) | *b as u32);
+    return Option::Some(Decoded65816 {
+        addr,
+        opcode,
+        mnemonic,
+        mode,
+        operand,
+        size: 1 + len,
+    });
+}
+
+/// Decodes instructions one after another, following `rep`, `sep` and
+/// `clc`/`sec` before `xce` to know the length of immediate operands. The flags
+/// are only tracked along the bytes, so code jumped to with other widths needs a
+/// decoder of its own.
+#[derive(Debug, Clone)]
+pub struct Decoder65816<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+    addr: u32,
+    flags: Flags,
+    carry: Option<bool>,
+}
+
+impl<'a> Decoder65816<'a> {
+    pub fn new(bytes: &'a [u8], addr: u32, flags: Flags) -> Decoder65816<'a> {
+        return Decoder65816 {
+            bytes,
+            offset: 0,
+            addr,
+            flags,
+            carry: Option::None,
+        };
+    }
+
+    /// The flags the next instruction is decoded with.
+    pub fn flags(&self) -> Flags {
+        return self.flags;
+    }
+
+    /// Offset into the bytes of the next instruction.
+    pub fn offset(&self) -> usize {
+        return self.offset;
+    }
+}
+
+impl Iterator for Decoder65816<'_> {
+    type Item = Decoded65816;
+
+    fn next(&mut self) -> Option<Decoded65816> {
+        let instr = decode_65816(
+            &self.bytes[self.offset..],
+            self.addr.wrapping_add(self.offset as u32),
+            self.flags,
+        )?;
+        let carry = match instr.mnemonic {
+            "clc" => Option::Some(false),
+            "sec" => Option::Some(true),
+            "rep" if instr.operand & 0x01 != 0 => Option::Some(false),
+            "sep" if instr.operand & 0x01 != 0 => Option::Some(true),
+            // swapped with the emulation flag
+            "xce" => Option::Some(self.flags.emulation),
+            _ => Option::None,
+        };
+        self.flags = self.flags.after(&instr, self.carry);
+        self.carry = carry;
+        self.offset += instr.size;
+        return Option::Some(instr);
+    }
+}
+
+// https://undisbeliever.net/snesdev/65816-opcodes.html
+#[rustfmt::skip]
+const OPCODES_65816: [(&str, Mode65816); 256] = {
+    use Mode65816::*;
+    [
+        ("brk", Immediate8), ("ora", DirectIndirectX), ("cop", Immediate8), ("ora", StackRelative),
+        ("tsb", Direct), ("ora", Direct), ("asl", Direct), ("ora", DirectIndirectLong),
+        ("php", Implied), ("ora", ImmediateM), ("asl", Accumulator), ("phd", Implied),
+        ("tsb", Absolute), ("ora", Absolute), ("asl", Absolute), ("ora", AbsoluteLong),
+        // $10
+        ("bpl", Relative), ("ora", DirectIndirectY), ("ora", DirectIndirect), ("ora", StackRelativeIndirectY),
+        ("trb", Direct), ("ora", DirectX), ("asl", DirectX), ("ora", DirectIndirectLongY),
+        ("clc", Implied), ("ora", AbsoluteY), ("inc", Accumulator), ("tcs", Implied),
+        ("trb", Absolute), ("ora", AbsoluteX), ("asl", AbsoluteX), ("ora", AbsoluteLongX),
+        // $20
+        ("jsr", Absolute), ("and", DirectIndirectX), ("jsl", AbsoluteLong), ("and", StackRelative),
+        ("bit", Direct), ("and", Direct), ("rol", Direct), ("and", DirectIndirectLong),
+        ("plp", Implied), ("and", ImmediateM), ("rol", Accumulator), ("pld", Implied),
+        ("bit", Absolute), ("and", Absolute), ("rol", Absolute), ("and", AbsoluteLong),
+        // $30
+        ("bmi", Relative), ("and", DirectIndirectY), ("and", DirectIndirect), ("and", StackRelativeIndirectY),
+        ("bit", DirectX), ("and", DirectX), ("rol", DirectX), ("and", DirectIndirectLongY),
+        ("sec", Implied), ("and", AbsoluteY), ("dec", Accumulator), ("tsc", Implied),
+        ("bit", AbsoluteX), ("and", AbsoluteX), ("rol", AbsoluteX), ("and", AbsoluteLongX),
+        // $40
+        ("rti", Implied), ("eor", DirectIndirectX), ("wdm", Immediate8), ("eor", StackRelative),
+        ("mvp", BlockMove), ("eor", Direct), ("lsr", Direct), ("eor", DirectIndirectLong),
+        ("pha", Implied), ("eor", ImmediateM), ("lsr", Accumulator), ("phk", Implied),
+        ("jmp", Absolute), ("eor", Absolute), ("lsr", Absolute), ("eor", AbsoluteLong),
+        // $50
+        ("bvc", Relative), ("eor", DirectIndirectY), ("eor", DirectIndirect), ("eor", StackRelativeIndirectY),
+        ("mvn", BlockMove), ("eor", DirectX), ("lsr", DirectX), ("eor", DirectIndirectLongY),
+        ("cli", Implied), ("eor", AbsoluteY), ("phy", Implied), ("tcd", Implied),
+        ("jml", AbsoluteLong), ("eor", AbsoluteX), ("lsr", AbsoluteX), ("eor", AbsoluteLongX),
+        // $60
+        ("rts", Implied), ("adc", DirectIndirectX), ("per", RelativeLong), ("adc", StackRelative),
+        ("stz", Direct), ("adc", Direct), ("ror", Direct), ("adc", DirectIndirectLong),
+        ("pla", Implied), ("adc", ImmediateM), ("ror", Accumulator), ("rtl", Implied),
+        ("jmp", AbsoluteIndirect), ("adc", Absolute), ("ror", Absolute), ("adc", AbsoluteLong),
+        // $70
+        ("bvs", Relative), ("adc", DirectIndirectY), ("adc", DirectIndirect), ("adc", StackRelativeIndirectY),
+        ("stz", DirectX), ("adc", DirectX), ("ror", DirectX), ("adc", DirectIndirectLongY),
+        ("sei", Implied), ("adc", AbsoluteY), ("ply", Implied), ("tdc", Implied),
+        ("jmp", AbsoluteIndirectX), ("adc", AbsoluteX), ("ror", AbsoluteX), ("adc", AbsoluteLongX),
+        // $80
+        ("bra", Relative), ("sta", DirectIndirectX), ("brl", RelativeLong), ("sta", StackRelative),
+        ("sty", Direct), ("sta", Direct), ("stx", Direct), ("sta", DirectIndirectLong),
+        ("dey", Implied), ("bit", ImmediateM), ("txa", Implied), ("phb", Implied),
+        ("sty", Absolute), ("sta", Absolute), ("stx", Absolute), ("sta", AbsoluteLong),
+        // $90
+        ("bcc", Relative), ("sta", DirectIndirectY), ("sta", DirectIndirect), ("sta", StackRelativeIndirectY),
+        ("sty", DirectX), ("sta", DirectX), ("stx", DirectY), ("sta", DirectIndirectLongY),
+        ("tya", Implied), ("sta", AbsoluteY), ("txs", Implied), ("txy", Implied),
+        ("stz", Absolute), ("sta", AbsoluteX), ("stz", AbsoluteX), ("sta", AbsoluteLongX),
+        // $a0
+        ("ldy", ImmediateX), ("lda", DirectIndirectX), ("ldx", ImmediateX), ("lda", StackRelative),
+        ("ldy", Direct), ("lda", Direct), ("ldx", Direct), ("lda", DirectIndirectLong),
+        ("tay", Implied), ("lda", ImmediateM), ("tax", Implied), ("plb", Implied),
+        ("ldy", Absolute), ("lda", Absolute), ("ldx", Absolute), ("lda", AbsoluteLong),
+        // $b0
+        ("bcs", Relative), ("lda", DirectIndirectY), ("lda", DirectIndirect), ("lda", StackRelativeIndirectY),
+        ("ldy", DirectX), ("lda", DirectX), ("ldx", DirectY), ("lda", DirectIndirectLongY),
+        ("clv", Implied), ("lda", AbsoluteY), ("tsx", Implied), ("tyx", Implied),
+        ("ldy", AbsoluteX), ("lda", AbsoluteX), ("ldx", AbsoluteY), ("lda", AbsoluteLongX),
+        // $c0
+        ("cpy", ImmediateX), ("cmp", DirectIndirectX), ("rep", Immediate8), ("cmp", StackRelative),
+        ("cpy", Direct), ("cmp", Direct), ("dec", Direct), ("cmp", DirectIndirectLong),
+        ("iny", Implied), ("cmp", ImmediateM), ("dex", Implied), ("wai", Implied),
+        ("cpy", Absolute), ("cmp", Absolute), ("dec", Absolute), ("cmp", AbsoluteLong),
+        // $d0
+        ("bne", Relative), ("cmp", DirectIndirectY), ("cmp", DirectIndirect), ("cmp", StackRelativeIndirectY),
+        ("pei", DirectIndirect), ("cmp", DirectX), ("dec", DirectX), ("cmp", DirectIndirectLongY),
+        ("cld", Implied), ("cmp", AbsoluteY), ("phx", Implied), ("stp", Implied),
+        ("jml", AbsoluteIndirectLong), ("cmp", AbsoluteX), ("dec", AbsoluteX), ("cmp", AbsoluteLongX),
+        // $e0
+        ("cpx", ImmediateX), ("sbc", DirectIndirectX), ("sep", Immediate8), ("sbc", StackRelative),
+        ("cpx", Direct), ("sbc", Direct), ("inc", Direct), ("sbc", DirectIndirectLong),
+        ("inx", Implied), ("sbc", ImmediateM), ("nop", Implied), ("xba", Implied),
+        ("cpx", Absolute), ("sbc", Absolute), ("inc", Absolute), ("sbc", AbsoluteLong),
+        // $f0
+        ("beq", Relative), ("sbc", DirectIndirectY), ("sbc", DirectIndirect), ("sbc", StackRelativeIndirectY),
+        ("pea", Immediate16), ("sbc", DirectX), ("inc", DirectX), ("sbc", DirectIndirectLongY),
+        ("sed", Implied), ("sbc", AbsoluteY), ("plx", Implied), ("xce", Implied),
+        ("jsr", AbsoluteIndirectX), ("sbc", AbsoluteX), ("inc", AbsoluteX), ("sbc", AbsoluteLongX),
+    ]
+};
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_65816_widths() {
+        // clc; xce; rep #$30; lda #$1234; ldx #$0010; sep #$20; lda #$12;
+        // mvn $7e,$00; jml $008000
+        let bytes = [
+            0x18, 0xfb, 0xc2, 0x30, 0xa9, 0x34, 0x12, 0xa2, 0x10, 0x00, 0xe2, 0x20, 0xa9, 0x12,
+            0x54, 0x00, 0x7e, 0x5c, 0x00, 0x80, 0x00,
+        ];
+        let decoded: Vec<String> = Decoder65816::new(&bytes, 0x008000, Flags::RESET)
+            .map(|i| i.to_string())
+            .collect();
+        assert_eq!(
+            decoded,
+            vec![
+                "clc",
+                "xce",
+                "rep #$30",
+                "lda #$1234",
+                "ldx #$0010",
+                "sep #$20",
+                "lda #$12",
+                "mvn $7e,$00",
+                "jml $008000"
+            ]
+        );
+
+        // in emulation mode rep can't widen the registers
+        let emulation: Vec<usize> = Decoder65816::new(&[0xc2, 0x30, 0xa9, 0x12], 0, Flags::RESET)
+            .map(|i| i.size)
+            .collect();
+        assert_eq!(emulation, vec![2, 2]);
+
+        let bra = decode_65816(&[0x80, 0xfe], 0x018000, Flags::RESET).unwrap();
+        assert_eq!(bra.target(), Option::Some(0x018000));
+        assert!(decode_65816(&[0xa9, 0x12], 0, Flags::native(false, true)).is_none());
+    }
+}
@@ -0,0 +1,263 @@
+//! Decoder for the HuC6280 of the PC Engine, a 65C02 with the Rockwell bit
+//! instructions, block transfers, `st0`-`st2` for the video chip and `tam`/`tma`
+//! for the MPR bank registers. Opcodes it shares with the 6502 decode as in
+//! [`crate::decode`]. Doesn't need `std` or an allocator either.
+
+use core::fmt;
+
+use crate::decode::{decode_opcode, AddressingMode, DecodedInstruction};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ModeHuC6280 {
+    /// a mode the 6502 has
+    Base(AddressingMode),
+    /// `(zp)`
+    ZeroPageIndirect,
+    /// `jmp (abs,x)`
+    AbsoluteIndirectX,
+    /// `bbr`/`bbs`, the zero page byte to test then the branch
+    ZeroPageRelative,
+    /// `tst #imm,zp`
+    ImmediateZeroPage,
+    /// `tst #imm,zp,x`
+    ImmediateZeroPageX,
+    /// `tst #imm,abs`
+    ImmediateAbsolute,
+    /// `tst #imm,abs,x`
+    ImmediateAbsoluteX,
+    /// `tii` and the like, source, destination and length
+    BlockTransfer,
+}
+
+impl ModeHuC6280 {
+    pub fn operand_len(&self) -> usize {
+        return match self {
+            ModeHuC6280::Base(mode) => mode.operand_len(),
+            ModeHuC6280::ZeroPageIndirect => 1,
+            ModeHuC6280::AbsoluteIndirectX
+            | ModeHuC6280::ZeroPageRelative
+            | ModeHuC6280::ImmediateZeroPage
+            | ModeHuC6280::ImmediateZeroPageX => 2,
+            ModeHuC6280::ImmediateAbsolute | ModeHuC6280::ImmediateAbsoluteX => 3,
+            ModeHuC6280::BlockTransfer => 6,
+        };
+    }
+}
+
+/// An instruction decoded from bytes, see [`decode_huc6280`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodedHuC6280 {
+    pub addr: u16,
+    pub opcode: u8,
+    pub mnemonic: &'static str,
+    pub mode: ModeHuC6280,
+    /// little endian 8 or 16-bit operands in the order they are written, e.g. the
+    /// immediate then the address of `tst`, unused ones are 0
+    pub operands: [u16; 3],
+}
+
+impl DecodedHuC6280 {
+    /// length in bytes including the opcode
+    pub fn size(&self) -> usize {
+        return 1 + self.mode.operand_len();
+    }
+
+    /// Address a branch goes to, or the operand address of a jump.
+    pub fn target(&self) -> Option<u16> {
+        return match self.mode {
+            ModeHuC6280::Base(_) => self.base().and_then(|i| i.target()),
+            ModeHuC6280::ZeroPageRelative => Option::Some(
+                self.addr
+                    .wrapping_add(3)
+                    .wrapping_add(self.operands[1] as u8 as i8 as u16),
+            ),
+            _ => Option::None,
+        };
+    }
+
+    /// The 6502 instruction with the same mode, for modes the 6502 has.
+    pub fn base(&self) -> Option<DecodedInstruction> {
+        return match self.mode {
+            ModeHuC6280::Base(mode) => Option::Some(DecodedInstruction {
+                addr: self.addr,
+                opcode: self.opcode,
+                mnemonic: self.mnemonic,
+                mode,
+                operand: self.operands[0],
+            }),
+            _ => Option::None,
+        };
+    }
+}
+
+impl fmt::Display for DecodedHuC6280 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let m = self.mnemonic;
+        let [a, b, c] = self.operands;
+        return match self.mode {
+            ModeHuC6280::Base(_) => match self.base() {
+                Option::Some(base) => write!(f, "{}", base),
+                Option::None => Result::Ok(()),
+            },
+            ModeHuC6280::ZeroPageIndirect => write!(f, "{} (${:02x})", m, a),
+            ModeHuC6280::AbsoluteIndirectX => write!(f, "{} (${:04x},x)", m, a),
+            ModeHuC6280::ZeroPageRelative => {
+                write!(f, "{} ${:02x},${:04x}", m, a, self.target().unwrap_or(0))
+            }
+            ModeHuC6280::ImmediateZeroPage => write!(f, "{} #${:02x},${:02x}", m, a, b),
+            ModeHuC6280::ImmediateZeroPageX => write!(f, "{} #${:02x},${:02x},x", m, a, b),
+            ModeHuC6280::ImmediateAbsolute => write!(f, "{} #${:02x},${:04x}", m, a, b),
+            ModeHuC6280::ImmediateAbsoluteX => write!(f, "{} #${:02x},${:04x},x", m, a, b),
+            ModeHuC6280::BlockTransfer => write!(f, "{} ${:04x},${:04x},${:04x}", m, a, b, c),
+        };
+    }
+}
+
+/// Mnemonic and addressing mode of a HuC6280 opcode.
+pub fn decode_opcode_huc6280(op: u8) -> Option<(&'static str, ModeHuC6280)> {
+    if let Option::Some((_, m, mode)) = HUC6280_OPCODES.iter().find(|(o, _, _)| *o == op) {
+        return Option::Some((*m, *mode));
+    }
+    return decode_opcode(op).map(|(m, mode)| (m, ModeHuC6280::Base(mode)));
+}
+
+/// Decodes the instruction at the start of `bytes`, which is at `addr`. `None` if
+/// the first byte isn't an opcode or the operand is cut off.
+pub fn decode_huc6280(bytes: &[u8], addr: u16) -> Option<DecodedHuC6280> {
+    let opcode = *bytes.first()?;
+    let (mnemonic, mode) = decode_opcode_huc6280(opcode)?;
+    let operand = bytes.get(1..1 + mode.operand_len())?;
+    let byte = |i: usize| operand[i] as u16;
+    let word = |i: usize| u16::from_le_bytes([operand[i], operand[i + 1]]);
+    let operands = match mode {
+        ModeHuC6280::Base(base) if base.operand_len() == 1 => [byte(0), 0, 0],
+        ModeHuC6280::Base(base) if base.operand_len() == 2 => [word(0), 0, 0],
+        ModeHuC6280::Base(_) => [0, 0, 0],
+        ModeHuC6280::ZeroPageIndirect => [byte(0), 0, 0],
+        ModeHuC6280::AbsoluteIndirectX => [word(0), 0, 0],
+        ModeHuC6280::ZeroPageRelative
+        | ModeHuC6280::ImmediateZeroPage
+        | ModeHuC6280::ImmediateZeroPageX => [byte(0), byte(1), 0],
+        ModeHuC6280::ImmediateAbsolute | ModeHuC6280::ImmediateAbsoluteX => [byte(0), word(1), 0],
+        ModeHuC6280::BlockTransfer => [word(0), word(2), word(4)],
+    };
+    return Option::Some(DecodedHuC6280 {
+        addr,
+        opcode,
+        mnemonic,
+        mode,
+        operands,
+    });
+}
+
+// opcodes added by the 65C02 and the HuC6280, or taken by them from the 6502's
+// undocumented ones
+// http://shu.sheldows.com/shu/download/pcedocs/pce_cpu.html
+const HUC6280_OPCODES: &[(u8, &str, ModeHuC6280)] = &[
+    (0x02, "sxy", ModeHuC6280::Base(AddressingMode::Implied)),
+    (0x03, "st0", ModeHuC6280::Base(AddressingMode::Immediate)),
+    (0x04, "tsb", ModeHuC6280::Base(AddressingMode::ZeroPage)),
+    (0x0c, "tsb", ModeHuC6280::Base(AddressingMode::Absolute)),
+    (0x13, "st1", ModeHuC6280::Base(AddressingMode::Immediate)),
+    (0x14, "trb", ModeHuC6280::Base(AddressingMode::ZeroPage)),
+    (0x1a, "inc", ModeHuC6280::Base(AddressingMode::Accumulator)),
+    (0x1c, "trb", ModeHuC6280::Base(AddressingMode::Absolute)),
+    (0x22, "sax", ModeHuC6280::Base(AddressingMode::Implied)),
+    (0x23, "st2", ModeHuC6280::Base(AddressingMode::Immediate)),
+    (0x34, "bit", ModeHuC6280::Base(AddressingMode::ZeroPageX)),
+    (0x3a, "dec", ModeHuC6280::Base(AddressingMode::Accumulator)),
+    (0x3c, "bit", ModeHuC6280::Base(AddressingMode::AbsoluteX)),
+    (0x42, "say", ModeHuC6280::Base(AddressingMode::Implied)),
+    (0x43, "tma", ModeHuC6280::Base(AddressingMode::Immediate)),
+    (0x44, "bsr", ModeHuC6280::Base(AddressingMode::Relative)),
+    (0x53, "tam", ModeHuC6280::Base(AddressingMode::Immediate)),
+    (0x54, "csl", ModeHuC6280::Base(AddressingMode::Implied)),
+    (0x5a, "phy", ModeHuC6280::Base(AddressingMode::Implied)),
+    (0x62, "cla", ModeHuC6280::Base(AddressingMode::Implied)),
+    (0x64, "stz", ModeHuC6280::Base(AddressingMode::ZeroPage)),
+    (0x74, "stz", ModeHuC6280::Base(AddressingMode::ZeroPageX)),
+    (0x7a, "ply", ModeHuC6280::Base(AddressingMode::Implied)),
+    (0x80, "bra", ModeHuC6280::Base(AddressingMode::Relative)),
+    (0x82, "clx", ModeHuC6280::Base(AddressingMode::Implied)),
+    (0x89, "bit", ModeHuC6280::Base(AddressingMode::Immediate)),
+    (0x9c, "stz", ModeHuC6280::Base(AddressingMode::Absolute)),
+    (0x9e, "stz", ModeHuC6280::Base(AddressingMode::AbsoluteX)),
+    (0xc2, "cly", ModeHuC6280::Base(AddressingMode::Implied)),
+    (0xd4, "csh", ModeHuC6280::Base(AddressingMode::Implied)),
+    (0xda, "phx", ModeHuC6280::Base(AddressingMode::Implied)),
+    (0xf4, "set", ModeHuC6280::Base(AddressingMode::Implied)),
+    (0xfa, "plx", ModeHuC6280::Base(AddressingMode::Implied)),
+    (0x12, "ora", ModeHuC6280::ZeroPageIndirect),
+    (0x32, "and", ModeHuC6280::ZeroPageIndirect),
+    (0x52, "eor", ModeHuC6280::ZeroPageIndirect),
+    (0x72, "adc", ModeHuC6280::ZeroPageIndirect),
+    (0x92, "sta", ModeHuC6280::ZeroPageIndirect),
+    (0xb2, "lda", ModeHuC6280::ZeroPageIndirect),
+    (0xd2, "cmp", ModeHuC6280::ZeroPageIndirect),
+    (0xf2, "sbc", ModeHuC6280::ZeroPageIndirect),
+    (0x7c, "jmp", ModeHuC6280::AbsoluteIndirectX),
+    (0x83, "tst", ModeHuC6280::ImmediateZeroPage),
+    (0x93, "tst", ModeHuC6280::ImmediateAbsolute),
+    (0xa3, "tst", ModeHuC6280::ImmediateZeroPageX),
+    (0xb3, "tst", ModeHuC6280::ImmediateAbsoluteX),
+    (0x73, "tii", ModeHuC6280::BlockTransfer),
+    (0xc3, "tdd", ModeHuC6280::BlockTransfer),
+    (0xd3, "tin", ModeHuC6280::BlockTransfer),
+    (0xe3, "tia", ModeHuC6280::BlockTransfer),
+    (0xf3, "tai", ModeHuC6280::BlockTransfer),
+    (0x07, "rmb0", ModeHuC6280::Base(AddressingMode::ZeroPage)),
+    (0x17, "rmb1", ModeHuC6280::Base(AddressingMode::ZeroPage)),
+    (0x27, "rmb2", ModeHuC6280::Base(AddressingMode::ZeroPage)),
+    (0x37, "rmb3", ModeHuC6280::Base(AddressingMode::ZeroPage)),
+    (0x47, "rmb4", ModeHuC6280::Base(AddressingMode::ZeroPage)),
+    (0x57, "rmb5", ModeHuC6280::Base(AddressingMode::ZeroPage)),
+    (0x67, "rmb6", ModeHuC6280::Base(AddressingMode::ZeroPage)),
+    (0x77, "rmb7", ModeHuC6280::Base(AddressingMode::ZeroPage)),
+    (0x87, "smb0", ModeHuC6280::Base(AddressingMode::ZeroPage)),
+    (0x97, "smb1", ModeHuC6280::Base(AddressingMode::ZeroPage)),
+    (0xa7, "smb2", ModeHuC6280::Base(AddressingMode::ZeroPage)),
+    (0xb7, "smb3", ModeHuC6280::Base(AddressingMode::ZeroPage)),
+    (0xc7, "smb4", ModeHuC6280::Base(AddressingMode::ZeroPage)),
+    (0xd7, "smb5", ModeHuC6280::Base(AddressingMode::ZeroPage)),
+    (0xe7, "smb6", ModeHuC6280::Base(AddressingMode::ZeroPage)),
+    (0xf7, "smb7", ModeHuC6280::Base(AddressingMode::ZeroPage)),
+    (0x0f, "bbr0", ModeHuC6280::ZeroPageRelative),
+    (0x1f, "bbr1", ModeHuC6280::ZeroPageRelative),
+    (0x2f, "bbr2", ModeHuC6280::ZeroPageRelative),
+    (0x3f, "bbr3", ModeHuC6280::ZeroPageRelative),
+    (0x4f, "bbr4", ModeHuC6280::ZeroPageRelative),
+    (0x5f, "bbr5", ModeHuC6280::ZeroPageRelative),
+    (0x6f, "bbr6", ModeHuC6280::ZeroPageRelative),
+    (0x7f, "bbr7", ModeHuC6280::ZeroPageRelative),
+    (0x8f, "bbs0", ModeHuC6280::ZeroPageRelative),
+    (0x9f, "bbs1", ModeHuC6280::ZeroPageRelative),
+    (0xaf, "bbs2", ModeHuC6280::ZeroPageRelative),
+    (0xbf, "bbs3", ModeHuC6280::ZeroPageRelative),
+    (0xcf, "bbs4", ModeHuC6280::ZeroPageRelative),
+    (0xdf, "bbs5", ModeHuC6280::ZeroPageRelative),
+    (0xef, "bbs6", ModeHuC6280::ZeroPageRelative),
+    (0xff, "bbs7", ModeHuC6280::ZeroPageRelative),
+];
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_huc6280() {
+        let decoded = |bytes: &[u8]| decode_huc6280(bytes, 0xe000).unwrap().to_string();
+        assert_eq!(decoded(&[0xa9, 0x12]), "lda #$12");
+        assert_eq!(decoded(&[0x53, 0x04]), "tam #$04");
+        assert_eq!(decoded(&[0x03, 0x05]), "st0 #$05");
+        assert_eq!(decoded(&[0xb2, 0x20]), "lda ($20)");
+        assert_eq!(decoded(&[0x83, 0x80, 0x10]), "tst #$80,$10");
+        assert_eq!(decoded(&[0xb3, 0x01, 0x00, 0x22, 0x00]), "tst #$01,$2200,x");
+        assert_eq!(
+            decoded(&[0x73, 0x00, 0x40, 0x00, 0x22, 0x00, 0x01]),
+            "tii $4000,$2200,$0100"
+        );
+        assert_eq!(decoded(&[0x8f, 0x10, 0xfd]), "bbs0 $10,$e000");
+        assert_eq!(decode_huc6280(&[0x73, 0x00, 0x40], 0xe000), Option::None);
+        assert_eq!(decode_opcode_huc6280(0x33), Option::None);
+    }
+}
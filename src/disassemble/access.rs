@@ -0,0 +1,174 @@
+use std::{collections::BTreeMap, io::Write};
+
+use super::{
+    address::{BankedAddr, CpuAddr},
+    interrupts::{is_ram, Access},
+};
+
+/// How often the decoded instructions read and write an address, and whether
+/// one of them is at it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AddressAccess {
+    pub reads: usize,
+    pub writes: usize,
+    pub executed: bool,
+}
+
+impl AddressAccess {
+    /// `R`, `W` and `X` for what is done at the address, `-` for what isn't
+    pub fn flags(&self) -> String {
+        return [
+            if self.reads > 0 { 'R' } else { '-' },
+            if self.writes > 0 { 'W' } else { '-' },
+            if self.executed { 'X' } else { '-' },
+        ]
+        .iter()
+        .collect();
+    }
+
+    /// What the accesses say about RAM at the address.
+    fn describe_ram(&self) -> &'static str {
+        return match (self.reads > 0, self.writes > 0) {
+            (true, true) => "variable",
+            (false, true) => "never read",
+            // set through a pointer or the stack, or left as it was at power on
+            (true, false) => "never written directly",
+            (false, false) => "",
+        };
+    }
+}
+
+/// What the decoded instructions do at each address: RAM and registers by CPU
+/// address, ROM by bank as well.
+#[derive(Debug, Default)]
+pub struct AccessMap {
+    addrs: BTreeMap<BankedAddr, AddressAccess>,
+}
+
+impl AccessMap {
+    /// Records an access through an operand. `addr` is in `bank` if it is ROM.
+    pub fn access(&mut self, addr: BankedAddr, access: Access) {
+        let entry = self.addrs.entry(addr).or_default();
+        if access.reads() {
+            entry.reads += 1;
+        }
+        if access.writes() {
+            entry.writes += 1;
+        }
+    }
+
+    /// Records the `len` bytes of an instruction at `addr`.
+    pub fn execute(&mut self, addr: BankedAddr, len: usize) {
+        for i in 0..len {
+            let addr = BankedAddr {
+                bank: addr.bank,
+                addr: CpuAddr(addr.addr.0.wrapping_add(i as u16)),
+            };
+            self.addrs.entry(addr).or_default().executed = true;
+        }
+    }
+
+    /// Writes a line per RAM address with how often it is read and written, the
+    /// registers the same way, then the runs of ROM, and of RAM only code is run
+    /// from, with the same access.
+    pub fn write(&self, out: &mut dyn Write) -> std::io::Result<()> {
+        let (ram, rest): (Vec<_>, Vec<_>) = self.addrs.iter().partition(|(addr, access)| {
+            is_ram(addr.addr.0) && (access.reads > 0 || access.writes > 0)
+        });
+        let (registers, code): (Vec<_>, Vec<_>) = rest
+            .into_iter()
+            .partition(|(addr, _)| !is_ram(addr.addr.0) && addr.addr.0 < 0x8000);
+
+        writeln!(out, "RAM, {} addresses", ram.len())?;
+        for (addr, access) in ram {
+            let line = format!(
+                "${:04x}  {}  {:>4} reads {:>4} writes  {}",
+                addr.addr.0,
+                access.flags(),
+                access.reads,
+                access.writes,
+                access.describe_ram()
+            );
+            writeln!(out, "{}", line.trim_end())?;
+        }
+        writeln!(out, "\nregisters, {} addresses", registers.len())?;
+        for (addr, access) in registers {
+            writeln!(
+                out,
+                "${:04x}  {}  {:>4} reads {:>4} writes",
+                addr.addr.0,
+                access.flags(),
+                access.reads,
+                access.writes
+            )?;
+        }
+
+        let mut runs: Vec<(BankedAddr, BankedAddr, String)> = Vec::new();
+        for (addr, access) in code {
+            let flags = access.flags();
+            match runs.last_mut() {
+                Option::Some((_, end, run_flags))
+                    if end.bank == addr.bank
+                        && end.addr.0.checked_add(1) == Option::Some(addr.addr.0)
+                        && *run_flags == flags =>
+                {
+                    *end = *addr;
+                }
+                _ => runs.push((*addr, *addr, flags)),
+            }
+        }
+        writeln!(out, "\ncode and ROM, {} runs", runs.len())?;
+        for (start, end, flags) in runs {
+            writeln!(
+                out,
+                "{:02x}:{:04x}-{:04x}  {}",
+                start.bank, start.addr.0, end.addr.0, flags
+            )?;
+        }
+        return Result::Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn banked(bank: u8, addr: u16) -> BankedAddr {
+        return BankedAddr {
+            bank,
+            addr: CpuAddr(addr),
+        };
+    }
+
+    #[test]
+    fn test_access_map() {
+        let mut map = AccessMap::default();
+        map.execute(banked(0, 0x8000), 3);
+        map.execute(banked(0, 0x8003), 2);
+        map.access(banked(0, 0x8100), Access::Read);
+        map.access(banked(0, 0x0010), Access::Write);
+        map.access(banked(0, 0x0010), Access::ReadWrite);
+        map.access(banked(0, 0x0300), Access::Write);
+        map.access(banked(0, 0x2002), Access::Read);
+        map.execute(banked(0, 0x7000), 1);
+        map.execute(banked(1, 0x8005), 1);
+
+        let mut out = Vec::new();
+        map.write(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "RAM, 2 addresses\n\
+             $0010  RW-     1 reads    2 writes  variable\n\
+             $0300  -W-     0 reads    1 writes  never read\n\
+             \n\
+             registers, 1 addresses\n\
+             $2002  R--     1 reads    0 writes\n\
+             \n\
+             code and ROM, 4 runs\n\
+             00:7000-7000  --X\n\
+             00:8000-8004  --X\n\
+             00:8100-8100  R--\n\
+             01:8005-8005  --X\n"
+        );
+    }
+}
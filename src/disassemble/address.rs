@@ -0,0 +1,112 @@
+use std::{
+    fmt,
+    ops::{Add, AddAssign, Sub},
+};
+
+/// Position of a byte in the input file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FileOffset(pub usize);
+
+/// Address as seen by the CPU, which depending on the mapper can refer to
+/// different file offsets over time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CpuAddr(pub u16);
+
+/// CPU address together with the bank that is mapped in when it is accessed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BankedAddr {
+    pub bank: u8,
+    pub addr: CpuAddr,
+}
+
+impl Add<usize> for FileOffset {
+    type Output = FileOffset;
+
+    fn add(self, rhs: usize) -> FileOffset {
+        return FileOffset(self.0 + rhs);
+    }
+}
+
+impl AddAssign<usize> for FileOffset {
+    fn add_assign(&mut self, rhs: usize) {
+        self.0 += rhs;
+    }
+}
+
+impl Sub<FileOffset> for FileOffset {
+    type Output = isize;
+
+    fn sub(self, rhs: FileOffset) -> isize {
+        return self.0 as isize - rhs.0 as isize;
+    }
+}
+
+impl fmt::LowerHex for FileOffset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return fmt::LowerHex::fmt(&self.0, f);
+    }
+}
+
+/// Wraps at $ffff like the program counter does.
+impl Add<u16> for CpuAddr {
+    type Output = CpuAddr;
+
+    fn add(self, rhs: u16) -> CpuAddr {
+        return CpuAddr(self.0.wrapping_add(rhs));
+    }
+}
+
+impl CpuAddr {
+    /// Target of a relative branch whose opcode is at this address.
+    pub fn branch_target(self, rel: i8) -> CpuAddr {
+        return self + 2 + rel as u16;
+    }
+}
+
+impl Sub<CpuAddr> for CpuAddr {
+    type Output = isize;
+
+    fn sub(self, rhs: CpuAddr) -> isize {
+        return self.0 as isize - rhs.0 as isize;
+    }
+}
+
+impl fmt::LowerHex for CpuAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return fmt::LowerHex::fmt(&self.0, f);
+    }
+}
+
+impl fmt::Display for BankedAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, "{:02x}:{:04x}", self.bank, self.addr);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_offset_arithmetic() {
+        let mut offset = FileOffset(0x10) + 3;
+        assert_eq!(offset, FileOffset(0x13));
+        offset += 0x20;
+        assert_eq!(offset - FileOffset(0x10), 0x23);
+        assert_eq!(FileOffset(0x10) - offset, -0x23);
+        assert_eq!(format!("{:04x}", offset), "0033");
+    }
+
+    #[test]
+    fn test_addr_arithmetic() {
+        assert_eq!(CpuAddr(0xfffe) + 3, CpuAddr(0x0001));
+        assert_eq!(CpuAddr(0x8010).branch_target(-0x12), CpuAddr(0x8000));
+        assert_eq!(CpuAddr(0x8010).branch_target(0x7f), CpuAddr(0x8091));
+        assert_eq!(CpuAddr(0x8000) - CpuAddr(0x8010), -0x10);
+        let addr = BankedAddr {
+            bank: 3,
+            addr: CpuAddr(0xc0de),
+        };
+        assert_eq!(addr.to_string(), "03:c0de");
+    }
+}
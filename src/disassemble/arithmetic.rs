@@ -0,0 +1,787 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::decode::{AddressingMode, DecodedInstruction};
+
+/// What a [`WideOp`] does with its 16-bit operands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WideOpKind {
+    Add,
+    Subtract,
+    Increment,
+    Decrement,
+}
+
+/// The value added or subtracted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WideOperand {
+    Immediate(u16),
+    /// address of the low byte, the high byte is after it
+    Memory(u16),
+}
+
+/// A 16-bit operation done a byte at a time, e.g. `clc / lda lo / adc #$20 / sta
+/// lo / lda hi / adc #$01 / sta hi`. Addresses are of the low bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WideOp {
+    /// index of the first instruction
+    pub start: usize,
+    pub kind: WideOpKind,
+    pub dst: u16,
+    pub lhs: u16,
+    /// what is added or subtracted, `None` for increments and decrements
+    pub rhs: Option<WideOperand>,
+}
+
+impl WideOp {
+    /// Comment for the first instruction, e.g. `16-bit add, player_x = player_x +
+    /// $0120`, with `name` giving the name of the 16-bit value at an address.
+    pub fn describe(&self, name: impl Fn(u16) -> String) -> String {
+        let rhs = match self.rhs {
+            Option::Some(WideOperand::Immediate(v)) => format!("${:04x}", v),
+            Option::Some(WideOperand::Memory(addr)) => name(addr),
+            Option::None => String::new(),
+        };
+        return match self.kind {
+            WideOpKind::Add => format!(
+                "16-bit add, {} = {} + {}",
+                name(self.dst),
+                name(self.lhs),
+                rhs
+            ),
+            WideOpKind::Subtract => format!(
+                "16-bit subtract, {} = {} - {}",
+                name(self.dst),
+                name(self.lhs),
+                rhs
+            ),
+            WideOpKind::Increment => format!("16-bit increment of {}", name(self.dst)),
+            WideOpKind::Decrement => format!("16-bit decrement of {}", name(self.dst)),
+        };
+    }
+}
+
+/// The zero page or absolute address `instr` uses if it is `mnemonic` with one.
+fn direct(instr: &DecodedInstruction, mnemonic: &str) -> Option<u16> {
+    if instr.mnemonic != mnemonic
+        || !matches!(
+            instr.mode,
+            AddressingMode::ZeroPage | AddressingMode::Absolute
+        )
+    {
+        return Option::None;
+    }
+    return Option::Some(instr.operand);
+}
+
+/// The operand of `adc`/`sbc` for the low and the high byte, which has to be the
+/// byte after or the high byte of the same immediate.
+fn operand(low: &DecodedInstruction, high: &DecodedInstruction) -> Option<WideOperand> {
+    if low.mnemonic != high.mnemonic {
+        return Option::None;
+    }
+    if low.mode == AddressingMode::Immediate && high.mode == AddressingMode::Immediate {
+        return Option::Some(WideOperand::Immediate(
+            (high.operand << 8) | (low.operand & 0xff),
+        ));
+    }
+    let low = direct(low, low.mnemonic)?;
+    let high = direct(high, high.mnemonic)?;
+    if low.checked_add(1) != Option::Some(high) {
+        return Option::None;
+    }
+    return Option::Some(WideOperand::Memory(low));
+}
+
+/// Whether `branch` is `mnemonic` and goes to the instruction at `to`.
+fn skips_to(branch: &DecodedInstruction, mnemonic: &str, to: &DecodedInstruction) -> bool {
+    return branch.mnemonic == mnemonic && branch.target() == Option::Some(to.addr);
+}
+
+/// Whether `branch` is `mnemonic` and goes to right after `skipped`.
+fn skips_over(branch: &DecodedInstruction, mnemonic: &str, skipped: &DecodedInstruction) -> bool {
+    let after = skipped.addr.wrapping_add(skipped.size() as u16);
+    return branch.mnemonic == mnemonic && branch.target() == Option::Some(after);
+}
+
+/// Finds 16-bit adds, subtracts, increments and decrements in `instrs`, which
+/// follow each other in memory.
+pub fn find_wide_ops(instrs: &[DecodedInstruction]) -> Vec<WideOp> {
+    let mut result = Vec::new();
+    let mut start = 0;
+    while start < instrs.len() {
+        match wide_op(&instrs[start..]) {
+            Option::Some((mut op, len)) => {
+                op.start = start;
+                result.push(op);
+                start += len;
+            }
+            Option::None => start += 1,
+        }
+    }
+    return result;
+}
+
+/// The operation at the start of `instrs` and how many instructions it takes.
+fn wide_op(instrs: &[DecodedInstruction]) -> Option<(WideOp, usize)> {
+    let op = |kind, dst, lhs, rhs| WideOp {
+        start: 0,
+        kind,
+        dst,
+        lhs,
+        rhs,
+    };
+
+    // inc lo / bne skip / inc hi / skip:
+    if let [inc_lo, bne, inc_hi, ..] = instrs {
+        let addr = direct(inc_lo, "inc");
+        if addr.is_some()
+            && direct(inc_hi, "inc") == addr.and_then(|a| a.checked_add(1))
+            && skips_over(bne, "bne", inc_hi)
+        {
+            let addr = addr?;
+            return Option::Some((op(WideOpKind::Increment, addr, addr, Option::None), 3));
+        }
+    }
+
+    // lda lo / bne skip / dec hi / skip: dec lo
+    if let [lda, bne, dec_hi, dec_lo, ..] = instrs {
+        let addr = direct(lda, "lda");
+        if addr.is_some()
+            && direct(dec_lo, "dec") == addr
+            && direct(dec_hi, "dec") == addr.and_then(|a| a.checked_add(1))
+            && skips_to(bne, "bne", dec_lo)
+        {
+            let addr = addr?;
+            return Option::Some((op(WideOpKind::Decrement, addr, addr, Option::None), 4));
+        }
+    }
+
+    // clc / lda lo, or lda lo / clc, then the adc and sta of each byte
+    let (flag, lda, rest) = match instrs {
+        [flag, lda, rest @ ..] if matches!(flag.mnemonic, "clc" | "sec") => (flag, lda, rest),
+        [lda, flag, rest @ ..] if matches!(flag.mnemonic, "clc" | "sec") => (flag, lda, rest),
+        _ => return Option::None,
+    };
+    let (kind, arith) = match flag.mnemonic {
+        "clc" => (WideOpKind::Add, "adc"),
+        _ => (WideOpKind::Subtract, "sbc"),
+    };
+    let lhs = direct(lda, "lda")?;
+    let (adc_lo, sta_lo) = match rest {
+        [adc, sta, ..] if adc.mnemonic == arith => (adc, sta),
+        _ => return Option::None,
+    };
+    let dst = direct(sta_lo, "sta")?;
+    match &rest[2..] {
+        [lda_hi, adc_hi, sta_hi, ..]
+            if direct(lda_hi, "lda") == lhs.checked_add(1)
+                && direct(sta_hi, "sta") == dst.checked_add(1) =>
+        {
+            let rhs = operand(adc_lo, adc_hi)?;
+            return Option::Some((op(kind, dst, lhs, Option::Some(rhs)), 7));
+        }
+        // only the carry goes into the high byte: bcc skip / inc hi / skip:
+        [bcc, inc_hi, ..]
+            if kind == WideOpKind::Add
+                && dst == lhs
+                && adc_lo.mode == AddressingMode::Immediate
+                && skips_over(bcc, "bcc", inc_hi)
+                && direct(inc_hi, "inc") == dst.checked_add(1) =>
+        {
+            let rhs = WideOperand::Immediate(adc_lo.operand & 0xff);
+            return Option::Some((op(kind, dst, lhs, Option::Some(rhs)), 6));
+        }
+        _ => return Option::None,
+    }
+}
+
+/// A pointer set with an immediate load and a store of each byte, e.g. `lda #$00 /
+/// sta $10 / lda #$90 / sta $11`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PointerSetup {
+    /// index of the load of the low byte
+    pub low: usize,
+    /// index of the load of the high byte
+    pub high: usize,
+    pub pointer: u8,
+    pub target: u16,
+}
+
+/// The byte `load` loads and the zero page address `store` stores it to, when
+/// they are an immediate load and a store of the same register.
+fn immediate_store(load: &DecodedInstruction, store: &DecodedInstruction) -> Option<(u8, u8)> {
+    let store_mnemonic = match load.mnemonic {
+        "lda" => "sta",
+        "ldx" => "stx",
+        "ldy" => "sty",
+        _ => return Option::None,
+    };
+    if load.mode != AddressingMode::Immediate
+        || store.mnemonic != store_mnemonic
+        || store.mode != AddressingMode::ZeroPage
+    {
+        return Option::None;
+    }
+    return Option::Some((load.operand as u8, store.operand as u8));
+}
+
+/// Finds pointers set up a byte at a time in `instrs`, which follow each other in
+/// memory, the low byte first or the high byte first.
+pub fn find_pointer_setups(instrs: &[DecodedInstruction]) -> Vec<PointerSetup> {
+    let mut result = Vec::new();
+    let mut start = 0;
+    while start + 4 <= instrs.len() {
+        let first = immediate_store(&instrs[start], &instrs[start + 1]);
+        let second = immediate_store(&instrs[start + 2], &instrs[start + 3]);
+        let ((low, (low_value, pointer)), (high, (high_value, _))) = match (first, second) {
+            (Option::Some(a), Option::Some(b)) if a.1.checked_add(1) == Option::Some(b.1) => {
+                ((start, a), (start + 2, b))
+            }
+            (Option::Some(a), Option::Some(b)) if b.1.checked_add(1) == Option::Some(a.1) => {
+                ((start + 2, b), (start, a))
+            }
+            _ => {
+                start += 1;
+                continue;
+            }
+        };
+        result.push(PointerSetup {
+            low,
+            high,
+            pointer,
+            target: ((high_value as u16) << 8) | low_value as u16,
+        });
+        start += 4;
+    }
+    return result;
+}
+
+/// longest basic block whose stores are described by [`find_block_expressions`]
+const MAX_EXPRESSION_BLOCK_LENGTH: usize = 16;
+/// most operators in an expression described, beyond that it is no easier to read
+/// than the instructions
+const MAX_EXPRESSION_OPERATORS: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Subtract,
+    And,
+    Or,
+    Xor,
+    ShiftLeft,
+    ShiftRight,
+}
+
+impl BinaryOp {
+    fn symbol(&self) -> &'static str {
+        return match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Subtract => "-",
+            BinaryOp::And => "&",
+            BinaryOp::Or => "|",
+            BinaryOp::Xor => "^",
+            BinaryOp::ShiftLeft => "<<",
+            BinaryOp::ShiftRight => ">>",
+        };
+    }
+
+    /// binding as in C, higher binds tighter
+    fn precedence(&self) -> u8 {
+        return match self {
+            BinaryOp::Add | BinaryOp::Subtract => 4,
+            BinaryOp::ShiftLeft | BinaryOp::ShiftRight => 3,
+            BinaryOp::And => 2,
+            BinaryOp::Xor => 1,
+            BinaryOp::Or => 0,
+        };
+    }
+
+    fn fold(&self, lhs: u8, rhs: u8) -> u8 {
+        return match self {
+            BinaryOp::Add => lhs.wrapping_add(rhs),
+            BinaryOp::Subtract => lhs.wrapping_sub(rhs),
+            BinaryOp::And => lhs & rhs,
+            BinaryOp::Or => lhs | rhs,
+            BinaryOp::Xor => lhs ^ rhs,
+            BinaryOp::ShiftLeft => lhs.wrapping_shl(rhs as u32),
+            BinaryOp::ShiftRight => lhs.wrapping_shr(rhs as u32),
+        };
+    }
+}
+
+/// A byte computed in a basic block, in terms of what it held when the block
+/// started.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    Const(u8),
+    /// the byte at an address
+    Memory(u16),
+    /// the byte at an address indexed by a register, `table[x]`
+    Indexed(u16, Box<Expr>),
+    /// a register, `a`, `x` or `y`
+    Register(&'static str),
+    /// the carry left by an `adc`, `sbc` or shift, or from before the block
+    Carry,
+    /// the inverted carry subtracted by `sbc`
+    Borrow,
+    Binary(Box<Expr>, BinaryOp, Box<Expr>),
+}
+
+impl Expr {
+    /// `lhs op rhs`, with constants folded and adding or or-ing zero left out.
+    fn binary(lhs: Expr, op: BinaryOp, rhs: Expr) -> Expr {
+        return match (lhs, rhs) {
+            (Expr::Const(lhs), Expr::Const(rhs)) => Expr::Const(op.fold(lhs, rhs)),
+            (lhs, Expr::Const(0))
+                if matches!(
+                    op,
+                    BinaryOp::Add | BinaryOp::Subtract | BinaryOp::Or | BinaryOp::Xor
+                ) =>
+            {
+                lhs
+            }
+            (lhs, rhs) => Expr::Binary(Box::new(lhs), op, Box::new(rhs)),
+        };
+    }
+
+    fn operators(&self) -> usize {
+        return match self {
+            Expr::Indexed(_, index) => index.operators(),
+            Expr::Binary(lhs, _, rhs) => 1 + lhs.operators() + rhs.operators(),
+            _ => 0,
+        };
+    }
+
+    /// Written like C, with `name` giving the name of the byte at an address.
+    pub fn describe(&self, name: &impl Fn(u16) -> String) -> String {
+        return match self {
+            Expr::Const(v) => format!("${:02x}", v),
+            Expr::Memory(addr) => name(*addr),
+            Expr::Indexed(addr, index) => format!("{}[{}]", name(*addr), index.describe(name)),
+            Expr::Register(register) => register.to_string(),
+            Expr::Carry => "carry".to_string(),
+            Expr::Borrow => "borrow".to_string(),
+            Expr::Binary(lhs, op, rhs) => {
+                let operand = |e: &Expr, parenthesize: &dyn Fn(BinaryOp) -> bool| match e {
+                    Expr::Binary(_, inner, _) if parenthesize(*inner) => {
+                        format!("({})", e.describe(name))
+                    }
+                    _ => e.describe(name),
+                };
+                let rhs = match (op, &**rhs) {
+                    // a shift is by a count, not a byte
+                    (BinaryOp::ShiftLeft | BinaryOp::ShiftRight, Expr::Const(n)) => n.to_string(),
+                    _ => operand(rhs, &|inner| inner.precedence() <= op.precedence()),
+                };
+                format!(
+                    "{} {} {}",
+                    operand(lhs, &|inner| inner.precedence() < op.precedence()),
+                    op.symbol(),
+                    rhs
+                )
+            }
+        };
+    }
+}
+
+/// The stores of a short basic block with what they store, e.g. `score_hi =
+/// score_hi + carry` for `lda score_hi / adc #$00 / sta score_hi`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockExpressions {
+    /// index of the last instruction of the block
+    pub end: usize,
+    /// where each value is stored last and the value, in the order of the stores
+    pub stores: Vec<(Expr, Expr)>,
+}
+
+impl BlockExpressions {
+    /// Comment for the end of the block, with `name` giving the name of the byte at
+    /// an address.
+    pub fn describe(&self, name: impl Fn(u16) -> String) -> String {
+        return self
+            .stores
+            .iter()
+            .map(|(dst, value)| format!("{} = {}", dst.describe(&name), value.describe(&name)))
+            .collect::<Vec<String>>()
+            .join(", ");
+    }
+}
+
+/// What is known of the carry going into an instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CarryState {
+    Clear,
+    Set,
+    Unknown,
+}
+
+/// Values of the registers and of the memory stored to while a basic block is
+/// followed, in terms of what they held when it started.
+struct BlockState {
+    a: Option<Expr>,
+    x: Option<Expr>,
+    y: Option<Expr>,
+    carry: CarryState,
+    memory: HashMap<u16, Expr>,
+    stores: Vec<(Expr, Expr)>,
+}
+
+impl BlockState {
+    fn new() -> BlockState {
+        return BlockState {
+            a: Option::Some(Expr::Register("a")),
+            x: Option::Some(Expr::Register("x")),
+            y: Option::Some(Expr::Register("y")),
+            carry: CarryState::Unknown,
+            memory: HashMap::new(),
+            stores: Vec::new(),
+        };
+    }
+
+    /// The byte `instr` reads, `None` for modes it can't be told for.
+    fn operand(&self, instr: &DecodedInstruction) -> Option<Expr> {
+        let index = |register: &Option<Expr>, name| {
+            return Box::new(register.clone().unwrap_or(Expr::Register(name)));
+        };
+        return match instr.mode {
+            AddressingMode::Immediate => Option::Some(Expr::Const(instr.operand as u8)),
+            AddressingMode::ZeroPage | AddressingMode::Absolute => Option::Some(
+                self.memory
+                    .get(&instr.operand)
+                    .cloned()
+                    .unwrap_or(Expr::Memory(instr.operand)),
+            ),
+            AddressingMode::ZeroPageX | AddressingMode::AbsoluteX => {
+                Option::Some(Expr::Indexed(instr.operand, index(&self.x, "x")))
+            }
+            AddressingMode::ZeroPageY | AddressingMode::AbsoluteY => {
+                Option::Some(Expr::Indexed(instr.operand, index(&self.y, "y")))
+            }
+            _ => Option::None,
+        };
+    }
+
+    /// Where `instr` writes, `None` for modes it can't be told for.
+    fn destination(&self, instr: &DecodedInstruction) -> Option<Expr> {
+        return match self.operand(instr)? {
+            Expr::Const(_) => Option::None,
+            _ if matches!(
+                instr.mode,
+                AddressingMode::ZeroPage | AddressingMode::Absolute
+            ) =>
+            {
+                Option::Some(Expr::Memory(instr.operand))
+            }
+            indexed => Option::Some(indexed),
+        };
+    }
+
+    fn store(&mut self, dst: Option<Expr>, value: Option<Expr>) {
+        let dst = match dst {
+            Option::Some(dst) => dst,
+            Option::None => {
+                // a store through a pointer could have gone anywhere
+                self.memory.clear();
+                return;
+            }
+        };
+        self.stores.retain(|(stored, _)| *stored != dst);
+        if let Expr::Memory(addr) = dst {
+            match &value {
+                Option::Some(value) => self.memory.insert(addr, value.clone()),
+                Option::None => self.memory.remove(&addr),
+            };
+        }
+        if let Option::Some(value) = value {
+            self.stores.push((dst, value));
+        }
+    }
+
+    /// The carry going into an `adc` or `sbc`, added or subtracted for `op`.
+    fn carry_in(&self, op: BinaryOp) -> Expr {
+        return match (self.carry, op) {
+            (CarryState::Clear, BinaryOp::Add) => Expr::Const(0),
+            (CarryState::Set, BinaryOp::Add) => Expr::Const(1),
+            (CarryState::Unknown, BinaryOp::Add) => Expr::Carry,
+            (CarryState::Set, _) => Expr::Const(0),
+            (CarryState::Clear, _) => Expr::Const(1),
+            (CarryState::Unknown, _) => Expr::Borrow,
+        };
+    }
+
+    /// Updates the state with what `instr` does, `false` if it can't be followed.
+    fn step(&mut self, instr: &DecodedInstruction) -> bool {
+        let shift = |value: Option<Expr>, mnemonic: &str, carry: CarryState| {
+            let value = value?;
+            let carry = match carry {
+                CarryState::Clear => Expr::Const(0),
+                CarryState::Set => Expr::Const(1),
+                CarryState::Unknown => Expr::Carry,
+            };
+            return Option::Some(match mnemonic {
+                "asl" => Expr::binary(value, BinaryOp::ShiftLeft, Expr::Const(1)),
+                "lsr" => Expr::binary(value, BinaryOp::ShiftRight, Expr::Const(1)),
+                "rol" => Expr::binary(
+                    Expr::binary(value, BinaryOp::ShiftLeft, Expr::Const(1)),
+                    BinaryOp::Or,
+                    carry,
+                ),
+                _ => Expr::binary(
+                    Expr::binary(value, BinaryOp::ShiftRight, Expr::Const(1)),
+                    BinaryOp::Or,
+                    Expr::binary(carry, BinaryOp::ShiftLeft, Expr::Const(7)),
+                ),
+            });
+        };
+        let logic = |op| {
+            let operand = self.operand(instr)?;
+            return Option::Some(Expr::binary(self.a.clone()?, op, operand));
+        };
+        match instr.mnemonic {
+            "lda" => self.a = self.operand(instr),
+            "ldx" => self.x = self.operand(instr),
+            "ldy" => self.y = self.operand(instr),
+            "tax" => self.x = self.a.clone(),
+            "tay" => self.y = self.a.clone(),
+            "txa" => self.a = self.x.clone(),
+            "tya" => self.a = self.y.clone(),
+            "inx" | "dex" | "iny" | "dey" => {
+                let op = match instr.mnemonic.starts_with("in") {
+                    true => BinaryOp::Add,
+                    false => BinaryOp::Subtract,
+                };
+                let register = match instr.mnemonic.ends_with('x') {
+                    true => &mut self.x,
+                    false => &mut self.y,
+                };
+                *register = register.take().map(|v| Expr::binary(v, op, Expr::Const(1)));
+            }
+            "adc" | "sbc" => {
+                let op = match instr.mnemonic {
+                    "adc" => BinaryOp::Add,
+                    _ => BinaryOp::Subtract,
+                };
+                let carry = self.carry_in(op);
+                self.a = logic(op).map(|sum| Expr::binary(sum, op, carry));
+                self.carry = CarryState::Unknown;
+            }
+            "and" => self.a = logic(BinaryOp::And),
+            "ora" => self.a = logic(BinaryOp::Or),
+            "eor" => self.a = logic(BinaryOp::Xor),
+            "asl" | "lsr" | "rol" | "ror" => {
+                if instr.mode == AddressingMode::Accumulator {
+                    self.a = shift(self.a.clone(), instr.mnemonic, self.carry);
+                } else {
+                    let value = shift(self.operand(instr), instr.mnemonic, self.carry);
+                    self.store(self.destination(instr), value);
+                }
+                self.carry = CarryState::Unknown;
+            }
+            "inc" | "dec" => {
+                let op = match instr.mnemonic {
+                    "inc" => BinaryOp::Add,
+                    _ => BinaryOp::Subtract,
+                };
+                let value = self
+                    .operand(instr)
+                    .map(|v| Expr::binary(v, op, Expr::Const(1)));
+                self.store(self.destination(instr), value);
+            }
+            "sta" | "stx" | "sty" => {
+                let value = match instr.mnemonic {
+                    "sta" => self.a.clone(),
+                    "stx" => self.x.clone(),
+                    _ => self.y.clone(),
+                };
+                self.store(self.destination(instr), value);
+            }
+            "clc" => self.carry = CarryState::Clear,
+            "sec" => self.carry = CarryState::Set,
+            "cmp" | "cpx" | "cpy" => self.carry = CarryState::Unknown,
+            "bit" | "nop" | "clv" | "cld" | "sed" | "cli" | "sei" | "pha" | "php" => {}
+            "pla" => self.a = Option::None,
+            "plp" => self.carry = CarryState::Unknown,
+            _ => return false,
+        }
+        return true;
+    }
+}
+
+/// Whether control can go somewhere else than the next instruction after `instr`.
+fn ends_block(instr: &DecodedInstruction) -> bool {
+    return instr.mode == AddressingMode::Relative
+        || matches!(instr.mnemonic, "jmp" | "jsr" | "rts" | "rti" | "brk");
+}
+
+/// Finds the short basic blocks of `instrs`, which follow each other in memory
+/// and are entered only at the first, storing values computed from what they
+/// load. Blocks also start at the branches into them from within `instrs`.
+pub fn find_block_expressions(instrs: &[DecodedInstruction]) -> Vec<BlockExpressions> {
+    let targets = instrs
+        .iter()
+        .filter_map(|instr| instr.target())
+        .collect::<HashSet<u16>>();
+    let mut result = Vec::new();
+    let mut start = 0;
+    while start < instrs.len() {
+        let mut end = start;
+        while end + 1 < instrs.len()
+            && !ends_block(&instrs[end])
+            && !targets.contains(&instrs[end + 1].addr)
+        {
+            end += 1;
+        }
+        if end - start < MAX_EXPRESSION_BLOCK_LENGTH {
+            let mut state = BlockState::new();
+            let mut followed = true;
+            for instr in &instrs[start..=end] {
+                if !ends_block(instr) && !state.step(instr) {
+                    followed = false;
+                    break;
+                }
+            }
+            // copies, constants and a byte stepped by one as with `inc` or `asl` read
+            // as well from the instructions
+            let stores = state
+                .stores
+                .into_iter()
+                .filter(|(dst, value)| {
+                    let operators = dst.operators() + value.operators();
+                    let stepped = matches!(value, Expr::Binary(lhs, _, rhs)
+                        if **lhs == *dst && **rhs == Expr::Const(1));
+                    operators > 0 && operators <= MAX_EXPRESSION_OPERATORS && !stepped
+                })
+                .collect::<Vec<(Expr, Expr)>>();
+            if followed && !stores.is_empty() {
+                result.push(BlockExpressions { end, stores });
+            }
+        }
+        start = end + 1;
+    }
+    return result;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decode::decode;
+
+    fn decode_all(code: &[u8]) -> Vec<DecodedInstruction> {
+        let mut instrs = Vec::new();
+        let mut offset = 0;
+        while offset < code.len() {
+            let instr = decode(&code[offset..], 0x8000 + offset as u16).unwrap();
+            offset += instr.size();
+            instrs.push(instr);
+        }
+        return instrs;
+    }
+
+    #[test]
+    fn test_find_wide_ops() {
+        let name = |addr: u16| format!("${:02x}", addr);
+        // clc / lda $10 / adc #$20 / sta $10 / lda $11 / adc #$01 / sta $11
+        let found = find_wide_ops(&decode_all(&[
+            0x18, 0xa5, 0x10, 0x69, 0x20, 0x85, 0x10, 0xa5, 0x11, 0x69, 0x01, 0x85, 0x11,
+        ]));
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].describe(name), "16-bit add, $10 = $10 + $0120");
+
+        // sec / lda $10 / sbc $12 / sta $14 / lda $11 / sbc $13 / sta $15
+        let found = find_wide_ops(&decode_all(&[
+            0x38, 0xa5, 0x10, 0xe5, 0x12, 0x85, 0x14, 0xa5, 0x11, 0xe5, 0x13, 0x85, 0x15,
+        ]));
+        assert_eq!(found[0].describe(name), "16-bit subtract, $14 = $10 - $12");
+
+        // nop / inc $10 / bne +2 / inc $11
+        let found = find_wide_ops(&decode_all(&[0xea, 0xe6, 0x10, 0xd0, 0x02, 0xe6, 0x11]));
+        assert_eq!(found[0].start, 1);
+        assert_eq!(found[0].describe(name), "16-bit increment of $10");
+
+        // lda $10 / bne +2 / dec $11 / dec $10
+        let found = find_wide_ops(&decode_all(&[
+            0xa5, 0x10, 0xd0, 0x02, 0xc6, 0x11, 0xc6, 0x10,
+        ]));
+        assert_eq!(found[0].kind, WideOpKind::Decrement);
+
+        // lda $10 / clc / adc #$08 / sta $10 / bcc +2 / inc $11
+        let found = find_wide_ops(&decode_all(&[
+            0xa5, 0x10, 0x18, 0x69, 0x08, 0x85, 0x10, 0x90, 0x02, 0xe6, 0x11,
+        ]));
+        assert_eq!(found[0].describe(name), "16-bit add, $10 = $10 + $0008");
+
+        // the high byte of another variable
+        let found = find_wide_ops(&decode_all(&[
+            0x18, 0xa5, 0x10, 0x69, 0x20, 0x85, 0x10, 0xa5, 0x21, 0x69, 0x01, 0x85, 0x21,
+        ]));
+        assert!(found.is_empty());
+    }
+
+    #[test]
+    fn test_find_block_expressions() {
+        let name = |addr: u16| format!("${:02x}", addr);
+        let describe = |code: &[u8]| {
+            return find_block_expressions(&decode_all(code))
+                .iter()
+                .map(|found| (found.end, found.describe(name)))
+                .collect::<Vec<(usize, String)>>();
+        };
+        // lda $10 / adc #$00 / sta $10
+        assert_eq!(
+            describe(&[0xa5, 0x10, 0x69, 0x00, 0x85, 0x10]),
+            [(2, "$10 = $10 + carry".to_string())]
+        );
+        // lda $10 / sec / sbc $11 / asl / and #$0f / sta $12 / rts
+        assert_eq!(
+            describe(&[0xa5, 0x10, 0x38, 0xe5, 0x11, 0x0a, 0x29, 0x0f, 0x85, 0x12, 0x60]),
+            [(6, "$12 = $10 - $11 << 1 & $0f".to_string())]
+        );
+        // lda $10 / ora #$80 / tax / lda $0300,x / sta $11 / stx $12
+        assert_eq!(
+            describe(&[0xa5, 0x10, 0x09, 0x80, 0xaa, 0xbd, 0x00, 0x03, 0x85, 0x11, 0x86, 0x12]),
+            [(5, "$11 = $300[$10 | $80], $12 = $10 | $80".to_string())]
+        );
+        // the store after the branch target is a block of its own, and a copy or a
+        // byte stepped by one reads as well from the instructions
+        // lda $10 / clc / adc #$02 / bcc +2 / inc $11 / sta $10 / inc $12 / lda $13 / sta $14
+        assert_eq!(
+            describe(&[
+                0xa5, 0x10, 0x18, 0x69, 0x02, 0x90, 0x02, 0xe6, 0x11, 0x85, 0x10, 0xe6, 0x12, 0xa5,
+                0x13, 0x85, 0x14,
+            ]),
+            []
+        );
+        // a subroutine may have changed a: lda $10 / asl / jsr $9000 / sta $11
+        assert!(describe(&[0xa5, 0x10, 0x0a, 0x20, 0x00, 0x90, 0x85, 0x11]).is_empty());
+    }
+
+    #[test]
+    fn test_find_pointer_setups() {
+        // lda #$00 / sta $10 / lda #$90 / sta $11
+        let found = find_pointer_setups(&decode_all(&[
+            0xa9, 0x00, 0x85, 0x10, 0xa9, 0x90, 0x85, 0x11,
+        ]));
+        assert_eq!(
+            found,
+            vec![PointerSetup {
+                low: 0,
+                high: 2,
+                pointer: 0x10,
+                target: 0x9000
+            }]
+        );
+
+        // ldx #$c1 / stx $21 / ldy #$23 / sty $20
+        let found = find_pointer_setups(&decode_all(&[
+            0xa2, 0xc1, 0x86, 0x21, 0xa0, 0x23, 0x84, 0x20,
+        ]));
+        assert_eq!((found[0].low, found[0].target), (2, 0xc123));
+
+        // lda #$00 / sta $10 / lda #$90 / sta $12
+        let found = find_pointer_setups(&decode_all(&[
+            0xa9, 0x00, 0x85, 0x10, 0xa9, 0x90, 0x85, 0x12,
+        ]));
+        assert!(found.is_empty());
+    }
+}
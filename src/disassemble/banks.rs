@@ -0,0 +1,267 @@
+use std::fmt;
+
+use super::{
+    nes_header::{NesHeader, NES_CHR_ROM_PAGE_LENGTH, NES_HEADER_LENGTH, NES_PRG_ROM_PAGE_LENGTH},
+    DisassembleError,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BankKind {
+    Prg,
+    Chr,
+}
+
+impl fmt::Display for BankKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            BankKind::Prg => write!(f, "PRG"),
+            BankKind::Chr => write!(f, "CHR"),
+        };
+    }
+}
+
+/// Smallest PRG and CHR ROM windows the mapper switches, the size banks are
+/// listed and moved in. Mappers not listed are treated like NROM page sizes.
+pub fn bank_sizes(mapper: u16) -> (usize, usize) {
+    return match mapper {
+        // MMC1
+        1 => (16 * 1024, 4 * 1024),
+        // MMC3, MMC6
+        4 => (8 * 1024, 1024),
+        // AxROM, GxROM
+        7 | 66 => (32 * 1024, 8 * 1024),
+        // MMC2
+        9 => (8 * 1024, 4 * 1024),
+        // MMC4
+        10 => (16 * 1024, 4 * 1024),
+        _ => (NES_PRG_ROM_PAGE_LENGTH, NES_CHR_ROM_PAGE_LENGTH),
+    };
+}
+
+/// Whether the mapper has registers written at $8000-$ffff, as all but a few with
+/// no registers or with them below the cartridge ROM do.
+pub fn has_rom_registers(mapper: u16) -> bool {
+    // NROM, the Vs. System and mapper 87 with its register at $6000
+    return !matches!(mapper, 0 | 87 | 99);
+}
+
+/// A bank of PRG or CHR ROM in the file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bank {
+    pub kind: BankKind,
+    pub index: usize,
+    pub offset: usize,
+    pub len: usize,
+    pub crc32: u32,
+}
+
+/// The PRG then CHR ROM banks of `data`, sized by the mapper in `header`.
+pub fn list_banks(data: &[u8], header: &NesHeader) -> Result<Vec<Bank>, DisassembleError> {
+    check_layout(data, header)?;
+    let (prg_size, chr_size) = bank_sizes(header.mapper);
+    let prg = (
+        BankKind::Prg,
+        NES_HEADER_LENGTH,
+        header.prg_rom_size,
+        prg_size,
+    );
+    let chr = (
+        BankKind::Chr,
+        NES_HEADER_LENGTH + header.prg_rom_size,
+        header.chr_rom_size,
+        chr_size,
+    );
+    let mut result = Vec::new();
+    for (kind, start, rom_size, bank_size) in [prg, chr] {
+        // ROMs smaller than the mapper's window are one short bank
+        let bank_size = bank_size.min(rom_size);
+        for index in 0..rom_size.checked_div(bank_size).unwrap_or(0) {
+            let offset = start + index * bank_size;
+            result.push(Bank {
+                kind,
+                index,
+                offset,
+                len: bank_size,
+                crc32: crc32(&data[offset..offset + bank_size]),
+            });
+        }
+    }
+    return Result::Ok(result);
+}
+
+/// Parses `prg:3` or `chr:0` into the bank it names.
+pub fn parse_bank(banks: &[Bank], s: &str) -> Result<Bank, DisassembleError> {
+    let (kind, index) = parse_kind(s)?;
+    let index = index
+        .parse::<usize>()
+        .map_err(|_| DisassembleError::ParseError(format!("invalid bank \"{}\"", s)))?;
+    return banks
+        .iter()
+        .find(|b| b.kind == kind && b.index == index)
+        .cloned()
+        .ok_or_else(|| DisassembleError::ParseError(format!("no {} bank {}", kind, index)));
+}
+
+/// Writes `replacement` over `bank`, which it has to be the size of.
+pub fn replace_bank(
+    data: &mut [u8],
+    bank: &Bank,
+    replacement: &[u8],
+) -> Result<(), DisassembleError> {
+    if replacement.len() != bank.len {
+        return Result::Err(DisassembleError::ParseError(format!(
+            "{} bank {} is {} bytes, the replacement is {}",
+            bank.kind,
+            bank.index,
+            bank.len,
+            replacement.len()
+        )));
+    }
+    data[bank.offset..bank.offset + bank.len].copy_from_slice(replacement);
+    return Result::Ok(());
+}
+
+/// Rebuilds the PRG or CHR ROM from banks in the order given by `order`, e.g.
+/// `prg:1,0,3,2`. Banks may be repeated or left out, the header gets the new
+/// size.
+pub fn reorder_banks(
+    data: &[u8],
+    header: &mut NesHeader,
+    banks: &[Bank],
+    order: &str,
+) -> Result<Vec<u8>, DisassembleError> {
+    let (kind, list) = parse_kind(order)?;
+    let mut rom = Vec::new();
+    for index in list.split(',') {
+        let bank = parse_bank(banks, &format!("{}:{}", kind, index.trim()))?;
+        rom.extend_from_slice(&data[bank.offset..bank.offset + bank.len]);
+    }
+
+    let (page_length, start, old_size) = match kind {
+        BankKind::Prg => (
+            NES_PRG_ROM_PAGE_LENGTH,
+            NES_HEADER_LENGTH,
+            header.prg_rom_size,
+        ),
+        BankKind::Chr => (
+            NES_CHR_ROM_PAGE_LENGTH,
+            NES_HEADER_LENGTH + header.prg_rom_size,
+            header.chr_rom_size,
+        ),
+    };
+    if !rom.len().is_multiple_of(page_length) {
+        return Result::Err(DisassembleError::ParseError(format!(
+            "{} ROM of {} bytes isn't a whole number of {}k pages",
+            kind,
+            rom.len(),
+            page_length / 1024
+        )));
+    }
+    match kind {
+        BankKind::Prg => header.prg_rom_size = rom.len(),
+        BankKind::Chr => header.chr_rom_size = rom.len(),
+    }
+
+    let mut result = data[..start].to_vec();
+    result.extend_from_slice(&rom);
+    result.extend_from_slice(&data[start + old_size..]);
+    header.write_to(&mut result)?;
+    return Result::Ok(result);
+}
+
+fn parse_kind(s: &str) -> Result<(BankKind, &str), DisassembleError> {
+    return match s.trim().split_once(':') {
+        Option::Some((kind, rest)) if kind.eq_ignore_ascii_case("prg") => {
+            Result::Ok((BankKind::Prg, rest))
+        }
+        Option::Some((kind, rest)) if kind.eq_ignore_ascii_case("chr") => {
+            Result::Ok((BankKind::Chr, rest))
+        }
+        _ => Result::Err(DisassembleError::ParseError(format!(
+            "expected prg:... or chr:..., found \"{}\"",
+            s
+        ))),
+    };
+}
+
+fn check_layout(data: &[u8], header: &NesHeader) -> Result<(), DisassembleError> {
+    if header.trainer {
+        return Result::Err(DisassembleError::ParseError(
+            "ROMs with a trainer aren't handled".to_string(),
+        ));
+    }
+    if data.len() < header.misc_rom_offset() {
+        return Result::Err(DisassembleError::ParseError(format!(
+            "the header needs {} bytes of PRG and CHR ROM but the file only has {}",
+            header.prg_rom_size + header.chr_rom_size,
+            data.len().saturating_sub(NES_HEADER_LENGTH)
+        )));
+    }
+    return Result::Ok(());
+}
+
+/// CRC-32 as used by ROM databases like No-Intro.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffffffffu32;
+    for b in data {
+        crc ^= *b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb88320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    return !crc;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rom(prg_pages: u8, chr_pages: u8, mapper: u8) -> Vec<u8> {
+        let mut data = vec![b'N', b'E', b'S', 0x1a, prg_pages, chr_pages, mapper << 4];
+        data.resize(NES_HEADER_LENGTH, 0);
+        let len = prg_pages as usize * NES_PRG_ROM_PAGE_LENGTH
+            + chr_pages as usize * NES_CHR_ROM_PAGE_LENGTH;
+        data.extend((0..len).map(|i| (i / 1024) as u8));
+        return data;
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+    }
+
+    #[test]
+    fn test_list_banks() {
+        let data = rom(2, 1, 4);
+        let header = NesHeader::parse(&data).unwrap();
+        let banks = list_banks(&data, &header).unwrap();
+        assert_eq!(banks.len(), 4 + 8);
+        assert_eq!(banks[4].kind, BankKind::Chr);
+        assert_eq!(banks[4].offset, NES_HEADER_LENGTH + 32 * 1024);
+        assert_eq!(banks[4].len, 1024);
+        assert_eq!(parse_bank(&banks, "chr:0").unwrap(), banks[4]);
+        assert!(parse_bank(&banks, "chr:8").is_err());
+    }
+
+    #[test]
+    fn test_reorder_banks() {
+        let data = rom(2, 1, 0);
+        let mut header = NesHeader::parse(&data).unwrap();
+        let banks = list_banks(&data, &header).unwrap();
+        let reordered = reorder_banks(&data, &mut header, &banks, "prg:1,0,1").unwrap();
+        assert_eq!(header.prg_rom_size, 48 * 1024);
+        assert_eq!(reordered[4], 3);
+        assert_eq!(reordered[NES_HEADER_LENGTH], 16);
+        assert_eq!(reordered[NES_HEADER_LENGTH + 16 * 1024], 0);
+        assert_eq!(reordered.len(), data.len() + 16 * 1024);
+
+        let mut replaced = data.clone();
+        assert!(replace_bank(&mut replaced, &banks[0], &[0; 10]).is_err());
+        replace_bank(&mut replaced, &banks[0], &[0xea; 16 * 1024]).unwrap();
+        assert_eq!(replaced[NES_HEADER_LENGTH], 0xea);
+    }
+}
@@ -0,0 +1,388 @@
+//! Worst case cycle counts of routines, for checking an NMI handler's PPU updates
+//! fit in vblank.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt,
+    ops::Range,
+};
+
+use crate::decode::DecodedInstruction;
+
+use super::{loops::find_counted_loops, nes_header::Timing};
+
+/// cycles the CPU takes to push the state and jump to the handler
+pub const INTERRUPT_CYCLES: usize = 7;
+/// PPU registers and their mirrors
+const PPU_REGISTERS: Range<u16> = 0x2000..0x4000;
+/// writing it starts the sprite DMA, which stalls the CPU
+const OAM_DMA: u16 = 0x4014;
+/// cycles of the sprite DMA, one more when it starts on an odd cycle
+const OAM_DMA_CYCLES: usize = 514;
+
+/// Worst case cycles of a routine up to and including its `rts` or `rti`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Cycles {
+    pub total: usize,
+    /// cycles up to and including the last access to a PPU register or the sprite
+    /// DMA, the part that has to be done in vblank
+    pub ppu: Option<usize>,
+}
+
+impl Cycles {
+    /// `self` followed by `rest`.
+    pub fn then(self, rest: Cycles) -> Cycles {
+        return Cycles {
+            total: self.total + rest.total,
+            ppu: rest.ppu.map(|ppu| self.total + ppu).or(self.ppu),
+        };
+    }
+
+    /// The worse of `self` and `other`, each count on its own.
+    fn max(self, other: Cycles) -> Cycles {
+        return Cycles {
+            total: self.total.max(other.total),
+            ppu: self.ppu.max(other.ppu),
+        };
+    }
+
+    /// `self` run `n` times.
+    fn times(self, n: usize) -> Cycles {
+        return Cycles {
+            total: self.total * n,
+            ppu: self.ppu.map(|ppu| self.total * n.saturating_sub(1) + ppu),
+        };
+    }
+}
+
+/// Why a routine has no worst case.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unbounded {
+    /// a loop with no known count goes back to the address
+    Loop(u16),
+    /// the `jmp (...)` at the address could go anywhere
+    IndirectJump(u16),
+    /// the `jsr` at the address calls a routine already being called
+    Recursion(u16),
+    /// there is no decoded instruction at the address
+    Undecoded(u16),
+}
+
+impl fmt::Display for Unbounded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            Unbounded::Loop(addr) => {
+                write!(f, "the loop back to ${:04x} has no known count", addr)
+            }
+            Unbounded::IndirectJump(addr) => {
+                write!(f, "the indirect jump at ${:04x} can go anywhere", addr)
+            }
+            Unbounded::Recursion(addr) => write!(f, "the call at ${:04x} recurses", addr),
+            Unbounded::Undecoded(addr) => write!(f, "the code at ${:04x} isn't decoded", addr),
+        };
+    }
+}
+
+/// A loop counting a register from a constant, see [`find_counted_loops`], run as
+/// a whole.
+#[derive(Debug, Clone, Copy)]
+struct Loop {
+    branch: u16,
+    iterations: usize,
+}
+
+struct Walk<'a> {
+    decode_at: &'a dyn Fn(u16) -> Option<DecodedInstruction>,
+    routines: HashMap<u16, Result<Cycles, Unbounded>>,
+    calling: HashSet<u16>,
+}
+
+impl Walk<'_> {
+    fn routine(&mut self, entry: u16) -> Result<Cycles, Unbounded> {
+        if let Option::Some(result) = self.routines.get(&entry) {
+            return *result;
+        }
+        self.calling.insert(entry);
+        let result = self.instructions(entry).and_then(|instrs| {
+            let loops = counted_loops(&instrs);
+            let mut walk = RoutineWalk {
+                instrs: &instrs,
+                loops: &loops,
+                done: HashMap::new(),
+                walking: HashSet::new(),
+            };
+            return walk.from(self, entry, Option::None);
+        });
+        self.calling.remove(&entry);
+        self.routines.insert(entry, result);
+        return result;
+    }
+
+    /// The instructions of the routine at `entry` by address, up to the `rts` or
+    /// `rti` of each path, calls left out.
+    fn instructions(&self, entry: u16) -> Result<BTreeMap<u16, DecodedInstruction>, Unbounded> {
+        let mut instrs = BTreeMap::new();
+        let mut pending = vec![entry];
+        while let Option::Some(addr) = pending.pop() {
+            if instrs.contains_key(&addr) {
+                continue;
+            }
+            let instr = (self.decode_at)(addr).ok_or(Unbounded::Undecoded(addr))?;
+            instrs.insert(addr, instr);
+            pending.extend(successors(&instr)?);
+        }
+        return Result::Ok(instrs);
+    }
+}
+
+struct RoutineWalk<'a> {
+    instrs: &'a BTreeMap<u16, DecodedInstruction>,
+    loops: &'a HashMap<u16, Loop>,
+    /// worst case from each address to the end of the routine, or of the loop body
+    /// it is in
+    done: HashMap<(u16, Option<u16>), Cycles>,
+    walking: HashSet<(u16, Option<u16>)>,
+}
+
+impl RoutineWalk<'_> {
+    /// Worst case from `addr` to the end of the routine or, in the body of the loop
+    /// `in_loop` branches back at, to the end of the iteration.
+    fn from(
+        &mut self,
+        walk: &mut Walk,
+        addr: u16,
+        in_loop: Option<u16>,
+    ) -> Result<Cycles, Unbounded> {
+        let key = (addr, in_loop);
+        if let Option::Some(cycles) = self.done.get(&key) {
+            return Result::Ok(*cycles);
+        }
+        if !self.walking.insert(key) {
+            return Result::Err(Unbounded::Loop(addr));
+        }
+        let result = self.walk(walk, addr, in_loop);
+        self.walking.remove(&key);
+        let cycles = result?;
+        self.done.insert(key, cycles);
+        return Result::Ok(cycles);
+    }
+
+    fn walk(
+        &mut self,
+        walk: &mut Walk,
+        addr: u16,
+        in_loop: Option<u16>,
+    ) -> Result<Cycles, Unbounded> {
+        let instr = self.instrs[&addr];
+        if let Option::Some(counted) = self.loops.get(&addr).copied() {
+            if in_loop != Option::Some(counted.branch) {
+                let body = self.from(walk, addr, Option::Some(counted.branch))?;
+                // going on after the last iteration or leaving the body early
+                let mut after = Cycles::default();
+                for exit in self.exits(addr, counted.branch) {
+                    after = after.max(self.from(walk, exit, in_loop)?);
+                }
+                return Result::Ok(body.times(counted.iterations).then(after));
+            }
+        }
+        let mut cycles = instruction_cycles(&instr);
+        if instr.mnemonic == "jsr" {
+            let target = instr.operand;
+            if walk.calling.contains(&target) {
+                return Result::Err(Unbounded::Recursion(addr));
+            }
+            cycles = cycles.then(walk.routine(target)?);
+        }
+        if in_loop == Option::Some(addr) {
+            return Result::Ok(cycles);
+        }
+        let (low, high) = match in_loop.and_then(|branch| self.loops_body(branch)) {
+            Option::Some((head, branch)) => (head, branch),
+            Option::None => (0, u16::MAX),
+        };
+        let mut rest = Cycles::default();
+        for next in successors(&instr)? {
+            // leaving a loop body ends the iteration, what follows is counted once
+            // after the loop
+            if !(low..=high).contains(&next) {
+                continue;
+            }
+            rest = rest.max(self.from(walk, next, in_loop)?);
+        }
+        return Result::Ok(cycles.then(rest));
+    }
+
+    /// First and last address of the body of the loop branching back at `branch`.
+    fn loops_body(&self, branch: u16) -> Option<(u16, u16)> {
+        return self
+            .loops
+            .iter()
+            .find(|(_, l)| l.branch == branch)
+            .map(|(head, _)| (*head, branch));
+    }
+
+    /// Where the loop `head..=branch` goes on to: after the branch, and wherever
+    /// the body jumps out to.
+    fn exits(&self, head: u16, branch: u16) -> Vec<u16> {
+        let mut exits = Vec::new();
+        for (addr, instr) in self.instrs.range(head..=branch) {
+            for next in successors(instr).unwrap_or_default() {
+                if !(head..=branch).contains(&next) || (*addr == branch && next != head) {
+                    exits.push(next);
+                }
+            }
+        }
+        return exits;
+    }
+}
+
+/// Where execution can go after `instr` within its routine, nothing after the end
+/// of it. A `jsr` returns to the next instruction.
+fn successors(instr: &DecodedInstruction) -> Result<Vec<u16>, Unbounded> {
+    let next = instr.addr.wrapping_add(instr.size() as u16);
+    return match instr.mnemonic {
+        "rts" | "rti" | "brk" => Result::Ok(Vec::new()),
+        "jmp" => match instr.target() {
+            Option::Some(target) => Result::Ok(vec![target]),
+            Option::None => Result::Err(Unbounded::IndirectJump(instr.addr)),
+        },
+        _ => match instr.target() {
+            Option::Some(target) if instr.mnemonic != "jsr" => Result::Ok(vec![next, target]),
+            _ => Result::Ok(vec![next]),
+        },
+    };
+}
+
+/// Most cycles `instr` takes, with the sprite DMA it starts.
+fn instruction_cycles(instr: &DecodedInstruction) -> Cycles {
+    let mut total = instr.cycles().1 as usize;
+    let operand = instr.mode.operand_len() == 2 && !matches!(instr.mnemonic, "jsr" | "jmp");
+    if operand && instr.operand == OAM_DMA && instr.mnemonic.starts_with("st") {
+        total += OAM_DMA_CYCLES;
+    }
+    let ppu = operand && (PPU_REGISTERS.contains(&instr.operand) || instr.operand == OAM_DMA);
+    return Cycles {
+        total,
+        ppu: ppu.then_some(total),
+    };
+}
+
+/// The counted loops of a routine by the address of their head.
+fn counted_loops(instrs: &BTreeMap<u16, DecodedInstruction>) -> HashMap<u16, Loop> {
+    // find_counted_loops takes instructions following each other in memory
+    let mut runs: Vec<Vec<DecodedInstruction>> = Vec::new();
+    for instr in instrs.values() {
+        match runs.last_mut() {
+            Option::Some(run)
+                if run.last().is_some_and(|last| {
+                    last.addr.wrapping_add(last.size() as u16) == instr.addr
+                }) =>
+            {
+                run.push(*instr)
+            }
+            _ => runs.push(vec![*instr]),
+        }
+    }
+    let mut loops = HashMap::new();
+    for run in runs {
+        for found in find_counted_loops(&run) {
+            let counted = Loop {
+                branch: run[found.branch].addr,
+                iterations: found.iterations,
+            };
+            loops.insert(run[found.head].addr, counted);
+        }
+    }
+    return loops;
+}
+
+/// CPU cycles of vblank, 20 scanlines of 341 dots, for the slowest console `timing`
+/// runs on.
+pub fn vblank_cycles(timing: Timing) -> usize {
+    return match timing {
+        // 70 scanlines, 3.2 dots a cycle
+        Timing::Pal => 7459,
+        // 3 dots a cycle, Dendy running the NMI after its extra idle scanlines
+        Timing::Ntsc | Timing::MultiRegion | Timing::Dendy => 2273,
+    };
+}
+
+/// Worst case cycles of the routine at `entry`, following the calls it makes and
+/// running each loop counting a register from a constant as many times as it
+/// counts. Branches are taken as taking their longest time, indexing as crossing a
+/// page. `decode_at` gives the instruction decoded at an address.
+pub fn worst_case_cycles(
+    entry: u16,
+    decode_at: &dyn Fn(u16) -> Option<DecodedInstruction>,
+) -> Result<Cycles, Unbounded> {
+    let mut walk = Walk {
+        decode_at,
+        routines: HashMap::new(),
+        calling: HashSet::new(),
+    };
+    return walk.routine(entry);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decode::decode;
+
+    fn cycles_of(bytes: &[u8]) -> Result<Cycles, Unbounded> {
+        let decode_at = |addr: u16| {
+            let offset = addr.checked_sub(0x8000)? as usize;
+            return decode(bytes.get(offset..)?, addr);
+        };
+        return worst_case_cycles(0x8000, &decode_at);
+    }
+
+    #[test]
+    fn test_worst_case_cycles() {
+        // lda #$00, sta $2001, rti
+        assert_eq!(
+            cycles_of(&[0xa9, 0x00, 0x8d, 0x01, 0x20, 0x40]),
+            Result::Ok(Cycles {
+                total: 2 + 4 + 6,
+                ppu: Option::Some(2 + 4)
+            })
+        );
+
+        // lda #$02, sta $4014, jsr $800a, rti, pad, lda $00, beq +2, inc $01, rts
+        let dma = [
+            0xa9, 0x02, 0x8d, 0x14, 0x40, 0x20, 0x0a, 0x80, 0x40, 0xea, 0xa5, 0x00, 0xf0, 0x02,
+            0xe6, 0x01, 0x60,
+        ];
+        let called = 3 + 3 + 5 + 6;
+        assert_eq!(
+            cycles_of(&dma),
+            Result::Ok(Cycles {
+                total: 2 + 4 + 514 + 6 + called + 6,
+                ppu: Option::Some(2 + 4 + 514)
+            })
+        );
+
+        // ldx #$04, loop: lda $0200,x, sta $2007, dex, bne loop, rti
+        let copy = [
+            0xa2, 0x04, 0xbd, 0x00, 0x02, 0x8d, 0x07, 0x20, 0xca, 0xd0, 0xf7, 0x40,
+        ];
+        let body = 5 + 4 + 2 + 3;
+        assert_eq!(
+            cycles_of(&copy),
+            Result::Ok(Cycles {
+                total: 2 + 4 * body + 6,
+                ppu: Option::Some(2 + 3 * body + 5 + 4)
+            })
+        );
+
+        // wait: bit $2002, bpl wait, rti
+        assert_eq!(
+            cycles_of(&[0x2c, 0x02, 0x20, 0x10, 0xfb, 0x40]),
+            Result::Err(Unbounded::Loop(0x8000))
+        );
+        // jmp ($0010)
+        assert_eq!(
+            cycles_of(&[0x6c, 0x10, 0x00]),
+            Result::Err(Unbounded::IndirectJump(0x8000))
+        );
+    }
+}
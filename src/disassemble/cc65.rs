@@ -0,0 +1,102 @@
+use std::fmt;
+
+/// What a cc65 runtime routine does to the C argument stack.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StackOp {
+    Push(ArgType),
+    Pop(ArgType),
+    /// drops bytes without reading them, how functions clean up their arguments
+    Drop(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgType {
+    Char,
+    Int,
+}
+
+impl ArgType {
+    pub fn size(&self) -> usize {
+        return match self {
+            ArgType::Char => 1,
+            ArgType::Int => 2,
+        };
+    }
+}
+
+impl fmt::Display for ArgType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            ArgType::Char => write!(f, "char"),
+            ArgType::Int => write!(f, "int"),
+        };
+    }
+}
+
+/// Stack effect of the runtime routine named `name` by the signature matcher.
+pub fn stack_op(name: &str) -> Option<StackOp> {
+    return match name {
+        "cc65_pusha" => Option::Some(StackOp::Push(ArgType::Char)),
+        "cc65_pushax" => Option::Some(StackOp::Push(ArgType::Int)),
+        "cc65_popa" => Option::Some(StackOp::Pop(ArgType::Char)),
+        "cc65_popax" => Option::Some(StackOp::Pop(ArgType::Int)),
+        "cc65_incsp1" => Option::Some(StackOp::Drop(1)),
+        "cc65_incsp2" => Option::Some(StackOp::Drop(2)),
+        _ => Option::None,
+    };
+}
+
+/// Arguments a function is called with on the stack, in the order they are pushed.
+/// With `__fastcall__`, the cc65 default, the last argument is passed in A/X
+/// instead and isn't part of this.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Prototype {
+    pub name: String,
+    pub args: Vec<ArgType>,
+}
+
+impl Prototype {
+    pub fn stack_size(&self) -> usize {
+        return self.args.iter().map(|a| a.size()).sum();
+    }
+}
+
+impl fmt::Display for Prototype {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let args = self
+            .args
+            .iter()
+            .map(|a| a.to_string())
+            .collect::<Vec<String>>()
+            .join(", ");
+        return write!(
+            f,
+            "{}({}), {} bytes of arguments on the cc65 stack",
+            self.name,
+            args,
+            self.stack_size()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prototype() {
+        let p = Prototype {
+            name: "draw".to_string(),
+            args: vec![ArgType::Char, ArgType::Int],
+        };
+        assert_eq!(
+            p.to_string(),
+            "draw(char, int), 3 bytes of arguments on the cc65 stack"
+        );
+        assert_eq!(
+            stack_op("cc65_pushax"),
+            Option::Some(StackOp::Push(ArgType::Int))
+        );
+        assert_eq!(stack_op("cc65_ldaxysp"), Option::None);
+    }
+}
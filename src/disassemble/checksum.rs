@@ -0,0 +1,99 @@
+use super::signatures::{find_signatures, Library, Signature};
+
+/// bytes before a checksum loop searched for the setup of its pointer
+const MAX_SETUP_BYTES: usize = 16;
+
+// Loops adding or xoring every byte from the pointer up to $ffff a page at a
+// time, the usual way games check at runtime that their PRG ROM wasn't patched.
+const CHECKSUMS: Library = Library {
+    name: "checksum",
+    prefix: "checksum_",
+    min_matches: 1,
+    signatures: &[
+        Signature {
+            name: "add",
+            pattern: "b1 ptr 18 65 sum 85 sum c8 d0 ?? e6 ptr+1 d0 ??",
+        },
+        Signature {
+            name: "adc",
+            pattern: "b1 ptr 65 sum 85 sum c8 d0 ?? e6 ptr+1 d0 ??",
+        },
+        Signature {
+            name: "eor",
+            pattern: "b1 ptr 45 sum 85 sum c8 d0 ?? e6 ptr+1 d0 ??",
+        },
+    ],
+};
+
+/// A loop summing the ROM, which fails its check once bytes in `start..=$ffff`
+/// change.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChecksumRoutine {
+    /// `checksum_` and how it sums
+    pub name: String,
+    pub offset: usize,
+    pub len: usize,
+    /// zero page pointer walking the ROM
+    pub ptr: u8,
+    /// zero page byte the sum is kept in
+    pub sum: u8,
+    /// first address summed, $8000 when the setup of the pointer isn't found
+    pub start: u16,
+}
+
+/// Finds the checksum loops in `data[start..end]`.
+pub fn find_checksum_routines(data: &[u8], start: usize, end: usize) -> Vec<ChecksumRoutine> {
+    return find_signatures(data, start, end, &[CHECKSUMS])
+        .into_iter()
+        .map(|m| {
+            let ptr = m.zero_page["checksum_ptr"];
+            let setup = &data[m.offset.saturating_sub(MAX_SETUP_BYTES).max(start)..m.offset];
+            // the last `lda #imm` / `sta zp` of each pointer byte
+            let stored = |zp: u8| {
+                return setup
+                    .windows(4)
+                    .rev()
+                    .find(|w| w[0] == 0xa9 && w[2] == 0x85 && w[3] == zp)
+                    .map(|w| w[1]);
+            };
+            let start = match stored(ptr.wrapping_add(1)) {
+                Option::Some(high) => u16::from_le_bytes([stored(ptr).unwrap_or(0), high]),
+                Option::None => 0x8000,
+            };
+            return ChecksumRoutine {
+                name: m.name,
+                offset: m.offset,
+                len: m.len,
+                ptr,
+                sum: m.zero_page["checksum_sum"],
+                start,
+            };
+        })
+        .collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_checksum_routines() {
+        let mut data = vec![0xea; 64];
+        // lda #$c0 / sta $11 / lda #$00 / sta $10 / ldy #$00, then the eor loop
+        data[8..31].copy_from_slice(&[
+            0xa9, 0xc0, 0x85, 0x11, 0xa9, 0x00, 0x85, 0x10, 0xa0, 0x00, 0xb1, 0x10, 0x45, 0x12,
+            0x85, 0x12, 0xc8, 0xd0, 0xf7, 0xe6, 0x11, 0xd0, 0xf3,
+        ]);
+
+        let found = find_checksum_routines(&data, 0, data.len());
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].name, "checksum_eor");
+        assert_eq!(found[0].offset, 18);
+        assert_eq!((found[0].ptr, found[0].sum), (0x10, 0x12));
+        assert_eq!(found[0].start, 0xc000);
+
+        // without the setup the whole PRG ROM is assumed
+        let found = find_checksum_routines(&data, 18, data.len());
+        assert_eq!(found[0].start, 0x8000);
+    }
+}
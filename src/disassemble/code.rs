@@ -1,10 +1,33 @@
 use itertools::Itertools;
-use std::{collections::HashMap, fmt, io::Write, mem};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fmt,
+    io::Write,
+    mem,
+    ops::{Range, RangeInclusive},
+    rc::Rc,
+};
 
 use crate::disassemble::DisassembleError;
 
+use super::address::FileOffset;
+use super::dialect::{AsmDialect, Ca65};
+use super::hexdump::hexdump;
 use super::instruction::Instruction;
-use super::variable::Variable;
+use super::intern::Interner;
+use super::template::OutputTemplate;
+use super::variable::{Variable, VariableValue};
+
+/// bytes on each `.verify` line, see [`Code::set_verify`]
+const VERIFY_BYTES_PER_LINE: usize = 16;
+/// words on each `.word` line, see [`Code::mark_words`]
+const WORDS_PER_LINE: usize = 8;
+/// bytes of a statement shown in a listing, as many as the longest instruction has
+const LISTING_BYTES: usize = 3;
+/// width of the address and bytes columns of a listing, `8000: A9 00 00+ `, a `+`
+/// marking a statement with more bytes than shown
+const LISTING_WIDTH: usize = 16;
 
 #[derive(Debug)]
 pub enum AsmCode {
@@ -14,14 +37,20 @@ pub enum AsmCode {
     DataBinaryU8(u8),
     DataString(String),
     DataSeq(Vec<AsmCode>),
+    /// little endian words, see [`Code::mark_words`]
+    DataWords(Vec<u16>),
     Instruction(Instruction),
+    /// bytes missing from the input, see [`Code::pad`]
+    Res(usize),
+    /// a run of the same byte, see [`Code::collapse_fill`]
+    Fill(usize, u8),
     Used,
 }
 
 impl fmt::Display for AsmCode {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let mut addr_to_variable = HashMap::new();
-        return write!(f, "{}", self.to_write_string(&mut addr_to_variable));
+        return write!(f, "{}", self.to_write_string(&Ca65, &mut addr_to_variable));
     }
 }
 
@@ -45,31 +74,37 @@ impl AsmCode {
         };
     }
 
-    pub fn to_write_string(&self, addr_to_variable: &mut HashMap<u16, Variable>) -> String {
+    pub fn to_write_string(
+        &self,
+        dialect: &dyn AsmDialect,
+        addr_to_variable: &mut HashMap<u16, Variable>,
+    ) -> String {
+        let bytes = dialect.bytes();
         return match self {
             AsmCode::DataHexU8(v) => {
-                format!(".byte ${:02X?}", v)
+                format!("{} {}", bytes, dialect.hex_u8(*v))
             }
             AsmCode::DataHexU16(v) => {
-                format!(".byte ${:04X?}", v)
+                format!("{} {}", dialect.words(), dialect.hex_u16(*v))
             }
             AsmCode::DataU8(v) => {
-                format!(".byte {}", v)
+                format!("{} {}", bytes, v)
             }
             AsmCode::DataBinaryU8(v) => {
-                format!(".byte {:#010b}", v)
+                format!("{} {}", bytes, dialect.binary_u8(*v))
             }
             AsmCode::DataString(str) => {
-                format!(".byte \"{}\"", str)
+                format!("{} \"{}\"", bytes, str)
             }
             AsmCode::DataSeq(v) => {
                 return format!(
-                     ".byte {}",
+                     "{} {}",
+                            bytes,
                             v.iter()
                                 .map(|i| match i {
-                                    AsmCode::DataHexU8(v) => format!("${:02X?}", v),
+                                    AsmCode::DataHexU8(v) => dialect.hex_u8(*v),
                                     AsmCode::DataU8(v) => format!("{}", v),
-                                    AsmCode::DataBinaryU8(v) => format!("{:#010b}", v),
+                                    AsmCode::DataBinaryU8(v) => dialect.binary_u8(*v),
                                     AsmCode::DataString(str) => format!("\"{}\"", str),
                                     v => panic!(
                                         "data sequence can only contain data elements. found: {}",
@@ -80,30 +115,231 @@ impl AsmCode {
                                 .join(", ")
                 );
             }
+            AsmCode::DataWords(v) => {
+                let words = v.iter().map(|w| dialect.hex_u16(*w)).join(", ");
+                format!("{} {}", dialect.words(), words)
+            }
             AsmCode::Instruction(instr) => {
-                format!("    {}", instr.to_write_string(addr_to_variable))
+                let instr = dialect.instruction(instr.to_write_string(addr_to_variable));
+                format!("    {}", instr)
+            }
+            AsmCode::Res(len) => format!("{} {}", dialect.reserve(), len),
+            AsmCode::Fill(len, value) => {
+                format!("{} {}, {}", dialect.reserve(), len, dialect.hex_u8(*value))
             }
-            AsmCode::Used => format!(""),
+            AsmCode::Used => String::new(),
         };
     }
 }
 
 pub struct Statement {
     pub asm_code: AsmCode,
-    pub comment: Option<String>,
+    pub comment: Option<Rc<str>>,
+    pub segment: Option<Rc<str>>,
+    pub label: Option<Rc<str>>,
+}
+
+#[derive(Serialize)]
+pub struct JsonStatement {
+    pub offset: usize,
+    /// address the CPU sees the statement at
+    pub addr: u16,
+    /// input bytes the statement was decoded from, in hex, `A9 00`
+    pub bytes: String,
+    /// segment the statement is in
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub segment: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub label: Option<String>,
+    pub asm: String,
+    /// for instructions, `asm` split into the mnemonic and what follows it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mnemonic: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub operands: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    /// entry points the instruction can be reached from
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub reached_from: Vec<String>,
+}
+
+struct LineCountingWriter<'a> {
+    out: &'a mut dyn Write,
+    lines: usize,
+}
+
+impl Write for LineCountingWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let len = self.out.write(buf)?;
+        self.lines += buf[..len].iter().filter(|b| **b == b'\n').count();
+        return Result::Ok(len);
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        return self.out.flush();
+    }
+}
+
+/// How [`Code::write_split`] splits the source into files, each included from the
+/// main one where its part would be written.
+pub struct SourceSplit {
+    /// file each segment is written to, those of segments not in it staying in the
+    /// main file
+    pub segment_files: HashMap<String, String>,
+    /// file the constants for hardware registers are written to
+    pub registers_file: String,
+    /// addresses of the hardware registers
+    pub registers: Range<u16>,
+}
+
+/// files written by [`Code::write_split`] besides the main one, by name
+pub type SplitFiles = Vec<(String, Vec<u8>)>;
+
+/// Writes to `main` until [`SplitWriter::switch`] moves on to another file, which
+/// is included from `main` where it is first switched to.
+struct SplitWriter<'a> {
+    main: LineCountingWriter<'a>,
+    include: &'a dyn Fn(&str) -> String,
+    files: SplitFiles,
+    current: Option<usize>,
+}
+
+impl SplitWriter<'_> {
+    /// Writes to `file` from now on, to `main` if `None`.
+    fn switch(&mut self, file: Option<&String>) -> Result<(), DisassembleError> {
+        let file = match file {
+            Option::Some(file) => file,
+            Option::None => {
+                self.current = Option::None;
+                return Result::Ok(());
+            }
+        };
+        self.current = match self.files.iter().position(|(name, _)| name == file) {
+            Option::Some(index) => Option::Some(index),
+            Option::None => {
+                writeln!(self.main, "{}", (self.include)(file))?;
+                self.files.push((file.clone(), Vec::new()));
+                Option::Some(self.files.len() - 1)
+            }
+        };
+        return Result::Ok(());
+    }
+}
+
+impl Write for SplitWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        return match self.current {
+            Option::Some(index) => self.files[index].1.write(buf),
+            Option::None => self.main.write(buf),
+        };
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        return self.main.flush();
+    }
 }
 
 pub struct Code {
     stmts: Vec<Statement>,
+    /// the input as read, for showing it in errors
+    data: Vec<u8>,
     addr_to_variable: HashMap<u16, Variable>,
+    /// `ZP_xx` and `ABS_xxxx` names for the operands of the decoded instructions,
+    /// by the offset of each instruction using them. The first one's addressing
+    /// mode picks the name
+    operand_variables: HashMap<u16, BTreeMap<usize, Variable>>,
+    /// labels renamed by the user, applied to labels added later as well
+    renamed_labels: HashMap<String, String>,
+    /// statements written right after the statement at the key instead of where
+    /// they are in the file
+    moves: HashMap<FileOffset, Range<FileOffset>>,
+    /// what decoded the statement at an offset, for reporting overlaps
+    claims: HashMap<FileOffset, String>,
+    /// ca65 `.setcpu` the code needs, when more than the documented 6502 opcodes
+    cpu: Option<&'static str>,
+    /// offsets of the statements written, all of them when not set
+    only: Option<HashSet<usize>>,
+    /// operand addresses written as numbers even if they have a name
+    unnamed: Vec<RangeInclusive<u16>>,
+    /// labels written with `.export`, by offset so they stay exported when renamed
+    exports: BTreeSet<FileOffset>,
+    /// labels, segment names and comments of the statements
+    strings: Interner,
+    /// addresses of the jumps, branches and calls to each offset
+    xrefs: BTreeMap<FileOffset, BTreeSet<u16>>,
+    /// last statement of the routines written as `.proc`, by their first
+    procs: BTreeMap<FileOffset, FileOffset>,
+    /// the character each byte of the strings stands for, written as `.charmap`
+    charmap: BTreeMap<u8, char>,
+    /// last statement of the routines whose bytes are written as `.verify`, by
+    /// their first
+    verified: BTreeMap<FileOffset, FileOffset>,
+    /// writes the statements, ca65 unless set
+    dialect: Box<dyn AsmDialect>,
+    /// header, footer, segment banners and labels as styled by `--template`
+    template: Option<Box<dyn OutputTemplate>>,
+    /// address of the statement starting each segment, set with `.base` by the
+    /// dialects without segments
+    segment_addrs: BTreeMap<FileOffset, u16>,
+    /// write each statement after its address and bytes, see [`Code::set_listing`]
+    listing: bool,
+    /// write each segment as a scope of its own, see [`Code::set_scoped`]
+    scoped: bool,
+}
+
+/// The scope each statement and label is in, named after the segment, for writing
+/// the segments as scopes.
+struct Scopes<'a> {
+    /// scope of the statements from each offset on
+    starts: BTreeMap<usize, String>,
+    labels: HashMap<&'a str, String>,
+}
+
+impl Scopes<'_> {
+    /// Offsets of the statements in the scope of the statement at `offset`.
+    fn range(&self, offset: usize) -> Option<(Range<usize>, &str)> {
+        let (start, scope) = self.starts.range(..=offset).next_back()?;
+        let end = self
+            .starts
+            .range(offset + 1..)
+            .next()
+            .map_or(usize::MAX, |(end, _)| *end);
+        return Option::Some((*start..end, scope));
+    }
+
+    /// `asm` written in the scope of the statement at `offset`, the labels of other
+    /// scopes qualified with theirs.
+    fn qualify(&self, dialect: &dyn AsmDialect, offset: usize, asm: &str) -> String {
+        let scope = self.range(offset).map(|(_, scope)| scope);
+        let mut result = String::new();
+        let mut rest = asm;
+        while let Option::Some(start) = rest.find(|c: char| c.is_ascii_alphabetic() || c == '_') {
+            let (before, word) = rest.split_at(start);
+            let len = word
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(word.len());
+            let (word, next) = word.split_at(len);
+            result += before;
+            // the letters of `$A0` and `$0A` aren't a name
+            let number = before.ends_with(|c: char| c == '$' || c == '%' || c.is_ascii_digit());
+            match self.labels.get(word) {
+                Option::Some(of) if !number && scope != Option::Some(of.as_str()) => {
+                    result += &dialect.qualified(of, word)
+                }
+                _ => result += word,
+            }
+            rest = next;
+        }
+        result += rest;
+        return result;
+    }
 }
 
 impl Code {
     pub fn new(data: Vec<u8>) -> Code {
         let mut stmts = Vec::new();
-        for value in data {
+        for value in data.iter().copied() {
             stmts.push(Statement {
                 asm_code: AsmCode::DataHexU8(value),
                 comment: Option::None,
@@ -114,143 +350,1543 @@ impl Code {
 
         return Code {
             stmts,
+            data,
             addr_to_variable: HashMap::new(),
+            operand_variables: HashMap::new(),
+            renamed_labels: HashMap::new(),
+            moves: HashMap::new(),
+            claims: HashMap::new(),
+            cpu: Option::None,
+            only: Option::None,
+            unnamed: Vec::new(),
+            exports: BTreeSet::new(),
+            strings: Interner::default(),
+            xrefs: BTreeMap::new(),
+            procs: BTreeMap::new(),
+            charmap: BTreeMap::new(),
+            verified: BTreeMap::new(),
+            dialect: Box::new(Ca65),
+            template: Option::None,
+            segment_addrs: BTreeMap::new(),
+            listing: false,
+            scoped: false,
         };
     }
 
-    pub fn set_variable(&mut self, addr: u16, variable: Variable) {
-        self.addr_to_variable.insert(addr, variable);
+    /// the input as read, before anything was decoded
+    pub fn data(&self) -> &[u8] {
+        return &self.data;
     }
 
-    pub fn is_eq_u8(&self, offset: usize, d: u8) -> bool {
-        return self.stmts[offset].asm_code.is_eq_u8(d);
+    /// Input bytes around `offset`, see [`hexdump`].
+    pub fn hexdump(&self, offset: FileOffset) -> String {
+        return hexdump(&self.data, offset.0);
     }
 
-    pub fn take(&mut self, offset: usize) -> Result<Statement, DisassembleError> {
-        return Result::Ok(mem::replace(
-            &mut self.stmts[offset],
-            Statement {
+    /// offset just past the last statement
+    pub fn end(&self) -> FileOffset {
+        return FileOffset(self.stmts.len());
+    }
+
+    /// Adds a `.res` statement covering the bytes between the current end and `end`
+    /// so segments keep their size when the input is shorter than expected.
+    pub fn pad(&mut self, end: FileOffset, comment: &str) {
+        let start = self.end();
+        if end <= start {
+            return;
+        }
+        self.stmts.push(Statement {
+            asm_code: AsmCode::Res(end.0 - start.0),
+            comment: Option::Some(self.strings.intern(comment)),
+            segment: Option::None,
+            label: Option::None,
+        });
+        while self.end() < end {
+            self.stmts.push(Statement {
                 asm_code: AsmCode::Used,
                 comment: Option::None,
                 segment: Option::None,
                 label: Option::None,
-            },
-        ));
+            });
+        }
+    }
+
+    /// Writes the `len` bytes at `offset`, all `value`, as a single `.res`.
+    pub fn collapse_fill(&mut self, offset: FileOffset, len: usize, value: u8) {
+        for i in offset.0..offset.0 + len {
+            self.remove_operand_variables(i);
+            self.stmts[i].asm_code = AsmCode::Used;
+        }
+        self.stmts[offset.0].asm_code = AsmCode::Fill(len, value);
+        self.claim(offset, "padding");
+    }
+
+    pub fn set_variable(&mut self, addr: u16, variable: Variable) {
+        self.addr_to_variable.insert(addr, variable);
+    }
+
+    /// Keeps operands in `range` from being replaced by variable names or labels,
+    /// e.g. for operands code modifies or table bases also used as constants.
+    pub fn keep_unnamed(&mut self, range: RangeInclusive<u16>) {
+        self.unnamed.push(range);
+    }
+
+    /// The variable named for `addr`, not counting the generated `ZP_xx` names.
+    pub fn variable(&self, addr: u16) -> Option<&Variable> {
+        return self.addr_to_variable.get(&addr);
+    }
+
+    /// The name operands at `addr` are written with, the generated `ZP_xx` names
+    /// included.
+    pub fn operand_name(&self, addr: u16) -> Option<&str> {
+        if self.unnamed.iter().any(|range| range.contains(&addr)) {
+            return Option::None;
+        }
+        return self
+            .addr_to_variable
+            .get(&addr)
+            .or_else(|| {
+                self.operand_variables
+                    .get(&addr)
+                    .and_then(|users| users.values().next())
+            })
+            .filter(|variable| variable.is_named())
+            .map(|variable| variable.name.as_str());
+    }
+
+    pub fn is_eq_u8(&self, offset: FileOffset, d: u8) -> bool {
+        return self.stmts[offset.0].asm_code.is_eq_u8(d);
+    }
+
+    /// Takes the code at `offset`, leaving [`AsmCode::Used`]. The label, comment
+    /// and segment stay, for whatever is set there next, see [`Code::set`].
+    pub fn take(&mut self, offset: FileOffset) -> Result<Statement, DisassembleError> {
+        self.remove_operand_variables(offset.0);
+        return Result::Ok(Statement {
+            asm_code: mem::replace(&mut self.stmts[offset.0].asm_code, AsmCode::Used),
+            comment: Option::None,
+            segment: Option::None,
+            label: Option::None,
+        });
     }
 
-    pub fn get_u8(&self, offset: usize) -> Result<u8, DisassembleError> {
-        return self.stmts[offset].asm_code.to_u8();
+    pub fn get_u8(&self, offset: FileOffset) -> Result<u8, DisassembleError> {
+        return self.stmts[offset.0].asm_code.to_u8();
     }
 
-    pub fn get_i8(&self, offset: usize) -> Result<i8, DisassembleError> {
+    pub fn get_i8(&self, offset: FileOffset) -> Result<i8, DisassembleError> {
         return Result::Ok(self.get_u8(offset)? as i8);
     }
 
-    pub fn set(&mut self, offset: usize, stmt: Statement) -> Result<(), DisassembleError> {
-        self.stmts[offset] = stmt;
+    /// Replaces the statement at `offset`, keeping the label, comment and segment
+    /// already there where `stmt` has none. When both have a label the old one is
+    /// renamed to the new one, so what jumps to it follows, and both comments are
+    /// kept.
+    pub fn set(&mut self, offset: FileOffset, stmt: Statement) -> Result<(), DisassembleError> {
+        self.remove_operand_variables(offset.0);
+        let old = mem::replace(&mut self.stmts[offset.0], stmt);
+        self.add_operand_variables(offset.0);
+        match (old.label, self.stmts[offset.0].label.clone()) {
+            (Option::Some(old_label), Option::Some(label)) if old_label != label => {
+                self.rename_label(&old_label, &label);
+            }
+            (Option::Some(old_label), Option::None) => {
+                self.stmts[offset.0].label = Option::Some(old_label);
+            }
+            _ => {}
+        }
+        if let Option::Some(comment) = old.comment {
+            self.merge_comment(offset, &comment);
+        }
+        let stmt = &mut self.stmts[offset.0];
+        stmt.segment = stmt.segment.take().or(old.segment);
         return Result::Ok(());
     }
 
+    /// Keeps `comment` ahead of the comment at `offset`, unless it's the same one.
+    fn merge_comment(&mut self, offset: FileOffset, comment: &str) {
+        let merged = match &self.stmts[offset.0].comment {
+            Option::Some(existing) if existing.as_ref() == comment => return,
+            Option::Some(existing) => format!("{}\n{}", comment, existing),
+            Option::None => comment.to_string(),
+        };
+        self.stmts[offset.0].comment = Option::Some(self.strings.intern(&merged));
+    }
+
+    /// Writes `new_code` at the start of `range`, the rest of it becoming part of
+    /// that statement. Labels in the rest stay where they are and are written
+    /// relative to the statement, see [`Code::inner_labels`], and comments move to
+    /// the start.
     pub fn replace(
         &mut self,
-        range: std::ops::Range<usize>,
+        range: std::ops::Range<FileOffset>,
         new_code: AsmCode,
     ) -> Result<(), DisassembleError> {
-        for i in range.clone() {
+        for i in range.start.0..range.end.0 {
+            self.remove_operand_variables(i);
             self.stmts[i].asm_code = AsmCode::Used;
+            if i > range.start.0 {
+                if let Option::Some(comment) = self.stmts[i].comment.take() {
+                    self.add_comment(range.start, &comment);
+                }
+            }
         }
-        self.stmts[range.start].asm_code = new_code;
+        self.stmts[range.start.0].asm_code = new_code;
+        self.add_operand_variables(range.start.0);
         return Result::Ok(());
     }
 
-    pub fn replace_with_u8(&mut self, offset: usize) -> Result<u8, DisassembleError> {
-        let result = self.stmts[offset].asm_code.to_u8()?;
-        self.stmts[offset].asm_code = AsmCode::DataU8(result);
+    /// Keeps the variable map up to date as instructions are decoded, so writing
+    /// doesn't need a pass over every statement first.
+    fn add_operand_variables(&mut self, offset: usize) {
+        let AsmCode::Instruction(instr) = &self.stmts[offset].asm_code else {
+            return;
+        };
+        let mut generated = HashMap::new();
+        instr.to_write_string(&mut generated);
+        for (addr, variable) in generated {
+            self.operand_variables
+                .entry(addr)
+                .or_default()
+                .insert(offset, variable);
+        }
+    }
+
+    /// Drops the operands of the instruction at `offset` from the variable map,
+    /// before it is replaced.
+    fn remove_operand_variables(&mut self, offset: usize) {
+        let AsmCode::Instruction(instr) = &self.stmts[offset].asm_code else {
+            return;
+        };
+        let mut generated = HashMap::new();
+        instr.to_write_string(&mut generated);
+        for addr in generated.keys() {
+            if let Option::Some(users) = self.operand_variables.get_mut(addr) {
+                users.remove(&offset);
+                if users.is_empty() {
+                    self.operand_variables.remove(addr);
+                }
+            }
+        }
+    }
+
+    pub fn replace_with_u8(&mut self, offset: FileOffset) -> Result<u8, DisassembleError> {
+        let result = self.stmts[offset.0].asm_code.to_u8()?;
+        self.stmts[offset.0].asm_code = AsmCode::DataU8(result);
         return Result::Ok(result);
     }
 
-    pub fn replace_with_binary_u8(&mut self, offset: usize) -> Result<u8, DisassembleError> {
-        let result = self.stmts[offset].asm_code.to_u8()?;
-        self.stmts[offset].asm_code = AsmCode::DataBinaryU8(result);
+    pub fn replace_with_binary_u8(&mut self, offset: FileOffset) -> Result<u8, DisassembleError> {
+        let result = self.stmts[offset.0].asm_code.to_u8()?;
+        self.stmts[offset.0].asm_code = AsmCode::DataBinaryU8(result);
         return Result::Ok(result);
     }
 
-    pub fn replace_with_instr<F: FnMut(Vec<AsmCode>) -> Result<Instruction, DisassembleError>>(
+    pub fn replace_with_instr<F: FnOnce(Vec<AsmCode>) -> Result<Instruction, DisassembleError>>(
         &mut self,
-        offset: usize,
+        offset: FileOffset,
         args_len: usize,
-        mut instr_fn: F,
+        instr_fn: F,
     ) -> Result<usize, DisassembleError> {
         let mut args = Vec::new();
         for i in 0..args_len {
             args.push(self.take(offset + i + 1)?.asm_code);
         }
-        let instr = instr_fn(args)?;
+        let mut instr = instr_fn(args)?;
+        if let Option::Some(label) = instr.label_mut() {
+            if let Option::Some(renamed) = self.renamed_labels.get(label) {
+                *label = renamed.clone();
+            }
+        }
         self.replace(offset..offset + args_len + 1, AsmCode::Instruction(instr))?;
         return Result::Ok(args_len + 1);
     }
 
-    pub fn set_comment(&mut self, offset: usize, comment: &str) {
-        self.stmts[offset].comment = Option::Some(comment.to_string());
+    /// Writes the immediate load at `offset` as the low or, if `high`, the high
+    /// byte of `label`. Other instructions are left as they are.
+    pub fn set_label_byte(&mut self, offset: FileOffset, high: bool, label: &str) {
+        if let AsmCode::Instruction(instr) = &mut self.stmts[offset.0].asm_code {
+            if let Option::Some(with_label) = instr.with_label_byte(high, label) {
+                *instr = with_label;
+            }
+        }
+    }
+
+    /// Writes the target of the jump or branch at `offset` as `label`, which can be
+    /// any operand the assembler takes.
+    pub fn set_target_label(&mut self, offset: FileOffset, label: &str) {
+        if let AsmCode::Instruction(instr) = &mut self.stmts[offset.0].asm_code {
+            let jumps = instr.target(0).is_some();
+            if let Option::Some(target) = instr.label_mut().filter(|_| jumps) {
+                *target = label.to_string();
+            }
+        }
+    }
+
+    pub fn set_comment(&mut self, offset: FileOffset, comment: &str) {
+        self.stmts[offset.0].comment = Option::Some(self.strings.intern(comment));
+    }
+
+    /// Writes the statements in `dialect`, `segment_addrs` being the address of the
+    /// statement starting each segment.
+    pub fn set_dialect(
+        &mut self,
+        dialect: Box<dyn AsmDialect>,
+        segment_addrs: BTreeMap<FileOffset, u16>,
+    ) {
+        self.dialect = dialect;
+        self.segment_addrs = segment_addrs;
+    }
+
+    pub fn dialect(&self) -> &dyn AsmDialect {
+        return self.dialect.as_ref();
+    }
+
+    /// Writes a listing rather than assembly, each statement prefixed with its
+    /// address and the bytes it was decoded from, for reading next to a debugger.
+    pub fn set_listing(&mut self, listing: bool) {
+        self.listing = listing;
+    }
+
+    /// Writes the header, footer, segment banners and labels through `template`.
+    pub fn set_template(&mut self, template: Box<dyn OutputTemplate>) {
+        self.template = Option::Some(template);
+    }
+
+    /// Writes each segment as a scope named after it, lowercase, so the labels of
+    /// different banks can't collide and show which bank they are in where they
+    /// are used from others. Only for dialects that have scopes.
+    pub fn set_scoped(&mut self, scoped: bool) {
+        self.scoped = scoped;
+    }
+
+    fn scopes(&self) -> Option<Scopes<'_>> {
+        if !self.scoped || self.dialect.scope_end().is_none() {
+            return Option::None;
+        }
+        let mut starts = BTreeMap::new();
+        let mut labels = HashMap::new();
+        let mut scope = Option::None;
+        for (offset, c) in self.stmts.iter().enumerate() {
+            if let Option::Some(segment) = &c.segment {
+                starts.insert(offset, segment.to_lowercase());
+                scope = Option::Some(segment.to_lowercase());
+            }
+            if let (Option::Some(label), Option::Some(scope)) = (&c.label, &scope) {
+                labels.insert(label.as_ref(), scope.clone());
+            }
+        }
+        return Option::Some(Scopes { starts, labels });
+    }
+
+    /// Address of the statement at `offset`, counted from the start of its segment.
+    fn segment_addr(&self, offset: FileOffset) -> u16 {
+        return match self.segment_addrs.range(..=offset).next_back() {
+            Option::Some((start, addr)) => addr.wrapping_add((offset.0 - start.0) as u16),
+            Option::None => 0,
+        };
+    }
+
+    pub fn set_cpu(&mut self, cpu: &'static str) {
+        self.cpu = Option::Some(cpu);
+    }
+
+    pub fn set_charmap(&mut self, charmap: BTreeMap<u8, char>) {
+        self.charmap = charmap;
+    }
+
+    /// Whether the `.charmap` makes any char of `s` assemble to another byte.
+    pub fn charmap_changes(&self, s: &str) -> bool {
+        return self
+            .charmap
+            .iter()
+            .any(|(byte, c)| s.contains(*c) && *byte != *c as u8);
+    }
+
+    /// Writes only the statements at `offsets` from now on, of the ones written so
+    /// far.
+    pub fn set_only(&mut self, offsets: HashSet<usize>) {
+        self.only = Option::Some(match self.only.take() {
+            Option::Some(only) => only.intersection(&offsets).copied().collect(),
+            Option::None => offsets,
+        });
+    }
+
+    /// Whether [`Code::set_only`] leaves the statement at `offset` out.
+    pub fn is_left_out(&self, offset: usize) -> bool {
+        return self
+            .only
+            .as_ref()
+            .is_some_and(|only| !only.contains(&offset));
+    }
+
+    /// Adds a line to the comment at `offset`, see [`Code::set_comment`].
+    pub fn add_comment(&mut self, offset: FileOffset, comment: &str) {
+        let comment = match &self.stmts[offset.0].comment {
+            Option::Some(existing) => format!("{}\n{}", existing, comment),
+            Option::None => comment.to_string(),
+        };
+        self.stmts[offset.0].comment = Option::Some(self.strings.intern(&comment));
+    }
+
+    /// Exports the label at `offset` so other modules can link against it.
+    pub fn export(&mut self, offset: FileOffset) {
+        self.exports.insert(offset);
+    }
+
+    pub fn set_segment(&mut self, offset: FileOffset, segment: &str) {
+        self.stmts[offset.0].segment = Option::Some(self.strings.intern(segment));
+    }
+
+    pub fn set_label(&mut self, offset: FileOffset, label: &str) {
+        let label = self.renamed_labels.get(label).map_or(label, |l| l.as_str());
+        self.stmts[offset.0].label = Option::Some(self.strings.intern(label));
+    }
+
+    /// Records that the instruction at `from` jumps, branches or calls to `target`.
+    pub fn add_xref(&mut self, target: FileOffset, from: u16) {
+        self.xrefs.entry(target).or_default().insert(from);
+    }
+
+    /// addresses of the instructions jumping, branching or calling to each offset
+    pub fn xrefs(&self) -> &BTreeMap<FileOffset, BTreeSet<u16>> {
+        return &self.xrefs;
+    }
+
+    /// Writes the statements from `start` to `end`, inclusive, as a ca65 `.proc`
+    /// named after the label at `start`. Other labels in it become local to it.
+    pub fn set_proc(&mut self, start: FileOffset, end: FileOffset) {
+        self.procs.insert(start, end);
+    }
+
+    /// Writes the bytes read from `start` to the end of the statement at `end` as
+    /// `.verify` after the label at `start`, so the assembler checks the code still
+    /// assembles to them.
+    pub fn set_verify(&mut self, start: FileOffset, end: FileOffset) {
+        self.verified.insert(start, end);
+    }
+
+    /// The input bytes of the statements from `start` to `end`, inclusive, if they
+    /// are written in order and none are left out.
+    fn verify_bytes(&self, start: FileOffset, end: FileOffset, moved: &HashSet<usize>) -> &[u8] {
+        let len = self.stmts[end.0 + 1..]
+            .iter()
+            .take_while(|stmt| matches!(stmt.asm_code, AsmCode::Used))
+            .count()
+            + 1;
+        let range = start.0..end.0 + len;
+        let in_order = range.clone().all(|o| {
+            !moved.contains(&o)
+                && (matches!(self.stmts[o].asm_code, AsmCode::Used) || !self.is_left_out(o))
+                && (o >= end.0 || !self.moves.contains_key(&FileOffset(o)))
+        });
+        if !in_order {
+            return &[];
+        }
+        return &self.data[range];
+    }
+
+    /// number of bytes decoded as something other than plain bytes
+    pub fn classified(&self) -> usize {
+        return self
+            .stmts
+            .iter()
+            .filter(|c| !matches!(c.asm_code, AsmCode::DataHexU8(_)))
+            .count();
+    }
+
+    /// number of statements with a label
+    pub fn labeled(&self) -> usize {
+        return self.stmts.iter().filter(|c| c.label.is_some()).count();
+    }
+
+    /// number of statements with a comment
+    pub fn commented(&self) -> usize {
+        return self.stmts.iter().filter(|c| c.comment.is_some()).count();
+    }
+
+    pub fn label(&self, offset: FileOffset) -> Option<&str> {
+        return self.stmts[offset.0].label.as_deref();
+    }
+
+    /// Renames a label and every jump or branch to it. The rename sticks, so
+    /// code disassembled afterwards that generates the old name uses the new one.
+    pub fn rename_label(&mut self, from: &str, to: &str) {
+        for to_rename in self.renamed_labels.values_mut() {
+            if to_rename == from {
+                *to_rename = to.to_string();
+            }
+        }
+        self.renamed_labels.insert(from.to_string(), to.to_string());
+
+        let renamed = self.strings.intern(to);
+        for c in &mut self.stmts {
+            if c.label.as_deref() == Option::Some(from) {
+                c.label = Option::Some(renamed.clone());
+            }
+            if let AsmCode::Instruction(instr) = &mut c.asm_code {
+                if let Option::Some(label) = instr.label_mut() {
+                    if label == from {
+                        *label = to.to_string();
+                    }
+                }
+            }
+        }
+    }
+
+    /// Records `by` as what decoded the statement at `offset`.
+    pub fn claim(&mut self, offset: FileOffset, by: &str) {
+        self.claims.insert(offset, by.to_string());
+    }
+
+    /// First byte in `range` already decoded as something, if any.
+    pub fn first_claimed(&self, range: Range<FileOffset>) -> Option<FileOffset> {
+        let end = range.end.min(self.end());
+        return (range.start.0..end.0)
+            .map(FileOffset)
+            .find(|o| !matches!(self.stmts[o.0].asm_code, AsmCode::DataHexU8(_)));
+    }
+
+    /// Offset of the statement the byte at `offset` is part of.
+    pub fn statement_start(&self, offset: FileOffset) -> FileOffset {
+        let mut start = offset;
+        while start.0 > 0 && matches!(self.stmts[start.0].asm_code, AsmCode::Used) {
+            start = FileOffset(start.0 - 1);
+        }
+        return start;
+    }
+
+    /// First byte in `range` that is part of an instruction, if any.
+    pub fn first_instruction_byte(&self, range: Range<FileOffset>) -> Option<FileOffset> {
+        let end = range.end.min(self.end());
+        return (range.start.0..end.0)
+            .map(FileOffset)
+            .find(|o| self.is_instruction(self.statement_start(*o)));
+    }
+
+    /// Describes what the byte at `offset` was decoded as and by what.
+    pub fn claimant(&self, offset: FileOffset) -> String {
+        let start = self.statement_start(offset);
+        if let Option::Some(by) = self.claims.get(&start) {
+            return format!("{} (offset ${:04x})", by, start);
+        }
+        let what = match self.stmts[start.0].asm_code {
+            AsmCode::Instruction(_) => "an instruction",
+            AsmCode::DataHexU16(_) => "a vector",
+            _ => "data",
+        };
+        return format!("{} (offset ${:04x})", what, start);
+    }
+
+    /// Groups the bytes in `range`, none of them claimed by anything else, into rows
+    /// of `.word` data. `by` is what marked them, see [`Code::claim`].
+    pub fn mark_words(
+        &mut self,
+        range: std::ops::Range<FileOffset>,
+        by: &str,
+    ) -> Result<(), DisassembleError> {
+        let mut offset = range.start;
+        while offset + 1 < range.end {
+            let len = (range.end.0 - offset.0).min(WORDS_PER_LINE * 2) & !1;
+            let mut words = Vec::new();
+            for i in (0..len).step_by(2) {
+                let low = self.stmts[(offset + i).0].asm_code.to_u8()? as u16;
+                let high = self.stmts[(offset + i + 1).0].asm_code.to_u8()? as u16;
+                words.push(low | (high << 8));
+            }
+            self.replace(offset..offset + len, AsmCode::DataWords(words))?;
+            self.claim(offset, by);
+            offset += len;
+        }
+        return Result::Ok(());
     }
 
-    pub fn set_segment(&mut self, offset: usize, segment: &str) {
-        self.stmts[offset].segment = Option::Some(segment.to_string());
+    /// Groups the bytes in `range` that are not claimed by anything else into
+    /// rows of `.byte` data, which also stops code from being followed into them.
+    /// `by` is what marked them, see [`Code::claim`]. Returns the number of bytes
+    /// marked.
+    pub fn mark_data(
+        &mut self,
+        range: std::ops::Range<FileOffset>,
+        by: &str,
+    ) -> Result<usize, DisassembleError> {
+        let mut marked = 0;
+        let mut offset = range.start;
+        while offset < range.end {
+            let mut len = 0;
+            while len < 16
+                && offset + len < range.end
+                && matches!(self.stmts[(offset + len).0].asm_code, AsmCode::DataHexU8(_))
+            {
+                len += 1;
+            }
+            if len == 0 {
+                offset += 1;
+                continue;
+            }
+
+            let mut bytes = Vec::new();
+            for i in 0..len {
+                bytes.push(self.stmts[(offset + i).0].asm_code.to_u8()?);
+            }
+            self.replace(
+                offset..offset + len,
+                AsmCode::DataSeq(bytes.into_iter().map(AsmCode::DataHexU8).collect()),
+            )?;
+            self.claim(offset, by);
+            marked += len;
+            offset += len;
+        }
+        return Result::Ok(marked);
     }
 
-    pub fn set_label(&mut self, offset: usize, label: &str) {
-        self.stmts[offset].label = Option::Some(label.to_string());
+    /// Writes the statements in `range` right after the one at `after`, see
+    /// [`Code::write`]. Only the output changes, the statements keep their offsets.
+    pub fn move_after(&mut self, range: Range<FileOffset>, after: FileOffset) {
+        self.moves.insert(after, range);
     }
 
-    pub fn write(&self, mut out: Box<dyn Write>) -> Result<(), DisassembleError> {
-        let mut addr_to_variable = self.addr_to_variable.clone();
+    /// Whether [`Code::move_after`] writes the statement at `offset` elsewhere.
+    pub fn is_moved(&self, offset: usize) -> bool {
+        return self
+            .moves
+            .values()
+            .any(|range| (range.start.0..range.end.0).contains(&offset));
+    }
 
-        for c in &self.stmts {
-            c.asm_code.to_write_string(&mut addr_to_variable);
+    pub fn write(&self, out: &mut dyn Write) -> Result<(), DisassembleError> {
+        self.write_with_lines(out)?;
+        return Result::Ok(());
+    }
+
+    /// Same as [`Code::write`], also returning the 1-based output line the code of
+    /// each statement ends up on.
+    pub fn write_with_lines(
+        &self,
+        out: &mut dyn Write,
+    ) -> Result<Vec<(usize, FileOffset)>, DisassembleError> {
+        let (lines, _) = self.write_files(out, Option::None)?;
+        return Result::Ok(lines);
+    }
+
+    /// Same as [`Code::write`], writing the segments and constants `split` picks
+    /// to files of their own, each included from `out`. Returns those files by
+    /// name, in the order they are included.
+    pub fn write_split(
+        &self,
+        out: &mut dyn Write,
+        split: &SourceSplit,
+    ) -> Result<SplitFiles, DisassembleError> {
+        let (_, files) = self.write_files(out, Option::Some(split))?;
+        return Result::Ok(files);
+    }
+
+    /// The output lines of [`Code::write_with_lines`] and the files of
+    /// [`Code::write_split`], the lines only meaningful without a `split`.
+    fn write_files(
+        &self,
+        out: &mut dyn Write,
+        split: Option<&SourceSplit>,
+    ) -> Result<(Vec<(usize, FileOffset)>, SplitFiles), DisassembleError> {
+        let include = |file: &str| self.dialect.include(file);
+        let mut out = SplitWriter {
+            main: LineCountingWriter { out, lines: 0 },
+            include: &include,
+            files: Vec::new(),
+            current: Option::None,
+        };
+        let mut addr_to_variable = self.collect_variables();
+
+        if let Option::Some(header) = self.templated(|t| t.header())? {
+            self.write_line(&mut out, Option::None, &header)?;
+        }
+        if let Option::Some(cpu) = self.dialect.cpu(self.cpu.unwrap_or("6502")) {
+            self.write_line(&mut out, Option::None, &cpu)?;
+        }
+        for (byte, c) in &self.charmap {
+            if let Option::Some(charmap) = self.dialect.charmap(*c, *byte) {
+                self.write_line(&mut out, Option::None, &charmap)?;
+            }
         }
 
         for v_addr in addr_to_variable.keys().sorted() {
             if let Option::Some(v) = addr_to_variable.get(v_addr) {
-                writeln!(out, ".define {:<25} = {}", v.name, v.value)?;
+                if let VariableValue::Label(_) | VariableValue::Unnamed(_) = v.value {
+                    continue;
+                }
+                let register = split.filter(|split| split.registers.contains(v_addr));
+                out.switch(register.map(|split| &split.registers_file))?;
+                let constant = self.dialect.constant(&v.name, &v.value.to_string());
+                self.write_line(&mut out, Option::None, &constant)?;
             }
         }
+        out.switch(Option::None)?;
 
-        for c in &self.stmts {
+        // labels in scopes are exported from inside them
+        let scopes = self.scopes();
+        if scopes.is_none() {
+            self.write_exports(&mut out, 0..self.stmts.len())?;
+        }
+
+        let moved = self
+            .moves
+            .values()
+            .flat_map(|r| r.start.0..r.end.0)
+            .collect::<HashSet<usize>>();
+        let mut lines = Vec::new();
+        // the segment of statements left out, written before the next one that isn't
+        let mut segment = Option::None;
+        // last statement of the `.proc` being written
+        let mut proc_end = Option::None;
+        let mut in_scope = false;
+        for (offset, c) in self.stmts.iter().enumerate() {
             if let AsmCode::Used = c.asm_code {
                 continue;
             }
-            if let Option::Some(segment) = &c.segment {
-                writeln!(
-                    out,
-                    "\n; -------------------------- {} -----------------------\n.segment \"{}\"",
-                    segment, segment
-                )?;
+            if moved.contains(&offset) {
+                continue;
+            }
+            if self.is_left_out(offset) {
+                segment = c.segment.clone().or(segment);
+                if proc_end == Option::Some(FileOffset(offset)) {
+                    self.write_proc_end(&mut out)?;
+                    proc_end = Option::None;
+                }
+                continue;
+            }
+            let pending = segment.take().filter(|_| c.segment.is_none());
+            if in_scope && (pending.is_some() || c.segment.is_some()) {
+                self.write_scope_end(&mut out)?;
+                in_scope = false;
+            }
+            if let (Option::Some(split), Option::Some(segment)) =
+                (split, pending.as_deref().or(c.segment.as_deref()))
+            {
+                out.switch(split.segment_files.get(segment))?;
             }
-            if let Option::Some(label) = &c.label {
-                writeln!(out, "{}:", label)?;
+            if let Option::Some(segment) = pending {
+                let addr = self.segment_addr(FileOffset(offset));
+                let segment = self.dialect.segment(&segment, addr);
+                self.write_line(&mut out, Option::None, &format!("\n{}", segment))?;
+                in_scope = self.write_scope_start(&mut out, scopes.as_ref(), offset)?;
             }
-            let asm = c.asm_code.to_write_string(&mut addr_to_variable);
-            writeln!(out, "{}", Code::with_comment(asm, &c.comment))?;
+            let proc = self.procs.get(&FileOffset(offset));
+            let verify = match self.verified.get(&FileOffset(offset)) {
+                Option::Some(end) => self.verify_bytes(FileOffset(offset), *end, &moved),
+                Option::None => &[],
+            };
+            let proc_start = proc.is_some();
+            self.write_statement(
+                &mut out,
+                offset,
+                proc_start,
+                verify,
+                scopes.as_ref(),
+                &mut addr_to_variable,
+            )?;
+            in_scope |= c.segment.is_some() && scopes.is_some();
+            lines.push((out.main.lines, FileOffset(offset)));
+            proc_end = proc.copied().or(proc_end);
+            if proc_end == Option::Some(FileOffset(offset)) {
+                self.write_proc_end(&mut out)?;
+                proc_end = Option::None;
+            }
+            if let Option::Some(range) = self.moves.get(&FileOffset(offset)) {
+                for offset in range.start.0..range.end.0 {
+                    if self.is_left_out(offset) {
+                        continue;
+                    }
+                    let (out, scopes) = (&mut out, scopes.as_ref());
+                    self.write_statement(out, offset, false, &[], scopes, &mut addr_to_variable)?;
+                    if let AsmCode::Used = self.stmts[offset].asm_code {
+                        continue;
+                    }
+                    lines.push((out.main.lines, FileOffset(offset)));
+                }
+            }
+        }
+        if in_scope {
+            self.write_scope_end(&mut out)?;
         }
+        if let Option::Some(footer) = self.templated(|t| t.footer())? {
+            out.switch(Option::None)?;
+            self.write_line(&mut out, Option::None, &footer)?;
+        }
+        return Result::Ok((lines, out.files));
+    }
+
+    /// What the `--template` writes for a part of the output, `None` without one
+    /// or if it leaves that part as it is.
+    fn templated(
+        &self,
+        part: impl FnOnce(&dyn OutputTemplate) -> Result<Option<String>, DisassembleError>,
+    ) -> Result<Option<String>, DisassembleError> {
+        return match &self.template {
+            Option::Some(template) => part(template.as_ref()),
+            Option::None => Result::Ok(Option::None),
+        };
+    }
+
+    /// Exports the labels of the statements in `range` that aren't left out.
+    fn write_exports(
+        &self,
+        out: &mut dyn Write,
+        range: Range<usize>,
+    ) -> Result<(), DisassembleError> {
+        for offset in self
+            .exports
+            .range(FileOffset(range.start)..FileOffset(range.end))
+        {
+            if self.is_left_out(offset.0) {
+                continue;
+            }
+            let label = self.stmts[offset.0].label.as_deref();
+            if let Option::Some(export) = label.and_then(|label| self.dialect.export(label)) {
+                self.write_line(out, Option::None, &export)?;
+            }
+        }
+        return Result::Ok(());
+    }
+
+    /// Starts the scope of the statement at `offset` with the exports of its
+    /// labels, returning whether there was one to start.
+    fn write_scope_start(
+        &self,
+        out: &mut dyn Write,
+        scopes: Option<&Scopes>,
+        offset: usize,
+    ) -> Result<bool, DisassembleError> {
+        let (range, scope) = match scopes.and_then(|scopes| scopes.range(offset)) {
+            Option::Some(range) => range,
+            Option::None => return Result::Ok(false),
+        };
+        let start = match self.dialect.scope_start(scope) {
+            Option::Some(start) => start,
+            Option::None => return Result::Ok(false),
+        };
+        self.write_line(out, Option::None, &start)?;
+        self.write_exports(out, range)?;
+        return Result::Ok(true);
+    }
+
+    fn write_scope_end(&self, out: &mut dyn Write) -> Result<(), DisassembleError> {
+        if let Option::Some(end) = self.dialect.scope_end() {
+            self.write_line(out, Option::None, &end)?;
+        }
+        return Result::Ok(());
+    }
+
+    /// Writes up to `count` statements starting at `start` the way [`Code::write`]
+    /// does, returning the offset just past the last one written.
+    pub fn write_range(
+        &self,
+        out: &mut dyn Write,
+        start: FileOffset,
+        count: usize,
+    ) -> Result<FileOffset, DisassembleError> {
+        let mut addr_to_variable = self.collect_variables();
+        let mut offset = start;
+        let mut written = 0;
+        while offset < self.end() && written < count {
+            let c = &self.stmts[offset.0];
+            if let AsmCode::Used = c.asm_code {
+                offset += 1;
+                continue;
+            }
+            self.write_statement(
+                out,
+                offset.0,
+                false,
+                &[],
+                Option::None,
+                &mut addr_to_variable,
+            )?;
+            written += 1;
+            offset += 1;
+        }
+        return Result::Ok(offset);
+    }
+
+    /// Labels on the bytes after the statement at `offset` that are part of it, with
+    /// how far into the statement each is.
+    fn inner_labels(&self, offset: usize) -> Vec<(usize, &str)> {
+        return self.stmts[offset + 1..]
+            .iter()
+            .take_while(|c| matches!(c.asm_code, AsmCode::Used))
+            .enumerate()
+            .filter_map(|(i, c)| c.label.as_deref().map(|label| (i + 1, label)))
+            .collect();
+    }
+
+    /// Writes the statement at `offset`, its label as the start of a routine if
+    /// `proc`, the labels of bytes inside it, followed by the bytes in `verify`.
+    /// A segment it starts is started as a scope when given `scopes`.
+    fn write_statement(
+        &self,
+        out: &mut dyn Write,
+        offset: usize,
+        proc: bool,
+        verify: &[u8],
+        scopes: Option<&Scopes>,
+        addr_to_variable: &mut HashMap<u16, Variable>,
+    ) -> Result<(), DisassembleError> {
+        let c = &self.stmts[offset];
+        if let AsmCode::Used = c.asm_code {
+            return Result::Ok(());
+        }
+        let dialect = self.dialect.as_ref();
+        let addr = self.segment_addr(FileOffset(offset));
+        if let Option::Some(segment) = &c.segment {
+            let directive = dialect.segment(segment, addr);
+            let start = format!(
+                "\n{} -------------------------- {} -----------------------\n{}",
+                dialect.comment(),
+                segment,
+                directive
+            );
+            let start = self
+                .templated(|t| t.segment(segment, addr, &directive, &start))?
+                .unwrap_or(start);
+            self.write_line(out, Option::None, &start)?;
+            self.write_scope_start(out, scopes, offset)?;
+        }
+        if let Option::Some(label) = &c.label {
+            let line = match proc {
+                true => dialect.proc_start(label),
+                false => dialect.label(label),
+            };
+            let line = self
+                .templated(|t| t.label(label, addr, &line))?
+                .unwrap_or(line);
+            self.write_line(out, Option::None, &line)?;
+        }
+        for (n, label) in self.inner_labels(offset) {
+            self.write_line(out, Option::None, &dialect.label_at(label, n))?;
+        }
+        for bytes in verify.chunks(VERIFY_BYTES_PER_LINE) {
+            if let Option::Some(verify) = dialect.verify(bytes) {
+                self.write_line(out, Option::None, &verify)?;
+            }
+        }
+        let mut asm = c.asm_code.to_write_string(dialect, addr_to_variable);
+        if let AsmCode::Instruction(instr) = &c.asm_code {
+            if let Option::Some(scopes) = scopes {
+                asm = scopes.qualify(dialect, offset, &asm);
+            }
+            let illegal = matches!(instr, Instruction::ILLEGAL(_) | Instruction::JAM(0x02));
+            if !dialect.assembles(&asm, illegal) {
+                let bytes = self
+                    .statement_bytes(offset)
+                    .iter()
+                    .map(|b| dialect.hex_u8(*b))
+                    .join(", ");
+                let comment = dialect.comment();
+                asm = format!(
+                    "    {} {} {} {}",
+                    dialect.bytes(),
+                    bytes,
+                    comment,
+                    asm.trim()
+                );
+            }
+        }
+        self.write_line(
+            out,
+            Option::Some(offset),
+            &self.with_comment(asm, &c.comment),
+        )?;
         return Result::Ok(());
     }
 
-    fn with_comment(first: String, comment: &Option<String>) -> String {
+    /// The input bytes of the statement at `offset`, fewer at the end of a padded
+    /// input.
+    fn statement_bytes(&self, offset: usize) -> &[u8] {
+        let len = self.stmts[offset + 1..]
+            .iter()
+            .take_while(|c| matches!(c.asm_code, AsmCode::Used))
+            .count();
+        let end = (offset + 1 + len).min(self.data.len());
+        return &self.data[offset.min(end)..end];
+    }
+
+    /// Writes the lines of `text`, the last being the code of the statement at
+    /// `offset` if given. In a listing that one is prefixed with the statement's
+    /// address and bytes and the others are indented past those columns.
+    fn write_line(
+        &self,
+        out: &mut dyn Write,
+        offset: Option<usize>,
+        text: &str,
+    ) -> Result<(), DisassembleError> {
+        if !self.listing {
+            writeln!(out, "{}", text)?;
+            return Result::Ok(());
+        }
+        let lines = text.split('\n').collect::<Vec<&str>>();
+        for (i, line) in lines.iter().enumerate() {
+            let columns = match offset {
+                Option::Some(offset) if i == lines.len() - 1 => {
+                    let bytes = self.statement_bytes(offset);
+                    let mut shown = bytes
+                        .iter()
+                        .take(LISTING_BYTES)
+                        .map(|b| format!("{:02X}", b))
+                        .join(" ");
+                    if bytes.len() > LISTING_BYTES {
+                        shown += "+";
+                    }
+                    let addr = self.segment_addr(FileOffset(offset));
+                    format!("{:04X}: {:<width$}", addr, shown, width = LISTING_WIDTH - 6)
+                }
+                _ if line.is_empty() => String::new(),
+                _ => " ".repeat(LISTING_WIDTH),
+            };
+            writeln!(out, "{}{}", columns, line)?;
+        }
+        return Result::Ok(());
+    }
+
+    /// Ends the routine being written, for dialects that have an end to them.
+    fn write_proc_end(&self, out: &mut dyn Write) -> Result<(), DisassembleError> {
+        if let Option::Some(end) = self.dialect.proc_end() {
+            self.write_line(out, Option::None, &end)?;
+        }
+        return Result::Ok(());
+    }
+
+    /// Statements as they are written by [`Code::write`], keyed by file offset.
+    pub fn to_json_statements(&self) -> Vec<JsonStatement> {
+        return self.to_json_statements_range(FileOffset(0), usize::MAX);
+    }
+
+    /// Up to `count` statements starting at `start`, see [`Code::to_json_statements`].
+    pub fn to_json_statements_range(&self, start: FileOffset, count: usize) -> Vec<JsonStatement> {
+        let mut addr_to_variable = self.collect_variables();
+        let mut result = Vec::new();
+        let mut segment = self.stmts[..start.0.min(self.stmts.len())]
+            .iter()
+            .rev()
+            .find_map(|c| c.segment.as_deref());
+        for (offset, c) in self.stmts.iter().enumerate().skip(start.0) {
+            if result.len() >= count {
+                break;
+            }
+            segment = c.segment.as_deref().or(segment);
+            if let AsmCode::Used = c.asm_code {
+                continue;
+            }
+            if self.is_left_out(offset) {
+                continue;
+            }
+            let asm = c
+                .asm_code
+                .to_write_string(self.dialect.as_ref(), &mut addr_to_variable);
+            let asm = asm.trim().to_string();
+            let (mnemonic, operands) = match &c.asm_code {
+                AsmCode::Instruction(_) => match asm.split_once(' ') {
+                    Option::Some((mnemonic, operands)) => (
+                        Option::Some(mnemonic.to_string()),
+                        Option::Some(operands.to_string()),
+                    ),
+                    Option::None => (Option::Some(asm.clone()), Option::None),
+                },
+                _ => (Option::None, Option::None),
+            };
+            result.push(JsonStatement {
+                offset,
+                addr: self.segment_addr(FileOffset(offset)),
+                bytes: self
+                    .statement_bytes(offset)
+                    .iter()
+                    .map(|b| format!("{:02X}", b))
+                    .join(" "),
+                segment: segment.map(str::to_string),
+                label: c.label.as_deref().map(str::to_string),
+                asm,
+                mnemonic,
+                operands,
+                comment: c.comment.as_deref().map(str::to_string),
+                reached_from: Vec::new(),
+            });
+        }
+        return result;
+    }
+
+    pub fn statements(&self) -> &[Statement] {
+        return &self.stmts;
+    }
+
+    /// Known variables plus the ones created for addresses referenced by the code.
+    pub fn collect_variables(&self) -> HashMap<u16, Variable> {
+        let mut addr_to_variable = self
+            .operand_variables
+            .iter()
+            .filter_map(|(addr, users)| Option::Some((*addr, users.values().next()?.clone())))
+            .collect::<HashMap<u16, Variable>>();
+        addr_to_variable.extend(self.addr_to_variable.clone());
+        for addr in self.unnamed.iter().flat_map(|r| r.clone()) {
+            addr_to_variable.insert(
+                addr,
+                Variable {
+                    name: format!("${:04x}", addr),
+                    value: VariableValue::Unnamed(addr),
+                },
+            );
+        }
+        return addr_to_variable;
+    }
+
+    fn with_comment(&self, first: String, comment: &Option<Rc<str>>) -> String {
+        let c = self.dialect.comment();
         if let Option::Some(comment) = comment {
             if comment.contains("\n") {
-                return format!("\n; {}\n{:<25}", comment.replace("\n", "\n; "), first);
+                let comment = comment.replace("\n", &format!("\n{} ", c));
+                return format!("\n{} {}\n{:<25}", c, comment, first);
             } else {
-                return format!("{:<25} ; {}", first, comment);
+                return format!("{:<25} {} {}", first, c, comment);
             }
         } else {
             return first;
         }
     }
 
-    pub fn is_instruction(&self, offset: usize) -> bool {
-        if let AsmCode::Instruction(_) = self.stmts[offset].asm_code {
+    pub fn is_instruction(&self, offset: FileOffset) -> bool {
+        if let AsmCode::Instruction(_) = self.stmts[offset.0].asm_code {
             return true;
         }
         return false;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disassemble::dialect::{Acme, Asm6, Dasm};
+
+    fn written(code: &Code) -> String {
+        let mut out = Vec::new();
+        code.write(&mut out).unwrap();
+        return String::from_utf8(out).unwrap();
+    }
+
+    #[test]
+    fn test_set_keeps_metadata() {
+        let mut code = Code::new(vec![0x4c, 0x00, 0x80, 0x01]);
+        code.set_label(FileOffset(3), "table");
+        code.set_comment(FileOffset(3), "read by the reset routine");
+        code.set_segment(FileOffset(3), "CODE");
+        let byte = code.take(FileOffset(3)).unwrap();
+        code.set(
+            FileOffset(3),
+            Statement {
+                asm_code: AsmCode::DataSeq(vec![byte.asm_code]),
+                comment: Option::Some(Rc::from("one entry")),
+                segment: Option::None,
+                label: Option::None,
+            },
+        )
+        .unwrap();
+        assert_eq!(code.label(FileOffset(3)), Option::Some("table"));
+        assert_eq!(
+            code.statements()[3].comment.as_deref(),
+            Option::Some("read by the reset routine\none entry")
+        );
+        assert_eq!(
+            code.statements()[3].segment.as_deref(),
+            Option::Some("CODE")
+        );
+
+        // a new label wins, what jumps to the old one follows it
+        code.replace_with_instr(FileOffset(0), 2, |_| {
+            return Result::Ok(Instruction::JMP_ABS(0x8003, "table".to_string()));
+        })
+        .unwrap();
+        code.set(
+            FileOffset(3),
+            Statement {
+                asm_code: AsmCode::DataHexU8(0x01),
+                comment: Option::None,
+                segment: Option::None,
+                label: Option::Some(Rc::from("levels")),
+            },
+        )
+        .unwrap();
+        assert_eq!(code.label(FileOffset(3)), Option::Some("levels"));
+        assert!(written(&code).contains("jmp levels"));
+    }
+
+    #[test]
+    fn test_replace_keeps_inner_labels() {
+        let mut code = Code::new(vec![0x01, 0x02, 0x03, 0x04]);
+        code.set_label(FileOffset(0), "table");
+        code.set_label(FileOffset(2), "table_middle");
+        code.set_comment(FileOffset(2), "second half");
+        code.mark_data(FileOffset(0)..FileOffset(4), "a test")
+            .unwrap();
+        assert_eq!(
+            written(&code),
+            "table:\ntable_middle := * + 2\n.byte $01, $02, $03, $04  ; second half\n"
+        );
+    }
+    /// The variables as writing every statement in order names them.
+    fn rendered_variables(code: &Code) -> HashMap<u16, String> {
+        let mut addr_to_variable = HashMap::new();
+        for stmt in code.statements() {
+            if let AsmCode::Instruction(instr) = &stmt.asm_code {
+                instr.to_write_string(&mut addr_to_variable);
+            }
+        }
+        return addr_to_variable
+            .into_iter()
+            .map(|(addr, variable)| (addr, variable.name))
+            .collect();
+    }
+
+    fn collected_variables(code: &Code) -> HashMap<u16, String> {
+        return code
+            .collect_variables()
+            .into_iter()
+            .map(|(addr, variable)| (addr, variable.name))
+            .collect();
+    }
+
+    #[test]
+    fn test_variables_follow_replaced_instructions() {
+        // lda $10 / lda $0010 / sta $2000 / jmp $8000
+        let mut code = Code::new(vec![
+            0xa5, 0x10, 0xad, 0x10, 0x00, 0x8d, 0x00, 0x20, 0x4c, 0x00, 0x80,
+        ]);
+        for (offset, instr) in [
+            (0, Instruction::LDA_ZP(0x10)),
+            (2, Instruction::LDA_ABS(0x0010)),
+            (5, Instruction::STA_ABS(0x2000)),
+        ] {
+            let args_len = if offset == 0 { 1 } else { 2 };
+            code.replace_with_instr(FileOffset(offset), args_len, |_| {
+                return Result::Ok(instr);
+            })
+            .unwrap();
+        }
+        code.replace_with_instr(FileOffset(8), 2, |_| {
+            return Result::Ok(Instruction::JMP_ABS(0x8000, "start".to_string()));
+        })
+        .unwrap();
+        assert_eq!(collected_variables(&code), rendered_variables(&code));
+        assert_eq!(code.operand_name(0x10), Option::Some("ZP_10"));
+
+        // relabeled, the operands stay
+        code.set_label(FileOffset(0), "start");
+        code.rename_label("start", "reset");
+        let stmt = code.take(FileOffset(5)).unwrap();
+        code.set(
+            FileOffset(5),
+            Statement {
+                label: Option::Some(Rc::from("store")),
+                ..stmt
+            },
+        )
+        .unwrap();
+        assert_eq!(collected_variables(&code), rendered_variables(&code));
+
+        // the first user turned into data, the next one names the address
+        let byte = code.take(FileOffset(0)).unwrap();
+        assert!(matches!(byte.asm_code, AsmCode::Instruction(_)));
+        code.set(
+            FileOffset(0),
+            Statement {
+                asm_code: AsmCode::DataSeq(vec![AsmCode::DataHexU8(0xa5)]),
+                comment: Option::None,
+                segment: Option::None,
+                label: Option::None,
+            },
+        )
+        .unwrap();
+        assert_eq!(collected_variables(&code), rendered_variables(&code));
+        assert_eq!(code.operand_name(0x10), Option::Some("ABS_0010"));
+
+        // and with no user left there is no variable
+        code.replace(
+            FileOffset(5)..FileOffset(8),
+            AsmCode::DataSeq(vec![AsmCode::DataHexU8(0x8d)]),
+        )
+        .unwrap();
+        assert_eq!(collected_variables(&code), rendered_variables(&code));
+        assert!(!code.collect_variables().contains_key(&0x2000));
+    }
+
+    #[test]
+    fn test_write_asm6() {
+        let mut code = Code::new(vec![0xad, 0x10, 0x00, 0xd0, 0xfb, 0x01, 0x02]);
+        code.replace_with_instr(FileOffset(0), 2, |_| {
+            return Result::Ok(Instruction::LDA_ABS(0x0010));
+        })
+        .unwrap();
+        code.replace_with_instr(FileOffset(3), 1, |_| {
+            return Result::Ok(Instruction::BNE_REL(-5, "*-3".to_string()));
+        })
+        .unwrap();
+        code.set_segment(FileOffset(0), "PRGROM0");
+        code.set_label(FileOffset(5), "table");
+        code.set_label(FileOffset(6), "table_end");
+        code.mark_data(FileOffset(5)..FileOffset(7), "a test")
+            .unwrap();
+        code.set_dialect(Box::new(Asm6), BTreeMap::from([(FileOffset(0), 0x8000)]));
+        assert_eq!(
+            written(&code),
+            "ABS_0010                  = $0010\n\
+             \n; -------------------------- PRGROM0 -----------------------\n.base $8000\n\
+             \x20   .db $AD, $10, $00 ; lda a:ABS_0010\n    bne $-3\n\
+             table:\ntable_end = $ + 1\n.db $01, $02\n"
+        );
+    }
+
+    /// The code of [`test_write_asm6`] with a fill after the table.
+    fn dialect_test_code() -> Code {
+        let mut code = Code::new(vec![
+            0xad, 0x10, 0x00, 0xd0, 0xfb, 0x01, 0x02, 0xff, 0xff, 0xff,
+        ]);
+        code.replace_with_instr(FileOffset(0), 2, |_| {
+            return Result::Ok(Instruction::LDA_ABS(0x0010));
+        })
+        .unwrap();
+        code.replace_with_instr(FileOffset(3), 1, |_| {
+            return Result::Ok(Instruction::BNE_REL(-5, "*-3".to_string()));
+        })
+        .unwrap();
+        code.set_segment(FileOffset(0), "CODE");
+        code.set_label(FileOffset(5), "table");
+        code.set_label(FileOffset(6), "table_end");
+        code.mark_data(FileOffset(5)..FileOffset(7), "a test")
+            .unwrap();
+        code.collapse_fill(FileOffset(7), 3, 0xff);
+        return code;
+    }
+
+    #[test]
+    fn test_write_dasm() {
+        let mut code = dialect_test_code();
+        code.set_dialect(Box::new(Dasm), BTreeMap::from([(FileOffset(0), 0xf000)]));
+        assert_eq!(
+            written(&code),
+            "    processor 6502\n\
+             ABS_0010                  = $0010\n\
+             \n; -------------------------- CODE -----------------------\n\
+             \x20   SEG CODE\n    ORG $F000\n\
+             \x20   lda.w ABS_0010\n    bne .-3\n\
+             table\ntable_end = . + 1\n    .byte $01, $02\n    ds.b 3, $FF\n"
+        );
+    }
+
+    #[test]
+    fn test_write_acme() {
+        let mut code = dialect_test_code();
+        code.set_dialect(Box::new(Acme), BTreeMap::from([(FileOffset(0), 0x0801)]));
+        assert_eq!(
+            written(&code),
+            "ABS_0010                  = $0010\n\
+             \n; -------------------------- CODE -----------------------\n* = $0801\n\
+             \x20   lda+2 ABS_0010\n    bne *-3\n\
+             table\ntable_end = * + 1\n!byte $01, $02\n!fill 3, $FF\n"
+        );
+    }
+
+    #[test]
+    fn test_write_listing() {
+        let mut code = Code::new(vec![0xa9, 0x00, 0xd0, 0xfc, 0x01, 0x02, 0x03, 0x04]);
+        code.replace_with_instr(FileOffset(0), 1, |_| {
+            return Result::Ok(Instruction::LDA_IMM(0x00));
+        })
+        .unwrap();
+        code.replace_with_instr(FileOffset(2), 1, |_| {
+            return Result::Ok(Instruction::BNE_REL(-4, "reset".to_string()));
+        })
+        .unwrap();
+        code.set_segment(FileOffset(0), "PRGROM0");
+        code.set_label(FileOffset(0), "reset");
+        code.set_comment(FileOffset(2), "wait");
+        code.mark_data(FileOffset(4)..FileOffset(8), "a test")
+            .unwrap();
+        code.set_dialect(Box::new(Ca65), BTreeMap::from([(FileOffset(0), 0x8000)]));
+        code.set_listing(true);
+        assert_eq!(
+            written(&code),
+            "\n                ; -------------------------- PRGROM0 -----------------------\n\
+             \x20               .segment \"PRGROM0\"\n\
+             \x20               reset:\n\
+             8000: A9 00         lda #$00\n\
+             8002: D0 FC         bne reset             ; wait\n\
+             8004: 01 02 03+ .byte $01, $02, $03, $04\n"
+        );
+    }
+
+    #[test]
+    fn test_json_statements() {
+        let mut code = Code::new(vec![0xa9, 0x00, 0x60, 0x01, 0x02]);
+        code.replace_with_instr(FileOffset(0), 1, |_| {
+            return Result::Ok(Instruction::LDA_IMM(0x00));
+        })
+        .unwrap();
+        code.replace_with_instr(FileOffset(2), 0, |_| {
+            return Result::Ok(Instruction::RTS);
+        })
+        .unwrap();
+        code.set_segment(FileOffset(0), "PRGROM0");
+        code.set_label(FileOffset(0), "reset");
+        code.mark_data(FileOffset(3)..FileOffset(5), "a test")
+            .unwrap();
+        code.set_dialect(Box::new(Ca65), BTreeMap::from([(FileOffset(0), 0x8000)]));
+        let json = serde_json::to_value(code.to_json_statements()).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!([
+                {
+                    "offset": 0,
+                    "addr": 0x8000,
+                    "bytes": "A9 00",
+                    "segment": "PRGROM0",
+                    "label": "reset",
+                    "asm": "lda #$00",
+                    "mnemonic": "lda",
+                    "operands": "#$00"
+                },
+                {
+                    "offset": 2,
+                    "addr": 0x8002,
+                    "bytes": "60",
+                    "segment": "PRGROM0",
+                    "asm": "rts",
+                    "mnemonic": "rts"
+                },
+                {
+                    "offset": 3,
+                    "addr": 0x8003,
+                    "bytes": "01 02",
+                    "segment": "PRGROM0",
+                    "asm": ".byte $01, $02"
+                }
+            ])
+        );
+    }
+
+    #[test]
+    fn test_write_scoped() {
+        let mut code = Code::new(vec![0x4c, 0x03, 0x80, 0x4c, 0x00, 0x80]);
+        code.replace_with_instr(FileOffset(0), 2, |_| {
+            return Result::Ok(Instruction::JMP_ABS(0x8003, "prgrom1_start".to_string()));
+        })
+        .unwrap();
+        code.replace_with_instr(FileOffset(3), 2, |_| {
+            return Result::Ok(Instruction::JMP_ABS(0x8000, "prgrom1_start".to_string()));
+        })
+        .unwrap();
+        code.set_segment(FileOffset(0), "PRGROM0");
+        code.set_segment(FileOffset(3), "PRGROM1");
+        code.set_label(FileOffset(3), "prgrom1_start");
+        code.export(FileOffset(3));
+        code.set_scoped(true);
+        assert_eq!(
+            written(&code),
+            "\n; -------------------------- PRGROM0 -----------------------\n\
+             .segment \"PRGROM0\"\n.scope prgrom0\n    jmp prgrom1::prgrom1_start\n\
+             .endscope\n\
+             \n; -------------------------- PRGROM1 -----------------------\n\
+             .segment \"PRGROM1\"\n.scope prgrom1\n.export prgrom1_start\n\
+             prgrom1_start:\n    jmp prgrom1_start\n.endscope\n"
+        );
+    }
+
+    #[test]
+    fn test_write_split() {
+        let mut code = Code::new(vec![0x8d, 0x00, 0x20, 0x4c, 0x00, 0x80]);
+        code.replace_with_instr(FileOffset(3), 2, |_| {
+            return Result::Ok(Instruction::JMP_ABS(0x8000, "prgrom0_start".to_string()));
+        })
+        .unwrap();
+        code.set_segment(FileOffset(0), "HEADER");
+        code.set_segment(FileOffset(3), "PRGROM0");
+        code.set_label(FileOffset(3), "prgrom0_start");
+        for (addr, name, value) in [
+            (0x10, "counter", VariableValue::U8(0x10)),
+            (0x2000, "PPU_CTRL", VariableValue::U16(0x2000)),
+        ] {
+            code.set_variable(
+                addr,
+                Variable {
+                    name: name.to_string(),
+                    value,
+                },
+            );
+        }
+        let split = SourceSplit {
+            segment_files: HashMap::from([("PRGROM0".to_string(), "prgrom0.s".to_string())]),
+            registers_file: "registers.inc".to_string(),
+            registers: 0x2000..0x4020,
+        };
+        let mut out = Vec::new();
+        let files = code.write_split(&mut out, &split).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "counter                   = $10\n.include \"registers.inc\"\n\
+             \n; -------------------------- HEADER -----------------------\n\
+             .segment \"HEADER\"\n.byte $8D\n.byte $00\n.byte $20\n\
+             .include \"prgrom0.s\"\n"
+        );
+        let files = files
+            .into_iter()
+            .map(|(name, data)| (name, String::from_utf8(data).unwrap()))
+            .collect::<Vec<(String, String)>>();
+        assert_eq!(
+            files,
+            vec![
+                (
+                    "registers.inc".to_string(),
+                    "PPU_CTRL                  = $2000\n".to_string()
+                ),
+                (
+                    "prgrom0.s".to_string(),
+                    "\n; -------------------------- PRGROM0 -----------------------\n\
+                     .segment \"PRGROM0\"\nprgrom0_start:\n    jmp prgrom0_start\n"
+                        .to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_write_other_dialect() {
+        struct Dasm;
+        impl AsmDialect for Dasm {
+            fn name(&self) -> &'static str {
+                return "dasm";
+            }
+            fn extension(&self) -> &'static str {
+                return "asm";
+            }
+            fn bytes(&self) -> &'static str {
+                return "dc.b";
+            }
+            fn words(&self) -> &'static str {
+                return "dc.w";
+            }
+            fn reserve(&self) -> &'static str {
+                return "ds.b";
+            }
+            fn here(&self) -> &'static str {
+                return ".";
+            }
+            fn segment(&self, name: &str, addr: u16) -> String {
+                return format!("    SEG {}\n    ORG {}", name, self.hex_u16(addr));
+            }
+        }
+
+        let mut code = Code::new(vec![0x01, 0x02]);
+        code.set_segment(FileOffset(0), "CODE");
+        code.set_comment(FileOffset(0), "first");
+        code.set_dialect(Box::new(Dasm), BTreeMap::from([(FileOffset(0), 0xf000)]));
+        assert_eq!(
+            written(&code),
+            "\n; -------------------------- CODE -----------------------\n    SEG CODE\n\
+             \x20   ORG $F000\ndc.b $01                  ; first\ndc.b $02\n"
+        );
+    }
+}
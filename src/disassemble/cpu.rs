@@ -0,0 +1,96 @@
+use std::{fmt, str::FromStr};
+
+/// Members of the 6502 family, which decode the same opcodes but differ in small
+/// ways that matter when reading their code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CpuVariant {
+    Mos6502,
+    /// 6502s made before June 1976, without a working `ror`
+    Early6502,
+    /// C64, with an I/O port at $00/$01
+    Mos6510,
+    /// NES, without decimal mode
+    Ricoh2A03,
+}
+
+/// What sets a variant apart from the plain 6502.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Quirks {
+    /// `ror` doesn't exist, so code using it is likely data
+    pub missing_ror: bool,
+    /// $00 and $01 are the data direction and data registers of the I/O port
+    pub io_port: bool,
+    /// the D flag is there but `adc` and `sbc` ignore it
+    pub no_decimal: bool,
+}
+
+impl CpuVariant {
+    pub fn quirks(&self) -> Quirks {
+        return match self {
+            CpuVariant::Mos6502 => Quirks::default(),
+            CpuVariant::Early6502 => Quirks {
+                missing_ror: true,
+                ..Quirks::default()
+            },
+            CpuVariant::Mos6510 => Quirks {
+                io_port: true,
+                ..Quirks::default()
+            },
+            CpuVariant::Ricoh2A03 => Quirks {
+                no_decimal: true,
+                ..Quirks::default()
+            },
+        };
+    }
+}
+
+impl fmt::Display for CpuVariant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            CpuVariant::Mos6502 => write!(f, "6502"),
+            CpuVariant::Early6502 => write!(f, "early-6502"),
+            CpuVariant::Mos6510 => write!(f, "6510"),
+            CpuVariant::Ricoh2A03 => write!(f, "2a03"),
+        };
+    }
+}
+
+impl FromStr for CpuVariant {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        return match s.to_ascii_lowercase().as_str() {
+            "6502" => Result::Ok(CpuVariant::Mos6502),
+            "early-6502" => Result::Ok(CpuVariant::Early6502),
+            "6510" => Result::Ok(CpuVariant::Mos6510),
+            "2a03" | "2a07" => Result::Ok(CpuVariant::Ricoh2A03),
+            _ => Result::Err(format!(
+                "unknown cpu variant \"{}\", expected 6502, early-6502, 6510 or 2a03",
+                s
+            )),
+        };
+    }
+}
+
+/// Opcodes of `ror` in all its addressing modes.
+pub fn is_ror(op: u8) -> bool {
+    return matches!(op, 0x66 | 0x6a | 0x6e | 0x76 | 0x7e);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cpu_variant() {
+        let variant = "2A03".parse::<CpuVariant>().unwrap();
+        assert_eq!(variant, CpuVariant::Ricoh2A03);
+        assert!(variant.quirks().no_decimal);
+        assert!(!variant.quirks().missing_ror);
+        assert_eq!(
+            "early-6502".parse::<CpuVariant>().unwrap().to_string(),
+            "early-6502"
+        );
+        assert!("65c02".parse::<CpuVariant>().is_err());
+    }
+}
@@ -0,0 +1,145 @@
+use std::{fmt, str::FromStr};
+
+use super::address::{BankedAddr, FileOffset};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+/// Something noticed about the input that does not stop the disassembly.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub offset: FileOffset,
+    /// address the offset was reached through, if it was reached by following code
+    pub addr: Option<BankedAddr>,
+    pub message: String,
+    /// lines shown below the message, e.g. what the bytes would decode to
+    pub notes: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DiagnosticsFormat {
+    /// `warning: message at offset $0010 (addr 00:8010)`
+    #[default]
+    Human,
+    /// `file:00:8010: warning: message`, parsed by editor problem matchers
+    Gcc,
+}
+
+impl Diagnostic {
+    pub fn warning(offset: FileOffset, addr: Option<BankedAddr>, message: String) -> Diagnostic {
+        return Diagnostic {
+            severity: Severity::Warning,
+            offset,
+            addr,
+            message,
+            notes: Vec::new(),
+        };
+    }
+
+    /// Location used by the gcc format, the banked address when known since that
+    /// is what shows up in a debugger, otherwise the file offset.
+    fn location(&self) -> String {
+        return match self.addr {
+            Option::Some(addr) => addr.to_string(),
+            Option::None => format!("0x{:04x}", self.offset),
+        };
+    }
+
+    pub fn format(&self, format: DiagnosticsFormat, file: &str) -> String {
+        let mut result = match format {
+            DiagnosticsFormat::Human => match self.addr {
+                Option::Some(addr) => format!(
+                    "{}: {} at offset ${:04x} (addr {})",
+                    self.severity, self.message, self.offset, addr
+                ),
+                Option::None => format!(
+                    "{}: {} at offset ${:04x}",
+                    self.severity, self.message, self.offset
+                ),
+            },
+            DiagnosticsFormat::Gcc => format!(
+                "{}:{}: {}: {}",
+                file,
+                self.location(),
+                self.severity,
+                self.message
+            ),
+        };
+        for note in &self.notes {
+            result += &match format {
+                DiagnosticsFormat::Human => format!("\n    {}", note),
+                DiagnosticsFormat::Gcc => format!("\n{}:{}: note: {}", file, self.location(), note),
+            };
+        }
+        return result;
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        };
+    }
+}
+
+impl FromStr for DiagnosticsFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        return match s {
+            "human" => Result::Ok(DiagnosticsFormat::Human),
+            "gcc" => Result::Ok(DiagnosticsFormat::Gcc),
+            _ => Result::Err(format!(
+                "unknown diagnostics format \"{}\", expected human or gcc",
+                s
+            )),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disassemble::address::CpuAddr;
+
+    #[test]
+    fn test_format() {
+        let d = Diagnostic::warning(
+            FileOffset(0x10),
+            Option::Some(BankedAddr {
+                bank: 0,
+                addr: CpuAddr(0x8000),
+            }),
+            "something".to_string(),
+        );
+        assert_eq!(
+            d.format(DiagnosticsFormat::Gcc, "rom.nes"),
+            "rom.nes:00:8000: warning: something"
+        );
+        assert_eq!(
+            d.format(DiagnosticsFormat::Human, "rom.nes"),
+            "warning: something at offset $0010 (addr 00:8000)"
+        );
+
+        let mut d = Diagnostic::warning(FileOffset(0x4010), Option::None, "missing".to_string());
+        assert_eq!(
+            d.format(DiagnosticsFormat::Gcc, "rom.nes"),
+            "rom.nes:0x4010: warning: missing"
+        );
+        d.notes.push("$8000: rts".to_string());
+        assert_eq!(
+            d.format(DiagnosticsFormat::Gcc, "rom.nes"),
+            "rom.nes:0x4010: warning: missing\nrom.nes:0x4010: note: $8000: rts"
+        );
+        assert_eq!(
+            d.format(DiagnosticsFormat::Human, "rom.nes"),
+            "warning: missing at offset $4010\n    $8000: rts"
+        );
+    }
+}
@@ -0,0 +1,401 @@
+use std::{fmt, str::FromStr};
+
+/// Assembler syntax the disassembly is written in, picking the [`AsmDialect`] that
+/// writes it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Dialect {
+    /// ca65, segments placed by the ld65 config written with `--linker-config`
+    #[default]
+    Ca65,
+    /// asm6 and asm6f, which write the bytes in the order they come with each
+    /// segment's address set by `.base`
+    Asm6,
+    /// DASM, what Atari 2600 programs are written in, with `ORG` for each segment
+    Dasm,
+    /// ACME, common for the C64, with `* =` for each segment
+    Acme,
+}
+
+impl Dialect {
+    pub fn writer(&self) -> Box<dyn AsmDialect> {
+        return match self {
+            Dialect::Ca65 => Box::new(Ca65),
+            Dialect::Asm6 => Box::new(Asm6),
+            Dialect::Dasm => Box::new(Dasm),
+            Dialect::Acme => Box::new(Acme),
+        };
+    }
+}
+
+impl fmt::Display for Dialect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, "{}", self.writer().name());
+    }
+}
+
+impl FromStr for Dialect {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        return match s {
+            "ca65" => Result::Ok(Dialect::Ca65),
+            "asm6" | "asm6f" => Result::Ok(Dialect::Asm6),
+            "dasm" => Result::Ok(Dialect::Dasm),
+            "acme" => Result::Ok(Dialect::Acme),
+            _ => Result::Err(format!(
+                "unknown dialect \"{}\", expected ca65, asm6, asm6f, dasm or acme",
+                s
+            )),
+        };
+    }
+}
+
+/// Every choice of syntax [`Code::write`](super::code::Code::write) makes. The
+/// defaults are what most 6502 assemblers take, a dialect overrides what it
+/// writes differently and returns `None` for what it has no directive for.
+pub trait AsmDialect {
+    /// name of the assembler
+    fn name(&self) -> &'static str;
+
+    /// extension of the assembly files written
+    fn extension(&self) -> &'static str;
+
+    /// directive for rows of bytes
+    fn bytes(&self) -> &'static str;
+
+    /// directive for rows of little endian words
+    fn words(&self) -> &'static str;
+
+    /// directive for a run of bytes given by its length
+    fn reserve(&self) -> &'static str;
+
+    /// symbol for the address being assembled
+    fn here(&self) -> &'static str;
+
+    fn comment(&self) -> &'static str {
+        return ";";
+    }
+
+    fn hex_u8(&self, v: u8) -> String {
+        return format!("${:02X}", v);
+    }
+
+    fn hex_u16(&self, v: u16) -> String {
+        return format!("${:04X}", v);
+    }
+
+    fn binary_u8(&self, v: u8) -> String {
+        return format!("%{:08b}", v);
+    }
+
+    /// the constant `name` defined as `value`
+    fn constant(&self, name: &str, value: &str) -> String {
+        return format!("{:<25} = {}", name, value);
+    }
+
+    /// `name` defined as the address it is written at
+    fn label(&self, name: &str) -> String {
+        return format!("{}:", name);
+    }
+
+    /// `name` defined as `n` bytes past the address it is written at
+    fn label_at(&self, name: &str, n: usize) -> String {
+        return format!("{} = {} + {}", name, self.here(), n);
+    }
+
+    /// Starts the segment `name`, its first byte being at `addr`.
+    fn segment(&self, name: &str, addr: u16) -> String;
+
+    /// Assembles `file`, a path relative to the file including it, as if it were
+    /// written in its place.
+    fn include(&self, file: &str) -> String {
+        return format!(".include \"{}\"", file);
+    }
+
+    /// Starts the scope `name` holding the labels up to [`AsmDialect::scope_end`],
+    /// see [`AsmDialect::qualified`].
+    fn scope_start(&self, _name: &str) -> Option<String> {
+        return Option::None;
+    }
+
+    fn scope_end(&self) -> Option<String> {
+        return Option::None;
+    }
+
+    /// The label `name` of the scope `scope` as written outside of it.
+    fn qualified(&self, scope: &str, name: &str) -> String {
+        return format!("{}::{}", scope, name);
+    }
+
+    /// Lets the assembler take the mnemonics of `cpu`, `6502` unless the code
+    /// needs another.
+    fn cpu(&self, _cpu: &str) -> Option<String> {
+        return Option::None;
+    }
+
+    /// Makes `c` in strings assemble to `byte`.
+    fn charmap(&self, _c: char, _byte: u8) -> Option<String> {
+        return Option::None;
+    }
+
+    /// Makes the label `name` visible to other modules.
+    fn export(&self, _name: &str) -> Option<String> {
+        return Option::None;
+    }
+
+    /// `name` defined as the start of a routine, ended with [`AsmDialect::proc_end`].
+    fn proc_start(&self, name: &str) -> String {
+        return self.label(name);
+    }
+
+    fn proc_end(&self) -> Option<String> {
+        return Option::None;
+    }
+
+    /// Has the assembler check the code after it assembles to `bytes`.
+    fn verify(&self, _bytes: &[u8]) -> Option<String> {
+        return Option::None;
+    }
+
+    /// Rewrites `instr`, written the way [`Instruction`](super::Instruction) writes
+    /// it, which is for ca65.
+    fn instruction(&self, instr: String) -> String {
+        return instr;
+    }
+
+    /// Whether `instr` as written by [`AsmDialect::instruction`] assembles to the
+    /// bytes it came from, `illegal` if it uses a `6502X` mnemonic. Those that don't
+    /// are written as bytes.
+    fn assembles(&self, _instr: &str, _illegal: bool) -> bool {
+        return true;
+    }
+}
+
+pub struct Ca65;
+
+impl AsmDialect for Ca65 {
+    fn name(&self) -> &'static str {
+        return "ca65";
+    }
+
+    fn extension(&self) -> &'static str {
+        return "s";
+    }
+
+    fn bytes(&self) -> &'static str {
+        return ".byte";
+    }
+
+    fn words(&self) -> &'static str {
+        return ".word";
+    }
+
+    fn reserve(&self) -> &'static str {
+        return ".res";
+    }
+
+    fn here(&self) -> &'static str {
+        return "*";
+    }
+
+    fn label_at(&self, name: &str, n: usize) -> String {
+        return format!("{} := * + {}", name, n);
+    }
+
+    fn segment(&self, name: &str, _addr: u16) -> String {
+        return format!(".segment \"{}\"", name);
+    }
+
+    fn scope_start(&self, name: &str) -> Option<String> {
+        return Option::Some(format!(".scope {}", name));
+    }
+
+    fn scope_end(&self) -> Option<String> {
+        return Option::Some(".endscope".to_string());
+    }
+
+    fn cpu(&self, cpu: &str) -> Option<String> {
+        // what ca65 assembles without being told
+        if cpu == "6502" {
+            return Option::None;
+        }
+        return Option::Some(format!(".setcpu \"{}\"", cpu));
+    }
+
+    fn charmap(&self, c: char, byte: u8) -> Option<String> {
+        return Option::Some(format!(".charmap ${:02X}, ${:02X}", c as u8, byte));
+    }
+
+    fn export(&self, name: &str) -> Option<String> {
+        return Option::Some(format!(".export {}", name));
+    }
+
+    fn proc_start(&self, name: &str) -> String {
+        return format!(".proc {}", name);
+    }
+
+    fn proc_end(&self) -> Option<String> {
+        return Option::Some(".endproc".to_string());
+    }
+
+    fn verify(&self, bytes: &[u8]) -> Option<String> {
+        let bytes = bytes
+            .iter()
+            .map(|b| self.hex_u8(*b))
+            .collect::<Vec<String>>();
+        return Option::Some(format!(".verify {}", bytes.join(", ")));
+    }
+}
+
+/// asm6 has neither `a:` to keep a zero page operand absolute nor the `6502X`
+/// mnemonics, nor segments, procs or `.verify`.
+pub struct Asm6;
+
+impl AsmDialect for Asm6 {
+    fn name(&self) -> &'static str {
+        return "asm6";
+    }
+
+    fn extension(&self) -> &'static str {
+        return "asm";
+    }
+
+    fn bytes(&self) -> &'static str {
+        return ".db";
+    }
+
+    fn words(&self) -> &'static str {
+        return ".dw";
+    }
+
+    fn reserve(&self) -> &'static str {
+        return ".dsb";
+    }
+
+    fn here(&self) -> &'static str {
+        return "$";
+    }
+
+    fn segment(&self, _name: &str, addr: u16) -> String {
+        return format!(".base {}", self.hex_u16(addr));
+    }
+
+    fn include(&self, file: &str) -> String {
+        return format!("include {}", file);
+    }
+
+    fn instruction(&self, instr: String) -> String {
+        return instr.replace(".byte", ".db").replace(" *", " $");
+    }
+
+    fn assembles(&self, instr: &str, illegal: bool) -> bool {
+        return !illegal && !instr.contains(" a:");
+    }
+}
+
+/// DASM takes anything starting in the first column for a label, so every
+/// directive is indented. It has none of the `6502X` mnemonics and forces an
+/// absolute operand with `.w` after the mnemonic.
+pub struct Dasm;
+
+impl AsmDialect for Dasm {
+    fn name(&self) -> &'static str {
+        return "dasm";
+    }
+
+    fn extension(&self) -> &'static str {
+        return "asm";
+    }
+
+    fn bytes(&self) -> &'static str {
+        return "    .byte";
+    }
+
+    fn words(&self) -> &'static str {
+        return "    .word";
+    }
+
+    fn reserve(&self) -> &'static str {
+        return "    ds.b";
+    }
+
+    fn here(&self) -> &'static str {
+        return ".";
+    }
+
+    fn label(&self, name: &str) -> String {
+        return name.to_string();
+    }
+
+    fn segment(&self, name: &str, addr: u16) -> String {
+        return format!("    SEG {}\n    ORG {}", name, self.hex_u16(addr));
+    }
+
+    fn include(&self, file: &str) -> String {
+        return format!("    include \"{}\"", file);
+    }
+
+    // every DASM source starts by naming the CPU
+    fn cpu(&self, _cpu: &str) -> Option<String> {
+        return Option::Some("    processor 6502".to_string());
+    }
+
+    fn instruction(&self, instr: String) -> String {
+        return instr.replacen(" a:", ".w ", 1).replace(" *", " .");
+    }
+
+    fn assembles(&self, _instr: &str, illegal: bool) -> bool {
+        return !illegal;
+    }
+}
+
+/// ACME writes its directives with `!` and forces an absolute operand with `+2`
+/// after the mnemonic. Its `6510` undocumented mnemonics aren't ca65's, so those
+/// opcodes are written as bytes.
+pub struct Acme;
+
+impl AsmDialect for Acme {
+    fn name(&self) -> &'static str {
+        return "acme";
+    }
+
+    fn extension(&self) -> &'static str {
+        return "a";
+    }
+
+    fn bytes(&self) -> &'static str {
+        return "!byte";
+    }
+
+    fn words(&self) -> &'static str {
+        return "!word";
+    }
+
+    fn reserve(&self) -> &'static str {
+        return "!fill";
+    }
+
+    fn here(&self) -> &'static str {
+        return "*";
+    }
+
+    fn label(&self, name: &str) -> String {
+        return name.to_string();
+    }
+
+    fn segment(&self, _name: &str, addr: u16) -> String {
+        return format!("* = {}", self.hex_u16(addr));
+    }
+
+    fn include(&self, file: &str) -> String {
+        return format!("!source \"{}\"", file);
+    }
+
+    fn instruction(&self, instr: String) -> String {
+        return instr.replacen(" a:", "+2 ", 1).replace(".byte", "!byte");
+    }
+
+    fn assembles(&self, _instr: &str, illegal: bool) -> bool {
+        return !illegal;
+    }
+}
@@ -1,684 +1,364 @@
-use super::{DisassembleError, code::{Code, AsmCode}, instruction::Instruction};
+use std::{collections::BTreeMap, ops::Range};
+
+use crate::decode::{
+    decode, decode_illegal, decode_illegal_opcode, decode_opcode, AddressingMode,
+    DecodedInstruction,
+};
+use crate::opcodes::{lookup, Opcode};
+
+use super::{
+    address::{CpuAddr, FileOffset},
+    code::{AsmCode, Code},
+    cpu::{is_ror, Quirks},
+    diagnostic::Diagnostic,
+    instruction::Instruction,
+    mapper::Mapper,
+    registers::KnownValues,
+    DisassembleError, IllegalOpcodes, JamOpcodes,
+};
+
+/// instructions decoded for a diagnostic where tracing stopped
+const PREVIEW_LENGTH: usize = 4;
+
+/// How following code goes on after an instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Flow {
+    /// on to the next instruction
+    Next,
+    /// the code ends here
+    Stop,
+    /// to the target and the next instruction
+    Branch,
+    Jump,
+    /// to where a pointer in ROM points
+    JumpIndirect,
+    /// to the subroutine and, once it returns, the next instruction
+    Call,
+    /// through the IRQ/BRK vector, then past the signature byte
+    Break,
+    /// nowhere, the CPU halts, unless `jam_opcodes` says otherwise
+    Jam,
+}
+
+/// The opcode table tells the mode and size of each opcode, [`Instruction::decode`]
+/// builds the instruction and this how tracing goes on after it.
+fn flow(opcode: &Opcode) -> Flow {
+    return match (opcode.mnemonic, opcode.mode) {
+        (_, AddressingMode::Relative) => Flow::Branch,
+        ("jmp", AddressingMode::Indirect) => Flow::JumpIndirect,
+        ("jmp", _) => Flow::Jump,
+        ("jsr", _) => Flow::Call,
+        ("rts" | "rti", _) => Flow::Stop,
+        ("brk", _) => Flow::Break,
+        _ => Flow::Next,
+    };
+}
 
 pub struct Disassembler {
     pub code: Code,
+    pub diagnostics: Vec<Diagnostic>,
+    /// fail when code runs into bytes already claimed instead of warning
+    pub strict: bool,
+    pub illegal_opcodes: IllegalOpcodes,
+    pub jam_opcodes: JamOpcodes,
+    pub quirks: Quirks,
+    /// target of each `jsr` followed, by the offset of the `jsr`
+    pub calls: BTreeMap<FileOffset, CpuAddr>,
+    /// where each opcode that stopped the code being followed was found
+    pub unhandled_opcodes: BTreeMap<u8, Vec<FileOffset>>,
+    /// pointer of each `jmp (ptr)` whose target isn't known, by the offset of the
+    /// `jmp`
+    pub unresolved_jumps: BTreeMap<FileOffset, CpuAddr>,
 }
 
 impl Disassembler {
     pub fn new(data: Vec<u8>) -> Disassembler {
         return Disassembler {
             code: Code::new(data),
+            diagnostics: Vec::new(),
+            strict: false,
+            illegal_opcodes: IllegalOpcodes::Stop,
+            jam_opcodes: JamOpcodes::Stop,
+            quirks: Quirks::default(),
+            calls: BTreeMap::new(),
+            unhandled_opcodes: BTreeMap::new(),
+            unresolved_jumps: BTreeMap::new(),
         };
     }
 
-    pub fn disassemble<F1: Fn(u16) -> usize, F2: Fn(usize) -> u16>(
+    /// Reports that `by` wants bytes `claimed` already belongs to, as a warning or,
+    /// in strict mode, an error.
+    pub fn overlap(
+        &mut self,
+        offset: FileOffset,
+        claimed: FileOffset,
+        by: &str,
+        mapper: &dyn Mapper,
+    ) -> Result<(), DisassembleError> {
+        let message = format!("{} overlaps {}", by, self.code.claimant(claimed));
+        if self.strict {
+            return Result::Err(DisassembleError::Overlap(format!(
+                "{} at offset ${:04x} (addr {})",
+                message,
+                offset,
+                mapper.to_banked_addr(offset)
+            )));
+        }
+        self.diagnostics.push(Diagnostic::warning(
+            offset,
+            Option::Some(mapper.to_banked_addr(offset)),
+            message,
+        ));
+        return Result::Ok(());
+    }
+
+    /// Warns about stores and read-modify-writes into decoded instructions in
+    /// `range`, at the store and at the instruction it changes, since what runs
+    /// there isn't what is written. `writable` tells the addresses code can be
+    /// changed at, stores to ROM go to the hardware instead.
+    pub fn find_self_modifying_code(
+        &mut self,
+        range: Range<FileOffset>,
+        writable: &dyn Fn(u16) -> bool,
+        mapper: &dyn Mapper,
+    ) {
+        let mut stores = Vec::new();
+        for offset in range.start.0..range.end.0 {
+            if !self.code.is_instruction(FileOffset(offset)) {
+                continue;
+            }
+            let addr = mapper.to_addr(FileOffset(offset));
+            let Option::Some(instr) = decode(&self.code.data()[offset..], addr.0) else {
+                continue;
+            };
+            let writes = matches!(
+                instr.mnemonic,
+                "sta" | "stx" | "sty" | "inc" | "dec" | "asl" | "lsr" | "rol" | "ror"
+            ) && matches!(
+                instr.mode,
+                AddressingMode::ZeroPage
+                    | AddressingMode::ZeroPageX
+                    | AddressingMode::ZeroPageY
+                    | AddressingMode::Absolute
+                    | AddressingMode::AbsoluteX
+                    | AddressingMode::AbsoluteY
+            );
+            if !writes || !writable(instr.operand) {
+                continue;
+            }
+            let Option::Some(target) = mapper.to_offset(CpuAddr(instr.operand)) else {
+                continue;
+            };
+            // the instruction the written byte is the opcode or an operand of
+            let modified = (0..3)
+                .filter_map(|back| target.0.checked_sub(back))
+                .find(|o| {
+                    self.code.is_instruction(FileOffset(*o))
+                        && decode(&self.code.data()[*o..], mapper.to_addr(FileOffset(*o)).0)
+                            .is_some_and(|i| i.size() > target.0 - o)
+                });
+            if let Option::Some(modified) = modified {
+                stores.push((
+                    FileOffset(offset),
+                    addr,
+                    FileOffset(modified),
+                    instr.operand,
+                ));
+            }
+        }
+
+        for (offset, addr, modified, target) in stores {
+            let modified_addr = mapper.to_addr(modified);
+            let message = format!(
+                "self-modifying code, ${:04x} writes ${:04x} in the instruction at ${:04x}",
+                addr.0, target, modified_addr.0
+            );
+            self.diagnostics.push(Diagnostic::warning(
+                offset,
+                Option::Some(mapper.to_banked_addr(offset)),
+                message,
+            ));
+            self.code.add_comment(
+                offset,
+                &format!(
+                    "self-modifying: changes the instruction at ${:04x}",
+                    modified_addr.0
+                ),
+            );
+            self.code.add_comment(
+                modified,
+                &format!("modified by ${:04x}, may not be what runs", addr.0),
+            );
+        }
+    }
+
+    /// Follows the code at `addr`, labeling it `name` after `label_prefix`. Code
+    /// outside the file, e.g. copied to RAM, can't be followed and is left alone.
+    pub fn disassemble(
         &mut self,
-        addr: u16,
+        addr: CpuAddr,
         name: &str,
         label_prefix: &str,
-        addr_to_offset_fn: &F1,
-        offset_to_addr_fn: &F2,
+        mapper: &dyn Mapper,
     ) -> Result<(), DisassembleError> {
         let mut addr = addr;
-        let mut offset = addr_to_offset_fn(addr);
-        self.code
-            .set_label(offset, format!("{}_{}", label_prefix, name).as_str());
+        let mut offset = match mapper.to_offset(addr) {
+            Option::Some(offset) => offset,
+            Option::None => return Result::Ok(()),
+        };
+        let entry = format!("{}_{}", label_prefix, name);
+        self.code.set_label(offset, entry.as_str());
+        let by = format!("code traced from {}", entry);
+        let start = offset;
+        let mut values = KnownValues::default();
 
         loop {
-            let mut set_addr: Option<u16> = Option::None;
+            let mut set_addr: Option<(CpuAddr, FileOffset)> = Option::None;
             if self.code.is_instruction(offset) {
                 break;
             }
-
-            let op = self.code.get_u8(offset)?;
-            let result = match op {
-                // JAM
-                0x02 => {
-                    self.code
-                        .replace_with_instr(offset, 0, |_args| Result::Ok(Instruction::JAM))?;
-                    Result::Ok(0)
-                }
-                
-                // ORA ZP
-                0x05 => self.code.replace_with_instr(offset, 1, |args| {
-                    Result::Ok(Instruction::ORA_ZP(args[0].to_u8()?))
-                }),
-
-                // ASL ZP
-                0x06 => self.code.replace_with_instr(offset, 1, |args| {
-                    Result::Ok(Instruction::ASL_ZP(args[0].to_u8()?))
-                }),
-
-                // PHP
-                0x08 => self
-                    .code
-                    .replace_with_instr(offset, 0, |_args| Result::Ok(Instruction::PHP)),
-
-                // ORA IMM
-                0x09 => self.code.replace_with_instr(offset, 1, |args| {
-                    Result::Ok(Instruction::ORA_IMM(args[0].to_u8()?))
-                }),
-
-                // ASL
-                0x0a => self
-                    .code
-                    .replace_with_instr(offset, 0, |_args| Result::Ok(Instruction::ASL)),
-
-                // BPL REL
-                0x10 => self.branch_relative(
-                    offset,
-                    addr,
-                    label_prefix,
-                    addr_to_offset_fn,
-                    offset_to_addr_fn,
-                    &|rel, label| Instruction::BPL_REL(rel, label),
-                ),
-
-                // JAM
-                0x12 => {
-                    self.code
-                        .replace_with_instr(offset, 0, |_args| Result::Ok(Instruction::JAM))?;
-                    Result::Ok(0)
-                }
-
-                // CLC
-                0x18 => self
-                    .code
-                    .replace_with_instr(offset, 0, |_args| Result::Ok(Instruction::CLC)),
-
-                // JSR ABS
-                0x20 => {
-                    let l = self.code.get_u8(offset + 1)? as u16;
-                    let h = self.code.get_u8(offset + 2)? as u16;
-                    let jsr_addr = (h << 8) | l;
-                    let label = format!("{}_{:04x}", label_prefix, jsr_addr);
-                    let jsr_result = self.code.replace_with_instr(offset, 2, |_args| {
-                        Result::Ok(Instruction::JSR_ABS(jsr_addr, label.clone()))
-                    });
-
-                    // disassemble jump address
-                    self.disassemble(
-                        jsr_addr,
-                        format!("{:04x}", jsr_addr).as_str(),
-                        label_prefix,
-                        addr_to_offset_fn,
-                        offset_to_addr_fn,
-                    )?;
-
-                    jsr_result
-                }
-
-                // JAM
-                0x22 => {
-                    self.code
-                        .replace_with_instr(offset, 0, |_args| Result::Ok(Instruction::JAM))?;
-                    Result::Ok(0)
-                }
-
-                // BIT ZP
-                0x24 => self.code.replace_with_instr(offset, 1, |args| {
-                    Result::Ok(Instruction::BIT_ZP(args[0].to_u8()?))
-                }),
-
-                // AND ZP
-                0x25 => self.code.replace_with_instr(offset, 1, |args| {
-                    Result::Ok(Instruction::AND_ZP(args[0].to_u8()?))
-                }),
-
-                // PLP
-                0x28 => self
-                    .code
-                    .replace_with_instr(offset, 0, |_args| Result::Ok(Instruction::PLP)),
-
-                // AND IMM
-                0x29 => self.code.replace_with_instr(offset, 1, |args| {
-                    Result::Ok(Instruction::AND_IMM(args[0].to_u8()?))
-                }),
-
-                // ROL
-                0x2a => self
-                    .code
-                    .replace_with_instr(offset, 0, |_args| Result::Ok(Instruction::ROL)),
-
-                // BMI REL
-                0x30 => self.branch_relative(
-                    offset,
-                    addr,
-                    label_prefix,
-                    addr_to_offset_fn,
-                    offset_to_addr_fn,
-                    &|rel, label| Instruction::BMI_REL(rel, label),
-                ),
-
-                // JAM
-                0x32 => {
-                    self.code
-                        .replace_with_instr(offset, 0, |_args| Result::Ok(Instruction::JAM))?;
-                    Result::Ok(0)
-                }
-
-                // AND ZP,x
-                0x35 => self.code.replace_with_instr(offset, 1, |args| {
-                    Result::Ok(Instruction::AND_ZP_X(args[0].to_u8()?))
-                }),
-
-                // SEC
-                0x38 => self
-                    .code
-                    .replace_with_instr(offset, 0, |_args| Result::Ok(Instruction::SEC)),
-
-                // RTI
-                0x40 => {
-                    self.code
-                        .replace_with_instr(offset, 0, |_args| Result::Ok(Instruction::RTI))?;
-                    Result::Ok(0)
-                }
-
-                // JAM
-                0x42 => {
-                    self.code
-                        .replace_with_instr(offset, 0, |_args| Result::Ok(Instruction::JAM))?;
-                    Result::Ok(0)
-                }
-
-                // EOR ZP
-                0x45 => self.code.replace_with_instr(offset, 1, |args| {
-                    Result::Ok(Instruction::EOR_ZP(args[0].to_u8()?))
-                }),
-
-                // LSR ZP
-                0x46 => self.code.replace_with_instr(offset, 1, |args| {
-                    Result::Ok(Instruction::LSR_ZP(args[0].to_u8()?))
-                }),
-
-                // PHA
-                0x48 => self
-                    .code
-                    .replace_with_instr(offset, 0, |_args| Result::Ok(Instruction::PHA)),
-
-                // EOR IMM
-                0x49 => self.code.replace_with_instr(offset, 1, |args| {
-                    Result::Ok(Instruction::EOR_IMM(args[0].to_u8()?))
-                }),
-
-                // LSR
-                0x4a => self
-                    .code
-                    .replace_with_instr(offset, 0, |_args| Result::Ok(Instruction::LSR)),
-
-                // JMP ABS
-                0x4c => {
-                    let l = self.code.get_u8(offset + 1)? as u16;
-                    let h = self.code.get_u8(offset + 2)? as u16;
-                    let jmp_addr = (h << 8) | l;
-                    let label = format!("{}_{:04x}", label_prefix, jmp_addr);
-                    self.code.replace_with_instr(offset, 2, |_args| {
-                        Result::Ok(Instruction::JMP_ABS(jmp_addr, label.clone()))
-                    })?;
-
-                    set_addr = Option::Some(jmp_addr);
-                    Result::Ok(0)
-                }
-
-                // EOR ABS
-                0x4d => self.code.replace_with_instr(offset, 2, |args| {
-                    Result::Ok(Instruction::EOR_ABS(to_u16(&args[0], &args[1])?))
-                }),
-
-                // JAM
-                0x52 => {
-                    self.code
-                        .replace_with_instr(offset, 0, |_args| Result::Ok(Instruction::JAM))?;
-                    Result::Ok(0)
-                }
-
-                // RTS
-                0x60 => {
-                    self.code
-                        .replace_with_instr(offset, 0, |_args| Result::Ok(Instruction::RTS))?;
-                    Result::Ok(0)
-                }
-
-                // JAM
-                0x62 => {
-                    self.code
-                        .replace_with_instr(offset, 0, |_args| Result::Ok(Instruction::JAM))?;
-                    Result::Ok(0)
-                }
-
-                // ADC ZP
-                0x65 => self.code.replace_with_instr(offset, 1, |args| {
-                    Result::Ok(Instruction::ADC_ZP(args[0].to_u8()?))
-                }),
-
-                // ROR ZP
-                0x66 => self.code.replace_with_instr(offset, 1, |args| {
-                    Result::Ok(Instruction::ROR_ZP(args[0].to_u8()?))
-                }),
-
-                // PLA
-                0x68 => self
-                    .code
-                    .replace_with_instr(offset, 0, |_args| Result::Ok(Instruction::PLA)),
-
-                // ADC IMM
-                0x69 => self.code.replace_with_instr(offset, 1, |args| {
-                    Result::Ok(Instruction::ADC_IMM(args[0].to_u8()?))
-                }),
-
-                // ROR
-                0x6a => self
-                    .code
-                    .replace_with_instr(offset, 0, |_args| Result::Ok(Instruction::ROR)),
-
-                // ADC ABS
-                0x6d => self.code.replace_with_instr(offset, 2, |args| {
-                    Result::Ok(Instruction::ADC_ABS(to_u16(&args[0], &args[1])?))
-                }),
-
-                // JAM
-                0x72 => {
-                    self.code
-                        .replace_with_instr(offset, 0, |_args| Result::Ok(Instruction::JAM))?;
-                    Result::Ok(0)
-                }
-
-                // SEI
-                0x78 => self
-                    .code
-                    .replace_with_instr(offset, 0, |_args| Result::Ok(Instruction::SEI)),
-
-                // ADC ABS,x
-                0x7d => self.code.replace_with_instr(offset, 2, |args| {
-                    Result::Ok(Instruction::ADC_ABS_X(to_u16(&args[0], &args[1])?))
-                }),
-
-                // STY ZP
-                0x84 => self.code.replace_with_instr(offset, 1, |args| {
-                    Result::Ok(Instruction::STY_ZP(args[0].to_u8()?))
-                }),
-
-                // STA ZP
-                0x85 => self.code.replace_with_instr(offset, 1, |args| {
-                    Result::Ok(Instruction::STA_ZP(args[0].to_u8()?))
-                }),
-
-                // STX ZP
-                0x86 => self.code.replace_with_instr(offset, 1, |args| {
-                    Result::Ok(Instruction::STX_ZP(args[0].to_u8()?))
-                }),
-
-                // DEY
-                0x88 => self
-                    .code
-                    .replace_with_instr(offset, 0, |_args| Result::Ok(Instruction::DEY)),
-
-                // TXA
-                0x8a => self
-                    .code
-                    .replace_with_instr(offset, 0, |_args| Result::Ok(Instruction::TXA)),
-
-                // STY ABS
-                0x8c => self.code.replace_with_instr(offset, 2, |args| {
-                    Result::Ok(Instruction::STY_ABS(to_u16(&args[0], &args[1])?))
-                }),
-
-                // STA ABS
-                0x8d => self.code.replace_with_instr(offset, 2, |args| {
-                    Result::Ok(Instruction::STA_ABS(to_u16(&args[0], &args[1])?))
-                }),
-
-                // STX ABS
-                0x8e => self.code.replace_with_instr(offset, 2, |args| {
-                    Result::Ok(Instruction::STX_ABS(to_u16(&args[0], &args[1])?))
-                }),
-
-                // BCC
-                0x90 => self.branch_relative(
-                    offset,
-                    addr,
-                    label_prefix,
-                    addr_to_offset_fn,
-                    offset_to_addr_fn,
-                    &|rel, label| Instruction::BCC_REL(rel, label),
-                ),
-
-                // STA IND,y
-                0x91 => self.code.replace_with_instr(offset, 1, |args| {
-                    Result::Ok(Instruction::STA_IND_Y(args[0].to_u8()?))
-                }),
-
-                // JAM
-                0x92 => {
-                    self.code
-                        .replace_with_instr(offset, 0, |_args| Result::Ok(Instruction::JAM))?;
-                    Result::Ok(0)
-                }
-
-                // STY ZP,x
-                0x94 => self.code.replace_with_instr(offset, 1, |args| {
-                    Result::Ok(Instruction::STY_ZP_X(args[0].to_u8()?))
-                }),
-
-                // STA ZP,x
-                0x95 => self.code.replace_with_instr(offset, 1, |args| {
-                    Result::Ok(Instruction::STA_ZP_X(args[0].to_u8()?))
-                }),
-
-                // TYA
-                0x98 => self
-                    .code
-                    .replace_with_instr(offset, 0, |_args| Result::Ok(Instruction::TYA)),
-
-                // STA ABS,y
-                0x99 => self.code.replace_with_instr(offset, 2, |args| {
-                    Result::Ok(Instruction::STA_ABS_Y(to_u16(&args[0], &args[1])?))
-                }),
-
-                // TXS
-                0x9a => self
-                    .code
-                    .replace_with_instr(offset, 0, |_args| Result::Ok(Instruction::TXS)),
-
-                // STA ABS,x
-                0x9d => self.code.replace_with_instr(offset, 2, |args| {
-                    Result::Ok(Instruction::STA_ABS_X(to_u16(&args[0], &args[1])?))
-                }),
-
-                // LDY IMM
-                0xa0 => self.code.replace_with_instr(offset, 1, |args| {
-                    Result::Ok(Instruction::LDY_IMM(args[0].to_u8()?))
-                }),
-
-                // LDX IMM
-                0xa2 => self.code.replace_with_instr(offset, 1, |args| {
-                    Result::Ok(Instruction::LDX_IMM(args[0].to_u8()?))
-                }),
-
-                // LDY ZP
-                0xa4 => self.code.replace_with_instr(offset, 1, |args| {
-                    Result::Ok(Instruction::LDY_ZP(args[0].to_u8()?))
-                }),
-
-                // LDA ZP
-                0xa5 => self.code.replace_with_instr(offset, 1, |args| {
-                    Result::Ok(Instruction::LDA_ZP(args[0].to_u8()?))
-                }),
-
-                // LDX ZP
-                0xa6 => self.code.replace_with_instr(offset, 1, |args| {
-                    Result::Ok(Instruction::LDX_ZP(args[0].to_u8()?))
-                }),
-
-                // LDA IMM
-                0xa9 => self.code.replace_with_instr(offset, 1, |args| {
-                    Result::Ok(Instruction::LDA_IMM(args[0].to_u8()?))
-                }),
-
-                // TAX
-                0xaa => self
-                    .code
-                    .replace_with_instr(offset, 0, |_args| Result::Ok(Instruction::TAX)),
-
-                // TAY
-                0xa8 => self
-                    .code
-                    .replace_with_instr(offset, 0, |_args| Result::Ok(Instruction::TAY)),
-
-                // LDY ABS
-                0xac => self.code.replace_with_instr(offset, 2, |args| {
-                    Result::Ok(Instruction::LDY_ABS(to_u16(&args[0], &args[1])?))
-                }),
-
-                // LDA ABS
-                0xad => self.code.replace_with_instr(offset, 2, |args| {
-                    Result::Ok(Instruction::LDA_ABS(to_u16(&args[0], &args[1])?))
-                }),
-
-                // LDX ABS
-                0xae => self.code.replace_with_instr(offset, 2, |args| {
-                    Result::Ok(Instruction::LDX_ABS(to_u16(&args[0], &args[1])?))
-                }),
-
-                // BCS REL
-                0xb0 => self.branch_relative(
-                    offset,
-                    addr,
-                    label_prefix,
-                    addr_to_offset_fn,
-                    offset_to_addr_fn,
-                    &|rel, label| Instruction::BCS_REL(rel, label),
-                ),
-
-                // LDA IND,y
-                0xb1 => self.code.replace_with_instr(offset, 1, |args| {
-                    Result::Ok(Instruction::LDA_IND_Y(args[0].to_u8()?))
-                }),
-
-                // JAM
-                0xb2 => {
-                    self.code
-                        .replace_with_instr(offset, 0, |_args| Result::Ok(Instruction::JAM))?;
-                    Result::Ok(0)
-                }
-
-                // LDY ZP,x
-                0xb4 => self.code.replace_with_instr(offset, 1, |args| {
-                    Result::Ok(Instruction::LDY_ZP_X(args[0].to_u8()?))
-                }),
-
-                // LDA ZP,x
-                0xb5 => self.code.replace_with_instr(offset, 1, |args| {
-                    Result::Ok(Instruction::LDA_ZP_X(args[0].to_u8()?))
-                }),
-
-                // LDA ABS,y
-                0xb9 => self.code.replace_with_instr(offset, 2, |args| {
-                    Result::Ok(Instruction::LDA_ABS_Y(to_u16(&args[0], &args[1])?))
-                }),
-
-                // LDY ABS,x
-                0xbc => self.code.replace_with_instr(offset, 2, |args| {
-                    Result::Ok(Instruction::LDY_ABS_X(to_u16(&args[0], &args[1])?))
-                }),
-
-                // LDA abs,x
-                0xbd => self.code.replace_with_instr(offset, 2, |args| {
-                    Result::Ok(Instruction::LDA_ABS_X(to_u16(&args[0], &args[1])?))
-                }),
-
-                // LDX abs,y
-                0xbe => self.code.replace_with_instr(offset, 2, |args| {
-                    Result::Ok(Instruction::LDX_ABS_Y(to_u16(&args[0], &args[1])?))
-                }),
-
-                // CPY IMM
-                0xc0 => self.code.replace_with_instr(offset, 1, |args| {
-                    Result::Ok(Instruction::CPY_IMM(args[0].to_u8()?))
-                }),
-
-                // CPY ZP
-                0xc4 => self.code.replace_with_instr(offset, 1, |args| {
-                    Result::Ok(Instruction::CPY_ZP(args[0].to_u8()?))
-                }),
-
-                // CMP ZP
-                0xc5 => self.code.replace_with_instr(offset, 1, |args| {
-                    Result::Ok(Instruction::CMP_ZP(args[0].to_u8()?))
-                }),
-
-                // DEC ZP
-                0xc6 => self.code.replace_with_instr(offset, 1, |args| {
-                    Result::Ok(Instruction::DEC_ZP(args[0].to_u8()?))
-                }),
-
-                // INY
-                0xc8 => self
-                    .code
-                    .replace_with_instr(offset, 0, |_args| Result::Ok(Instruction::INY)),
-
-                // CMP IMM
-                0xc9 => self.code.replace_with_instr(offset, 1, |args| {
-                    Result::Ok(Instruction::CMP_IMM(args[0].to_u8()?))
-                }),
-
-                // DEX
-                0xca => self
-                    .code
-                    .replace_with_instr(offset, 0, |_args| Result::Ok(Instruction::DEX)),
-
-                // CMP ABS
-                0xcd => self.code.replace_with_instr(offset, 2, |args| {
-                    Result::Ok(Instruction::CMP_ABS(to_u16(&args[0], &args[1])?))
-                }),
-
-                // DEC ABS
-                0xce => self.code.replace_with_instr(offset, 2, |args| {
-                    Result::Ok(Instruction::DEC_ABS(to_u16(&args[0], &args[1])?))
-                }),
-
-                // BNE REL
-                0xd0 => self.branch_relative(
-                    offset,
-                    addr,
-                    label_prefix,
-                    addr_to_offset_fn,
-                    offset_to_addr_fn,
-                    &|rel, label| Instruction::BNE_REL(rel, label),
-                ),
-
-                // JAM
-                0xd2 => {
-                    self.code
-                        .replace_with_instr(offset, 0, |_args| Result::Ok(Instruction::JAM))?;
-                    Result::Ok(0)
+            // other code goes here too, with other values
+            if offset != start && self.code.label(offset).is_some() {
+                values.clear();
+            }
+            let len = match self.code.statements()[offset.0].asm_code {
+                AsmCode::DataHexU8(op) => {
+                    let decoded = match self.illegal_opcodes {
+                        IllegalOpcodes::Stop => decode_opcode(op),
+                        _ => decode_opcode(op).or_else(|| decode_illegal_opcode(op)),
+                    };
+                    1 + decoded.map_or(0, |(_, mode)| mode.operand_len())
                 }
+                _ => 1,
+            };
+            if let Option::Some(claimed) = self.code.first_claimed(offset..offset + len) {
+                self.overlap(offset, claimed, &by, mapper)?;
+                break;
+            }
+            self.code.claim(offset, &by);
 
-                // CMP ZP,x
-                0xd5 => self.code.replace_with_instr(offset, 1, |args| {
-                    Result::Ok(Instruction::CMP_ZP_X(args[0].to_u8()?))
-                }),
-
-                // DEC ZP,x
-                0xd6 => self.code.replace_with_instr(offset, 1, |args| {
-                    Result::Ok(Instruction::DEC_ZP_X(args[0].to_u8()?))
-                }),
-
-                // CLD
-                0xd8 => self
-                    .code
-                    .replace_with_instr(offset, 0, |_args| Result::Ok(Instruction::CLD)),
-
-                // CMP ABS,y
-                0xd9 => self.code.replace_with_instr(offset, 2, |args| {
-                    Result::Ok(Instruction::CMP_ABS_Y(to_u16(&args[0], &args[1])?))
-                }),
-
-                // CMP ABS,x
-                0xdd => self.code.replace_with_instr(offset, 2, |args| {
-                    Result::Ok(Instruction::CMP_ABS_X(to_u16(&args[0], &args[1])?))
-                }),
-
-                // DEC ABS,x
-                0xde => self.code.replace_with_instr(offset, 2, |args| {
-                    Result::Ok(Instruction::DEC_ABS_X(to_u16(&args[0], &args[1])?))
-                }),
-
-                // CPX IMM
-                0xe0 => self.code.replace_with_instr(offset, 1, |args| {
-                    Result::Ok(Instruction::CPX_IMM(args[0].to_u8()?))
-                }),
-
-                // CPX ZP
-                0xe4 => self.code.replace_with_instr(offset, 1, |args| {
-                    Result::Ok(Instruction::CPX_ZP(args[0].to_u8()?))
-                }),
-
-                // SBC ZP
-                0xe5 => self.code.replace_with_instr(offset, 1, |args| {
-                    Result::Ok(Instruction::SBC_ZP(args[0].to_u8()?))
-                }),
-
-                // INC ZP
-                0xe6 => self.code.replace_with_instr(offset, 1, |args| {
-                    Result::Ok(Instruction::INC_ZP(args[0].to_u8()?))
-                }),
-
-                // INX
-                0xe8 => self
-                    .code
-                    .replace_with_instr(offset, 0, |_args| Result::Ok(Instruction::INX)),
-
-                // SBC IMM
-                0xe9 => self.code.replace_with_instr(offset, 1, |args| {
-                    Result::Ok(Instruction::SBC_IMM(args[0].to_u8()?))
-                }),
-
-                // INC ABS
-                0xee => self.code.replace_with_instr(offset, 2, |args| {
-                    Result::Ok(Instruction::INC_ABS(to_u16(&args[0], &args[1])?))
-                }),
-
-                // BEQ
-                0xf0 => self.branch_relative(
+            let op = self.code.get_u8(offset)?;
+            if self.quirks.missing_ror && is_ror(op) {
+                self.diagnostics.push(Diagnostic::warning(
                     offset,
-                    addr,
-                    label_prefix,
-                    addr_to_offset_fn,
-                    offset_to_addr_fn,
-                    &|rel, label| Instruction::BEQ_REL(rel, label),
-                ),
-
-                // JAM
-                0xf2 => {
-                    self.code
-                        .replace_with_instr(offset, 0, |_args| Result::Ok(Instruction::JAM))?;
-                    Result::Ok(0)
-                }
-
-                // INC ZP,x
-                0xf6 => self.code.replace_with_instr(offset, 1, |args| {
-                    Result::Ok(Instruction::INC_ZP_X(args[0].to_u8()?))
-                }),
-
-                // SBC ABS,x
-                0xfd => self.code.replace_with_instr(offset, 2, |args| {
-                    Result::Ok(Instruction::SBC_ABS_X(to_u16(&args[0], &args[1])?))
-                }),
-
-                // INC ABS,x
-                0xfe => self.code.replace_with_instr(offset, 2, |args| {
-                    Result::Ok(Instruction::INC_ABS_X(to_u16(&args[0], &args[1])?))
-                }),
-
-                // Other
-                _ => {
-                    // TODO i => return Result::Err(DisassembleError::UnhandledInstruction(i))
-                    println!("unhandled: 0x{:02x}", op);
-                    break;
+                    Option::Some(mapper.to_banked_addr(offset)),
+                    "ror doesn't exist on this CPU, stopped following code".to_string(),
+                ));
+                break;
+            }
+            let bytes = &self.code.data()[offset.0..];
+            let decoded = decode(bytes, addr.0).or_else(|| decode_illegal(bytes, addr.0));
+            let target = decoded.and_then(|d| d.target()).map(CpuAddr);
+            let result = match Instruction::decode(bytes, addr.0) {
+                Option::Some((mut instr, len)) => {
+                    if let (Option::Some(label), Option::Some(target)) = (instr.label_mut(), target)
+                    {
+                        *label = format!("{}_{:04x}", label_prefix, target);
+                    }
+                    // the JAMs aren't in the table
+                    match lookup(op).map_or(Flow::Jam, flow) {
+                        Flow::Next => {
+                            let sed = matches!(instr, Instruction::SED);
+                            let size = self.replace_decoded(offset, len, instr)?;
+                            if sed && self.quirks.no_decimal {
+                                self.code.set_comment(
+                                    offset,
+                                    "no decimal mode on this CPU, adc and sbc stay binary",
+                                );
+                            }
+                            Result::Ok(size)
+                        }
+                        Flow::Stop => self.replace_decoded(offset, len, instr).map(|_| 0),
+                        Flow::Branch => {
+                            self.branch_relative(offset, addr, label_prefix, mapper, instr)
+                        }
+                        Flow::Jump => {
+                            if let Option::Some(target) = target {
+                                set_addr = self
+                                    .rom_target(offset, target, mapper)
+                                    .map(|target_offset| (target, target_offset));
+                            }
+                            if let Option::Some((_, target_offset)) = set_addr {
+                                self.code.add_xref(target_offset, addr.0);
+                            }
+                            self.replace_decoded(offset, len, instr).map(|_| 0)
+                        }
+                        Flow::JumpIndirect => {
+                            let pointer = match instr {
+                                Instruction::JMP_IND(pointer) => CpuAddr(pointer),
+                                _ => unreachable!("jmp (ind) decodes to JMP_IND"),
+                            };
+                            self.replace_decoded(offset, len, instr)?;
+                            self.jump_indirect(offset, pointer, &values, label_prefix, mapper)?;
+                            Result::Ok(0)
+                        }
+                        Flow::Call => {
+                            let result = self.replace_decoded(offset, len, instr);
+                            let target_offset =
+                                target.and_then(|target| self.rom_target(offset, target, mapper));
+                            if let (Option::Some(target), Option::Some(target_offset)) =
+                                (target, target_offset)
+                            {
+                                self.calls.insert(offset, target);
+                                self.code.add_xref(target_offset, addr.0);
+                                self.disassemble(
+                                    target,
+                                    format!("{:04x}", target).as_str(),
+                                    label_prefix,
+                                    mapper,
+                                )?;
+                            }
+                            result
+                        }
+                        Flow::Break => self.brk(offset, label_prefix, mapper),
+                        Flow::Jam => self.jam(offset, op, mapper),
+                    }
                 }
+                Option::None => match decode_illegal(bytes, addr.0) {
+                    Option::Some(instr) if self.illegal_opcodes != IllegalOpcodes::Stop => {
+                        self.illegal_instruction(offset, instr)
+                    }
+                    _ => {
+                        // TODO i => return Result::Err(DisassembleError::UnhandledInstruction(i))
+                        let mut diagnostic = Diagnostic::warning(
+                            offset,
+                            Option::Some(mapper.to_banked_addr(offset)),
+                            format!("unhandled opcode ${:02x}, stopped following code", op),
+                        );
+                        diagnostic.notes = self.preview(offset, mapper);
+                        self.diagnostics.push(diagnostic);
+                        self.unhandled_opcodes.entry(op).or_default().push(offset);
+                        break;
+                    }
+                },
             };
 
+            if let Option::Some(decoded) = decoded {
+                values.step(&decoded);
+            }
+
             match result {
                 Result::Ok(size) => {
                     if size == 0 {
-                        if let Option::Some(new_addr) = set_addr {
-                            offset = addr_to_offset_fn(new_addr);
+                        if let Option::Some((new_addr, new_offset)) = set_addr {
+                            offset = new_offset;
                             addr = new_addr;
                         } else {
                             break;
                         }
                     } else {
                         offset += size;
-                        addr += size as u16;
+                        addr = addr + size as u16;
                     }
                 }
+                Result::Err(err @ DisassembleError::Overlap(_)) => return Result::Err(err),
                 Result::Err(err) => {
                     return Result::Err(DisassembleError::WrappedError(format!(
-                        "{} at offset ${:04x} (addr ${:04x})",
+                        "{} at offset ${:04x} (addr {})\n{}",
                         err,
                         offset,
-                        offset_to_addr_fn(offset)
+                        mapper.to_banked_addr(offset),
+                        self.code.hexdump(offset)
                     )));
                 }
             }
@@ -687,41 +367,474 @@ impl Disassembler {
         return Result::Ok(());
     }
 
-    fn branch_relative<
-        F1: Fn(u16) -> usize,
-        F2: Fn(usize) -> u16,
-        F3: Fn(i8, String) -> Instruction,
-    >(
+    /// Decodes an undocumented opcode the way `illegal_opcodes` asks for. Opcodes
+    /// that assemble back to a different one are always written as bytes.
+    fn illegal_instruction(
         &mut self,
-        offset: usize,
-        addr: u16,
+        offset: FileOffset,
+        instr: DecodedInstruction,
+    ) -> Result<usize, DisassembleError> {
+        let as_bytes = self.illegal_opcodes == IllegalOpcodes::Bytes || !instr.reassembles();
+        let size = self
+            .code
+            .replace_with_instr(offset, instr.size() - 1, |_args| {
+                return Result::Ok(if as_bytes {
+                    Instruction::ILLEGAL_BYTES(instr)
+                } else {
+                    Instruction::ILLEGAL(instr)
+                });
+            })?;
+        if as_bytes {
+            self.code
+                .set_comment(offset, &format!("{} (undocumented opcode)", instr));
+        } else {
+            self.code.set_cpu("6502X");
+        }
+        return Result::Ok(size);
+    }
+
+    /// Handles an opcode halting the CPU the way `jam_opcodes` asks for.
+    fn jam(
+        &mut self,
+        offset: FileOffset,
+        op: u8,
+        mapper: &dyn Mapper,
+    ) -> Result<usize, DisassembleError> {
+        return match self.jam_opcodes {
+            JamOpcodes::Stop => {
+                self.replace_decoded(offset, 1, Instruction::JAM(op))?;
+                self.code.set_cpu("6502X");
+                Result::Ok(0)
+            }
+            JamOpcodes::Continue => {
+                let size = self.replace_decoded(offset, 1, Instruction::JAM_BYTE(op))?;
+                self.code
+                    .set_comment(offset, "jam, halts the CPU if reached");
+                Result::Ok(size)
+            }
+            JamOpcodes::Data => {
+                self.diagnostics.push(Diagnostic::warning(
+                    offset,
+                    Option::Some(mapper.to_banked_addr(offset)),
+                    format!(
+                        "jam opcode ${:02x}, left as data and stopped following code",
+                        op
+                    ),
+                ));
+                Result::Ok(0)
+            }
+        };
+    }
+
+    /// `brk` skips the byte after it, which is written as `.byte` and usually tells
+    /// the handler at the IRQ/BRK vector what to do. The handler is followed and
+    /// `rti` returns past the signature byte, where the code goes on. A `brk` in
+    /// zero fill is where the code ran off into padding instead.
+    fn brk(
+        &mut self,
+        offset: FileOffset,
         label_prefix: &str,
-        addr_to_offset_fn: &F1,
-        offset_to_addr_fn: &F2,
-        to_instruction_fn: &F3,
+        mapper: &dyn Mapper,
     ) -> Result<usize, DisassembleError> {
-        let rel = self.code.get_i8(offset + 1)?;
-        let new_addr = addr.wrapping_add(rel as u16) + 2;
-        let label = format!("{}_{:04x}", label_prefix, new_addr);
-        let result = self.code.replace_with_instr(offset, 1, |_args| {
-            Result::Ok(to_instruction_fn(rel, label.clone()))
+        let signature = self.code.get_u8(offset + 1)?;
+        if self.code.data().get(offset.0 + 1..offset.0 + 3) == Option::Some(&[0, 0]) {
+            self.diagnostics.push(Diagnostic::warning(
+                offset,
+                Option::Some(mapper.to_banked_addr(offset)),
+                "brk in a run of $00, likely fill, stopped following code".to_string(),
+            ));
+            return Result::Ok(0);
+        }
+        let size = self
+            .code
+            .replace_with_instr(offset, 1, |_args| Result::Ok(Instruction::BRK(signature)))?;
+        let data = self.code.data();
+        let handler = match mapper
+            .to_offset(CpuAddr(0xfffe))
+            .and_then(|vector| data.get(vector.0..vector.0 + 2))
+        {
+            Option::Some(&[l, h]) => CpuAddr(((h as u16) << 8) | l as u16),
+            _ => return Result::Ok(size),
+        };
+        self.code.set_comment(
+            offset,
+            &format!("signature ${:02x}, handled at ${:04x}", signature, handler),
+        );
+        if handler.0 >= 0x8000 {
+            self.disassemble(handler, "irq", label_prefix, mapper)?;
+        }
+        return Result::Ok(size);
+    }
+
+    /// Follows `jmp (pointer)` when the pointer is in ROM, or in RAM and `values`
+    /// knows what was stored there. A pointer at $xxff has its high byte read from
+    /// $xx00 since the 6502 doesn't carry into the high byte of the pointer
+    /// address, which is warned about.
+    fn jump_indirect(
+        &mut self,
+        offset: FileOffset,
+        pointer: CpuAddr,
+        values: &KnownValues,
+        label_prefix: &str,
+        mapper: &dyn Mapper,
+    ) -> Result<(), DisassembleError> {
+        let high_addr = CpuAddr((pointer.0 & 0xff00) | (pointer.0.wrapping_add(1) & 0x00ff));
+        let target = if pointer.0 >= 0x8000 {
+            let data = self.code.data();
+            let byte = |addr| mapper.to_offset(addr).and_then(|offset| data.get(offset.0));
+            match (byte(pointer), byte(high_addr)) {
+                (Option::Some(l), Option::Some(h)) => {
+                    Option::Some(CpuAddr(((*h as u16) << 8) | *l as u16))
+                }
+                _ => Option::None,
+            }
+        } else {
+            match (values.read(pointer.0), values.read(high_addr.0)) {
+                (Option::Some(l), Option::Some(h)) => {
+                    let target = CpuAddr(((h as u16) << 8) | l as u16);
+                    self.code.add_comment(
+                        offset,
+                        &format!("jumps to ${:04x}, the pointer is set right before", target),
+                    );
+                    Option::Some(target)
+                }
+                _ => Option::None,
+            }
+        };
+
+        if pointer.0 & 0xff == 0xff {
+            let mut message = format!(
+                "jmp (${:04x}) reads the high byte of the target from ${:04x}, not ${:04x} \
+                 (6502 page wrap bug)",
+                pointer,
+                high_addr,
+                pointer.0.wrapping_add(1)
+            );
+            if let Option::Some(target) = target {
+                message = format!("{}, jumps to ${:04x}", message, target);
+            }
+            self.code.add_comment(offset, &message);
+            self.diagnostics.push(Diagnostic::warning(
+                offset,
+                Option::Some(mapper.to_banked_addr(offset)),
+                message,
+            ));
+        }
+
+        if target.is_none() {
+            self.unresolved_jumps.insert(offset, pointer);
+            let mut diagnostic = Diagnostic::warning(
+                offset,
+                Option::Some(mapper.to_banked_addr(offset)),
+                format!(
+                    "jmp (${:04x}) goes where a pointer in RAM points, stopped following code",
+                    pointer
+                ),
+            );
+            diagnostic.notes = self.preview(offset + 3, mapper);
+            self.diagnostics.push(diagnostic);
+        }
+
+        let target_offset = target.and_then(|target| mapper.to_offset(target));
+        if let (Option::Some(target), Option::Some(target_offset)) = (target, target_offset) {
+            self.code.add_xref(target_offset, mapper.to_addr(offset).0);
+            // nothing refers to the target by name, so a label it already has, an
+            // entry point's say, is kept rather than renamed after the jump
+            let label = self.code.label(target_offset).map(str::to_string);
+            if !self.code.is_instruction(target_offset) {
+                self.disassemble(
+                    target,
+                    format!("{:04x}", target).as_str(),
+                    label_prefix,
+                    mapper,
+                )?;
+            }
+            if let Option::Some(label) = label {
+                self.code.set_label(target_offset, &label);
+            }
+        }
+        return Result::Ok(());
+    }
+
+    /// Where `target`, jumped, branched or called to from `offset`, is in the file,
+    /// warning that the code there isn't followed if it isn't in it.
+    fn rom_target(
+        &mut self,
+        offset: FileOffset,
+        target: CpuAddr,
+        mapper: &dyn Mapper,
+    ) -> Option<FileOffset> {
+        let target_offset = mapper.to_offset(target);
+        if target_offset.is_none() {
+            self.diagnostics.push(Diagnostic::warning(
+                offset,
+                Option::Some(mapper.to_banked_addr(offset)),
+                format!(
+                    "${:04x} isn't in the ROM, the code there isn't followed",
+                    target
+                ),
+            ));
+        }
+        return target_offset;
+    }
+
+    /// What the bytes from `offset` on would decode to, one line per instruction, for
+    /// judging from a diagnostic whether tracing stopped before more code.
+    fn preview(&self, offset: FileOffset, mapper: &dyn Mapper) -> Vec<String> {
+        let data = self.code.data();
+        let mut lines = Vec::new();
+        let mut offset = offset;
+        while lines.len() < PREVIEW_LENGTH && offset.0 < data.len() {
+            let addr = mapper.to_addr(offset);
+            let bytes = &data[offset.0..];
+            let (text, len) = match decode(bytes, addr.0) {
+                Option::Some(instr) => (instr.to_string(), instr.size()),
+                Option::None => match decode_illegal(bytes, addr.0) {
+                    Option::Some(instr) => (format!("{} (undocumented)", instr), instr.size()),
+                    Option::None => (format!(".byte ${:02x}", bytes[0]), 1),
+                },
+            };
+            let hex = bytes[..len]
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect::<Vec<String>>()
+                .join(" ");
+            lines.push(format!("${:04x}: {:<9} {}", addr, hex, text));
+            offset += len;
+        }
+        return lines;
+    }
+
+    /// Puts `instr`, `len` bytes long, in place of the bytes at `offset`, which
+    /// have to still be data.
+    fn replace_decoded(
+        &mut self,
+        offset: FileOffset,
+        len: usize,
+        instr: Instruction,
+    ) -> Result<usize, DisassembleError> {
+        return self.code.replace_with_instr(offset, len - 1, |args| {
+            for arg in &args {
+                arg.to_u8()?;
+            }
+            return Result::Ok(instr);
         });
+    }
+
+    fn branch_relative(
+        &mut self,
+        offset: FileOffset,
+        addr: CpuAddr,
+        label_prefix: &str,
+        mapper: &dyn Mapper,
+        instr: Instruction,
+    ) -> Result<usize, DisassembleError> {
+        let rel = self.code.get_i8(offset + 1)?;
+        let new_addr = addr.branch_target(rel);
+
+        // moving code between these and their target puts it out of reach
+        if rel == i8::MAX || rel == i8::MIN {
+            self.code.add_comment(
+                offset,
+                format!("branch at the edge of its range ({:+} bytes)", rel).as_str(),
+            );
+        }
+
+        let result = self.replace_decoded(offset, 2, instr);
+        let new_offset = match self.rom_target(offset, new_addr, mapper) {
+            Option::Some(new_offset) => new_offset,
+            Option::None => return result,
+        };
+
+        // a branch can cross from one mirror of a bank into another (e.g. from $c010
+        // back to $bff0 on a 16k rom). It works on hardware but the label ends up on
+        // the other side of the bank so reassembling the branch will be out of range.
+        if new_offset - offset != new_addr - addr {
+            let message = format!(
+                "branch to ${:04x} only reaches its target through bank mirroring",
+                new_addr
+            );
+            self.code.set_comment(offset, message.as_str());
+            self.diagnostics.push(Diagnostic::warning(
+                offset,
+                Option::Some(mapper.to_banked_addr(offset)),
+                message,
+            ));
+        }
+        self.code.add_xref(new_offset, addr.0);
 
         // disassemble jump address
         self.disassemble(
             new_addr,
             format!("{:04x}", new_addr).as_str(),
             label_prefix,
-            addr_to_offset_fn,
-            offset_to_addr_fn,
+            mapper,
         )?;
 
         return result;
     }
 }
 
-fn to_u16(arg0: &AsmCode, arg1: &AsmCode) -> Result<u16, DisassembleError> {
-    let l = arg0.to_u8()? as u16;
-    let h = arg1.to_u8()? as u16;
-    return Result::Ok((h << 8) | l);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disassemble::address::BankedAddr;
+    use crate::opcodes::OPCODES;
+
+    /// The bytes traced mapped from $8000 on.
+    struct FlatMapper;
+
+    impl Mapper for FlatMapper {
+        fn to_offset(&self, addr: CpuAddr) -> Option<FileOffset> {
+            return (addr.0 as usize).checked_sub(0x8000).map(FileOffset);
+        }
+
+        fn to_addr(&self, offset: FileOffset) -> CpuAddr {
+            return CpuAddr(0x8000 + offset.0 as u16);
+        }
+
+        fn to_banked_addr(&self, offset: FileOffset) -> BankedAddr {
+            return BankedAddr {
+                bank: 0,
+                addr: self.to_addr(offset),
+            };
+        }
+    }
+
+    /// `code` traced from its start, padded so nothing runs off the end.
+    fn traced(code: &[u8]) -> Disassembler {
+        return traced_with_jam(code, JamOpcodes::default());
+    }
+
+    fn traced_with_jam(code: &[u8], jam_opcodes: JamOpcodes) -> Disassembler {
+        let mut data = code.to_vec();
+        data.resize(code.len() + 8, 0xff);
+        let mut d = Disassembler::new(data);
+        d.jam_opcodes = jam_opcodes;
+        d.disassemble(CpuAddr(0x8000), "reset", "test", &FlatMapper)
+            .unwrap();
+        return d;
+    }
+
+    fn instructions(d: &Disassembler) -> Vec<usize> {
+        return (0..d.code.data().len())
+            .filter(|offset| d.code.is_instruction(FileOffset(*offset)))
+            .collect();
+    }
+
+    #[test]
+    fn test_flow() {
+        let flow_of = |op: u8| lookup(op).map_or(Flow::Jam, flow);
+        assert_eq!(flow_of(0xa9), Flow::Next); // lda #
+        assert_eq!(flow_of(0x60), Flow::Stop); // rts
+        assert_eq!(flow_of(0x40), Flow::Stop); // rti
+        assert_eq!(flow_of(0xd0), Flow::Branch); // bne
+        assert_eq!(flow_of(0x4c), Flow::Jump); // jmp abs
+        assert_eq!(flow_of(0x6c), Flow::JumpIndirect); // jmp (ind)
+        assert_eq!(flow_of(0x20), Flow::Call); // jsr
+        assert_eq!(flow_of(0x00), Flow::Break); // brk
+        assert_eq!(flow_of(0x02), Flow::Jam);
+    }
+
+    #[test]
+    fn test_flow_next() {
+        // lda #$01, ora ($10,x), tax, rts
+        let d = traced(&[0xa9, 0x01, 0x01, 0x10, 0xaa, 0x60]);
+        assert_eq!(instructions(&d), vec![0, 2, 4, 5]);
+    }
+
+    #[test]
+    fn test_flow_stop() {
+        // rts, lda #$01
+        let d = traced(&[0x60, 0xa9, 0x01]);
+        assert_eq!(instructions(&d), vec![0]);
+    }
+
+    #[test]
+    fn test_flow_branch() {
+        // bne past the rts to another rts, both followed
+        let d = traced(&[0xd0, 0x01, 0x60, 0x60]);
+        assert_eq!(instructions(&d), vec![0, 2, 3]);
+        assert_eq!(d.code.label(FileOffset(3)), Option::Some("test_8003"));
+    }
+
+    #[test]
+    fn test_flow_jump() {
+        // jmp over a byte of data to an rts
+        let d = traced(&[0x4c, 0x04, 0x80, 0xff, 0x60]);
+        assert_eq!(instructions(&d), vec![0, 4]);
+        assert!(d.code.xrefs()[&FileOffset(4)].contains(&0x8000));
+    }
+
+    fn written(d: &Disassembler) -> String {
+        let mut out = Vec::new();
+        d.code.write(&mut out).unwrap();
+        return String::from_utf8(out).unwrap();
+    }
+
+    #[test]
+    fn test_flow_jam() {
+        // jam stops the code, the lda after it isn't followed
+        let d = traced(&[0x02, 0xa9, 0x01, 0x60]);
+        assert_eq!(instructions(&d), vec![0]);
+        let out = written(&d);
+        assert!(out.starts_with(".setcpu \"6502X\"\n"), "{}", out);
+        assert!(out.contains("    jam\n"), "{}", out);
+        assert!(d.diagnostics.is_empty());
+    }
+
+    #[test]
+    fn test_flow_jam_continue() {
+        // the jam is a byte and the lda after it is followed
+        let d = traced_with_jam(&[0x02, 0xa9, 0x01, 0x60], JamOpcodes::Continue);
+        assert_eq!(instructions(&d), vec![0, 1, 3]);
+        let out = written(&d);
+        assert!(!out.contains(".setcpu"), "{}", out);
+        assert!(
+            out.contains(".byte $02") && out.contains("; jam, halts the CPU if reached"),
+            "{}",
+            out
+        );
+        assert!(d.diagnostics.is_empty());
+    }
+
+    #[test]
+    fn test_flow_jam_data() {
+        // the jam stays data and stops the code with a warning
+        let d = traced_with_jam(&[0x02, 0xa9, 0x01, 0x60], JamOpcodes::Data);
+        assert!(instructions(&d).is_empty());
+        assert!(matches!(
+            d.code.statements()[0].asm_code,
+            AsmCode::DataHexU8(0x02)
+        ));
+        assert_eq!(d.diagnostics.len(), 1);
+        assert_eq!(
+            d.diagnostics[0].message,
+            "jam opcode $02, left as data and stopped following code"
+        );
+    }
+
+    #[test]
+    fn test_decode_agrees_with_instruction_decode() {
+        for opcode in OPCODES {
+            // brk's signature byte is decoded with it
+            if opcode.opcode == 0x00 {
+                continue;
+            }
+            let bytes = [opcode.opcode, 0x34, 0x12];
+            // undocumented opcodes are only decoded as asked for by `illegal_opcodes`
+            if opcode.illegal {
+                assert!(Instruction::decode(&bytes, 0x8000).is_none());
+                assert!(decode_illegal(&bytes, 0x8000).is_some());
+                continue;
+            }
+            let decoded = decode(&bytes, 0x8000).unwrap();
+            let (instr, len) = Instruction::decode(&bytes, 0x8000)
+                .unwrap_or_else(|| panic!("${:02x} isn't decoded", opcode.opcode));
+            assert_eq!(len, decoded.size(), "{}", decoded);
+            // the accumulator of `asl a` and the like goes without saying
+            let expected = decoded.to_string();
+            assert_eq!(instr.to_string(), expected.trim_end_matches(" a"));
+        }
+    }
 }
@@ -0,0 +1,377 @@
+use std::{io::Write, str::FromStr};
+
+use crate::decode::{AddressingMode, DecodedInstruction};
+
+use super::{arithmetic::find_pointer_setups, loops::find_counted_loops};
+
+/// PPU registers the pattern tables are written through
+const PPUSTATUS: u16 = 0x2002;
+const PPUADDR: u16 = 0x2006;
+const PPUDATA: u16 = 0x2007;
+/// PPU addresses past the pattern tables
+const PATTERN_TABLES_END: u8 = 0x20;
+
+/// How the bitplanes of a tile follow each other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interleave {
+    /// every row of one bitplane before the next bitplane, as the NES does
+    Planar,
+    /// the rows of two bitplanes alternating, a pair at a time, as the Game Boy
+    /// and the SNES do
+    RowPairs,
+}
+
+/// The layout of the tiles in a graphics region.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TileFormat {
+    pub bits_per_pixel: usize,
+    /// in pixels, a multiple of 8
+    pub width: usize,
+    pub height: usize,
+    pub interleave: Interleave,
+}
+
+impl TileFormat {
+    /// 8x8 tiles with 2 bits a pixel, the low bitplane first
+    pub const NES: TileFormat = TileFormat {
+        bits_per_pixel: 2,
+        width: 8,
+        height: 8,
+        interleave: Interleave::Planar,
+    };
+    pub const GAME_BOY: TileFormat = TileFormat {
+        bits_per_pixel: 2,
+        width: 8,
+        height: 8,
+        interleave: Interleave::RowPairs,
+    };
+    pub const SNES_4BPP: TileFormat = TileFormat {
+        bits_per_pixel: 4,
+        width: 8,
+        height: 8,
+        interleave: Interleave::RowPairs,
+    };
+    /// 8x8 tiles with a bit a pixel, e.g. C64 characters
+    pub const MONO: TileFormat = TileFormat {
+        bits_per_pixel: 1,
+        width: 8,
+        height: 8,
+        interleave: Interleave::Planar,
+    };
+
+    /// bytes each tile takes
+    pub fn tile_len(&self) -> usize {
+        return self.width / 8 * self.height * self.bits_per_pixel;
+    }
+
+    /// The color index of each pixel of `tile`, row by row.
+    pub fn pixels(&self, tile: &[u8]) -> Vec<u8> {
+        let row_len = self.width / 8;
+        let mut pixels = Vec::with_capacity(self.width * self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let mut color = 0;
+                for plane in 0..self.bits_per_pixel {
+                    let index = match self.interleave {
+                        Interleave::Planar => (plane * self.height + y) * row_len,
+                        Interleave::RowPairs => {
+                            ((plane / 2 * self.height + y) * 2 + plane % 2) * row_len
+                        }
+                    } + x / 8;
+                    let bit = tile.get(index).map_or(0, |b| (b >> (7 - x % 8)) & 1);
+                    color |= bit << plane;
+                }
+                pixels.push(color);
+            }
+        }
+        return pixels;
+    }
+}
+
+impl FromStr for TileFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        return match s {
+            "nes" => Result::Ok(TileFormat::NES),
+            "gb" => Result::Ok(TileFormat::GAME_BOY),
+            "snes4" => Result::Ok(TileFormat::SNES_4BPP),
+            "mono" => Result::Ok(TileFormat::MONO),
+            _ => Result::Err(format!(
+                "unknown tile format \"{}\", expected nes, gb, snes4 or mono",
+                s
+            )),
+        };
+    }
+}
+
+/// tiles a row of the image [`write_tiles`] writes
+const TILES_PER_ROW: usize = 16;
+
+/// Writes the whole tiles of `data` as a binary PGM image, 16 tiles a row, each
+/// pixel its color index with white the highest.
+pub fn write_tiles(out: &mut dyn Write, data: &[u8], format: &TileFormat) -> std::io::Result<()> {
+    let tiles = tile_offsets(data.len(), format)
+        .map(|offset| format.pixels(&data[offset..offset + format.tile_len()]))
+        .collect::<Vec<Vec<u8>>>();
+    let width = TILES_PER_ROW * format.width;
+    let rows = tiles.len().div_ceil(TILES_PER_ROW);
+    write!(
+        out,
+        "P5\n{} {}\n{}\n",
+        width,
+        rows * format.height,
+        (1 << format.bits_per_pixel) - 1
+    )?;
+    for row in 0..rows {
+        for y in 0..format.height {
+            let mut line = Vec::with_capacity(width);
+            for column in 0..TILES_PER_ROW {
+                match tiles.get(row * TILES_PER_ROW + column) {
+                    Option::Some(pixels) => {
+                        line.extend_from_slice(&pixels[y * format.width..(y + 1) * format.width])
+                    }
+                    Option::None => line.resize(line.len() + format.width, 0),
+                }
+            }
+            out.write_all(&line)?;
+        }
+    }
+    return Result::Ok(());
+}
+
+/// Offsets into a graphics region of `len` bytes of its whole tiles. Bytes after
+/// the last whole one aren't a tile.
+pub fn tile_offsets(len: usize, format: &TileFormat) -> impl Iterator<Item = usize> {
+    let tile_len = format.tile_len().max(1);
+    return (0..len / tile_len * tile_len).step_by(tile_len);
+}
+
+/// Tiles a loop copies from ROM to CHR RAM through PPUDATA.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChrCopy {
+    /// index of the store to PPUDATA
+    pub store: usize,
+    /// address the tiles are read from
+    pub src: u16,
+    pub len: usize,
+}
+
+/// Registers and the PPU address as far as immediate loads tell.
+#[derive(Default)]
+struct PpuState {
+    a: Option<u8>,
+    x: Option<u8>,
+    y: Option<u8>,
+    /// whether the next store to PPUADDR is the high byte
+    low_next: bool,
+    /// whether the address last stored to PPUADDR is in the pattern tables
+    pattern_tables: bool,
+}
+
+impl PpuState {
+    fn step(&mut self, instr: &DecodedInstruction) {
+        let immediate = match instr.mode {
+            AddressingMode::Immediate => Option::Some(instr.operand as u8),
+            _ => Option::None,
+        };
+        // reading PPUSTATUS makes the next store to PPUADDR the high byte
+        let reads_status = matches!(instr.mnemonic, "lda" | "ldx" | "ldy" | "bit");
+        if reads_status && instr.mode == AddressingMode::Absolute && instr.operand == PPUSTATUS {
+            self.low_next = false;
+        }
+        match instr.mnemonic {
+            "lda" => self.a = immediate,
+            "ldx" => self.x = immediate,
+            "ldy" => self.y = immediate,
+            "txa" => self.a = self.x,
+            "tya" => self.a = self.y,
+            "tax" => self.x = self.a,
+            "tay" => self.y = self.a,
+            "inx" | "dex" | "tsx" => self.x = Option::None,
+            "iny" | "dey" => self.y = Option::None,
+            "adc" | "sbc" | "and" | "ora" | "eor" | "pla" => self.a = Option::None,
+            "asl" | "lsr" | "rol" | "ror" if instr.mode == AddressingMode::Accumulator => {
+                self.a = Option::None
+            }
+            _ => {}
+        }
+        let stored = match instr.mnemonic {
+            "sta" => self.a,
+            "stx" => self.x,
+            "sty" => self.y,
+            _ => return,
+        };
+        if instr.mode == AddressingMode::Absolute && instr.operand == PPUADDR {
+            if !self.low_next {
+                self.pattern_tables = stored.is_some_and(|high| high < PATTERN_TABLES_END);
+            }
+            self.low_next = !self.low_next;
+        }
+    }
+}
+
+/// Finds loops in `instrs`, which follow each other in memory, copying tiles
+/// from ROM to the pattern tables with `lda table,x` or `lda (pointer),y` and
+/// `sta PPUDATA`. A table is as long as the loop counts, a pointer is set up a
+/// byte at a time and copies 256 bytes, or X pages when `inc pointer+1 / dex /
+/// bne` follows.
+pub fn find_chr_ram_copies(instrs: &[DecodedInstruction]) -> Vec<ChrCopy> {
+    let loops = find_counted_loops(instrs);
+    let pointers = find_pointer_setups(instrs);
+    let mut state = PpuState::default();
+    let mut result = Vec::new();
+    for (i, instr) in instrs.iter().enumerate() {
+        let copies_tiles = state.pattern_tables
+            && instr.mnemonic == "sta"
+            && instr.mode == AddressingMode::Absolute
+            && instr.operand == PPUDATA
+            && i > 0
+            && instrs[i - 1].mnemonic == "lda";
+        let pages = state.x;
+        state.step(instr);
+        if !copies_tiles {
+            continue;
+        }
+        let read = &instrs[i - 1];
+        let found = match read.mode {
+            AddressingMode::AbsoluteX | AddressingMode::AbsoluteY => loops
+                .iter()
+                .filter(|l| l.head < i && i < l.branch)
+                .flat_map(|l| &l.tables)
+                .find(|table| table.addr == read.operand)
+                .map(|table| (table.addr, table.len)),
+            AddressingMode::IndirectY => {
+                let pointer = read.operand as u8;
+                let setup = pointers
+                    .iter()
+                    .rfind(|setup| setup.pointer == pointer && setup.high.max(setup.low) < i);
+                let is = |at: usize, mnemonic: &str| {
+                    instrs
+                        .get(at)
+                        .is_some_and(|instr| instr.mnemonic == mnemonic)
+                };
+                let pages = if !(is(i + 1, "iny") && is(i + 2, "bne")) {
+                    Option::None
+                } else if is(i + 3, "inc")
+                    && instrs[i + 3].operand == pointer as u16 + 1
+                    && is(i + 4, "dex")
+                    && is(i + 5, "bne")
+                {
+                    pages.filter(|pages| *pages > 0)
+                } else {
+                    Option::Some(1)
+                };
+                setup
+                    .zip(pages)
+                    .map(|(setup, pages)| (setup.target, pages as usize * 256))
+            }
+            _ => Option::None,
+        };
+        if let Option::Some((src, len)) = found {
+            result.push(ChrCopy { store: i, src, len });
+        }
+    }
+    return result;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pixels() {
+        // the top row of a tile with colors 0 to 3, the rest left blank
+        let mut nes = [0; 16];
+        nes[0] = 0b0101_0000;
+        nes[8] = 0b0011_0000;
+        let pixels = TileFormat::NES.pixels(&nes);
+        assert_eq!(pixels[..4], [0, 1, 2, 3]);
+        assert!(pixels[8..].iter().all(|p| *p == 0));
+
+        let mut game_boy = [0; 16];
+        game_boy[0] = 0b0101_0000;
+        game_boy[1] = 0b0011_0000;
+        assert_eq!(TileFormat::GAME_BOY.pixels(&game_boy), pixels);
+
+        // the third bitplane comes after both rows of the first two
+        let mut snes = [0; 32];
+        snes[16] = 0b1000_0000;
+        assert_eq!(TileFormat::SNES_4BPP.pixels(&snes)[0], 4);
+        assert_eq!(TileFormat::SNES_4BPP.tile_len(), 32);
+        assert_eq!(TileFormat::MONO.tile_len(), 8);
+    }
+
+    fn decode_all(code: &[u8]) -> Vec<DecodedInstruction> {
+        let mut instrs = Vec::new();
+        let mut offset = 0;
+        while offset < code.len() {
+            let instr = crate::decode::decode(&code[offset..], 0x8000 + offset as u16).unwrap();
+            offset += instr.size();
+            instrs.push(instr);
+        }
+        return instrs;
+    }
+
+    #[test]
+    fn test_find_chr_ram_copies() {
+        // lda #$00 / sta $00 / lda #$90 / sta $01 / lda #$00 / sta PPUADDR / sta PPUADDR /
+        // ldy #$00 / ldx #$02 / lda ($00),y / sta PPUDATA / iny / bne -8 / inc $01 /
+        // dex / bne -13
+        let code = [
+            0xa9, 0x00, 0x85, 0x00, 0xa9, 0x90, 0x85, 0x01, 0xa9, 0x00, 0x8d, 0x06, 0x20, 0x8d,
+            0x06, 0x20, 0xa0, 0x00, 0xa2, 0x02, 0xb1, 0x00, 0x8d, 0x07, 0x20, 0xc8, 0xd0, 0xf8,
+            0xe6, 0x01, 0xca, 0xd0, 0xf3,
+        ];
+        let found = find_chr_ram_copies(&decode_all(&code));
+        assert_eq!(
+            found,
+            [ChrCopy {
+                store: 10,
+                src: 0x9000,
+                len: 512
+            }]
+        );
+
+        // the same loop filling a nametable isn't copying tiles
+        let mut nametable = code;
+        nametable[9] = 0x20;
+        assert!(find_chr_ram_copies(&decode_all(&nametable)).is_empty());
+
+        // lda #$10 / sta PPUADDR / lda #$00 / sta PPUADDR / ldx #$00 / lda $9000,x /
+        // sta PPUDATA / inx / cpx #$20 / bne -11
+        let found = find_chr_ram_copies(&decode_all(&[
+            0xa9, 0x10, 0x8d, 0x06, 0x20, 0xa9, 0x00, 0x8d, 0x06, 0x20, 0xa2, 0x00, 0xbd, 0x00,
+            0x90, 0x8d, 0x07, 0x20, 0xe8, 0xe0, 0x20, 0xd0, 0xf5,
+        ]));
+        assert_eq!(found.len(), 1);
+        assert_eq!((found[0].src, found[0].len), (0x9000, 32));
+    }
+
+    #[test]
+    fn test_write_tiles() {
+        // two mono tiles, the first with its top left pixel set, and a partial one
+        let mut data = [0; 20];
+        data[0] = 0b1000_0000;
+        data[15] = 0b0000_0001;
+        let mut out = Vec::new();
+        write_tiles(&mut out, &data, &"mono".parse().unwrap()).unwrap();
+        let header = b"P5\n128 8\n1\n";
+        assert_eq!(out[..header.len()], header[..]);
+        let pixels = &out[header.len()..];
+        assert_eq!(pixels.len(), 128 * 8);
+        assert_eq!(pixels[0], 1);
+        // the last row of the second tile, its rightmost pixel
+        assert_eq!(pixels[7 * 128 + 15], 1);
+        assert_eq!(pixels.iter().filter(|p| **p != 0).count(), 2);
+        assert!("c64".parse::<TileFormat>().is_err());
+    }
+
+    #[test]
+    fn test_tile_offsets() {
+        let offsets = tile_offsets(40, &TileFormat::NES).collect::<Vec<usize>>();
+        assert_eq!(offsets, [0, 16]);
+        let offsets = tile_offsets(16, &TileFormat::MONO).collect::<Vec<usize>>();
+        assert_eq!(offsets, [0, 8]);
+    }
+}
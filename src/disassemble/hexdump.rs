@@ -0,0 +1,45 @@
+const WINDOW_LENGTH: usize = 16;
+
+/// Hexdump of the 16 bytes around `offset` with a caret under the byte at
+/// `offset`, for pointing at the input in error messages.
+pub fn hexdump(data: &[u8], offset: usize) -> String {
+    if data.is_empty() {
+        return String::new();
+    }
+    let offset = offset.min(data.len() - 1);
+    let start = offset
+        .saturating_sub(WINDOW_LENGTH / 2)
+        .min(data.len().saturating_sub(WINDOW_LENGTH));
+    let end = (start + WINDOW_LENGTH).min(data.len());
+
+    let prefix = format!("  ${:06x}: ", start);
+    let bytes = data[start..end]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<Vec<String>>()
+        .join(" ");
+    let caret_column = prefix.len() + (offset - start) * 3;
+    return format!("{}{}\n{}^^", prefix, bytes, " ".repeat(caret_column));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hexdump() {
+        let data = (0..32).collect::<Vec<u8>>();
+        assert_eq!(
+            hexdump(&data, 10),
+            "  $000002: 02 03 04 05 06 07 08 09 0a 0b 0c 0d 0e 0f 10 11\n                                   ^^"
+        );
+        assert_eq!(
+            hexdump(&data, 1),
+            "  $000000: 00 01 02 03 04 05 06 07 08 09 0a 0b 0c 0d 0e 0f\n              ^^"
+        );
+        assert_eq!(
+            hexdump(&data[0..3], 2),
+            "  $000000: 00 01 02\n                 ^^"
+        );
+    }
+}
@@ -0,0 +1,201 @@
+//! The disassembly as a standalone HTML page for browsing, each label it uses a
+//! link to where it is defined.
+
+use itertools::Itertools;
+use std::{collections::HashSet, io::Write};
+
+use super::{code::JsonStatement, DisassembleError};
+
+const STYLE: &str = "\
+body { background: #fdfdfd; color: #222; font-family: monospace; }
+.line { white-space: pre; }
+.line:target, .line:hover { background: #fff3c4; }
+.addr { color: #888; }
+.bytes { color: #aaa; }
+.segment { color: #6a3d9a; font-weight: bold; margin-top: 1em; }
+.label { color: #1f4e9c; font-weight: bold; }
+.code .asm { color: #222; }
+.data .asm { color: #a35a00; }
+.comment { color: #2e7d32; }
+.operand { border-bottom: 1px dotted #888; }
+a { color: #1f4e9c; text-decoration: none; }
+a:hover { text-decoration: underline; }
+";
+
+/// bytes of a statement shown, as in a listing
+const LISTING_BYTES: usize = 3;
+/// width of the bytes column, `A9 00 00+`
+const BYTES_WIDTH: usize = LISTING_BYTES * 3;
+
+fn escape(s: &str) -> String {
+    return s
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;");
+}
+
+/// `asm` with each of `labels` in it a link to the label.
+fn link_labels(asm: &str, labels: &HashSet<&str>) -> String {
+    let mut result = String::new();
+    let mut rest = asm;
+    while let Option::Some(start) = rest.find(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        let (before, word) = rest.split_at(start);
+        let len = word
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(word.len());
+        let (word, next) = word.split_at(len);
+        result += &escape(before);
+        // the letters of `$A0` and `$0A` aren't a name
+        let number = before.ends_with(|c: char| c == '$' || c == '%' || c.is_ascii_digit());
+        match !number && labels.contains(word) {
+            true => result += &format!("<a href=\"#{}\">{}</a>", word, word),
+            false => result += &escape(word),
+        }
+        rest = next;
+    }
+    result += &escape(rest);
+    return result;
+}
+
+/// Writes `statements` as a page titled `title`, each instruction operand showing
+/// the address `operand_addr` resolves it to when hovered.
+pub fn write_html(
+    out: &mut dyn Write,
+    title: &str,
+    statements: &[JsonStatement],
+    operand_addr: impl Fn(&JsonStatement) -> Option<u16>,
+) -> Result<(), DisassembleError> {
+    let labels = statements
+        .iter()
+        .filter_map(|stmt| stmt.label.as_deref())
+        .collect::<HashSet<&str>>();
+    writeln!(out, "<!DOCTYPE html>")?;
+    writeln!(out, "<html>")?;
+    writeln!(out, "<head>")?;
+    writeln!(out, "<meta charset=\"utf-8\">")?;
+    writeln!(out, "<title>{}</title>", escape(title))?;
+    writeln!(out, "<style>\n{}</style>", STYLE)?;
+    writeln!(out, "</head>")?;
+    writeln!(out, "<body>")?;
+    let mut segment = Option::None;
+    for stmt in statements {
+        if stmt.segment.is_some() && stmt.segment != segment {
+            segment = stmt.segment.clone();
+            writeln!(
+                out,
+                "<div class=\"line segment\">{}</div>",
+                escape(stmt.segment.as_deref().unwrap_or_default())
+            )?;
+        }
+        let bytes = stmt.bytes.split(' ').collect::<Vec<&str>>();
+        let mut shown = bytes.iter().take(LISTING_BYTES).join(" ");
+        if bytes.len() > LISTING_BYTES {
+            shown += "+";
+        }
+        let columns = format!(
+            "<span class=\"addr\">{:04X}</span>  <span class=\"bytes\">{:<width$}</span> ",
+            stmt.addr,
+            shown,
+            width = BYTES_WIDTH
+        );
+        if let Option::Some(label) = &stmt.label {
+            writeln!(
+                out,
+                "<div class=\"line\" id=\"{}\">{}<span class=\"label\">{}:</span></div>",
+                escape(label),
+                " ".repeat(6 + BYTES_WIDTH + 1),
+                escape(label)
+            )?;
+        }
+        let asm = match (&stmt.mnemonic, &stmt.operands) {
+            (Option::Some(mnemonic), Option::Some(operands)) => {
+                let operands = link_labels(operands, &labels);
+                let operands = match operand_addr(stmt) {
+                    Option::Some(addr) => format!(
+                        "<span class=\"operand\" title=\"${:04X}\">{}</span>",
+                        addr, operands
+                    ),
+                    Option::None => operands,
+                };
+                format!("    {} {}", escape(mnemonic), operands)
+            }
+            (Option::Some(mnemonic), Option::None) => format!("    {}", escape(mnemonic)),
+            _ => link_labels(&stmt.asm, &labels),
+        };
+        let comment = match &stmt.comment {
+            Option::Some(comment) => format!(
+                " <span class=\"comment\">; {}</span>",
+                escape(&comment.replace('\n', " "))
+            ),
+            Option::None => String::new(),
+        };
+        let kind = match stmt.mnemonic.is_some() {
+            true => "code",
+            false => "data",
+        };
+        writeln!(
+            out,
+            "<div class=\"line {}\">{}<span class=\"asm\">{}</span>{}</div>",
+            kind, columns, asm, comment
+        )?;
+    }
+    writeln!(out, "</body>")?;
+    writeln!(out, "</html>")?;
+    return Result::Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn statement(addr: u16, label: Option<&str>, asm: &str) -> JsonStatement {
+        let (mnemonic, operands) = match asm.starts_with('.') {
+            true => (Option::None, Option::None),
+            false => match asm.split_once(' ') {
+                Option::Some((m, o)) => (Option::Some(m.to_string()), Option::Some(o.to_string())),
+                Option::None => (Option::Some(asm.to_string()), Option::None),
+            },
+        };
+        return JsonStatement {
+            offset: addr as usize - 0x8000 + 16,
+            addr,
+            bytes: String::new(),
+            segment: Option::Some("PRGROM0".to_string()),
+            label: label.map(str::to_string),
+            asm: asm.to_string(),
+            mnemonic,
+            operands,
+            comment: Option::None,
+            reached_from: Vec::new(),
+        };
+    }
+
+    #[test]
+    fn test_write_html() {
+        let statements = [
+            statement(0x8000, Option::Some("reset"), "jmp reset"),
+            statement(0x8003, Option::Some("table"), ".word reset, $A0"),
+            statement(0x8007, Option::None, "lda #<table"),
+        ];
+        let mut out = Vec::new();
+        write_html(&mut out, "a <test>", &statements, |stmt| match stmt.addr {
+            0x8000 => Option::Some(0x8000),
+            _ => Option::None,
+        })
+        .unwrap();
+        let html = String::from_utf8(out).unwrap();
+        assert!(html.contains("<title>a &lt;test&gt;</title>"));
+        assert!(html.contains("<div class=\"line segment\">PRGROM0</div>"));
+        assert!(html.contains(" id=\"reset\">"));
+        assert!(html.contains(
+            "<span class=\"operand\" title=\"$8000\"><a href=\"#reset\">reset</a></span>"
+        ));
+        assert!(html.contains(
+            "<div class=\"line data\">\
+             <span class=\"addr\">8003</span>  <span class=\"bytes\">         </span> \
+             <span class=\"asm\">.word <a href=\"#reset\">reset</a>, $A0</span></div>"
+        ));
+        assert!(html.contains("#&lt;<a href=\"#table\">table</a>"));
+    }
+}
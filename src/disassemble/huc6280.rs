@@ -0,0 +1,154 @@
+use crate::decode::AddressingMode;
+use crate::decode_huc6280::{DecodedHuC6280, ModeHuC6280};
+
+/// Bank of the hardware registers, mapped to $0000-$1fff through MPR0 by
+/// practically every game.
+pub const IO_BANK: u8 = 0xff;
+/// Copier header some HuCard dumps start with.
+pub const COPIER_HEADER_LENGTH: usize = 512;
+const BANK_LENGTH: usize = 8 * 1024;
+
+/// Video display controller, the sound generator, the timer, the joypad port and
+/// the interrupt controller as seen with the I/O bank in MPR0.
+// https://www.archaicpixels.com/HuC6280
+const REGISTERS: &[(u16, &str)] = &[
+    (0x0000, "VDC_ADDR_STATUS"),
+    (0x0002, "VDC_DATA_LO"),
+    (0x0003, "VDC_DATA_HI"),
+    (0x0400, "VCE_CTRL"),
+    (0x0402, "VCE_ADDR_LO"),
+    (0x0403, "VCE_ADDR_HI"),
+    (0x0404, "VCE_DATA_LO"),
+    (0x0405, "VCE_DATA_HI"),
+    (0x0800, "PSG_CH_SELECT"),
+    (0x0801, "PSG_MAIN_VOLUME"),
+    (0x0802, "PSG_FREQ_LO"),
+    (0x0803, "PSG_FREQ_HI"),
+    (0x0804, "PSG_CH_CTRL"),
+    (0x0805, "PSG_CH_BALANCE"),
+    (0x0806, "PSG_CH_WAVE"),
+    (0x0807, "PSG_NOISE"),
+    (0x0808, "PSG_LFO_FREQ"),
+    (0x0809, "PSG_LFO_CTRL"),
+    (0x0c00, "TIMER_COUNTER"),
+    (0x0c01, "TIMER_CTRL"),
+    (0x1000, "JOYPAD"),
+    (0x1402, "IRQ_DISABLE"),
+    (0x1403, "IRQ_STATUS"),
+];
+
+/// Name of the hardware register at `addr`.
+pub fn register_name(addr: u16) -> Option<&'static str> {
+    return REGISTERS
+        .iter()
+        .find(|(a, _)| *a == addr)
+        .map(|(_, name)| *name);
+}
+
+/// What `st0`, `st1` and `st2` write.
+pub fn st_comment(mnemonic: &str) -> Option<&'static str> {
+    return match mnemonic {
+        "st0" => Option::Some("select VDC register"),
+        "st1" => Option::Some("VDC data low byte"),
+        "st2" => Option::Some("VDC data high byte"),
+        _ => Option::None,
+    };
+}
+
+/// The eight MPR registers mapping 8k banks into the 64k address space, as far as
+/// `lda #`/`tam` pairs seen so far tell.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mprs {
+    banks: [Option<u8>; 8],
+    /// value of the accumulator if loaded with a constant
+    a: Option<u8>,
+}
+
+impl Mprs {
+    /// MPR7 holds bank 0 after reset so the vectors are read from it, the rest is
+    /// unknown.
+    pub fn new() -> Mprs {
+        let mut banks = [Option::None; 8];
+        banks[7] = Option::Some(0);
+        return Mprs {
+            banks,
+            a: Option::None,
+        };
+    }
+
+    pub fn get(&self, mpr: usize) -> Option<u8> {
+        return self.banks[mpr];
+    }
+
+    /// Follows `instr`, returning a comment with the MPRs it sets for `tam`.
+    pub fn update(&mut self, instr: &DecodedHuC6280) -> Option<String> {
+        let operand = instr.operands[0] as u8;
+        match (instr.mnemonic, instr.mode) {
+            ("lda", ModeHuC6280::Base(AddressingMode::Immediate)) => {
+                self.a = Option::Some(operand);
+            }
+            ("cla", _) => self.a = Option::Some(0),
+            ("tam", _) => {
+                let mut set = Vec::new();
+                for mpr in 0..8 {
+                    if operand & (1 << mpr) != 0 {
+                        self.banks[mpr] = self.a;
+                        set.push(match self.a {
+                            Option::Some(bank) => format!("MPR{} = ${:02x}", mpr, bank),
+                            Option::None => format!("MPR{} = a", mpr),
+                        });
+                    }
+                }
+                return Option::Some(set.join(", "));
+            }
+            ("tma", _) => {
+                let mpr = operand.trailing_zeros() as usize;
+                self.a = self.banks.get(mpr).copied().flatten();
+            }
+            (
+                "lda" | "pla" | "txa" | "tya" | "adc" | "sbc" | "and" | "ora" | "eor" | "inc"
+                | "dec" | "asl" | "lsr" | "rol" | "ror" | "sax" | "say",
+                _,
+            ) => self.a = Option::None,
+            _ => {}
+        }
+        return Option::None;
+    }
+}
+
+impl Default for Mprs {
+    fn default() -> Self {
+        return Mprs::new();
+    }
+}
+
+/// Length of the copier header `data` starts with, if any.
+pub fn header_len(data: &[u8]) -> usize {
+    return if data.len() % BANK_LENGTH == COPIER_HEADER_LENGTH {
+        COPIER_HEADER_LENGTH
+    } else {
+        0
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decode_huc6280::decode_huc6280;
+
+    #[test]
+    fn test_mprs() {
+        let mut mprs = Mprs::new();
+        let mut run = |bytes: &[u8]| mprs.update(&decode_huc6280(bytes, 0xe000).unwrap());
+        assert_eq!(run(&[0xa9, 0xff]), Option::None);
+        assert_eq!(run(&[0x53, 0x01]).as_deref(), Option::Some("MPR0 = $ff"));
+        assert_eq!(run(&[0x62]), Option::None);
+        assert_eq!(
+            run(&[0x53, 0x06]).as_deref(),
+            Option::Some("MPR1 = $00, MPR2 = $00")
+        );
+        assert_eq!(mprs.get(0), Option::Some(IO_BANK));
+        assert_eq!(mprs.get(7), Option::Some(0));
+        assert_eq!(register_name(0x0402), Option::Some("VCE_ADDR_LO"));
+    }
+}
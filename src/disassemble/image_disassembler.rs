@@ -0,0 +1,628 @@
+use std::{collections::BTreeMap, io::Write, ops::Range};
+
+use super::{
+    address::{BankedAddr, CpuAddr, FileOffset},
+    code::AsmCode,
+    diagnostic::Diagnostic,
+    dialect::AsmDialect,
+    disassembler::Disassembler,
+    instruction::Instruction,
+    interrupts::operand_access,
+    mapper::Mapper,
+    platform::Platform,
+    sweep::is_fill_run,
+    variable::{Variable, VariableValue},
+    AnalysisOptions, DisassembleError,
+};
+use crate::decode::decode;
+
+// https://problemkaputt.de/2k6specs.htm
+// https://www.c64-wiki.com/wiki/BASIC_token
+/// sizes of the Atari 2600 cartridges the 6507 sees all of, without bank switching
+const ATARI_2600_ROM_LENGTHS: [usize; 2] = [0x0800, 0x1000];
+/// where a C64 program with a BASIC line starting it is loaded
+const C64_BASIC_START: u16 = 0x0801;
+/// BASIC token of `SYS`
+const SYS_TOKEN: u8 = 0x9e;
+/// sizes of 35 and 40 track C64 disk images, with and without error bytes
+const D64_LENGTHS: [usize; 4] = [174848, 175531, 196608, 197376];
+/// start of the header of a C64 cartridge image
+const CRT_SIGNATURE: &[u8] = b"C64 CARTRIDGE   ";
+
+/// TIA registers named by the writes to them
+const TIA_WRITE_REGISTERS: [&str; 0x2d] = [
+    "VSYNC", "VBLANK", "WSYNC", "RSYNC", "NUSIZ0", "NUSIZ1", "COLUP0", "COLUP1", "COLUPF",
+    "COLUBK", "CTRLPF", "REFP0", "REFP1", "PF0", "PF1", "PF2", "RESP0", "RESP1", "RESM0", "RESM1",
+    "RESBL", "AUDC0", "AUDC1", "AUDF0", "AUDF1", "AUDV0", "AUDV1", "GRP0", "GRP1", "ENAM0",
+    "ENAM1", "ENABL", "HMP0", "HMP1", "HMM0", "HMM1", "HMBL", "VDELP0", "VDELP1", "VDELBL",
+    "RESMP0", "RESMP1", "HMOVE", "HMCLR", "CXCLR",
+];
+/// TIA registers named by the reads of them, also read at $30-$3d
+const TIA_READ_REGISTERS: [&str; 0x0e] = [
+    "CXM0P", "CXM1P", "CXP0FB", "CXP1FB", "CXM0FB", "CXM1FB", "CXBLPF", "CXPPMM", "INPT0", "INPT1",
+    "INPT2", "INPT3", "INPT4", "INPT5",
+];
+/// the RIOT's ports and timer
+const RIOT_REGISTERS: [(u16, &str); 10] = [
+    (0x280, "SWCHA"),
+    (0x281, "SWACNT"),
+    (0x282, "SWCHB"),
+    (0x283, "SWBCNT"),
+    (0x284, "INTIM"),
+    (0x285, "TIMINT"),
+    (0x294, "TIM1T"),
+    (0x295, "TIM8T"),
+    (0x296, "TIM64T"),
+    (0x297, "T1024T"),
+];
+
+/// Disassembles the ROM images of the platforms other than the NES, one block of
+/// code at a single address: Atari 2600 cartridges without bank switching and C64
+/// programs. Only the code is traced and the hardware registers named, none of the
+/// NES passes run.
+pub struct ImageDisassembler {
+    d: Disassembler,
+    mapper: ImageMapper,
+}
+
+impl ImageDisassembler {
+    pub fn disassemble(
+        platform: Platform,
+        data: Vec<u8>,
+        opts: &AnalysisOptions,
+    ) -> Result<ImageDisassembler, DisassembleError> {
+        let mapper = ImageMapper::new(platform, &data)?;
+        let mut d = ImageDisassembler {
+            d: Disassembler::new(data),
+            mapper,
+        };
+        d.d.strict = opts.strict;
+        d.d.illegal_opcodes = opts.illegal_opcodes;
+        d.d.jam_opcodes = opts.jam_opcodes;
+        d.d.quirks = opts.cpu_variant.unwrap_or(platform.cpu_variant()).quirks();
+
+        match platform {
+            Platform::Atari2600 => d.disassemble_cartridge()?,
+            Platform::C64 => d.disassemble_program()?,
+            Platform::Nes => {
+                return Result::Err(DisassembleError::ParseError(
+                    "NES ROMs are disassembled by NesDisassembler".to_string(),
+                ))
+            }
+        }
+        if platform == Platform::Atari2600 {
+            d.name_registers();
+        }
+        if platform == Platform::C64 {
+            // the program runs from RAM, all of it can be written
+            let mapper = d.mapper;
+            let end = FileOffset(d.d.code.data().len());
+            d.d.find_self_modifying_code(
+                FileOffset(2)..end,
+                &|addr| mapper.to_offset(CpuAddr(addr)).is_some(),
+                &mapper,
+            );
+        }
+        d.resolve_jump_labels();
+        d.collapse_fill();
+        d.d.code.set_segment(FileOffset(0), "CODE");
+        return Result::Ok(d);
+    }
+
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        return &self.d.diagnostics;
+    }
+
+    /// Writes the disassembly with `dialect` from now on.
+    pub fn set_dialect(&mut self, dialect: Box<dyn AsmDialect>) {
+        let start = self.mapper.to_addr(FileOffset(0)).0;
+        self.d
+            .code
+            .set_dialect(dialect, BTreeMap::from([(FileOffset(0), start)]));
+    }
+
+    /// Writes a listing with the address and bytes of each statement, see
+    /// [`Code::set_listing`](super::code::Code::set_listing).
+    pub fn set_listing(&mut self, listing: bool) {
+        self.d.code.set_listing(listing);
+    }
+
+    pub fn write(&self, out: &mut dyn Write) -> Result<(), DisassembleError> {
+        return self.d.code.write(out);
+    }
+
+    /// Traces the RESET and IRQ/BRK vectors at the end of the cartridge. The 6507
+    /// has no NMI line, its vector is only written as the word it holds.
+    fn disassemble_cartridge(&mut self) -> Result<(), DisassembleError> {
+        let mapper = self.mapper;
+        let len = self.d.code.data().len();
+        for (vector, name) in [(6, "nmi"), (4, "reset"), (2, "irq")] {
+            let vector = FileOffset(len - vector);
+            let addr = self.decode_vector(vector, &name.to_uppercase())?;
+            if name == "nmi" {
+                continue;
+            }
+            if mapper.to_offset(addr).is_none() {
+                self.d.diagnostics.push(Diagnostic::warning(
+                    vector,
+                    Option::Some(mapper.to_banked_addr(vector)),
+                    format!(
+                        "the {} vector points to ${:04x}, which isn't in the ROM, not followed",
+                        name.to_uppercase(),
+                        addr
+                    ),
+                ));
+                continue;
+            }
+            self.d.disassemble(addr, name, "rom", &mapper)?;
+        }
+        return Result::Ok(());
+    }
+
+    /// Traces the program from the `SYS` of the BASIC line starting it or, without
+    /// one, from where it is loaded. The load address in front of the program is
+    /// kept as a word so the assembly assembles back to the same file.
+    fn disassemble_program(&mut self) -> Result<(), DisassembleError> {
+        let mapper = self.mapper;
+        let load = self.decode_load_address()?;
+        let entry = match basic_sys(&self.d.code.data()[2..], load) {
+            Option::Some(entry) => {
+                self.d
+                    .code
+                    .set_comment(FileOffset(2), &format!("BASIC line: SYS {}", entry));
+                entry
+            }
+            Option::None => load,
+        };
+        if mapper.to_offset(CpuAddr(entry)).is_none() {
+            self.d.diagnostics.push(Diagnostic::warning(
+                FileOffset(2),
+                Option::Some(mapper.to_banked_addr(FileOffset(2))),
+                format!(
+                    "the SYS line starts the program at ${:04x}, which isn't in the file, not followed",
+                    entry
+                ),
+            ));
+            return Result::Ok(());
+        }
+        self.d
+            .disassemble(CpuAddr(entry), "start", "prg", &mapper)?;
+        return Result::Ok(());
+    }
+
+    fn decode_load_address(&mut self) -> Result<u16, DisassembleError> {
+        let low = self.d.code.take(FileOffset(0))?.asm_code.to_u8()? as u16;
+        let high = self.d.code.take(FileOffset(1))?.asm_code.to_u8()? as u16;
+        let addr = low | (high << 8);
+        self.d
+            .code
+            .replace(FileOffset(0)..FileOffset(2), AsmCode::DataHexU16(addr))?;
+        self.d.code.set_comment(FileOffset(0), "load address");
+        self.d.code.claim(FileOffset(0), "the load address");
+        return Result::Ok(addr);
+    }
+
+    fn decode_vector(
+        &mut self,
+        offset: FileOffset,
+        name: &str,
+    ) -> Result<CpuAddr, DisassembleError> {
+        let low = self.d.code.take(offset)?.asm_code.to_u8()? as u16;
+        let high = self.d.code.take(offset + 1)?.asm_code.to_u8()? as u16;
+        let addr = low | (high << 8);
+        self.d
+            .code
+            .replace(offset..offset + 2, AsmCode::DataHexU16(addr))?;
+        self.d.code.set_comment(offset, name);
+        self.d.code.claim(offset, &format!("the {} vector", name));
+        return Result::Ok(CpuAddr(addr));
+    }
+
+    /// Names the TIA and RIOT registers the code reads and writes. The TIA has
+    /// different registers at the same address for reading and writing, one read
+    /// and written under both names is left as its address.
+    fn name_registers(&mut self) {
+        let mapper = self.mapper;
+        let data = self.d.code.data();
+        let mut names: BTreeMap<u16, Vec<&'static str>> = BTreeMap::new();
+        for offset in (0..data.len()).map(FileOffset) {
+            if !self.d.code.is_instruction(offset) {
+                continue;
+            }
+            let Option::Some(instr) = decode(&data[offset.0..], mapper.to_addr(offset).0) else {
+                continue;
+            };
+            let Option::Some((addr, access)) = operand_access(&instr) else {
+                continue;
+            };
+            let mut wanted = Vec::new();
+            if access.writes() {
+                wanted.extend(tia_write_register(addr));
+            }
+            if access.reads() {
+                wanted.extend(tia_read_register(addr));
+            }
+            wanted.extend(riot_register(addr));
+            let names = names.entry(addr).or_default();
+            for name in wanted {
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+        }
+        for (addr, names) in names {
+            let [name] = names[..] else {
+                continue;
+            };
+            let value = match addr < 0x100 {
+                true => VariableValue::U8(addr as u8),
+                false => VariableValue::U16(addr),
+            };
+            self.d.code.set_variable(
+                addr,
+                Variable {
+                    name: name.to_string(),
+                    value,
+                },
+            );
+        }
+    }
+
+    /// Refers jumps and branches to the label of the code they land on, labeling
+    /// it if it isn't yet, and writes the others as the address or relative to `*`.
+    fn resolve_jump_labels(&mut self) {
+        let mapper = self.mapper;
+        for offset in (0..self.d.code.data().len()).map(FileOffset) {
+            let target = match &self.d.code.statements()[offset.0].asm_code {
+                AsmCode::Instruction(instr) if instr.label().is_some() => {
+                    match instr.target(mapper.to_addr(offset).0) {
+                        Option::Some(target) => target,
+                        Option::None => continue,
+                    }
+                }
+                _ => continue,
+            };
+            let target_offset = mapper
+                .to_offset(CpuAddr(target))
+                .filter(|t| mapper.to_addr(*t).0 == target);
+            let operand = match target_offset {
+                Option::Some(t) => match self.d.code.label(t) {
+                    Option::Some(existing) => existing.to_string(),
+                    Option::None => {
+                        let name = format!("{}_{:04x}", mapper.label_prefix, target);
+                        self.d.code.set_label(t, &name);
+                        name
+                    }
+                },
+                Option::None => match &self.d.code.statements()[offset.0].asm_code {
+                    AsmCode::Instruction(Instruction::JMP_ABS(..) | Instruction::JSR_ABS(..)) => {
+                        format!("${:04x}", target)
+                    }
+                    _ => format!(
+                        "*{:+}",
+                        target.wrapping_sub(mapper.to_addr(offset).0) as i16
+                    ),
+                },
+            };
+            self.d.code.set_target_label(offset, &operand);
+        }
+    }
+
+    /// Writes runs of the same byte no code reached as a single fill. Runs stop at
+    /// labels and comments so nothing written about the bytes is lost.
+    fn collapse_fill(&mut self) {
+        let len = self.d.code.data().len();
+        let mut offset = 0;
+        while offset < len {
+            let value = self.d.code.data()[offset];
+            let run = self.d.code.statements()[offset..]
+                .iter()
+                .enumerate()
+                .take_while(|(i, stmt)| {
+                    return matches!(stmt.asm_code, AsmCode::DataHexU8(v) if v == value)
+                        && (*i == 0 || (stmt.label.is_none() && stmt.comment.is_none()));
+                })
+                .count();
+            if is_fill_run(value, run) {
+                self.d.code.collapse_fill(FileOffset(offset), run, value);
+            }
+            offset += run.max(1);
+        }
+    }
+}
+
+/// The address in the `SYS` of the first line of the BASIC program `program`
+/// loaded at `load`, e.g. `10 SYS 2061`, if it is one.
+fn basic_sys(program: &[u8], load: u16) -> Option<u16> {
+    if load != C64_BASIC_START || program.get(4) != Option::Some(&SYS_TOKEN) {
+        return Option::None;
+    }
+    let digits = program[5..]
+        .iter()
+        .skip_while(|b| **b == b' ' || **b == b'(')
+        .take_while(|b| b.is_ascii_digit())
+        .map(|b| *b as char)
+        .collect::<String>();
+    return digits.parse::<u16>().ok();
+}
+
+fn tia_write_register(addr: u16) -> Option<&'static str> {
+    return TIA_WRITE_REGISTERS.get(addr as usize).copied();
+}
+
+fn tia_read_register(addr: u16) -> Option<&'static str> {
+    return match addr {
+        0x00..=0x0d => Option::Some(TIA_READ_REGISTERS[addr as usize]),
+        0x30..=0x3d => Option::Some(TIA_READ_REGISTERS[addr as usize - 0x30]),
+        _ => Option::None,
+    };
+}
+
+fn riot_register(addr: u16) -> Option<&'static str> {
+    return RIOT_REGISTERS
+        .iter()
+        .find(|(register, _)| *register == addr)
+        .map(|(_, name)| *name);
+}
+
+/// The image at `org`, `len` bytes from `data_offset` on, seen by the CPU in
+/// `window` of the addresses masked by `mask`, mirrored across it if it is shorter.
+#[derive(Debug, Clone, Copy)]
+struct ImageMapper {
+    org: u16,
+    data_offset: usize,
+    len: usize,
+    mask: u16,
+    window: (u16, u16),
+    label_prefix: &'static str,
+}
+
+impl ImageMapper {
+    fn new(platform: Platform, data: &[u8]) -> Result<ImageMapper, DisassembleError> {
+        return match platform {
+            Platform::Atari2600 => {
+                if !ATARI_2600_ROM_LENGTHS.contains(&data.len()) {
+                    return Result::Err(DisassembleError::ParseError(format!(
+                        "only 2k and 4k Atari 2600 cartridges are supported, not ones of {} bytes, which switch banks",
+                        data.len()
+                    )));
+                }
+                // the 6507's 13 address lines put the cartridge at $1000-$1fff and its
+                // mirrors, the vectors at the end of $f000-$ffff
+                Result::Ok(ImageMapper {
+                    org: (0x10000 - data.len()) as u16,
+                    data_offset: 0,
+                    len: data.len(),
+                    mask: 0x1fff,
+                    window: (0x1000, 0x2000),
+                    label_prefix: "rom",
+                })
+            }
+            Platform::C64 => {
+                // containers holding programs, not a program at its load address
+                if data.starts_with(CRT_SIGNATURE) {
+                    return Result::Err(DisassembleError::ParseError(
+                        "unsupported container: C64 cartridge images (.crt) aren't supported, \
+                         only programs (.prg)"
+                            .to_string(),
+                    ));
+                }
+                if D64_LENGTHS.contains(&data.len()) {
+                    return Result::Err(DisassembleError::ParseError(
+                        "unsupported container: C64 disk images (.d64) aren't supported, \
+                         extract the program (.prg) from it first"
+                            .to_string(),
+                    ));
+                }
+                if data.len() < 3 {
+                    return Result::Err(DisassembleError::ParseError(
+                        "only C64 programs (.prg) are supported, a load address and the program"
+                            .to_string(),
+                    ));
+                }
+                let org = u16::from_le_bytes([data[0], data[1]]);
+                let len = (data.len() - 2).min(0x10000 - org as usize);
+                Result::Ok(ImageMapper {
+                    org,
+                    data_offset: 2,
+                    len,
+                    mask: 0xffff,
+                    window: (org, org.wrapping_add(len as u16)),
+                    label_prefix: "prg",
+                })
+            }
+            Platform::Nes => Result::Err(DisassembleError::ParseError(
+                "NES ROMs are mapped by their mapper".to_string(),
+            )),
+        };
+    }
+
+    fn window(&self) -> Range<usize> {
+        let (start, end) = self.window;
+        return match end {
+            0 => start as usize..0x10000,
+            end => start as usize..end as usize,
+        };
+    }
+}
+
+impl Mapper for ImageMapper {
+    fn to_offset(&self, addr: CpuAddr) -> Option<FileOffset> {
+        let window = self.window();
+        let addr = (addr.0 & self.mask) as usize;
+        if !window.contains(&addr) {
+            return Option::None;
+        }
+        return Option::Some(FileOffset(
+            self.data_offset + (addr - window.start) % self.len,
+        ));
+    }
+
+    fn to_addr(&self, offset: FileOffset) -> CpuAddr {
+        return CpuAddr(
+            self.org
+                .wrapping_add(offset.0.wrapping_sub(self.data_offset) as u16),
+        );
+    }
+
+    fn to_banked_addr(&self, offset: FileOffset) -> BankedAddr {
+        return BankedAddr {
+            bank: 0,
+            addr: self.to_addr(offset),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disassemble::dialect::Dialect;
+
+    fn disassemble(platform: Platform, data: Vec<u8>, dialect: Dialect) -> String {
+        let mut d =
+            ImageDisassembler::disassemble(platform, data, &AnalysisOptions::default()).unwrap();
+        d.set_dialect(dialect.writer());
+        let mut out = Vec::new();
+        d.write(&mut out).unwrap();
+        return String::from_utf8(out).unwrap();
+    }
+
+    #[test]
+    fn test_atari_2600_cartridge() {
+        let mut data = vec![0xff; 0x1000];
+        let code = [
+            0x85, 0x02, // sta WSYNC
+            0xa5, 0x0c, // lda INPT4
+            0x8d, 0x96, 0x02, // sta TIM64T
+            0x4c, 0x00, 0xf0, // jmp $f000
+            0x40, // rti
+        ];
+        data[..code.len()].copy_from_slice(&code);
+        data[0xffa..].copy_from_slice(&[0x00, 0xf0, 0x00, 0xf0, 0x0a, 0xf0]);
+        let asm = disassemble(Platform::Atari2600, data, Dialect::Dasm);
+        assert!(asm.starts_with("    processor 6502\n"), "{}", asm);
+        assert!(asm.contains("    ORG $F000\n"), "{}", asm);
+        assert!(asm.contains("rom_reset\n    sta WSYNC\n"), "{}", asm);
+        assert!(asm.contains("    lda INPT4\n"), "{}", asm);
+        assert!(asm.contains("    sta TIM64T\n"), "{}", asm);
+        assert!(asm.contains("    jmp rom_reset\n"), "{}", asm);
+        assert!(
+            asm.contains("rom_irq\n    rti\n    ds.b 4079, $FF\n"),
+            "{}",
+            asm
+        );
+    }
+
+    #[test]
+    fn test_atari_2600_mirrors() {
+        let mapper = ImageMapper::new(Platform::Atari2600, &[0; 0x800]).unwrap();
+        assert_eq!(mapper.to_addr(FileOffset(0)), CpuAddr(0xf800));
+        assert_eq!(
+            mapper.to_offset(CpuAddr(0xf800)),
+            Option::Some(FileOffset(0))
+        );
+        assert_eq!(
+            mapper.to_offset(CpuAddr(0xf000)),
+            Option::Some(FileOffset(0))
+        );
+        assert_eq!(
+            mapper.to_offset(CpuAddr(0x1fff)),
+            Option::Some(FileOffset(0x7ff))
+        );
+        assert_eq!(mapper.to_offset(CpuAddr(0x0080)), Option::None);
+        assert!(ImageMapper::new(Platform::Atari2600, &[0; 0x2000]).is_err());
+    }
+
+    #[test]
+    fn test_c64_program() {
+        // 10 SYS 2061
+        let mut data = vec![0x01, 0x08];
+        data.extend([
+            0x0b, 0x08, 0x0a, 0x00, 0x9e, b'2', b'0', b'6', b'1', 0x00, 0x00, 0x00,
+        ]);
+        data.extend([0xee, 0x20, 0xd0, 0x4c, 0x0d, 0x08]); // inc $d020, jmp $080d
+        let asm = disassemble(Platform::C64, data, Dialect::Acme);
+        assert!(asm.contains("* = $07FF\n!word $0801"), "{}", asm);
+        assert!(asm.contains("BASIC line: SYS 2061"), "{}", asm);
+        assert!(asm.contains("prg_start\n    inc ABS_D020\n"), "{}", asm);
+        assert!(asm.contains("    jmp prg_start\n"), "{}", asm);
+    }
+
+    #[test]
+    fn test_basic_sys() {
+        let line = [
+            0x0b, 0x08, 0x0a, 0x00, 0x9e, b' ', b'4', b'9', b'1', b'5', b'2', 0x00,
+        ];
+        assert_eq!(basic_sys(&line, 0x0801), Option::Some(49152));
+        assert_eq!(basic_sys(&line, 0xc000), Option::None);
+        assert_eq!(
+            basic_sys(&[0x0b, 0x08, 0x0a, 0x00, 0x99], 0x0801),
+            Option::None
+        );
+    }
+
+    #[test]
+    fn test_c64_self_modifying_code() {
+        // lda #$01, sta $c006, lda $d020, inc $c007, rts: the sta and the inc
+        // both patch the operand of the lda at $c005
+        let mut data = vec![0x00, 0xc0];
+        data.extend([
+            0xa9, 0x01, 0x8d, 0x06, 0xc0, 0xad, 0x20, 0xd0, 0xee, 0x07, 0xc0, 0x60,
+        ]);
+        let d = ImageDisassembler::disassemble(Platform::C64, data, &AnalysisOptions::default())
+            .unwrap();
+        let messages = d
+            .diagnostics()
+            .iter()
+            .map(|diagnostic| diagnostic.message.as_str())
+            .collect::<Vec<&str>>();
+        assert_eq!(
+            messages,
+            [
+                "self-modifying code, $c002 writes $c006 in the instruction at $c005",
+                "self-modifying code, $c008 writes $c007 in the instruction at $c005",
+            ]
+        );
+        let comment = |offset: usize| {
+            return d.d.code.statements()[offset]
+                .comment
+                .as_deref()
+                .unwrap_or("");
+        };
+        assert_eq!(
+            comment(4),
+            "self-modifying: changes the instruction at $c005"
+        );
+        assert_eq!(
+            comment(7),
+            "modified by $c002, may not be what runs\nmodified by $c008, may not be what runs"
+        );
+    }
+
+    #[test]
+    fn test_c64_containers() {
+        let unsupported = |file: &str, data: Vec<u8>| {
+            let platform = Platform::from_path(std::path::Path::new(file)).unwrap();
+            assert_eq!(platform, Platform::C64);
+            return match ImageDisassembler::disassemble(platform, data, &AnalysisOptions::default())
+            {
+                Result::Err(DisassembleError::ParseError(msg)) => msg,
+                _ => panic!("{} was disassembled", file),
+            };
+        };
+        let msg = unsupported("game.d64", vec![0; 174848]);
+        assert!(
+            msg.starts_with("unsupported container: C64 disk images"),
+            "{}",
+            msg
+        );
+        let mut crt = CRT_SIGNATURE.to_vec();
+        crt.resize(0x4050, 0);
+        let msg = unsupported("game.crt", crt);
+        assert!(
+            msg.starts_with("unsupported container: C64 cartridge images"),
+            "{}",
+            msg
+        );
+    }
+}
@@ -1,10 +1,14 @@
 use std::{collections::HashMap, fmt};
 
+use crate::decode::{absolute_prefix, decode, AddressingMode, DecodedInstruction};
+
 use super::variable::{Variable, VariableValue};
 
 #[derive(Debug)]
-#[allow(non_camel_case_types)]
+#[allow(non_camel_case_types, clippy::upper_case_acronyms, dead_code)]
 pub enum Instruction {
+    /// signature byte
+    BRK(u8),
     ORA_ZP(u8),
     ASL_ZP(u8),
     PHP,
@@ -28,6 +32,8 @@ pub enum Instruction {
     EOR_IMM(u8),
     LSR,
     JMP_ABS(u16, String),
+    /// pointer address
+    JMP_IND(u16),
     EOR_ABS(u16),
     RTS,
     ADC_ZP(u8),
@@ -98,9 +104,26 @@ pub enum Instruction {
     INC_ABS(u16),
     BEQ_REL(i8, String),
     INC_ZP_X(u8),
+    SED,
     SBC_ABS_X(u16),
     INC_ABS_X(u16),
-    JAM,
+    NOP,
+    BVC_REL(i8, String),
+    BVS_REL(i8, String),
+    /// documented opcode without a variant of its own, written from the opcode
+    /// table like [`Instruction::ILLEGAL`]
+    OTHER(DecodedInstruction),
+    /// opcode
+    JAM(u8),
+    /// opcode, written as `.byte`
+    JAM_BYTE(u8),
+    /// undocumented opcode written with its mnemonic
+    ILLEGAL(DecodedInstruction),
+    /// undocumented opcode written as `.byte`
+    ILLEGAL_BYTES(DecodedInstruction),
+    /// mnemonic, `<` or `>` and label of an immediate load written as a byte of
+    /// the label's address, see [`Instruction::with_label_byte`]
+    IMM_LABEL(&'static str, char, String),
 }
 
 impl fmt::Display for Instruction {
@@ -111,50 +134,308 @@ impl fmt::Display for Instruction {
 }
 
 impl Instruction {
+    /// Label of the jump or branch target, or of the address an immediate is a byte
+    /// of, if the instruction has one.
+    pub fn label(&self) -> Option<&str> {
+        return match self {
+            Instruction::BPL_REL(_, v)
+            | Instruction::JSR_ABS(_, v)
+            | Instruction::BMI_REL(_, v)
+            | Instruction::JMP_ABS(_, v)
+            | Instruction::BCC_REL(_, v)
+            | Instruction::BCS_REL(_, v)
+            | Instruction::BNE_REL(_, v)
+            | Instruction::BEQ_REL(_, v)
+            | Instruction::BVC_REL(_, v)
+            | Instruction::BVS_REL(_, v)
+            | Instruction::IMM_LABEL(_, _, v) => Option::Some(v),
+            _ => Option::None,
+        };
+    }
+
+    /// Mutable [`Instruction::label`].
+    pub fn label_mut(&mut self) -> Option<&mut String> {
+        return match self {
+            Instruction::BPL_REL(_, v)
+            | Instruction::JSR_ABS(_, v)
+            | Instruction::BMI_REL(_, v)
+            | Instruction::JMP_ABS(_, v)
+            | Instruction::BCC_REL(_, v)
+            | Instruction::BCS_REL(_, v)
+            | Instruction::BNE_REL(_, v)
+            | Instruction::BEQ_REL(_, v)
+            | Instruction::BVC_REL(_, v)
+            | Instruction::BVS_REL(_, v)
+            | Instruction::IMM_LABEL(_, _, v) => Option::Some(v),
+            _ => Option::None,
+        };
+    }
+
+    /// Address the jump or branch at `addr` goes to when it runs there.
+    pub fn target(&self, addr: u16) -> Option<u16> {
+        return match self {
+            Instruction::JSR_ABS(target, _) | Instruction::JMP_ABS(target, _) => {
+                Option::Some(*target)
+            }
+            Instruction::BPL_REL(rel, _)
+            | Instruction::BMI_REL(rel, _)
+            | Instruction::BCC_REL(rel, _)
+            | Instruction::BCS_REL(rel, _)
+            | Instruction::BNE_REL(rel, _)
+            | Instruction::BEQ_REL(rel, _)
+            | Instruction::BVC_REL(rel, _)
+            | Instruction::BVS_REL(rel, _) => {
+                Option::Some(addr.wrapping_add(2).wrapping_add(*rel as u16))
+            }
+            _ => Option::None,
+        };
+    }
+
+    /// The immediate `lda`, `ldx` or `ldy` written as the low byte of `label`, or the
+    /// high byte if `high`, for pointers to it set up a byte at a time.
+    pub fn with_label_byte(&self, high: bool, label: &str) -> Option<Instruction> {
+        let mnemonic = match self {
+            Instruction::LDA_IMM(_) => "lda",
+            Instruction::LDX_IMM(_) => "ldx",
+            Instruction::LDY_IMM(_) => "ldy",
+            _ => return Option::None,
+        };
+        let byte = if high { '>' } else { '<' };
+        return Option::Some(Instruction::IMM_LABEL(mnemonic, byte, label.to_string()));
+    }
+
+    /// Decodes the instruction at the start of `bytes`, which is at `addr`, returning
+    /// it with its length. Jumps and branches are labeled with their target address.
+    /// Documented opcodes without a variant of their own are [`Instruction::OTHER`],
+    /// `None` is for the undocumented ones and a cut off operand. This is what the
+    /// disassembler builds instructions with, so every opcode of the table is
+    /// followed as code.
+    pub fn decode(bytes: &[u8], addr: u16) -> Option<(Instruction, usize)> {
+        let opcode = *bytes.first()?;
+        if matches!(
+            opcode,
+            0x02 | 0x12 | 0x22 | 0x32 | 0x42 | 0x52 | 0x62 | 0x72 | 0x92 | 0xb2 | 0xd2 | 0xf2
+        ) {
+            return Option::Some((Instruction::JAM(opcode), 1));
+        }
+        if opcode == 0x00 {
+            return Option::Some((Instruction::BRK(*bytes.get(1)?), 2));
+        }
+        let decoded = decode(bytes, addr)?;
+        let operand = decoded.operand;
+        let target = format!("${:04x}", decoded.target().unwrap_or(operand));
+        let instr = match opcode {
+            0x05 => Instruction::ORA_ZP(operand as u8),
+            0x06 => Instruction::ASL_ZP(operand as u8),
+            0x08 => Instruction::PHP,
+            0x09 => Instruction::ORA_IMM(operand as u8),
+            0x0a => Instruction::ASL,
+            0x10 => Instruction::BPL_REL(operand as u8 as i8, target),
+            0x18 => Instruction::CLC,
+            0x20 => Instruction::JSR_ABS(operand, target),
+            0x24 => Instruction::BIT_ZP(operand as u8),
+            0x25 => Instruction::AND_ZP(operand as u8),
+            0x28 => Instruction::PLP,
+            0x29 => Instruction::AND_IMM(operand as u8),
+            0x2a => Instruction::ROL,
+            0x30 => Instruction::BMI_REL(operand as u8 as i8, target),
+            0x35 => Instruction::AND_ZP_X(operand as u8),
+            0x38 => Instruction::SEC,
+            0x40 => Instruction::RTI,
+            0x45 => Instruction::EOR_ZP(operand as u8),
+            0x46 => Instruction::LSR_ZP(operand as u8),
+            0x48 => Instruction::PHA,
+            0x49 => Instruction::EOR_IMM(operand as u8),
+            0x4a => Instruction::LSR,
+            0x4c => Instruction::JMP_ABS(operand, target),
+            0x6c => Instruction::JMP_IND(operand),
+            0x4d => Instruction::EOR_ABS(operand),
+            0x60 => Instruction::RTS,
+            0x65 => Instruction::ADC_ZP(operand as u8),
+            0x66 => Instruction::ROR_ZP(operand as u8),
+            0x68 => Instruction::PLA,
+            0x69 => Instruction::ADC_IMM(operand as u8),
+            0x6a => Instruction::ROR,
+            0x6d => Instruction::ADC_ABS(operand),
+            0x78 => Instruction::SEI,
+            0x7d => Instruction::ADC_ABS_X(operand),
+            0x84 => Instruction::STY_ZP(operand as u8),
+            0x85 => Instruction::STA_ZP(operand as u8),
+            0x86 => Instruction::STX_ZP(operand as u8),
+            0x88 => Instruction::DEY,
+            0x8a => Instruction::TXA,
+            0x8c => Instruction::STY_ABS(operand),
+            0x8d => Instruction::STA_ABS(operand),
+            0x8e => Instruction::STX_ABS(operand),
+            0x90 => Instruction::BCC_REL(operand as u8 as i8, target),
+            0x91 => Instruction::STA_IND_Y(operand as u8),
+            0x94 => Instruction::STY_ZP_X(operand as u8),
+            0x95 => Instruction::STA_ZP_X(operand as u8),
+            0x98 => Instruction::TYA,
+            0x99 => Instruction::STA_ABS_Y(operand),
+            0x9a => Instruction::TXS,
+            0x9d => Instruction::STA_ABS_X(operand),
+            0xa0 => Instruction::LDY_IMM(operand as u8),
+            0xa2 => Instruction::LDX_IMM(operand as u8),
+            0xa4 => Instruction::LDY_ZP(operand as u8),
+            0xa5 => Instruction::LDA_ZP(operand as u8),
+            0xa6 => Instruction::LDX_ZP(operand as u8),
+            0xa8 => Instruction::TAY,
+            0xa9 => Instruction::LDA_IMM(operand as u8),
+            0xaa => Instruction::TAX,
+            0xac => Instruction::LDY_ABS(operand),
+            0xad => Instruction::LDA_ABS(operand),
+            0xae => Instruction::LDX_ABS(operand),
+            0xb0 => Instruction::BCS_REL(operand as u8 as i8, target),
+            0xb1 => Instruction::LDA_IND_Y(operand as u8),
+            0xb4 => Instruction::LDY_ZP_X(operand as u8),
+            0xb5 => Instruction::LDA_ZP_X(operand as u8),
+            0xb9 => Instruction::LDA_ABS_Y(operand),
+            0xbc => Instruction::LDY_ABS_X(operand),
+            0xbd => Instruction::LDA_ABS_X(operand),
+            0xbe => Instruction::LDX_ABS_Y(operand),
+            0xc0 => Instruction::CPY_IMM(operand as u8),
+            0xc4 => Instruction::CPY_ZP(operand as u8),
+            0xc5 => Instruction::CMP_ZP(operand as u8),
+            0xc6 => Instruction::DEC_ZP(operand as u8),
+            0xc8 => Instruction::INY,
+            0xc9 => Instruction::CMP_IMM(operand as u8),
+            0xca => Instruction::DEX,
+            0xcd => Instruction::CMP_ABS(operand),
+            0xce => Instruction::DEC_ABS(operand),
+            0xd0 => Instruction::BNE_REL(operand as u8 as i8, target),
+            0xd5 => Instruction::CMP_ZP_X(operand as u8),
+            0xd6 => Instruction::DEC_ZP_X(operand as u8),
+            0xd8 => Instruction::CLD,
+            0xd9 => Instruction::CMP_ABS_Y(operand),
+            0xdd => Instruction::CMP_ABS_X(operand),
+            0xde => Instruction::DEC_ABS_X(operand),
+            0xe0 => Instruction::CPX_IMM(operand as u8),
+            0xe4 => Instruction::CPX_ZP(operand as u8),
+            0xe5 => Instruction::SBC_ZP(operand as u8),
+            0xe6 => Instruction::INC_ZP(operand as u8),
+            0xe8 => Instruction::INX,
+            0xe9 => Instruction::SBC_IMM(operand as u8),
+            0xee => Instruction::INC_ABS(operand),
+            0xf0 => Instruction::BEQ_REL(operand as u8 as i8, target),
+            0xf6 => Instruction::INC_ZP_X(operand as u8),
+            0xf8 => Instruction::SED,
+            0xea => Instruction::NOP,
+            0xfd => Instruction::SBC_ABS_X(operand),
+            0xfe => Instruction::INC_ABS_X(operand),
+            0x50 => Instruction::BVC_REL(operand as u8 as i8, target),
+            0x70 => Instruction::BVS_REL(operand as u8 as i8, target),
+            _ => Instruction::OTHER(decoded),
+        };
+        return Option::Some((instr, decoded.size()));
+    }
+
+    /// Address an absolute, possibly indexed, operand refers to. Jumps and branches
+    /// refer to labels instead, see [`Instruction::label_mut`].
+    pub fn abs_operand(&self) -> Option<u16> {
+        return match self {
+            Instruction::EOR_ABS(v)
+            | Instruction::ADC_ABS(v)
+            | Instruction::STY_ABS(v)
+            | Instruction::STA_ABS(v)
+            | Instruction::STX_ABS(v)
+            | Instruction::STA_ABS_Y(v)
+            | Instruction::STA_ABS_X(v)
+            | Instruction::LDY_ABS(v)
+            | Instruction::LDA_ABS(v)
+            | Instruction::LDX_ABS(v)
+            | Instruction::CMP_ABS(v)
+            | Instruction::DEC_ABS(v)
+            | Instruction::DEC_ABS_X(v)
+            | Instruction::INC_ABS(v)
+            | Instruction::INC_ABS_X(v) => Option::Some(*v),
+            Instruction::OTHER(instr)
+                if matches!(
+                    instr.mode,
+                    AddressingMode::Absolute
+                        | AddressingMode::AbsoluteX
+                        | AddressingMode::AbsoluteY
+                ) =>
+            {
+                Option::Some(instr.operand)
+            }
+            _ => self.indexed_read_operand(),
+        };
+    }
+
+    /// Address of the table an indexed load, compare or arithmetic instruction
+    /// reads from.
+    pub fn indexed_read_operand(&self) -> Option<u16> {
+        return match self {
+            Instruction::ADC_ABS_X(v)
+            | Instruction::LDA_ABS_Y(v)
+            | Instruction::LDY_ABS_X(v)
+            | Instruction::LDA_ABS_X(v)
+            | Instruction::LDX_ABS_Y(v)
+            | Instruction::CMP_ABS_Y(v)
+            | Instruction::CMP_ABS_X(v)
+            | Instruction::SBC_ABS_X(v) => Option::Some(*v),
+            Instruction::OTHER(instr)
+                if matches!(
+                    instr.mode,
+                    AddressingMode::AbsoluteX | AddressingMode::AbsoluteY
+                ) && matches!(
+                    instr.mnemonic,
+                    "ora" | "and" | "eor" | "adc" | "sbc" | "cmp" | "lda" | "ldx" | "ldy"
+                ) =>
+            {
+                Option::Some(instr.operand)
+            }
+            _ => Option::None,
+        };
+    }
+
     pub fn to_write_string(&self, addr_to_variable: &mut HashMap<u16, Variable>) -> String {
         return match self {
+            Instruction::BRK(v) => format!("brk\n    .byte ${:02x}", v),
             Instruction::ORA_ZP(v) => Instruction::to_write_string_zp("ora", v, addr_to_variable),
             Instruction::ASL_ZP(v) => Instruction::to_write_string_zp("asl", v, addr_to_variable),
-            Instruction::PHP => format!("php"),
+            Instruction::PHP => "php".to_string(),
             Instruction::ORA_IMM(v) => format!("ora #${:02x}", v),
-            Instruction::ASL => format!("asl"),
+            Instruction::ASL => "asl".to_string(),
             Instruction::BPL_REL(_, v) => format!("bpl {}", v),
-            Instruction::CLC => format!("clc"),
+            Instruction::CLC => "clc".to_string(),
             Instruction::JSR_ABS(_addr, v) => format!("jsr {}", v),
             Instruction::BIT_ZP(v) => Instruction::to_write_string_zp("bit", v, addr_to_variable),
             Instruction::AND_ZP(v) => Instruction::to_write_string_zp("and", v, addr_to_variable),
-            Instruction::PLP => format!("plp"),
+            Instruction::PLP => "plp".to_string(),
             Instruction::AND_IMM(v) => format!("and #${:02x}", v),
-            Instruction::ROL => format!("rol"),
+            Instruction::ROL => "rol".to_string(),
             Instruction::BMI_REL(_, v) => format!("bmi {}", v),
             Instruction::AND_ZP_X(v) => {
                 Instruction::to_write_string_zp_x("and", v, addr_to_variable)
             }
-            Instruction::SEC => format!("sec"),
-            Instruction::RTI => format!("rti"),
+            Instruction::SEC => "sec".to_string(),
+            Instruction::RTI => "rti".to_string(),
             Instruction::EOR_ZP(v) => Instruction::to_write_string_zp("eor", v, addr_to_variable),
             Instruction::LSR_ZP(v) => Instruction::to_write_string_zp("lsr", v, addr_to_variable),
-            Instruction::PHA => format!("pha"),
+            Instruction::PHA => "pha".to_string(),
             Instruction::EOR_IMM(v) => format!("eor #${:02x}", v),
-            Instruction::LSR => format!("lsr"),
+            Instruction::LSR => "lsr".to_string(),
             Instruction::JMP_ABS(_addr, v) => format!("jmp {}", v),
+            Instruction::JMP_IND(v) => format!("jmp (${:04x})", v),
             Instruction::EOR_ABS(v) => Instruction::to_write_string_abs("eor", v, addr_to_variable),
-            Instruction::RTS => format!("rts"),
+            Instruction::RTS => "rts".to_string(),
             Instruction::ADC_ZP(v) => Instruction::to_write_string_zp("adc", v, addr_to_variable),
             Instruction::ROR_ZP(v) => Instruction::to_write_string_zp("ror", v, addr_to_variable),
-            Instruction::PLA => format!("pla"),
+            Instruction::PLA => "pla".to_string(),
             Instruction::ADC_IMM(v) => format!("adc #${:02x}", v),
-            Instruction::ROR => format!("ror"),
+            Instruction::ROR => "ror".to_string(),
             Instruction::ADC_ABS(v) => Instruction::to_write_string_abs("adc", v, addr_to_variable),
-            Instruction::SEI => format!("sei"),
+            Instruction::SEI => "sei".to_string(),
             Instruction::ADC_ABS_X(v) => {
                 Instruction::to_write_string_abs_x("adc", v, addr_to_variable)
             }
             Instruction::STY_ZP(v) => Instruction::to_write_string_zp("sty", v, addr_to_variable),
             Instruction::STA_ZP(v) => Instruction::to_write_string_zp("sta", v, addr_to_variable),
             Instruction::STX_ZP(v) => Instruction::to_write_string_zp("stx", v, addr_to_variable),
-            Instruction::DEY => format!("dey"),
-            Instruction::TXA => format!("txa"),
+            Instruction::DEY => "dey".to_string(),
+            Instruction::TXA => "txa".to_string(),
             Instruction::STY_ABS(v) => Instruction::to_write_string_abs("sty", v, addr_to_variable),
             Instruction::STA_ABS(v) => Instruction::to_write_string_abs("sta", v, addr_to_variable),
             Instruction::STX_ABS(v) => Instruction::to_write_string_abs("stx", v, addr_to_variable),
@@ -166,11 +447,11 @@ impl Instruction {
             Instruction::STA_ZP_X(v) => {
                 Instruction::to_write_string_zp_x("sta", v, addr_to_variable)
             }
-            Instruction::TYA => format!("tya"),
+            Instruction::TYA => "tya".to_string(),
             Instruction::STA_ABS_Y(v) => {
                 Instruction::to_write_string_abs_y("sta", v, addr_to_variable)
             }
-            Instruction::TXS => format!("txs"),
+            Instruction::TXS => "txs".to_string(),
             Instruction::STA_ABS_X(v) => {
                 Instruction::to_write_string_abs_x("sta", v, addr_to_variable)
             }
@@ -180,8 +461,8 @@ impl Instruction {
             Instruction::LDA_ZP(v) => Instruction::to_write_string_zp("lda", v, addr_to_variable),
             Instruction::LDX_ZP(v) => Instruction::to_write_string_zp("ldx", v, addr_to_variable),
             Instruction::LDA_IMM(v) => format!("lda #${:02x}", v),
-            Instruction::TAX => format!("tax"),
-            Instruction::TAY => format!("tay"),
+            Instruction::TAX => "tax".to_string(),
+            Instruction::TAY => "tay".to_string(),
             Instruction::LDA_IND_Y(v) => format!("lda (${:02x}),y", v),
             Instruction::LDY_ZP_X(v) => {
                 Instruction::to_write_string_zp_x("ldy", v, addr_to_variable)
@@ -209,19 +490,19 @@ impl Instruction {
             Instruction::CPY_ZP(v) => Instruction::to_write_string_zp("cpy", v, addr_to_variable),
             Instruction::CMP_ZP(v) => Instruction::to_write_string_zp("cmp", v, addr_to_variable),
             Instruction::DEC_ZP(v) => Instruction::to_write_string_zp("dec", v, addr_to_variable),
-            Instruction::INY => format!("iny"),
+            Instruction::INY => "iny".to_string(),
             Instruction::CMP_IMM(v) => format!("cmp #${:02x}", v),
-            Instruction::DEX => format!("dex"),
+            Instruction::DEX => "dex".to_string(),
             Instruction::CMP_ABS(v) => Instruction::to_write_string_abs("cmp", v, addr_to_variable),
             Instruction::DEC_ABS(v) => Instruction::to_write_string_abs("dec", v, addr_to_variable),
             Instruction::BNE_REL(_, v) => format!("bne {}", v),
             Instruction::CMP_ZP_X(v) => {
-                Instruction::to_write_string_zp_x("dec", v, addr_to_variable)
+                Instruction::to_write_string_zp_x("cmp", v, addr_to_variable)
             }
             Instruction::DEC_ZP_X(v) => {
                 Instruction::to_write_string_zp_x("dec", v, addr_to_variable)
             }
-            Instruction::CLD => format!("cld"),
+            Instruction::CLD => "cld".to_string(),
             Instruction::CMP_ABS_Y(v) => {
                 Instruction::to_write_string_abs_y("cmp", v, addr_to_variable)
             }
@@ -235,7 +516,7 @@ impl Instruction {
             Instruction::CPX_ZP(v) => Instruction::to_write_string_zp("cpx", v, addr_to_variable),
             Instruction::SBC_ZP(v) => Instruction::to_write_string_zp("sbc", v, addr_to_variable),
             Instruction::INC_ZP(v) => Instruction::to_write_string_zp("inc", v, addr_to_variable),
-            Instruction::INX => format!("inx"),
+            Instruction::INX => "inx".to_string(),
             Instruction::SBC_IMM(v) => format!("sbc #${:02x}", v),
             Instruction::INC_ABS(v) => Instruction::to_write_string_abs("inc", v, addr_to_variable),
             Instruction::BEQ_REL(_, v) => format!("beq {}", v),
@@ -248,8 +529,63 @@ impl Instruction {
             Instruction::INC_ABS_X(v) => {
                 Instruction::to_write_string_abs_x("inc", v, addr_to_variable)
             }
-            Instruction::JAM => format!("jam"),
+            Instruction::SED => "sed".to_string(),
+            Instruction::NOP => "nop".to_string(),
+            Instruction::BVC_REL(_, v) => format!("bvc {}", v),
+            Instruction::BVS_REL(_, v) => format!("bvs {}", v),
+            Instruction::OTHER(instr) => {
+                Instruction::to_write_string_decoded(instr, addr_to_variable)
+            }
+            // ca65 assembles `jam` to the first of them
+            Instruction::JAM(0x02) => "jam".to_string(),
+            Instruction::JAM(v) => format!(".byte ${:02X}", v),
+            Instruction::JAM_BYTE(v) => format!(".byte ${:02X}", v),
+            Instruction::ILLEGAL(instr) => {
+                Instruction::to_write_string_decoded(instr, addr_to_variable)
+            }
+            Instruction::ILLEGAL_BYTES(instr) => {
+                let bytes = instr.encode()[..instr.size()]
+                    .iter()
+                    .map(|b| format!("${:02X}", b))
+                    .collect::<Vec<String>>()
+                    .join(", ");
+                format!(".byte {}", bytes)
+            }
+            Instruction::IMM_LABEL(mnemonic, byte, label) => {
+                format!("{} #{}{}", mnemonic, byte, label)
+            }
+        };
+    }
+
+    /// Writes `instr` naming its operand address like the other instructions do.
+    fn to_write_string_decoded(
+        instr: &DecodedInstruction,
+        addr_to_variable: &mut HashMap<u16, Variable>,
+    ) -> String {
+        let (name, value, suffix) = match instr.mode {
+            AddressingMode::ZeroPage => ("ZP", VariableValue::U8(instr.operand as u8), ""),
+            AddressingMode::ZeroPageX => ("ZP", VariableValue::U8(instr.operand as u8), ",x"),
+            AddressingMode::ZeroPageY => ("ZP", VariableValue::U8(instr.operand as u8), ",y"),
+            AddressingMode::Absolute => ("ABS", VariableValue::U16(instr.operand), ""),
+            AddressingMode::AbsoluteX => ("ABS", VariableValue::U16(instr.operand), ",x"),
+            AddressingMode::AbsoluteY => ("ABS", VariableValue::U16(instr.operand), ",y"),
+            _ => return instr.to_string(),
         };
+        if let Option::Some(var) = addr_to_variable
+            .get(&instr.operand)
+            .filter(|v| v.is_named())
+        {
+            let prefix = absolute_prefix(instr.mnemonic, instr.mode, instr.operand);
+            return format!("{} {}{}{}", instr.mnemonic, prefix, var.name, suffix);
+        }
+        let name = match value {
+            VariableValue::U8(v) => format!("{}_{:02X}", name, v),
+            _ => format!("{}_{:04X}", name, instr.operand),
+        };
+        addr_to_variable
+            .entry(instr.operand)
+            .or_insert(Variable { name, value });
+        return instr.to_string();
     }
 
     fn to_write_string_zp(
@@ -258,16 +594,13 @@ impl Instruction {
         addr_to_variable: &mut HashMap<u16, Variable>,
     ) -> String {
         let addr = *zp_addr as u16;
-        if let Option::Some(var) = addr_to_variable.get(&addr) {
+        if let Option::Some(var) = addr_to_variable.get(&addr).filter(|v| v.is_named()) {
             return format!("{} {}", instr, var.name);
         } else {
-            addr_to_variable.insert(
-                addr,
-                Variable {
-                    name: format!("ZP_{:02X}", zp_addr),
-                    value: VariableValue::U8(*zp_addr),
-                },
-            );
+            addr_to_variable.entry(addr).or_insert(Variable {
+                name: format!("ZP_{:02X}", zp_addr),
+                value: VariableValue::U8(*zp_addr),
+            });
             return format!("{} ${:02x}", instr, zp_addr);
         }
     }
@@ -278,16 +611,13 @@ impl Instruction {
         addr_to_variable: &mut HashMap<u16, Variable>,
     ) -> String {
         let addr = *zp_addr as u16;
-        if let Option::Some(var) = addr_to_variable.get(&addr) {
+        if let Option::Some(var) = addr_to_variable.get(&addr).filter(|v| v.is_named()) {
             return format!("{} {},x", instr, var.name);
         } else {
-            addr_to_variable.insert(
-                addr,
-                Variable {
-                    name: format!("ZP_{:02X}", zp_addr),
-                    value: VariableValue::U8(*zp_addr),
-                },
-            );
+            addr_to_variable.entry(addr).or_insert(Variable {
+                name: format!("ZP_{:02X}", zp_addr),
+                value: VariableValue::U8(*zp_addr),
+            });
             return format!("{} ${:02x},x", instr, zp_addr);
         }
     }
@@ -297,17 +627,15 @@ impl Instruction {
         addr: &u16,
         addr_to_variable: &mut HashMap<u16, Variable>,
     ) -> String {
-        if let Option::Some(var) = addr_to_variable.get(&addr) {
-            return format!("{} {}", instr, var.name);
+        let prefix = absolute_prefix(instr, AddressingMode::Absolute, *addr);
+        if let Option::Some(var) = addr_to_variable.get(addr).filter(|v| v.is_named()) {
+            return format!("{} {}{}", instr, prefix, var.name);
         } else {
-            addr_to_variable.insert(
-                *addr,
-                Variable {
-                    name: format!("ABS_{:04X}", addr),
-                    value: VariableValue::U16(*addr),
-                },
-            );
-            return format!("{} ${:04x}", instr, addr);
+            addr_to_variable.entry(*addr).or_insert(Variable {
+                name: format!("ABS_{:04X}", addr),
+                value: VariableValue::U16(*addr),
+            });
+            return format!("{} {}${:04x}", instr, prefix, addr);
         }
     }
 
@@ -316,17 +644,15 @@ impl Instruction {
         addr: &u16,
         addr_to_variable: &mut HashMap<u16, Variable>,
     ) -> String {
-        if let Option::Some(var) = addr_to_variable.get(&addr) {
-            return format!("{} {}", instr, var.name);
+        let prefix = absolute_prefix(instr, AddressingMode::AbsoluteX, *addr);
+        if let Option::Some(var) = addr_to_variable.get(addr).filter(|v| v.is_named()) {
+            return format!("{} {}{},x", instr, prefix, var.name);
         } else {
-            addr_to_variable.insert(
-                *addr,
-                Variable {
-                    name: format!("ABS_{:04X}", addr),
-                    value: VariableValue::U16(*addr),
-                },
-            );
-            return format!("{} ${:04x},x", instr, addr);
+            addr_to_variable.entry(*addr).or_insert(Variable {
+                name: format!("ABS_{:04X}", addr),
+                value: VariableValue::U16(*addr),
+            });
+            return format!("{} {}${:04x},x", instr, prefix, addr);
         }
     }
 
@@ -335,17 +661,48 @@ impl Instruction {
         addr: &u16,
         addr_to_variable: &mut HashMap<u16, Variable>,
     ) -> String {
-        if let Option::Some(var) = addr_to_variable.get(&addr) {
-            return format!("{} {}", instr, var.name);
+        let prefix = absolute_prefix(instr, AddressingMode::AbsoluteY, *addr);
+        if let Option::Some(var) = addr_to_variable.get(addr).filter(|v| v.is_named()) {
+            return format!("{} {}{},y", instr, prefix, var.name);
         } else {
-            addr_to_variable.insert(
-                *addr,
-                Variable {
-                    name: format!("ABS_{:04X}", addr),
-                    value: VariableValue::U16(*addr),
-                },
-            );
-            return format!("{} ${:04x},y", instr, addr);
+            addr_to_variable.entry(*addr).or_insert(Variable {
+                name: format!("ABS_{:04X}", addr),
+                value: VariableValue::U16(*addr),
+            });
+            return format!("{} {}${:04x},y", instr, prefix, addr);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        let (instr, len) = Instruction::decode(&[0xbd, 0x00, 0x90], 0x8000).unwrap();
+        assert_eq!(len, 3);
+        assert_eq!(instr.to_string(), "lda $9000,x");
+
+        let (instr, len) = Instruction::decode(&[0xd0, 0xfe], 0x8010).unwrap();
+        assert_eq!(len, 2);
+        assert_eq!(instr.to_string(), "bne $8010");
+
+        let (instr, _) = Instruction::decode(&[0x6c, 0xff, 0x02], 0x8000).unwrap();
+        assert_eq!(instr.to_string(), "jmp ($02ff)");
+
+        let (instr, len) = Instruction::decode(&[0x00, 0x12], 0x8000).unwrap();
+        assert_eq!(len, 2);
+        assert_eq!(instr.to_string(), "brk\n    .byte $12");
+
+        let (instr, len) = Instruction::decode(&[0xea], 0x8000).unwrap();
+        assert_eq!(len, 1);
+        assert_eq!(instr.to_string(), "nop");
+
+        assert!(matches!(
+            Instruction::decode(&[0x72], 0x8000),
+            Option::Some((Instruction::JAM(0x72), 1))
+        ));
+        assert!(Instruction::decode(&[0x4c, 0x00], 0x8000).is_none());
+    }
+}
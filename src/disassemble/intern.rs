@@ -0,0 +1,36 @@
+use std::{collections::HashSet, rc::Rc};
+
+/// Keeps one copy of each label, segment name and comment given to the
+/// statements, which on big ROMs share a handful of segment names and many
+/// identical generated comments.
+#[derive(Default)]
+pub struct Interner {
+    strings: HashSet<Rc<str>>,
+}
+
+impl Interner {
+    /// The pooled copy of `s`, added to the pool the first time it is asked for.
+    pub fn intern(&mut self, s: &str) -> Rc<str> {
+        if let Option::Some(existing) = self.strings.get(s) {
+            return existing.clone();
+        }
+        let s: Rc<str> = Rc::from(s);
+        self.strings.insert(s.clone());
+        return s;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_intern() {
+        let mut interner = Interner::default();
+        let a = interner.intern("PRGROM0");
+        let b = interner.intern(&format!("PRGROM{}", 0));
+        assert!(Rc::ptr_eq(&a, &b));
+        assert_eq!(&*interner.intern("CHRROM"), "CHRROM");
+        assert_eq!(interner.strings.len(), 2);
+    }
+}
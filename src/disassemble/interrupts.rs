@@ -0,0 +1,103 @@
+use serde::Serialize;
+
+use crate::decode::{AddressingMode, DecodedInstruction};
+
+/// How an instruction uses the memory its operand points at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    /// `inc`, `asl` and the like read and write back
+    ReadWrite,
+}
+
+impl Access {
+    pub fn reads(&self) -> bool {
+        return matches!(self, Access::Read | Access::ReadWrite);
+    }
+
+    pub fn writes(&self) -> bool {
+        return matches!(self, Access::Write | Access::ReadWrite);
+    }
+}
+
+/// RAM written by an interrupt handler and read by the main code, or the other
+/// way around, where one may see the other half way through an update.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SharedVariable {
+    pub addr: u16,
+    pub name: String,
+    /// entry points of the code writing it
+    pub written_from: Vec<String>,
+    /// entry points of the code reading it
+    pub read_from: Vec<String>,
+    /// the main code waits in a loop for it to change, e.g. for the NMI to say a
+    /// frame is done
+    pub polled: bool,
+}
+
+/// The address `instr` reads or writes directly and how, the base address for
+/// indexed operands. Pointers and the stack aren't followed.
+pub fn operand_access(instr: &DecodedInstruction) -> Option<(u16, Access)> {
+    if !matches!(
+        instr.mode,
+        AddressingMode::ZeroPage
+            | AddressingMode::ZeroPageX
+            | AddressingMode::ZeroPageY
+            | AddressingMode::Absolute
+            | AddressingMode::AbsoluteX
+            | AddressingMode::AbsoluteY
+    ) {
+        return Option::None;
+    }
+    let access = match instr.mnemonic {
+        "sta" | "stx" | "sty" | "sax" => Access::Write,
+        "inc" | "dec" | "asl" | "lsr" | "rol" | "ror" | "dcp" | "isc" | "slo" | "rla" | "sre"
+        | "rra" => Access::ReadWrite,
+        "lda" | "ldx" | "ldy" | "cmp" | "cpx" | "cpy" | "adc" | "sbc" | "and" | "ora" | "eor"
+        | "bit" | "lax" => Access::Read,
+        _ => return Option::None,
+    };
+    return Option::Some((instr.operand, access));
+}
+
+/// Same as [`operand_access`] for RAM only, leaving out hardware registers and
+/// ROM.
+pub fn ram_access(instr: &DecodedInstruction) -> Option<(u16, Access)> {
+    return operand_access(instr).filter(|(addr, _)| is_ram(*addr));
+}
+
+/// Internal RAM and its mirrors, then battery or work RAM on the cartridge.
+pub fn is_ram(addr: u16) -> bool {
+    return addr < 0x2000 || (0x6000..0x8000).contains(&addr);
+}
+
+/// Whether the entry point named `entry` is an interrupt handler rather than the
+/// main code started by RESET.
+pub fn is_interrupt(entry: &str) -> bool {
+    return entry.ends_with("_nmi") || entry.ends_with("_irq");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decode::decode;
+
+    #[test]
+    fn test_ram_access() {
+        let access = |bytes: &[u8]| ram_access(&decode(bytes, 0x8000).unwrap());
+        assert_eq!(access(&[0x85, 0x10]), Option::Some((0x10, Access::Write)));
+        assert_eq!(
+            access(&[0xad, 0x00, 0x03]),
+            Option::Some((0x300, Access::Read))
+        );
+        assert_eq!(
+            access(&[0xee, 0x00, 0x60]),
+            Option::Some((0x6000, Access::ReadWrite))
+        );
+        // PPU register
+        assert_eq!(access(&[0xad, 0x02, 0x20]), Option::None);
+        assert_eq!(access(&[0xa9, 0x10]), Option::None);
+        assert_eq!(access(&[0xb1, 0x10]), Option::None);
+    }
+}
@@ -0,0 +1,230 @@
+//! ld65 configs for common targets, built from what the disassembler knows about
+//! the platforms and mappers so the configs place code where the analysis expects
+//! it.
+
+use super::{
+    banks::bank_sizes,
+    nes_header::{NES_CHR_ROM_PAGE_LENGTH, NES_HEADER_LENGTH, NES_PRG_ROM_PAGE_LENGTH},
+    platform::Platform,
+};
+
+/// Names of the configs [`builtin_linker_config`] builds.
+pub const BUILTIN_LINKER_CONFIGS: [&str; 4] = ["nes-nrom-128", "nes-mmc1", "c64-asm", "atari2600"];
+
+/// where BASIC programs start, the C64 loads programs with a `SYS` stub there
+const C64_BASIC_START: u16 = 0x0801;
+/// start of the I/O area, the end of the RAM a program can be loaded into
+const C64_IO_START: u16 = 0xd000;
+/// size of the NMI, RESET and IRQ vectors at the end of the address space
+const VECTORS_LENGTH: usize = 6;
+
+/// The built-in config called `name`, see [`BUILTIN_LINKER_CONFIGS`].
+pub fn builtin_linker_config(name: &str) -> Option<String> {
+    return match name {
+        // 16k of PRG ROM mirrored at $8000 and $c000, 8k of CHR ROM
+        "nes-nrom-128" => Option::Some(nes_config(0, 1, 1)),
+        // SLROM-like, 128k of PRG ROM and 8k of CHR ROM
+        "nes-mmc1" => Option::Some(nes_config(1, 8, 1)),
+        "c64-asm" => Option::Some(c64_config()),
+        "atari2600" => Option::Some(atari2600_config()),
+        _ => Option::None,
+    };
+}
+
+/// Areas of a `MEMORY` or `SEGMENTS` block by name, with their attributes.
+#[derive(Default)]
+struct Block(Vec<(String, String)>);
+
+impl Block {
+    fn add(&mut self, name: &str, attributes: String) {
+        self.0.push((name.to_string(), attributes));
+    }
+
+    fn write(&self, out: &mut String, category: &str) {
+        let width = self
+            .0
+            .iter()
+            .map(|(name, _)| name.len() + 1)
+            .max()
+            .unwrap_or(0);
+        *out += &format!("{} {{\n", category);
+        for (name, attributes) in &self.0 {
+            *out += &format!("    {:<width$} {};\n", format!("{}:", name), attributes);
+        }
+        *out += "}\n";
+    }
+}
+
+/// `file = "", ...` memory area for the RAM from `start` to `end`, inclusive
+fn ram(start: u16, end: u16) -> String {
+    return format!(
+        "file = \"\", start = ${:04X}, size = ${:04X}, type = rw",
+        start,
+        end as usize - start as usize + 1
+    );
+}
+
+/// `file = %O, ...` memory area for `size` bytes of ROM at `start`
+fn rom(start: usize, size: usize) -> String {
+    return format!(
+        "file = %O, start = ${:04X}, size = ${:04X}, fill = yes, fillval = $FF",
+        start, size
+    );
+}
+
+/// The header, `prg_pages` 16k pages of PRG ROM in the banks `mapper` switches
+/// and `chr_pages` 8k pages of CHR ROM. The last PRG bank is the one fixed at the
+/// end of the address space, holding `CODE`, `RODATA` and the vectors, the others
+/// are each a segment of their own at the start of the cartridge ROM.
+fn nes_config(mapper: u16, prg_pages: usize, chr_pages: usize) -> String {
+    let platform = Platform::Nes;
+    let cartridge = platform.cartridge_rom().unwrap_or(0x8000..=0xffff);
+    let end = *cartridge.end() as usize + 1;
+    let (prg_bank, chr_bank) = bank_sizes(mapper);
+    let prg_bank = prg_bank.min(prg_pages * NES_PRG_ROM_PAGE_LENGTH);
+    let prg_banks = prg_pages * NES_PRG_ROM_PAGE_LENGTH / prg_bank;
+    let chr_bank = chr_bank.min(chr_pages * NES_CHR_ROM_PAGE_LENGTH);
+    let chr_banks = (chr_pages * NES_CHR_ROM_PAGE_LENGTH).checked_div(chr_bank);
+
+    let mut memory = Block::default();
+    let mut segments = Block::default();
+    let zp = platform.zero_page();
+    memory.add("ZP", ram(*zp.start(), *zp.end()));
+    segments.add("ZEROPAGE", "load = ZP, type = zp".to_string());
+    // $0100 holds the stack and $0200 the sprites copied to OAM
+    memory.add("RAM", ram(0x0300, 0x07ff));
+    segments.add("BSS", "load = RAM, type = bss".to_string());
+    memory.add("HEADER", rom(0, NES_HEADER_LENGTH));
+    segments.add("HEADER", "load = HEADER, type = ro".to_string());
+    for bank in 0..prg_banks - 1 {
+        let name = format!("PRG{}", bank);
+        memory.add(&name, rom(*cartridge.start() as usize, prg_bank));
+        segments.add(&name, format!("load = {}, type = ro", name));
+    }
+    let fixed = format!("PRG{}", prg_banks - 1);
+    memory.add(&fixed, rom(end - prg_bank, prg_bank - VECTORS_LENGTH));
+    segments.add("CODE", format!("load = {}, type = ro", fixed));
+    segments.add("RODATA", format!("load = {}, type = ro", fixed));
+    memory.add("VECTORS", rom(end - VECTORS_LENGTH, VECTORS_LENGTH));
+    segments.add("VECTORS", "load = VECTORS, type = ro".to_string());
+    for bank in 0..chr_banks.unwrap_or(0) {
+        let name = format!("CHR{}", bank);
+        memory.add(&name, rom(0, chr_bank));
+        segments.add(&name, format!("load = {}, type = ro", name));
+    }
+
+    let mut out = format!(
+        "# NES, iNES mapper {}, {}k PRG ROM, {}k CHR ROM\n",
+        mapper,
+        prg_pages * 16,
+        chr_pages * 8
+    );
+    memory.write(&mut out, "MEMORY");
+    segments.write(&mut out, "SEGMENTS");
+    return out;
+}
+
+/// A program loaded at the start of BASIC, preceded by its load address the way
+/// `.prg` files are.
+fn c64_config() -> String {
+    let zp = Platform::C64.zero_page();
+    let mut memory = Block::default();
+    memory.add("ZP", ram(*zp.start(), *zp.end()));
+    memory.add(
+        "LOADADDR",
+        "file = %O, start = __LOADADDR__ - 2, size = $0002".to_string(),
+    );
+    memory.add(
+        "MAIN",
+        format!(
+            "file = %O, start = __LOADADDR__, size = ${:04X} - __LOADADDR__",
+            C64_IO_START
+        ),
+    );
+    let mut segments = Block::default();
+    segments.add("ZEROPAGE", "load = ZP, type = zp".to_string());
+    segments.add("LOADADDR", "load = LOADADDR, type = ro".to_string());
+    segments.add("CODE", "load = MAIN, type = ro".to_string());
+    segments.add("RODATA", "load = MAIN, type = ro".to_string());
+    segments.add("DATA", "load = MAIN, type = rw".to_string());
+    segments.add("BSS", "load = MAIN, type = bss, optional = yes".to_string());
+
+    let mut out = format!(
+        "# C64 program\nSYMBOLS {{\n    __LOADADDR__: type = weak, value = ${:04X};\n}}\n",
+        C64_BASIC_START
+    );
+    memory.write(&mut out, "MEMORY");
+    segments.write(&mut out, "SEGMENTS");
+    return out;
+}
+
+/// A 4k cartridge, its RAM all zero page.
+fn atari2600_config() -> String {
+    let platform = Platform::Atari2600;
+    let zp = platform.zero_page();
+    let cartridge = platform.cartridge_rom().unwrap_or(0xf000..=0xffff);
+    let start = *cartridge.start() as usize;
+    let end = *cartridge.end() as usize + 1;
+    let mut memory = Block::default();
+    memory.add("ZP", ram(*zp.start(), *zp.end()));
+    memory.add("ROM", rom(start, end - start - VECTORS_LENGTH));
+    memory.add("VECTORS", rom(end - VECTORS_LENGTH, VECTORS_LENGTH));
+    let mut segments = Block::default();
+    segments.add("ZEROPAGE", "load = ZP, type = zp".to_string());
+    segments.add("CODE", "load = ROM, type = ro".to_string());
+    segments.add("RODATA", "load = ROM, type = ro".to_string());
+    segments.add("VECTORS", "load = VECTORS, type = ro".to_string());
+
+    let mut out = "# Atari 2600, 4k cartridge\n".to_string();
+    memory.write(&mut out, "MEMORY");
+    segments.write(&mut out, "SEGMENTS");
+    return out;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::linker_file::read_linker_file;
+
+    /// Bytes the config writes to the output file, each memory area filled.
+    fn output_size(name: &str, symbols: &HashMap<String, i64>) -> i64 {
+        let config = read_linker_file(name.to_string()).unwrap();
+        return config
+            .category("MEMORY")
+            .unwrap()
+            .items()
+            .iter()
+            .filter(|(_, item)| item.argument("file") == Option::Some("%O"))
+            .map(|(_, item)| item.value("size", symbols).unwrap().unwrap())
+            .sum();
+    }
+
+    #[test]
+    fn test_builtin_linker_configs() {
+        let none = HashMap::new();
+        assert_eq!(
+            output_size("nes-nrom-128", &none),
+            16 + 16 * 1024 + 8 * 1024
+        );
+        assert_eq!(output_size("nes-mmc1", &none), 16 + 128 * 1024 + 8 * 1024);
+        assert_eq!(output_size("atari2600", &none), 4 * 1024);
+        let load_addr = HashMap::from([("__LOADADDR__".to_string(), 0x0801)]);
+        assert_eq!(output_size("c64-asm", &load_addr), 2 + 0xd000 - 0x0801);
+
+        let config = read_linker_file("nes-mmc1".to_string()).unwrap();
+        let memory = config.category("MEMORY").unwrap().items();
+        let start = |name: &str| {
+            let (_, item) = memory.iter().find(|(n, _)| n == name).unwrap();
+            return item.value("start", &none).unwrap().unwrap();
+        };
+        assert_eq!(
+            (start("PRG0"), start("PRG7"), start("VECTORS")),
+            (0x8000, 0xc000, 0xfffa)
+        );
+        for name in BUILTIN_LINKER_CONFIGS {
+            assert!(builtin_linker_config(name).is_some());
+        }
+    }
+}
@@ -0,0 +1,329 @@
+use std::collections::BTreeSet;
+
+use crate::decode::{AddressingMode, DecodedInstruction};
+
+/// instructions looked at between the counter being loaded and the loop start
+const MAX_SETUP_INSTRUCTIONS: usize = 4;
+
+/// A loop counting X or Y from a constant to a constant.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CountedLoop {
+    /// index of the first instruction of the body, where the branch goes back to
+    pub head: usize,
+    /// index of the branch going back
+    pub branch: usize,
+    pub iterations: usize,
+    /// a `lda src,x` / `sta dst,x` pair moving a byte per iteration
+    pub copy: Option<Copy>,
+    /// ROM or RAM the body reads indexed by the counter
+    pub tables: Vec<Table>,
+}
+
+/// What a loop reads indexed by its counter, from the lowest index to the highest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Table {
+    pub addr: u16,
+    pub len: usize,
+    /// read a byte and the byte after it each iteration, the counter stepping by 2
+    pub words: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Copy {
+    pub src: u16,
+    pub dst: u16,
+    pub len: usize,
+}
+
+impl CountedLoop {
+    /// Comment for the loop head, e.g. `loop 4 times, copies 4 bytes from $8100
+    /// to $0200`.
+    pub fn describe(&self) -> String {
+        let times = format!("loop {} times", self.iterations);
+        return match &self.copy {
+            Option::Some(copy) => format!(
+                "{}, copies {} bytes from ${:04x} to ${:04x}",
+                times, copy.len, copy.src, copy.dst
+            ),
+            Option::None => times,
+        };
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Register {
+    X,
+    Y,
+}
+
+/// Finds counted loops in `instrs`, which follow each other in memory:
+/// `ldx #N ... dex / bne`, `ldx #N ... dex / bpl` and `ldx #S ... inx / cpx #E /
+/// bne`, or the same with Y, stepping the counter once or twice. The body may not
+/// change the counter or call subroutines, which could.
+pub fn find_counted_loops(instrs: &[DecodedInstruction]) -> Vec<CountedLoop> {
+    let mut result = Vec::new();
+    for (branch, instr) in instrs.iter().enumerate() {
+        if !matches!(instr.mnemonic, "bne" | "bpl") {
+            continue;
+        }
+        let head = match instr
+            .target()
+            .and_then(|target| instrs[..branch].iter().position(|i| i.addr == target))
+        {
+            Option::Some(head) => head,
+            Option::None => continue,
+        };
+        if let Option::Some(found) = counted_loop(instrs, head, branch) {
+            result.push(found);
+        }
+    }
+    return result;
+}
+
+fn counted_loop(instrs: &[DecodedInstruction], head: usize, branch: usize) -> Option<CountedLoop> {
+    // the step and, counting up, the compare right before the branch
+    let (step, end) = match instrs[..branch] {
+        [.., ref step, ref cmp] if matches!(cmp.mnemonic, "cpx" | "cpy") => {
+            if cmp.mode != AddressingMode::Immediate || instrs[branch].mnemonic != "bne" {
+                return Option::None;
+            }
+            (step, Option::Some(cmp.operand as u8))
+        }
+        [.., ref step] => (step, Option::None),
+        _ => return Option::None,
+    };
+    let (register, up) = match step.mnemonic {
+        "dex" => (Register::X, false),
+        "dey" => (Register::Y, false),
+        "inx" => (Register::X, true),
+        "iny" => (Register::Y, true),
+        _ => return Option::None,
+    };
+    if up != end.is_some() {
+        return Option::None;
+    }
+    let last_step = branch - if up { 2 } else { 1 };
+    if last_step < head {
+        return Option::None;
+    }
+    // `inx / inx` for tables of words
+    let stride = if last_step > head && instrs[last_step - 1].mnemonic == step.mnemonic {
+        2
+    } else {
+        1
+    };
+    let step_index = last_step + 1 - stride;
+    let body = &instrs[head..step_index];
+    if body
+        .iter()
+        .any(|i| writes(i, register) || i.mnemonic == "jsr")
+    {
+        return Option::None;
+    }
+
+    // the load of the counter, before the loop starts
+    let setup_start = head.saturating_sub(MAX_SETUP_INSTRUCTIONS);
+    let load = instrs[setup_start..head]
+        .iter()
+        .rev()
+        .find(|i| writes(i, register))?;
+    let load_mnemonic = match register {
+        Register::X => "ldx",
+        Register::Y => "ldy",
+    };
+    if load.mnemonic != load_mnemonic || load.mode != AddressingMode::Immediate {
+        return Option::None;
+    }
+    let start = load.operand as u8;
+
+    let iterations = match (end, instrs[branch].mnemonic) {
+        (Option::Some(end), _) => wrap(end.wrapping_sub(start)) / stride,
+        (Option::None, "bne") => wrap(start) / stride,
+        // counting down to 0 with bpl only works from below $80
+        (Option::None, _) if start < 0x80 => start as usize / stride + 1,
+        _ => return Option::None,
+    };
+    // counting by 2 has to hit the end, not step over it
+    let hits_end = match (end, instrs[branch].mnemonic) {
+        (Option::Some(end), _) => (end.wrapping_sub(start) as usize).is_multiple_of(stride),
+        (Option::None, "bne") => (start as usize).is_multiple_of(stride),
+        _ => true,
+    };
+    if !hits_end {
+        return Option::None;
+    }
+    // lowest index the body runs with
+    let lowest = if up {
+        start as usize
+    } else if instrs[branch].mnemonic == "bne" {
+        stride
+    } else {
+        start as usize % stride
+    };
+
+    return Option::Some(CountedLoop {
+        head,
+        branch,
+        iterations,
+        copy: find_copy(body, register)
+            .filter(|_| stride == 1)
+            .map(|(src, dst)| Copy {
+                src: src.wrapping_add(lowest as u16),
+                dst: dst.wrapping_add(lowest as u16),
+                len: iterations,
+            }),
+        tables: find_tables(body, register, stride)
+            .into_iter()
+            .map(|(addr, words)| Table {
+                addr: addr.wrapping_add(lowest as u16),
+                len: if words {
+                    iterations * 2
+                } else {
+                    (iterations - 1) * stride + 1
+                },
+                words,
+            })
+            .collect(),
+    });
+}
+
+/// 0 iterations means the counter wraps around all 256 values.
+fn wrap(n: u8) -> usize {
+    return if n == 0 { 256 } else { n as usize };
+}
+
+/// A byte loaded indexed by `register` and stored indexed by it.
+fn find_copy(body: &[DecodedInstruction], register: Register) -> Option<(u16, u16)> {
+    let indexed = match register {
+        Register::X => AddressingMode::AbsoluteX,
+        Register::Y => AddressingMode::AbsoluteY,
+    };
+    let load = body
+        .iter()
+        .position(|i| i.mnemonic == "lda" && i.mode == indexed)?;
+    let store = body[load + 1..]
+        .iter()
+        .find(|i| i.mnemonic == "sta" && i.mode == indexed)?;
+    return Option::Some((body[load].operand, store.operand));
+}
+
+/// Addresses `body` reads indexed by `register`, and whether the byte after each
+/// is read as well, making it a table of words when the counter steps by 2.
+fn find_tables(body: &[DecodedInstruction], register: Register, step: usize) -> Vec<(u16, bool)> {
+    let indexed = match register {
+        Register::X => AddressingMode::AbsoluteX,
+        Register::Y => AddressingMode::AbsoluteY,
+    };
+    let reads = body
+        .iter()
+        .filter(|i| {
+            i.mode == indexed
+                && matches!(
+                    i.mnemonic,
+                    "lda" | "ldx" | "ldy" | "cmp" | "adc" | "sbc" | "and" | "ora" | "eor"
+                )
+        })
+        .map(|i| i.operand)
+        .collect::<BTreeSet<u16>>();
+    let mut result = Vec::new();
+    for addr in &reads {
+        let words = step == 2 && reads.contains(&addr.wrapping_add(1));
+        if step == 2 && reads.contains(&addr.wrapping_sub(1)) {
+            // the high byte of the word before
+            continue;
+        }
+        result.push((*addr, words));
+    }
+    return result;
+}
+
+fn writes(instr: &DecodedInstruction, register: Register) -> bool {
+    return match register {
+        Register::X => matches!(instr.mnemonic, "ldx" | "tax" | "tsx" | "inx" | "dex"),
+        Register::Y => matches!(instr.mnemonic, "ldy" | "tay" | "iny" | "dey"),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decode::decode;
+
+    fn decode_all(bytes: &[u8], addr: u16) -> Vec<DecodedInstruction> {
+        let mut result = Vec::new();
+        let mut i = 0;
+        while i < bytes.len() {
+            let instr = decode(&bytes[i..], addr + i as u16).unwrap();
+            i += instr.size();
+            result.push(instr);
+        }
+        return result;
+    }
+
+    #[test]
+    fn test_find_counted_loops() {
+        // ldx #$00; loop: lda $8100,x; sta $0200,x; inx; cpx #$04; bne loop
+        let up = decode_all(
+            &[
+                0xa2, 0x00, 0xbd, 0x00, 0x81, 0x9d, 0x00, 0x02, 0xe8, 0xe0, 0x04, 0xd0, 0xf5,
+            ],
+            0x8000,
+        );
+        let loops = find_counted_loops(&up);
+        assert_eq!(loops.len(), 1);
+        assert_eq!(loops[0].head, 1);
+        assert_eq!(
+            loops[0].describe(),
+            "loop 4 times, copies 4 bytes from $8100 to $0200"
+        );
+
+        // ldy #$10; loop: lda $0300,y; sta $0400,y; dey; bne loop
+        let down = decode_all(
+            &[
+                0xa0, 0x10, 0xb9, 0x00, 0x03, 0x99, 0x00, 0x04, 0x88, 0xd0, 0xf7,
+            ],
+            0x8000,
+        );
+        assert_eq!(
+            find_counted_loops(&down)[0].describe(),
+            "loop 16 times, copies 16 bytes from $0301 to $0401"
+        );
+
+        // ldx #$07; loop: dex; bpl loop
+        let bpl = decode_all(&[0xa2, 0x07, 0xca, 0x10, 0xfd], 0x8000);
+        assert_eq!(find_counted_loops(&bpl)[0].describe(), "loop 8 times");
+
+        assert_eq!(
+            find_counted_loops(&down)[0].tables,
+            vec![Table {
+                addr: 0x0301,
+                len: 16,
+                words: false
+            }]
+        );
+
+        // ldx #$00; loop: lda $8100,x; sta $10; lda $8101,x; sta $11; inx; inx;
+        // cpx #$06; bne loop
+        let words = decode_all(
+            &[
+                0xa2, 0x00, 0xbd, 0x00, 0x81, 0x85, 0x10, 0xbd, 0x01, 0x81, 0x85, 0x11, 0xe8, 0xe8,
+                0xe0, 0x06, 0xd0, 0xf0,
+            ],
+            0x8000,
+        );
+        let loops = find_counted_loops(&words);
+        assert_eq!(loops[0].describe(), "loop 3 times");
+        assert_eq!(
+            loops[0].tables,
+            vec![Table {
+                addr: 0x8100,
+                len: 6,
+                words: true
+            }]
+        );
+
+        // ldx #$07; loop: jsr $9000; dex; bne loop
+        let call = decode_all(&[0xa2, 0x07, 0x20, 0x00, 0x90, 0xca, 0xd0, 0xfa], 0x8000);
+        assert!(find_counted_loops(&call).is_empty());
+    }
+}
@@ -0,0 +1,185 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::{banks::crc32, DisassembleError};
+
+/// Name of the manifest in a project directory.
+pub const MANIFEST_FILE: &str = ".sixtyfive-manifest.json";
+
+/// The files `d` wrote into a project directory, and the ROM `assemble` built
+/// from them, with the CRC-32 they were written with, so `clean`, `d` and
+/// `assemble` can tell them from files written or edited by hand.
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// command line of the `d` that wrote the files
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+    /// CRC-32 of each file, keyed by its path relative to the directory
+    pub files: BTreeMap<String, u32>,
+}
+
+impl Manifest {
+    /// The manifest in `dir`, empty if there isn't one.
+    pub fn read(dir: &Path) -> Result<Manifest, DisassembleError> {
+        let path = dir.join(MANIFEST_FILE);
+        let json = match fs::read_to_string(&path) {
+            Result::Ok(json) => json,
+            Result::Err(err) if err.kind() == ErrorKind::NotFound => {
+                return Result::Ok(Manifest::default());
+            }
+            Result::Err(err) => return Result::Err(err.into()),
+        };
+        return serde_json::from_str(&json)
+            .map_err(|err| DisassembleError::ParseError(format!("{}: {}", path.display(), err)));
+    }
+
+    /// Writes the manifest to `dir`, removing it once no file is left in it.
+    pub fn write(&self, dir: &Path) -> Result<(), DisassembleError> {
+        let path = dir.join(MANIFEST_FILE);
+        if self.files.is_empty() {
+            return match fs::remove_file(&path) {
+                Result::Err(err) if err.kind() != ErrorKind::NotFound => Result::Err(err.into()),
+                _ => Result::Ok(()),
+            };
+        }
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|err| DisassembleError::WrappedError(err.to_string()))?;
+        writeln!(fs::File::create(path)?, "{}", json)?;
+        return Result::Ok(());
+    }
+
+    /// Records `file` as generated with the contents it has now. Files outside
+    /// `dir` aren't part of the project and are left out.
+    pub fn record(&mut self, dir: &Path, file: &Path) -> Result<(), DisassembleError> {
+        if let Result::Ok(relative) = file.strip_prefix(dir) {
+            let crc = crc32(&fs::read(file)?);
+            self.files
+                .insert(relative.to_string_lossy().to_string(), crc);
+        }
+        return Result::Ok(());
+    }
+
+    /// The `files` in `dir` that writing them again would overwrite hand edits in:
+    /// ones changed since they were generated and ones the manifest doesn't list.
+    pub fn changed(&self, dir: &Path, files: &[PathBuf]) -> Result<Vec<PathBuf>, DisassembleError> {
+        let mut changed = Vec::new();
+        for file in files {
+            let relative = match file.strip_prefix(dir) {
+                Result::Ok(relative) => relative.to_string_lossy().to_string(),
+                Result::Err(_) => continue,
+            };
+            let data = match fs::read(file) {
+                Result::Ok(data) => data,
+                Result::Err(err) if err.kind() == ErrorKind::NotFound => continue,
+                Result::Err(err) => return Result::Err(err.into()),
+            };
+            if self.files.get(&relative) != Option::Some(&crc32(&data)) {
+                changed.push(file.clone());
+            }
+        }
+        return Result::Ok(changed);
+    }
+}
+
+/// What `clean` did with a file of the manifest.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Cleaned {
+    Removed(PathBuf),
+    /// changed since it was generated
+    Kept(PathBuf),
+}
+
+/// Removes the files in `dir` the manifest lists and that are still as they were
+/// generated, unless `dry_run`. Ones already gone are dropped from the manifest,
+/// changed ones stay in it.
+pub fn clean_dir(dir: &Path, dry_run: bool) -> Result<Vec<Cleaned>, DisassembleError> {
+    let mut manifest = Manifest::read(dir)?;
+    if manifest.files.is_empty() {
+        return Result::Err(DisassembleError::ParseError(format!(
+            "no {} in {}, nothing was generated there",
+            MANIFEST_FILE,
+            dir.display()
+        )));
+    }
+    let mut cleaned = Vec::new();
+    for (file, crc) in manifest.files.clone() {
+        let path = dir.join(&file);
+        let data = match fs::read(&path) {
+            Result::Ok(data) => data,
+            Result::Err(err) if err.kind() == ErrorKind::NotFound => {
+                manifest.files.remove(&file);
+                continue;
+            }
+            Result::Err(err) => return Result::Err(err.into()),
+        };
+        if crc32(&data) != crc {
+            cleaned.push(Cleaned::Kept(path));
+            continue;
+        }
+        if !dry_run {
+            fs::remove_file(&path)?;
+        }
+        manifest.files.remove(&file);
+        cleaned.push(Cleaned::Removed(path));
+    }
+    if !dry_run {
+        manifest.write(dir)?;
+    }
+    return Result::Ok(cleaned);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clean_dir() {
+        let dir = std::env::temp_dir().join(format!("sixtyfive-clean-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut manifest = Manifest::default();
+        for name in ["game.s", "game.cfg", "REPORT.md"] {
+            fs::write(dir.join(name), name).unwrap();
+            manifest.record(&dir, &dir.join(name)).unwrap();
+        }
+        manifest.record(&dir, Path::new("/elsewhere.s")).unwrap();
+        manifest.write(&dir).unwrap();
+        assert_eq!(Manifest::read(&dir).unwrap(), manifest);
+
+        fs::write(dir.join("game.s"), "edited").unwrap();
+        fs::write(dir.join("notes.txt"), "by hand").unwrap();
+        let outputs = ["game.s", "game.cfg", "notes.txt", "game.json"].map(|f| dir.join(f));
+        assert_eq!(
+            manifest.changed(&dir, &outputs).unwrap(),
+            [dir.join("game.s"), dir.join("notes.txt")]
+        );
+        let cleaned = clean_dir(&dir, false).unwrap();
+        assert_eq!(
+            cleaned,
+            [
+                Cleaned::Removed(dir.join("REPORT.md")),
+                Cleaned::Removed(dir.join("game.cfg")),
+                Cleaned::Kept(dir.join("game.s")),
+            ]
+        );
+        assert!(dir.join("game.s").exists() && dir.join("notes.txt").exists());
+        assert_eq!(
+            Manifest::read(&dir)
+                .unwrap()
+                .files
+                .keys()
+                .collect::<Vec<_>>(),
+            ["game.s"]
+        );
+
+        fs::remove_file(dir.join("game.s")).unwrap();
+        assert!(clean_dir(&dir, false).unwrap().is_empty());
+        assert!(!dir.join(MANIFEST_FILE).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
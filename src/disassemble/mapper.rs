@@ -0,0 +1,132 @@
+use std::{path::Path, sync::Mutex};
+
+use super::{
+    address::{BankedAddr, CpuAddr, FileOffset},
+    nes_header::NesHeader,
+    DisassembleError,
+};
+
+/// Translates between CPU addresses and positions in the input file while
+/// following code.
+pub trait Mapper {
+    /// Where the byte the CPU reads at `addr` is in the file, `None` for addresses
+    /// the file doesn't hold, RAM, registers and cartridge RAM among them.
+    fn to_offset(&self, addr: CpuAddr) -> Option<FileOffset>;
+
+    fn to_addr(&self, offset: FileOffset) -> CpuAddr;
+
+    fn to_banked_addr(&self, offset: FileOffset) -> BankedAddr;
+}
+
+/// Builds the mapper for a ROM from its header.
+pub type MapperFactory = fn(&NesHeader) -> Box<dyn Mapper>;
+
+/// Symbol a mapper plugin exports, a `fn(&mut dyn FnMut(u16, &str, MapperFactory))`
+/// calling the function it is given once per mapper it implements.
+pub const PLUGIN_REGISTER_SYMBOL: &[u8] = b"sixtyfive_register_mappers";
+
+/// Mappers registered on top of the built-in NROM layout: iNES mapper number,
+/// name and factory. Later registrations of a number win.
+static REGISTRY: Mutex<Vec<(u16, String, MapperFactory)>> = Mutex::new(Vec::new());
+
+/// Makes ROMs with iNES mapper `number` use the mapper `factory` builds. Programs
+/// using the library register their mappers before disassembling, the CLI
+/// registers those of the plugins given with `--mapper-plugin`.
+pub fn register_mapper(number: u16, name: &str, factory: MapperFactory) {
+    let mut registry = REGISTRY.lock().unwrap_or_else(|err| err.into_inner());
+    registry.push((number, name.to_string(), factory));
+}
+
+/// Number and name of the registered mappers.
+pub fn registered_mappers() -> Vec<(u16, String)> {
+    let registry = REGISTRY.lock().unwrap_or_else(|err| err.into_inner());
+    return registry
+        .iter()
+        .map(|(number, name, _)| (*number, name.clone()))
+        .collect();
+}
+
+pub(crate) fn mapper_factory(number: u16) -> Option<MapperFactory> {
+    let registry = REGISTRY.lock().unwrap_or_else(|err| err.into_inner());
+    return registry
+        .iter()
+        .rev()
+        .find(|(n, _, _)| *n == number)
+        .map(|(_, _, factory)| *factory);
+}
+
+/// Loads the shared library at `path` and registers the mappers it exports, see
+/// [`PLUGIN_REGISTER_SYMBOL`]. Mappers are Rust trait objects so the plugin has to
+/// be built with the same compiler and version of this crate. Returns the names
+/// of the mappers registered.
+#[cfg(feature = "plugins")]
+pub fn load_mapper_plugin(path: &Path) -> Result<Vec<String>, DisassembleError> {
+    type RegisterFn = fn(&mut dyn FnMut(u16, &str, MapperFactory));
+
+    let error = |err: libloading::Error| {
+        DisassembleError::ParseError(format!("can't load {}: {}", path.display(), err))
+    };
+    let mut names = Vec::new();
+    // SAFETY: the plugin is trusted like any code the user runs, the symbol has
+    // the documented signature
+    unsafe {
+        let lib = libloading::Library::new(path).map_err(error)?;
+        let register = lib
+            .get::<RegisterFn>(PLUGIN_REGISTER_SYMBOL)
+            .map_err(error)?;
+        register(&mut |number, name, factory| {
+            register_mapper(number, name, factory);
+            names.push(name.to_string());
+        });
+        // the factories and the mappers they build point into the library
+        std::mem::forget(lib);
+    }
+    return Result::Ok(names);
+}
+
+#[cfg(not(feature = "plugins"))]
+pub fn load_mapper_plugin(path: &Path) -> Result<Vec<String>, DisassembleError> {
+    return Result::Err(DisassembleError::ParseError(format!(
+        "can't load {}, mapper plugins need the plugins feature",
+        path.display()
+    )));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct OffsetMapper;
+
+    impl Mapper for OffsetMapper {
+        fn to_offset(&self, addr: CpuAddr) -> Option<FileOffset> {
+            return Option::Some(FileOffset(addr.0 as usize));
+        }
+
+        fn to_addr(&self, offset: FileOffset) -> CpuAddr {
+            return CpuAddr(offset.0 as u16);
+        }
+
+        fn to_banked_addr(&self, offset: FileOffset) -> BankedAddr {
+            return BankedAddr {
+                bank: 0,
+                addr: self.to_addr(offset),
+            };
+        }
+    }
+
+    #[test]
+    fn test_register_mapper() {
+        assert!(mapper_factory(0xffe).is_none());
+        register_mapper(0xffe, "offset", |_header| Box::new(OffsetMapper));
+        assert!(registered_mappers().contains(&(0xffe, "offset".to_string())));
+        let mut rom = vec![0; 16 + 16 * 1024];
+        rom[..5].copy_from_slice(&[0x4e, 0x45, 0x53, 0x1a, 1]);
+        let header = NesHeader::parse(&rom).unwrap();
+        let mapper = mapper_factory(0xffe).unwrap()(&header);
+        assert_eq!(
+            mapper.to_offset(CpuAddr(0x8123)),
+            Option::Some(FileOffset(0x8123))
+        );
+    }
+}
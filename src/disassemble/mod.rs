@@ -1,22 +1,387 @@
+mod access;
+mod address;
+mod arithmetic;
+mod banks;
+mod budget;
+mod cc65;
+mod checksum;
+mod code;
+mod cpu;
+mod diagnostic;
+mod dialect;
 mod disassembler;
+mod graphics;
+mod hexdump;
+mod html;
+mod huc6280;
+mod image_disassembler;
+mod instruction;
+mod intern;
+mod interrupts;
+mod linker_configs;
+mod loops;
+mod manifest;
+mod mapper;
 mod nes_disassembler;
-mod code;
+mod nes_header;
+mod passes;
+mod platform;
+mod progress;
+mod registers;
+mod repl;
+mod serve;
+mod signatures;
+mod stubs;
+mod sweep;
+mod template;
+mod text;
 mod variable;
-mod instruction;
 
 use std::{
+    collections::BTreeMap,
     fmt,
     fs::File,
     io::{BufReader, Read, Write},
-    path::PathBuf,
+    path::{Path, PathBuf},
+    str::FromStr,
 };
 
-use self::nes_disassembler::NesDisassembler;
+use crate::decode::{decode, decode_opcode, AddressingMode};
+use crate::decode65816::{Decoder65816, Flags};
+use crate::decode_huc6280::{decode_huc6280, ModeHuC6280};
+
+use self::image_disassembler::ImageDisassembler;
+use self::manifest::{clean_dir, Cleaned};
+use self::nes_header::{ARCHAIC_INES_NOTE, NES_HEADER_LENGTH};
+use self::progress::StderrProgress;
+
+pub use self::address::{BankedAddr, CpuAddr, FileOffset};
+pub use self::checksum::{find_checksum_routines, ChecksumRoutine};
+pub use self::cpu::{CpuVariant, Quirks};
+pub use self::diagnostic::{Diagnostic, DiagnosticsFormat, Severity};
+pub use self::dialect::{AsmDialect, Dialect};
+pub use self::graphics::{Interleave, TileFormat};
+pub use self::instruction::Instruction;
+pub use self::linker_configs::{builtin_linker_config, BUILTIN_LINKER_CONFIGS};
+pub use self::manifest::{Manifest, MANIFEST_FILE};
+pub use self::mapper::{
+    load_mapper_plugin, register_mapper, registered_mappers, Mapper, MapperFactory,
+    PLUGIN_REGISTER_SYMBOL,
+};
+pub use self::nes_disassembler::NesDisassembler;
+pub use self::nes_header::{ConsoleType, HeaderFormat, Mirroring, NesHeader, Timing};
+pub use self::passes::{Pass, PassStats};
+pub use self::platform::{AccessPattern, NamingRule, Platform};
+pub use self::progress::{NoProgress, Progress};
+pub use self::repl::{repl, ReplOptions};
+pub use self::serve::{serve, ServeOptions, DEFAULT_SERVE_PORT};
+pub use self::signatures::parse_signatures;
+pub use self::sweep::DEFAULT_SWEEP_THRESHOLD;
+pub use self::template::{load_template, OutputTemplate, TemplateContext};
+pub use self::text::{parse_charmap, DEFAULT_TEXT_MIN_LEN};
 
 #[derive(Debug)]
 pub struct DisassembleOptions {
     pub in_file: Option<PathBuf>,
     pub out_file: Option<PathBuf>,
+    pub json_out_file: Option<PathBuf>,
+    /// Mesen label file (.mlb)
+    pub symbols_out_file: Option<PathBuf>,
+    /// FCEUX name lists, written to this path with `.ram.nl` and `.<bank>.nl`
+    /// appended, see [`NesDisassembler::fceux_name_lists`]
+    pub fceux_nl_base: Option<PathBuf>,
+    /// JSON map from lines of `out_file` back to ROM offsets
+    pub source_map_file: Option<PathBuf>,
+    /// which subroutines call which, as Graphviz DOT for `.dot`/`.gv` files and as
+    /// JSON otherwise
+    pub call_graph_file: Option<PathBuf>,
+    /// list of the PRG ROM regions no entry point reaches
+    pub unreached_file: Option<PathBuf>,
+    /// what the decoded instructions read, write and execute at each address
+    pub access_map_file: Option<PathBuf>,
+    /// PGM image of the tiles of the CHR ROM and the ones copied to CHR RAM
+    pub tiles_file: Option<PathBuf>,
+    /// ld65 config for linking the assembly back to the ROM, set to one next to the
+    /// assembly when `out_file` is a directory
+    pub linker_config_file: Option<PathBuf>,
+    /// `REPORT.md` summarizing the analysis for whoever picks up the reverse
+    /// engineering, set to one next to the assembly when `out_file` is a directory
+    pub report_file: Option<PathBuf>,
+    /// assembler `out_file` is written for, the platform's if not given, see
+    /// [`Platform::dialect`]
+    pub dialect: Option<Dialect>,
+    /// whether `out_file` is assembly, or the statements as JSON or HTML
+    pub format: OutputFormat,
+    /// write `out_file` as a listing, each line after the address and bytes it was
+    /// decoded from
+    pub listing: bool,
+    /// keep the labels in one namespace rather than each segment's in a scope
+    pub flat_symbols: bool,
+    /// write each PRG ROM bank and the hardware register constants to files of
+    /// their own next to `out_file`, which includes them, named `main` in a
+    /// directory
+    pub split_banks: bool,
+    pub diagnostics_format: DiagnosticsFormat,
+    /// shared libraries with mappers to register before disassembling
+    pub mapper_plugins: Vec<PathBuf>,
+    /// directory of Handlebars templates for the header, footer, segment banners
+    /// and labels of the assembly, see [`template::load_template`]
+    pub template_dir: Option<PathBuf>,
+    /// character map for the text, see [`parse_charmap`], read into
+    /// [`AnalysisOptions::charmap`]
+    pub charmap_file: Option<PathBuf>,
+    /// routines to recognize by their bytes, see [`parse_signatures`], read into
+    /// [`AnalysisOptions::signatures`]
+    pub signatures_file: Option<PathBuf>,
+    /// write how far the analysis got to stderr
+    pub progress: bool,
+    /// print what would be disassembled and written instead of doing it
+    pub dry_run: bool,
+    /// overwrite files of an output directory that were edited since they were
+    /// generated, or weren't generated at all
+    pub force: bool,
+    /// command line the options came from, recorded in an output directory's
+    /// manifest
+    pub args: Vec<String>,
+    pub analysis: AnalysisOptions,
+}
+
+/// What the disassembly is written as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    /// assembly in the `--dialect` asked for
+    #[default]
+    Asm,
+    /// an array of the statements with their address, bytes, mnemonic, operands,
+    /// label, comment and segment, for tools that would otherwise parse the
+    /// assembly
+    Json,
+    /// a page to browse, the labels linked to where they are defined
+    Html,
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            OutputFormat::Asm => write!(f, "asm"),
+            OutputFormat::Json => write!(f, "json"),
+            OutputFormat::Html => write!(f, "html"),
+        };
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        return match s {
+            "asm" => Result::Ok(OutputFormat::Asm),
+            "json" => Result::Ok(OutputFormat::Json),
+            "html" => Result::Ok(OutputFormat::Html),
+            _ => Result::Err(format!(
+                "unknown format \"{}\", expected asm, json or html",
+                s
+            )),
+        };
+    }
+}
+
+/// Settings for the analysis itself rather than for what gets written.
+#[derive(Debug, Clone, Default)]
+pub struct AnalysisOptions {
+    /// score the PRG ROM no entry point reaches, regions scoring at least this are
+    /// reported as likely code
+    pub sweep_threshold: Option<f64>,
+    /// disassemble the unreached PRG ROM scoring at least `sweep_threshold` as
+    /// code after following the vectors
+    pub sweep: bool,
+    /// write small data tables read by a single routine right after it
+    pub inline_tables: bool,
+    /// name tables and RAM by how they are used, see [`Platform::naming_rules`]
+    pub name_data: bool,
+    /// write runs of unclaimed bytes reading as text as strings
+    pub text: bool,
+    /// the analysis passes to run, in order, [`Pass::defaults`] if empty
+    pub passes: Vec<Pass>,
+    /// shortest run written as a string, [`DEFAULT_TEXT_MIN_LEN`] if not given
+    pub text_min_len: Option<usize>,
+    /// the character each byte of text stands for, printable ASCII if empty
+    pub charmap: BTreeMap<u8, char>,
+    /// routines to recognize besides the built-in ones, name and pattern, see
+    /// [`parse_signatures`]
+    pub signatures: Vec<(String, String)>,
+    /// fail instead of warning when a byte is claimed by two instructions or by
+    /// code and data
+    pub strict: bool,
+    /// comment where the entry points code is reached from change
+    pub reached_from_comments: bool,
+    /// comment labels with the addresses of the jumps, branches and calls to them
+    pub xref_comments: bool,
+    /// write the subroutines called with `jsr` as ca65 `.proc`s
+    pub procs: bool,
+    /// write the bytes of each routine as `.verify` for the assembler to check
+    pub verify: bool,
+    /// comment instructions with the cycles they take
+    pub cycle_comments: bool,
+    /// comment the end of short basic blocks with what they compute into what they
+    /// store
+    pub expression_comments: bool,
+    /// name RAM an interrupt handler writes and the main code reads
+    pub shared_variables: bool,
+    pub illegal_opcodes: IllegalOpcodes,
+    pub jam_opcodes: JamOpcodes,
+    /// 6502 variant whose quirks apply, otherwise the platform's
+    pub cpu_variant: Option<CpuVariant>,
+    /// how the tiles of the graphics are laid out, otherwise the platform's, see
+    /// [`Platform::tile_format`]
+    pub tile_format: Option<TileFormat>,
+    /// write only the code reached from this vector, entry point or address and
+    /// the data it reads
+    pub only_from: Option<String>,
+    /// addresses or `start..end` ranges whose operands are written as numbers
+    pub unnamed: Vec<String>,
+    /// labels or addresses to `.export` besides the entry points
+    pub exports: Vec<String>,
+    /// `address:entries` of the tables of words pointing at code to follow
+    pub vector_tables: Vec<String>,
+    /// `tag=address` pairs tagging the routine or table at the address
+    pub tags: Vec<String>,
+    /// write only what has one of these tags
+    pub include_tags: Vec<String>,
+    /// leave out what has one of these tags
+    pub exclude_tags: Vec<String>,
+}
+
+/// What to do when code being followed runs into an undocumented opcode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IllegalOpcodes {
+    /// stop following the code there with a warning
+    #[default]
+    Stop,
+    /// decode it and write it with its ca65 `6502X` mnemonic
+    Mnemonics,
+    /// decode it and write it as `.byte` with the mnemonic as a comment
+    Bytes,
+}
+
+impl FromStr for IllegalOpcodes {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        return match s {
+            "stop" => Result::Ok(IllegalOpcodes::Stop),
+            "mnemonics" => Result::Ok(IllegalOpcodes::Mnemonics),
+            "bytes" => Result::Ok(IllegalOpcodes::Bytes),
+            _ => Result::Err(format!(
+                "unknown illegal opcode handling \"{}\", expected stop, mnemonics or bytes",
+                s
+            )),
+        };
+    }
+}
+
+/// What to do when code being followed runs into one of the opcodes that halt the
+/// CPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum JamOpcodes {
+    /// write it with the ca65 `6502X` mnemonic `jam`, or as `.byte` for the opcodes
+    /// `jam` doesn't assemble to, and stop following the code
+    #[default]
+    Stop,
+    /// write it as `.byte` and go on with the byte after it
+    Continue,
+    /// leave it as data and stop following the code with a warning
+    Data,
+}
+
+impl FromStr for JamOpcodes {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        return match s {
+            "stop" => Result::Ok(JamOpcodes::Stop),
+            "continue" => Result::Ok(JamOpcodes::Continue),
+            "data" => Result::Ok(JamOpcodes::Data),
+            _ => Result::Err(format!(
+                "unknown jam opcode handling \"{}\", expected stop, continue or data",
+                s
+            )),
+        };
+    }
+}
+
+#[derive(Debug)]
+pub struct InfoOptions {
+    pub in_file: Option<PathBuf>,
+}
+
+#[derive(Debug)]
+pub struct HeaderOptions {
+    pub in_file: PathBuf,
+    pub out_file: Option<PathBuf>,
+    /// `field=value` pairs, see [`NesHeader::set`]
+    pub set: Vec<String>,
+    pub nes2: bool,
+}
+
+#[derive(Debug)]
+pub struct CleanOptions {
+    /// project directory `d` wrote into
+    pub dir: PathBuf,
+    /// print what would be removed instead of removing it
+    pub dry_run: bool,
+}
+
+#[derive(Debug)]
+pub struct BanksOptions {
+    pub in_file: PathBuf,
+    pub out_file: Option<PathBuf>,
+    /// bank to write on its own, e.g. `prg:3`
+    pub extract: Option<String>,
+    /// `bank=path` pairs, e.g. `chr:0=font.chr`
+    pub replace: Vec<String>,
+    /// new order of the PRG or CHR banks, e.g. `prg:1,0,3,2`
+    pub order: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct ListOptions {
+    pub in_file: Option<PathBuf>,
+    pub out_file: Option<PathBuf>,
+    pub cpu: Cpu,
+    /// address of the first byte, with the bank in bits 16-23 for the 65816
+    pub org: u32,
+    /// start with a 16-bit accumulator, 65816 only
+    pub m16: bool,
+    /// start with 16-bit index registers, 65816 only
+    pub x16: bool,
+}
+
+/// Instruction set a raw binary is listed with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Cpu {
+    #[default]
+    Mos6502,
+    /// SNES and Apple IIgs, register widths are tracked through `rep` and `sep`
+    W65816,
+    /// PC Engine, hardware registers are named and MPR banking followed
+    HuC6280,
+}
+
+impl FromStr for Cpu {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        return match s {
+            "6502" => Result::Ok(Cpu::Mos6502),
+            "65816" | "65c816" => Result::Ok(Cpu::W65816),
+            "huc6280" => Result::Ok(Cpu::HuC6280),
+            _ => Result::Err(format!(
+                "unknown cpu \"{}\", expected 6502, 65816 or huc6280",
+                s
+            )),
+        };
+    }
 }
 
 #[derive(Debug)]
@@ -25,6 +390,8 @@ pub enum DisassembleError {
     IoError(std::io::Error),
     ParseError(String),
     UnhandledInstruction(u8),
+    /// a byte claimed twice in strict mode
+    Overlap(String),
     WrappedError(String),
 }
 
@@ -43,22 +410,704 @@ impl fmt::Display for DisassembleError {
             DisassembleError::UnhandledInstruction(v) => {
                 write!(f, "unhandled instruction: ${:02x}", v)
             }
+            DisassembleError::Overlap(msg) => write!(f, "overlap: {}", msg),
             DisassembleError::WrappedError(msg) => write!(f, "{}", msg),
         }
     }
 }
 
-pub fn disassemble(opts: DisassembleOptions) -> Result<(), DisassembleError> {
+pub fn disassemble(mut opts: DisassembleOptions) -> Result<(), DisassembleError> {
+    let file_name = display_name(&opts.in_file);
+    let platform = opts
+        .in_file
+        .as_deref()
+        .and_then(Platform::from_path)
+        .unwrap_or(Platform::Nes);
+    let dialect = opts.dialect.unwrap_or(platform.dialect());
+    let nes = platform == Platform::Nes;
+    // an output directory gets the ROM's name with the dialect's extension and, for
+    // NES ROMs, the linker config and the report, listed in its manifest
+    let mut project_dir = Option::None;
+    if let (Option::Some(out_file), Option::Some(in_file)) = (&opts.out_file, &opts.in_file) {
+        if out_file.is_dir() {
+            project_dir = Option::Some(out_file.clone());
+            if nes {
+                opts.report_file
+                    .get_or_insert_with(|| out_file.join("REPORT.md"));
+            }
+            let asm_file = match opts.split_banks {
+                true => out_file.join("main"),
+                false => platform.out_file(out_file, in_file),
+            };
+            let asm_file = match (opts.format, opts.listing) {
+                (OutputFormat::Json, _) => asm_file.with_extension("json"),
+                (OutputFormat::Html, _) => asm_file.with_extension("html"),
+                (OutputFormat::Asm, true) => asm_file.with_extension("lst"),
+                (OutputFormat::Asm, false) => asm_file.with_extension(dialect.writer().extension()),
+            };
+            // a listing, JSON or HTML doesn't assemble, so there is nothing to link
+            if nes && dialect == Dialect::Ca65 && !opts.listing && opts.format == OutputFormat::Asm
+            {
+                opts.linker_config_file
+                    .get_or_insert_with(|| asm_file.with_extension("cfg"));
+            }
+            opts.out_file = Option::Some(asm_file);
+        }
+    }
+    if !nes {
+        return disassemble_image(opts, platform, dialect, project_dir);
+    }
+    let mut plugin_mappers = Vec::new();
+    for plugin in &opts.mapper_plugins {
+        plugin_mappers.push((plugin, load_mapper_plugin(plugin)?));
+    }
+    if let Option::Some(charmap_file) = &opts.charmap_file {
+        let charmap = std::fs::read_to_string(charmap_file)?;
+        opts.analysis.charmap = parse_charmap(&charmap).map_err(|err| {
+            DisassembleError::ParseError(format!("{}: {}", charmap_file.display(), err))
+        })?;
+    }
+    if let Option::Some(signatures_file) = &opts.signatures_file {
+        let signatures = std::fs::read_to_string(signatures_file)?;
+        opts.analysis.signatures = parse_signatures(&signatures).map_err(|err| {
+            DisassembleError::ParseError(format!("{}: {}", signatures_file.display(), err))
+        })?;
+    }
+    if opts.format != OutputFormat::Asm && (opts.listing || opts.source_map_file.is_some()) {
+        return Result::Err(DisassembleError::ParseError(format!(
+            "--listing and --source-map are for assembly, not --format {}",
+            opts.format
+        )));
+    }
+    if opts.template_dir.is_some() && opts.format != OutputFormat::Asm {
+        return Result::Err(DisassembleError::ParseError(format!(
+            "--template is for assembly, not --format {}",
+            opts.format
+        )));
+    }
+    if opts.split_banks && (opts.format != OutputFormat::Asm || opts.listing) {
+        return Result::Err(DisassembleError::ParseError(
+            "--split-banks writes assembly, not a listing, JSON or HTML".to_string(),
+        ));
+    }
+    if opts.split_banks && (opts.out_file.is_none() || opts.source_map_file.is_some()) {
+        return Result::Err(DisassembleError::ParseError(
+            "--split-banks writes the banks next to the output file given with -o, and \
+             without --source-map"
+                .to_string(),
+        ));
+    }
+    if dialect != Dialect::Ca65 && !opts.analysis.charmap.is_empty() {
+        return Result::Err(DisassembleError::ParseError(format!(
+            "{} has no .charmap for writing text with --charmap",
+            dialect
+        )));
+    }
+    let data = read_file_or_stdin(opts.in_file.clone())?;
+
+    check_handled(&data)?;
+    if opts.dry_run {
+        return write_plan(&opts, platform, &data, &plugin_mappers);
+    }
+    let mut generated = outputs(&opts)
+        .into_iter()
+        .filter_map(|(_, file)| file.cloned())
+        .collect::<Vec<PathBuf>>();
+    check_unchanged(project_dir.as_deref(), &generated, opts.force)?;
+
+    // every output is written from the same analysis
+    let mut d = if opts.progress {
+        NesDisassembler::disassemble_with_progress(data, &opts.analysis, &mut StderrProgress)?
+    } else {
+        NesDisassembler::disassemble_with_options(data, &opts.analysis)?
+    };
+    for diagnostic in d.diagnostics() {
+        eprintln!("{}", diagnostic.format(opts.diagnostics_format, &file_name));
+    }
+    d.set_dialect(dialect.writer());
+    d.set_listing(opts.listing);
+    d.set_scoped(!opts.flat_symbols);
+    if let Option::Some(dir) = &opts.template_dir {
+        let context = d.template_context(&file_name, dialect.writer().name());
+        d.set_template(load_template(dir, context)?);
+    }
+    if opts.format == OutputFormat::Json {
+        d.write_statements_json(&mut open_out_file(opts.out_file)?)?;
+    } else if opts.format == OutputFormat::Html {
+        d.write_html(&mut open_out_file(opts.out_file)?, &file_name)?;
+    } else if let Option::Some(source_map_file) = opts.source_map_file {
+        let file = opts.out_file.as_ref().map(|f| f.display().to_string());
+        d.write_with_source_map(
+            &mut open_out_file(opts.out_file)?,
+            &mut open_out_file(Option::Some(source_map_file))?,
+            file.as_deref(),
+        )?;
+    } else if let (true, Option::Some(out_file)) = (opts.split_banks, &opts.out_file) {
+        // the banks aren't known before the analysis, so neither are their files
+        let writer = dialect.writer();
+        let extension = writer.extension();
+        let dir = out_file.parent().unwrap_or(Path::new("")).to_path_buf();
+        let files = d
+            .split_files(extension)
+            .iter()
+            .map(|file| dir.join(file))
+            .collect::<Vec<PathBuf>>();
+        check_unchanged(project_dir.as_deref(), &files, opts.force)?;
+        let split = d.write_split(&mut open_out_file(opts.out_file.clone())?, extension)?;
+        for (file, data) in split {
+            std::fs::write(dir.join(&file), data)?;
+            generated.push(dir.join(file));
+        }
+    } else {
+        d.write(&mut open_out_file(opts.out_file)?)?;
+    }
+    if let Option::Some(json_out_file) = opts.json_out_file {
+        d.write_json(&mut open_out_file(Option::Some(json_out_file))?)?;
+    }
+    if let Option::Some(symbols_out_file) = opts.symbols_out_file {
+        d.write_symbols(&mut open_out_file(Option::Some(symbols_out_file))?)?;
+    }
+    if let Option::Some(base) = opts.fceux_nl_base {
+        let lists = d.fceux_name_lists();
+        let files = lists
+            .iter()
+            .map(|(suffix, _)| PathBuf::from(format!("{}.{}.nl", base.display(), suffix)))
+            .collect::<Vec<PathBuf>>();
+        check_unchanged(project_dir.as_deref(), &files, opts.force)?;
+        for ((_, list), file) in lists.into_iter().zip(files) {
+            std::fs::write(&file, list)?;
+            generated.push(file);
+        }
+    }
+    if let Option::Some(call_graph_file) = opts.call_graph_file {
+        let dot = matches!(
+            call_graph_file.extension().and_then(|ext| ext.to_str()),
+            Option::Some("dot" | "gv")
+        );
+        d.write_call_graph(&mut open_out_file(Option::Some(call_graph_file))?, dot)?;
+    }
+    if let Option::Some(unreached_file) = opts.unreached_file {
+        d.write_unreached(&mut open_out_file(Option::Some(unreached_file))?)?;
+    }
+    if let Option::Some(access_map_file) = opts.access_map_file {
+        d.write_access_map(&mut open_out_file(Option::Some(access_map_file))?)?;
+    }
+    if let Option::Some(tiles_file) = opts.tiles_file {
+        d.write_tiles(&mut open_out_file(Option::Some(tiles_file))?)?;
+    }
+    if let Option::Some(linker_config_file) = opts.linker_config_file {
+        d.write_linker_config(&mut open_out_file(Option::Some(linker_config_file))?)?;
+    }
+    if let Option::Some(report_file) = opts.report_file {
+        d.write_report(&mut open_out_file(Option::Some(report_file))?, &file_name)?;
+    }
+    if d.has_unhandled_opcodes() {
+        d.write_unhandled_opcodes(&mut std::io::stderr())?;
+    }
+    if let Option::Some(dir) = project_dir {
+        write_manifest(&dir, opts.args, &generated)?;
+    }
+    return Result::Ok(());
+}
+
+/// Disassembles the Atari 2600 cartridge or C64 program `opts.in_file`, for which
+/// only the assembly is written, or a listing of it.
+fn disassemble_image(
+    opts: DisassembleOptions,
+    platform: Platform,
+    dialect: Dialect,
+    project_dir: Option<PathBuf>,
+) -> Result<(), DisassembleError> {
+    let mut nes_only = outputs(&opts)
+        .into_iter()
+        .skip(1)
+        .filter(|(_, file)| file.is_some())
+        .map(|(what, _)| format!("the {}", what))
+        .collect::<Vec<String>>();
+    for (option, given) in [
+        ("--format", opts.format != OutputFormat::Asm),
+        ("--fceux-nl", opts.fceux_nl_base.is_some()),
+        ("--split-banks", opts.split_banks),
+        ("--charmap", opts.charmap_file.is_some()),
+        ("--signatures", opts.signatures_file.is_some()),
+        ("--template", opts.template_dir.is_some()),
+        ("--tile-format", opts.analysis.tile_format.is_some()),
+        ("--mapper-plugin", !opts.mapper_plugins.is_empty()),
+        ("--dry-run", opts.dry_run),
+    ] {
+        if given {
+            nes_only.push(option.to_string());
+        }
+    }
+    if !nes_only.is_empty() {
+        return Result::Err(DisassembleError::ParseError(format!(
+            "only the assembly is written for {} ROMs, not {}",
+            platform,
+            nes_only.join(", ")
+        )));
+    }
+    let file_name = display_name(&opts.in_file);
+    let data = read_file_or_stdin(opts.in_file.clone())?;
+    let generated = opts.out_file.iter().cloned().collect::<Vec<PathBuf>>();
+    check_unchanged(project_dir.as_deref(), &generated, opts.force)?;
+
+    let mut d = ImageDisassembler::disassemble(platform, data, &opts.analysis)?;
+    for diagnostic in d.diagnostics() {
+        eprintln!("{}", diagnostic.format(opts.diagnostics_format, &file_name));
+    }
+    d.set_dialect(dialect.writer());
+    d.set_listing(opts.listing);
+    d.write(&mut open_out_file(opts.out_file)?)?;
+    if let Option::Some(dir) = project_dir {
+        write_manifest(&dir, opts.args, &generated)?;
+    }
+    return Result::Ok(());
+}
+
+/// Records the files generated into the project directory `dir` by the command
+/// line `args` in its manifest.
+fn write_manifest(
+    dir: &Path,
+    args: Vec<String>,
+    generated: &[PathBuf],
+) -> Result<(), DisassembleError> {
+    let mut manifest = Manifest::read(dir)?;
+    manifest.args = args;
+    for file in generated {
+        manifest.record(dir, file)?;
+    }
+    manifest.write(dir)?;
+    return Result::Ok(());
+}
+
+/// Fails if writing `files` into the project directory `dir` would overwrite hand
+/// edits, unless `force`.
+fn check_unchanged(
+    dir: Option<&Path>,
+    files: &[PathBuf],
+    force: bool,
+) -> Result<(), DisassembleError> {
+    let dir = match (dir, force) {
+        (Option::Some(dir), false) => dir,
+        _ => return Result::Ok(()),
+    };
+    let changed = Manifest::read(dir)?.changed(dir, files)?;
+    if !changed.is_empty() {
+        return Result::Err(DisassembleError::ParseError(format!(
+            "{} changed since it was generated, move it away or pass --force to overwrite",
+            changed
+                .iter()
+                .map(|file| file.display().to_string())
+                .collect::<Vec<String>>()
+                .join(", ")
+        )));
+    }
+    return Result::Ok(());
+}
+
+/// Removes what `d` generated into a project directory, keeping the files changed
+/// since.
+pub fn clean(opts: CleanOptions) -> Result<(), DisassembleError> {
+    let mut out = open_out_file(Option::None)?;
+    for cleaned in clean_dir(&opts.dir, opts.dry_run)? {
+        match cleaned {
+            Cleaned::Removed(file) if opts.dry_run => {
+                writeln!(out, "would remove {}", file.display())?
+            }
+            Cleaned::Removed(file) => writeln!(out, "removed {}", file.display())?,
+            Cleaned::Kept(file) => writeln!(
+                out,
+                "kept {}, changed since it was generated",
+                file.display()
+            )?,
+        }
+    }
+    return Result::Ok(());
+}
+
+/// The files [`disassemble`] writes, by what they hold.
+fn outputs(opts: &DisassembleOptions) -> [(&'static str, Option<&PathBuf>); 10] {
+    return [
+        (
+            match opts.format {
+                OutputFormat::Asm => "assembly",
+                OutputFormat::Json => "statements",
+                OutputFormat::Html => "HTML",
+            },
+            opts.out_file.as_ref(),
+        ),
+        ("JSON", opts.json_out_file.as_ref()),
+        ("Mesen labels", opts.symbols_out_file.as_ref()),
+        ("source map", opts.source_map_file.as_ref()),
+        ("call graph", opts.call_graph_file.as_ref()),
+        ("unreached regions", opts.unreached_file.as_ref()),
+        ("access map", opts.access_map_file.as_ref()),
+        ("tiles", opts.tiles_file.as_ref()),
+        ("linker config", opts.linker_config_file.as_ref()),
+        ("report", opts.report_file.as_ref()),
+    ];
+}
+
+/// Prints what [`disassemble`] would read, start from and write for `--dry-run`.
+fn write_plan(
+    opts: &DisassembleOptions,
+    platform: Platform,
+    data: &[u8],
+    plugin_mappers: &[(&PathBuf, Vec<String>)],
+) -> Result<(), DisassembleError> {
+    let header = NesHeader::parse(data)?;
+    let mapper_name = registered_mappers()
+        .into_iter()
+        .rev()
+        .find(|(number, _)| *number == header.mapper)
+        .map_or(
+            "no mapper registered, NROM layout".to_string(),
+            |(_, name)| name,
+        );
+    let mut out = open_out_file(Option::None)?;
+    writeln!(out, "input:         {}", display_name(&opts.in_file))?;
+    writeln!(out, "platform:      {}", platform)?;
+    let writer = opts.dialect.unwrap_or(platform.dialect()).writer();
+    writeln!(
+        out,
+        "syntax:        {} (.{} files)",
+        writer.name(),
+        writer.extension()
+    )?;
+    writeln!(out, "format:        {}", header.format)?;
+    writeln!(
+        out,
+        "mapper:        {}.{} ({})",
+        header.mapper, header.submapper, mapper_name
+    )?;
+    writeln!(
+        out,
+        "banks:         {} x 16k PRG ROM, {} x 8k CHR ROM",
+        header.prg_rom_page_count(),
+        header.chr_rom_page_count()
+    )?;
+    for (plugin, names) in plugin_mappers {
+        writeln!(
+            out,
+            "plugin:        {} ({})",
+            plugin.display(),
+            names.join(", ")
+        )?;
+    }
+    writeln!(out, "entry points:")?;
+    for (name, addr) in NesDisassembler::vectors(data)? {
+        writeln!(out, "  {:<24} ${:04x}", name, addr.0)?;
+    }
+    writeln!(out, "outputs:")?;
+    for (what, file) in outputs(opts) {
+        match (what, file) {
+            (_, Option::Some(file)) => writeln!(out, "  {:<24} {}", what, file.display())?,
+            ("assembly" | "statements" | "HTML", Option::None) => {
+                writeln!(out, "  {:<24} <stdout>", what)?
+            }
+            _ => {}
+        }
+    }
+    return Result::Ok(());
+}
+
+pub fn info(opts: InfoOptions) -> Result<(), DisassembleError> {
     let data = read_file_or_stdin(opts.in_file)?;
-    let out = open_out_file(opts.out_file)?;
+    check_handled(&data)?;
+
+    let header = NesHeader::parse(&data)?;
+    if header.format == HeaderFormat::ArchaicINes {
+        eprintln!("warning: {}", ARCHAIC_INES_NOTE);
+    }
+    let mut out = open_out_file(Option::None)?;
+    writeln!(out, "format:        {}", header.format)?;
+    writeln!(out, "mapper:        {}.{}", header.mapper, header.submapper)?;
+    writeln!(
+        out,
+        "PRG ROM:       {} bytes ({} x 16k)",
+        header.prg_rom_size,
+        header.prg_rom_page_count()
+    )?;
+    writeln!(
+        out,
+        "CHR ROM:       {} bytes ({} x 8k)",
+        header.chr_rom_size,
+        header.chr_rom_page_count()
+    )?;
+    writeln!(
+        out,
+        "PRG RAM:       {} bytes (+{} non-volatile)",
+        header.prg_ram_size, header.prg_nvram_size
+    )?;
+    writeln!(
+        out,
+        "CHR RAM:       {} bytes (+{} non-volatile)",
+        header.chr_ram_size, header.chr_nvram_size
+    )?;
+    writeln!(out, "mirroring:     {}", header.mirroring)?;
+    writeln!(out, "battery:       {}", yes_no(header.battery))?;
+    writeln!(out, "trainer:       {}", yes_no(header.trainer))?;
+    writeln!(out, "console:       {}", header.console_type)?;
+    writeln!(out, "timing:        {}", header.timing)?;
+    writeln!(
+        out,
+        "misc ROMs:     {} ({} bytes)",
+        header.misc_rom_count,
+        header.misc_rom_size(data.len())
+    )?;
+    return Result::Ok(());
+}
+
+/// Rewrites header fields of a ROM, writing the whole ROM with the new header.
+pub fn header(opts: HeaderOptions) -> Result<(), DisassembleError> {
+    let mut data = read_file_or_stdin(Option::Some(opts.in_file))?;
+    check_handled(&data)?;
+
+    let mut header = NesHeader::parse(&data)?;
+    if opts.nes2 {
+        header.upgrade_to_nes2();
+    }
+    for set in &opts.set {
+        let (field, value) = set.split_once('=').ok_or_else(|| {
+            DisassembleError::ParseError(format!("expected field=value, found \"{}\"", set))
+        })?;
+        header.set(field, value)?;
+    }
+    if data.len() < header.misc_rom_offset() {
+        return Result::Err(DisassembleError::ParseError(format!(
+            "the header needs {} bytes of PRG and CHR ROM but the file only has {}",
+            header.prg_rom_size + header.chr_rom_size,
+            data.len() - NES_HEADER_LENGTH
+        )));
+    }
+    header.write_to(&mut data)?;
+
+    let mut out = open_out_file(opts.out_file)?;
+    out.write_all(&data)?;
+    return Result::Ok(());
+}
 
-    if NesDisassembler::is_handled(&data) {
-        return NesDisassembler::disassemble(data, out);
+/// Lists the PRG and CHR ROM banks of a ROM, or builds a new ROM from them.
+pub fn banks(opts: BanksOptions) -> Result<(), DisassembleError> {
+    let mut data = read_file_or_stdin(Option::Some(opts.in_file))?;
+    check_handled(&data)?;
+    let mut header = NesHeader::parse(&data)?;
+    let mut out = open_out_file(opts.out_file)?;
+
+    if let Option::Some(extract) = opts.extract {
+        let bank = banks::parse_bank(&banks::list_banks(&data, &header)?, &extract)?;
+        out.write_all(&data[bank.offset..bank.offset + bank.len])?;
+        return Result::Ok(());
+    }
+
+    if opts.replace.is_empty() && opts.order.is_empty() {
+        for bank in banks::list_banks(&data, &header)? {
+            writeln!(
+                out,
+                "{} {:<3} offset ${:06x} {:>6} bytes crc32 {:08x}",
+                bank.kind, bank.index, bank.offset, bank.len, bank.crc32
+            )?;
+        }
+        return Result::Ok(());
+    }
+
+    for replace in &opts.replace {
+        let (bank, path) = replace.split_once('=').ok_or_else(|| {
+            DisassembleError::ParseError(format!("expected bank=path, found \"{}\"", replace))
+        })?;
+        let bank = banks::parse_bank(&banks::list_banks(&data, &header)?, bank)?;
+        let replacement = read_file_or_stdin(Option::Some(PathBuf::from(path)))?;
+        banks::replace_bank(&mut data, &bank, &replacement)?;
+    }
+    for order in &opts.order {
+        let list = banks::list_banks(&data, &header)?;
+        data = banks::reorder_banks(&data, &mut header, &list, order)?;
+    }
+    out.write_all(&data)?;
+    return Result::Ok(());
+}
+
+/// Lists a raw binary, e.g. a SNES or Apple IIgs program, decoding it linearly
+/// from its first byte without any analysis.
+pub fn list(opts: ListOptions) -> Result<(), DisassembleError> {
+    let data = read_file_or_stdin(opts.in_file)?;
+    let mut out = open_out_file(opts.out_file)?;
+    match opts.cpu {
+        Cpu::Mos6502 => {
+            let mut offset = 0;
+            while offset < data.len() {
+                let addr = opts.org.wrapping_add(offset as u32);
+                let len = match decode(&data[offset..], addr as u16) {
+                    Option::Some(instr) => {
+                        let bytes = &data[offset..offset + instr.size()];
+                        write_list_line(&mut out, addr, bytes, &instr.to_string(), "")?;
+                        instr.size()
+                    }
+                    Option::None => {
+                        let byte = format!(".byte ${:02x}", data[offset]);
+                        write_list_line(&mut out, addr, &data[offset..offset + 1], &byte, "")?;
+                        1
+                    }
+                };
+                offset += len;
+            }
+        }
+        Cpu::W65816 => {
+            let flags = Flags {
+                m8: !opts.m16,
+                x8: !opts.x16,
+                emulation: !opts.m16 && !opts.x16,
+            };
+            let mut decoder = Decoder65816::new(&data, opts.org, flags);
+            loop {
+                let offset = decoder.offset();
+                let before = decoder.flags();
+                let instr = match decoder.next() {
+                    Option::Some(instr) => instr,
+                    Option::None => break,
+                };
+                let after = decoder.flags();
+                let widths = if after == before {
+                    String::new()
+                } else {
+                    format!(
+                        " ; a{} i{}{}",
+                        if after.m8 { 8 } else { 16 },
+                        if after.x8 { 8 } else { 16 },
+                        if after.emulation { " emulation" } else { "" }
+                    )
+                };
+                let bytes = &data[offset..offset + instr.size];
+                write_list_line(&mut out, instr.addr, bytes, &instr.to_string(), &widths)?;
+            }
+            // an operand cut off by the end of the file
+            let offset = decoder.offset();
+            for (i, b) in data[offset..].iter().enumerate() {
+                let addr = opts.org.wrapping_add((offset + i) as u32);
+                write_list_line(&mut out, addr, &[*b], &format!(".byte ${:02x}", b), "")?;
+            }
+        }
+        Cpu::HuC6280 => {
+            let mut mprs = huc6280::Mprs::new();
+            let mut offset = huc6280::header_len(&data);
+            while offset < data.len() {
+                let addr = opts.org.wrapping_add(offset as u32) as u16;
+                let instr = match decode_huc6280(&data[offset..], addr) {
+                    Option::Some(instr) => instr,
+                    Option::None => {
+                        let byte = format!(".byte ${:02x}", data[offset]);
+                        let bytes = &data[offset..offset + 1];
+                        write_list_line(&mut out, addr as u32, bytes, &byte, "")?;
+                        offset += 1;
+                        continue;
+                    }
+                };
+                // registers are only where they usually are with the I/O bank in MPR0
+                let io_mapped =
+                    matches!(mprs.get(0), Option::None | Option::Some(huc6280::IO_BANK));
+                let register = match instr.mode {
+                    _ if !io_mapped => huc6280::st_comment(instr.mnemonic),
+                    ModeHuC6280::Base(
+                        AddressingMode::Absolute
+                        | AddressingMode::AbsoluteX
+                        | AddressingMode::AbsoluteY,
+                    ) if instr.target().is_none() => huc6280::register_name(instr.operands[0]),
+                    ModeHuC6280::ImmediateAbsolute | ModeHuC6280::ImmediateAbsoluteX => {
+                        huc6280::register_name(instr.operands[1])
+                    }
+                    _ => huc6280::st_comment(instr.mnemonic),
+                };
+                let comments: Vec<String> = register
+                    .map(|r| r.to_string())
+                    .into_iter()
+                    .chain(mprs.update(&instr))
+                    .collect();
+                let comment = if comments.is_empty() {
+                    String::new()
+                } else {
+                    format!(" ; {}", comments.join(", "))
+                };
+                let bytes = &data[offset..offset + instr.size()];
+                write_list_line(&mut out, addr as u32, bytes, &instr.to_string(), &comment)?;
+                offset += instr.size();
+            }
+        }
+    }
+    return Result::Ok(());
+}
+
+fn write_list_line(
+    out: &mut Box<dyn Write>,
+    addr: u32,
+    bytes: &[u8],
+    text: &str,
+    comment: &str,
+) -> Result<(), DisassembleError> {
+    let hex: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    writeln!(
+        out,
+        "{:02x}:{:04x}  {:<21}{}{}",
+        addr >> 16,
+        addr & 0xffff,
+        hex.join(" "),
+        text,
+        comment
+    )?;
+    return Result::Ok(());
+}
+
+/// Parses the start address of `list`, e.g. `$8000` or `$008000`.
+pub fn parse_org(s: &str) -> Result<u32, String> {
+    let value = if let Option::Some(hex) = s.strip_prefix('$') {
+        u32::from_str_radix(hex, 16)
+    } else if let Option::Some(hex) = s.strip_prefix("0x") {
+        u32::from_str_radix(hex, 16)
     } else {
-        return Result::Err(DisassembleError::ParseError(
-            "unhandled file format".to_string(),
-        ));
+        s.parse::<u32>()
+    };
+    return value
+        .ok()
+        .filter(|v| *v <= 0xffffff)
+        .ok_or_else(|| format!("invalid address \"{}\"", s));
+}
+
+/// Decodes the instruction at the start of `bytes`, which is at `addr`, on its own
+/// without any analysis, e.g. for formatting trace logs. Returns the instruction
+/// and its length in bytes.
+pub fn decode_one(bytes: &[u8], addr: u16) -> Result<(Instruction, usize), DisassembleError> {
+    let opcode = *bytes
+        .first()
+        .ok_or_else(|| DisassembleError::ParseError(format!("no bytes at ${:04x}", addr)))?;
+    return Instruction::decode(bytes, addr).ok_or_else(|| match decode_opcode(opcode) {
+        Option::Some((_, mode)) if bytes.len() <= mode.operand_len() => {
+            DisassembleError::ParseError(format!("instruction at ${:04x} is cut off", addr))
+        }
+        _ => DisassembleError::UnhandledInstruction(opcode),
+    });
+}
+
+/// Name of the input as shown in diagnostics.
+pub fn display_name(in_file: &Option<PathBuf>) -> String {
+    return match in_file {
+        Option::Some(in_file) => in_file.display().to_string(),
+        Option::None => "<stdin>".to_string(),
+    };
+}
+
+fn yes_no(v: bool) -> &'static str {
+    return if v { "yes" } else { "no" };
+}
+
+/// Fails with a look at the start of the file if it is not a format that can be
+/// disassembled.
+fn check_handled(data: &[u8]) -> Result<(), DisassembleError> {
+    if !NesDisassembler::is_handled(data) {
+        return Result::Err(DisassembleError::ParseError(format!(
+            "unhandled file format\n{}",
+            hexdump::hexdump(data, 0)
+        )));
     }
+    return Result::Ok(());
 }
 
 fn open_out_file(f: Option<PathBuf>) -> Result<Box<dyn Write>, DisassembleError> {
@@ -70,8 +1119,12 @@ fn open_out_file(f: Option<PathBuf>) -> Result<Box<dyn Write>, DisassembleError>
     return Result::Ok(Box::new(std::io::stdout()) as Box<dyn Write>);
 }
 
+/// Reads a file, an http(s) URL given in place of a path or stdin.
 fn read_file_or_stdin(f: Option<PathBuf>) -> Result<Vec<u8>, DisassembleError> {
     if let Option::Some(in_file) = f {
+        if let Option::Some(url) = in_file.to_str().filter(|p| is_url(p)) {
+            return read_url(url);
+        }
         if !in_file.as_path().exists() {
             return Result::Err(DisassembleError::MissingFile(in_file));
         }
@@ -87,3 +1140,32 @@ fn read_file_or_stdin(f: Option<PathBuf>) -> Result<Vec<u8>, DisassembleError> {
     std::io::stdin().read_to_end(&mut buffer)?;
     return Result::Ok(buffer);
 }
+
+fn is_url(path: &str) -> bool {
+    return path.starts_with("http://") || path.starts_with("https://");
+}
+
+/// larger downloads are cut off, ROMs are a few MB at most
+#[cfg(feature = "http")]
+const MAX_URL_INPUT_LENGTH: u64 = 64 * 1024 * 1024;
+
+#[cfg(feature = "http")]
+fn read_url(url: &str) -> Result<Vec<u8>, DisassembleError> {
+    let response = ureq::get(url)
+        .call()
+        .map_err(|err| DisassembleError::ParseError(err.to_string()))?;
+    let mut buffer = Vec::new();
+    response
+        .into_reader()
+        .take(MAX_URL_INPUT_LENGTH)
+        .read_to_end(&mut buffer)?;
+    return Result::Ok(buffer);
+}
+
+#[cfg(not(feature = "http"))]
+fn read_url(url: &str) -> Result<Vec<u8>, DisassembleError> {
+    return Result::Err(DisassembleError::ParseError(format!(
+        "can't read {}, reading from URLs needs the http feature",
+        url
+    )));
+}
@@ -1,117 +1,1396 @@
-use std::io::Write;
+use itertools::Itertools;
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    io::Write,
+    mem,
+    ops::RangeInclusive,
+};
 
 use super::{
+    access::AccessMap,
+    address::{BankedAddr, CpuAddr, FileOffset},
+    arithmetic::{find_block_expressions, find_pointer_setups, find_wide_ops},
+    banks::has_rom_registers,
+    budget::{vblank_cycles, worst_case_cycles, Cycles, Unbounded, INTERRUPT_CYCLES},
+    cc65::{stack_op, ArgType, Prototype, StackOp},
+    checksum::find_checksum_routines,
+    code::{AsmCode, JsonStatement, SourceSplit, SplitFiles, Statement},
+    diagnostic::Diagnostic,
+    dialect::AsmDialect,
     disassembler::Disassembler,
+    graphics::{self, find_chr_ram_copies, tile_offsets, TileFormat},
+    html::write_html,
+    instruction::Instruction,
+    interrupts::{is_interrupt, operand_access, ram_access, SharedVariable},
+    loops::find_counted_loops,
+    mapper::{mapper_factory, Mapper},
+    nes_header::{
+        HeaderFormat, NesHeader, ARCHAIC_INES_NOTE, NES_CHR_ROM_PAGE_LENGTH, NES_HEADER_LENGTH,
+        NES_PRG_ROM_PAGE_LENGTH, NES_TRAINER_LENGTH,
+    },
+    passes::{Pass, PassStats},
+    platform::{AccessPattern, NamingRule, Platform},
+    progress::{NoProgress, Progress},
+    repl::{parse_addr, parse_addr_range},
+    signatures::{
+        find_signatures, Library, Signature, SignatureMatch, CC65_RUNTIME, LIBRARIES,
+        SIGNATURES_FILE,
+    },
+    stubs::{find_duplicated_routines, DuplicatedRoutine},
+    sweep::{
+        is_fill_run, padding_alignment, score_region, split_fill, ScoredRegion,
+        DEFAULT_SWEEP_THRESHOLD, FILL_RUN_LENGTH,
+    },
+    template::{OutputTemplate, TemplateContext},
+    text::{text_at, DEFAULT_TEXT_MIN_LEN},
     variable::{Variable, VariableValue},
-    DisassembleError, code::{AsmCode, Statement},
+    AnalysisOptions, DisassembleError,
 };
+use crate::decode::{decode, decode_illegal, AddressingMode, DecodedInstruction};
 
 // https://www.nesdev.org/wiki/NES_2.0
 // https://archive.nes.science/nesdev-forums/f2/t10469.xhtml
 // https://en.wikibooks.org/wiki/NES_Programming/Initializing_the_NES
 // https://www.pagetable.com/c64ref/6502/
-const NES_HEADER_LENGTH: usize = 16;
-const NES_PRG_ROM_PAGE_LENGTH: usize = 16 * 1024;
-const NES_CHR_ROM_PAGE_LENGTH: usize = 8 * 1024;
 const NES_PRG_ROM_START_ADDRESS: usize = 0x8000;
+/// where the trainer is loaded, in the middle of SRAM at $6000-$7fff
+const NES_TRAINER_START_ADDRESS: usize = 0x7000;
+const NES_SRAM: std::ops::Range<u16> = 0x6000..0x8000;
+/// the PPU registers, mirrored up to $3fff, and the APU and I/O registers
+const NES_REGISTERS: std::ops::Range<u16> = 0x2000..0x4020;
+/// file `--split-banks` writes the register constants to
+const REGISTERS_FILE: &str = "registers.inc";
+/// data tables longer than this stay where they are with `--inline-tables`
+const MAX_INLINE_TABLE_LENGTH: usize = 32;
+/// opcodes a routine ends with, see [`NesDisassembler::code_at`]
+const RTS: u8 = 0x60;
+const RTI: u8 = 0x40;
+/// fewest words pointing at code proposed as a vector table
+const VECTOR_TABLE_MIN_ENTRIES: usize = 3;
+/// bytes scored at the unreached address a proposed vector table entry points at
+const VECTOR_TARGET_SCORED_LENGTH: usize = 64;
+
+#[derive(Serialize)]
+struct JsonVariable {
+    name: String,
+    value: u16,
+}
+
+#[derive(Serialize)]
+struct JsonSourceMapEntry {
+    line: usize,
+    offset: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bank: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    addr: Option<u16>,
+}
+
+#[derive(Serialize)]
+struct JsonSourceMap<'a> {
+    file: Option<&'a str>,
+    entries: Vec<JsonSourceMapEntry>,
+}
+
+#[derive(Serialize)]
+struct JsonAnalysis<'a> {
+    format: &'static str,
+    header: &'a NesHeader,
+    variables: Vec<JsonVariable>,
+    duplicated_routines: &'a [DuplicatedRoutine],
+    library_routines: &'a [SignatureMatch],
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    unreached_regions: &'a [ScoredRegion],
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    shared_variables: &'a [SharedVariable],
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<JsonTag>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    unhandled_opcodes: Vec<JsonUnhandledOpcode>,
+    passes: Vec<JsonPass>,
+    statements: Vec<JsonStatement>,
+}
+
+#[derive(Serialize)]
+struct JsonPass {
+    pass: String,
+    #[serde(flatten)]
+    stats: PassStats,
+}
+
+#[derive(Serialize)]
+struct JsonUnhandledOpcode {
+    opcode: u8,
+    count: usize,
+    locations: Vec<JsonLocation>,
+}
+
+#[derive(Serialize)]
+struct JsonLocation {
+    offset: usize,
+    bank: u8,
+    addr: u16,
+}
+
+#[derive(Serialize)]
+struct JsonTag {
+    name: String,
+    /// labels of the tagged routines and tables
+    labels: Vec<String>,
+    bytes: usize,
+}
+
+#[derive(Serialize)]
+struct JsonRoutine {
+    label: String,
+    offset: usize,
+    bank: u8,
+    addr: u16,
+    /// labels of the subroutines it calls
+    calls: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct JsonCallGraph {
+    /// labels of the entry points the graph starts from
+    roots: Vec<String>,
+    routines: Vec<JsonRoutine>,
+}
+
+/// Length of `instr` as the code runs, `brk` skipping the signature byte after it.
+fn instr_len(instr: &DecodedInstruction) -> usize {
+    return if instr.mnemonic == "brk" {
+        2
+    } else {
+        instr.size()
+    };
+}
 
 pub struct NesDisassembler {
     d: Disassembler,
-    prg_rom_count: u8,
-    chr_rom_count: u8,
-    flags6: u8,
-    flags7: u8,
-    mapper: u8,
-    prg_chr_rom_size: u8,
-    prg_ram_eeprom_size: u8,
-    chr_ram_size: u8,
-    cpu_ppu_timing: u8,
-    flags13: u8,
-    misc_roms: u8,
-    default_expansion_device: u8,
+    header: NesHeader,
+    /// length of the input, which may be shorter than the header claims
+    data_len: usize,
+    duplicated_routines: Vec<DuplicatedRoutine>,
+    library_routines: Vec<SignatureMatch>,
+    unreached_regions: Vec<ScoredRegion>,
+    /// labels of the vectors code was traced from, with where they point
+    entry_points: Vec<(String, FileOffset)>,
+    /// entry points each instruction can be reached from, by offset
+    reached_from: HashMap<FileOffset, BTreeSet<String>>,
+    shared_variables: Vec<SharedVariable>,
+    /// bytes of the routines and tables given each tag
+    tags: BTreeMap<String, BTreeSet<usize>>,
+    /// how the tiles of the CHR ROM and the ones copied to CHR RAM are laid out
+    tile_format: TileFormat,
+    /// start and length of each run of tiles grouped, see
+    /// [`NesDisassembler::write_tiles`]
+    tile_regions: Vec<(FileOffset, usize)>,
+    /// what each pass run added, in the order they ran
+    pass_stats: Vec<(Pass, PassStats)>,
 }
 
 impl NesDisassembler {
-    pub fn is_handled(data: &Vec<u8>) -> bool {
-        return data[0] == b'N' && data[1] == b'E' && data[2] == b'S' && data[3] == 0x1a;
+    pub fn is_handled(data: &[u8]) -> bool {
+        return NesHeader::is_nes_file(data);
+    }
+
+    pub fn disassemble(data: Vec<u8>) -> Result<NesDisassembler, super::DisassembleError> {
+        return NesDisassembler::disassemble_with_options(data, &AnalysisOptions::default());
+    }
+
+    pub fn disassemble_with_options(
+        data: Vec<u8>,
+        opts: &AnalysisOptions,
+    ) -> Result<NesDisassembler, super::DisassembleError> {
+        return NesDisassembler::disassemble_with_progress(data, opts, &mut NoProgress);
     }
 
-    pub fn disassemble(data: Vec<u8>, out: Box<dyn Write>) -> Result<(), super::DisassembleError> {
+    /// Disassembles like [`NesDisassembler::disassemble_with_options`], telling
+    /// `progress` how far it got.
+    pub fn disassemble_with_progress(
+        data: Vec<u8>,
+        opts: &AnalysisOptions,
+        progress: &mut dyn Progress,
+    ) -> Result<NesDisassembler, super::DisassembleError> {
+        let header = NesHeader::parse(&data)?;
+        let data_len = data.len();
         let mut d = NesDisassembler {
             d: Disassembler::new(data),
-            prg_rom_count: 0,
-            chr_rom_count: 0,
-            flags6: 0,
-            flags7: 0,
-            mapper: 0,
-            prg_chr_rom_size: 0,
-            prg_ram_eeprom_size: 0,
-            chr_ram_size: 0,
-            cpu_ppu_timing: 0,
-            flags13: 0,
-            misc_roms: 0,
-            default_expansion_device: 0,
+            header,
+            data_len,
+            duplicated_routines: Vec::new(),
+            library_routines: Vec::new(),
+            unreached_regions: Vec::new(),
+            entry_points: Vec::new(),
+            reached_from: HashMap::new(),
+            shared_variables: Vec::new(),
+            tags: BTreeMap::new(),
+            tile_format: opts
+                .tile_format
+                .or(Platform::Nes.tile_format())
+                .unwrap_or(TileFormat::NES),
+            tile_regions: Vec::new(),
+            pass_stats: Vec::new(),
         };
+        d.d.strict = opts.strict;
+        d.d.illegal_opcodes = opts.illegal_opcodes;
+        d.d.jam_opcodes = opts.jam_opcodes;
+        d.d.quirks = opts
+            .cpu_variant
+            .unwrap_or(Platform::Nes.cpu_variant())
+            .quirks();
+        d.d.code.set_charmap(opts.charmap.clone());
 
         d.set_variables();
         d.parse_header()?;
+        d.pad_missing_pages();
         d.parse_chr_rom()?;
-        d.disassemble_entry_points()?;
+        d.parse_misc_rom()?;
+        let vector_tables = opts
+            .vector_tables
+            .iter()
+            .map(|table| parse_vector_table(table).map_err(DisassembleError::ParseError))
+            .collect::<Result<Vec<(CpuAddr, usize)>, DisassembleError>>()?;
+        let passes = match opts.passes.is_empty() {
+            true => Pass::defaults(opts),
+            false => opts.passes.clone(),
+        };
+        let mut roots = Option::None;
+        let mut labeled = false;
+        for pass in passes {
+            if !labeled && !pass.decodes_code() {
+                roots = d.label_routines(opts)?;
+                labeled = true;
+            }
+            let before = d.pass_stats();
+            match pass {
+                Pass::Trace => {
+                    d.disassemble_entry_points(progress)?;
+                    d.disassemble_trainer(progress)?;
+                    for (addr, entries) in &vector_tables {
+                        d.disassemble_vector_table(*addr, *entries, progress)?;
+                    }
+                }
+                Pass::Sweep => {
+                    let threshold = opts.sweep_threshold.unwrap_or(DEFAULT_SWEEP_THRESHOLD);
+                    d.sweep(threshold, progress)?;
+                }
+                Pass::Idioms => {
+                    d.annotate_loops()?;
+                    d.annotate_wide_ops();
+                    if opts.expression_comments {
+                        d.annotate_expressions();
+                    }
+                }
+                Pass::Pointers => {
+                    d.find_chr_ram_tiles()?;
+                    d.label_pointer_setups();
+                    d.label_indexed_tables();
+                }
+                Pass::Strings => {
+                    d.find_text(
+                        &opts.charmap,
+                        opts.text_min_len.unwrap_or(DEFAULT_TEXT_MIN_LEN),
+                    );
+                }
+            }
+            let stats = d.pass_stats().since(&before);
+            progress.pass_completed(pass, &stats);
+            d.pass_stats.push((pass, stats));
+        }
+        if !labeled {
+            roots = d.label_routines(opts)?;
+        }
+        d.propose_vector_tables();
+        d.check_nmi_budgets();
+        if opts.shared_variables {
+            d.find_shared_variables();
+        }
+        if opts.reached_from_comments {
+            d.comment_reachability();
+        }
+        if opts.inline_tables {
+            d.inline_small_tables()?;
+        }
+        if opts.procs {
+            d.find_procs();
+        }
+        if opts.verify {
+            d.add_verify();
+        }
+        if let Option::Some(threshold) = opts.sweep_threshold {
+            d.score_unreached_regions(threshold);
+        }
+        for range in &opts.unnamed {
+            let (start, end) = parse_addr_range(range).map_err(DisassembleError::ParseError)?;
+            d.keep_unnamed(start, end);
+        }
+        for symbol in &opts.exports {
+            d.export(symbol)?;
+        }
+        for tag in &opts.tags {
+            let (name, addr) = tag.split_once('=').ok_or_else(|| {
+                DisassembleError::ParseError(format!("expected tag=address, found \"{}\"", tag))
+            })?;
+            let addr = parse_addr(addr).map_err(DisassembleError::ParseError)?;
+            d.tag(addr, name)?;
+        }
+        if let Option::Some(roots) = roots {
+            d.restrict_output(&roots);
+        }
+        d.filter_tags(&opts.include_tags, &opts.exclude_tags)?;
+        d.resolve_jump_labels();
+        // after the jumps are resolved, which labels the targets only they go to
+        if opts.xref_comments {
+            d.comment_xrefs();
+        }
+        d.collapse_padding();
+        if opts.cycle_comments {
+            d.comment_cycles();
+        }
+        progress.bytes_classified(d.d.code.classified(), d.data_len);
+
+        return Result::Ok(d);
+    }
+
+    /// Labels and names what the code decoded so far calls and reads, before the
+    /// passes that go by those names. Returns the code `--only-from` keeps.
+    fn label_routines(
+        &mut self,
+        opts: &AnalysisOptions,
+    ) -> Result<Option<Vec<FileOffset>>, DisassembleError> {
+        self.find_self_modifying_code();
+        self.find_rom_writes();
+        let roots = match &opts.only_from {
+            Option::Some(root) => Option::Some(self.find_roots(root)?),
+            Option::None => Option::None,
+        };
+        self.find_reachability();
+        self.label_duplicated_routines();
+        self.label_library_routines(&opts.signatures)?;
+        self.label_checksum_routines()?;
+        self.annotate_cc65_stack();
+        if opts.name_data {
+            self.name_data_by_access(Platform::Nes.naming_rules());
+        }
+        return Result::Ok(roots);
+    }
+
+    fn pass_stats(&self) -> PassStats {
+        return PassStats {
+            classified: self.d.code.classified(),
+            labels: self.d.code.labeled(),
+            comments: self.d.code.commented(),
+        };
+    }
+
+    pub fn header(&self) -> &NesHeader {
+        return &self.header;
+    }
+
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        return &self.d.diagnostics;
+    }
+
+    pub fn write(&self, out: &mut dyn Write) -> Result<(), DisassembleError> {
+        return self.d.code.write(out);
+    }
 
-        d.d.code.write(out)?;
+    /// The files [`NesDisassembler::write_split`] writes besides the main one, each
+    /// PRG ROM bank's named after its segment with `extension`.
+    pub fn split_files(&self, extension: &str) -> Vec<String> {
+        let split = self.source_split(extension);
+        let mut files = split
+            .segment_files
+            .into_values()
+            .sorted()
+            .collect::<Vec<String>>();
+        files.push(split.registers_file);
+        return files;
+    }
+
+    /// Writes the disassembly with each PRG ROM bank in a file of its own and the
+    /// hardware register constants in another, all included from `out`. Returns
+    /// the files as named by [`NesDisassembler::split_files`].
+    pub fn write_split(
+        &self,
+        out: &mut dyn Write,
+        extension: &str,
+    ) -> Result<SplitFiles, DisassembleError> {
+        return self.d.code.write_split(out, &self.source_split(extension));
+    }
+
+    fn source_split(&self, extension: &str) -> SourceSplit {
+        let segment_files = self
+            .d
+            .code
+            .statements()
+            .iter()
+            .filter_map(|stmt| stmt.segment.as_deref())
+            .filter(|segment| segment.starts_with("PRGROM"))
+            .map(|segment| {
+                let file = format!("{}.{}", segment.to_lowercase(), extension);
+                return (segment.to_string(), file);
+            })
+            .collect();
+        return SourceSplit {
+            segment_files,
+            registers_file: REGISTERS_FILE.to_string(),
+            registers: NES_REGISTERS,
+        };
+    }
 
+    /// Writes the disassembly plus a JSON map from each line of it back to the file
+    /// offset and, for PRG ROM, the CPU address the line came from. `file` is the name
+    /// the disassembly is written to.
+    pub fn write_with_source_map(
+        &self,
+        out: &mut dyn Write,
+        map_out: &mut dyn Write,
+        file: Option<&str>,
+    ) -> Result<(), DisassembleError> {
+        let lines = self.d.code.write_with_lines(out)?;
+        let mapper = self.mapper();
+        let prg_rom_end = self.header.prg_rom_offset() + self.header.prg_rom_size;
+        let entries = lines
+            .into_iter()
+            .map(|(line, offset)| {
+                // the trainer, if any, comes between the header and the PRG ROM
+                let addr = if (NES_HEADER_LENGTH..prg_rom_end).contains(&offset.0) {
+                    Option::Some(mapper.to_banked_addr(offset))
+                } else {
+                    Option::None
+                };
+                return JsonSourceMapEntry {
+                    line,
+                    offset: offset.0,
+                    bank: addr.map(|a| a.bank),
+                    addr: addr.map(|a| a.addr.0),
+                };
+            })
+            .collect();
+        serde_json::to_writer_pretty(&mut *map_out, &JsonSourceMap { file, entries })
+            .map_err(|err| DisassembleError::WrappedError(format!("json error: {}", err)))?;
+        writeln!(map_out)?;
         return Result::Ok(());
     }
 
-    fn set_variables(&mut self) {
-        self.d.code.set_variable(
-            0x2000,
-            Variable {
-                name: "PPU_CTRL".to_string(),
-                value: VariableValue::U16(0x2000),
-            },
-        );
-        self.d.code.set_variable(
-            0x2001,
-            Variable {
-                name: "PPU_MASK".to_string(),
-                value: VariableValue::U16(0x2001),
-            },
-        );
-        self.d.code.set_variable(
-            0x2002,
-            Variable {
-                name: "PPU_STATUS".to_string(),
-                value: VariableValue::U16(0x2002),
-            },
-        );
-        self.d.code.set_variable(
-            0x2003,
-            Variable {
-                name: "OAM_ADDR".to_string(),
-                value: VariableValue::U16(0x2003),
-            },
-        );
-        self.d.code.set_variable(
-            0x2004,
-            Variable {
-                name: "OAM_DATA".to_string(),
-                value: VariableValue::U16(0x2004),
-            },
+    /// Writes which subroutines call which, starting from the entry points, as
+    /// Graphviz DOT if `dot` and as JSON otherwise.
+    pub fn write_call_graph(&self, out: &mut dyn Write, dot: bool) -> Result<(), DisassembleError> {
+        let mapper = self.mapper();
+        let label = |offset: FileOffset| match self.d.code.label(offset) {
+            Option::Some(label) => label.to_string(),
+            Option::None => {
+                let addr = mapper.to_banked_addr(offset);
+                format!("prgrom{}_{:04x}", addr.bank, addr.addr.0)
+            }
+        };
+        // vectors pointing to the same code share its label
+        let roots = self
+            .entry_points
+            .iter()
+            .map(|(_, offset)| label(*offset))
+            .unique()
+            .collect::<Vec<String>>();
+        let graph = self.call_graph();
+
+        if dot {
+            writeln!(out, "digraph calls {{")?;
+            for root in &roots {
+                writeln!(out, "    \"{}\" [shape=box];", root)?;
+            }
+            for (routine, calls) in &graph {
+                for callee in calls {
+                    writeln!(
+                        out,
+                        "    \"{}\" -> \"{}\";",
+                        label(*routine),
+                        label(*callee)
+                    )?;
+                }
+            }
+            writeln!(out, "}}")?;
+            return Result::Ok(());
+        }
+
+        let routines = graph
+            .iter()
+            .map(|(routine, calls)| {
+                let addr = mapper.to_banked_addr(*routine);
+                return JsonRoutine {
+                    label: label(*routine),
+                    offset: routine.0,
+                    bank: addr.bank,
+                    addr: addr.addr.0,
+                    calls: calls.iter().map(|callee| label(*callee)).collect(),
+                };
+            })
+            .collect();
+        serde_json::to_writer_pretty(&mut *out, &JsonCallGraph { roots, routines })
+            .map_err(|err| DisassembleError::WrappedError(format!("json error: {}", err)))?;
+        writeln!(out)?;
+        return Result::Ok(());
+    }
+
+    /// Lists the PRG ROM no entry point reaches, leaving out fill, one region per
+    /// line with its bank, addresses, file offset and size.
+    pub fn write_unreached(&self, out: &mut dyn Write) -> Result<(), DisassembleError> {
+        let mapper = self.mapper();
+        let runs = self.unreached_runs();
+        writeln!(
+            out,
+            "{} unreached regions, {} bytes",
+            runs.len(),
+            runs.iter().map(|(_, len)| len).sum::<usize>()
+        )?;
+        for (offset, len) in runs {
+            let addr = mapper.to_banked_addr(FileOffset(offset));
+            writeln!(
+                out,
+                "bank {:<3} ${:04x}-${:04x} offset ${:06x} {:>6} bytes",
+                addr.bank,
+                addr.addr.0,
+                addr.addr.0.wrapping_add(len as u16 - 1),
+                offset,
+                len
+            )?;
+        }
+        return Result::Ok(());
+    }
+
+    /// Writes a Markdown summary of the analysis for `name`: the entry points, the
+    /// subroutines, how much of PRG ROM is code, the jumps through pointers that
+    /// couldn't be followed and the diagnostics left to look into.
+    pub fn write_report(&self, out: &mut dyn Write, name: &str) -> Result<(), DisassembleError> {
+        let mapper = self.mapper();
+        let prg_rom_start = self.header.prg_rom_offset();
+        let prg_rom_end = self.prg_rom_end();
+        let stmts = self.d.code.statements();
+        let label = |offset: FileOffset| match self.d.code.label(offset) {
+            Option::Some(label) => label.to_string(),
+            Option::None => {
+                let addr = mapper.to_banked_addr(offset);
+                format!("prgrom{}_{:04x}", addr.bank, addr.addr.0)
+            }
+        };
+        let location = |offset: FileOffset| {
+            let addr = mapper.to_banked_addr(offset);
+            format!("{} | ${:04x}", addr.bank, addr.addr.0)
+        };
+
+        writeln!(out, "# {}\n", name)?;
+        writeln!(
+            out,
+            "Generated by sixtyfive, disassembling again overwrites it.\n"
+        )?;
+
+        writeln!(out, "## Entry points\n")?;
+        writeln!(out, "| label | bank | address |\n|---|---|---|")?;
+        for (name, offset) in &self.entry_points {
+            writeln!(out, "| {} | {} |", name, location(*offset))?;
+        }
+
+        let mut callers: BTreeMap<FileOffset, usize> = BTreeMap::new();
+        for target in self.d.calls.values() {
+            if let Option::Some(target) = mapper.to_offset(*target) {
+                *callers.entry(target).or_default() += 1;
+            }
+        }
+        writeln!(out, "\n## Subroutines\n")?;
+        writeln!(out, "{} subroutines called with `jsr`.\n", callers.len())?;
+        writeln!(
+            out,
+            "| label | bank | address | ends at | callers |\n|---|---|---|---|---|"
+        )?;
+        for (offset, count) in &callers {
+            let end = match self.routine_end(offset.0, prg_rom_end) {
+                Option::Some(end) => format!("${:04x}", mapper.to_addr(FileOffset(end)).0),
+                Option::None => "?".to_string(),
+            };
+            writeln!(
+                out,
+                "| {} | {} | {} | {} |",
+                label(*offset),
+                location(*offset),
+                end,
+                count
+            )?;
+        }
+
+        // bytes after the first of a statement count as what the statement is
+        let (mut code, mut data, mut fill, mut undecoded) = (0, 0, 0, 0);
+        let mut counter = &mut data;
+        for stmt in &stmts[prg_rom_start.min(prg_rom_end)..prg_rom_end] {
+            counter = match stmt.asm_code {
+                AsmCode::Used => counter,
+                AsmCode::Instruction(_) => &mut code,
+                AsmCode::Fill(_, _) | AsmCode::Res(_) => &mut fill,
+                AsmCode::DataHexU8(_) => &mut undecoded,
+                _ => &mut data,
+            };
+            *counter += 1;
+        }
+        let unreached = self
+            .unreached_runs()
+            .iter()
+            .map(|(_, len)| len)
+            .sum::<usize>();
+        let total = prg_rom_end.saturating_sub(prg_rom_start);
+        let percent = |bytes: usize| bytes as f64 * 100.0 / total.max(1) as f64;
+        writeln!(out, "\n## Coverage\n")?;
+        writeln!(out, "| PRG ROM | bytes | |\n|---|---|---|")?;
+        for (what, bytes) in [
+            ("code", code),
+            ("data", data),
+            ("fill", fill + undecoded - unreached),
+            ("not reached", unreached),
+        ] {
+            writeln!(out, "| {} | {} | {:.1}% |", what, bytes, percent(bytes))?;
+        }
+
+        writeln!(out, "\n## Analysis passes\n")?;
+        writeln!(out, "What each pass added, in the order they ran.\n")?;
+        writeln!(
+            out,
+            "| pass | bytes classified | labels | comments |\n|---|---|---|---|"
+        )?;
+        for (pass, stats) in &self.pass_stats {
+            writeln!(
+                out,
+                "| {} | {} | {} | {} |",
+                pass, stats.classified, stats.labels, stats.comments
+            )?;
+        }
+
+        let vblank = vblank_cycles(self.header.timing);
+        writeln!(out, "\n## NMI cycle budget\n")?;
+        writeln!(
+            out,
+            "Worst case cycles from the interrupt on, against the {} of vblank on {}.\n",
+            vblank, self.header.timing
+        )?;
+        writeln!(
+            out,
+            "| label | bank | address | cycles | up to the last PPU access |\n|---|---|---|---|---|"
+        )?;
+        for (offset, cycles) in self.nmi_budgets() {
+            let (total, ppu) = match cycles {
+                Result::Ok(cycles) => (
+                    cycles.total.to_string(),
+                    match cycles.ppu {
+                        Option::Some(ppu) if ppu > vblank => format!("{}, past vblank", ppu),
+                        Option::Some(ppu) => ppu.to_string(),
+                        Option::None => "none".to_string(),
+                    },
+                ),
+                Result::Err(unbounded) => (format!("unbounded, {}", unbounded), String::new()),
+            };
+            writeln!(
+                out,
+                "| {} | {} | {} | {} |",
+                label(offset),
+                location(offset),
+                total,
+                ppu
+            )?;
+        }
+
+        writeln!(out, "\n## Unresolved indirect jumps\n")?;
+        if self.d.unresolved_jumps.is_empty() {
+            writeln!(out, "None.")?;
+        }
+        for (offset, pointer) in &self.d.unresolved_jumps {
+            writeln!(
+                out,
+                "- ${:04x} `jmp (${:04x})`, the pointer is in RAM",
+                mapper.to_addr(*offset).0,
+                pointer
+            )?;
+        }
+
+        writeln!(out, "\n## TODO\n")?;
+        if self.d.diagnostics.is_empty() && self.d.unhandled_opcodes.is_empty() {
+            writeln!(out, "Nothing was flagged.")?;
+        }
+        for diagnostic in &self.d.diagnostics {
+            let at = match diagnostic.addr {
+                Option::Some(addr) => addr.to_string(),
+                Option::None => format!("offset ${:04x}", diagnostic.offset),
+            };
+            writeln!(out, "- [ ] {}: {}", at, diagnostic.message)?;
+        }
+        for (opcode, offsets) in &self.d.unhandled_opcodes {
+            let at = offsets
+                .iter()
+                .map(|offset| mapper.to_banked_addr(*offset).to_string())
+                .join(", ");
+            writeln!(
+                out,
+                "- [ ] unhandled opcode ${:02x} stopped the code at {}",
+                opcode, at
+            )?;
+        }
+        return Result::Ok(());
+    }
+
+    /// Writes the tiles of the CHR ROM and the ones copied to CHR RAM as an image,
+    /// see [`graphics::write_tiles`].
+    pub fn write_tiles(&self, out: &mut dyn Write) -> Result<(), DisassembleError> {
+        let data = self
+            .tile_regions
+            .iter()
+            .flat_map(|(start, len)| {
+                let tiles = *len / self.tile_format.tile_len() * self.tile_format.tile_len();
+                return self.d.code.data()[start.0..start.0 + tiles].iter().copied();
+            })
+            .collect::<Vec<u8>>();
+        graphics::write_tiles(out, &data, &self.tile_format)?;
+        return Result::Ok(());
+    }
+
+    /// Writes what the decoded instructions read, write and execute at each
+    /// address, see [`AccessMap::write`].
+    pub fn write_access_map(&self, out: &mut dyn Write) -> Result<(), DisassembleError> {
+        self.access_map().write(out)?;
+        return Result::Ok(());
+    }
+
+    /// Offset, name and address of the first statement of each segment, PRG ROM being
+    /// at the address the mapper maps it to and everything else at 0.
+    fn segment_starts(&self) -> Vec<(usize, &str, u16)> {
+        let mapper = self.mapper();
+        let code = self.code_offsets();
+        return self
+            .d
+            .code
+            .statements()
+            .iter()
+            .enumerate()
+            .filter_map(|(offset, stmt)| {
+                let name = stmt.segment.as_deref()?;
+                let start = match code.contains(&offset) {
+                    true => mapper.to_addr(FileOffset(offset)).0,
+                    false => 0,
+                };
+                return Option::Some((offset, name, start));
+            })
+            .collect();
+    }
+
+    /// Writes the disassembly with `dialect` from now on.
+    pub fn set_dialect(&mut self, dialect: Box<dyn AsmDialect>) {
+        let segment_addrs = self
+            .segment_starts()
+            .into_iter()
+            .map(|(offset, _, addr)| (FileOffset(offset), addr))
+            .collect();
+        self.d.code.set_dialect(dialect, segment_addrs);
+    }
+
+    /// Writes a listing with the address and bytes of each statement, see
+    /// [`Code::set_listing`](super::code::Code::set_listing).
+    pub fn set_listing(&mut self, listing: bool) {
+        self.d.code.set_listing(listing);
+    }
+
+    /// Writes the header, footer, segment banners and labels through `template`,
+    /// see [`Code::set_template`](super::code::Code::set_template).
+    pub fn set_template(&mut self, template: Box<dyn OutputTemplate>) {
+        self.d.code.set_template(template);
+    }
+
+    /// What the header and footer templates are written from, for the ROM `name`
+    /// written for `assembler`.
+    pub fn template_context(&self, name: &str, assembler: &str) -> TemplateContext {
+        return TemplateContext {
+            rom: name.to_string(),
+            mapper: self.header.mapper,
+            prg_banks: self.header.prg_rom_page_count(),
+            chr_banks: self.header.chr_rom_page_count(),
+            assembler: assembler.to_string(),
+            comment: self.d.code.dialect().comment().to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+        };
+    }
+
+    /// Writes each segment as a scope of its own, see
+    /// [`Code::set_scoped`](super::code::Code::set_scoped).
+    pub fn set_scoped(&mut self, scoped: bool) {
+        self.d.code.set_scoped(scoped);
+    }
+
+    /// Writes an ld65 config with a memory area for each segment, as large as it is
+    /// in the ROM and at the address the CPU sees it at, so the assembly links back
+    /// to the same bytes.
+    pub fn write_linker_config(&self, out: &mut dyn Write) -> Result<(), DisassembleError> {
+        let starts = self.segment_starts();
+        let width = starts
+            .iter()
+            .map(|(_, name, _)| name.len() + 1)
+            .max()
+            .unwrap_or(0);
+        writeln!(out, "MEMORY {{")?;
+        for (i, (offset, name, start)) in starts.iter().enumerate() {
+            let end = starts
+                .get(i + 1)
+                .map_or(self.data_len, |(next, _, _)| *next);
+            writeln!(
+                out,
+                "    {:<width$} file = %O, start = ${:04X}, size = ${:04X}, fill = yes;",
+                format!("{}:", name),
+                start,
+                end - offset,
+            )?;
+        }
+        writeln!(out, "}}")?;
+        writeln!(out, "SEGMENTS {{")?;
+        for (_, name, _) in &starts {
+            let area = format!("{}:", name);
+            writeln!(out, "    {:<width$} load = {}, type = ro;", area, name)?;
+        }
+        writeln!(out, "}}")?;
+        return Result::Ok(());
+    }
+
+    fn access_map(&self) -> AccessMap {
+        let mapper = self.mapper();
+        let data = self.d.code.data();
+        let mut map = AccessMap::default();
+        for (offset, stmt) in self.d.code.statements().iter().enumerate() {
+            if !matches!(stmt.asm_code, AsmCode::Instruction(_)) {
+                continue;
+            }
+            let addr = mapper.to_banked_addr(FileOffset(offset));
+            let instr = match decode(&data[offset..], addr.addr.0)
+                .or_else(|| decode_illegal(&data[offset..], addr.addr.0))
+            {
+                Option::Some(instr) => instr,
+                Option::None => continue,
+            };
+            map.execute(addr, instr.size());
+            if let Option::Some((operand, access)) = operand_access(&instr) {
+                // ROM is taken to be in the bank the mapper reads it from
+                let operand = match mapper.to_offset(CpuAddr(operand)) {
+                    Option::Some(offset) if operand as usize >= NES_PRG_ROM_START_ADDRESS => {
+                        mapper.to_banked_addr(offset)
+                    }
+                    _ => BankedAddr {
+                        bank: 0,
+                        addr: CpuAddr(operand),
+                    },
+                };
+                map.access(operand, access);
+            }
+        }
+        return map;
+    }
+
+    /// Summarizes the opcodes that stopped code being followed, the most frequent
+    /// first, each with where it was found.
+    pub fn write_unhandled_opcodes(&self, out: &mut dyn Write) -> Result<(), DisassembleError> {
+        let opcodes = self.json_unhandled_opcodes();
+        writeln!(
+            out,
+            "{} unhandled opcodes, found {} times",
+            opcodes.len(),
+            opcodes.iter().map(|o| o.count).sum::<usize>()
+        )?;
+        for opcode in opcodes {
+            let locations = opcode
+                .locations
+                .iter()
+                .map(|l| format!("{:02x}:{:04x}", l.bank, l.addr))
+                .join(", ");
+            writeln!(
+                out,
+                "${:02x} {:>5}x  {}",
+                opcode.opcode, opcode.count, locations
+            )?;
+        }
+        return Result::Ok(());
+    }
+
+    pub fn has_unhandled_opcodes(&self) -> bool {
+        return !self.d.unhandled_opcodes.is_empty();
+    }
+
+    fn json_unhandled_opcodes(&self) -> Vec<JsonUnhandledOpcode> {
+        let mapper = self.mapper();
+        return self
+            .d
+            .unhandled_opcodes
+            .iter()
+            .map(|(opcode, offsets)| JsonUnhandledOpcode {
+                opcode: *opcode,
+                count: offsets.len(),
+                locations: offsets
+                    .iter()
+                    .map(|offset| {
+                        let addr = mapper.to_banked_addr(*offset);
+                        return JsonLocation {
+                            offset: offset.0,
+                            bank: addr.bank,
+                            addr: addr.addr.0,
+                        };
+                    })
+                    .collect(),
+            })
+            .sorted_by_key(|o| std::cmp::Reverse(o.count))
+            .collect();
+    }
+
+    /// The subroutines the `jsr`s reachable from each routine call, for the entry
+    /// points and everything they call.
+    fn call_graph(&self) -> BTreeMap<FileOffset, BTreeSet<FileOffset>> {
+        let mapper = self.mapper();
+        let mut graph = BTreeMap::new();
+        let mut pending = self
+            .entry_points
+            .iter()
+            .map(|(_, offset)| *offset)
+            .collect::<Vec<FileOffset>>();
+        while let Option::Some(routine) = pending.pop() {
+            if graph.contains_key(&routine) {
+                continue;
+            }
+            let calls = self
+                .reachable(routine, false)
+                .iter()
+                .filter_map(|offset| self.d.calls.get(offset))
+                .filter_map(|target| mapper.to_offset(*target))
+                .collect::<BTreeSet<FileOffset>>();
+            pending.extend(calls.iter().copied());
+            graph.insert(routine, calls);
+        }
+        return graph;
+    }
+
+    pub fn write_json(&self, out: &mut dyn Write) -> Result<(), DisassembleError> {
+        let mut variables = self
+            .d
+            .code
+            .collect_variables()
+            .into_values()
+            .filter(|v| v.is_named())
+            .map(|v| JsonVariable {
+                name: v.name,
+                value: match v.value {
+                    VariableValue::U8(v) => v as u16,
+                    VariableValue::U16(v) | VariableValue::Label(v) | VariableValue::Unnamed(v) => {
+                        v
+                    }
+                },
+            })
+            .collect::<Vec<JsonVariable>>();
+        variables.sort_by_key(|v| v.value);
+
+        let json = JsonAnalysis {
+            format: "nes",
+            header: &self.header,
+            variables,
+            duplicated_routines: &self.duplicated_routines,
+            library_routines: &self.library_routines,
+            unreached_regions: &self.unreached_regions,
+            shared_variables: &self.shared_variables,
+            tags: self.json_tags(),
+            unhandled_opcodes: self.json_unhandled_opcodes(),
+            passes: self
+                .pass_stats
+                .iter()
+                .map(|(pass, stats)| JsonPass {
+                    pass: pass.to_string(),
+                    stats: *stats,
+                })
+                .collect(),
+            statements: self.json_statements(),
+        };
+        serde_json::to_writer_pretty(&mut *out, &json)
+            .map_err(|err| DisassembleError::WrappedError(format!("json error: {}", err)))?;
+        writeln!(out)?;
+        return Result::Ok(());
+    }
+
+    /// Writes the statements alone as a JSON array, for `--format json`.
+    pub fn write_statements_json(&self, out: &mut dyn Write) -> Result<(), DisassembleError> {
+        serde_json::to_writer_pretty(&mut *out, &self.json_statements())
+            .map_err(|err| DisassembleError::WrappedError(format!("json error: {}", err)))?;
+        writeln!(out)?;
+        return Result::Ok(());
+    }
+
+    /// Writes the statements as an HTML page titled `name`, see [`write_html`].
+    pub fn write_html(&self, out: &mut dyn Write, name: &str) -> Result<(), DisassembleError> {
+        let data = self.d.code.data();
+        let operand_addr = |stmt: &JsonStatement| {
+            if !self.d.code.is_instruction(FileOffset(stmt.offset)) {
+                return Option::None;
+            }
+            let instr = decode(&data[stmt.offset..], stmt.addr)?;
+            return match instr.mode {
+                AddressingMode::Implied
+                | AddressingMode::Accumulator
+                | AddressingMode::Immediate => Option::None,
+                AddressingMode::Relative => instr.target(),
+                _ => Option::Some(instr.operand),
+            };
+        };
+        return write_html(out, name, &self.json_statements(), operand_addr);
+    }
+
+    fn json_statements(&self) -> Vec<JsonStatement> {
+        let mut statements = self.d.code.to_json_statements();
+        for statement in &mut statements {
+            if let Option::Some(reached) = self.reached_from.get(&FileOffset(statement.offset)) {
+                statement.reached_from = reached.iter().cloned().collect();
+            }
+        }
+        return statements;
+    }
+
+    /// Writes labels, comments and variables in the Mesen label file (.mlb) format,
+    /// so they show in Mesen's debugger.
+    pub fn write_symbols(&self, out: &mut dyn Write) -> Result<(), DisassembleError> {
+        let prg_rom_offset = self.header.prg_rom_offset();
+        let prg_rom_end = prg_rom_offset + self.header.prg_rom_size;
+        for (offset, stmt) in self.d.code.statements().iter().enumerate() {
+            if !(prg_rom_offset..prg_rom_end).contains(&offset) {
+                continue;
+            }
+            let label = stmt.label.as_deref().unwrap_or_default();
+            match stmt.comment.as_deref() {
+                // Mesen reads `\n` in a comment as a line break
+                Option::Some(comment) => writeln!(
+                    out,
+                    "P:{:04X}:{}:{}",
+                    offset - prg_rom_offset,
+                    label,
+                    comment.replace('\n', "\\n")
+                )?,
+                Option::None if !label.is_empty() => {
+                    writeln!(out, "P:{:04X}:{}", offset - prg_rom_offset, label)?
+                }
+                Option::None => {}
+            }
+        }
+
+        let variables = self.d.code.collect_variables();
+        for addr in variables.keys().sorted() {
+            // `$0010` isn't a name, it stands for the address left unnamed
+            if let VariableValue::Unnamed(_) = variables[addr].value {
+                continue;
+            }
+            let name = &variables[addr].name;
+            match addr {
+                0x0000..=0x07ff => writeln!(out, "R:{:04X}:{}", addr, name)?,
+                0x2000..=0x401f => writeln!(out, "G:{:04X}:{}", addr, name)?,
+                0x6000..=0x7fff => writeln!(out, "S:{:04X}:{}", addr - 0x6000, name)?,
+                _ => {}
+            }
+        }
+        return Result::Ok(());
+    }
+
+    /// Labels, comments and variables as FCEUX name lists (.nl), by the part of the
+    /// file name between the ROM's name and `.nl`: `ram` for RAM and registers, and
+    /// the bank number in hex for each PRG ROM bank. Banks are 16k whatever the
+    /// mapper switches, since FCEUX's debugger numbers and loads them that way and
+    /// would skip lists split finer.
+    pub fn fceux_name_lists(&self) -> Vec<(String, String)> {
+        let mapper = self.mapper();
+        let prg_rom_offset = self.header.prg_rom_offset();
+        let prg_rom_end = self.header.prg_rom_offset() + self.header.prg_rom_size;
+        let mut banks: BTreeMap<usize, String> = BTreeMap::new();
+        for (offset, stmt) in self.d.code.statements().iter().enumerate() {
+            if !(prg_rom_offset..prg_rom_end).contains(&offset) {
+                continue;
+            }
+            if stmt.label.is_none() && stmt.comment.is_none() {
+                continue;
+            }
+            // lines after the first of a comment start with `\`
+            let comment = stmt
+                .comment
+                .as_deref()
+                .unwrap_or_default()
+                .replace('\n', "\n\\");
+            let bank = (offset - prg_rom_offset) / NES_PRG_ROM_PAGE_LENGTH;
+            *banks.entry(bank).or_default() += &format!(
+                "${:04X}#{}#{}\n",
+                mapper.to_addr(FileOffset(offset)).0,
+                stmt.label.as_deref().unwrap_or_default(),
+                comment
+            );
+        }
+
+        let mut ram = String::new();
+        let variables = self.d.code.collect_variables();
+        for addr in variables.keys().sorted() {
+            if *addr as usize >= NES_PRG_ROM_START_ADDRESS {
+                continue;
+            }
+            if let VariableValue::Unnamed(_) = variables[addr].value {
+                continue;
+            }
+            ram += &format!("${:04X}#{}#\n", addr, variables[addr].name);
+        }
+        let mut lists = vec![("ram".to_string(), ram)];
+        lists.extend(
+            banks
+                .into_iter()
+                .map(|(bank, list)| (format!("{:X}", bank), list)),
         );
-        self.d.code.set_variable(
-            0x2005,
-            Variable {
-                name: "PPU_SCROLL".to_string(),
-                value: VariableValue::U16(0x2005),
-            },
+        return lists;
+    }
+
+    /// Follows code starting at `addr` as if it were another entry point.
+    pub fn disassemble_at(&mut self, addr: CpuAddr) -> Result<(), DisassembleError> {
+        let mapper = self.mapper();
+        let offset = self.prg_rom_offset(addr)?;
+        let label_prefix = format!("prgrom{}", mapper.to_banked_addr(offset).bank);
+        return self.d.disassemble(
+            addr,
+            format!("{:04x}", addr).as_str(),
+            &label_prefix,
+            mapper.as_ref(),
         );
-        self.d.code.set_variable(
-            0x2006,
-            Variable {
-                name: "PPU_ADDR".to_string(),
-                value: VariableValue::U16(0x2006),
-            },
+    }
+
+    /// Names the code or data at `addr`, replacing the generated label
+    /// everywhere it is used.
+    pub fn set_label(&mut self, addr: CpuAddr, name: &str) -> Result<(), DisassembleError> {
+        let offset = self.prg_rom_offset(addr)?;
+        self.label_offset(offset, name);
+        return Result::Ok(());
+    }
+
+    /// Marks `start..=end` as data. Bytes already decoded as code are left alone,
+    /// or in strict mode refused.
+    pub fn mark_data(&mut self, start: CpuAddr, end: CpuAddr) -> Result<usize, DisassembleError> {
+        let start_offset = self.prg_rom_offset(start)?;
+        let end_offset = self.prg_rom_offset(end)?;
+        if end_offset < start_offset {
+            return Result::Err(DisassembleError::ParseError(format!(
+                "invalid range ${:04x}..${:04x}",
+                start, end
+            )));
+        }
+        let by = "data marked by the user";
+        if let Option::Some(claimed) = self
+            .d
+            .code
+            .first_instruction_byte(start_offset..end_offset + 1)
+        {
+            let mapper = self.mapper();
+            self.d.overlap(claimed, claimed, by, mapper.as_ref())?;
+        }
+        return self.d.code.mark_data(start_offset..end_offset + 1, by);
+    }
+
+    /// Writes `count` statements of the disassembly starting at `addr`.
+    pub fn write_at(
+        &self,
+        out: &mut dyn Write,
+        addr: CpuAddr,
+        count: usize,
+    ) -> Result<(), DisassembleError> {
+        let offset = self.prg_rom_offset(addr)?;
+        self.d.code.write_range(out, offset, count)?;
+        return Result::Ok(());
+    }
+
+    /// Statements as in [`NesDisassembler::write_json`], starting at `addr`.
+    pub fn json_statements_at(
+        &self,
+        addr: CpuAddr,
+        count: usize,
+    ) -> Result<Vec<JsonStatement>, DisassembleError> {
+        let offset = self.prg_rom_offset(addr)?;
+        return Result::Ok(self.d.code.to_json_statements_range(offset, count));
+    }
+
+    /// The NMI, RESET and IRQ vectors of each PRG ROM bank, named like the labels
+    /// the code they point at gets, read without disassembling anything.
+    pub fn vectors(data: &[u8]) -> Result<Vec<(String, CpuAddr)>, DisassembleError> {
+        let header = NesHeader::parse(data)?;
+        let mut vectors = Vec::new();
+        for prg_rom_idx in 0..header.prg_rom_page_count() {
+            let end = header.prg_rom_offset() + (prg_rom_idx + 1) * NES_PRG_ROM_PAGE_LENGTH;
+            if end > data.len() {
+                continue;
+            }
+            for (i, name) in ["nmi", "reset", "irq"].iter().enumerate() {
+                let offset = end - 6 + i * 2;
+                let addr = u16::from_le_bytes([data[offset], data[offset + 1]]);
+                vectors.push((format!("prgrom{}_{}", prg_rom_idx, name), CpuAddr(addr)));
+            }
+        }
+        return Result::Ok(vectors);
+    }
+
+    /// The mapper registered for the ROM's mapper number, otherwise NROM, with the
+    /// trainer at $7000 if there is one.
+    fn mapper(&self) -> Box<dyn Mapper> {
+        let rom: Box<dyn Mapper> = match mapper_factory(self.header.mapper) {
+            Option::Some(factory) => factory(&self.header),
+            Option::None => Box::new(NromMapper {
+                prg_rom_offset: self.header.prg_rom_offset(),
+                prg_rom_count: self.header.prg_rom_page_count(),
+            }),
+        };
+        if self.header.trainer {
+            return Box::new(TrainerMapper { rom });
+        }
+        return rom;
+    }
+
+    /// offset just past the PRG ROM, or the end of a truncated file
+    fn prg_rom_end(&self) -> usize {
+        return (self.header.prg_rom_offset() + self.header.prg_rom_size).min(self.data_len);
+    }
+
+    /// offsets of the trainer, if there is one, and the PRG ROM, the code the CPU
+    /// sees
+    fn code_offsets(&self) -> std::ops::Range<usize> {
+        let start = match self.header.trainer {
+            true => NES_HEADER_LENGTH,
+            false => self.header.prg_rom_offset(),
+        };
+        return start..self.prg_rom_end();
+    }
+
+    fn prg_rom_offset(&self, addr: CpuAddr) -> Result<FileOffset, DisassembleError> {
+        let prg_rom_end = self.prg_rom_end();
+        if (addr.0 as usize) < NES_PRG_ROM_START_ADDRESS {
+            return Result::Err(DisassembleError::ParseError(format!(
+                "${:04x} is not in PRG ROM",
+                addr
+            )));
+        }
+        return match self.mapper().to_offset(addr) {
+            Option::Some(offset) if offset.0 < prg_rom_end => Result::Ok(offset),
+            _ => Result::Err(DisassembleError::ParseError(format!(
+                "${:04x} is not in PRG ROM",
+                addr
+            ))),
+        };
+    }
+
+    /// Writes operands addressing `start..=end` as numbers, not with the variable
+    /// name or label they would otherwise get.
+    pub fn keep_unnamed(&mut self, start: CpuAddr, end: CpuAddr) {
+        self.d.code.keep_unnamed(start.0..=end.0);
+    }
+
+    /// Exports the label `symbol` or the code or data at the address `symbol`,
+    /// labeling it if it isn't yet.
+    pub fn export(&mut self, symbol: &str) -> Result<(), DisassembleError> {
+        let labeled = (0..self.d.code.end().0)
+            .map(FileOffset)
+            .find(|offset| self.d.code.label(*offset) == Option::Some(symbol));
+        let offset = match labeled {
+            Option::Some(offset) => offset,
+            Option::None => {
+                let addr = parse_addr(symbol).map_err(|_| {
+                    DisassembleError::ParseError(format!(
+                        "can't export \"{}\", expected a label or an address",
+                        symbol
+                    ))
+                })?;
+                let offset = self.prg_rom_offset(addr)?;
+                if self.d.code.statement_start(offset) != offset {
+                    return Result::Err(DisassembleError::ParseError(format!(
+                        "can't export ${:04x}, it is in the middle of an instruction or word",
+                        addr
+                    )));
+                }
+                if self.d.code.label(offset).is_none() {
+                    let bank = self.mapper().to_banked_addr(offset).bank;
+                    self.d
+                        .code
+                        .set_label(offset, format!("prgrom{}_{:04x}", bank, addr).as_str());
+                }
+                offset
+            }
+        };
+        self.d.code.export(offset);
+        return Result::Ok(());
+    }
+
+    fn label_offset(&mut self, offset: FileOffset, name: &str) {
+        let addr = self.mapper().to_banked_addr(offset);
+        if let Option::Some(existing) = self.d.code.label(offset).map(|l| l.to_string()) {
+            self.d.code.rename_label(&existing, name);
+        }
+        self.d.code.rename_label(
+            format!("prgrom{}_{:04x}", addr.bank, addr.addr).as_str(),
+            name,
+        );
+        self.d.code.set_label(offset, name);
+    }
+
+    fn set_variables(&mut self) {
+        if self.d.quirks.io_port {
+            for (addr, name) in [(0x00, "CPU_PORT_DDR"), (0x01, "CPU_PORT")] {
+                self.d.code.set_variable(
+                    addr,
+                    Variable {
+                        name: name.to_string(),
+                        value: VariableValue::U8(addr as u8),
+                    },
+                );
+            }
+        }
+        self.d.code.set_variable(
+            0x2000,
+            Variable {
+                name: "PPU_CTRL".to_string(),
+                value: VariableValue::U16(0x2000),
+            },
+        );
+        self.d.code.set_variable(
+            0x2001,
+            Variable {
+                name: "PPU_MASK".to_string(),
+                value: VariableValue::U16(0x2001),
+            },
+        );
+        self.d.code.set_variable(
+            0x2002,
+            Variable {
+                name: "PPU_STATUS".to_string(),
+                value: VariableValue::U16(0x2002),
+            },
+        );
+        self.d.code.set_variable(
+            0x2003,
+            Variable {
+                name: "OAM_ADDR".to_string(),
+                value: VariableValue::U16(0x2003),
+            },
+        );
+        self.d.code.set_variable(
+            0x2004,
+            Variable {
+                name: "OAM_DATA".to_string(),
+                value: VariableValue::U16(0x2004),
+            },
+        );
+        self.d.code.set_variable(
+            0x2005,
+            Variable {
+                name: "PPU_SCROLL".to_string(),
+                value: VariableValue::U16(0x2005),
+            },
+        );
+        self.d.code.set_variable(
+            0x2006,
+            Variable {
+                name: "PPU_ADDR".to_string(),
+                value: VariableValue::U16(0x2006),
+            },
         );
         self.d.code.set_variable(
             0x2007,
@@ -219,306 +1498,3652 @@ impl NesDisassembler {
                 value: VariableValue::U16(0x400d),
             },
         );
-        self.d.code.set_variable(
-            0x400e,
-            Variable {
-                name: "APU_NOISE_LP".to_string(),
-                value: VariableValue::U16(0x400e),
-            },
+        self.d.code.set_variable(
+            0x400e,
+            Variable {
+                name: "APU_NOISE_LP".to_string(),
+                value: VariableValue::U16(0x400e),
+            },
+        );
+        self.d.code.set_variable(
+            0x400f,
+            Variable {
+                name: "APU_NOISE_LOAD".to_string(),
+                value: VariableValue::U16(0x400f),
+            },
+        );
+        self.d.code.set_variable(
+            0x4010,
+            Variable {
+                name: "APU_DMC_IL__RRRR".to_string(),
+                value: VariableValue::U16(0x4010),
+            },
+        );
+        self.d.code.set_variable(
+            0x4011,
+            Variable {
+                name: "APU_DMC_LOAD".to_string(),
+                value: VariableValue::U16(0x4011),
+            },
+        );
+        self.d.code.set_variable(
+            0x4012,
+            Variable {
+                name: "APU_DMC_SAMPLE_ADDR".to_string(),
+                value: VariableValue::U16(0x4012),
+            },
+        );
+        self.d.code.set_variable(
+            0x4013,
+            Variable {
+                name: "APU_DMC_SAMPLE_LEN".to_string(),
+                value: VariableValue::U16(0x4013),
+            },
+        );
+        self.d.code.set_variable(
+            0x4014,
+            Variable {
+                name: "OAM_DMA".to_string(),
+                value: VariableValue::U16(0x4014),
+            },
+        );
+        self.d.code.set_variable(
+            0x4015,
+            Variable {
+                name: "APU_CH_ENABLE_STATUS".to_string(),
+                value: VariableValue::U16(0x4015),
+            },
+        );
+        self.d.code.set_variable(
+            0x4017,
+            Variable {
+                name: "APU_ALL_FRAME_COUNTER".to_string(),
+                value: VariableValue::U16(0x4017),
+            },
+        );
+    }
+
+    fn parse_header(&mut self) -> Result<(), DisassembleError> {
+        if self.d.code.is_eq_u8(FileOffset(0), b'N')
+            && self.d.code.is_eq_u8(FileOffset(1), b'E')
+            && self.d.code.is_eq_u8(FileOffset(2), b'S')
+            && self.d.code.is_eq_u8(FileOffset(3), 0x1a)
+        {
+            // a `.charmap` of N, E or S would change what the string assembles to
+            let mut magic = if self.d.code.charmap_changes("NES") {
+                b"NES".iter().map(|b| AsmCode::DataHexU8(*b)).collect()
+            } else {
+                vec![AsmCode::DataString("NES".to_string())]
+            };
+            magic.push(AsmCode::DataHexU8(0x1a));
+            self.d
+                .code
+                .replace(FileOffset(0)..FileOffset(4), AsmCode::DataSeq(magic))?;
+            self.d.code.set_segment(FileOffset(0), "HEADER");
+        } else {
+            return Result::Err(DisassembleError::ParseError(
+                "invalid nes header".to_string(),
+            ));
+        }
+
+        if self.header.format == HeaderFormat::ArchaicINes {
+            self.d.diagnostics.push(Diagnostic::warning(
+                FileOffset(7),
+                Option::None,
+                ARCHAIC_INES_NOTE.to_string(),
+            ));
+        }
+
+        self.d.code.replace_with_u8(FileOffset(4))?;
+        self.d.code.set_comment(FileOffset(4), "PRG ROM count");
+
+        self.d.code.replace_with_u8(FileOffset(5))?;
+        let chr_rom_count = match self.header.has_chr_ram() {
+            true => "CHR ROM count, none so the tiles are in CHR RAM",
+            false => "CHR ROM count",
+        };
+        self.d.code.set_comment(FileOffset(5), chr_rom_count);
+
+        self.d.code.replace_with_binary_u8(FileOffset(6))?;
+        self.d.code.set_comment(
+            FileOffset(6),
+            "Flags 6
+      ++++------ Mapper Number D0..D3
+      ||||        1: Yes
+      ||||        0: No
+      ||||+----- Hard-wired four-screen mode
+      |||||       1: Present between Header and PRG-ROM data
+      |||||       0: Not present
+      |||||+---- 512-byte Trainer
+      ||||||      1: Present
+      ||||||      0: Not present
+      ||||||+--- \"Battery\" and other non-volatile memory
+      |||||||     1: Vertical
+      |||||||     0: Horizontal or mapper-controlled
+      |||||||+-- Hard-wired nametable mirroring type
+      NNNNFTBM",
+        );
+
+        self.d.code.replace_with_binary_u8(FileOffset(7))?;
+        self.d.code.set_comment(
+            FileOffset(7),
+            "Flags 7
+      ++++------ Mapper Number D4..D7
+      ||||++---- NES 2.0 identifier
+      ||||||      3: Extended Console Type
+      ||||||      2: Nintendo Playchoice 10
+      ||||||      1: Nintendo Vs. System
+      ||||||      0: Nintendo Entertainment System/Family Computer
+      ||||||++-- Console type
+      NNNN10TT",
+        );
+
+        self.d.code.replace_with_binary_u8(FileOffset(8))?;
+        self.d.code.set_comment(
+            FileOffset(8),
+            "Mapper MSB/Submapper
+      ++++------ Submapper number
+      ||||++++-- Mapper number D8..D11
+      SSSSNNNN",
+        );
+
+        self.d.code.replace_with_binary_u8(FileOffset(9))?;
+        self.d.code.set_comment(
+            FileOffset(9),
+            "PRG-ROM/CHR-ROM size MSB
+      ++++------ CHR-ROM size MSB
+      ||||++++-- PRG-ROM size MSB
+      CCCCPPPP",
+        );
+
+        self.d.code.replace_with_binary_u8(FileOffset(10))?;
+        self.d.code.set_comment(
+            FileOffset(10),
+            "PRG-RAM/EEPROM size            
+  If the shift count is zero, there is no PRG-(NV)RAM.
+  If the shift count is non-zero, the actual size is
+  \"64 << shift count\" bytes, i.e. 8192 bytes for a shift count of 7.
+      ++++------ PRG-NVRAM/EEPROM (non-volatile) shift count
+      ||||++++-- PRG-RAM (volatile) shift count
+      ppppPPPP",
+        );
+
+        self.d.code.replace_with_binary_u8(FileOffset(11))?;
+        self.d.code.set_comment(
+            FileOffset(11),
+            "CHR-RAM size
+  If the shift count is zero, there is no CHR-(NV)RAM.
+  If the shift count is non-zero, the actual size is
+  \"64 << shift count\" bytes, i.e. 8192 bytes for a shift count of 7.
+      ++++------ CHR-NVRAM size (non-volatile) shift count
+      ||||++++-- CHR-RAM size (volatile) shift count
+      ccccCCCC",
+        );
+
+        self.d.code.replace_with_binary_u8(FileOffset(12))?;
+        self.d.code.set_comment(
+            FileOffset(12),
+            "CPU/PPU Timing
+            ++- CPU/PPU timing mode
+            ||   0: RP2C02 (\"NTSC NES\")
+            ||   1: RP2C07 (\"Licensed PAL NES\")
+            ||   2: Multiple-region
+            ||   3: UMC 6527P (\"Dendy\")
+      ......VV",
+        );
+
+        self.d.code.replace_with_binary_u8(FileOffset(13))?;
+        self.d.code.set_comment(
+            FileOffset(13),
+            "When Byte 7 AND 3 =1: Vs. System Type
+      ++++------ Vs. Hardware Type
+      ||||++++-- Vs. PPU Type
+      MMMMPPPP",
+        );
+
+        self.d.code.replace_with_binary_u8(FileOffset(14))?;
+        self.d.code.set_comment(
+            FileOffset(14),
+            "Miscellaneous ROMs
+            ++- Number of miscellaneous ROMs present
+      ......RR",
+        );
+
+        self.d.code.replace_with_binary_u8(FileOffset(15))?;
+        self.d.code.set_comment(
+            FileOffset(15),
+            "Default Expansion Device
+        ++++++- Default Expansion Device
+      ..DDDDDD",
+        );
+
+        return Result::Ok(());
+    }
+
+    /// Pads every PRG/CHR page missing from a truncated file so each segment keeps
+    /// the size the header gives it when reassembled.
+    fn pad_missing_pages(&mut self) {
+        let mut end = self.header.prg_rom_offset();
+        let pages = (0..self.header.prg_rom_page_count())
+            .map(|i| (format!("PRGROM{}", i), NES_PRG_ROM_PAGE_LENGTH))
+            .chain(
+                (0..self.header.chr_rom_page_count())
+                    .map(|i| (format!("CHRROM{}", i), NES_CHR_ROM_PAGE_LENGTH)),
+            );
+        for (segment, page_length) in pages {
+            end += page_length;
+            if self.d.code.end().0 < end {
+                let missing = end - self.d.code.end().0;
+                self.d.diagnostics.push(Diagnostic::warning(
+                    self.d.code.end(),
+                    Option::None,
+                    format!("{} bytes of {} missing from input", missing, segment),
+                ));
+                self.d.code.pad(
+                    FileOffset(end),
+                    format!(
+                        "padding: {} bytes of {} missing from input",
+                        missing, segment
+                    )
+                    .as_str(),
+                );
+            }
+        }
+    }
+
+    fn parse_chr_rom(&mut self) -> Result<(), DisassembleError> {
+        let format = self.tile_format;
+        let chr_rom_start_offset = self.header.prg_rom_offset() + self.header.prg_rom_size;
+        for chr_rom_index in 0..self.header.chr_rom_page_count() {
+            let start = FileOffset(chr_rom_start_offset + chr_rom_index * NES_CHR_ROM_PAGE_LENGTH);
+            let len = NES_CHR_ROM_PAGE_LENGTH.min(self.data_len.saturating_sub(start.0));
+            self.group_tiles(start, len, &format)?;
+            self.d
+                .code
+                .set_segment(start, format!("CHRROM{}", chr_rom_index).as_str());
+        }
+        return Result::Ok(());
+    }
+
+    /// Writes each whole tile of the `len` bytes of graphics at `start` as a row of
+    /// `.byte`s.
+    fn group_tiles(
+        &mut self,
+        start: FileOffset,
+        len: usize,
+        format: &TileFormat,
+    ) -> Result<(), DisassembleError> {
+        self.tile_regions.push((start, len));
+        for tile in tile_offsets(len, format) {
+            let offset = start + tile;
+            let mut bytes = Vec::new();
+            for i in 0..format.tile_len() {
+                bytes.push(self.d.code.take(offset + i)?.asm_code);
+            }
+            self.d.code.set(
+                offset,
+                Statement {
+                    asm_code: AsmCode::DataSeq(bytes),
+                    comment: Option::None,
+                    segment: Option::None,
+                    label: Option::None,
+                },
+            )?;
+        }
+        return Result::Ok(());
+    }
+
+    fn parse_misc_rom(&mut self) -> Result<(), DisassembleError> {
+        let misc_rom_size = self.header.misc_rom_size(self.data_len);
+        if misc_rom_size == 0 {
+            return Result::Ok(());
+        }
+
+        let start = FileOffset(self.header.misc_rom_offset());
+        let end = start + misc_rom_size;
+        let mut offset = start;
+        while offset < end {
+            let len = (end.0 - offset.0).min(16);
+            let mut bytes = Vec::new();
+            for i in 0..len {
+                bytes.push(self.d.code.take(offset + i)?.asm_code);
+            }
+            self.d.code.set(
+                offset,
+                Statement {
+                    asm_code: AsmCode::DataSeq(bytes),
+                    comment: Option::None,
+                    segment: Option::None,
+                    label: Option::None,
+                },
+            )?;
+            offset += len;
+        }
+        self.d.code.set_segment(start, "MISCROM");
+        self.d.code.set_label(start, "miscrom");
+        self.d.code.set_comment(
+            start,
+            format!(
+                "{} misc ROM(s), {} bytes",
+                self.header.misc_rom_count, misc_rom_size
+            )
+            .as_str(),
+        );
+        return Result::Ok(());
+    }
+
+    fn disassemble_entry_points(
+        &mut self,
+        progress: &mut dyn Progress,
+    ) -> Result<(), DisassembleError> {
+        let mapper = self.mapper();
+        let mut offset = FileOffset(self.header.prg_rom_offset());
+        let banks = self.header.prg_rom_page_count();
+        for prg_rom_idx in 0..banks {
+            if offset.0 + NES_PRG_ROM_PAGE_LENGTH > self.data_len {
+                // vectors are missing, the page is only padding
+                self.d
+                    .code
+                    .set_segment(offset, format!("PRGROM{}", prg_rom_idx).as_str());
+                offset += NES_PRG_ROM_PAGE_LENGTH;
+                continue;
+            }
+            let label_prefix = format!("prgrom{}", prg_rom_idx);
+            for (vector, name) in [(6, "nmi"), (4, "reset"), (2, "irq")] {
+                let vector = offset + (NES_PRG_ROM_PAGE_LENGTH - vector);
+                let addr = self.decode_vector(vector, &name.to_uppercase())?;
+                let Option::Some(entry_offset) = mapper.to_offset(addr) else {
+                    self.d.diagnostics.push(Diagnostic::warning(
+                        vector,
+                        Option::Some(mapper.to_banked_addr(vector)),
+                        format!(
+                            "the {} vector points to ${:04x}, which isn't in the ROM, not followed",
+                            name.to_uppercase(),
+                            addr
+                        ),
+                    ));
+                    continue;
+                };
+                let entry = format!("{}_{}", label_prefix, name);
+                progress.entry_point_started(&entry, addr);
+                self.d
+                    .disassemble(addr, name, &label_prefix, mapper.as_ref())?;
+                self.d.code.export(entry_offset);
+                self.entry_points.push((entry, entry_offset));
+            }
+
+            self.d
+                .code
+                .set_segment(offset, format!("PRGROM{}", prg_rom_idx).as_str());
+            progress.bank_completed(prg_rom_idx, banks);
+            progress.bytes_classified(self.d.code.classified(), self.data_len);
+
+            offset += NES_PRG_ROM_PAGE_LENGTH;
+        }
+
+        return Result::Ok(());
+    }
+
+    /// Traces the trainer from $7000. The SRAM it addresses gets variables and
+    /// the PRG ROM code it jumps or calls into, often the reset code it patched
+    /// the vector of, gets labeled and commented as hooked.
+    fn disassemble_trainer(&mut self, progress: &mut dyn Progress) -> Result<(), DisassembleError> {
+        let trainer_end = self.header.prg_rom_offset();
+        if !self.header.trainer || self.data_len < trainer_end {
+            return Result::Ok(());
+        }
+        let mapper = self.mapper();
+        let prg_rom_end = self.prg_rom_end();
+        // the trace renames the labels of PRG ROM code it reaches
+        let prg_labels: HashMap<FileOffset, String> = (trainer_end..prg_rom_end)
+            .filter_map(|o| Option::Some((FileOffset(o), self.d.code.label(FileOffset(o))?)))
+            .map(|(offset, label)| (offset, label.to_string()))
+            .collect();
+
+        let start = CpuAddr(NES_TRAINER_START_ADDRESS as u16);
+        progress.entry_point_started("trainer_start", start);
+        self.d
+            .disassemble(start, "start", "trainer", mapper.as_ref())?;
+        let start = FileOffset(NES_HEADER_LENGTH);
+        self.d.code.export(start);
+        self.d.code.set_segment(start, "TRAINER");
+        self.entry_points.push(("trainer_start".to_string(), start));
+
+        let mut hooks: BTreeMap<u16, Vec<u16>> = BTreeMap::new();
+        let mut sram = BTreeSet::new();
+        for offset in NES_HEADER_LENGTH..trainer_end {
+            if !self.d.code.is_instruction(FileOffset(offset)) {
+                continue;
+            }
+            let addr = mapper.to_addr(FileOffset(offset)).0;
+            let Option::Some(instr) = decode(&self.d.code.data()[offset..], addr) else {
+                continue;
+            };
+            let absolute = matches!(
+                instr.mode,
+                AddressingMode::Absolute | AddressingMode::AbsoluteX | AddressingMode::AbsoluteY
+            );
+            match instr.target() {
+                Option::Some(target) if target as usize >= NES_PRG_ROM_START_ADDRESS => {
+                    hooks.entry(target).or_default().push(addr);
+                }
+                Option::Some(_) => {}
+                Option::None
+                    if absolute
+                        && NES_SRAM.contains(&instr.operand)
+                        && !TrainerMapper::ADDRS.contains(&(instr.operand as usize)) =>
+                {
+                    sram.insert(instr.operand);
+                }
+                Option::None => {}
+            }
+        }
+
+        for addr in sram {
+            if self.d.code.variable(addr).is_none() {
+                self.d.code.set_variable(
+                    addr,
+                    Variable {
+                        name: format!("sram_{:04x}", addr),
+                        value: VariableValue::U16(addr),
+                    },
+                );
+            }
+        }
+        for (target, from) in hooks {
+            let offset = match mapper.to_offset(CpuAddr(target)) {
+                Option::Some(offset) if (trainer_end..prg_rom_end).contains(&offset.0) => offset,
+                _ => continue,
+            };
+            let name = prg_labels
+                .get(&offset)
+                .cloned()
+                .unwrap_or_else(|| format!("trainer_hook_{:04x}", target));
+            // the trainer's jumps and calls name it trainer_xxxx, a jmp into code
+            // traced before doesn't label it
+            let traced = self.d.code.label(offset).map(str::to_string);
+            for label in traced
+                .into_iter()
+                .chain([format!("trainer_{:04x}", target)])
+            {
+                if label != name {
+                    self.d.code.rename_label(&label, &name);
+                }
+            }
+            self.d.code.set_label(offset, &name);
+            let from = from.iter().map(|addr| format!("${:04x}", addr)).join(", ");
+            self.d
+                .code
+                .add_comment(offset, &format!("hooked by the trainer at {}", from));
+        }
+        return Result::Ok(());
+    }
+
+    /// Warns about code changing itself, see
+    /// [`Disassembler::find_self_modifying_code`]. Only the trainer is code in
+    /// writable memory, stores to PRG ROM go to the mapper.
+    fn find_self_modifying_code(&mut self) {
+        if !self.header.trainer {
+            return;
+        }
+        let mapper = self.mapper();
+        self.d.find_self_modifying_code(
+            FileOffset(NES_HEADER_LENGTH)..FileOffset(self.prg_rom_end()),
+            &|addr| TrainerMapper::ADDRS.contains(&(addr as usize)),
+            mapper.as_ref(),
+        );
+    }
+
+    /// Warns about stores to the cartridge ROM when the mapper has no registers
+    /// there, naming the routine they are in, since nothing takes the write and
+    /// the store is more likely data decoded as code.
+    fn find_rom_writes(&mut self) {
+        if has_rom_registers(self.header.mapper) {
+            return;
+        }
+        let mapper = self.mapper();
+        let stmts = self.d.code.statements();
+        let mut writes = Vec::new();
+        for offset in self.header.prg_rom_offset()..self.prg_rom_end() {
+            if !self.d.code.is_instruction(FileOffset(offset)) {
+                continue;
+            }
+            let addr = mapper.to_addr(FileOffset(offset));
+            let Option::Some(instr) = decode(&self.d.code.data()[offset..], addr.0) else {
+                continue;
+            };
+            let target = match operand_access(&instr) {
+                Option::Some((target, access))
+                    if access.writes() && target as usize >= NES_PRG_ROM_START_ADDRESS =>
+                {
+                    target
+                }
+                _ => continue,
+            };
+            // the label the code runs down from to the store
+            let routine = (self.header.prg_rom_offset()..=offset)
+                .rev()
+                .take_while(|o| {
+                    matches!(stmts[*o].asm_code, AsmCode::Instruction(_) | AsmCode::Used)
+                })
+                .find_map(|o| stmts[o].label.as_deref());
+            let routine = match routine {
+                Option::Some(label) => format!(" in {}", label),
+                Option::None => String::new(),
+            };
+            writes.push((FileOffset(offset), addr, target, routine));
+        }
+
+        for (offset, addr, target, routine) in writes {
+            let message = format!(
+                "${:04x}{} writes ROM at ${:04x}, mapper {} has no registers there, likely \
+                 data decoded as code",
+                addr.0, routine, target, self.header.mapper
+            );
+            self.d.diagnostics.push(Diagnostic::warning(
+                offset,
+                Option::Some(mapper.to_banked_addr(offset)),
+                message,
+            ));
+            self.d
+                .code
+                .add_comment(offset, "writes ROM, nothing takes the write");
+        }
+    }
+
+    /// Worst case cycles of each NMI handler from the interrupt on, see
+    /// [`worst_case_cycles`].
+    fn nmi_budgets(&self) -> Vec<(FileOffset, Result<Cycles, Unbounded>)> {
+        let mapper = self.mapper();
+        let data = self.d.code.data();
+        let prg_rom_end = self.prg_rom_end();
+        let decode_at = |addr: u16| {
+            if (addr as usize) < NES_PRG_ROM_START_ADDRESS {
+                return Option::None;
+            }
+            let offset = mapper.to_offset(CpuAddr(addr))?;
+            if offset.0 >= prg_rom_end || !self.d.code.is_instruction(offset) {
+                return Option::None;
+            }
+            return decode(&data[offset.0..], addr);
+        };
+        let interrupt = Cycles {
+            total: INTERRUPT_CYCLES,
+            ppu: Option::None,
+        };
+        return self
+            .entry_points
+            .iter()
+            .filter(|(name, _)| name.ends_with("_nmi"))
+            .map(|(_, offset)| *offset)
+            .unique()
+            .map(|offset| {
+                let cycles = worst_case_cycles(mapper.to_addr(offset).0, &decode_at);
+                return (offset, cycles.map(|cycles| interrupt.then(cycles)));
+            })
+            .collect();
+    }
+
+    /// Warns about NMI handlers whose PPU updates can run past the end of vblank,
+    /// where writing the PPU garbles the picture.
+    fn check_nmi_budgets(&mut self) {
+        let mapper = self.mapper();
+        let vblank = vblank_cycles(self.header.timing);
+        for (offset, cycles) in self.nmi_budgets() {
+            let ppu = match cycles {
+                Result::Ok(Cycles {
+                    ppu: Option::Some(ppu),
+                    ..
+                }) if ppu > vblank => ppu,
+                _ => continue,
+            };
+            let message = format!(
+                "NMI handler at ${:04x} can take {} cycles up to its last PPU access, more \
+                 than the {} of vblank on {}",
+                mapper.to_addr(offset).0,
+                ppu,
+                vblank,
+                self.header.timing
+            );
+            self.d.diagnostics.push(Diagnostic::warning(
+                offset,
+                Option::Some(mapper.to_banked_addr(offset)),
+                message,
+            ));
+        }
+    }
+
+    /// Writes runs of at least `min_len` PRG ROM bytes no code or data claimed that
+    /// read as text, through `charmap` if given, as strings.
+    fn find_text(&mut self, charmap: &BTreeMap<u8, char>, min_len: usize) {
+        let prg_rom_end = self.prg_rom_end();
+        let mut texts = Vec::new();
+        let mut offset = self.header.prg_rom_offset();
+        while offset < prg_rom_end {
+            let stmts = self.d.code.statements();
+            // a run of plain bytes, without labels, comments or segments to keep
+            // after its first byte
+            let run_end = (offset..prg_rom_end)
+                .find(|o| {
+                    let stmt = &stmts[*o];
+                    let annotated =
+                        stmt.label.is_some() || stmt.comment.is_some() || stmt.segment.is_some();
+                    return !matches!(stmt.asm_code, AsmCode::DataHexU8(_))
+                        || (*o > offset && annotated);
+                })
+                .unwrap_or(prg_rom_end);
+            if run_end == offset {
+                offset += 1;
+                continue;
+            }
+            let mut start = offset;
+            while start < run_end {
+                match text_at(&self.d.code.data()[start..run_end], charmap, min_len) {
+                    Option::Some(text) => {
+                        let len = text.len();
+                        texts.push((start, text));
+                        start += len;
+                    }
+                    Option::None => start += 1,
+                }
+            }
+            offset = run_end;
+        }
+        for (start, text) in texts {
+            let end = FileOffset(start + text.len());
+            // replacing bytes with a string can't fail
+            let _ = self
+                .d
+                .code
+                .replace(FileOffset(start)..end, AsmCode::DataString(text));
+        }
+    }
+
+    /// Follows the decoded code from each entry point, falling through, branching,
+    /// jumping and calling, to record which entry points reach each instruction.
+    fn find_reachability(&mut self) {
+        for (name, start) in &self.entry_points {
+            for offset in self.reachable(*start, true) {
+                self.reached_from
+                    .entry(offset)
+                    .or_default()
+                    .insert(name.clone());
+            }
+        }
+    }
+
+    /// Offsets of the decoded instructions reached from `start`, leaving out the
+    /// subroutines called unless `follow_calls`.
+    fn reachable(&self, start: FileOffset, follow_calls: bool) -> HashSet<FileOffset> {
+        let mapper = self.mapper();
+        let data = self.d.code.data();
+        let mut result = HashSet::new();
+        let mut pending = vec![start];
+        while let Option::Some(offset) = pending.pop() {
+            if !self.d.code.is_instruction(offset) || !result.insert(offset) {
+                continue;
+            }
+            let addr = mapper.to_addr(offset);
+            let instr = match decode(&data[offset.0..], addr.0) {
+                Option::Some(instr) => instr,
+                Option::None => continue,
+            };
+            if !matches!(instr.mnemonic, "rts" | "rti" | "jmp") {
+                pending.push(offset + instr_len(&instr));
+            }
+            if let Option::Some(target) = instr.target() {
+                let call = instr.mnemonic == "jsr";
+                if target as usize >= NES_PRG_ROM_START_ADDRESS && (follow_calls || !call) {
+                    pending.extend(mapper.to_offset(CpuAddr(target)));
+                }
+            }
+        }
+        return result;
+    }
+
+    /// Where `--only-from` starts: the entry points named by `root`, either a
+    /// vector (`reset`) in every bank, an entry point (`prgrom1_nmi`) or an
+    /// address, which is followed as code if it hasn't been yet.
+    fn find_roots(&mut self, root: &str) -> Result<Vec<FileOffset>, DisassembleError> {
+        let named = self
+            .entry_points
+            .iter()
+            .filter(|(name, _)| name == root || name.ends_with(&format!("_{}", root)))
+            .map(|(_, offset)| *offset)
+            .collect::<Vec<FileOffset>>();
+        if !named.is_empty() {
+            return Result::Ok(named);
+        }
+        let addr = parse_addr(root).map_err(|_| {
+            DisassembleError::ParseError(format!(
+                "unknown root \"{}\", expected nmi, reset, irq, an entry point like \
+                 prgrom0_reset or an address",
+                root
+            ))
+        })?;
+        let offset = self.prg_rom_offset(addr)?;
+        if !self.d.code.is_instruction(offset) {
+            self.disassemble_at(addr)?;
+        }
+        return Result::Ok(vec![offset]);
+    }
+
+    /// Leaves everything out of the output but the code reached from `roots` and
+    /// the tables in PRG ROM it reads. Tables end where any code, reached or not,
+    /// reads the next one.
+    fn restrict_output(&mut self, roots: &[FileOffset]) {
+        let mapper = self.mapper();
+        let data = self.d.code.data();
+        let prg_rom_end = self.prg_rom_end();
+        // the instruction at `offset`, with the table in PRG ROM it reads
+        let decode_at = |offset: FileOffset| {
+            let instr = decode(&data[offset.0..], mapper.to_addr(offset).0)?;
+            let reads_rom = matches!(
+                instr.mode,
+                AddressingMode::Absolute
+                    | AddressingMode::AbsoluteX
+                    | AddressingMode::AbsoluteY
+                    | AddressingMode::Indirect
+            ) && instr.operand as usize >= NES_PRG_ROM_START_ADDRESS
+                && instr.target().is_none();
+            let table = mapper
+                .to_offset(CpuAddr(instr.operand))
+                .filter(|_| reads_rom);
+            return Option::Some((instr, table));
+        };
+        // tables read, with the instructions reading them
+        let mut refs: HashMap<usize, Vec<usize>> = HashMap::new();
+        for offset in self.code_offsets().map(FileOffset) {
+            if !self.d.code.is_instruction(offset) {
+                continue;
+            }
+            if let Option::Some((_, Option::Some(table))) = decode_at(offset) {
+                refs.entry(table.0).or_default().push(offset.0);
+            }
+        }
+        let mut only = HashSet::new();
+        let mut read = HashSet::new();
+        for root in roots {
+            for offset in self.reachable(*root, true) {
+                let Option::Some((instr, table)) = decode_at(offset) else {
+                    continue;
+                };
+                only.extend(offset.0..offset.0 + instr_len(&instr));
+                read.extend(table.map(|table| table.0));
+            }
+        }
+        for table in read {
+            if let Option::Some(len) = self.table_len(table, prg_rom_end, &refs) {
+                only.extend(table..table + len);
+            }
+        }
+        self.d.code.set_only(only);
+    }
+
+    /// Tags the routine or data table at `addr`, e.g. as `audio`, returning how
+    /// many bytes it has. A routine is the code reached from `addr` without the
+    /// subroutines it calls, a table runs up to the next label, code or fill.
+    pub fn tag(&mut self, addr: CpuAddr, tag: &str) -> Result<usize, DisassembleError> {
+        let offset = self.prg_rom_offset(addr)?;
+        let mapper = self.mapper();
+        let data = self.d.code.data();
+        let mut bytes = BTreeSet::new();
+        if self.d.code.is_instruction(offset) {
+            for instr_offset in self.reachable(offset, false) {
+                let addr = mapper.to_addr(instr_offset);
+                if let Option::Some(instr) = decode(&data[instr_offset.0..], addr.0) {
+                    bytes.extend(instr_offset.0..instr_offset.0 + instr_len(&instr));
+                }
+            }
+        } else {
+            let prg_rom_end = self.prg_rom_end();
+            let len = self
+                .table_len(offset.0, prg_rom_end, &HashMap::new())
+                .ok_or_else(|| {
+                    DisassembleError::ParseError(format!(
+                        "no routine or table at ${:04x} to tag",
+                        addr
+                    ))
+                })?;
+            bytes.extend(offset.0..offset.0 + len);
+        }
+        let len = bytes.len();
+        self.tags.entry(tag.to_string()).or_default().extend(bytes);
+        self.d.code.add_comment(offset, &format!("tag {}", tag));
+        return Result::Ok(len);
+    }
+
+    /// Leaves everything out of the output but what is tagged with one of
+    /// `include`, if given, then what is tagged with one of `exclude`.
+    fn filter_tags(
+        &mut self,
+        include: &[String],
+        exclude: &[String],
+    ) -> Result<(), DisassembleError> {
+        for tag in include.iter().chain(exclude) {
+            if !self.tags.contains_key(tag) {
+                return Result::Err(DisassembleError::ParseError(format!(
+                    "nothing is tagged \"{}\"",
+                    tag
+                )));
+            }
+        }
+        if !include.is_empty() {
+            let only = include
+                .iter()
+                .flat_map(|tag| &self.tags[tag])
+                .copied()
+                .collect();
+            self.d.code.set_only(only);
+        }
+        if !exclude.is_empty() {
+            let excluded = exclude
+                .iter()
+                .flat_map(|tag| &self.tags[tag])
+                .collect::<HashSet<&usize>>();
+            let only = (0..self.data_len)
+                .filter(|o| !excluded.contains(o))
+                .collect();
+            self.d.code.set_only(only);
+        }
+        return Result::Ok(());
+    }
+
+    /// Tags with the labels of what they cover, for the JSON report.
+    fn json_tags(&self) -> Vec<JsonTag> {
+        let stmts = self.d.code.statements();
+        return self
+            .tags
+            .iter()
+            .map(|(name, bytes)| JsonTag {
+                name: name.clone(),
+                labels: bytes
+                    .iter()
+                    .filter_map(|offset| stmts[*offset].label.as_deref().map(str::to_string))
+                    .collect(),
+                bytes: bytes.len(),
+            })
+            .collect();
+    }
+
+    /// Comments the instructions where the set of entry points reaching the code
+    /// changes, e.g. where NMI-only code starts.
+    fn comment_reachability(&mut self) {
+        let mut previous: Option<&BTreeSet<String>> = Option::None;
+        let mut comments = Vec::new();
+        for offset in self.reached_from.keys().sorted() {
+            let reached = &self.reached_from[offset];
+            if previous != Option::Some(reached) {
+                let names = reached.iter().join(", ");
+                comments.push((*offset, format!("reached from {}", names)));
+            }
+            previous = Option::Some(reached);
+        }
+        for (offset, comment) in comments {
+            self.d.code.add_comment(offset, &comment);
+        }
+    }
+
+    /// Comments each instruction with the cycles it takes, a range when crossing a
+    /// page or taking a branch costs more.
+    fn comment_cycles(&mut self) {
+        let mapper = self.mapper();
+        let mut comments = Vec::new();
+        for (offset, stmt) in self.d.code.statements().iter().enumerate() {
+            if !matches!(stmt.asm_code, AsmCode::Instruction(_)) {
+                continue;
+            }
+            let addr = mapper.to_addr(FileOffset(offset));
+            if let Option::Some(instr) = decode(&self.d.code.data()[offset..], addr.0) {
+                comments.push((offset, instr.cycles()));
+            }
+        }
+        for (offset, (min, max)) in comments {
+            let comment = if min == max {
+                format!("{} cycles", min)
+            } else {
+                format!("{}-{} cycles", min, max)
+            };
+            self.d.code.add_comment(FileOffset(offset), &comment);
+        }
+    }
+
+    /// Comments each label with the addresses of the jumps, branches and calls to it.
+    fn comment_xrefs(&mut self) {
+        let comments = self
+            .d
+            .code
+            .xrefs()
+            .iter()
+            .filter(|(offset, _)| self.d.code.label(**offset).is_some())
+            .map(|(offset, from)| {
+                let from = from.iter().map(|addr| format!("${:04x}", addr)).join(", ");
+                return (*offset, format!("xrefs: {}", from));
+            })
+            .collect::<Vec<_>>();
+        for (offset, comment) in comments {
+            self.d.code.add_comment(offset, &comment);
+        }
+    }
+
+    /// Comments counted loops with how many times they run and, for copy loops,
+    /// what they copy. The PRG ROM tables they read become rows of `.byte` or
+    /// `.word` data as long as the loop runs, when nothing else claimed the bytes.
+    /// With bank switching only tables in the loop's own bank are, since the
+    /// address may be in any bank.
+    fn annotate_loops(&mut self) -> Result<(), DisassembleError> {
+        let mapper = self.mapper();
+        let prg_rom_end = self.prg_rom_end();
+        let mut comments = Vec::new();
+        let mut tables = BTreeMap::new();
+        for (offsets, run) in self.instruction_runs() {
+            for found in find_counted_loops(&run) {
+                comments.push((offsets[found.head], found.describe()));
+                let bank = mapper.to_banked_addr(offsets[found.head]).bank;
+                for table in found.tables {
+                    if (table.addr as usize) < NES_PRG_ROM_START_ADDRESS {
+                        continue;
+                    }
+                    let Option::Some(start) = mapper.to_offset(CpuAddr(table.addr)) else {
+                        continue;
+                    };
+                    if self.header.mapper != 0 && mapper.to_banked_addr(start).bank != bank {
+                        continue;
+                    }
+                    let end = start + table.len;
+                    let longest = tables.entry(start).or_insert((end, table.words));
+                    *longest = (longest.0.max(end), longest.1 && table.words);
+                }
+            }
+        }
+        for (offset, comment) in comments {
+            self.d.code.add_comment(offset, &comment);
+        }
+
+        // byte tables running into another table's start are split there, so the
+        // other table can be labeled
+        let starts = tables.keys().copied().collect::<BTreeSet<FileOffset>>();
+        for (start, (end, words)) in tables {
+            if end.0 > prg_rom_end {
+                continue;
+            }
+            if words {
+                if self.d.code.first_claimed(start..end).is_none() {
+                    self.d
+                        .code
+                        .mark_words(start..end, "a table of words read in a loop")?;
+                }
+                continue;
+            }
+            let mut from = start;
+            for to in starts.range(start + 1..end).copied().chain([end]) {
+                if self.d.code.first_claimed(from..to).is_none() {
+                    self.d.code.mark_data(from..to, "a table read in a loop")?;
+                }
+                from = to;
+            }
+        }
+        return Result::Ok(());
+    }
+
+    /// Comments 16-bit adds, subtracts, increments and decrements done a byte at a
+    /// time with the pair of variables they work on.
+    fn annotate_wide_ops(&mut self) {
+        let code = &self.d.code;
+        let name = |addr: u16| {
+            let byte = |addr: u16| match code.operand_name(addr) {
+                Option::Some(name) => name.to_string(),
+                Option::None if addr < 0x100 => format!("${:02x}", addr),
+                Option::None => format!("${:04x}", addr),
+            };
+            return format!("{}/{}", byte(addr), byte(addr.wrapping_add(1)));
+        };
+        let mut comments = Vec::new();
+        for (offsets, run) in self.instruction_runs() {
+            for found in find_wide_ops(&run) {
+                comments.push((offsets[found.start], found.describe(name)));
+            }
+        }
+        for (offset, comment) in comments {
+            self.d.code.add_comment(offset, &comment);
+        }
+    }
+
+    /// Comments the end of short basic blocks with the expressions they compute
+    /// into what they store. Blocks start at labels as well, where code can be
+    /// reached from elsewhere.
+    fn annotate_expressions(&mut self) {
+        let code = &self.d.code;
+        let name = |addr: u16| match code.operand_name(addr) {
+            Option::Some(name) => name.to_string(),
+            Option::None if addr < 0x100 => format!("${:02x}", addr),
+            Option::None => format!("${:04x}", addr),
+        };
+        let mut comments = Vec::new();
+        for (offsets, run) in self.instruction_runs() {
+            let mut start = 0;
+            for end in 1..=run.len() {
+                if end < run.len() && code.label(offsets[end]).is_none() {
+                    continue;
+                }
+                for found in find_block_expressions(&run[start..end]) {
+                    comments.push((offsets[start + found.end], found.describe(name)));
+                }
+                start = end;
+            }
+        }
+        for (offset, comment) in comments {
+            self.d.code.add_comment(offset, &comment);
+        }
+    }
+
+    /// Writes the immediates of pointers into PRG ROM set up a byte at a time as
+    /// `#<label` and `#>label`, labeling what they point at, so the pointers follow
+    /// it when the code is moved.
+    fn label_pointer_setups(&mut self) {
+        let mapper = self.mapper();
+        let prg_rom_start = self.header.prg_rom_offset();
+        let prg_rom_end = self.prg_rom_end();
+        let mut found = Vec::new();
+        for (offsets, run) in self.instruction_runs() {
+            for setup in find_pointer_setups(&run) {
+                if (setup.target as usize) < NES_PRG_ROM_START_ADDRESS {
+                    continue;
+                }
+                let Option::Some(target) = mapper.to_offset(CpuAddr(setup.target)) else {
+                    continue;
+                };
+                // a label on the mirror the address is in assembles to another one
+                if (prg_rom_start..prg_rom_end).contains(&target.0)
+                    && mapper.to_addr(target).0 == setup.target
+                    && self.d.code.statement_start(target) == target
+                {
+                    found.push((offsets[setup.low], offsets[setup.high], target));
+                }
+            }
+        }
+        for (low, high, target) in found {
+            let label = match self.d.code.label(target) {
+                Option::Some(label) => label.to_string(),
+                Option::None => {
+                    let addr = mapper.to_banked_addr(target);
+                    let label = format!("prgrom{}_{:04x}", addr.bank, addr.addr);
+                    self.label_offset(target, &label);
+                    label
+                }
+            };
+            self.d.code.set_label_byte(low, false, &label);
+            self.d.code.set_label_byte(high, true, &label);
+        }
+    }
+
+    /// For carts with CHR RAM, finds the tiles the code copies from PRG ROM to the
+    /// pattern tables, labeling them and writing a tile a row.
+    fn find_chr_ram_tiles(&mut self) -> Result<(), DisassembleError> {
+        if !self.header.has_chr_ram() {
+            return Result::Ok(());
+        }
+        let mapper = self.mapper();
+        let prg_rom_start = self.header.prg_rom_offset();
+        let prg_rom_end = self.prg_rom_end();
+        let mut found = Vec::new();
+        for (offsets, run) in self.instruction_runs() {
+            for copy in find_chr_ram_copies(&run) {
+                if (copy.src as usize) < NES_PRG_ROM_START_ADDRESS {
+                    continue;
+                }
+                let Option::Some(start) = mapper.to_offset(CpuAddr(copy.src)) else {
+                    continue;
+                };
+                if (prg_rom_start..prg_rom_end).contains(&start.0) {
+                    let len = copy.len.min(prg_rom_end - start.0);
+                    found.push((offsets[copy.store], start, len));
+                }
+            }
+        }
+        for (store, start, len) in found {
+            if self.d.code.first_claimed(start..start + len).is_some() {
+                continue;
+            }
+            let format = self.tile_format;
+            self.group_tiles(start, len, &format)?;
+            for tile in tile_offsets(len, &format) {
+                self.d.code.claim(start + tile, "tiles copied to CHR RAM");
+            }
+            if self.d.code.label(start).is_none() {
+                let addr = mapper.to_banked_addr(start);
+                self.label_offset(start, &format!("chr_tiles_{:04x}", addr.addr));
+            }
+            let tiles = len / format.tile_len();
+            self.d
+                .code
+                .add_comment(store, &format!("copies {} tiles to CHR RAM", tiles));
+        }
+        return Result::Ok(());
+    }
+
+    /// Labels the PRG ROM data indexed loads, compares and arithmetic read from,
+    /// writing their operands with the label, so a table inside a run of data
+    /// keeps its start when the code is moved. The data, text and fill found
+    /// afterwards stop at the label. Bank switching ROMs are left alone since an
+    /// address may be in any bank.
+    fn label_indexed_tables(&mut self) {
+        if self.header.mapper != 0 {
+            return;
+        }
+        let mapper = self.mapper();
+        let prg_rom_start = self.header.prg_rom_offset();
+        let prg_rom_end = self.prg_rom_end();
+        let stmts = self.d.code.statements();
+        let mut tables = BTreeMap::new();
+        for stmt in stmts.iter().take(prg_rom_end).skip(prg_rom_start) {
+            let addr = match &stmt.asm_code {
+                AsmCode::Instruction(instr) => match instr.indexed_read_operand() {
+                    Option::Some(addr) if addr as usize >= NES_PRG_ROM_START_ADDRESS => addr,
+                    _ => continue,
+                },
+                _ => continue,
+            };
+            let Option::Some(target) = mapper.to_offset(CpuAddr(addr)) else {
+                continue;
+            };
+            if target.0 < prg_rom_end
+                && mapper.to_addr(target).0 == addr
+                && matches!(
+                    stmts[target.0].asm_code,
+                    AsmCode::DataHexU8(_) | AsmCode::DataSeq(_) | AsmCode::DataWords(_)
+                )
+            {
+                tables.insert(addr, target);
+            }
+        }
+        for (addr, target) in tables {
+            if self.d.code.variable(addr).is_some() {
+                continue;
+            }
+            let label = match self.d.code.label(target) {
+                Option::Some(label) => label.to_string(),
+                Option::None => {
+                    let label = format!("prgrom0_{:04x}", addr);
+                    self.label_offset(target, &label);
+                    label
+                }
+            };
+            self.d.code.set_variable(
+                addr,
+                Variable {
+                    name: label,
+                    value: VariableValue::Label(addr),
+                },
+            );
+        }
+    }
+
+    /// The runs of decoded PRG ROM instructions following each other in memory,
+    /// with the offset of each.
+    fn instruction_runs(&self) -> Vec<(Vec<FileOffset>, Vec<DecodedInstruction>)> {
+        let mapper = self.mapper();
+        let data = self.d.code.data();
+        let prg_rom_end = self.prg_rom_end();
+        let mut runs = Vec::new();
+        let mut run = Vec::new();
+        let mut offsets = Vec::new();
+        let mut offset = FileOffset(self.header.prg_rom_offset());
+        while offset.0 <= prg_rom_end {
+            let instr = if offset.0 < prg_rom_end && self.d.code.is_instruction(offset) {
+                decode(&data[offset.0..], mapper.to_addr(offset).0)
+            } else {
+                Option::None
+            };
+            match instr {
+                Option::Some(instr) => {
+                    offsets.push(offset);
+                    offset += instr_len(&instr);
+                    run.push(instr);
+                }
+                Option::None => {
+                    if !run.is_empty() {
+                        runs.push((mem::take(&mut offsets), mem::take(&mut run)));
+                    }
+                    offset += 1;
+                }
+            }
+        }
+        return runs;
+    }
+
+    /// Names ROM tables and RAM by how the code uses them, going by `rules`. Data
+    /// that already has a name keeps it.
+    fn name_data_by_access(&mut self, rules: &[NamingRule]) {
+        let mapper = self.mapper();
+        let mut names: BTreeMap<u16, String> = BTreeMap::new();
+        for rule in rules {
+            let found = match &rule.pattern {
+                AccessPattern::ReadInLoopStoringTo { modes, stores } => {
+                    self.read_in_loops_storing_to(modes, stores)
+                }
+                AccessPattern::WrittenOnlyDuringInit => self.written_only_during_init(),
+            };
+            for addr in found {
+                names
+                    .entry(addr)
+                    .or_insert_with(|| format!("{}{:04x}", rule.prefix, addr));
+            }
+        }
+
+        for (addr, name) in names {
+            if self.d.code.variable(addr).is_some() {
+                continue;
+            }
+            if addr as usize >= NES_PRG_ROM_START_ADDRESS {
+                let Option::Some(offset) = mapper.to_offset(CpuAddr(addr)) else {
+                    continue;
+                };
+                if self.d.code.is_instruction(offset)
+                    || self.d.code.label(offset).is_some()
+                    || mapper.to_addr(offset).0 != addr
+                {
+                    continue;
+                }
+                self.label_offset(offset, &name);
+            }
+            let value = if addr as usize >= NES_PRG_ROM_START_ADDRESS {
+                VariableValue::Label(addr)
+            } else if addr < 0x100 {
+                VariableValue::U8(addr as u8)
+            } else {
+                VariableValue::U16(addr)
+            };
+            self.d.code.set_variable(addr, Variable { name, value });
+        }
+    }
+
+    /// PRG ROM read with one of `modes` in loops that also store into `stores`.
+    fn read_in_loops_storing_to(
+        &self,
+        modes: &[AddressingMode],
+        stores: &[RangeInclusive<u16>],
+    ) -> BTreeSet<u16> {
+        let mut found = BTreeSet::new();
+        for (_, run) in self.instruction_runs() {
+            for (branch, instr) in run.iter().enumerate() {
+                if instr.mode != AddressingMode::Relative {
+                    continue;
+                }
+                let head = match instr
+                    .target()
+                    .and_then(|target| run[..branch].iter().position(|i| i.addr == target))
+                {
+                    Option::Some(head) => head,
+                    Option::None => continue,
+                };
+                let body = &run[head..branch];
+                let stores_there = body.iter().any(|i| {
+                    operand_access(i).is_some_and(|(addr, access)| {
+                        access.writes() && stores.iter().any(|r| r.contains(&addr))
+                    })
+                });
+                if !stores_there {
+                    continue;
+                }
+                found.extend(
+                    body.iter()
+                        .filter(|i| modes.contains(&i.mode))
+                        .filter_map(operand_access)
+                        .filter(|(addr, access)| {
+                            access.reads() && *addr as usize >= NES_PRG_ROM_START_ADDRESS
+                        })
+                        .map(|(addr, _)| addr),
+                );
+            }
+        }
+        return found;
+    }
+
+    /// RAM written, some of it indexed, only by [`NesDisassembler::init_code`] and
+    /// read by the rest.
+    fn written_only_during_init(&self) -> BTreeSet<u16> {
+        let mapper = self.mapper();
+        let data = self.d.code.data();
+        let init = self.init_code();
+        let mut written_indexed = BTreeSet::new();
+        let mut written_later = HashSet::new();
+        let mut read_later = HashSet::new();
+        for offset in self.reached_from.keys() {
+            let instr = match decode(&data[offset.0..], mapper.to_addr(*offset).0) {
+                Option::Some(instr) => instr,
+                Option::None => continue,
+            };
+            let (addr, access) = match ram_access(&instr) {
+                Option::Some(access) => access,
+                Option::None => continue,
+            };
+            let indexed = matches!(
+                instr.mode,
+                AddressingMode::AbsoluteX | AddressingMode::AbsoluteY | AddressingMode::ZeroPageX
+            );
+            match (init.contains(offset), access.writes()) {
+                (true, true) if indexed => {
+                    written_indexed.insert(addr);
+                }
+                (false, true) => {
+                    written_later.insert(addr);
+                }
+                _ => {}
+            }
+            if access.reads() && !init.contains(offset) {
+                read_later.insert(addr);
+            }
+        }
+        return written_indexed
+            .into_iter()
+            .filter(|addr| !written_later.contains(addr) && read_later.contains(addr))
+            .collect();
+    }
+
+    /// Offsets of the instructions only run while starting up: each RESET handler
+    /// up to the `jmp` back that starts its main loop and the subroutines only
+    /// called from there.
+    fn init_code(&self) -> HashSet<FileOffset> {
+        let mapper = self.mapper();
+        let data = self.d.code.data();
+        let mut init = HashSet::new();
+        let mut later = HashSet::new();
+        for (name, start) in &self.entry_points {
+            if is_interrupt(name) {
+                later.extend(self.reachable(*start, true));
+                continue;
+            }
+            let own = self.reachable(*start, false);
+            let decoded =
+                |offset: &FileOffset| decode(&data[offset.0..], mapper.to_addr(*offset).0);
+            // the earliest target of a jmp back, where the main loop starts
+            let main_loop = own
+                .iter()
+                .filter_map(|offset| {
+                    let instr = decoded(offset).filter(|i| i.mnemonic == "jmp")?;
+                    let target = mapper.to_offset(CpuAddr(instr.target()?))?;
+                    return Option::Some(target).filter(|t| t <= offset && own.contains(t));
+                })
+                .min();
+            if let Option::Some(main_loop) = main_loop {
+                later.extend(self.reachable(main_loop, true));
+            }
+            for offset in own.iter().filter(|o| main_loop.is_none_or(|m| **o < m)) {
+                init.insert(*offset);
+                let call = decoded(offset)
+                    .filter(|i| i.mnemonic == "jsr")
+                    .and_then(|i| i.target())
+                    .filter(|target| *target as usize >= NES_PRG_ROM_START_ADDRESS)
+                    .and_then(|target| mapper.to_offset(CpuAddr(target)));
+                if let Option::Some(target) = call {
+                    init.extend(self.reachable(target, true));
+                }
+            }
+        }
+        return init.difference(&later).copied().collect();
+    }
+
+    /// Finds RAM written by code an interrupt handler reaches and read by code
+    /// RESET reaches and names it, `frame_ready_flag` when the main code waits for
+    /// it in a loop. Addresses that already have a name keep it.
+    fn find_shared_variables(&mut self) {
+        let mapper = self.mapper();
+        let data = self.d.code.data();
+        let mut written: HashMap<u16, BTreeSet<String>> = HashMap::new();
+        let mut read: HashMap<u16, BTreeSet<String>> = HashMap::new();
+        let mut polled = HashSet::new();
+        for (offset, reached) in &self.reached_from {
+            let instr = match decode(&data[offset.0..], mapper.to_addr(*offset).0) {
+                Option::Some(instr) => instr,
+                Option::None => continue,
+            };
+            let (addr, access) = match ram_access(&instr) {
+                Option::Some(access) => access,
+                Option::None => continue,
+            };
+            if access.writes() {
+                written
+                    .entry(addr)
+                    .or_default()
+                    .extend(reached.iter().cloned());
+            }
+            if access.reads() {
+                read.entry(addr)
+                    .or_default()
+                    .extend(reached.iter().cloned());
+                let main = reached.iter().any(|e| !is_interrupt(e));
+                if main && self.is_polled(data, *offset + instr_len(&instr), instr.addr) {
+                    polled.insert(addr);
+                }
+            }
+        }
+
+        let mut shared = Vec::new();
+        for (addr, writers) in written.into_iter().sorted_by_key(|(addr, _)| *addr) {
+            let readers = match read.remove(&addr) {
+                Option::Some(readers) => readers,
+                Option::None => continue,
+            };
+            if !writers.iter().any(|e| is_interrupt(e)) || readers.iter().all(|e| is_interrupt(e)) {
+                continue;
+            }
+            let kind = if writers.iter().any(|e| e.ends_with("_nmi")) {
+                "nmi"
+            } else {
+                "irq"
+            };
+            shared.push(SharedVariable {
+                addr,
+                name: format!("{}_shared_{:04x}", kind, addr),
+                written_from: writers.into_iter().collect(),
+                read_from: readers.into_iter().collect(),
+                polled: polled.contains(&addr),
+            });
+        }
+
+        let polled_count = shared.iter().filter(|v| v.polled).count();
+        for v in &mut shared {
+            if v.polled {
+                v.name = if polled_count == 1 {
+                    "frame_ready_flag".to_string()
+                } else {
+                    format!("frame_ready_flag_{:04x}", v.addr)
+                };
+            }
+            match self.d.code.variable(v.addr) {
+                Option::Some(existing) => v.name = existing.name.clone(),
+                Option::None => self.d.code.set_variable(
+                    v.addr,
+                    Variable {
+                        name: v.name.clone(),
+                        value: if v.addr < 0x100 {
+                            VariableValue::U8(v.addr as u8)
+                        } else {
+                            VariableValue::U16(v.addr)
+                        },
+                    },
+                ),
+            }
+        }
+        self.shared_variables = shared;
+    }
+
+    /// Whether one of the two instructions at `offset` branches back to `read_addr`
+    /// or before, waiting for what was read there to change.
+    fn is_polled(&self, data: &[u8], offset: FileOffset, read_addr: u16) -> bool {
+        let mapper = self.mapper();
+        let mut offset = offset;
+        for _ in 0..2 {
+            if !self.d.code.is_instruction(offset) {
+                return false;
+            }
+            let instr = match decode(&data[offset.0..], mapper.to_addr(offset).0) {
+                Option::Some(instr) => instr,
+                Option::None => return false,
+            };
+            if instr.mode == AddressingMode::Relative {
+                return instr.target().is_some_and(|target| target <= read_addr);
+            }
+            offset += instr_len(&instr);
+        }
+        return false;
+    }
+
+    /// Gives routines that are identical at the same address in several banks one
+    /// name for the address and a label per bank. Only done for mappers that switch
+    /// banks, on NROM the banks are never at the same address.
+    fn label_duplicated_routines(&mut self) {
+        if self.header.mapper == 0 {
+            return;
+        }
+        let banks = (0..self.header.prg_rom_page_count())
+            .map(|i| self.header.prg_rom_offset() + i * NES_PRG_ROM_PAGE_LENGTH)
+            .filter(|start| start + NES_PRG_ROM_PAGE_LENGTH <= self.data_len)
+            .collect::<Vec<usize>>();
+        if banks.len() < 2 {
+            return;
+        }
+
+        let mapper = self.mapper();
+        let data = self.d.code.data();
+        let mut candidates = Vec::new();
+        for start in &banks {
+            let vectors = start + NES_PRG_ROM_PAGE_LENGTH - 6;
+            for i in 0..3 {
+                let addr = u16::from_le_bytes([data[vectors + i * 2], data[vectors + i * 2 + 1]]);
+                if addr as usize >= NES_PRG_ROM_START_ADDRESS {
+                    candidates.push(CpuAddr(addr));
+                }
+            }
+        }
+        for (offset, stmt) in self.d.code.statements().iter().enumerate() {
+            let in_prg_rom = banks
+                .iter()
+                .any(|b| (*b..*b + NES_PRG_ROM_PAGE_LENGTH).contains(&offset));
+            if stmt.label.is_some() && in_prg_rom {
+                candidates.push(mapper.to_addr(FileOffset(offset)));
+            }
+        }
+
+        let duplicated =
+            find_duplicated_routines(data, &banks, NES_PRG_ROM_PAGE_LENGTH, &candidates);
+        for routine in &duplicated {
+            self.d.code.set_variable(
+                routine.addr.0,
+                Variable {
+                    name: routine.name.clone(),
+                    value: VariableValue::U16(routine.addr.0),
+                },
+            );
+            let comment = format!(
+                "{}, identical in banks {}",
+                routine.name,
+                routine.banks.iter().join(", ")
+            );
+            for bank in &routine.banks {
+                let offset = FileOffset(
+                    banks[*bank as usize] + routine.addr.0 as usize % NES_PRG_ROM_PAGE_LENGTH,
+                );
+                let alias = routine.alias(*bank);
+                match self.d.code.label(offset).map(|l| l.to_string()) {
+                    Option::Some(existing) => self.d.code.rename_label(&existing, &alias),
+                    Option::None => self.d.code.set_label(offset, &alias),
+                }
+                self.d.code.set_comment(offset, &comment);
+            }
+        }
+        self.duplicated_routines = duplicated;
+    }
+
+    /// Names the routines of known runtimes, such as the cc65 one, and the zero page
+    /// they use. Routines no entry point reached are disassembled from the match.
+    fn label_library_routines(
+        &mut self,
+        signatures: &[(String, String)],
+    ) -> Result<(), DisassembleError> {
+        let mapper = self.mapper();
+        let prg_rom_end = self.prg_rom_end();
+        let extra = signatures
+            .iter()
+            .map(|(name, pattern)| Signature { name, pattern })
+            .collect::<Vec<Signature>>();
+        let mut libraries = LIBRARIES.to_vec();
+        libraries.push(Library {
+            name: SIGNATURES_FILE,
+            prefix: "",
+            min_matches: 1,
+            signatures: &extra,
+        });
+        let found = find_signatures(
+            self.d.code.data(),
+            self.header.prg_rom_offset(),
+            prg_rom_end,
+            &libraries,
+        );
+        let counts = found.iter().counts_by(|m| m.name.clone());
+        let bank_counts = found.iter().counts_by(|m| {
+            (
+                m.name.clone(),
+                mapper.to_banked_addr(FileOffset(m.offset)).bank,
+            )
+        });
+
+        for m in &found {
+            let offset = FileOffset(m.offset);
+            let addr = mapper.to_banked_addr(offset);
+            let decoded = self.d.code.is_instruction(offset);
+            let undecoded = matches!(
+                self.d.code.statements()[m.offset].asm_code,
+                AsmCode::DataHexU8(_)
+            );
+            if !decoded && !undecoded {
+                // the match starts inside other code or data
+                continue;
+            }
+            if !decoded && mapper.to_offset(addr.addr) == Option::Some(offset) {
+                let label_prefix = format!("prgrom{}", addr.bank);
+                self.d.disassemble(
+                    addr.addr,
+                    format!("{:04x}", addr.addr).as_str(),
+                    &label_prefix,
+                    mapper.as_ref(),
+                )?;
+            }
+            // stubs like waiting for vblank can be written more than once in a bank
+            let name = if bank_counts[&(m.name.clone(), addr.bank)] > 1 {
+                format!("{}_bank{}_{:04x}", m.name, addr.bank, addr.addr)
+            } else if counts[&m.name] > 1 {
+                format!("{}_bank{}", m.name, addr.bank)
+            } else {
+                m.name.clone()
+            };
+            self.label_offset(offset, &name);
+            for (name, zp_addr) in &m.zero_page {
+                self.d.code.set_variable(
+                    *zp_addr as u16,
+                    Variable {
+                        name: name.clone(),
+                        value: VariableValue::U8(*zp_addr),
+                    },
+                );
+            }
+        }
+        self.library_routines = found;
+        return Result::Ok(());
+    }
+
+    /// Labels the loops checksumming the PRG ROM, commented with what they check
+    /// since patching those bytes makes the check fail.
+    fn label_checksum_routines(&mut self) -> Result<(), DisassembleError> {
+        let mapper = self.mapper();
+        let found = find_checksum_routines(
+            self.d.code.data(),
+            self.header.prg_rom_offset(),
+            self.prg_rom_end(),
+        );
+        for routine in &found {
+            let offset = FileOffset(routine.offset);
+            let addr = mapper.to_banked_addr(offset);
+            if matches!(
+                self.d.code.statements()[routine.offset].asm_code,
+                AsmCode::DataHexU8(_)
+            ) && mapper.to_offset(addr.addr) == Option::Some(offset)
+            {
+                let label_prefix = format!("prgrom{}", addr.bank);
+                self.d.disassemble(
+                    addr.addr,
+                    format!("{:04x}", addr.addr).as_str(),
+                    &label_prefix,
+                    mapper.as_ref(),
+                )?;
+            }
+            if !self.d.code.is_instruction(offset) {
+                // the match starts inside other code or data
+                continue;
+            }
+            let name = if found.len() > 1 {
+                format!("{}_{:04x}", routine.name, addr.addr.0)
+            } else {
+                routine.name.clone()
+            };
+            self.label_offset(offset, &name);
+            self.d.code.add_comment(
+                offset,
+                &format!(
+                    "checksum of ${:04x}-$ffff, patching there makes the check fail",
+                    routine.start
+                ),
+            );
+            for (addr, name) in [
+                (routine.ptr, "checksum_ptr"),
+                (routine.ptr.wrapping_add(1), "checksum_ptr_hi"),
+                (routine.sum, "checksum_sum"),
+            ] {
+                if self.d.code.variable(addr as u16).is_none() {
+                    self.d.code.set_variable(
+                        addr as u16,
+                        Variable {
+                            name: name.to_string(),
+                            value: VariableValue::U8(addr),
+                        },
+                    );
+                }
+            }
+        }
+        return Result::Ok(());
+    }
+
+    /// For ROMs built with cc65, follows what the code does to the C argument stack.
+    /// Pushes and pops are commented with the argument type, functions get a
+    /// prototype from the arguments their callers push or else the number of bytes
+    /// they drop.
+    fn annotate_cc65_stack(&mut self) {
+        let routines = self
+            .library_routines
+            .iter()
+            .filter(|m| m.library == CC65_RUNTIME.name)
+            .filter_map(|m| {
+                let label = self.d.code.label(FileOffset(m.offset))?;
+                return Option::Some((label.to_string(), m.name.as_str()));
+            })
+            .collect::<HashMap<String, &str>>();
+        if routines.is_empty() {
+            return;
+        }
+
+        let prg_rom_end = self.prg_rom_end();
+        let mut comments = Vec::new();
+        let mut labels = HashMap::new();
+        let mut calls: HashMap<String, Vec<Vec<ArgType>>> = HashMap::new();
+        let mut dropped: HashMap<String, usize> = HashMap::new();
+        let mut pushed = Vec::new();
+        let mut function = Option::None;
+        for offset in self.header.prg_rom_offset()..prg_rom_end {
+            let stmt = &self.d.code.statements()[offset];
+            let instr = match &stmt.asm_code {
+                AsmCode::Instruction(instr) => instr,
+                _ => continue,
+            };
+            if let Option::Some(label) = &stmt.label {
+                labels.insert(label.to_string(), offset);
+                function = Option::Some(label.to_string());
+                pushed.clear();
+            }
+            let target = match instr {
+                Instruction::JSR_ABS(_, target) | Instruction::JMP_ABS(_, target) => target,
+                Instruction::RTS | Instruction::RTI | Instruction::JMP_IND(_) => {
+                    pushed.clear();
+                    continue;
+                }
+                _ => continue,
+            };
+            let drop = match routines.get(target).and_then(|name| stack_op(name)) {
+                Option::Some(StackOp::Push(arg)) => {
+                    comments.push((offset, format!("push {} argument", arg)));
+                    pushed.push(arg);
+                    0
+                }
+                Option::Some(StackOp::Pop(arg)) => {
+                    comments.push((offset, format!("pop {} argument", arg)));
+                    arg.size()
+                }
+                Option::Some(StackOp::Drop(len)) => {
+                    comments.push((offset, format!("drop {} bytes of arguments", len)));
+                    len
+                }
+                Option::None => {
+                    if !routines.contains_key(target) && !pushed.is_empty() {
+                        calls
+                            .entry(target.clone())
+                            .or_default()
+                            .push(std::mem::take(&mut pushed));
+                    }
+                    0
+                }
+            };
+            if let Option::Some(function) = &function {
+                *dropped.entry(function.clone()).or_default() += drop;
+            }
+            if let Instruction::JMP_ABS(_, _) = instr {
+                pushed.clear();
+            }
+        }
+
+        for (name, offset) in labels {
+            let comment = match (calls.get(&name), dropped.get(&name)) {
+                (Option::Some(args), _) if args.iter().all_equal() => Prototype {
+                    name,
+                    args: args[0].clone(),
+                }
+                .to_string(),
+                (Option::Some(_), _) => {
+                    "called with different arguments on the cc65 stack".to_string()
+                }
+                (Option::None, Option::Some(len)) if *len > 0 => {
+                    format!("drops {} bytes of arguments from the cc65 stack", len)
+                }
+                _ => continue,
+            };
+            if self.d.code.statements()[offset].comment.is_none() {
+                // ahead of the comment of the function's first instruction
+                comments.insert(0, (offset, comment));
+            }
+        }
+        for (offset, comments) in comments.into_iter().into_group_map() {
+            self.d
+                .code
+                .set_comment(FileOffset(offset), &comments.join("\n"));
+        }
+    }
+
+    /// Moves small data tables read by a single routine to right after it in the
+    /// output, referring to them by label. A table is only moved when nothing refers
+    /// by address to it or to the code and data it moves past, so the output still
+    /// assembles to working code. Addresses computed at run time can't be checked.
+    /// Bank switching ROMs are left alone since an address may be in any bank.
+    fn inline_small_tables(&mut self) -> Result<(), DisassembleError> {
+        if self.header.mapper != 0 {
+            return Result::Ok(());
+        }
+        let mapper = self.mapper();
+        let prg_rom_end = self.prg_rom_end();
+        let stmts = self.d.code.statements();
+
+        // file offsets referred to by address and the instructions/vectors doing so
+        let mut refs: HashMap<usize, Vec<usize>> = HashMap::new();
+        let mut tables = Vec::new();
+        for (offset, stmt) in stmts
+            .iter()
+            .enumerate()
+            .take(prg_rom_end)
+            .skip(self.header.prg_rom_offset())
+        {
+            let (addr, table) = match &stmt.asm_code {
+                AsmCode::Instruction(instr) => match instr.abs_operand() {
+                    Option::Some(addr) => (addr, instr.indexed_read_operand().is_some()),
+                    Option::None => continue,
+                },
+                AsmCode::DataHexU16(addr) => (*addr, false),
+                _ => continue,
+            };
+            if (addr as usize) < NES_PRG_ROM_START_ADDRESS {
+                continue;
+            }
+            let Option::Some(FileOffset(target)) = mapper.to_offset(CpuAddr(addr)) else {
+                continue;
+            };
+            if target >= prg_rom_end {
+                continue;
+            }
+            refs.entry(target).or_default().push(offset);
+            if table {
+                tables.push(target);
+            }
+        }
+
+        let mut moves = Vec::new();
+        for table in tables.into_iter().sorted().dedup() {
+            let len = match self.table_len(table, prg_rom_end, &refs) {
+                Option::Some(len) if len <= MAX_INLINE_TABLE_LENGTH => len,
+                _ => continue,
+            };
+            // the instruction ending the routine of every reference
+            let ends = refs[&table]
+                .iter()
+                .map(|r| self.routine_end(*r, prg_rom_end))
+                .collect::<Option<Vec<usize>>>();
+            let end = match ends {
+                Option::Some(ends) if ends.iter().all_equal() => ends[0],
+                _ => continue,
+            };
+            let next = (end + 1..prg_rom_end)
+                .find(|o| !matches!(stmts[*o].asm_code, AsmCode::Used))
+                .unwrap_or(prg_rom_end);
+            let moved_past = if next < table {
+                next..table
+            } else if next > table + len {
+                table + len..next
+            } else {
+                continue;
+            };
+            let referred_to = refs.keys().any(|o| {
+                *o != table && (moved_past.contains(o) || (table..table + len).contains(o))
+            });
+            let new_segment = moved_past
+                .clone()
+                .chain(table..table + len)
+                .any(|o| stmts[o].segment.is_some());
+            if referred_to || new_segment || moves.iter().any(|(_, _, e)| *e == end) {
+                continue;
+            }
+            moves.push((table, len, end));
+        }
+
+        for (table, len, end) in moves {
+            let offset = FileOffset(table);
+            let addr = mapper.to_banked_addr(offset);
+            self.d
+                .code
+                .mark_data(offset..offset + len, "a moved table")?;
+            let label = match self.d.code.label(offset) {
+                Option::Some(label) => label.to_string(),
+                Option::None => {
+                    let label = format!("prgrom{}_{:04x}", addr.bank, addr.addr);
+                    self.label_offset(offset, &label);
+                    label
+                }
+            };
+            let operands = refs[&table]
+                .iter()
+                .filter_map(|r| match &self.d.code.statements()[*r].asm_code {
+                    AsmCode::Instruction(instr) => instr.abs_operand(),
+                    _ => Option::None,
+                })
+                .collect::<Vec<u16>>();
+            for operand in operands {
+                self.d.code.set_variable(
+                    operand,
+                    Variable {
+                        name: label.clone(),
+                        value: VariableValue::Label(operand),
+                    },
+                );
+            }
+            self.d
+                .code
+                .set_comment(offset, format!("moved from ${:04x}", addr.addr).as_str());
+            self.d
+                .code
+                .move_after(offset..offset + len, FileOffset(end));
+        }
+        return Result::Ok(());
+    }
+
+    /// Length of the data table at `offset`, up to the next label, address referred
+    /// to, fill or code, `None` if it isn't data.
+    fn table_len(
+        &self,
+        offset: usize,
+        prg_rom_end: usize,
+        refs: &HashMap<usize, Vec<usize>>,
+    ) -> Option<usize> {
+        let stmts = self.d.code.statements();
+        let data = self.d.code.data();
+        let mut end = offset;
+        while end < prg_rom_end {
+            let stmt = &stmts[end];
+            if end > offset && (stmt.label.is_some() || refs.contains_key(&end)) {
+                break;
+            }
+            let fill = data[end..prg_rom_end]
+                .iter()
+                .take_while(|b| **b == data[end])
+                .count();
+            if fill >= FILL_RUN_LENGTH {
+                break;
+            }
+            match &stmt.asm_code {
+                AsmCode::DataHexU8(_) => end += 1,
+                AsmCode::DataSeq(bytes)
+                    if bytes.iter().all(|b| matches!(b, AsmCode::DataHexU8(_))) =>
+                {
+                    end += bytes.len()
+                }
+                _ => break,
+            }
+        }
+        return if end > offset {
+            Option::Some(end - offset)
+        } else {
+            Option::None
+        };
+    }
+
+    /// Writes the subroutines `jsr`s call as `.proc`s, each running from its first
+    /// instruction to the `rts`, `rti` or `jmp` it ends with, past the ones its
+    /// branches skip. Routines whose labels inside are used from outside, that
+    /// run into data or another segment or that start inside another routine stay
+    /// bare labels.
+    fn find_procs(&mut self) {
+        let mapper = self.mapper();
+        let prg_rom_end = self.prg_rom_end();
+        let stmts = self.d.code.statements();
+        let labeled = stmts
+            .iter()
+            .enumerate()
+            .filter_map(|(offset, stmt)| stmt.label.as_deref().map(|label| (label, offset)))
+            .collect::<HashMap<&str, usize>>();
+        // offsets of the instructions jumping or branching to each offset, by label
+        // or, for targets only labeled once the jumps are resolved, by address
+        let mut refs: HashMap<usize, Vec<usize>> = HashMap::new();
+        for (offset, stmt) in stmts.iter().enumerate() {
+            if let AsmCode::Instruction(instr) = &stmt.asm_code {
+                let Option::Some(label) = instr.label() else {
+                    continue;
+                };
+                let target = labeled.get(label).copied().or_else(|| {
+                    instr
+                        .target(mapper.to_addr(FileOffset(offset)).0)
+                        .and_then(|target| mapper.to_offset(CpuAddr(target)))
+                        .map(|target| target.0)
+                });
+                if let Option::Some(target) = target {
+                    refs.entry(target).or_default().push(offset);
+                }
+            }
+        }
+        let starts = self
+            .d
+            .calls
+            .values()
+            .filter(|addr| addr.0 as usize >= NES_PRG_ROM_START_ADDRESS)
+            .filter_map(|addr| mapper.to_offset(*addr).map(|offset| offset.0))
+            .filter(|offset| *offset < prg_rom_end && stmts[*offset].label.is_some())
+            .collect::<BTreeSet<usize>>();
+
+        let mut procs = Vec::new();
+        let mut last_end = Option::None;
+        for start in starts {
+            if last_end.is_some_and(|end| start <= end) {
+                continue;
+            }
+            let bank_end = prg_rom_end.min(prg_rom_page_end(self.header.prg_rom_offset(), start));
+            let mut end = match self.routine_end(start, bank_end) {
+                Option::Some(end) => end,
+                Option::None => continue,
+            };
+            // branches past the end make the routine go on after it
+            let mut offset = start;
+            let mut ends_in_data = false;
+            while offset <= end {
+                let branch = match &stmts[offset].asm_code {
+                    AsmCode::Instruction(_) => decode(&self.d.code.data()[offset..], 0)
+                        .filter(|d| d.mode == AddressingMode::Relative),
+                    _ => Option::None,
+                };
+                // relative to the branch, which is decoded at address 0
+                let target = branch
+                    .and_then(|d| d.target())
+                    .map(|t| offset.wrapping_add(t as i16 as usize));
+                if let Option::Some(target) = target.filter(|t| *t > end && *t < bank_end) {
+                    match self.routine_end(target, bank_end) {
+                        Option::Some(new_end) => end = new_end,
+                        Option::None => {
+                            ends_in_data = true;
+                            break;
+                        }
+                    }
+                }
+                offset += 1;
+            }
+            let inside = |o: &usize| (start..=end).contains(o);
+            let used_from_outside = (start + 1..=end).any(|o| {
+                stmts[o].segment.is_some()
+                    || refs.get(&o).is_some_and(|refs| !refs.iter().all(inside))
+            });
+            if ends_in_data || used_from_outside {
+                continue;
+            }
+            procs.push((start, end));
+            last_end = Option::Some(end);
+        }
+        for (start, end) in procs {
+            self.d.code.set_proc(FileOffset(start), FileOffset(end));
+        }
+    }
+
+    /// Writes the bytes of the entry points and the subroutines `jsr`s call, up to
+    /// the `rts`, `rti` or `jmp` each runs into, as `.verify` so reassembling it
+    /// checks nothing drifted.
+    fn add_verify(&mut self) {
+        let mapper = self.mapper();
+        let prg_rom_end = self.prg_rom_end();
+        let calls = self
+            .d
+            .calls
+            .values()
+            .filter(|addr| addr.0 as usize >= NES_PRG_ROM_START_ADDRESS)
+            .filter_map(|addr| mapper.to_offset(*addr).map(|offset| offset.0));
+        let starts = self
+            .entry_points
+            .iter()
+            .map(|(_, offset)| offset.0)
+            .chain(calls)
+            .filter(|offset| {
+                (self.header.prg_rom_offset()..prg_rom_end).contains(offset)
+                    && self.d.code.statements()[*offset].label.is_some()
+            })
+            .collect::<BTreeSet<usize>>();
+        for start in starts {
+            let bank_end = prg_rom_end.min(prg_rom_page_end(self.header.prg_rom_offset(), start));
+            if let Option::Some(end) = self.routine_end(start, bank_end) {
+                self.d.code.set_verify(FileOffset(start), FileOffset(end));
+            }
+        }
+    }
+
+    /// Offset of the `rts`, `rti` or `jmp` the code at `offset` runs into.
+    fn routine_end(&self, offset: usize, prg_rom_end: usize) -> Option<usize> {
+        let stmts = self.d.code.statements();
+        for (o, stmt) in stmts.iter().enumerate().take(prg_rom_end).skip(offset) {
+            match &stmt.asm_code {
+                AsmCode::Instruction(Instruction::RTS)
+                | AsmCode::Instruction(Instruction::RTI)
+                | AsmCode::Instruction(Instruction::JMP_ABS(_, _))
+                | AsmCode::Instruction(Instruction::JMP_IND(_)) => return Option::Some(o),
+                AsmCode::Instruction(_) | AsmCode::Used => {}
+                _ => return Option::None,
+            }
+        }
+        return Option::None;
+    }
+
+    /// Offset and length of the runs of PRG ROM bytes nothing decoded, fill left
+    /// out.
+    fn unreached_runs(&self) -> Vec<(usize, usize)> {
+        let prg_rom_end = self.prg_rom_end();
+        let mut runs = Vec::new();
+        let mut offset = self.header.prg_rom_offset();
+        while offset < prg_rom_end {
+            let bank_end = prg_rom_end.min(prg_rom_page_end(self.header.prg_rom_offset(), offset));
+            let len = self.d.code.statements()[offset..bank_end]
+                .iter()
+                .take_while(|stmt| matches!(stmt.asm_code, AsmCode::DataHexU8(_)))
+                .count();
+            if len == 0 {
+                offset += 1;
+                continue;
+            }
+            runs.extend(split_fill(self.d.code.data(), offset, len));
+            offset += len;
+        }
+        return runs;
+    }
+
+    /// Disassembles the unreached regions scoring at least `threshold` as code,
+    /// for code only reached through jump tables and pointers. Each region found
+    /// becomes an entry point of its own, and regions are scored again after each
+    /// pass since code decoded in one can shorten another.
+    fn sweep(
+        &mut self,
+        threshold: f64,
+        progress: &mut dyn Progress,
+    ) -> Result<(), DisassembleError> {
+        let mapper = self.mapper();
+        let mut tried = HashSet::new();
+        loop {
+            let mut found = false;
+            for (offset, len) in self.unreached_runs() {
+                let start = FileOffset(offset);
+                let addr = mapper.to_banked_addr(start);
+                // only what the mapper can reach at that address
+                let mapped = addr.addr.0 as usize >= NES_PRG_ROM_START_ADDRESS
+                    && mapper.to_offset(addr.addr) == Option::Some(start);
+                if !tried.insert(offset) || !mapped {
+                    continue;
+                }
+                let score = score_region(&self.d.code.data()[offset..offset + len], addr.addr.0);
+                if score.score < threshold {
+                    continue;
+                }
+                let label_prefix = format!("prgrom{}", addr.bank);
+                let name = format!("{:04x}", addr.addr.0);
+                progress.entry_point_started(&format!("{}_{}", label_prefix, name), addr.addr);
+                self.d
+                    .disassemble(addr.addr, &name, &label_prefix, mapper.as_ref())?;
+                if !self.d.code.is_instruction(start) {
+                    continue;
+                }
+                self.d.code.add_comment(
+                    start,
+                    format!("found by --sweep, code score {:.2}", score.score).as_str(),
+                );
+                self.entry_points
+                    .push((format!("{}_{}", label_prefix, name), start));
+                found = true;
+            }
+            if !found {
+                return Result::Ok(());
+            }
+        }
+    }
+
+    /// Follows each of the `entries` words of the table at `addr`, declared with
+    /// `--vector-table` since the code using it, like a dispatch on the game state,
+    /// doesn't say how long it is.
+    fn disassemble_vector_table(
+        &mut self,
+        addr: CpuAddr,
+        entries: usize,
+        progress: &mut dyn Progress,
+    ) -> Result<(), DisassembleError> {
+        let mapper = self.mapper();
+        let start = self.prg_rom_offset(addr)?;
+        let end = start + entries * 2;
+        if end.0 > prg_rom_page_end(self.header.prg_rom_offset(), start.0) {
+            return Result::Err(DisassembleError::ParseError(format!(
+                "vector table at ${:04x} runs past the end of its bank",
+                addr
+            )));
+        }
+        if let Option::Some(claimed) = self.d.code.first_claimed(start..end) {
+            return Result::Err(DisassembleError::Overlap(format!(
+                "vector table at ${:04x} overlaps {}",
+                addr,
+                self.d.code.claimant(claimed)
+            )));
+        }
+        let data = self.d.code.data();
+        let targets = data[start.0..end.0]
+            .chunks_exact(2)
+            .map(|word| u16::from_le_bytes([word[0], word[1]]))
+            .collect::<Vec<u16>>();
+        let by = format!("the vector table at ${:04x}", addr);
+        self.d.code.mark_words(start..end, &by)?;
+        let bank = mapper.to_banked_addr(start).bank;
+        self.d
+            .code
+            .set_label(start, &format!("prgrom{}_vectors_{:04x}", bank, addr));
+
+        for (i, target) in targets.into_iter().enumerate() {
+            let offset = match self.prg_rom_offset(CpuAddr(target)) {
+                Result::Ok(offset) => offset,
+                Result::Err(_) => {
+                    self.d.diagnostics.push(Diagnostic::warning(
+                        start + i * 2,
+                        Option::Some(mapper.to_banked_addr(start + i * 2)),
+                        format!(
+                            "entry {} of the vector table at ${:04x} points at ${:04x}, \
+                             outside PRG ROM",
+                            i, addr, target
+                        ),
+                    ));
+                    continue;
+                }
+            };
+            let label_prefix = format!("prgrom{}", mapper.to_banked_addr(offset).bank);
+            let name = format!("vector_{:04x}_{}", addr, i);
+            let entry = format!("{}_{}", label_prefix, name);
+            progress.entry_point_started(&entry, CpuAddr(target));
+            self.d
+                .disassemble(CpuAddr(target), &name, &label_prefix, mapper.as_ref())?;
+            self.entry_points.push((entry, offset));
+        }
+        return Result::Ok(());
+    }
+
+    /// Warns about unreached runs of words all pointing at code, likely tables of
+    /// handlers only reached by an indirect jump, proposing them for
+    /// `--vector-table`.
+    fn propose_vector_tables(&mut self) {
+        let mapper = self.mapper();
+        let mut proposals = Vec::new();
+        for (offset, len) in self.unreached_runs() {
+            // a table can start at either byte of a word
+            let mut start = 0;
+            while start + VECTOR_TABLE_MIN_ENTRIES * 2 <= len {
+                let targets = self.vector_table_at(offset + start, offset + len);
+                // the same word over and over is more likely fill than a table
+                if targets.len() >= VECTOR_TABLE_MIN_ENTRIES && targets.iter().all_equal() {
+                    start += targets.len() * 2;
+                } else if targets.len() >= VECTOR_TABLE_MIN_ENTRIES {
+                    proposals.push((FileOffset(offset + start), targets.len()));
+                    start += targets.len() * 2;
+                } else {
+                    start += 1;
+                }
+            }
+        }
+        for (offset, entries) in proposals {
+            let addr = mapper.to_banked_addr(offset);
+            self.d.diagnostics.push(Diagnostic::warning(
+                offset,
+                Option::Some(addr),
+                format!(
+                    "{} unreached words all point at code, likely a vector table, \
+                     follow them with --vector-table '${:04x}:{}'",
+                    entries, addr.addr.0, entries
+                ),
+            ));
+        }
+    }
+
+    /// The targets of the words from `table` up to `end` that all point at code, the
+    /// table ending early where the first routine it points at comes right after it.
+    fn vector_table_at(&self, table: usize, end: usize) -> Vec<u16> {
+        let data = self.d.code.data();
+        let mut targets = Vec::new();
+        let mut table_end = table;
+        let mut follows_table = Option::None;
+        while table_end + 2 <= end && Option::Some(table_end) != follows_table {
+            let target = u16::from_le_bytes([data[table_end], data[table_end + 1]]);
+            let (target_offset, routine_start) = match self.code_at(target) {
+                Option::Some(code) => code,
+                Option::None => break,
+            };
+            if !routine_start {
+                // only the routine right after the table starts after other data
+                let after_table = target_offset.0 > table_end
+                    && (target_offset.0 - table).is_multiple_of(2)
+                    && follows_table.is_none_or(|o| o == target_offset.0);
+                if !after_table {
+                    break;
+                }
+                follows_table = Option::Some(target_offset.0);
+            }
+            targets.push(target);
+            table_end += 2;
+        }
+        if follows_table.is_some_and(|o| o != table_end) {
+            return vec![];
+        }
+        return targets;
+    }
+
+    /// Where `addr` is in PRG ROM if decoded code starts there or the unreached
+    /// bytes from there score as code, with whether it starts the way routines do:
+    /// decoded, or after an `rts`, `rti` or what was decoded.
+    fn code_at(&self, addr: u16) -> Option<(FileOffset, bool)> {
+        let offset = self.prg_rom_offset(CpuAddr(addr)).ok()?;
+        if self.d.code.is_instruction(offset) {
+            return Option::Some((offset, true));
+        }
+        let stmts = self.d.code.statements();
+        let routine_start = offset.0 == self.header.prg_rom_offset()
+            || match stmts[offset.0 - 1].asm_code {
+                AsmCode::DataHexU8(RTS | RTI) => true,
+                AsmCode::DataHexU8(_) => false,
+                _ => true,
+            };
+        let bank_end = prg_rom_page_end(self.header.prg_rom_offset(), offset.0);
+        let end = (offset.0 + VECTOR_TARGET_SCORED_LENGTH).min(bank_end);
+        let end = self
+            .d
+            .code
+            .first_claimed(offset..FileOffset(end))
+            .map_or(end, |o| o.0);
+        // scored up to any fill after it
+        let len = match split_fill(self.d.code.data(), offset.0, end - offset.0).first() {
+            Option::Some((start, len)) if *start == offset.0 => *len,
+            _ => return Option::None,
+        };
+        let data = &self.d.code.data()[offset.0..offset.0 + len];
+        return match score_region(data, addr).score >= DEFAULT_SWEEP_THRESHOLD {
+            true => Option::Some((offset, routine_start)),
+            false => Option::None,
+        };
+    }
+
+    /// Scores the PRG ROM bytes no entry point reached on how much they look like
+    /// code, so ambiguous regions can be told apart from data and the threshold
+    /// tuned. Each region gets its score as a comment.
+    fn score_unreached_regions(&mut self, threshold: f64) {
+        let mapper = self.mapper();
+        let mut regions = Vec::new();
+        for (offset, len) in self.unreached_runs() {
+            let addr = mapper.to_banked_addr(FileOffset(offset));
+            let score = score_region(&self.d.code.data()[offset..offset + len], addr.addr.0);
+            let likely_code = score.score >= threshold;
+            self.d.code.set_comment(
+                FileOffset(offset),
+                format!(
+                    "unreached, {} bytes, code score {:.2}: likely {}",
+                    len,
+                    score.score,
+                    if likely_code { "code" } else { "data" }
+                )
+                .as_str(),
+            );
+            regions.push(ScoredRegion {
+                offset,
+                bank: addr.bank,
+                addr: addr.addr.0,
+                len,
+                score,
+                likely_code,
+            });
+        }
+        self.unreached_regions = regions;
+    }
+
+    /// Makes every jump and branch assemble back to the same bytes. Ones to code or
+    /// data that's written refer to its label, labeling it if it isn't yet, others,
+    /// e.g. into RAM or to a mirror of a bank, are written as the address or relative
+    /// to `*`.
+    fn resolve_jump_labels(&mut self) {
+        let mapper = self.mapper();
+        let code = self.code_offsets();
+        let trainer_addrs =
+            NES_TRAINER_START_ADDRESS..NES_TRAINER_START_ADDRESS + NES_TRAINER_LENGTH;
+        let mut labels = self
+            .d
+            .code
+            .statements()
+            .iter()
+            .filter_map(|stmt| stmt.label.as_deref().map(|l| l.to_string()))
+            .collect::<HashSet<String>>();
+        for offset in code.clone().map(FileOffset) {
+            let (target, label) = match &self.d.code.statements()[offset.0].asm_code {
+                AsmCode::Instruction(instr) => {
+                    match instr.target(mapper.to_addr(offset).0).zip(instr.label()) {
+                        Option::Some((target, label)) => (target, label.to_string()),
+                        Option::None => continue,
+                    }
+                }
+                _ => continue,
+            };
+            let in_rom = target as usize >= NES_PRG_ROM_START_ADDRESS
+                || (self.header.trainer && trainer_addrs.contains(&(target as usize)));
+            let target_offset = Option::Some(target)
+                .filter(|_| in_rom)
+                .and_then(|target| mapper.to_offset(CpuAddr(target)))
+                .filter(|t| {
+                    code.contains(&t.0)
+                        && mapper.to_addr(*t).0 == target
+                        && !self.d.code.is_left_out(self.d.code.statement_start(*t).0)
+                });
+            let operand = match target_offset {
+                Option::Some(t) => match self.d.code.label(t) {
+                    Option::Some(existing) => existing.to_string(),
+                    Option::None => {
+                        let is_name = label.starts_with(|c: char| c.is_ascii_alphabetic());
+                        let name = if is_name && !labels.contains(&label) {
+                            label
+                        } else if trainer_addrs.contains(&(target as usize)) {
+                            format!("trainer_{:04x}", target)
+                        } else {
+                            format!("prgrom{}_{:04x}", mapper.to_banked_addr(t).bank, target)
+                        };
+                        self.d.code.set_label(t, &name);
+                        labels.insert(name.clone());
+                        name
+                    }
+                },
+                Option::None => match &self.d.code.statements()[offset.0].asm_code {
+                    AsmCode::Instruction(Instruction::JMP_ABS(..) | Instruction::JSR_ABS(..)) => {
+                        format!("${:04x}", target)
+                    }
+                    _ => format!(
+                        "*{:+}",
+                        target.wrapping_sub(mapper.to_addr(offset).0) as i16
+                    ),
+                },
+            };
+            self.d.code.set_target_label(offset, &operand);
+        }
+    }
+
+    /// Writes runs of the same byte no code reached and runs of `nop`s as `.res`,
+    /// noting the ones that align the code after them. Runs stop at labels and
+    /// comments so nothing written about the bytes is lost.
+    fn collapse_padding(&mut self) {
+        let mapper = self.mapper();
+        let prg_rom_end = self.prg_rom_end();
+        let mut offset = self.header.prg_rom_offset();
+        while offset < prg_rom_end {
+            let bank_end = prg_rom_end.min(prg_rom_page_end(self.header.prg_rom_offset(), offset));
+            let stmts = self.d.code.statements();
+            let value = self.d.code.data()[offset];
+            let reached = match stmts[offset].asm_code {
+                AsmCode::DataHexU8(_) => false,
+                AsmCode::Instruction(Instruction::NOP) => true,
+                _ => {
+                    offset += 1;
+                    continue;
+                }
+            };
+            let len = stmts[offset..bank_end]
+                .iter()
+                .enumerate()
+                .take_while(|(i, stmt)| {
+                    let same = match stmt.asm_code {
+                        AsmCode::DataHexU8(v) => !reached && v == value,
+                        AsmCode::Instruction(Instruction::NOP) => reached,
+                        _ => false,
+                    };
+                    return same
+                        && (*i == 0
+                            || (stmt.label.is_none()
+                                && stmt.comment.is_none()
+                                && stmt.segment.is_none()));
+                })
+                .count();
+            let collapse = if reached {
+                len >= FILL_RUN_LENGTH
+            } else {
+                is_fill_run(value, len)
+            };
+            if !collapse {
+                offset += len;
+                continue;
+            }
+
+            let end = offset + len;
+            // a moved table isn't what comes after the padding in the output
+            let aligned = if end < bank_end
+                && !self.d.code.is_moved(end)
+                && (stmts[end].label.is_some()
+                    || matches!(stmts[end].asm_code, AsmCode::Instruction(_)))
+            {
+                let end_addr = mapper.to_addr(FileOffset(end)).0;
+                padding_alignment(end_addr, len).map(|alignment| {
+                    format!("padding, aligns ${:04x} to ${:x}", end_addr, alignment)
+                })
+            } else {
+                Option::None
+            };
+            let comment = match aligned {
+                Option::Some(aligned) if reached => {
+                    Option::Some(format!("{} nops, {}", len, aligned))
+                }
+                Option::Some(aligned) => Option::Some(aligned),
+                Option::None if reached => Option::Some(format!("{} nops", len)),
+                Option::None => Option::None,
+            };
+            self.d.code.collapse_fill(FileOffset(offset), len, value);
+            if let Option::Some(comment) = comment {
+                self.d.code.add_comment(FileOffset(offset), &comment);
+            }
+            offset = end;
+        }
+    }
+
+    fn decode_vector(
+        &mut self,
+        offset: FileOffset,
+        name: &str,
+    ) -> Result<CpuAddr, DisassembleError> {
+        let low = self.d.code.take(offset)?.asm_code.to_u8()? as u16;
+        let high = self.d.code.take(offset + 1)?.asm_code.to_u8()? as u16;
+        let addr = low | (high << 8);
+        self.d
+            .code
+            .replace(offset..offset + 2, AsmCode::DataHexU16(addr))?;
+        self.d.code.set_comment(offset, name);
+        self.d.code.claim(offset, &format!("the {} vector", name));
+        return Result::Ok(CpuAddr(addr));
+    }
+}
+
+/// `address:entries` of `--vector-table`, e.g. `$ffd0:8` for the 8 words at $ffd0.
+fn parse_vector_table(s: &str) -> Result<(CpuAddr, usize), String> {
+    let (addr, entries) = s
+        .split_once(':')
+        .ok_or_else(|| format!("expected address:entries, found \"{}\"", s))?;
+    let entries = entries
+        .parse::<usize>()
+        .ok()
+        .filter(|entries| *entries > 0)
+        .ok_or_else(|| format!("invalid number of entries \"{}\"", entries))?;
+    return Result::Ok((parse_addr(addr)?, entries));
+}
+
+/// Offset just past the PRG ROM page `offset` is in, for PRG ROM starting at
+/// `prg_rom_offset`.
+fn prg_rom_page_end(prg_rom_offset: usize, offset: usize) -> usize {
+    return offset - (offset - prg_rom_offset) % NES_PRG_ROM_PAGE_LENGTH + NES_PRG_ROM_PAGE_LENGTH;
+}
+
+/// The trainer at $7000-$71ff in front of the layout of the ROM's mapper.
+struct TrainerMapper {
+    rom: Box<dyn Mapper>,
+}
+
+impl TrainerMapper {
+    const ADDRS: std::ops::Range<usize> =
+        NES_TRAINER_START_ADDRESS..NES_TRAINER_START_ADDRESS + NES_TRAINER_LENGTH;
+    const OFFSETS: std::ops::Range<usize> =
+        NES_HEADER_LENGTH..NES_HEADER_LENGTH + NES_TRAINER_LENGTH;
+}
+
+impl Mapper for TrainerMapper {
+    fn to_offset(&self, addr: CpuAddr) -> Option<FileOffset> {
+        if TrainerMapper::ADDRS.contains(&(addr.0 as usize)) {
+            return Option::Some(FileOffset(
+                addr.0 as usize - NES_TRAINER_START_ADDRESS + NES_HEADER_LENGTH,
+            ));
+        }
+        return self.rom.to_offset(addr);
+    }
+
+    fn to_addr(&self, offset: FileOffset) -> CpuAddr {
+        if TrainerMapper::OFFSETS.contains(&offset.0) {
+            return CpuAddr((offset.0 - NES_HEADER_LENGTH + NES_TRAINER_START_ADDRESS) as u16);
+        }
+        return self.rom.to_addr(offset);
+    }
+
+    fn to_banked_addr(&self, offset: FileOffset) -> BankedAddr {
+        if TrainerMapper::OFFSETS.contains(&offset.0) {
+            return BankedAddr {
+                bank: 0,
+                addr: self.to_addr(offset),
+            };
+        }
+        return self.rom.to_banked_addr(offset);
+    }
+}
+
+/// PRG ROM mapped linearly at $8000 with a single 16k page mirrored at $c000.
+struct NromMapper {
+    prg_rom_offset: usize,
+    prg_rom_count: usize,
+}
+
+impl Mapper for NromMapper {
+    fn to_offset(&self, addr: CpuAddr) -> Option<FileOffset> {
+        let mut prg_offset = (addr.0 as usize).checked_sub(NES_PRG_ROM_START_ADDRESS)?;
+        if self.prg_rom_count == 1 && prg_offset >= NES_PRG_ROM_PAGE_LENGTH {
+            prg_offset -= NES_PRG_ROM_PAGE_LENGTH;
+        }
+        return Option::Some(FileOffset(prg_offset + self.prg_rom_offset));
+    }
+
+    fn to_addr(&self, offset: FileOffset) -> CpuAddr {
+        return CpuAddr((offset.0 - self.prg_rom_offset + NES_PRG_ROM_START_ADDRESS) as u16);
+    }
+
+    fn to_banked_addr(&self, offset: FileOffset) -> BankedAddr {
+        return BankedAddr {
+            bank: ((offset.0 - self.prg_rom_offset) / NES_PRG_ROM_PAGE_LENGTH) as u8,
+            addr: self.to_addr(offset),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// NROM with a routine at $8000 jumping through the table of 3 handlers at
+    /// $8020, the first handler right after the table.
+    fn vector_table_rom() -> Vec<u8> {
+        let mut rom = vec![0xff; 16 + 0x4000];
+        rom[..16].copy_from_slice(&[0x4e, 0x45, 0x53, 0x1a, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        let prg = &mut rom[16..];
+        // lda $00, asl, tax, lda $8020,x, sta $10, lda $8021,x, sta $11, jmp ($0010)
+        prg[..17].copy_from_slice(&[
+            0xa5, 0x00, 0x0a, 0xaa, 0xbd, 0x20, 0x80, 0x85, 0x10, 0xbd, 0x21, 0x80, 0x85, 0x11,
+            0x6c, 0x10, 0x00,
+        ]);
+        // lda #$01, sta $20, lda $21, clc, adc #$02, sta $21, rts
+        let handler = [
+            0xa9, 0x01, 0x85, 0x20, 0xa5, 0x21, 0x18, 0x69, 0x02, 0x85, 0x21, 0x60,
+        ];
+        for i in 0..3 {
+            let addr = 0x26 + i * handler.len();
+            prg[0x20 + i * 2] = addr as u8;
+            prg[0x21 + i * 2] = 0x80;
+            prg[addr..addr + handler.len()].copy_from_slice(&handler);
+        }
+        prg[0x3ffa..].copy_from_slice(&[0x00, 0x80, 0x00, 0x80, 0x00, 0x80]);
+        return rom;
+    }
+
+    #[test]
+    fn test_nrom_mapper() {
+        let mapper = NromMapper {
+            prg_rom_offset: 16,
+            prg_rom_count: 1,
+        };
+        assert_eq!(
+            mapper.to_offset(CpuAddr(0x8000)),
+            Option::Some(FileOffset(16))
+        );
+        // a single page is mirrored at $c000
+        assert_eq!(
+            mapper.to_offset(CpuAddr(0xfffc)),
+            Option::Some(FileOffset(16 + 0x3ffc))
         );
-        self.d.code.set_variable(
-            0x400f,
-            Variable {
-                name: "APU_NOISE_LOAD".to_string(),
-                value: VariableValue::U16(0x400f),
-            },
+        assert_eq!(mapper.to_offset(CpuAddr(0x6000)), Option::None);
+        assert_eq!(mapper.to_offset(CpuAddr(0x0010)), Option::None);
+        assert_eq!(mapper.to_addr(FileOffset(16 + 0x123)), CpuAddr(0x8123));
+
+        let mapper = NromMapper {
+            prg_rom_offset: 16,
+            prg_rom_count: 2,
+        };
+        assert_eq!(
+            mapper.to_offset(CpuAddr(0xc000)),
+            Option::Some(FileOffset(16 + 0x4000))
         );
-        self.d.code.set_variable(
-            0x4010,
-            Variable {
-                name: "APU_DMC_IL__RRRR".to_string(),
-                value: VariableValue::U16(0x4010),
-            },
+        assert_eq!(
+            mapper.to_banked_addr(FileOffset(16 + 0x4010)),
+            BankedAddr {
+                bank: 1,
+                addr: CpuAddr(0xc010)
+            }
         );
-        self.d.code.set_variable(
-            0x4011,
-            Variable {
-                name: "APU_DMC_LOAD".to_string(),
-                value: VariableValue::U16(0x4011),
-            },
+    }
+
+    #[test]
+    fn test_trainer_mapper() {
+        let prg_rom_offset = NES_HEADER_LENGTH + NES_TRAINER_LENGTH;
+        let mapper = TrainerMapper {
+            rom: Box::new(NromMapper {
+                prg_rom_offset,
+                prg_rom_count: 1,
+            }),
+        };
+        assert_eq!(
+            mapper.to_offset(CpuAddr(0x7010)),
+            Option::Some(FileOffset(NES_HEADER_LENGTH + 0x10))
         );
-        self.d.code.set_variable(
-            0x4012,
-            Variable {
-                name: "APU_DMC_SAMPLE_ADDR".to_string(),
-                value: VariableValue::U16(0x4012),
-            },
+        assert_eq!(
+            mapper.to_addr(FileOffset(NES_HEADER_LENGTH + 0x10)),
+            CpuAddr(0x7010)
         );
-        self.d.code.set_variable(
-            0x4013,
-            Variable {
-                name: "APU_DMC_SAMPLE_LEN".to_string(),
-                value: VariableValue::U16(0x4013),
-            },
+        assert_eq!(
+            mapper.to_offset(CpuAddr(0x8000)),
+            Option::Some(FileOffset(prg_rom_offset))
         );
-        self.d.code.set_variable(
-            0x4014,
-            Variable {
-                name: "OAM_DMA".to_string(),
-                value: VariableValue::U16(0x4014),
-            },
+        // the rest of cartridge RAM isn't in the file
+        assert_eq!(mapper.to_offset(CpuAddr(0x7200)), Option::None);
+        assert_eq!(mapper.to_offset(CpuAddr(0x6000)), Option::None);
+    }
+
+    #[test]
+    fn test_trainer_self_modifying_code() {
+        let mut rom = vec![0xff; NES_HEADER_LENGTH + NES_TRAINER_LENGTH + 0x4000];
+        rom[..16].copy_from_slice(&[0x4e, 0x45, 0x53, 0x1a, 1, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        // lda $10, rts in the trainer at $7000
+        rom[16..19].copy_from_slice(&[0xa5, 0x10, 0x60]);
+        let prg = NES_HEADER_LENGTH + NES_TRAINER_LENGTH;
+        // sta $7001, inc $7001, jsr $7000, sta $8000, rti
+        rom[prg..prg + 13].copy_from_slice(&[
+            0x8d, 0x01, 0x70, 0xee, 0x01, 0x70, 0x20, 0x00, 0x70, 0x8d, 0x00, 0x80, 0x40,
+        ]);
+        rom[prg + 0x3ffa..].copy_from_slice(&[0x00, 0x80, 0x00, 0x80, 0x00, 0x80]);
+        let d = NesDisassembler::disassemble(rom).unwrap();
+        let warnings = d
+            .diagnostics()
+            .iter()
+            .filter(|diagnostic| diagnostic.message.starts_with("self-modifying code"))
+            .map(|diagnostic| diagnostic.message.as_str())
+            .collect::<Vec<&str>>();
+        // the store to PRG ROM goes to the mapper instead
+        assert_eq!(
+            warnings,
+            [
+                "self-modifying code, $8000 writes $7001 in the instruction at $7000",
+                "self-modifying code, $8003 writes $7001 in the instruction at $7000",
+            ]
         );
-        self.d.code.set_variable(
-            0x4015,
-            Variable {
-                name: "APU_CH_ENABLE_STATUS".to_string(),
-                value: VariableValue::U16(0x4015),
-            },
+        assert_eq!(
+            d.d.code.statements()[prg + 3].comment.as_deref(),
+            Option::Some("self-modifying: changes the instruction at $7000")
         );
-        self.d.code.set_variable(
-            0x4017,
-            Variable {
-                name: "APU_ALL_FRAME_COUNTER".to_string(),
-                value: VariableValue::U16(0x4017),
-            },
+    }
+
+    #[test]
+    fn test_tile_format() {
+        let mut rom = vec![0x00; 16 + 0x4000 + 0x2000];
+        rom[..16].copy_from_slice(&[0x4e, 0x45, 0x53, 0x1a, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        rom[16] = 0x40; // rti
+        rom[16 + 0x3ffa..16 + 0x4000].copy_from_slice(&[0x00, 0x80, 0x00, 0x80, 0x00, 0x80]);
+        let chr = 16 + 0x4000;
+        // the top left pixel of the first tile, color 3 as NES tiles, set in the
+        // first two as mono ones
+        rom[chr] = 0x80;
+        rom[chr + 8] = 0x80;
+        let tiles = |opts: &AnalysisOptions| {
+            let d = NesDisassembler::disassemble_with_options(rom.clone(), opts).unwrap();
+            let row = match &d.d.code.statements()[chr].asm_code {
+                AsmCode::DataSeq(bytes) => bytes.len(),
+                _ => panic!("the CHR ROM isn't grouped into tiles"),
+            };
+            let mut out = Vec::new();
+            d.write_tiles(&mut out).unwrap();
+            return (row, out);
+        };
+
+        let (row, image) = tiles(&AnalysisOptions::default());
+        assert_eq!(row, 16);
+        let header = b"P5\n128 256\n3\n";
+        assert_eq!(image[..header.len()], header[..]);
+        assert_eq!(image[header.len()..][..2], [3, 0]);
+
+        let (row, image) = tiles(&AnalysisOptions {
+            tile_format: Option::Some(TileFormat::MONO),
+            ..AnalysisOptions::default()
+        });
+        assert_eq!(row, 8);
+        let header = b"P5\n128 512\n1\n";
+        assert_eq!(image[..header.len()], header[..]);
+        assert_eq!(image[header.len()..][..9], [1, 0, 0, 0, 0, 0, 0, 0, 1]);
+    }
+
+    #[test]
+    fn test_targets_outside_rom() {
+        let mut rom = vec![0xff; 16 + 0x4000];
+        rom[..16].copy_from_slice(&[0x4e, 0x45, 0x53, 0x1a, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        // jsr $0300, bne $7ff9, jmp $6000
+        rom[16..24].copy_from_slice(&[0x20, 0x00, 0x03, 0xd0, 0xf4, 0x4c, 0x00, 0x60]);
+        // the NMI runs from cartridge RAM
+        rom[16 + 0x3ffa..].copy_from_slice(&[0x00, 0x60, 0x00, 0x80, 0x00, 0x80]);
+        let d = NesDisassembler::disassemble(rom).unwrap();
+        assert!(d.d.code.is_instruction(FileOffset(16 + 5)));
+        let messages = d
+            .diagnostics()
+            .iter()
+            .map(|diagnostic| diagnostic.message.as_str())
+            .collect::<Vec<&str>>();
+        for message in [
+            "$0300 isn't in the ROM, the code there isn't followed",
+            "$7ff9 isn't in the ROM, the code there isn't followed",
+            "$6000 isn't in the ROM, the code there isn't followed",
+            "the NMI vector points to $6000, which isn't in the ROM, not followed",
+        ] {
+            assert!(
+                messages.contains(&message),
+                "{} not in {:?}",
+                message,
+                messages
+            );
+        }
+        assert!(!d.entry_points.iter().any(|(name, _)| name == "prgrom0_nmi"));
+
+        let mut out = Vec::new();
+        d.write(&mut out).unwrap();
+        let asm = String::from_utf8(out).unwrap();
+        assert!(asm.contains("jsr $0300"));
+        assert!(asm.contains("jmp $6000"));
+    }
+
+    #[test]
+    fn test_jump_indirect_to_entry_point() {
+        let mut rom = vec![0xff; 16 + 0x4000];
+        rom[..16].copy_from_slice(&[0x4e, 0x45, 0x53, 0x1a, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        // lda #$00, sta $10, lda #$80, sta $11, jmp ($0010), back to reset
+        rom[16..27].copy_from_slice(&[
+            0xa9, 0x00, 0x85, 0x10, 0xa9, 0x80, 0x85, 0x11, 0x6c, 0x10, 0x00,
+        ]);
+        // rti
+        rom[16 + 0x100] = 0x40;
+        rom[16 + 0x3ffa..].copy_from_slice(&[0x00, 0x81, 0x00, 0x80, 0x00, 0x81]);
+        let d = NesDisassembler::disassemble(rom).unwrap();
+        assert_eq!(
+            d.d.code.label(FileOffset(16)),
+            Option::Some("prgrom0_reset")
         );
+        assert!(d.d.code.xrefs()[&FileOffset(16)].contains(&0x8008));
     }
 
-    fn parse_header(&mut self) -> Result<(), DisassembleError> {
-        if self.d.code.is_eq_u8(0, b'N')
-            && self.d.code.is_eq_u8(1, b'E')
-            && self.d.code.is_eq_u8(2, b'S')
-            && self.d.code.is_eq_u8(3, 0x1a)
-        {
-            self.d.code.replace(
-                0..4,
-                AsmCode::DataSeq(vec![
-                    AsmCode::DataString("NES".to_string()),
-                    AsmCode::DataHexU8(0x1a),
-                ]),
-            )?;
-            self.d.code.set_segment(0, "HEADER");
-        } else {
-            return Result::Err(DisassembleError::ParseError(
-                "invalid nes header".to_string(),
-            ));
+    /// NROM with `code` at RESET, $8000, and an `rti` at $8020.
+    fn jump_indirect_rom(code: &[u8]) -> Vec<u8> {
+        let mut rom = vec![0xff; 16 + 0x4000];
+        rom[..16].copy_from_slice(&[0x4e, 0x45, 0x53, 0x1a, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        rom[16..16 + code.len()].copy_from_slice(code);
+        rom[16 + 0x20] = 0x40;
+        rom[16 + 0x3ffa..].copy_from_slice(&[0x20, 0x80, 0x00, 0x80, 0x20, 0x80]);
+        return rom;
+    }
+
+    /// A 16k PRG ROM with each of `parts` at its address and the rest $ff.
+    fn rom_with(parts: &[(usize, &[u8])]) -> Vec<u8> {
+        let mut rom = vec![0xff; 16 + 0x4000];
+        rom[..16].copy_from_slice(&[0x4e, 0x45, 0x53, 0x1a, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        for (addr, bytes) in parts {
+            let offset = 16 + addr - 0x8000;
+            rom[offset..offset + bytes.len()].copy_from_slice(bytes);
         }
+        return rom;
+    }
 
-        self.prg_rom_count = self.d.code.replace_with_u8(4)?;
-        self.d.code.set_comment(4, "PRG ROM count");
+    fn page_wrap_warned(d: &NesDisassembler, pointer: &str) -> bool {
+        return d.diagnostics().iter().any(|diagnostic| {
+            diagnostic.message.starts_with(&format!(
+                "jmp ({}) reads the high byte of the target from",
+                pointer
+            ))
+        });
+    }
 
-        self.chr_rom_count = self.d.code.replace_with_u8(5)?;
-        self.d.code.set_comment(5, "CHR ROM count");
+    #[test]
+    fn test_jump_indirect_page_wrap_in_rom() {
+        // jmp ($81ff), its high byte read from $8100
+        let mut rom = jump_indirect_rom(&[0x6c, 0xff, 0x81]);
+        rom[16 + 0x1ff] = 0x20;
+        rom[16 + 0x100] = 0x80;
+        let d = NesDisassembler::disassemble(rom).unwrap();
+        assert!(page_wrap_warned(&d, "$81ff"));
+        assert_eq!(
+            d.d.code.statements()[16].comment.as_deref(),
+            Option::Some(
+                "jmp ($81ff) reads the high byte of the target from $8100, not $8200 \
+                 (6502 page wrap bug), jumps to $8020"
+            )
+        );
+        assert!(d.d.code.xrefs()[&FileOffset(16 + 0x20)].contains(&0x8000));
+        assert!(d.d.unresolved_jumps.is_empty());
+    }
 
-        self.flags6 = self.d.code.replace_with_binary_u8(6)?;
-        self.d.code.set_comment(
-            6,
-            "Flags 6
-      ++++------ Mapper Number D0..D3
-      ||||        1: Yes
-      ||||        0: No
-      ||||+----- Hard-wired four-screen mode
-      |||||       1: Present between Header and PRG-ROM data
-      |||||       0: Not present
-      |||||+---- 512-byte Trainer
-      ||||||      1: Present
-      ||||||      0: Not present
-      ||||||+--- \"Battery\" and other non-volatile memory
-      |||||||     1: Vertical
-      |||||||     0: Horizontal or mapper-controlled
-      |||||||+-- Hard-wired nametable mirroring type
-      NNNNFTBM",
+    #[test]
+    fn test_jump_indirect_page_wrap_in_ram() {
+        // lda #$20, sta $10ff, lda #$80, sta $1000, jmp ($10ff)
+        let rom = jump_indirect_rom(&[
+            0xa9, 0x20, 0x8d, 0xff, 0x10, 0xa9, 0x80, 0x8d, 0x00, 0x10, 0x6c, 0xff, 0x10,
+        ]);
+        let d = NesDisassembler::disassemble(rom).unwrap();
+        assert!(page_wrap_warned(&d, "$10ff"));
+        // the page wrap is added to what the jump was commented with
+        assert_eq!(
+            d.d.code.statements()[16 + 10].comment.as_deref(),
+            Option::Some(
+                "jumps to $8020, the pointer is set right before\n\
+                 jmp ($10ff) reads the high byte of the target from $1000, not $1100 \
+                 (6502 page wrap bug), jumps to $8020"
+            )
         );
+        assert!(d.d.code.xrefs()[&FileOffset(16 + 0x20)].contains(&0x800a));
+        assert!(d.d.unresolved_jumps.is_empty());
+    }
 
-        self.flags7 = self.d.code.replace_with_binary_u8(7)?;
-        self.d.code.set_comment(
-            7,
-            "Flags 7
-      ++++------ Mapper Number D4..D7
-      ||||++---- NES 2.0 identifier
-      ||||||      3: Extended Console Type
-      ||||||      2: Nintendo Playchoice 10
-      ||||||      1: Nintendo Vs. System
-      ||||||      0: Nintendo Entertainment System/Family Computer
-      ||||||++-- Console type
-      NNNN10TT",
+    #[test]
+    fn test_jump_indirect_page_wrap_unresolved() {
+        // jmp ($10ff) with nothing stored there first
+        let rom = jump_indirect_rom(&[0x6c, 0xff, 0x10]);
+        let d = NesDisassembler::disassemble(rom).unwrap();
+        assert!(page_wrap_warned(&d, "$10ff"));
+        assert_eq!(
+            d.d.code.statements()[16].comment.as_deref(),
+            Option::Some(
+                "jmp ($10ff) reads the high byte of the target from $1000, not $1100 \
+                 (6502 page wrap bug)"
+            )
         );
+        assert_eq!(
+            d.d.unresolved_jumps.get(&FileOffset(16)),
+            Option::Some(&CpuAddr(0x10ff))
+        );
+        assert!(d.diagnostics().iter().any(|diagnostic| diagnostic.message
+            == "jmp ($10ff) goes where a pointer in RAM points, stopped following code"));
+    }
 
-        self.mapper = self.d.code.replace_with_binary_u8(8)?;
-        self.d.code.set_comment(
-            8,
-            "Mapper MSB/Submapper
-      ++++------ Submapper number
-      ||||++++-- Mapper number D8..D11
-      SSSSNNNN",
+    #[test]
+    fn test_overlapping_claims() {
+        // lda #$00, rts at reset, lda #$01, rts at $8010 reached by nothing
+        let mut rom = jump_indirect_rom(&[0xa9, 0x00, 0x60]);
+        rom[16 + 0x10..16 + 0x13].copy_from_slice(&[0xa9, 0x01, 0x60]);
+        let mut d = NesDisassembler::disassemble(rom.clone()).unwrap();
+        let warned = |d: &NesDisassembler, message: &str| {
+            return d
+                .diagnostics()
+                .iter()
+                .any(|diagnostic| diagnostic.message == message);
+        };
+
+        // data over traced code
+        d.mark_data(CpuAddr(0x8000), CpuAddr(0x8001)).unwrap();
+        assert!(warned(
+            &d,
+            "data marked by the user overlaps code traced from prgrom0_reset (offset $0010)"
+        ));
+        assert!(d.d.code.is_instruction(FileOffset(16)));
+
+        // code traced into data
+        d.mark_data(CpuAddr(0x8010), CpuAddr(0x8012)).unwrap();
+        d.disassemble_at(CpuAddr(0x8010)).unwrap();
+        assert!(warned(
+            &d,
+            "code traced from prgrom0_8010 overlaps data marked by the user (offset $0020)"
+        ));
+        assert!(!d.d.code.is_instruction(FileOffset(16 + 0x10)));
+
+        let mut d = NesDisassembler::disassemble(rom).unwrap();
+        d.d.strict = true;
+        assert!(matches!(
+            d.mark_data(CpuAddr(0x8000), CpuAddr(0x8001)),
+            Result::Err(DisassembleError::Overlap(_))
+        ));
+        d.mark_data(CpuAddr(0x8010), CpuAddr(0x8012)).unwrap();
+        assert!(matches!(
+            d.disassemble_at(CpuAddr(0x8010)),
+            Result::Err(DisassembleError::Overlap(_))
+        ));
+    }
+
+    #[test]
+    fn test_pass_stats() {
+        // lda #$00, rts at reset, rti for the interrupts
+        let rom = jump_indirect_rom(&[0xa9, 0x00, 0x60]);
+        let d = NesDisassembler::disassemble(rom).unwrap();
+        let mut report = Vec::new();
+        d.write_report(&mut report, "test.nes").unwrap();
+        let report = String::from_utf8(report).unwrap();
+        let mut json = Vec::new();
+        d.write_json(&mut json).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+
+        // tracing decodes the 4 bytes of code and the 6 of the vectors, labels reset
+        // and the interrupt handler and comments the vectors
+        assert!(
+            report.contains(
+                "| pass | bytes classified | labels | comments |\n|---|---|---|---|\n\
+                 | trace | 10 | 2 | 3 |\n\
+                 | idioms | 0 | 0 | 0 |\n\
+                 | pointers | 0 | 0 | 0 |\n"
+            ),
+            "{}",
+            report
         );
+        assert_eq!(
+            json["passes"],
+            serde_json::json!([
+                {"pass": "trace", "classified": 10, "labels": 2, "comments": 3},
+                {"pass": "idioms", "classified": 0, "labels": 0, "comments": 0},
+                {"pass": "pointers", "classified": 0, "labels": 0, "comments": 0},
+            ])
+        );
+    }
 
-        self.prg_chr_rom_size = self.d.code.replace_with_binary_u8(9)?;
-        self.d.code.set_comment(
-            9,
-            "PRG-ROM/CHR-ROM size MSB
-      ++++------ CHR-ROM size MSB
-      ||||++++-- PRG-ROM size MSB
-      CCCCPPPP",
+    #[test]
+    fn test_procs() {
+        let mut rom = vec![0xff; 16 + 0x4000];
+        rom[..16].copy_from_slice(&[0x4e, 0x45, 0x53, 0x1a, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        let mut code = |addr: usize, bytes: &[u8]| {
+            rom[16 + addr - 0x8000..16 + addr - 0x8000 + bytes.len()].copy_from_slice(bytes);
+        };
+        // jsr each routine, then loop
+        for (i, routine) in [0x8020u16, 0x8030, 0x8038, 0x8040, 0x8050, 0x8051]
+            .iter()
+            .enumerate()
+        {
+            let [low, high] = routine.to_le_bytes();
+            code(0x8000 + i * 3, &[0x20, low, high]);
+        }
+        code(0x8012, &[0x4c, 0x12, 0x80]);
+        // lda #$01, beq past the rts, rts, lda #$02, rts
+        code(0x8020, &[0xa9, 0x01, 0xf0, 0x01, 0x60, 0xa9, 0x02, 0x60]);
+        // ldx #$00, rts
+        code(0x8030, &[0xa2, 0x00, 0x60]);
+        // inx, jmp into the tail of the next routine
+        code(0x8038, &[0xe8, 0x4c, 0x42, 0x80]);
+        // iny, nop, the shared tail: dey, rts
+        code(0x8040, &[0xc8, 0xea, 0x88, 0x60]);
+        // inx, falling into the next routine: dey, rts
+        code(0x8050, &[0xe8, 0x88, 0x60]);
+        code(0x8060, &[0x40]);
+        code(0xbffa, &[0x60, 0x80, 0x00, 0x80, 0x60, 0x80]);
+        let d = NesDisassembler::disassemble_with_options(
+            rom,
+            &AnalysisOptions {
+                procs: true,
+                ..AnalysisOptions::default()
+            },
+        )
+        .unwrap();
+        let mut out = Vec::new();
+        d.write(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        // the branch past the rts keeps the code after it in the routine
+        assert!(out.contains(
+            ".proc prgrom0_8020\n    lda #$01\n    beq prgrom0_8025\n    rts\nprgrom0_8025:\n    lda #$02\n    rts\n.endproc\n"
+        ));
+        assert!(out.contains(".proc prgrom0_8030\n    ldx #$00\n    rts\n.endproc\n"));
+        // the routine jumping into a shared tail ends at the jmp, the one the tail
+        // is in stays a bare label
+        assert!(out.contains(".proc prgrom0_8038\n    inx\n    jmp prgrom0_8042\n.endproc\n"));
+        assert!(out.contains("prgrom0_8040:\n    iny\n"));
+        assert!(!out.contains(".proc prgrom0_8040"));
+        // the routine falling into one called on its own stays a bare label
+        assert!(out
+            .contains("prgrom0_8050:\n    inx\n.proc prgrom0_8051\n    dey\n    rts\n.endproc\n"));
+    }
+
+    /// Code and tables the reset and NMI handlers share, and some only the NMI
+    /// handler reaches.
+    fn shared_rom() -> Vec<u8> {
+        return rom_with(&[
+            // lda $8040, jsr $8020, jmp *
+            (
+                0x8000,
+                &[0xad, 0x40, 0x80, 0x20, 0x20, 0x80, 0x4c, 0x06, 0x80],
+            ),
+            // lda $8040, lda $8048, jsr $8020, jsr $8028, rti
+            (
+                0x8010,
+                &[
+                    0xad, 0x40, 0x80, 0xad, 0x48, 0x80, 0x20, 0x20, 0x80, 0x20, 0x28, 0x80, 0x40,
+                ],
+            ),
+            // ldx #$01, rts
+            (0x8020, &[0xa2, 0x01, 0x60]),
+            // ldy #$02, rts
+            (0x8028, &[0xa0, 0x02, 0x60]),
+            (0x8040, &[0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08]),
+            (0x8048, &[0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17, 0x18]),
+            (0x8050, &[0x40]),
+            (0xbffa, &[0x10, 0x80, 0x00, 0x80, 0x50, 0x80]),
+        ]);
+    }
+
+    #[test]
+    fn test_reached_from_comments() {
+        let d = NesDisassembler::disassemble_with_options(
+            shared_rom(),
+            &AnalysisOptions {
+                reached_from_comments: true,
+                ..AnalysisOptions::default()
+            },
+        )
+        .unwrap();
+        let mut out = Vec::new();
+        d.write(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out
+            .contains("prgrom0_reset:\n    lda ABS_8040          ; reached from prgrom0_reset\n"));
+        assert!(
+            out.contains("prgrom0_nmi:\n    lda ABS_8040          ; reached from prgrom0_nmi\n")
         );
+        assert!(out.contains(
+            "prgrom0_8020:\n    ldx #$01              ; reached from prgrom0_nmi, prgrom0_reset\n"
+        ));
+        assert!(
+            out.contains("prgrom0_8028:\n    ldy #$02              ; reached from prgrom0_nmi\n")
+        );
+    }
 
-        self.prg_ram_eeprom_size = self.d.code.replace_with_binary_u8(10)?;
-        self.d.code.set_comment(
-            10,
-            "PRG-RAM/EEPROM size            
-  If the shift count is zero, there is no PRG-(NV)RAM.
-  If the shift count is non-zero, the actual size is
-  \"64 << shift count\" bytes, i.e. 8192 bytes for a shift count of 7.
-      ++++------ PRG-NVRAM/EEPROM (non-volatile) shift count
-      ||||++++-- PRG-RAM (volatile) shift count
-      ppppPPPP",
+    /// The assembly `--only-from root` writes for [`shared_rom`].
+    fn only_from(root: &str) -> String {
+        let d = NesDisassembler::disassemble_with_options(
+            shared_rom(),
+            &AnalysisOptions {
+                only_from: Option::Some(root.to_string()),
+                ..AnalysisOptions::default()
+            },
+        )
+        .unwrap();
+        let mut out = Vec::new();
+        d.write(&mut out).unwrap();
+        return String::from_utf8(out).unwrap();
+    }
+
+    #[test]
+    fn test_only_from() {
+        let out = only_from("reset");
+        assert!(out.contains("prgrom0_reset:\n"));
+        assert!(out.contains("prgrom0_8020:\n    ldx #$01\n    rts\n"));
+        // the table both read is kept, up to the one only the NMI handler reads
+        assert!(out.contains(".byte $01\n"));
+        assert!(out.contains(".byte $08\n"));
+        assert!(!out.contains(".byte $11\n"));
+        assert!(!out.contains("prgrom0_nmi:"));
+        assert!(!out.contains("ldy #$02"));
+
+        let out = only_from("nmi");
+        assert!(!out.contains("prgrom0_reset:"));
+        assert!(out.contains("prgrom0_nmi:\n"));
+        assert!(out.contains("prgrom0_8020:\n    ldx #$01\n    rts\n"));
+        assert!(out.contains("prgrom0_8028:\n    ldy #$02\n    rts\n"));
+        assert!(out.contains(".byte $01\n"));
+        assert!(out.contains(".byte $11\n"));
+    }
+
+    /// The assembly written for [`shared_rom`] with the subroutine at $8020 tagged
+    /// `audio` and `video` and the one at $8028 `video`, keeping only what has one
+    /// of `include`, if any, and none of `exclude`.
+    fn filtered_by_tags(include: &[&str], exclude: &[&str]) -> String {
+        let d = NesDisassembler::disassemble_with_options(
+            shared_rom(),
+            &AnalysisOptions {
+                tags: vec![
+                    "audio=$8020".to_string(),
+                    "video=$8020".to_string(),
+                    "video=$8028".to_string(),
+                ],
+                include_tags: include.iter().map(|tag| tag.to_string()).collect(),
+                exclude_tags: exclude.iter().map(|tag| tag.to_string()).collect(),
+                ..AnalysisOptions::default()
+            },
+        )
+        .unwrap();
+        let mut out = Vec::new();
+        d.write(&mut out).unwrap();
+        return String::from_utf8(out).unwrap();
+    }
+
+    #[test]
+    fn test_tags() {
+        let out = filtered_by_tags(&[], &[]);
+        assert!(out.contains("; tag audio\n; tag video\n    ldx #$01"));
+        assert!(out.contains("prgrom0_reset:"));
+
+        let out = filtered_by_tags(&["audio"], &[]);
+        assert!(out.contains("ldx #$01"));
+        assert!(!out.contains("ldy #$02"));
+        assert!(!out.contains("prgrom0_reset:"));
+
+        let out = filtered_by_tags(&["video"], &[]);
+        assert!(out.contains("ldx #$01"));
+        assert!(out.contains("ldy #$02"));
+        assert!(!out.contains("prgrom0_reset:"));
+
+        let out = filtered_by_tags(&[], &["video"]);
+        assert!(!out.contains("ldx #$01"));
+        assert!(!out.contains("ldy #$02"));
+        assert!(out.contains("prgrom0_reset:"));
+
+        // the routine with both tags is left out
+        let out = filtered_by_tags(&["audio"], &["video"]);
+        assert!(!out.contains("ldx #$01"));
+        assert!(!out.contains("ldy #$02"));
+        assert!(!out.contains("prgrom0_reset:"));
+
+        let d = NesDisassembler::disassemble_with_options(
+            shared_rom(),
+            &AnalysisOptions {
+                include_tags: vec!["audio".to_string()],
+                ..AnalysisOptions::default()
+            },
         );
+        assert!(d.is_err());
+    }
 
-        self.chr_ram_size = self.d.code.replace_with_binary_u8(11)?;
-        self.d.code.set_comment(
-            11,
-            "CHR-RAM size
-  If the shift count is zero, there is no CHR-(NV)RAM.
-  If the shift count is non-zero, the actual size is
-  \"64 << shift count\" bytes, i.e. 8192 bytes for a shift count of 7.
-      ++++------ CHR-NVRAM size (non-volatile) shift count
-      ||||++++-- CHR-RAM size (volatile) shift count
-      ccccCCCC",
+    #[test]
+    fn test_name_data_by_access() {
+        let rom = rom_with(&[
+            (
+                0x8000,
+                &[
+                    // ldx #$00, lda $8040,x, sta PPU_DATA, inx, bne
+                    0xa2, 0x00, 0xbd, 0x40, 0x80, 0x8d, 0x07, 0x20, 0xe8, 0xd0, 0xf7,
+                    // ldy #$00, lda $8080,y, sta $0200,y, iny, bne
+                    0xa0, 0x00, 0xb9, 0x80, 0x80, 0x99, 0x00, 0x02, 0xc8, 0xd0, 0xf7,
+                    // lda #$00, sta $0300,x
+                    0xa9, 0x00, 0x9d, 0x00, 0x03,
+                ],
+            ),
+            // the main loop: lda $0300, jmp back
+            (0x801b, &[0xad, 0x00, 0x03, 0x4c, 0x1b, 0x80]),
+            (0x8030, &[0x40]),
+            (0x8040, &[0x01, 0x02, 0x03, 0x04]),
+            (0x8080, &[0x11, 0x12, 0x13, 0x14]),
+            (0xbffa, &[0x30, 0x80, 0x00, 0x80, 0x30, 0x80]),
+        ]);
+        let d = NesDisassembler::disassemble_with_options(
+            rom,
+            &AnalysisOptions {
+                name_data: true,
+                ..AnalysisOptions::default()
+            },
+        )
+        .unwrap();
+        let mut out = Vec::new();
+        d.write(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        // copied to PPU_DATA in a loop
+        assert!(out.contains("    lda ppu_data_8040,x\n"));
+        assert!(out.contains("ppu_data_8040:\n.byte $01\n"));
+        // copied to the OAM page in a loop
+        assert!(out.contains("    lda oam_data_8080,y\n"));
+        assert!(out.contains("oam_data_8080:\n.byte $11\n"));
+        // filled in before the main loop, read in it
+        assert!(out.contains("init_table_0300           = $0300\n"));
+        assert!(out.contains("    sta init_table_0300,x\n"));
+        assert!(out.contains("    lda init_table_0300\n    jmp prgrom0_801b\n"));
+        // written in the loop but not read, so not named
+        assert!(out.contains("    sta ABS_0200,y\n"));
+    }
+
+    #[test]
+    fn test_xref_comments() {
+        let d = NesDisassembler::disassemble_with_options(
+            shared_rom(),
+            &AnalysisOptions {
+                xref_comments: true,
+                ..AnalysisOptions::default()
+            },
+        )
+        .unwrap();
+        let mut out = Vec::new();
+        d.write(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        // called from both handlers
+        assert!(out.contains("prgrom0_8020:\n    ldx #$01              ; xrefs: $8003, $8016\n"));
+        assert!(out.contains("prgrom0_8028:\n    ldy #$02              ; xrefs: $8019\n"));
+        // a jmp target, only labeled once the jumps are resolved
+        assert!(out.contains("prgrom0_8006:\n    jmp prgrom0_8006      ; xrefs: $8006\n"));
+        // entry points nothing jumps to
+        assert!(out.contains("prgrom0_reset:\n    lda ABS_8040\n"));
+    }
+
+    #[test]
+    fn test_call_graph() {
+        let d = NesDisassembler::disassemble(shared_rom()).unwrap();
+        let mut dot = Vec::new();
+        d.write_call_graph(&mut dot, true).unwrap();
+        let dot = String::from_utf8(dot).unwrap();
+        assert_eq!(
+            dot.lines().collect::<Vec<&str>>(),
+            vec![
+                "digraph calls {",
+                "    \"prgrom0_nmi\" [shape=box];",
+                "    \"prgrom0_reset\" [shape=box];",
+                "    \"prgrom0_irq\" [shape=box];",
+                "    \"prgrom0_reset\" -> \"prgrom0_8020\";",
+                "    \"prgrom0_nmi\" -> \"prgrom0_8020\";",
+                "    \"prgrom0_nmi\" -> \"prgrom0_8028\";",
+                "}",
+            ]
         );
 
-        self.cpu_ppu_timing = self.d.code.replace_with_binary_u8(12)?;
-        self.d.code.set_comment(
-            12,
-            "CPU/PPU Timing
-            ++- CPU/PPU timing mode
-            ||   0: RP2C02 (\"NTSC NES\")
-            ||   1: RP2C07 (\"Licensed PAL NES\")
-            ||   2: Multiple-region
-            ||   3: UMC 6527P (\"Dendy\")
-      ......VV",
+        let mut json = Vec::new();
+        d.write_call_graph(&mut json, false).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(
+            json["roots"],
+            serde_json::json!(["prgrom0_nmi", "prgrom0_reset", "prgrom0_irq"])
         );
+        let calls = json["routines"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|routine| (routine["label"].clone(), routine["calls"].clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            calls,
+            vec![
+                ("prgrom0_reset".into(), serde_json::json!(["prgrom0_8020"])),
+                (
+                    "prgrom0_nmi".into(),
+                    serde_json::json!(["prgrom0_8020", "prgrom0_8028"])
+                ),
+                ("prgrom0_8020".into(), serde_json::json!([])),
+                ("prgrom0_8028".into(), serde_json::json!([])),
+                ("prgrom0_irq".into(), serde_json::json!([])),
+            ]
+        );
+    }
 
-        self.flags13 = self.d.code.replace_with_binary_u8(13)?;
-        self.d.code.set_comment(
-            13,
-            "When Byte 7 AND 3 =1: Vs. System Type
-      ++++------ Vs. Hardware Type
-      ||||++++-- Vs. PPU Type
-      MMMMPPPP",
+    #[test]
+    fn test_write_symbols() {
+        let mut rom = vec![0xff; 16 + 0x4000];
+        rom[..16].copy_from_slice(&[0x4e, 0x45, 0x53, 0x1a, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        // lda #$00, sta $2000, sta $10, rts
+        rom[16..24].copy_from_slice(&[0xa9, 0x00, 0x8d, 0x00, 0x20, 0x85, 0x10, 0x60]);
+        rom[16 + 0x3ffa..].copy_from_slice(&[0x00, 0x80, 0x00, 0x80, 0x00, 0x80]);
+        let mut d = NesDisassembler::disassemble(rom).unwrap();
+        d.set_label(CpuAddr(0x8000), "init").unwrap();
+        d.d.code.set_comment(FileOffset(16 + 5), "first\nsecond");
+        let mut out = Vec::new();
+        d.write_symbols(&mut out).unwrap();
+        let mlb = String::from_utf8(out).unwrap();
+        assert!(mlb.starts_with("P:0000:init"));
+        assert!(mlb.contains("\nP:0005::first\\nsecond\n"));
+        assert!(mlb.contains("\nG:2000:PPU_CTRL\n"));
+        assert!(mlb.contains("\nR:0010:ZP_10\n"));
+    }
+
+    #[test]
+    fn test_nmi_budgets() {
+        let mut rom = vec![0xff; 16 + 0x4000];
+        rom[..16].copy_from_slice(&[0x4e, 0x45, 0x53, 0x1a, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        // ldx #$00, loop: lda $0300,x, sta $2007, lda $0400,x, sta $2007, dex, bne loop,
+        // rti, then jmp to itself for the reset
+        rom[16..37].copy_from_slice(&[
+            0xa2, 0x00, 0xbd, 0x00, 0x03, 0x8d, 0x07, 0x20, 0xbd, 0x00, 0x04, 0x8d, 0x07, 0x20,
+            0xca, 0xd0, 0xf1, 0x40, 0x4c, 0x12, 0x80,
+        ]);
+        rom[16 + 0x3ffa..].copy_from_slice(&[0x00, 0x80, 0x12, 0x80, 0x12, 0x80]);
+        let d = NesDisassembler::disassemble(rom).unwrap();
+        let body = 5 + 4 + 5 + 4 + 2 + 3;
+        let ppu = 7 + 2 + 255 * body + 5 + 4 + 5 + 4;
+        assert_eq!(
+            d.nmi_budgets(),
+            vec![(
+                FileOffset(16),
+                Result::Ok(Cycles {
+                    total: 7 + 2 + 256 * body + 6,
+                    ppu: Option::Some(ppu)
+                })
+            )]
         );
+        assert!(d.diagnostics().iter().any(|diag| diag.message
+            == format!(
+                "NMI handler at $8000 can take {} cycles up to its last PPU access, more than \
+                 the 2273 of vblank on NTSC",
+                ppu
+            )));
+    }
 
-        self.misc_roms = self.d.code.replace_with_binary_u8(14)?;
-        self.d.code.set_comment(
-            14,
-            "Miscellaneous ROMs
-            ++- Number of miscellaneous ROMs present
-      ......RR",
+    #[test]
+    fn test_fceux_name_lists() {
+        let mut rom = vec![0xff; 16 + 0x8000];
+        rom[..16].copy_from_slice(&[0x4e, 0x45, 0x53, 0x1a, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        // lda $10, sta $2000, rts in the second bank
+        rom[16 + 0x4000..16 + 0x4006].copy_from_slice(&[0xa5, 0x10, 0x8d, 0x00, 0x20, 0x60]);
+        rom[16 + 0x7ffa..].copy_from_slice(&[0x00, 0xc0, 0x00, 0xc0, 0x00, 0xc0]);
+        let mut d = NesDisassembler::disassemble(rom).unwrap();
+        d.set_label(CpuAddr(0xc000), "init").unwrap();
+        d.d.code
+            .set_comment(FileOffset(16 + 0x4002), "first\nsecond");
+        let lists = d
+            .fceux_name_lists()
+            .into_iter()
+            .collect::<HashMap<String, String>>();
+        assert!(lists["ram"].contains("$0010#ZP_10#\n"));
+        assert!(lists["ram"].contains("$2000#PPU_CTRL#\n"));
+        assert!(lists["1"].starts_with("$C000#init#"));
+        assert!(lists["1"].contains("\n$C002##first\n\\second\n"));
+    }
+
+    #[test]
+    fn test_fceux_name_lists_banks() {
+        let mut rom = vec![0xff; 16 + 0x8000];
+        rom[..16].copy_from_slice(&[0x4e, 0x45, 0x53, 0x1a, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        // jsr $9800, jsr $c000, jmp *
+        rom[16..16 + 9].copy_from_slice(&[0x20, 0x00, 0x98, 0x20, 0x00, 0xc0, 0x4c, 0x06, 0x80]);
+        rom[16 + 0x1800] = 0x60;
+        rom[16 + 0x4000] = 0x60;
+        rom[16 + 0x7ffa..].copy_from_slice(&[0x00, 0x80, 0x00, 0x80, 0x00, 0x80]);
+        let d = NesDisassembler::disassemble(rom).unwrap();
+        let lists = d.fceux_name_lists();
+        // one list per 16k bank, the way FCEUX numbers them, not per 4k
+        assert_eq!(
+            lists
+                .iter()
+                .map(|(bank, _)| bank.as_str())
+                .collect::<Vec<&str>>(),
+            ["ram", "0", "1"]
         );
+        let lists = lists.into_iter().collect::<HashMap<String, String>>();
+        assert!(lists["0"].starts_with("$8000#"));
+        assert!(lists["0"].contains("\n$9800#"));
+        assert!(!lists["0"].contains("$C000#"));
+        assert!(lists["1"].starts_with("$C000#prgrom1_c000#\n"));
+        assert!(!lists["1"].contains("$9800#"));
+    }
 
-        self.default_expansion_device = self.d.code.replace_with_binary_u8(15)?;
-        self.d.code.set_comment(
-            15,
-            "Default Expansion Device
-        ++++++- Default Expansion Device
-      ..DDDDDD",
+    #[test]
+    fn test_truncated_prg_rom() {
+        // two PRG ROM pages and a CHR ROM page in the header, only 4k of the second
+        // page in the file
+        let mut rom = jump_indirect_rom(&[0x60]);
+        rom[4] = 2;
+        rom[5] = 1;
+        rom.extend(vec![0xea; 0x1000]);
+        let d = NesDisassembler::disassemble(rom).unwrap();
+        let messages = d
+            .diagnostics()
+            .iter()
+            .map(|diagnostic| diagnostic.message.as_str())
+            .collect::<Vec<&str>>();
+        assert_eq!(
+            messages,
+            [
+                "12288 bytes of PRGROM1 missing from input",
+                "8192 bytes of CHRROM0 missing from input"
+            ]
         );
+        let mut out = Vec::new();
+        d.write(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        // each segment keeps the size the header gives it
+        assert!(out.contains(
+            ".segment \"PRGROM1\"\n\
+             .res 4096, $EA\n\
+             .res 12288                ; padding: 12288 bytes of PRGROM1 missing from input\n"
+        ));
+        assert!(out.contains(
+            ".segment \"CHRROM0\"\n\
+             .res 8192                 ; padding: 8192 bytes of CHRROM0 missing from input\n"
+        ));
+    }
 
-        return Result::Ok(());
+    #[test]
+    fn test_misc_rom() {
+        // NES 2.0 with one misc ROM, the 20 bytes after the PRG ROM
+        let mut rom = jump_indirect_rom(&[0x60]);
+        rom[7] = 0x08;
+        rom[14] = 1;
+        rom.extend(0..20u8);
+        let d = NesDisassembler::disassemble(rom).unwrap();
+        let mut out = Vec::new();
+        d.write(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.ends_with(
+            ".segment \"MISCROM\"\n\
+             miscrom:\n\
+             .byte $00, $01, $02, $03, $04, $05, $06, $07, $08, $09, $0A, $0B, $0C, $0D, $0E, $0F \
+             ; 1 misc ROM(s), 20 bytes\n\
+             .byte $10, $11, $12, $13\n"
+        ));
+        assert!(!d
+            .diagnostics()
+            .iter()
+            .any(|diagnostic| diagnostic.message.contains("missing from input")));
     }
 
-    fn parse_chr_rom(&mut self) -> Result<(), DisassembleError> {
-        let chr_rom_start_addr =
-            NES_HEADER_LENGTH + ((self.prg_rom_count as usize) * NES_PRG_ROM_PAGE_LENGTH);
-        let mut addr = chr_rom_start_addr;
-        for chr_rom_index in 0..self.chr_rom_count {
-            let chr_rom_start_addr = addr;
-            let chr_rom_end_addr = addr + NES_CHR_ROM_PAGE_LENGTH;
-            while addr < chr_rom_end_addr {
-                let mut bytes = Vec::new();
-                for i in 0..16 {
-                    let old_value = self.d.code.take(addr + i)?;
-                    bytes.push(old_value.asm_code);
-                }
-                // TODO create .neschr with values split out to visualize
-                self.d.code.set(
-                    addr,
-                    Statement {
-                        asm_code: AsmCode::DataSeq(bytes),
-                        comment: Option::None,
-                        segment: Option::None,
-                        label: Option::None,
-                    },
-                )?;
-                addr += 16;
-            }
-            self.d.code.set_segment(
-                chr_rom_start_addr,
-                format!("CHRROM{}", chr_rom_index).as_str(),
+    #[test]
+    fn test_source_map() {
+        // lda #$00, rts
+        let d = NesDisassembler::disassemble(jump_indirect_rom(&[0xa9, 0x00, 0x60])).unwrap();
+        let mut out = Vec::new();
+        let mut map = Vec::new();
+        d.write_with_source_map(&mut out, &mut map, Option::Some("test.s"))
+            .unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let lines = out.lines().collect::<Vec<&str>>();
+        let map: serde_json::Value = serde_json::from_slice(&map).unwrap();
+        assert_eq!(map["file"], "test.s");
+        let entries = map["entries"].as_array().unwrap();
+        let line_of = |offset: usize| {
+            let entry = entries
+                .iter()
+                .find(|entry| entry["offset"] == offset)
+                .unwrap();
+            return (
+                lines[entry["line"].as_u64().unwrap() as usize - 1],
+                entry["addr"].as_u64(),
             );
-        }
-        return Result::Ok(());
+        };
+        assert_eq!(line_of(16), ("    lda #$00", Option::Some(0x8000)));
+        assert_eq!(line_of(18), ("    rts", Option::Some(0x8002)));
+        assert_eq!(line_of(16 + 0x20), ("    rti", Option::Some(0x8020)));
+        // the header has no CPU address
+        let (line, addr) = line_of(4);
+        assert!(line.starts_with(".byte 1 "));
+        assert_eq!(addr, Option::None);
+        // lines only grow with the offsets of the statements on them
+        let lines = entries
+            .iter()
+            .map(|entry| entry["line"].as_u64().unwrap())
+            .collect::<Vec<u64>>();
+        assert!(lines.windows(2).all(|pair| pair[0] <= pair[1]));
     }
 
-    fn disassemble_entry_points(&mut self) -> Result<(), DisassembleError> {
-        let mut offset = NES_HEADER_LENGTH;
-        for prg_rom_idx in 0..self.prg_rom_count {
-            let nmi = self.decode_vector(offset + NES_PRG_ROM_PAGE_LENGTH - 6, "NMI")?;
-            let reset = self.decode_vector(offset + NES_PRG_ROM_PAGE_LENGTH - 4, "RESET")?;
-            let irq = self.decode_vector(offset + NES_PRG_ROM_PAGE_LENGTH - 2, "IRQ")?;
-
-            let addr_to_offset_fn = |a: u16| {
-                let mut addr = (a as usize) - NES_PRG_ROM_START_ADDRESS + NES_HEADER_LENGTH;
-                // TODO I think this should only happen if prg rom pages are mirrored
-                if addr > NES_PRG_ROM_PAGE_LENGTH {
-                    addr = addr - NES_PRG_ROM_PAGE_LENGTH;
-                }
-                return addr as usize;
-            };
+    #[test]
+    fn test_inline_small_tables() {
+        let at_most = (0..MAX_INLINE_TABLE_LENGTH as u8).collect::<Vec<u8>>();
+        let longer = (0x40..0x40 + MAX_INLINE_TABLE_LENGTH as u8 + 1).collect::<Vec<u8>>();
+        let rom = rom_with(&[
+            // ldx #$00, lda $8040,x, jsr $8100, jmp *
+            (
+                0x8000,
+                &[
+                    0xa2, 0x00, 0xbd, 0x40, 0x80, 0x20, 0x00, 0x81, 0x4c, 0x08, 0x80,
+                ],
+            ),
+            (0x8040, &at_most),
+            (0x8040 + at_most.len(), &longer),
+            // ldx #$00, lda $8060,x, rts
+            (0x8100, &[0xa2, 0x00, 0xbd, 0x60, 0x80, 0x60]),
+            (0x8120, &[0x40]),
+            (0xbffa, &[0x20, 0x81, 0x00, 0x80, 0x20, 0x81]),
+        ]);
+        let d = NesDisassembler::disassemble_with_options(
+            rom,
+            &AnalysisOptions {
+                inline_tables: true,
+                ..AnalysisOptions::default()
+            },
+        )
+        .unwrap();
+        let mut out = Vec::new();
+        d.write(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        // the table of the limit's length follows the routine reading it
+        assert!(out.contains(
+            "    jmp prgrom0_8008\n\
+             prgrom0_8040:\n\
+             .byte $00, $01, $02, $03, $04, $05, $06, $07, $08, $09, $0A, $0B, $0C, $0D, $0E, $0F \
+             ; moved from $8040\n\
+             .byte $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $1A, $1B, $1C, $1D, $1E, $1F\n\
+             .res 53, $FF\n\
+             prgrom0_8060:\n\
+             .byte $40\n"
+        ));
+        // the longer one stays where it was
+        assert!(
+            out.contains(".byte $60\n.res 127, $FF             ; padding, aligns $8100 to $100\n")
+        );
+        assert!(out.contains("    lda prgrom0_8060,x\n    rts\n.res 26, $FF"));
+    }
 
-            let offset_to_addr_fn = |offset: usize| {
-                return (offset - NES_HEADER_LENGTH + NES_PRG_ROM_START_ADDRESS) as u16;
-            };
+    #[test]
+    fn test_rom_writes() {
+        let mut rom = vec![0xff; 16 + 0x4000];
+        rom[..16].copy_from_slice(&[0x4e, 0x45, 0x53, 0x1a, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        // lda #$00, jsr $8006, rts, sta $8000, rts
+        rom[16..26].copy_from_slice(&[0xa9, 0x00, 0x20, 0x06, 0x80, 0x60, 0x8d, 0x00, 0x80, 0x60]);
+        rom[16 + 0x3ffa..].copy_from_slice(&[0x00, 0x80, 0x00, 0x80, 0x00, 0x80]);
+        let d = NesDisassembler::disassemble(rom.clone()).unwrap();
+        let writes = d
+            .diagnostics()
+            .iter()
+            .filter(|diag| diag.message.contains("writes ROM"))
+            .map(|diag| diag.message.as_str())
+            .collect::<Vec<&str>>();
+        assert_eq!(
+            writes,
+            [
+                "$8006 in prgrom0_8006 writes ROM at $8000, mapper 0 has no registers there, \
+              likely data decoded as code"
+            ]
+        );
 
-            self.d.disassemble(
-                nmi,
-                "nmi",
-                format!("prgrom{}", prg_rom_idx).as_str(),
-                &addr_to_offset_fn,
-                &offset_to_addr_fn,
-            )?;
-            self.d.disassemble(
-                reset,
-                "reset",
-                format!("prgrom{}", prg_rom_idx).as_str(),
-                &addr_to_offset_fn,
-                &offset_to_addr_fn,
-            )?;
-            self.d.disassemble(
-                irq,
-                "irq",
-                format!("prgrom{}", prg_rom_idx).as_str(),
-                &addr_to_offset_fn,
-                &offset_to_addr_fn,
-            )?;
+        // UxROM switches banks with that write
+        rom[6] = 0x20;
+        let d = NesDisassembler::disassemble(rom).unwrap();
+        assert!(!d
+            .diagnostics()
+            .iter()
+            .any(|diag| diag.message.contains("writes ROM")));
+    }
 
-            self.d
-                .code
-                .set_segment(offset, format!("PRGROM{}", prg_rom_idx).as_str());
+    #[test]
+    fn test_vector_tables() {
+        let d = NesDisassembler::disassemble(vector_table_rom()).unwrap();
+        assert!(d
+            .diagnostics()
+            .iter()
+            .any(|diag| diag.message.contains("--vector-table '$8020:3'")));
 
-            offset += NES_PRG_ROM_PAGE_LENGTH;
-        }
+        let opts = AnalysisOptions {
+            vector_tables: vec!["$8020:3".to_string()],
+            ..Default::default()
+        };
+        let d = NesDisassembler::disassemble_with_options(vector_table_rom(), &opts).unwrap();
+        let mut out = Vec::new();
+        d.write(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("prgrom0_vectors_8020:\n.word $8026, $8032, $803E\n"));
+        assert!(out.contains("prgrom0_vector_8020_2:\n    lda #$01\n"));
+        assert!(!d
+            .diagnostics()
+            .iter()
+            .any(|diag| diag.message.contains("--vector-table")));
 
-        return Result::Ok(());
+        let opts = AnalysisOptions {
+            vector_tables: vec!["$8020".to_string()],
+            ..Default::default()
+        };
+        assert!(NesDisassembler::disassemble_with_options(vector_table_rom(), &opts).is_err());
     }
 
-    fn decode_vector(&mut self, offset: usize, name: &str) -> Result<u16, DisassembleError> {
-        let low = self.d.code.take(offset)?.asm_code.to_u8()? as u16;
-        let high = self.d.code.take(offset + 1)?.asm_code.to_u8()? as u16;
-        let addr = low | (high << 8);
-        self.d
-            .code
-            .replace(offset..offset + 2, AsmCode::DataHexU16(addr))?;
-        self.d.code.set_comment(offset, name);
-        return Result::Ok(addr);
+    #[test]
+    fn test_signatures_file() {
+        let mut rom = vec![0xff; 16 + 0x4000];
+        rom[..16].copy_from_slice(&[0x4e, 0x45, 0x53, 0x1a, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        // jsr $8006, jmp $8000, then lda #$00, sta $42, rts
+        rom[16..25].copy_from_slice(&[0x20, 0x06, 0x80, 0x4c, 0x00, 0x80, 0xa9, 0x00, 0x85]);
+        rom[25..27].copy_from_slice(&[0x42, 0x60]);
+        rom[16 + 0x3ffa..].copy_from_slice(&[0x00, 0x80, 0x00, 0x80, 0x00, 0x80]);
+        let opts = AnalysisOptions {
+            signatures: vec![("fade".to_string(), "a9 00 85 vol 60".to_string())],
+            ..Default::default()
+        };
+        let d = NesDisassembler::disassemble_with_options(rom, &opts).unwrap();
+        let mut out = Vec::new();
+        d.write(&mut out).unwrap();
+        let asm = String::from_utf8(out).unwrap();
+        assert!(asm.contains("    jsr fade\n"), "{}", asm);
+        assert!(
+            asm.contains("fade:\n    lda #$00\n    sta vol\n"),
+            "{}",
+            asm
+        );
     }
 }
@@ -0,0 +1,615 @@
+use serde::Serialize;
+use std::fmt;
+
+use super::{hexdump::hexdump, DisassembleError};
+
+// https://www.nesdev.org/wiki/INES
+// https://www.nesdev.org/wiki/NES_2.0
+pub const NES_HEADER_LENGTH: usize = 16;
+pub const NES_PRG_ROM_PAGE_LENGTH: usize = 16 * 1024;
+pub const NES_CHR_ROM_PAGE_LENGTH: usize = 8 * 1024;
+pub const NES_TRAINER_LENGTH: usize = 512;
+/// How a header with garbage after flags 6 is read, see [`HeaderFormat::ArchaicINes`].
+pub const ARCHAIC_INES_NOTE: &str = "bytes 7 to 15 of the header look like garbage, read it \
+    as an archaic iNES header: only flags 6 counts and the mapper is its high nibble";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HeaderFormat {
+    #[serde(rename = "ines")]
+    INes,
+    /// iNES from before byte 7 was used, often with a ripper's name like
+    /// "DiskDude!" in bytes 7 to 15
+    #[serde(rename = "archaic_ines")]
+    ArchaicINes,
+    Nes2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Mirroring {
+    Horizontal,
+    Vertical,
+    FourScreen,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConsoleType {
+    Nes,
+    VsSystem,
+    Playchoice10,
+    Extended,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Timing {
+    Ntsc,
+    Pal,
+    MultiRegion,
+    Dendy,
+}
+
+/// Decoded iNES / NES 2.0 header. Sizes are in bytes; fields only present in
+/// NES 2.0 headers are zero for iNES files.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct NesHeader {
+    pub format: HeaderFormat,
+    pub mapper: u16,
+    pub submapper: u8,
+    pub prg_rom_size: usize,
+    pub chr_rom_size: usize,
+    pub prg_ram_size: usize,
+    pub prg_nvram_size: usize,
+    pub chr_ram_size: usize,
+    pub chr_nvram_size: usize,
+    pub mirroring: Mirroring,
+    pub battery: bool,
+    pub trainer: bool,
+    pub console_type: ConsoleType,
+    pub timing: Timing,
+    pub misc_rom_count: u8,
+    pub default_expansion_device: u8,
+}
+
+impl NesHeader {
+    pub fn is_nes_file(data: &[u8]) -> bool {
+        return data.len() >= NES_HEADER_LENGTH && data[0..4] == [b'N', b'E', b'S', 0x1a];
+    }
+
+    pub fn parse(data: &[u8]) -> Result<NesHeader, DisassembleError> {
+        if !NesHeader::is_nes_file(data) {
+            return Result::Err(DisassembleError::ParseError(format!(
+                "invalid nes header, expected \"NES\" $1a\n{}",
+                hexdump(data, 0)
+            )));
+        }
+
+        let flags6 = data[6];
+        let flags7 = data[7];
+        let format = match flags7 & 0x0c {
+            0x08 => HeaderFormat::Nes2,
+            0x00 if data[12..NES_HEADER_LENGTH].iter().all(|b| *b == 0) => HeaderFormat::INes,
+            _ => HeaderFormat::ArchaicINes,
+        };
+        // everything after flags 6 may be garbage
+        let flags7 = if format == HeaderFormat::ArchaicINes {
+            0
+        } else {
+            flags7
+        };
+
+        let mirroring = if flags6 & 0x08 != 0 {
+            Mirroring::FourScreen
+        } else if flags6 & 0x01 != 0 {
+            Mirroring::Vertical
+        } else {
+            Mirroring::Horizontal
+        };
+        let console_type = match flags7 & 0x03 {
+            0 => ConsoleType::Nes,
+            1 => ConsoleType::VsSystem,
+            2 => ConsoleType::Playchoice10,
+            _ => ConsoleType::Extended,
+        };
+        let mut header = NesHeader {
+            format,
+            mapper: ((flags6 >> 4) | (flags7 & 0xf0)) as u16,
+            submapper: 0,
+            prg_rom_size: data[4] as usize * NES_PRG_ROM_PAGE_LENGTH,
+            chr_rom_size: data[5] as usize * NES_CHR_ROM_PAGE_LENGTH,
+            prg_ram_size: 0,
+            prg_nvram_size: 0,
+            chr_ram_size: 0,
+            chr_nvram_size: 0,
+            mirroring,
+            battery: flags6 & 0x02 != 0,
+            trainer: flags6 & 0x04 != 0,
+            console_type,
+            timing: Timing::Ntsc,
+            misc_rom_count: 0,
+            default_expansion_device: 0,
+        };
+
+        if format == HeaderFormat::Nes2 {
+            header.mapper |= ((data[8] & 0x0f) as u16) << 8;
+            header.submapper = data[8] >> 4;
+            header.prg_rom_size = rom_size(data[4], data[9] & 0x0f, NES_PRG_ROM_PAGE_LENGTH);
+            header.chr_rom_size = rom_size(data[5], data[9] >> 4, NES_CHR_ROM_PAGE_LENGTH);
+            header.prg_ram_size = shift_size(data[10] & 0x0f);
+            header.prg_nvram_size = shift_size(data[10] >> 4);
+            header.chr_ram_size = shift_size(data[11] & 0x0f);
+            header.chr_nvram_size = shift_size(data[11] >> 4);
+            header.timing = match data[12] & 0x03 {
+                0 => Timing::Ntsc,
+                1 => Timing::Pal,
+                2 => Timing::MultiRegion,
+                _ => Timing::Dendy,
+            };
+            header.misc_rom_count = data[14] & 0x03;
+            header.default_expansion_device = data[15] & 0x3f;
+        } else if format == HeaderFormat::INes {
+            // byte 8 is PRG RAM in 8k units, 0 meaning 8k for compatibility
+            header.prg_ram_size = (data[8].max(1) as usize) * 8 * 1024;
+        } else {
+            header.prg_ram_size = 8 * 1024;
+        }
+
+        return Result::Ok(header);
+    }
+
+    pub fn prg_rom_page_count(&self) -> usize {
+        return self.prg_rom_size / NES_PRG_ROM_PAGE_LENGTH;
+    }
+
+    pub fn chr_rom_page_count(&self) -> usize {
+        return self.chr_rom_size / NES_CHR_ROM_PAGE_LENGTH;
+    }
+
+    /// Whether the pattern tables are RAM the code fills, there being no CHR ROM.
+    pub fn has_chr_ram(&self) -> bool {
+        return self.chr_rom_size == 0;
+    }
+
+    /// file offset of the PRG ROM, after the trainer if there is one
+    pub fn prg_rom_offset(&self) -> usize {
+        if self.trainer {
+            return NES_HEADER_LENGTH + NES_TRAINER_LENGTH;
+        }
+        return NES_HEADER_LENGTH;
+    }
+
+    /// file offset just past the CHR ROM, where misc ROMs start
+    pub fn misc_rom_offset(&self) -> usize {
+        return self.prg_rom_offset() + self.prg_rom_size + self.chr_rom_size;
+    }
+
+    /// Misc ROMs have no size in the header, they take up the rest of the file.
+    pub fn misc_rom_size(&self, data_len: usize) -> usize {
+        if self.misc_rom_count == 0 {
+            return 0;
+        }
+        return data_len.saturating_sub(self.misc_rom_offset());
+    }
+
+    /// Switches an iNES header to NES 2.0. RAM iNES can only imply is made
+    /// explicit: battery backed PRG RAM becomes non-volatile and no CHR ROM
+    /// means 8k of CHR RAM.
+    pub fn upgrade_to_nes2(&mut self) {
+        if self.format == HeaderFormat::Nes2 {
+            return;
+        }
+        self.format = HeaderFormat::Nes2;
+        if self.battery {
+            self.prg_nvram_size = self.prg_ram_size;
+            self.prg_ram_size = 0;
+        }
+        if self.chr_rom_size == 0 {
+            self.chr_ram_size = NES_CHR_ROM_PAGE_LENGTH;
+        }
+    }
+
+    /// Sets the field named like in the JSON output, e.g. `mapper` to `4`.
+    /// Sizes are in bytes with an optional `k` suffix.
+    pub fn set(&mut self, field: &str, value: &str) -> Result<(), DisassembleError> {
+        let value = value.trim().to_lowercase().replace('-', "_");
+        match field.trim() {
+            "mapper" => match value.split_once('.') {
+                Option::Some((mapper, submapper)) => {
+                    self.mapper = parse_number(field, mapper)?;
+                    self.submapper = parse_number(field, submapper)?;
+                }
+                Option::None => self.mapper = parse_number(field, &value)?,
+            },
+            "submapper" => self.submapper = parse_number(field, &value)?,
+            "prg_rom_size" => self.prg_rom_size = parse_size(field, &value)?,
+            "chr_rom_size" => self.chr_rom_size = parse_size(field, &value)?,
+            "prg_ram_size" => self.prg_ram_size = parse_size(field, &value)?,
+            "prg_nvram_size" => self.prg_nvram_size = parse_size(field, &value)?,
+            "chr_ram_size" => self.chr_ram_size = parse_size(field, &value)?,
+            "chr_nvram_size" => self.chr_nvram_size = parse_size(field, &value)?,
+            "mirroring" => {
+                self.mirroring = match value.as_str() {
+                    "horizontal" => Mirroring::Horizontal,
+                    "vertical" => Mirroring::Vertical,
+                    "four_screen" => Mirroring::FourScreen,
+                    _ => return Result::Err(invalid_value(field, &value)),
+                }
+            }
+            "battery" => self.battery = parse_bool(field, &value)?,
+            "trainer" => self.trainer = parse_bool(field, &value)?,
+            "console_type" => {
+                self.console_type = match value.as_str() {
+                    "nes" => ConsoleType::Nes,
+                    "vs_system" => ConsoleType::VsSystem,
+                    "playchoice10" => ConsoleType::Playchoice10,
+                    "extended" => ConsoleType::Extended,
+                    _ => return Result::Err(invalid_value(field, &value)),
+                }
+            }
+            "timing" => {
+                self.timing = match value.as_str() {
+                    "ntsc" => Timing::Ntsc,
+                    "pal" => Timing::Pal,
+                    "multi_region" => Timing::MultiRegion,
+                    "dendy" => Timing::Dendy,
+                    _ => return Result::Err(invalid_value(field, &value)),
+                }
+            }
+            "misc_rom_count" => self.misc_rom_count = parse_number(field, &value)?,
+            "default_expansion_device" => {
+                self.default_expansion_device = parse_number(field, &value)?
+            }
+            _ => {
+                return Result::Err(DisassembleError::ParseError(format!(
+                    "unknown header field \"{}\"",
+                    field
+                )))
+            }
+        }
+        return Result::Ok(());
+    }
+
+    /// Writes the header over the first 16 bytes of `data`. NES 2.0 byte 13, the
+    /// Vs. System and extended console type, isn't modeled and is left alone. The
+    /// unused iNES bytes 9-15 are cleared, dumps often have junk there.
+    pub fn write_to(&self, data: &mut [u8]) -> Result<(), DisassembleError> {
+        if data.len() < NES_HEADER_LENGTH {
+            return Result::Err(DisassembleError::ParseError(
+                "no room for a nes header".to_string(),
+            ));
+        }
+        let mut flags6 = ((self.mapper & 0x0f) << 4) as u8;
+        flags6 |= match self.mirroring {
+            Mirroring::Horizontal => 0x00,
+            Mirroring::Vertical => 0x01,
+            Mirroring::FourScreen => 0x08,
+        };
+        if self.battery {
+            flags6 |= 0x02;
+        }
+        if self.trainer {
+            flags6 |= 0x04;
+        }
+        let mut flags7 = (self.mapper & 0xf0) as u8;
+        flags7 |= match self.console_type {
+            ConsoleType::Nes => 0,
+            ConsoleType::VsSystem => 1,
+            ConsoleType::Playchoice10 => 2,
+            ConsoleType::Extended => 3,
+        };
+
+        data[0..4].copy_from_slice(&[b'N', b'E', b'S', 0x1a]);
+        data[6] = flags6;
+        // archaic headers are written without their garbage
+        if self.format != HeaderFormat::Nes2 {
+            self.check_ines()?;
+            data[4] = (self.prg_rom_size / NES_PRG_ROM_PAGE_LENGTH) as u8;
+            data[5] = (self.chr_rom_size / NES_CHR_ROM_PAGE_LENGTH) as u8;
+            data[7] = flags7;
+            // 0 also means 8k
+            data[8] = match self.prg_ram_size {
+                0x2000 => 0,
+                size => (size / 0x2000) as u8,
+            };
+            data[9..NES_HEADER_LENGTH].fill(0);
+            return Result::Ok(());
+        }
+
+        if self.mapper > 0xfff || self.submapper > 0x0f {
+            return Result::Err(DisassembleError::ParseError(format!(
+                "mapper {}.{} doesn't fit in a NES 2.0 header",
+                self.mapper, self.submapper
+            )));
+        }
+        let (prg_lsb, prg_msb) =
+            encode_rom_size("prg_rom_size", self.prg_rom_size, NES_PRG_ROM_PAGE_LENGTH)?;
+        let (chr_lsb, chr_msb) =
+            encode_rom_size("chr_rom_size", self.chr_rom_size, NES_CHR_ROM_PAGE_LENGTH)?;
+        data[4] = prg_lsb;
+        data[5] = chr_lsb;
+        data[7] = flags7 | 0x08;
+        data[8] = (self.submapper << 4) | (self.mapper >> 8) as u8;
+        data[9] = (chr_msb << 4) | prg_msb;
+        data[10] = (encode_shift("prg_nvram_size", self.prg_nvram_size)? << 4)
+            | encode_shift("prg_ram_size", self.prg_ram_size)?;
+        data[11] = (encode_shift("chr_nvram_size", self.chr_nvram_size)? << 4)
+            | encode_shift("chr_ram_size", self.chr_ram_size)?;
+        data[12] = match self.timing {
+            Timing::Ntsc => 0,
+            Timing::Pal => 1,
+            Timing::MultiRegion => 2,
+            Timing::Dendy => 3,
+        };
+        if self.misc_rom_count > 3 || self.default_expansion_device > 0x3f {
+            return Result::Err(DisassembleError::ParseError(format!(
+                "{} misc ROMs or expansion device {} don't fit in a NES 2.0 header",
+                self.misc_rom_count, self.default_expansion_device
+            )));
+        }
+        data[14] = self.misc_rom_count;
+        data[15] = self.default_expansion_device;
+        return Result::Ok(());
+    }
+
+    /// Fails if a field can't be stored in an iNES header.
+    fn check_ines(&self) -> Result<(), DisassembleError> {
+        let nes2_only = [
+            ("submapper", self.submapper != 0),
+            ("prg_nvram_size", self.prg_nvram_size != 0),
+            ("chr_ram_size", self.chr_ram_size != 0),
+            ("chr_nvram_size", self.chr_nvram_size != 0),
+            ("timing", self.timing != Timing::Ntsc),
+            ("misc_rom_count", self.misc_rom_count != 0),
+            (
+                "default_expansion_device",
+                self.default_expansion_device != 0,
+            ),
+        ];
+        if let Option::Some((field, _)) = nes2_only.iter().find(|(_, set)| *set) {
+            return Result::Err(DisassembleError::ParseError(format!(
+                "{} needs a NES 2.0 header",
+                field
+            )));
+        }
+        let fits = self.mapper <= 0xff
+            && self.prg_rom_size.is_multiple_of(NES_PRG_ROM_PAGE_LENGTH)
+            && self.prg_rom_size / NES_PRG_ROM_PAGE_LENGTH <= 0xff
+            && self.chr_rom_size.is_multiple_of(NES_CHR_ROM_PAGE_LENGTH)
+            && self.chr_rom_size / NES_CHR_ROM_PAGE_LENGTH <= 0xff
+            && self.prg_ram_size.is_multiple_of(0x2000)
+            && self.prg_ram_size / 0x2000 <= 0xff;
+        if !fits {
+            return Result::Err(DisassembleError::ParseError(format!(
+                "mapper {} or ROM/RAM sizes don't fit in an iNES header",
+                self.mapper
+            )));
+        }
+        return Result::Ok(());
+    }
+}
+
+impl fmt::Display for HeaderFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            HeaderFormat::INes => write!(f, "iNES"),
+            HeaderFormat::ArchaicINes => write!(f, "archaic iNES"),
+            HeaderFormat::Nes2 => write!(f, "NES 2.0"),
+        };
+    }
+}
+
+impl fmt::Display for Mirroring {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            Mirroring::Horizontal => write!(f, "horizontal"),
+            Mirroring::Vertical => write!(f, "vertical"),
+            Mirroring::FourScreen => write!(f, "four-screen"),
+        };
+    }
+}
+
+impl fmt::Display for ConsoleType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            ConsoleType::Nes => write!(f, "NES/Famicom"),
+            ConsoleType::VsSystem => write!(f, "Vs. System"),
+            ConsoleType::Playchoice10 => write!(f, "PlayChoice-10"),
+            ConsoleType::Extended => write!(f, "extended"),
+        };
+    }
+}
+
+impl fmt::Display for Timing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            Timing::Ntsc => write!(f, "NTSC"),
+            Timing::Pal => write!(f, "PAL"),
+            Timing::MultiRegion => write!(f, "multi-region"),
+            Timing::Dendy => write!(f, "Dendy"),
+        };
+    }
+}
+
+/// NES 2.0 ROM size, either a page count or, if the MSB nibble is $f, the
+/// exponent-multiplier form `2^E * (MM * 2 + 1)`.
+fn rom_size(lsb: u8, msb: u8, page_length: usize) -> usize {
+    if msb == 0x0f {
+        let exponent = (lsb >> 2) as u32;
+        let multiplier = (lsb & 0x03) as usize * 2 + 1;
+        return 2usize.pow(exponent) * multiplier;
+    }
+    return (((msb as usize) << 8) | lsb as usize) * page_length;
+}
+
+fn shift_size(shift: u8) -> usize {
+    if shift == 0 {
+        return 0;
+    }
+    return 64 << shift;
+}
+
+/// Inverse of [`rom_size`], preferring the page count form.
+fn encode_rom_size(
+    field: &str,
+    size: usize,
+    page_length: usize,
+) -> Result<(u8, u8), DisassembleError> {
+    let pages = size / page_length;
+    if size.is_multiple_of(page_length) && pages <= 0xeff {
+        return Result::Ok(((pages & 0xff) as u8, (pages >> 8) as u8));
+    }
+    for multiplier in 0..4 {
+        let odd = multiplier * 2 + 1;
+        if size.is_multiple_of(odd) && (size / odd).is_power_of_two() {
+            let exponent = (size / odd).trailing_zeros() as usize;
+            if exponent < 64 {
+                return Result::Ok((((exponent << 2) | multiplier) as u8, 0x0f));
+            }
+        }
+    }
+    return Result::Err(invalid_value(field, &size.to_string()));
+}
+
+/// Inverse of [`shift_size`].
+fn encode_shift(field: &str, size: usize) -> Result<u8, DisassembleError> {
+    if size == 0 {
+        return Result::Ok(0);
+    }
+    return (1..=15)
+        .find(|shift| 64 << shift == size)
+        .ok_or_else(|| invalid_value(field, &size.to_string()));
+}
+
+fn parse_number<T: std::str::FromStr>(field: &str, value: &str) -> Result<T, DisassembleError> {
+    return value.parse().map_err(|_| invalid_value(field, value));
+}
+
+fn parse_size(field: &str, value: &str) -> Result<usize, DisassembleError> {
+    return match value.strip_suffix('k') {
+        Option::Some(k) => Result::Ok(parse_number::<usize>(field, k)? * 1024),
+        Option::None => parse_number(field, value),
+    };
+}
+
+fn parse_bool(field: &str, value: &str) -> Result<bool, DisassembleError> {
+    return match value {
+        "yes" | "true" | "1" => Result::Ok(true),
+        "no" | "false" | "0" => Result::Ok(false),
+        _ => Result::Err(invalid_value(field, value)),
+    };
+}
+
+fn invalid_value(field: &str, value: &str) -> DisassembleError {
+    return DisassembleError::ParseError(format!("invalid {} \"{}\"", field, value));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ines_header() {
+        let data = [
+            b'N', b'E', b'S', 0x1a, 2, 1, 0x13, 0x00, 0, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        let header = NesHeader::parse(&data).unwrap();
+        assert_eq!(header.format, HeaderFormat::INes);
+        assert_eq!(header.mapper, 1);
+        assert_eq!(header.prg_rom_size, 32 * 1024);
+        assert_eq!(header.chr_rom_size, 8 * 1024);
+        assert_eq!(header.prg_ram_size, 8 * 1024);
+        assert_eq!(header.mirroring, Mirroring::Vertical);
+        assert!(header.battery);
+        assert!(!header.trainer);
+    }
+
+    #[test]
+    fn test_parse_ines_header_with_trainer() {
+        let data = [
+            b'N', b'E', b'S', 0x1a, 1, 1, 0x04, 0x00, 0, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        let header = NesHeader::parse(&data).unwrap();
+        assert!(header.trainer);
+        assert_eq!(header.prg_rom_offset(), 16 + 512);
+        assert_eq!(header.misc_rom_offset(), 16 + 512 + 16 * 1024 + 8 * 1024);
+    }
+
+    #[test]
+    fn test_parse_archaic_ines_header() {
+        let data = [
+            b'N', b'E', b'S', 0x1a, 2, 1, 0x41, b'D', b'i', b's', b'k', b'D', b'u', b'd', b'e',
+            b'!',
+        ];
+        let header = NesHeader::parse(&data).unwrap();
+        assert_eq!(header.format, HeaderFormat::ArchaicINes);
+        assert_eq!(header.mapper, 4);
+        assert_eq!(header.console_type, ConsoleType::Nes);
+        assert_eq!(header.prg_ram_size, 8 * 1024);
+        assert_eq!(header.mirroring, Mirroring::Vertical);
+
+        let mut written = data;
+        header.write_to(&mut written).unwrap();
+        assert_eq!(
+            written,
+            [b'N', b'E', b'S', 0x1a, 2, 1, 0x41, 0, 0, 0, 0, 0, 0, 0, 0, 0]
+        );
+        assert_eq!(
+            NesHeader::parse(&written).unwrap().format,
+            HeaderFormat::INes
+        );
+    }
+
+    #[test]
+    fn test_parse_nes2_header() {
+        let data = [
+            b'N', b'E', b'S', 0x1a, 0x08, 0x00, 0x40, 0x08, 0x21, 0x00, 0x07, 0x07, 0x01, 0, 0x01,
+            0x01,
+        ];
+        let header = NesHeader::parse(&data).unwrap();
+        assert_eq!(header.format, HeaderFormat::Nes2);
+        assert_eq!(header.mapper, 0x104);
+        assert_eq!(header.submapper, 2);
+        assert_eq!(header.prg_rom_size, 128 * 1024);
+        assert_eq!(header.prg_ram_size, 8 * 1024);
+        assert_eq!(header.chr_ram_size, 8 * 1024);
+        assert_eq!(header.timing, Timing::Pal);
+        assert_eq!(header.misc_rom_count, 1);
+        assert_eq!(header.misc_rom_size(16 + 128 * 1024 + 100), 100);
+        assert_eq!(header.default_expansion_device, 1);
+
+        let mut written = [0; NES_HEADER_LENGTH];
+        header.write_to(&mut written).unwrap();
+        assert_eq!(written, data);
+    }
+
+    #[test]
+    fn test_edit_header() {
+        let mut data = [
+            b'N', b'E', b'S', 0x1a, 2, 0, 0x02, 0, 0, 0, 0, 0, b'D', b'u', b'd', b'e',
+        ];
+        let mut header = NesHeader::parse(&data).unwrap();
+        header.set("mapper", "4").unwrap();
+        header.set("mirroring", "vertical").unwrap();
+        header.write_to(&mut data).unwrap();
+        assert_eq!(
+            data,
+            [b'N', b'E', b'S', 0x1a, 2, 0, 0x43, 0, 0, 0, 0, 0, 0, 0, 0, 0]
+        );
+
+        assert!(header.set("timing", "pal").is_ok());
+        assert!(header.write_to(&mut data).is_err());
+        header.upgrade_to_nes2();
+        header.write_to(&mut data).unwrap();
+        let upgraded = NesHeader::parse(&data).unwrap();
+        assert_eq!(upgraded.format, HeaderFormat::Nes2);
+        assert_eq!(upgraded.timing, Timing::Pal);
+        assert_eq!(upgraded.prg_ram_size, 0);
+        assert_eq!(upgraded.prg_nvram_size, 8 * 1024);
+        assert_eq!(upgraded.chr_ram_size, 8 * 1024);
+
+        assert!(header.set("mirroring", "diagonal").is_err());
+        assert!(header.set("color", "red").is_err());
+    }
+}
@@ -0,0 +1,122 @@
+use std::{fmt, str::FromStr};
+
+use serde::Serialize;
+
+use super::AnalysisOptions;
+
+/// An analysis pass `--passes` can leave out or move. The passes decoding code
+/// come first by default, the routines are labeled and named before the first of
+/// the others runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pass {
+    /// follow the code from the vectors and the trainer
+    Trace,
+    /// disassemble unreached PRG ROM that scores as code
+    Sweep,
+    /// write unclaimed bytes reading as text as strings
+    Strings,
+    /// label what pointers set up a byte at a time and indexed reads point at, and
+    /// the tiles copied to CHR RAM
+    Pointers,
+    /// comment counted loops and 16-bit operations, sizing the tables loops read
+    Idioms,
+}
+
+pub const ALL_PASSES: [Pass; 5] = [
+    Pass::Trace,
+    Pass::Sweep,
+    Pass::Strings,
+    Pass::Pointers,
+    Pass::Idioms,
+];
+
+impl Pass {
+    /// The passes run without `--passes`, in order, the ones with flags of their
+    /// own only when they are set.
+    pub fn defaults(opts: &AnalysisOptions) -> Vec<Pass> {
+        let mut passes = vec![Pass::Trace];
+        if opts.sweep {
+            passes.push(Pass::Sweep);
+        }
+        passes.extend([Pass::Idioms, Pass::Pointers]);
+        if opts.text {
+            passes.push(Pass::Strings);
+        }
+        return passes;
+    }
+
+    /// Whether the pass decodes code the routines are labeled in.
+    pub fn decodes_code(&self) -> bool {
+        return matches!(self, Pass::Trace | Pass::Sweep);
+    }
+}
+
+impl FromStr for Pass {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        return ALL_PASSES
+            .into_iter()
+            .find(|pass| pass.to_string() == s)
+            .ok_or_else(|| {
+                format!(
+                    "unknown pass \"{}\", expected trace, sweep, strings, pointers or idioms",
+                    s
+                )
+            });
+    }
+}
+
+impl fmt::Display for Pass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            Pass::Trace => write!(f, "trace"),
+            Pass::Sweep => write!(f, "sweep"),
+            Pass::Strings => write!(f, "strings"),
+            Pass::Pointers => write!(f, "pointers"),
+            Pass::Idioms => write!(f, "idioms"),
+        };
+    }
+}
+
+/// What a pass changed, counted over the whole input.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct PassStats {
+    /// bytes decoded as something other than plain bytes
+    pub classified: usize,
+    pub labels: usize,
+    pub comments: usize,
+}
+
+impl PassStats {
+    /// What was added since `before`.
+    pub fn since(&self, before: &PassStats) -> PassStats {
+        return PassStats {
+            classified: self.classified.saturating_sub(before.classified),
+            labels: self.labels.saturating_sub(before.labels),
+            comments: self.comments.saturating_sub(before.comments),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_passes() {
+        for pass in ALL_PASSES {
+            assert_eq!(pass.to_string().parse::<Pass>(), Result::Ok(pass));
+        }
+        assert!("loops".parse::<Pass>().is_err());
+
+        let opts = AnalysisOptions {
+            text: true,
+            ..AnalysisOptions::default()
+        };
+        assert_eq!(
+            Pass::defaults(&opts),
+            [Pass::Trace, Pass::Idioms, Pass::Pointers, Pass::Strings]
+        );
+    }
+}
@@ -0,0 +1,184 @@
+use std::{
+    fmt,
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+};
+
+use crate::decode::AddressingMode;
+
+use super::{cpu::CpuVariant, dialect::Dialect, graphics::TileFormat};
+
+/// How data is used, for naming it after what it is for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AccessPattern {
+    /// ROM read with one of `modes` in a loop that also stores into one of
+    /// `stores`
+    ReadInLoopStoringTo {
+        modes: &'static [AddressingMode],
+        stores: &'static [RangeInclusive<u16>],
+    },
+    /// RAM only written, some of it indexed, by the code RESET runs before its
+    /// main loop, and read after
+    WrittenOnlyDuringInit,
+}
+
+/// Data accessed the way `pattern` says is named `prefix` and its address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NamingRule {
+    pub prefix: &'static str,
+    pub pattern: AccessPattern,
+}
+
+const NES_NAMING_RULES: &[NamingRule] = &[
+    // the page usually copied to OAM with OAMDMA, or OAMDATA itself
+    NamingRule {
+        prefix: "oam_data_",
+        pattern: AccessPattern::ReadInLoopStoringTo {
+            modes: &[AddressingMode::AbsoluteY],
+            stores: &[0x0200..=0x02ff, 0x2004..=0x2004],
+        },
+    },
+    NamingRule {
+        prefix: "ppu_data_",
+        pattern: AccessPattern::ReadInLoopStoringTo {
+            modes: &[AddressingMode::AbsoluteX, AddressingMode::AbsoluteY],
+            stores: &[0x2007..=0x2007],
+        },
+    },
+    NamingRule {
+        prefix: "init_table_",
+        pattern: AccessPattern::WrittenOnlyDuringInit,
+    },
+];
+
+/// Systems whose ROMs are recognized, with the conventions their communities
+/// write assembly in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Platform {
+    Nes,
+    Atari2600,
+    C64,
+}
+
+impl Platform {
+    /// The platform a ROM file is for, going by its extension.
+    pub fn from_path(path: &Path) -> Option<Platform> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        return match extension.as_str() {
+            "nes" => Option::Some(Platform::Nes),
+            "a26" => Option::Some(Platform::Atari2600),
+            "prg" | "d64" | "crt" => Option::Some(Platform::C64),
+            _ => Option::None,
+        };
+    }
+
+    pub fn cpu_variant(&self) -> CpuVariant {
+        return match self {
+            Platform::Nes => CpuVariant::Ricoh2A03,
+            // a 6507, a 6502 with fewer address lines
+            Platform::Atari2600 => CpuVariant::Mos6502,
+            Platform::C64 => CpuVariant::Mos6510,
+        };
+    }
+
+    /// assembler the output is written for unless another is asked for
+    pub fn dialect(&self) -> Dialect {
+        return match self {
+            Platform::Nes => Dialect::Ca65,
+            Platform::Atari2600 => Dialect::Dasm,
+            Platform::C64 => Dialect::Acme,
+        };
+    }
+
+    /// Zero page the program has to itself.
+    pub fn zero_page(&self) -> RangeInclusive<u16> {
+        return match self {
+            Platform::Nes => 0x00..=0xff,
+            // all of its 128 bytes of RAM, the stack included
+            Platform::Atari2600 => 0x80..=0xff,
+            // the 6510's I/O port is at $00 and $01
+            Platform::C64 => 0x02..=0xff,
+        };
+    }
+
+    /// Where the cartridge ROM is mapped, ending with the vectors, for platforms
+    /// running from one.
+    pub fn cartridge_rom(&self) -> Option<RangeInclusive<u16>> {
+        return match self {
+            Platform::Nes => Option::Some(0x8000..=0xffff),
+            // the 4k the 6507's 13 address lines see
+            Platform::Atari2600 => Option::Some(0xf000..=0xffff),
+            Platform::C64 => Option::None,
+        };
+    }
+
+    /// How data is named by the way it is used, the first rule matching wins.
+    pub fn naming_rules(&self) -> &'static [NamingRule] {
+        return match self {
+            Platform::Nes => NES_NAMING_RULES,
+            Platform::Atari2600 | Platform::C64 => &[],
+        };
+    }
+
+    /// How the tiles of its graphics ROM are laid out, if it has tiles.
+    pub fn tile_format(&self) -> Option<TileFormat> {
+        return match self {
+            Platform::Nes => Option::Some(TileFormat::NES),
+            // player graphics are a byte a row of any height
+            Platform::Atari2600 => Option::None,
+            // characters are wherever the program points the VIC-II, which isn't
+            // followed
+            Platform::C64 => Option::None,
+        };
+    }
+
+    /// Where the assembly for `in_file` goes when the output is the directory
+    /// `dir`: the ROM's name with the extension of its dialect's files.
+    pub fn out_file(&self, dir: &Path, in_file: &Path) -> PathBuf {
+        let stem = in_file.file_stem().unwrap_or(in_file.as_os_str());
+        return dir
+            .join(stem)
+            .with_extension(self.dialect().writer().extension());
+    }
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            Platform::Nes => write!(f, "NES"),
+            Platform::Atari2600 => write!(f, "Atari 2600"),
+            Platform::C64 => write!(f, "C64"),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_platform() {
+        let platform = Platform::from_path(Path::new("roms/Game.NES")).unwrap();
+        assert_eq!(platform, Platform::Nes);
+        assert_eq!(platform.cpu_variant(), CpuVariant::Ricoh2A03);
+        assert_eq!(
+            platform.out_file(Path::new("out"), Path::new("roms/Game.NES")),
+            PathBuf::from("out/Game.s")
+        );
+        let platform = Platform::from_path(Path::new("pitfall.a26")).unwrap();
+        assert_eq!(platform.dialect(), Dialect::Dasm);
+        assert_eq!(
+            platform.out_file(Path::new("out"), Path::new("pitfall.a26")),
+            PathBuf::from("out/pitfall.asm")
+        );
+        assert!(platform.naming_rules().is_empty());
+        assert_eq!(platform.tile_format(), Option::None);
+        let prefixes = Platform::Nes
+            .naming_rules()
+            .iter()
+            .map(|rule| rule.prefix)
+            .collect::<Vec<&str>>();
+        assert_eq!(prefixes, ["oam_data_", "ppu_data_", "init_table_"]);
+        assert_eq!(Platform::from_path(Path::new("game.bin")), Option::None);
+    }
+}
@@ -0,0 +1,96 @@
+use super::{
+    address::CpuAddr,
+    passes::{Pass, PassStats},
+};
+
+/// Told how far the analysis got, so programs embedding the disassembler can
+/// show progress on big ROMs. Every method does nothing unless overridden.
+pub trait Progress {
+    /// code is about to be followed from the entry point `name` at `addr`
+    fn entry_point_started(&mut self, _name: &str, _addr: CpuAddr) {}
+
+    /// the entry points of PRG ROM bank `bank` of `banks` were followed
+    fn bank_completed(&mut self, _bank: usize, _banks: usize) {}
+
+    /// `classified` of the `total` bytes of the input are decoded as something
+    /// other than plain bytes
+    fn bytes_classified(&mut self, _classified: usize, _total: usize) {}
+
+    /// the analysis pass `pass` ran, changing what `stats` counts
+    fn pass_completed(&mut self, _pass: Pass, _stats: &PassStats) {}
+}
+
+/// Ignores the progress.
+pub struct NoProgress;
+
+impl Progress for NoProgress {}
+
+/// Writes the progress to stderr, for `--progress`.
+pub(crate) struct StderrProgress;
+
+impl Progress for StderrProgress {
+    fn bank_completed(&mut self, bank: usize, banks: usize) {
+        eprintln!("bank {}/{} followed", bank + 1, banks);
+    }
+
+    fn bytes_classified(&mut self, classified: usize, total: usize) {
+        let percent = classified as f64 * 100.0 / total.max(1) as f64;
+        eprintln!("{:.1}% of the bytes classified", percent);
+    }
+
+    fn pass_completed(&mut self, pass: Pass, stats: &PassStats) {
+        eprintln!(
+            "{} pass: {} bytes classified, {} labels and {} comments added",
+            pass, stats.classified, stats.labels, stats.comments
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disassemble::{AnalysisOptions, NesDisassembler};
+
+    #[derive(Default)]
+    struct Recorder {
+        events: Vec<String>,
+    }
+
+    impl Progress for Recorder {
+        fn entry_point_started(&mut self, name: &str, addr: CpuAddr) {
+            self.events.push(format!("{} {:04x}", name, addr.0));
+        }
+
+        fn bank_completed(&mut self, bank: usize, banks: usize) {
+            self.events.push(format!("bank {}/{}", bank, banks));
+        }
+
+        fn bytes_classified(&mut self, classified: usize, _total: usize) {
+            self.events.push(format!("{} classified", classified));
+        }
+    }
+
+    #[test]
+    fn test_progress() {
+        let mut rom = vec![0; 16 + 0x4000];
+        rom[..5].copy_from_slice(&[0x4e, 0x45, 0x53, 0x1a, 1]);
+        // rti at $8000, every vector pointing at it
+        rom[16] = 0x40;
+        for vector in [0x3ffa, 0x3ffc, 0x3ffe] {
+            rom[16 + vector + 1] = 0x80;
+        }
+        let mut recorder = Recorder::default();
+        NesDisassembler::disassemble_with_progress(rom, &AnalysisOptions::default(), &mut recorder)
+            .unwrap();
+        assert_eq!(
+            recorder.events[..5],
+            [
+                "prgrom0_nmi 8000",
+                "prgrom0_reset 8000",
+                "prgrom0_irq 8000",
+                "bank 0/1",
+                "23 classified"
+            ]
+        );
+    }
+}
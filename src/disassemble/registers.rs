@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+
+use crate::decode::{AddressingMode, DecodedInstruction};
+
+/// Values of A, X and Y and of the memory stored to that are known while code is
+/// followed in a straight line, enough to tell where a `jmp (ptr)` goes when the
+/// pointer was set with immediate loads right before it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KnownValues {
+    a: Option<u8>,
+    x: Option<u8>,
+    y: Option<u8>,
+    memory: HashMap<u16, u8>,
+}
+
+impl KnownValues {
+    /// the byte at `addr` if an earlier store wrote a known value there
+    pub fn read(&self, addr: u16) -> Option<u8> {
+        return self.memory.get(&addr).copied();
+    }
+
+    /// Forgets everything, where code can be reached from elsewhere or a
+    /// subroutine may have changed it.
+    pub fn clear(&mut self) {
+        *self = KnownValues::default();
+    }
+
+    /// Updates the values with what `instr` does.
+    pub fn step(&mut self, instr: &DecodedInstruction) {
+        let direct = matches!(
+            instr.mode,
+            AddressingMode::ZeroPage | AddressingMode::Absolute
+        );
+        let loaded = match instr.mode {
+            AddressingMode::Immediate => Option::Some(instr.operand as u8),
+            _ if direct => self.read(instr.operand),
+            _ => Option::None,
+        };
+        match instr.mnemonic {
+            "lda" => self.a = loaded,
+            "ldx" => self.x = loaded,
+            "ldy" => self.y = loaded,
+            "tax" => self.x = self.a,
+            "tay" => self.y = self.a,
+            "txa" => self.a = self.x,
+            "tya" => self.a = self.y,
+            "inx" => self.x = self.x.map(|x| x.wrapping_add(1)),
+            "iny" => self.y = self.y.map(|y| y.wrapping_add(1)),
+            "dex" => self.x = self.x.map(|x| x.wrapping_sub(1)),
+            "dey" => self.y = self.y.map(|y| y.wrapping_sub(1)),
+            "sta" | "stx" | "sty" => {
+                let value = match instr.mnemonic {
+                    "sta" => self.a,
+                    "stx" => self.x,
+                    _ => self.y,
+                };
+                self.store(instr, value);
+            }
+            "clc" | "sec" | "cli" | "sei" | "cld" | "sed" | "clv" | "nop" | "cmp" | "cpx"
+            | "cpy" | "bit" | "pha" | "php" | "txs" => {}
+            "jsr" | "brk" => self.clear(),
+            _ if instr.mode == AddressingMode::Relative => {}
+            _ => {
+                // anything else may change any register and what it points at
+                self.a = Option::None;
+                self.x = Option::None;
+                self.y = Option::None;
+                if !matches!(
+                    instr.mode,
+                    AddressingMode::Implied
+                        | AddressingMode::Accumulator
+                        | AddressingMode::Immediate
+                ) {
+                    self.store(instr, Option::None);
+                }
+            }
+        }
+    }
+
+    /// Records `value` written where `instr` stores, forgetting all of memory when
+    /// that depends on an index or pointer.
+    fn store(&mut self, instr: &DecodedInstruction, value: Option<u8>) {
+        if !matches!(
+            instr.mode,
+            AddressingMode::ZeroPage | AddressingMode::Absolute
+        ) {
+            self.memory.clear();
+            return;
+        }
+        match value {
+            Option::Some(value) => self.memory.insert(instr.operand, value),
+            Option::None => self.memory.remove(&instr.operand),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decode::decode;
+
+    fn run(code: &[u8]) -> KnownValues {
+        let mut values = KnownValues::default();
+        let mut offset = 0;
+        while offset < code.len() {
+            let instr = decode(&code[offset..], 0x8000 + offset as u16).unwrap();
+            values.step(&instr);
+            offset += instr.size();
+        }
+        return values;
+    }
+
+    #[test]
+    fn test_known_values() {
+        // lda #$34 / sta $10 / ldx #$12 / stx $11
+        let values = run(&[0xa9, 0x34, 0x85, 0x10, 0xa2, 0x12, 0x86, 0x11]);
+        assert_eq!(
+            (values.read(0x10), values.read(0x11)),
+            (Option::Some(0x34), Option::Some(0x12))
+        );
+
+        // ldx #$12 / inx / txa / sta $0300 / adc #1 / sta $12
+        let values = run(&[
+            0xa2, 0x12, 0xe8, 0x8a, 0x8d, 0x00, 0x03, 0x69, 0x01, 0x85, 0x12,
+        ]);
+        assert_eq!(values.read(0x0300), Option::Some(0x13));
+        assert_eq!(values.read(0x12), Option::None);
+
+        // a store through a pointer could have gone anywhere
+        let values = run(&[0xa9, 0x34, 0x85, 0x10, 0x91, 0x20]);
+        assert_eq!(values.read(0x10), Option::None);
+        // and a subroutine could have changed it
+        let values = run(&[0xa9, 0x34, 0x85, 0x10, 0x20, 0x00, 0x90]);
+        assert_eq!(values.read(0x10), Option::None);
+    }
+}
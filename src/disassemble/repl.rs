@@ -0,0 +1,257 @@
+use std::{
+    fs::File,
+    io::{BufRead, IsTerminal, Write},
+    path::{Path, PathBuf},
+};
+
+use super::{
+    address::CpuAddr, check_handled, read_file_or_stdin, DisassembleError, NesDisassembler,
+};
+
+const DEFAULT_LINE_COUNT: usize = 16;
+
+const HELP: &str = "commands:
+  d <addr> [lines]        disassemble from addr and show the result
+  l <addr> [lines]        show the disassembly at addr
+  label <addr> <name>     name the code or data at addr
+  data <start>..<end>     mark an inclusive range as data
+  tag <addr> <tag>        tag the routine or data table at addr, e.g. audio
+  unnamed <addr>[..<end>] write operands at addr as numbers instead of names
+  save [file]             write the disassembly
+  help                    show this message
+  quit                    leave the repl
+addresses are $hex, 0xhex or decimal";
+
+#[derive(Debug)]
+pub struct ReplOptions {
+    pub in_file: PathBuf,
+    /// where `save` writes when it is given no file
+    pub out_file: Option<PathBuf>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Command {
+    Disassemble(CpuAddr, usize),
+    List(CpuAddr, usize),
+    Label(CpuAddr, String),
+    Data(CpuAddr, CpuAddr),
+    Tag(CpuAddr, String),
+    Unnamed(CpuAddr, CpuAddr),
+    Save(Option<PathBuf>),
+    Help,
+    Quit,
+}
+
+/// Reads commands from stdin one per line, so the session can be scripted by
+/// piping a file of commands in.
+pub fn repl(opts: ReplOptions) -> Result<(), DisassembleError> {
+    let data = read_file_or_stdin(Option::Some(opts.in_file.clone()))?;
+    check_handled(&data)?;
+    let mut d = NesDisassembler::disassemble(data)?;
+    let save_file = opts
+        .out_file
+        .unwrap_or_else(|| opts.in_file.with_extension("s"));
+
+    let stdin = std::io::stdin();
+    let interactive = stdin.is_terminal();
+    let mut out = std::io::stdout();
+    loop {
+        if interactive {
+            write!(out, "> ")?;
+            out.flush()?;
+        }
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            break;
+        }
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let command = match parse_command(line) {
+            Result::Ok(command) => command,
+            Result::Err(err) => {
+                writeln!(out, "error: {}", err)?;
+                continue;
+            }
+        };
+        if command == Command::Quit {
+            break;
+        }
+        if let Result::Err(err) = run_command(&mut d, command, &save_file, &mut out) {
+            writeln!(out, "error: {}", err)?;
+        }
+    }
+    return Result::Ok(());
+}
+
+fn run_command(
+    d: &mut NesDisassembler,
+    command: Command,
+    save_file: &Path,
+    out: &mut dyn Write,
+) -> Result<(), DisassembleError> {
+    match command {
+        Command::Disassemble(addr, count) => {
+            d.disassemble_at(addr)?;
+            d.write_at(out, addr, count)?;
+        }
+        Command::List(addr, count) => {
+            d.write_at(out, addr, count)?;
+        }
+        Command::Label(addr, name) => {
+            d.set_label(addr, &name)?;
+            d.write_at(out, addr, 1)?;
+        }
+        Command::Data(start, end) => {
+            let marked = d.mark_data(start, end)?;
+            writeln!(out, "marked {} bytes as data", marked)?;
+            d.write_at(out, start, marked.div_ceil(16))?;
+        }
+        Command::Tag(addr, tag) => {
+            let tagged = d.tag(addr, &tag)?;
+            writeln!(out, "tagged {} bytes as {}", tagged, tag)?;
+        }
+        Command::Unnamed(start, end) => {
+            d.keep_unnamed(start, end);
+            writeln!(
+                out,
+                "operands at ${:04x}..${:04x} are written as numbers",
+                start.0, end.0
+            )?;
+        }
+        Command::Save(file) => {
+            let file = file.unwrap_or_else(|| save_file.to_path_buf());
+            d.write(&mut File::create(&file)?)?;
+            writeln!(out, "saved {}", file.display())?;
+        }
+        Command::Help => {
+            writeln!(out, "{}", HELP)?;
+        }
+        Command::Quit => {}
+    }
+    return Result::Ok(());
+}
+
+fn parse_command(line: &str) -> Result<Command, String> {
+    let args = line.split_whitespace().collect::<Vec<&str>>();
+    return match args.as_slice() {
+        ["d", addr] => Result::Ok(Command::Disassemble(parse_addr(addr)?, DEFAULT_LINE_COUNT)),
+        ["d", addr, count] => {
+            Result::Ok(Command::Disassemble(parse_addr(addr)?, parse_count(count)?))
+        }
+        ["l", addr] => Result::Ok(Command::List(parse_addr(addr)?, DEFAULT_LINE_COUNT)),
+        ["l", addr, count] => Result::Ok(Command::List(parse_addr(addr)?, parse_count(count)?)),
+        ["label", addr, name] => {
+            if !is_identifier(name) {
+                return Result::Err(format!("invalid label \"{}\"", name));
+            }
+            Result::Ok(Command::Label(parse_addr(addr)?, name.to_string()))
+        }
+        ["data", range] => {
+            if !range.contains("..") {
+                return Result::Err(format!(
+                    "expected a range like $9000..$90ff, found {}",
+                    range
+                ));
+            }
+            let (start, end) = parse_addr_range(range)?;
+            Result::Ok(Command::Data(start, end))
+        }
+        ["tag", addr, tag] => {
+            if !is_identifier(tag) {
+                return Result::Err(format!("invalid tag \"{}\"", tag));
+            }
+            Result::Ok(Command::Tag(parse_addr(addr)?, tag.to_string()))
+        }
+        ["unnamed", range] => {
+            let (start, end) = parse_addr_range(range)?;
+            Result::Ok(Command::Unnamed(start, end))
+        }
+        ["save"] => Result::Ok(Command::Save(Option::None)),
+        ["save", file] => Result::Ok(Command::Save(Option::Some(PathBuf::from(file)))),
+        ["help"] | ["?"] => Result::Ok(Command::Help),
+        ["quit"] | ["exit"] | ["q"] => Result::Ok(Command::Quit),
+        _ => Result::Err(format!("unknown command \"{}\", try help", line)),
+    };
+}
+
+pub(super) fn parse_addr(s: &str) -> Result<CpuAddr, String> {
+    let value = if let Option::Some(hex) = s.strip_prefix('$') {
+        u16::from_str_radix(hex, 16)
+    } else if let Option::Some(hex) = s.strip_prefix("0x") {
+        u16::from_str_radix(hex, 16)
+    } else {
+        s.parse::<u16>()
+    };
+    return value
+        .map(CpuAddr)
+        .map_err(|_| format!("invalid address \"{}\"", s));
+}
+
+/// Parses `start..end`, inclusive, or a single address.
+pub(super) fn parse_addr_range(s: &str) -> Result<(CpuAddr, CpuAddr), String> {
+    let (start, end) = match s.split_once("..") {
+        Option::Some((start, end)) => (parse_addr(start)?, parse_addr(end)?),
+        Option::None => (parse_addr(s)?, parse_addr(s)?),
+    };
+    if end < start {
+        return Result::Err(format!("range {} ends before it starts", s));
+    }
+    return Result::Ok((start, end));
+}
+
+fn parse_count(s: &str) -> Result<usize, String> {
+    return s
+        .parse::<usize>()
+        .map_err(|_| format!("invalid line count \"{}\"", s));
+}
+
+pub(super) fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    return match chars.next() {
+        Option::Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(
+            parse_command("d $8000").unwrap(),
+            Command::Disassemble(CpuAddr(0x8000), DEFAULT_LINE_COUNT)
+        );
+        assert_eq!(
+            parse_command("label 0x8123 main_loop").unwrap(),
+            Command::Label(CpuAddr(0x8123), "main_loop".to_string())
+        );
+        assert_eq!(
+            parse_command("data $9000..$90FF").unwrap(),
+            Command::Data(CpuAddr(0x9000), CpuAddr(0x90ff))
+        );
+        assert_eq!(
+            parse_command("tag $8123 audio").unwrap(),
+            Command::Tag(CpuAddr(0x8123), "audio".to_string())
+        );
+        assert_eq!(
+            parse_command("unnamed $0300..$03ff").unwrap(),
+            Command::Unnamed(CpuAddr(0x0300), CpuAddr(0x03ff))
+        );
+        assert_eq!(
+            parse_command("unnamed $8123").unwrap(),
+            Command::Unnamed(CpuAddr(0x8123), CpuAddr(0x8123))
+        );
+        assert!(parse_command("unnamed $0400..$03ff").is_err());
+        assert_eq!(parse_command("save").unwrap(), Command::Save(Option::None));
+        assert!(parse_command("label $8000 1abc").is_err());
+        assert!(parse_command("data $9000").is_err());
+        assert!(parse_command("d $10000").is_err());
+    }
+}
@@ -0,0 +1,278 @@
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{
+    fmt,
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    path::PathBuf,
+};
+
+use super::{
+    address::CpuAddr,
+    check_handled, read_file_or_stdin,
+    repl::{is_identifier, parse_addr},
+    DisassembleError, NesDisassembler,
+};
+
+pub const DEFAULT_SERVE_PORT: u16 = 6502;
+
+#[derive(Debug)]
+pub struct ServeOptions {
+    pub port: u16,
+}
+
+#[derive(Deserialize)]
+struct Request {
+    #[serde(default)]
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Debug)]
+enum RpcError {
+    Parse(String),
+    MethodNotFound(String),
+    InvalidParams(String),
+    Analysis(DisassembleError),
+}
+
+impl RpcError {
+    fn code(&self) -> i64 {
+        return match self {
+            RpcError::Parse(_) => -32700,
+            RpcError::MethodNotFound(_) => -32601,
+            RpcError::InvalidParams(_) => -32602,
+            RpcError::Analysis(_) => -32000,
+        };
+    }
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RpcError::Parse(msg) => write!(f, "parse error: {}", msg),
+            RpcError::MethodNotFound(method) => write!(f, "method not found: {}", method),
+            RpcError::InvalidParams(msg) => write!(f, "invalid params: {}", msg),
+            RpcError::Analysis(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl From<DisassembleError> for RpcError {
+    fn from(err: DisassembleError) -> Self {
+        return RpcError::Analysis(err);
+    }
+}
+
+impl From<std::io::Error> for RpcError {
+    fn from(err: std::io::Error) -> Self {
+        return RpcError::Analysis(DisassembleError::IoError(err));
+    }
+}
+
+/// State shared by every connection, so a client can reconnect without
+/// losing the labels it has set.
+#[derive(Default)]
+struct Session {
+    d: Option<NesDisassembler>,
+}
+
+/// Serves the analysis API as JSON-RPC 2.0 on localhost, one request per line.
+/// Connections are handled one at a time.
+pub fn serve(opts: ServeOptions) -> Result<(), DisassembleError> {
+    let listener = TcpListener::bind(("127.0.0.1", opts.port))?;
+    eprintln!("listening on {}", listener.local_addr()?);
+
+    let mut session = Session::default();
+    for stream in listener.incoming() {
+        if let Result::Err(err) = handle_connection(&mut session, stream?) {
+            eprintln!("connection error: {}", err);
+        }
+    }
+    return Result::Ok(());
+}
+
+fn handle_connection(session: &mut Session, stream: TcpStream) -> Result<(), DisassembleError> {
+    let mut out = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Option::Some(response) = session.handle(&line) {
+            writeln!(out, "{}", response)?;
+        }
+    }
+    return Result::Ok(());
+}
+
+impl Session {
+    /// Returns the response to send, or `None` for notifications.
+    fn handle(&mut self, line: &str) -> Option<Value> {
+        let request = match serde_json::from_str::<Request>(line) {
+            Result::Ok(request) => request,
+            Result::Err(err) => {
+                return Option::Some(error_response(
+                    Value::Null,
+                    RpcError::Parse(err.to_string()),
+                ));
+            }
+        };
+        let result = self.call(&request.method, &request.params);
+        let id = request.id?;
+        return Option::Some(match result {
+            Result::Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+            Result::Err(err) => error_response(id, err),
+        });
+    }
+
+    fn call(&mut self, method: &str, params: &Value) -> Result<Value, RpcError> {
+        match method {
+            "load" => {
+                let path = PathBuf::from(str_param(params, "path")?);
+                let data = read_file_or_stdin(Option::Some(path))?;
+                check_handled(&data)?;
+                let d = NesDisassembler::disassemble(data)?;
+                let header = json!(d.header());
+                self.d = Option::Some(d);
+                return Result::Ok(header);
+            }
+            "header" => return Result::Ok(json!(self.loaded()?.header())),
+            "statements" => {
+                let count = match params.get("count") {
+                    Option::Some(count) => count.as_u64().ok_or_else(|| {
+                        RpcError::InvalidParams("count must be a number".to_string())
+                    })? as usize,
+                    Option::None => usize::MAX,
+                };
+                let d = self.loaded()?;
+                let addr = if params.get("addr").is_some() {
+                    addr_param(params, "addr")?
+                } else {
+                    CpuAddr(0x8000)
+                };
+                return Result::Ok(json!(d.json_statements_at(addr, count)?));
+            }
+            "disassemble" => {
+                let addr = addr_param(params, "addr")?;
+                self.loaded_mut()?.disassemble_at(addr)?;
+                return Result::Ok(Value::Null);
+            }
+            "set_label" => {
+                let addr = addr_param(params, "addr")?;
+                let name = str_param(params, "name")?;
+                if !is_identifier(name) {
+                    return Result::Err(RpcError::InvalidParams(format!(
+                        "invalid label \"{}\"",
+                        name
+                    )));
+                }
+                self.loaded_mut()?.set_label(addr, name)?;
+                return Result::Ok(Value::Null);
+            }
+            "mark_data" => {
+                let start = addr_param(params, "start")?;
+                let end = addr_param(params, "end")?;
+                let marked = self.loaded_mut()?.mark_data(start, end)?;
+                return Result::Ok(json!({ "marked": marked }));
+            }
+            "export" => {
+                let d = self.loaded()?;
+                let mut out = Vec::new();
+                match str_param(params, "format")? {
+                    "asm" => d.write(&mut out)?,
+                    "json" => d.write_json(&mut out)?,
+                    "mlb" => d.write_symbols(&mut out)?,
+                    format => {
+                        return Result::Err(RpcError::InvalidParams(format!(
+                            "unknown export format \"{}\", expected asm, json or mlb",
+                            format
+                        )));
+                    }
+                }
+                return Result::Ok(json!({ "text": String::from_utf8_lossy(&out) }));
+            }
+            _ => return Result::Err(RpcError::MethodNotFound(method.to_string())),
+        }
+    }
+
+    fn loaded(&self) -> Result<&NesDisassembler, RpcError> {
+        return self.d.as_ref().ok_or_else(no_rom_loaded);
+    }
+
+    fn loaded_mut(&mut self) -> Result<&mut NesDisassembler, RpcError> {
+        return self.d.as_mut().ok_or_else(no_rom_loaded);
+    }
+}
+
+fn no_rom_loaded() -> RpcError {
+    return RpcError::Analysis(DisassembleError::WrappedError(
+        "no ROM loaded, call load first".to_string(),
+    ));
+}
+
+fn error_response(id: Value, err: RpcError) -> Value {
+    return json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": {"code": err.code(), "message": err.to_string()},
+    });
+}
+
+fn str_param<'a>(params: &'a Value, name: &str) -> Result<&'a str, RpcError> {
+    return params
+        .get(name)
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| RpcError::InvalidParams(format!("missing string parameter \"{}\"", name)));
+}
+
+/// Addresses are accepted as numbers or as strings like `"$8000"`.
+fn addr_param(params: &Value, name: &str) -> Result<CpuAddr, RpcError> {
+    return match params.get(name) {
+        Option::Some(Value::Number(n)) => n
+            .as_u64()
+            .and_then(|n| u16::try_from(n).ok())
+            .map(CpuAddr)
+            .ok_or_else(|| RpcError::InvalidParams(format!("invalid address {}", n))),
+        Option::Some(Value::String(s)) => parse_addr(s).map_err(RpcError::InvalidParams),
+        _ => Result::Err(RpcError::InvalidParams(format!(
+            "missing address parameter \"{}\"",
+            name
+        ))),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_handle_errors() {
+        let mut session = Session::default();
+
+        let response = session.handle("{not json").unwrap();
+        assert_eq!(response["error"]["code"], -32700);
+
+        let response = session
+            .handle(r#"{"jsonrpc": "2.0", "id": 1, "method": "bogus"}"#)
+            .unwrap();
+        assert_eq!(response["id"], 1);
+        assert_eq!(response["error"]["code"], -32601);
+
+        let response = session
+            .handle(r#"{"jsonrpc": "2.0", "id": 2, "method": "header"}"#)
+            .unwrap();
+        assert_eq!(response["error"]["code"], -32000);
+
+        let response = session
+            .handle(r#"{"jsonrpc": "2.0", "id": 3, "method": "set_label", "params": {"addr": "$8000", "name": "1x"}}"#)
+            .unwrap();
+        assert_eq!(response["error"]["code"], -32602);
+
+        assert!(session
+            .handle(r#"{"jsonrpc": "2.0", "method": "header"}"#)
+            .is_none());
+    }
+}
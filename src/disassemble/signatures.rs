@@ -0,0 +1,432 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::Serialize;
+
+/// A routine recognized by its bytes. `pattern` is hex bytes separated by spaces,
+/// `??` matches any byte and a name matches a zero page address, the same one
+/// wherever the name appears. `name+1` matches the address after it.
+#[derive(Debug, Clone, Copy)]
+pub struct Signature<'a> {
+    pub name: &'a str,
+    pub pattern: &'a str,
+}
+
+/// Routines that come with a compiler or engine. The library is only reported
+/// once `min_matches` of its routines are found, the short ones match too easily
+/// on their own.
+#[derive(Debug, Clone, Copy)]
+pub struct Library<'a> {
+    pub name: &'static str,
+    /// prepended to the routine and zero page names
+    pub prefix: &'static str,
+    pub min_matches: usize,
+    pub signatures: &'a [Signature<'a>],
+}
+
+// https://github.com/cc65/cc65/tree/master/libsrc/runtime
+pub const CC65_RUNTIME: Library = Library {
+    name: "cc65 runtime",
+    prefix: "cc65_",
+    min_matches: 2,
+    signatures: &[
+        Signature {
+            name: "pusha",
+            pattern: "a4 sp f0 07 c6 sp a0 00 91 sp 60 c6 sp+1 c6 sp 91 sp 60",
+        },
+        Signature {
+            name: "pushax",
+            pattern: "48 a5 sp 38 e9 02 85 sp b0 02 c6 sp+1 a0 01 8a 91 sp 68 88 91 sp 60",
+        },
+        Signature {
+            name: "popa",
+            pattern: "a0 00 b1 sp e6 sp f0 01 60 e6 sp+1 60",
+        },
+        Signature {
+            name: "popax",
+            pattern: "a0 01 b1 sp aa 88 b1 sp 4c ?? ??",
+        },
+        Signature {
+            name: "incsp1",
+            pattern: "e6 sp d0 02 e6 sp+1 60",
+        },
+        Signature {
+            name: "incsp2",
+            pattern: "e6 sp f0 05 e6 sp f0 03 60 e6 sp e6 sp+1 60",
+        },
+        Signature {
+            name: "ldaxysp",
+            pattern: "b1 sp aa 88 b1 sp 60",
+        },
+    ],
+};
+
+// the engine NESmaker builds its games from, asm6 source shipped with the tool
+pub const NESMAKER: Library = Library {
+    name: "NESmaker",
+    prefix: "nesmaker_",
+    min_matches: 2,
+    signatures: &[
+        // sleeps until the NMI handler clears the flag
+        Signature {
+            name: "wait_frame",
+            pattern: "e6 sleeping a5 sleeping d0 fc 60",
+        },
+        // older versions wait for the NMI handler to count the frame instead
+        Signature {
+            name: "wait_frame",
+            pattern: "a5 vblank_timer c5 vblank_timer f0 fc 60",
+        },
+        // keeps only the standard controller bit of each read
+        Signature {
+            name: "gamepad_check",
+            pattern:
+                "a9 01 8d 16 40 a9 00 8d 16 40 a2 08 ad 16 40 29 03 c9 01 26 gamepad ca d0 f4 60",
+        },
+    ],
+};
+
+// the routines every NES game writes some version of, as the nesdev wiki has them
+pub const NES_STUBS: Library = Library {
+    name: "NES stubs",
+    prefix: "",
+    min_matches: 1,
+    signatures: &[
+        Signature {
+            name: "wait_vblank",
+            pattern: "2c 02 20 10 fb 60",
+        },
+        Signature {
+            name: "wait_vblank",
+            pattern: "ad 02 20 10 fb 60",
+        },
+        // shifts the buttons into a byte starting out as 1, done once the 1 is
+        // shifted out
+        Signature {
+            name: "read_joypad",
+            pattern: "a9 01 8d 16 40 85 buttons 4a 8d 16 40 ad 16 40 4a 26 buttons 90 f8 60",
+        },
+        // https://shiru.untergrund.net/code.shtml, Famitone2's entry points with the
+        // zero page pointer it borrows; the music data pointer and the rest of its
+        // state are in the RAM it is configured with
+        Signature {
+            name: "famitone_init",
+            pattern: "8e ?? ?? 8c ?? ?? 86 famitone_temp_ptr 84 famitone_temp_ptr+1 \
+                      a9 0f 8d 15 40 a9 80 8d 08 40 a9 00 8d 0f 40 a9 30 8d 00 40 8d 04 40 \
+                      8d 0c 40 a9 08 8d 01 40 8d 05 40",
+        },
+        // saves the pointer, for calling from the NMI handler while the main
+        // thread uses it, then skips the music while the speed is 0 or paused
+        Signature {
+            name: "famitone_update",
+            pattern: "a5 famitone_temp_ptr 48 a5 famitone_temp_ptr+1 48 ad ?? ?? 30 02 d0 03 \
+                      4c ?? ??",
+        },
+    ],
+};
+
+pub const LIBRARIES: &[Library] = &[CC65_RUNTIME, NESMAKER, NES_STUBS];
+
+/// name of the library of the routines read with [`parse_signatures`]
+pub const SIGNATURES_FILE: &str = "signatures file";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum PatternByte<'a> {
+    Byte(u8),
+    Any,
+    ZeroPage(&'a str, u8),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SignatureMatch {
+    pub library: &'static str,
+    /// routine name with the library prefix
+    pub name: String,
+    pub offset: usize,
+    pub len: usize,
+    /// zero page addresses named by the pattern, with the library prefix, the
+    /// address after one matched as `name+1` as `name_hi`
+    pub zero_page: HashMap<String, u8>,
+}
+
+/// Finds the routines of `libraries` in `data[start..end]`.
+pub fn find_signatures(
+    data: &[u8],
+    start: usize,
+    end: usize,
+    libraries: &[Library<'_>],
+) -> Vec<SignatureMatch> {
+    let mut result = Vec::new();
+    for library in libraries {
+        let mut matches = Vec::new();
+        for signature in library.signatures {
+            let pattern = parse_pattern(signature.pattern);
+            for offset in start..end.saturating_sub(pattern.len() - 1) {
+                if let Option::Some(zero_page) = match_pattern(&pattern, &data[offset..end]) {
+                    matches.push(SignatureMatch {
+                        library: library.name,
+                        name: format!("{}{}", library.prefix, signature.name),
+                        offset,
+                        len: pattern.len(),
+                        zero_page: named_zero_page(&pattern, &zero_page, library.prefix),
+                    });
+                }
+            }
+        }
+        let found = matches
+            .iter()
+            .map(|m| m.name.as_str())
+            .collect::<HashSet<&str>>()
+            .len();
+        if found >= library.min_matches {
+            result.extend(matches);
+        }
+    }
+    result.sort_by_key(|m| m.offset);
+    return result;
+}
+
+/// The zero page addresses `pattern` matched, named with `prefix`, a pointer's
+/// high byte as `name_hi`.
+fn named_zero_page(
+    pattern: &[PatternByte],
+    zero_page: &HashMap<&str, u8>,
+    prefix: &str,
+) -> HashMap<String, u8> {
+    let mut named = zero_page
+        .iter()
+        .map(|(name, addr)| (format!("{}{}", prefix, name), *addr))
+        .collect::<HashMap<String, u8>>();
+    for p in pattern {
+        if let PatternByte::ZeroPage(name, 1) = p {
+            named.insert(
+                format!("{}{}_hi", prefix, name),
+                zero_page[name].wrapping_add(1),
+            );
+        }
+    }
+    return named;
+}
+
+/// Parses signatures to look for besides [`LIBRARIES`], one `name = pattern` per
+/// line with the pattern written the way [`Signature::pattern`] is, e.g.
+/// `update = a5 ptr 48 a5 ptr+1 48`. `#` starts a comment.
+pub fn parse_signatures(s: &str) -> Result<Vec<(String, String)>, String> {
+    let mut signatures = Vec::new();
+    for line in s.lines() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let invalid = || format!("expected name = pattern, found \"{}\"", line);
+        let (name, pattern) = line.split_once('=').ok_or_else(invalid)?;
+        let (name, pattern) = (name.trim(), pattern.trim());
+        if !is_name(name) || pattern.is_empty() {
+            return Result::Err(invalid());
+        }
+        for p in pattern.split_whitespace() {
+            let zero_page = match p.split_once('+') {
+                Option::Some((name, add)) => is_name(name) && add.parse::<u8>().is_ok(),
+                Option::None => is_name(p),
+            };
+            let byte = p.len() == 2 && (p == "??" || u8::from_str_radix(p, 16).is_ok());
+            if !byte && !zero_page {
+                return Result::Err(format!(
+                    "expected a hex byte, ?? or a zero page name in the pattern of {}, found \"{}\"",
+                    name, p
+                ));
+            }
+        }
+        signatures.push((name.to_string(), pattern.to_string()));
+    }
+    return Result::Ok(signatures);
+}
+
+/// Whether `s` can name a routine or zero page address, a hex byte can't.
+fn is_name(s: &str) -> bool {
+    return s.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !(s.len() == 2 && u8::from_str_radix(s, 16).is_ok());
+}
+
+fn parse_pattern(pattern: &str) -> Vec<PatternByte<'_>> {
+    return pattern
+        .split_whitespace()
+        .map(|s| {
+            if s == "??" {
+                return PatternByte::Any;
+            }
+            if let Result::Ok(b) = u8::from_str_radix(s, 16) {
+                return PatternByte::Byte(b);
+            }
+            return match s.split_once('+') {
+                Option::Some((name, add)) => PatternByte::ZeroPage(name, add.parse().unwrap()),
+                Option::None => PatternByte::ZeroPage(s, 0),
+            };
+        })
+        .collect();
+}
+
+fn match_pattern<'a>(pattern: &[PatternByte<'a>], data: &[u8]) -> Option<HashMap<&'a str, u8>> {
+    if data.len() < pattern.len() {
+        return Option::None;
+    }
+    let mut zero_page = HashMap::new();
+    for (p, b) in pattern.iter().zip(data) {
+        match p {
+            PatternByte::Byte(v) if v != b => return Option::None,
+            PatternByte::ZeroPage(name, add) => {
+                let addr = b.checked_sub(*add)?;
+                if *zero_page.entry(*name).or_insert(addr) != addr {
+                    return Option::None;
+                }
+            }
+            _ => {}
+        }
+    }
+    return Option::Some(zero_page);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_signatures() {
+        let mut data = vec![0xea; 64];
+        // popa and incsp1 with sp at $22
+        data[4..16].copy_from_slice(&[
+            0xa0, 0x00, 0xb1, 0x22, 0xe6, 0x22, 0xf0, 0x01, 0x60, 0xe6, 0x23, 0x60,
+        ]);
+        data[32..39].copy_from_slice(&[0xe6, 0x22, 0xd0, 0x02, 0xe6, 0x23, 0x60]);
+
+        let found = find_signatures(&data, 0, data.len(), LIBRARIES);
+        assert_eq!(
+            found
+                .iter()
+                .map(|m| (m.name.as_str(), m.offset))
+                .collect::<Vec<_>>(),
+            vec![("cc65_popa", 4), ("cc65_incsp1", 32)]
+        );
+        assert_eq!(found[0].zero_page["cc65_sp"], 0x22);
+
+        // one routine on its own isn't enough
+        assert!(find_signatures(&data, 0, 30, LIBRARIES).is_empty());
+
+        assert_eq!(found[0].zero_page["cc65_sp_hi"], 0x23);
+
+        // sp+1 has to follow sp
+        data[36] = 0x24;
+        assert!(find_signatures(&data, 0, data.len(), LIBRARIES).is_empty());
+    }
+
+    #[test]
+    fn test_nes_stubs() {
+        let mut data = vec![0xea; 48];
+        data[8..14].copy_from_slice(&[0x2c, 0x02, 0x20, 0x10, 0xfb, 0x60]);
+        data[16..37].copy_from_slice(&[
+            0xa9, 0x01, 0x8d, 0x16, 0x40, 0x85, 0x30, 0x4a, 0x8d, 0x16, 0x40, 0xad, 0x16, 0x40,
+            0x4a, 0x26, 0x30, 0x90, 0xf8, 0x60, 0xea,
+        ]);
+        let found = find_signatures(&data, 0, data.len(), &[NES_STUBS]);
+        assert_eq!(
+            found
+                .iter()
+                .map(|m| (m.name.as_str(), m.offset))
+                .collect::<Vec<_>>(),
+            vec![("wait_vblank", 8), ("read_joypad", 16)]
+        );
+        assert_eq!(
+            found[1].zero_page,
+            HashMap::from([("buttons".to_string(), 0x30)])
+        );
+    }
+    #[test]
+    fn test_nesmaker() {
+        let mut data = vec![0xea; 64];
+        // wait_frame with sleeping at $14 and gamepad_check with gamepad at $20
+        data[4..11].copy_from_slice(&[0xe6, 0x14, 0xa5, 0x14, 0xd0, 0xfc, 0x60]);
+        data[16..41].copy_from_slice(&[
+            0xa9, 0x01, 0x8d, 0x16, 0x40, 0xa9, 0x00, 0x8d, 0x16, 0x40, 0xa2, 0x08, 0xad, 0x16,
+            0x40, 0x29, 0x03, 0xc9, 0x01, 0x26, 0x20, 0xca, 0xd0, 0xf4, 0x60,
+        ]);
+        let found = find_signatures(&data, 0, data.len(), LIBRARIES);
+        assert_eq!(
+            found
+                .iter()
+                .map(|m| (m.library, m.name.as_str(), m.offset))
+                .collect::<Vec<_>>(),
+            vec![
+                ("NESmaker", "nesmaker_wait_frame", 4),
+                ("NESmaker", "nesmaker_gamepad_check", 16)
+            ]
+        );
+        assert_eq!(found[0].zero_page["nesmaker_sleeping"], 0x14);
+        assert_eq!(found[1].zero_page["nesmaker_gamepad"], 0x20);
+
+        // the frame wait on its own isn't enough
+        assert!(find_signatures(&data, 0, 16, LIBRARIES).is_empty());
+    }
+
+    #[test]
+    fn test_famitone() {
+        let mut data = vec![0xea; 64];
+        // famitone_update with the pointer at $fc
+        data[8..24].copy_from_slice(&[
+            0xa5, 0xfc, 0x48, 0xa5, 0xfd, 0x48, 0xad, 0x00, 0x03, 0x30, 0x02, 0xd0, 0x03, 0x4c,
+            0x40, 0x81,
+        ]);
+        let found = find_signatures(&data, 0, data.len(), &[NES_STUBS]);
+        assert_eq!(
+            found
+                .iter()
+                .map(|m| (m.name.as_str(), m.offset))
+                .collect::<Vec<_>>(),
+            vec![("famitone_update", 8)]
+        );
+        assert_eq!(
+            found[0].zero_page,
+            HashMap::from([
+                ("famitone_temp_ptr".to_string(), 0xfc),
+                ("famitone_temp_ptr_hi".to_string(), 0xfd)
+            ])
+        );
+    }
+
+    #[test]
+    fn test_parse_signatures() {
+        let signatures = parse_signatures(
+            "# sound engine\n\nsound_update = a5 ptr 48 a5 ptr+1 48 ?? 60\nfade=a9 00 85 vol\n",
+        )
+        .unwrap();
+        assert_eq!(
+            signatures,
+            vec![
+                (
+                    "sound_update".to_string(),
+                    "a5 ptr 48 a5 ptr+1 48 ?? 60".to_string()
+                ),
+                ("fade".to_string(), "a9 00 85 vol".to_string()),
+            ]
+        );
+        let extra = signatures
+            .iter()
+            .map(|(name, pattern)| Signature { name, pattern })
+            .collect::<Vec<Signature>>();
+        let library = Library {
+            name: SIGNATURES_FILE,
+            prefix: "",
+            min_matches: 1,
+            signatures: &extra,
+        };
+        let data = [0xea, 0xa9, 0x00, 0x85, 0x42, 0xea];
+        let found = find_signatures(&data, 0, data.len(), &[library]);
+        assert_eq!(found[0].name, "fade");
+        assert_eq!(found[0].zero_page["vol"], 0x42);
+
+        assert!(parse_signatures("sound_update a5 ptr").is_err());
+        assert!(parse_signatures("ff = a5 ptr").is_err());
+        assert!(parse_signatures("fade = a9 0").is_err());
+        assert!(parse_signatures("fade = a9 vol+x").is_err());
+        assert!(parse_signatures("fade =").is_err());
+    }
+}
@@ -0,0 +1,119 @@
+use itertools::Itertools;
+use serde::Serialize;
+
+use crate::decode::decode_opcode;
+
+use super::address::CpuAddr;
+
+/// routines shorter than this are too common to mean anything, e.g. a lone `rts`
+const MIN_ROUTINE_LENGTH: usize = 4;
+const MAX_ROUTINE_LENGTH: usize = 256;
+
+/// A routine found byte for byte at the same address in several banks, typically
+/// a bank switching stub that has to exist in whichever bank is mapped in.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DuplicatedRoutine {
+    pub name: String,
+    #[serde(serialize_with = "serialize_addr")]
+    pub addr: CpuAddr,
+    pub len: usize,
+    pub banks: Vec<u8>,
+}
+
+impl DuplicatedRoutine {
+    /// label of the copy in `bank`
+    pub fn alias(&self, bank: u8) -> String {
+        return format!("{}_bank{}", self.name, bank);
+    }
+}
+
+fn serialize_addr<S: serde::Serializer>(addr: &CpuAddr, s: S) -> Result<S::Ok, S::Error> {
+    return s.serialize_u16(addr.0);
+}
+
+/// Looks for the routines starting at `candidates` in every bank. `banks` are the
+/// file offsets of each bank, which are `bank_length` bytes long.
+pub fn find_duplicated_routines(
+    data: &[u8],
+    banks: &[usize],
+    bank_length: usize,
+    candidates: &[CpuAddr],
+) -> Vec<DuplicatedRoutine> {
+    let mut result = Vec::new();
+    for addr in candidates.iter().unique() {
+        let bank_offset = addr.0 as usize % bank_length;
+        let copies = banks
+            .iter()
+            .enumerate()
+            .filter_map(|(bank, start)| {
+                let end = (start + bank_length).min(data.len());
+                return routine_bytes(data, start + bank_offset, end).map(|b| (bank as u8, b));
+            })
+            .into_group_map_by(|(_, bytes)| *bytes);
+        let largest = copies
+            .into_iter()
+            .max_by_key(|(bytes, copies)| (copies.len(), bytes.len()));
+        if let Option::Some((bytes, copies)) = largest {
+            if copies.len() < 2 {
+                continue;
+            }
+            result.push(DuplicatedRoutine {
+                name: format!("stub_{:04x}", addr),
+                addr: *addr,
+                len: bytes.len(),
+                banks: copies.iter().map(|(bank, _)| *bank).collect(),
+            });
+        }
+    }
+    result.sort_by_key(|r| r.addr);
+    return result;
+}
+
+/// Bytes of the routine at `offset` up to and including the first `rts`, `rti` or
+/// `jmp`, or `None` if that isn't plausible code.
+fn routine_bytes(data: &[u8], offset: usize, end: usize) -> Option<&[u8]> {
+    let mut len = 0;
+    while offset + len < end && len < MAX_ROUTINE_LENGTH {
+        let (mnemonic, mode) = decode_opcode(data[offset + len])?;
+        len += 1 + mode.operand_len();
+        if matches!(mnemonic, "rts" | "rti" | "jmp") {
+            if len < MIN_ROUTINE_LENGTH || offset + len > end {
+                return Option::None;
+            }
+            return Option::Some(&data[offset..offset + len]);
+        }
+    }
+    return Option::None;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_duplicated_routines() {
+        // lda #bank; sta $ffff; rts at $3f00 of every bank, except bank 2 loads 7
+        let stub = [0xa9, 0x00, 0x8d, 0xff, 0xff, 0x60];
+        let mut data = vec![0xff; 4 * 0x4000];
+        for bank in 0..4 {
+            let offset = bank * 0x4000 + 0x3f00;
+            data[offset..offset + stub.len()].copy_from_slice(&stub);
+        }
+        data[2 * 0x4000 + 0x3f01] = 7;
+
+        let banks = [0, 0x4000, 0x8000, 0xc000];
+        let found = find_duplicated_routines(&data, &banks, 0x4000, &[CpuAddr(0xff00)]);
+        assert_eq!(
+            found,
+            vec![DuplicatedRoutine {
+                name: "stub_ff00".to_string(),
+                addr: CpuAddr(0xff00),
+                len: 6,
+                banks: vec![0, 1, 3],
+            }]
+        );
+        assert_eq!(found[0].alias(3), "stub_ff00_bank3");
+
+        assert!(find_duplicated_routines(&data, &banks, 0x4000, &[CpuAddr(0xff10)]).is_empty());
+    }
+}
@@ -0,0 +1,213 @@
+use serde::Serialize;
+
+use crate::decode::{decode_opcode, AddressingMode};
+
+pub const DEFAULT_SWEEP_THRESHOLD: f64 = 0.6;
+
+/// runs of the same byte at least this long are fill, not code or data
+pub const FILL_RUN_LENGTH: usize = 8;
+/// opcode of `nop`, what code is usually padded with
+pub const NOP: u8 = 0xea;
+/// runs of `nop` at least this long are padding
+pub const NOP_RUN_LENGTH: usize = 4;
+/// padding is only said to align what follows to at least this many bytes
+const MIN_ALIGNMENT: u16 = 16;
+/// regions shorter than this don't say enough to be scored
+pub const MIN_REGION_LENGTH: usize = 8;
+/// more `rts`/`rti`/`jmp` than this per instruction looks like data
+const MAX_TERMINATOR_RATIO: f64 = 0.25;
+
+/// How much a region of bytes looks like code when decoded linearly from its
+/// start, between 0 (data) and 1 (code).
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CodeScore {
+    pub score: f64,
+    /// fraction of decoded bytes that are not a documented opcode
+    pub illegal_density: f64,
+    /// fraction of branches and jumps whose target could be code, 0.5 without any
+    pub branch_plausibility: f64,
+    /// 1 if `rts`/`rti`/`jmp` show up about as often as in real code
+    pub terminator_frequency: f64,
+    pub instructions: usize,
+}
+
+/// A run of PRG ROM bytes the recursive pass never reached, with its score.
+#[derive(Debug, Clone, Serialize)]
+pub struct ScoredRegion {
+    pub offset: usize,
+    pub bank: u8,
+    pub addr: u16,
+    pub len: usize,
+    #[serde(flatten)]
+    pub score: CodeScore,
+    /// the score is at least the sweep threshold
+    pub likely_code: bool,
+}
+
+pub fn score_region(data: &[u8], addr: u16) -> CodeScore {
+    let end_addr = addr as usize + data.len();
+    let mut boundaries = Vec::new();
+    let mut targets = Vec::new();
+    let mut illegal = 0;
+    let mut instructions = 0;
+    let mut terminators = 0;
+    let mut jumps = 0;
+    let mut plausible_jumps = 0;
+
+    let mut i = 0;
+    while i < data.len() {
+        let (mnemonic, mode) = match decode_opcode(data[i]) {
+            // `brk` decodes but in practice is a zero byte of data
+            Option::Some(("brk", _)) | Option::None => {
+                illegal += 1;
+                i += 1;
+                continue;
+            }
+            Option::Some(op) => op,
+        };
+        let len = 1 + mode.operand_len();
+        if i + len > data.len() {
+            break;
+        }
+        boundaries.push(addr as usize + i);
+        instructions += 1;
+        match (mnemonic, mode) {
+            (_, AddressingMode::Relative) => {
+                let target = (addr as usize + i + 2) as isize + data[i + 1] as i8 as isize;
+                targets.push(target);
+            }
+            ("jsr", _) | ("jmp", AddressingMode::Absolute) => {
+                jumps += 1;
+                if u16::from_le_bytes([data[i + 1], data[i + 2]]) >= 0x8000 {
+                    plausible_jumps += 1;
+                }
+            }
+            _ => {}
+        }
+        if matches!(mnemonic, "rts" | "rti" | "jmp") {
+            terminators += 1;
+        }
+        i += len;
+    }
+
+    for target in &targets {
+        jumps += 1;
+        let in_region = *target >= addr as isize && (*target as usize) < end_addr;
+        if !in_region || boundaries.contains(&(*target as usize)) {
+            plausible_jumps += 1;
+        }
+    }
+
+    let illegal_density = illegal as f64 / data.len().max(1) as f64;
+    let branch_plausibility = if jumps == 0 {
+        0.5
+    } else {
+        plausible_jumps as f64 / jumps as f64
+    };
+    let terminator_ratio = terminators as f64 / instructions.max(1) as f64;
+    let terminator_frequency = if terminators > 0 && terminator_ratio <= MAX_TERMINATOR_RATIO {
+        1.0
+    } else {
+        0.0
+    };
+    let score = if instructions == 0 {
+        0.0
+    } else {
+        0.5 * (1.0 - illegal_density).powi(2)
+            + 0.3 * branch_plausibility
+            + 0.2 * terminator_frequency
+    };
+    return CodeScore {
+        score,
+        illegal_density,
+        branch_plausibility,
+        terminator_frequency,
+        instructions,
+    };
+}
+
+/// Whether `len` bytes of `byte` are long enough a run to be fill or padding.
+pub fn is_fill_run(byte: u8, len: usize) -> bool {
+    let min_len = if byte == NOP {
+        NOP_RUN_LENGTH
+    } else {
+        FILL_RUN_LENGTH
+    };
+    return len >= min_len;
+}
+
+/// Alignment `len` bytes of padding ending at `end` bring what follows to, the
+/// way `.align` pads, `None` if the padding is longer than that.
+pub fn padding_alignment(end: u16, len: usize) -> Option<u16> {
+    let alignment = 1u16 << end.trailing_zeros().min(8);
+    return if alignment >= MIN_ALIGNMENT && len < alignment as usize {
+        Option::Some(alignment)
+    } else {
+        Option::None
+    };
+}
+
+/// Splits `len` bytes starting at `start` into the regions worth scoring, leaving
+/// out runs of fill bytes and `nop` padding.
+pub fn split_fill(data: &[u8], start: usize, len: usize) -> Vec<(usize, usize)> {
+    let mut result = Vec::new();
+    let end = start + len;
+    let mut region_start = start;
+    let mut i = start;
+    while i < end {
+        let run = data[i..end].iter().take_while(|b| **b == data[i]).count();
+        if is_fill_run(data[i], run) {
+            if i - region_start >= MIN_REGION_LENGTH {
+                result.push((region_start, i - region_start));
+            }
+            region_start = i + run;
+        }
+        i += run;
+    }
+    if end - region_start >= MIN_REGION_LENGTH {
+        result.push((region_start, end - region_start));
+    }
+    return result;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_score_region() {
+        // loop: lda $2002; bpl loop; jsr $8100; rts
+        let code = [0xad, 0x02, 0x20, 0x10, 0xfb, 0x20, 0x00, 0x81, 0x60];
+        let score = score_region(&code, 0x8000);
+        assert_eq!(score.instructions, 4);
+        assert_eq!(score.illegal_density, 0.0);
+        assert_eq!(score.branch_plausibility, 1.0);
+        assert_eq!(score.terminator_frequency, 1.0);
+        assert!(score.score > DEFAULT_SWEEP_THRESHOLD);
+
+        let data = [0x00, 0x12, 0x34, 0x02, 0x00, 0xff, 0x07, 0x00, 0x3f];
+        assert!(score_region(&data, 0x8000).score < DEFAULT_SWEEP_THRESHOLD);
+    }
+
+    #[test]
+    fn test_split_fill() {
+        let mut data = vec![1; 10];
+        data.extend_from_slice(&[0xff; 20]);
+        data.extend_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8, 9]);
+        assert_eq!(split_fill(&data, 0, data.len()), vec![(30, 9)]);
+        data[0..10].copy_from_slice(&[0, 1, 2, 3, 4, 5, 6, 7, 8, 9]);
+        assert_eq!(split_fill(&data, 0, data.len()), vec![(0, 10), (30, 9)]);
+        assert_eq!(split_fill(&data, 2, 20), vec![(2, 8)]);
+        data[12..16].fill(NOP);
+        assert_eq!(split_fill(&data, 0, data.len()), vec![(0, 12), (30, 9)]);
+    }
+
+    #[test]
+    fn test_padding_alignment() {
+        assert_eq!(padding_alignment(0x8100, 5), Option::Some(0x100));
+        assert_eq!(padding_alignment(0x8030, 9), Option::Some(0x10));
+        assert_eq!(padding_alignment(0x8030, 16), Option::None);
+        assert_eq!(padding_alignment(0x8004, 2), Option::None);
+        assert_eq!(padding_alignment(0xc000, 0x3000), Option::None);
+    }
+}
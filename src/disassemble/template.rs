@@ -0,0 +1,235 @@
+//! The parts of the assembly that are a matter of house style, the file header and
+//! footer, segment banners and labels, written through templates given with
+//! `--template`.
+
+use serde::Serialize;
+use std::path::Path;
+
+use super::DisassembleError;
+
+/// Writes the styled parts of the assembly. Each returns `None` to keep what is
+/// written without a template.
+pub trait OutputTemplate {
+    /// Lines before everything else.
+    fn header(&self) -> Result<Option<String>, DisassembleError>;
+
+    /// Lines starting the segment `name` at `addr` with `directive`, `default`
+    /// being the banner and directive otherwise written.
+    fn segment(
+        &self,
+        name: &str,
+        addr: u16,
+        directive: &str,
+        default: &str,
+    ) -> Result<Option<String>, DisassembleError>;
+
+    /// Line defining the label `name` at `addr`, `default` being what the dialect
+    /// writes for it, a `.proc` for the start of a routine.
+    fn label(
+        &self,
+        name: &str,
+        addr: u16,
+        default: &str,
+    ) -> Result<Option<String>, DisassembleError>;
+
+    /// Lines after everything else.
+    fn footer(&self) -> Result<Option<String>, DisassembleError>;
+}
+
+/// What the header and footer are written from, also given to the segment and
+/// label templates.
+#[derive(Debug, Clone, Serialize)]
+pub struct TemplateContext {
+    /// name of the ROM disassembled
+    pub rom: String,
+    pub mapper: u16,
+    pub prg_banks: usize,
+    pub chr_banks: usize,
+    /// assembler the output is written for
+    pub assembler: String,
+    /// what starts a comment in its syntax
+    pub comment: String,
+    /// version of sixtyfive writing it
+    pub version: String,
+}
+
+/// Loads the templates in `dir`: `header.hbs`, `segment.hbs`, `label.hbs` and
+/// `footer.hbs`, Handlebars templates each of which may be left out.
+#[cfg(feature = "templates")]
+pub fn load_template(
+    dir: &Path,
+    context: TemplateContext,
+) -> Result<Box<dyn OutputTemplate>, DisassembleError> {
+    if !dir.is_dir() {
+        return Result::Err(DisassembleError::MissingFile(dir.to_path_buf()));
+    }
+    let mut registry = handlebars::Handlebars::new();
+    registry.set_strict_mode(true);
+    // the output is assembly, not HTML
+    registry.register_escape_fn(handlebars::no_escape);
+    let mut found = false;
+    for part in TEMPLATE_PARTS {
+        let file = dir.join(format!("{}.hbs", part));
+        if !file.exists() {
+            continue;
+        }
+        registry
+            .register_template_file(part, &file)
+            .map_err(|err| DisassembleError::ParseError(format!("{}: {}", file.display(), err)))?;
+        found = true;
+    }
+    if !found {
+        return Result::Err(DisassembleError::ParseError(format!(
+            "{} has none of {}",
+            dir.display(),
+            TEMPLATE_PARTS
+                .map(|part| format!("{}.hbs", part))
+                .join(", ")
+        )));
+    }
+    return Result::Ok(Box::new(HandlebarsTemplate { registry, context }));
+}
+
+#[cfg(not(feature = "templates"))]
+pub fn load_template(
+    dir: &Path,
+    _context: TemplateContext,
+) -> Result<Box<dyn OutputTemplate>, DisassembleError> {
+    return Result::Err(DisassembleError::ParseError(format!(
+        "can't use the templates in {}, templates need the templates feature",
+        dir.display()
+    )));
+}
+
+/// the templates in a `--template` directory, by name
+#[cfg(feature = "templates")]
+const TEMPLATE_PARTS: [&str; 4] = ["header", "segment", "label", "footer"];
+
+#[cfg(feature = "templates")]
+struct HandlebarsTemplate {
+    registry: handlebars::Handlebars<'static>,
+    context: TemplateContext,
+}
+
+#[cfg(feature = "templates")]
+impl HandlebarsTemplate {
+    /// The template `part` rendered with the context plus `values`, without the
+    /// line break the template file ends with.
+    fn render(
+        &self,
+        part: &str,
+        values: &[(&str, serde_json::Value)],
+    ) -> Result<Option<String>, DisassembleError> {
+        if !self.registry.has_template(part) {
+            return Result::Ok(Option::None);
+        }
+        let mut data = serde_json::to_value(&self.context)
+            .map_err(|err| DisassembleError::WrappedError(err.to_string()))?;
+        if let serde_json::Value::Object(data) = &mut data {
+            for (name, value) in values {
+                data.insert(name.to_string(), value.clone());
+            }
+        }
+        let rendered = self
+            .registry
+            .render(part, &data)
+            .map_err(|err| DisassembleError::ParseError(format!("{}.hbs: {}", part, err)))?;
+        let rendered = rendered.strip_suffix('\n').unwrap_or(&rendered);
+        return Result::Ok(Option::Some(rendered.to_string()));
+    }
+}
+
+#[cfg(feature = "templates")]
+impl OutputTemplate for HandlebarsTemplate {
+    fn header(&self) -> Result<Option<String>, DisassembleError> {
+        return self.render("header", &[]);
+    }
+
+    fn segment(
+        &self,
+        name: &str,
+        addr: u16,
+        directive: &str,
+        default: &str,
+    ) -> Result<Option<String>, DisassembleError> {
+        return self.render(
+            "segment",
+            &[
+                ("name", name.into()),
+                ("addr", format!("{:04X}", addr).into()),
+                ("directive", directive.into()),
+                ("default", default.into()),
+            ],
+        );
+    }
+
+    fn label(
+        &self,
+        name: &str,
+        addr: u16,
+        default: &str,
+    ) -> Result<Option<String>, DisassembleError> {
+        return self.render(
+            "label",
+            &[
+                ("name", name.into()),
+                ("addr", format!("{:04X}", addr).into()),
+                ("default", default.into()),
+            ],
+        );
+    }
+
+    fn footer(&self) -> Result<Option<String>, DisassembleError> {
+        return self.render("footer", &[]);
+    }
+}
+
+#[cfg(all(test, feature = "templates"))]
+mod tests {
+    use super::*;
+
+    fn context() -> TemplateContext {
+        return TemplateContext {
+            rom: "game.nes".to_string(),
+            mapper: 1,
+            prg_banks: 8,
+            chr_banks: 0,
+            assembler: "ca65".to_string(),
+            comment: ";".to_string(),
+            version: "0.1.0".to_string(),
+        };
+    }
+
+    #[test]
+    fn test_load_template() {
+        let dir = std::env::temp_dir().join(format!("sixtyfive-template-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        assert!(load_template(&dir, context()).is_err());
+
+        std::fs::write(
+            dir.join("header.hbs"),
+            "{{comment}} {{rom}}, mapper {{mapper}}\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join("label.hbs"), "{{default}} {{comment}} ${{addr}}\n").unwrap();
+        let template = load_template(&dir, context()).unwrap();
+        assert_eq!(
+            template.header().unwrap(),
+            Option::Some("; game.nes, mapper 1".to_string())
+        );
+        assert_eq!(
+            template.label("reset", 0x8000, "reset:").unwrap(),
+            Option::Some("reset: ; $8000".to_string())
+        );
+        assert_eq!(
+            template.segment("PRGROM0", 0x8000, "", "").unwrap(),
+            Option::None
+        );
+
+        // strict, so a misspelled name fails rather than writing nothing
+        std::fs::write(dir.join("footer.hbs"), "{{rom_name}}\n").unwrap();
+        let template = load_template(&dir, context()).unwrap();
+        assert!(template.footer().is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,132 @@
+use std::collections::BTreeMap;
+
+pub const DEFAULT_TEXT_MIN_LEN: usize = 8;
+/// this many of the same char in a row are fill, not text
+const MAX_REPEAT: usize = 4;
+
+/// What a byte reads as, through `charmap` if there is one, otherwise as
+/// printable ASCII. `"` can't be written inside a ca65 string.
+fn text_char(byte: u8, charmap: &BTreeMap<u8, char>) -> Option<char> {
+    let c = if charmap.is_empty() {
+        byte as char
+    } else {
+        *charmap.get(&byte)?
+    };
+    return Option::Some(c).filter(|c| (' '..='~').contains(c) && *c != '"');
+}
+
+/// The text at the start of `data`, if at least `min_len` bytes read as text
+/// with a letter among them. Text ends before [`MAX_REPEAT`] of the same char,
+/// which is fill rather than text.
+pub fn text_at(data: &[u8], charmap: &BTreeMap<u8, char>, min_len: usize) -> Option<String> {
+    let mut text = String::new();
+    for c in data.iter().map_while(|b| text_char(*b, charmap)) {
+        text.push(c);
+        if text.len() >= MAX_REPEAT && text.chars().rev().take(MAX_REPEAT).all(|r| r == c) {
+            text.truncate(text.len() - MAX_REPEAT);
+            break;
+        }
+    }
+    if text.len() < min_len || !looks_like_text(&text) {
+        return Option::None;
+    }
+    return Option::Some(text);
+}
+
+/// Whether `text` reads like words rather than bytes that happen to be
+/// printable: mostly letters, few digits, only common punctuation and capitals
+/// only at the start of a word or in a word written all in capitals.
+fn looks_like_text(text: &str) -> bool {
+    let letters = text.chars().filter(|c| c.is_ascii_alphabetic()).count();
+    let digits = text.chars().filter(|c| c.is_ascii_digit()).count();
+    let punctuation = text
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || " .,!?'-:".contains(c));
+    let bytes = text.as_bytes();
+    let mixed_case = bytes
+        .windows(2)
+        .any(|w| w[0].is_ascii_lowercase() && w[1].is_ascii_uppercase())
+        || bytes.windows(3).any(|w| {
+            w[0].is_ascii_uppercase() && w[1].is_ascii_uppercase() && w[2].is_ascii_lowercase()
+        });
+    return letters * 2 >= text.len() && digits * 4 <= text.len() && punctuation && !mixed_case;
+}
+
+/// Parses a character map, one `byte=char` or `first-last=first-last` range per
+/// line with the bytes in hex, e.g. `0a-23=A-Z`. `#` starts a comment.
+pub fn parse_charmap(s: &str) -> Result<BTreeMap<u8, char>, String> {
+    let mut charmap = BTreeMap::new();
+    for line in s.lines() {
+        // not trimmed at the end, the char may be a space
+        let line = line.split('#').next().unwrap_or_default().trim_start();
+        if line.trim_end().is_empty() {
+            continue;
+        }
+        let invalid = || {
+            format!(
+                "expected byte=char or first-last=first-last, found \"{}\"",
+                line
+            )
+        };
+        let (bytes, chars) = line.split_once('=').ok_or_else(invalid)?;
+        let (first_byte, last_byte) = bytes.split_once('-').unwrap_or((bytes, bytes));
+        let parse_byte = |b: &str| u8::from_str_radix(b.trim().trim_start_matches('$'), 16);
+        let first_byte = parse_byte(first_byte).map_err(|_| invalid())?;
+        let last_byte = parse_byte(last_byte).map_err(|_| invalid())?;
+        let chars = match chars.trim() {
+            "" => " ",
+            chars => chars,
+        };
+        let (first_char, last_char) = match chars.chars().collect::<Vec<char>>()[..] {
+            [c] => (c, c),
+            [first, '-', last] => (first, last),
+            _ => return Result::Err(invalid()),
+        };
+        if last_byte < first_byte
+            || last_char < first_char
+            || last_byte as u32 - first_byte as u32 != last_char as u32 - first_char as u32
+        {
+            return Result::Err(format!("the ranges in \"{}\" differ in length", line));
+        }
+        for (byte, c) in (first_byte..=last_byte).zip(first_char..=last_char) {
+            // a .charmap per char, it can only stand for one byte
+            if charmap.values().any(|mapped| *mapped == c) {
+                return Result::Err(format!("'{}' is mapped from two bytes", c));
+            }
+            charmap.insert(byte, c);
+        }
+    }
+    return Result::Ok(charmap);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text_at() {
+        let ascii = BTreeMap::new();
+        assert_eq!(
+            text_at(b"GAME OVER\x00\xff", &ascii, 8),
+            Option::Some("GAME OVER".to_string())
+        );
+        assert_eq!(text_at(b"GAME\x00", &ascii, 8), Option::None);
+        assert_eq!(text_at(b"          ", &ascii, 8), Option::None);
+        assert_eq!(
+            text_at(b"GAME OVER    ", &ascii, 8),
+            Option::Some("GAME OVER".to_string())
+        );
+        assert_eq!(text_at(b"12345678", &ascii, 8), Option::None);
+        assert_eq!(text_at(b"AmKF!8R[", &ascii, 8), Option::None);
+
+        let charmap = parse_charmap("# letters\n0a-23=A-Z\n$00= \n").unwrap();
+        assert_eq!(charmap[&0x0a], 'A');
+        assert_eq!(charmap[&0x23], 'Z');
+        assert_eq!(
+            text_at(&[0x10, 0x0a, 0x16, 0x0e, 0x00, 0x18], &charmap, 6),
+            Option::Some("GAME O".to_string())
+        );
+        assert!(parse_charmap("0a-23=A-Y").is_err());
+        assert!(parse_charmap("0a=A\n4a=A").is_err());
+    }
+}
@@ -4,13 +4,17 @@ use std::fmt;
 pub enum VariableValue {
     U8(u8),
     U16(u16),
+    /// address of a label in the code, which defines it instead of an assignment
+    Label(u16),
+    /// address always written as a number, see [`super::code::Code::keep_unnamed`]
+    Unnamed(u16),
 }
 
 impl fmt::Display for VariableValue {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         return match self {
             Self::U8(v) => write!(f, "${:02X?}", v),
-            Self::U16(v) => write!(f, "${:04X?}", v),
+            Self::U16(v) | Self::Label(v) | Self::Unnamed(v) => write!(f, "${:04X?}", v),
         };
     }
 }
@@ -20,3 +24,10 @@ pub struct Variable {
     pub name: String,
     pub value: VariableValue,
 }
+
+impl Variable {
+    /// Whether operands at the address are written with the name.
+    pub fn is_named(&self) -> bool {
+        return !matches!(self.value, VariableValue::Unnamed(_));
+    }
+}
@@ -0,0 +1,13 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+#![allow(clippy::needless_return)]
+
+#[cfg(feature = "std")]
+pub mod assemble;
+pub mod decode;
+pub mod decode65816;
+pub mod decode_huc6280;
+#[cfg(feature = "std")]
+pub mod disassemble;
+#[cfg(feature = "std")]
+pub mod linker_file;
+pub mod opcodes;
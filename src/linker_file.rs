@@ -1,21 +1,31 @@
 use std::{collections::HashMap, fmt, path::PathBuf};
 
 use nom::{
-    character::complete::{alpha1, alphanumeric1, char, multispace0, space0},
-    error::{context, ErrorKind, ParseError as NomParseError, VerboseError},
+    branch::alt,
+    bytes::complete::tag,
+    character::complete::{
+        alpha1, alphanumeric1, char, multispace0, multispace1, not_line_ending, space0,
+    },
+    combinator::{cut, eof, recognize, value},
+    error::{context, ErrorKind, ParseError as NomParseError},
     multi::many0,
-    sequence::tuple,
-    AsChar, Err as NomErr, IResult, InputTakeAtPosition, Needed,
+    sequence::{delimited, pair, preceded, terminated, tuple},
+    AsChar, Err as NomErr, IResult, InputTakeAtPosition, Needed, Offset,
 };
-use nom_supreme::multi::parse_separated_terminated;
-use nom_supreme::ParserExt;
+use nom_supreme::{
+    error::{BaseErrorKind, ErrorTree, Expectation, GenericErrorTree, StackContext},
+    final_parser::Location,
+    multi::parse_separated_terminated,
+    ParserExt,
+};
+
+use crate::{assemble::expression::expr, disassemble::builtin_linker_config};
 
 #[derive(Debug)]
 pub enum ReadLinkerFileError {
     MissingFile(PathBuf),
     IoError(std::io::Error),
-    ParseError(VerboseError<String>),
-    ParseFailure(VerboseError<String>),
+    ParseError(SyntaxError),
     ParseIncomplete(Needed),
 }
 
@@ -25,27 +35,6 @@ impl From<std::io::Error> for ReadLinkerFileError {
     }
 }
 
-fn verbose_error_to_string(err: VerboseError<&str>) -> VerboseError<String> {
-    let mut result = VerboseError::from_error_kind("".to_string(), ErrorKind::Alpha);
-    result.errors.clear();
-    for err_item in err.errors {
-        result.errors.push((err_item.0.to_string(), err_item.1));
-    }
-    return result;
-}
-
-impl From<nom::Err<VerboseError<&str>>> for ReadLinkerFileError {
-    fn from(err: NomErr<VerboseError<&str>>) -> Self {
-        return match err {
-            nom::Err::Error(err) => ReadLinkerFileError::ParseError(verbose_error_to_string(err)),
-            nom::Err::Failure(err) => {
-                ReadLinkerFileError::ParseFailure(verbose_error_to_string(err))
-            }
-            nom::Err::Incomplete(needed) => ReadLinkerFileError::ParseIncomplete(needed),
-        };
-    }
-}
-
 impl fmt::Display for ReadLinkerFileError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -58,9 +47,6 @@ impl fmt::Display for ReadLinkerFileError {
             ReadLinkerFileError::ParseError(err) => {
                 write!(f, "Parse error {}", err)
             }
-            ReadLinkerFileError::ParseFailure(err) => {
-                write!(f, "Parse failure {}", err)
-            }
             ReadLinkerFileError::ParseIncomplete(needed) => {
                 write!(f, "Parse incomplete {:?}", needed)
             }
@@ -68,6 +54,121 @@ impl fmt::Display for ReadLinkerFileError {
     }
 }
 
+/// Where a linker file stops parsing and what would have let it continue.
+#[derive(Debug, PartialEq)]
+pub struct SyntaxError {
+    /// line and column of the first character that doesn't parse, from 1
+    pub location: Location,
+    /// the whole line holding it
+    pub line: String,
+    /// what could have come there, like `';'` or `a value`
+    pub expected: Vec<String>,
+    /// what was being parsed, outermost first, like `category` and `item`
+    pub contexts: Vec<&'static str>,
+}
+
+impl SyntaxError {
+    /// Picks the errors that got furthest into `input` out of everything the
+    /// parsers tried, since that is where the file has to be fixed. Each is placed
+    /// right after what parsed before it, separators skipping whitespace up to the
+    /// next line would otherwise put them a line after what is missing.
+    fn new(input: &str, tree: ErrorTree<&str>) -> SyntaxError {
+        let tree = tree.map_locations(|tail| {
+            let parsed = input[..input.offset(tail)].trim_end();
+            return Location::locate_tail(input, &input[parsed.len()..]);
+        });
+        let mut errors = Vec::new();
+        collect_errors(&tree, &[], &mut errors);
+        let location = errors
+            .iter()
+            .map(|(location, _, _)| *location)
+            .max_by_key(|location| (location.line, location.column))
+            .unwrap_or(Location { line: 1, column: 1 });
+        let mut expected = Vec::new();
+        let mut contexts = Vec::new();
+        for (_, expectation, error_contexts) in errors.into_iter().filter(|e| e.0 == location) {
+            if !expected.contains(&expectation) {
+                expected.push(expectation);
+            }
+            if error_contexts.len() > contexts.len() {
+                contexts = error_contexts;
+            }
+        }
+        return SyntaxError {
+            location,
+            line: input
+                .lines()
+                .nth(location.line - 1)
+                .unwrap_or("")
+                .to_string(),
+            expected,
+            contexts,
+        };
+    }
+}
+
+/// Every base error in `tree` with its location, a description of what was
+/// expected and the contexts around it.
+fn collect_errors(
+    tree: &ErrorTree<Location>,
+    contexts: &[&'static str],
+    errors: &mut Vec<(Location, String, Vec<&'static str>)>,
+) {
+    match tree {
+        GenericErrorTree::Base { location, kind } => {
+            let expected = match kind {
+                BaseErrorKind::Expected(Expectation::Eof) => "end of file".to_string(),
+                BaseErrorKind::Expected(expectation) => expectation.to_string(),
+                // only `not_arg_end` takes until something
+                BaseErrorKind::Kind(ErrorKind::TakeUntil) => "a value".to_string(),
+                BaseErrorKind::Kind(kind) => kind.description().to_lowercase(),
+                BaseErrorKind::External(err) => err.to_string(),
+            };
+            errors.push((*location, expected, contexts.to_vec()));
+        }
+        GenericErrorTree::Stack {
+            base,
+            contexts: stack,
+        } => {
+            // the stack holds the innermost context first
+            let mut contexts = contexts.to_vec();
+            contexts.extend(stack.iter().rev().filter_map(|(_, context)| match context {
+                StackContext::Context(context) => Option::Some(*context),
+                StackContext::Kind(_) => Option::None,
+            }));
+            collect_errors(base, &contexts, errors);
+        }
+        GenericErrorTree::Alt(siblings) => {
+            for sibling in siblings {
+                collect_errors(sibling, contexts, errors);
+            }
+        }
+    }
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "at {:#}", self.location)?;
+        if let Option::Some(context) = self.contexts.last() {
+            write!(f, " in {}", context)?;
+        }
+        if let Option::Some((last, rest)) = self.expected.split_last() {
+            if rest.is_empty() {
+                write!(f, ": expected {}", last)?;
+            } else {
+                write!(f, ": expected {} or {}", rest.join(", "), last)?;
+            }
+        }
+        // the column counts bytes, the caret has to count characters
+        let before = self
+            .line
+            .get(..self.location.column - 1)
+            .map(|before| before.chars().count())
+            .unwrap_or(self.location.column - 1);
+        return write!(f, "\n    {}\n    {}^", self.line, " ".repeat(before));
+    }
+}
+
 #[derive(Debug)]
 pub struct Item {
     arguments: HashMap<String, String>,
@@ -79,9 +180,57 @@ impl PartialEq for Item {
     }
 }
 
+impl Item {
+    pub fn argument(&self, name: &str) -> Option<&str> {
+        return self.arguments.get(name).map(|v| v.as_str());
+    }
+
+    /// The argument `name` as a number, written in `$` hex, `%` binary or decimal,
+    /// decimal optionally in kilobytes like `16K`, or as an expression of numbers
+    /// and `symbols` like `__STACKSIZE__ - $100`.
+    pub fn value(&self, name: &str, symbols: &HashMap<String, i64>) -> Result<Option<i64>, String> {
+        let value = match self.argument(name) {
+            Option::Some(value) => value,
+            Option::None => return Result::Ok(Option::None),
+        };
+        return match expr(&expand_kilobytes(value)) {
+            Result::Ok(("", e)) => e
+                .eval(symbols, 0)
+                .map(Option::Some)
+                .map_err(|err| format!("{} = {}: {}", name, value, err)),
+            _ => Result::Err(format!("invalid value {} = {}", name, value)),
+        };
+    }
+}
+
+/// `value` with the decimal numbers ending in `K` multiplied out, which the
+/// assembler's expressions don't know.
+fn expand_kilobytes(value: &str) -> String {
+    let mut result = String::new();
+    let mut rest = value;
+    while let Option::Some(c) = rest.chars().next() {
+        // hex numbers and identifiers can hold digits followed by a `K`
+        let word = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '$'))
+            .unwrap_or(rest.len())
+            .max(c.len_utf8());
+        let (token, next) = rest.split_at(word);
+        match token.strip_suffix(['K', 'k']) {
+            Option::Some(n) if !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()) => {
+                result += &format!("({} * 1024)", n);
+            }
+            _ => result += token,
+        }
+        rest = next;
+    }
+    return result;
+}
+
+/// Items are kept in file order since ld65 places memory areas and segments in
+/// the order they are declared, and names like `CONDES` may repeat.
 #[derive(Debug)]
 pub struct Category {
-    items: HashMap<String, Item>,
+    items: Vec<(String, Item)>,
 }
 
 impl PartialEq for Category {
@@ -90,6 +239,12 @@ impl PartialEq for Category {
     }
 }
 
+impl Category {
+    pub fn items(&self) -> &[(String, Item)] {
+        return &self.items;
+    }
+}
+
 #[derive(Debug)]
 pub struct LinkerFile {
     categories: HashMap<String, Category>,
@@ -101,12 +256,18 @@ impl PartialEq for LinkerFile {
     }
 }
 
+impl LinkerFile {
+    pub fn category(&self, name: &str) -> Option<&Category> {
+        return self.categories.get(name);
+    }
+}
+
 pub fn read_linker_file(linker_file: String) -> Result<LinkerFile, ReadLinkerFileError> {
     if linker_file == "nes" {
-        let str = include_str!("linker/nes.cfg");
-        return read_linker_from_string(str)
-            .map_err(|err| ReadLinkerFileError::from(err))
-            .map(|res| res.1);
+        return parse_linker_file(include_str!("linker/nes.cfg"));
+    }
+    if let Option::Some(config) = builtin_linker_config(&linker_file) {
+        return parse_linker_file(&config);
     }
 
     let file = PathBuf::from(linker_file);
@@ -115,57 +276,80 @@ pub fn read_linker_file(linker_file: String) -> Result<LinkerFile, ReadLinkerFil
     }
 
     let str = std::fs::read_to_string(file.as_path())?;
-    return read_linker_from_string(str.as_str())
-        .map_err(|err| ReadLinkerFileError::from(err))
-        .map(|res| res.1);
+    return parse_linker_file(str.as_str());
+}
+
+pub fn parse_linker_file(input: &str) -> Result<LinkerFile, ReadLinkerFileError> {
+    return match read_linker_from_string(input) {
+        Result::Ok((_, linker_file)) => Result::Ok(linker_file),
+        Result::Err(NomErr::Error(err)) | Result::Err(NomErr::Failure(err)) => Result::Err(
+            ReadLinkerFileError::ParseError(SyntaxError::new(input, err)),
+        ),
+        Result::Err(NomErr::Incomplete(needed)) => {
+            Result::Err(ReadLinkerFileError::ParseIncomplete(needed))
+        }
+    };
 }
 
-type Res<T, U> = IResult<T, U, VerboseError<T>>;
+type Res<T, U> = IResult<T, U, ErrorTree<T>>;
 
 #[rustfmt::skip]
 fn read_linker_from_string(input: &str) -> Res<&str, LinkerFile> {
     return context(
         "linker file",
-        many0(category)
-    )(input).and_then(|(next_input, res)| {
+        delimited(
+            ws,
+            many0(terminated(category, ws)),
+            eof
+        )
+    )(input).map(|(next_input, res)| {
         let mut categories = HashMap::new();
         for category in res {
             categories.insert(category.0, category.1);
         }
-        return Result::Ok((
+        (
             next_input,
             LinkerFile {
                 categories
             }
-        ));
+        )
     });
 }
 
+/// whitespace including newlines and `#` comments
+fn ws(input: &str) -> Res<&str, ()> {
+    return value(
+        (),
+        many0(alt((multispace1, preceded(char('#'), not_line_ending)))),
+    )(input);
+}
+
+fn identifier(input: &str) -> Res<&str, &str> {
+    return recognize(pair(
+        alt((alpha1, tag("_"))),
+        many0(alt((alphanumeric1, tag("_")))),
+    ))(input);
+}
+
 #[rustfmt::skip]
 fn category(input: &str) -> Res<&str, (String, Category)> {
     return context(
         "category",
-        tuple((
-          alphanumeric1,
-          multispace0,
-          char('{'),
-          multispace0,
-          parse_separated_terminated(
-            item,
-            char(';').delimited_by(space0),
-            char('}').preceded_by(space0),
-            HashMap::new,
-            |mut map, arg| {
-                map.insert(arg.0, arg.1);
-                map
-            },
-          ),
-        ))
-    )(input).and_then(|(next_input, res)| {
-        return Result::Ok((
+        pair(
+          identifier,
+          // past the name it can only be this category, so an item that doesn't
+          // parse is reported rather than backtracked over
+          cut(delimited(
+            tuple((ws, char('{'), ws)),
+            many0(terminated(item, ws)),
+            char('}'),
+          )),
+        )
+    )(input).map(|(next_input, res)| {
+        (
             next_input,
-            (res.0.to_string(), Category { items: res.4 })
-        ));
+            (res.0.to_string(), Category { items: res.1 })
+        )
     });
 }
 
@@ -173,32 +357,32 @@ fn category(input: &str) -> Res<&str, (String, Category)> {
 fn item(input: &str) -> Res<&str, (String, Item)> {
     return context(
         "item",
-        tuple((
-            alphanumeric1,
-            multispace0,
-            char(':'),
-            multispace0,
-            parse_separated_terminated(
-                arg,
-                char(',').delimited_by(space0),
-                char(';').preceded_by(space0),
-                HashMap::new,
-                |mut map, arg| {
-                    map.insert(arg.0.to_string(), arg.1.to_string());
-                    map
-                },
-            )
-        ))        
-    )(input).and_then(|(next_input, res)| {
-        return Result::Ok((
+        pair(
+            identifier,
+            cut(preceded(
+                tuple((multispace0, char(':'), multispace0)),
+                parse_separated_terminated(
+                    arg,
+                    char(',').delimited_by(ws),
+                    char(';').preceded_by(space0),
+                    HashMap::new,
+                    |mut map, arg| {
+                        map.insert(arg.0.to_string(), arg.1.trim_end().to_string());
+                        map
+                    },
+                )
+            ))
+        )
+    )(input).map(|(next_input, res)| {
+        (
             next_input,
             (
                 res.0.to_string(),
                 Item {
-                    arguments: HashMap::from(res.4)
+                    arguments: res.1
                 }
             )
-        ));
+        )
     });
 }
 
@@ -210,7 +394,9 @@ where
     return input.split_at_position1_complete(
         |item| {
             let c = item.as_char();
-            return c == ',' || c == ';';
+            // a value doesn't go past the end of its line, so a missing `;` is
+            // reported there rather than at the next item
+            return c == ',' || c == ';' || c == '\n';
         },
         ErrorKind::TakeUntil,
     );
@@ -233,22 +419,18 @@ fn arg(input: &str) -> Res<&str, (&str, &str)> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use nom::{
-        error::{ErrorKind, VerboseError, VerboseErrorKind},
-        Err as NomErr,
-    };
 
     #[test]
     fn test_read_linker_from_string() {
         assert_eq!(
-            read_linker_from_string("MEMORY { ZP: file = \"\", start = $0002; }"),
-            Ok((
+            read_linker_from_string("MEMORY { ZP: file = \"\", start = $0002; }").ok(),
+            Option::Some((
                 "",
                 LinkerFile {
                     categories: HashMap::from([(
                         "MEMORY".to_string(),
                         Category {
-                            items: HashMap::from([(
+                            items: vec![(
                                 "ZP".to_string(),
                                 Item {
                                     arguments: HashMap::from([
@@ -256,7 +438,7 @@ mod tests {
                                         ("start".to_string(), "$0002".to_string())
                                     ])
                                 }
-                            )])
+                            )]
                         }
                     )])
                 }
@@ -264,11 +446,59 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_read_nes_linker_file() {
+        let linker_file = read_linker_file("nes".to_string()).unwrap();
+        let memory = linker_file.category("MEMORY").unwrap();
+        assert_eq!(
+            memory
+                .items()
+                .iter()
+                .map(|i| i.0.as_str())
+                .collect::<Vec<&str>>(),
+            vec!["ZP", "HEADER", "ROM0", "ROMV", "ROM2", "SRAM", "RAM"]
+        );
+        assert_eq!(memory.items()[2].1.argument("start"), Option::Some("$8000"));
+        assert_eq!(
+            linker_file.category("SYMBOLS").unwrap().items()[0]
+                .1
+                .argument("value"),
+            Option::Some("$0300")
+        );
+        assert_eq!(linker_file.category("FEATURES").unwrap().items().len(), 3);
+    }
+
+    #[test]
+    fn test_value() {
+        let (_, (_, item)) = item(
+            "ROM: start = $8000, size = 16K, fill = %1010, bank = 2, \
+             stack = __STACKSIZE__ - $100, last = ZP_LAST, page = 1k * 2;",
+        )
+        .unwrap();
+        let symbols = HashMap::from([("__STACKSIZE__".to_string(), 0x300)]);
+        let value = |name| item.value(name, &symbols);
+        assert_eq!(value("start"), Result::Ok(Option::Some(0x8000)));
+        assert_eq!(value("size"), Result::Ok(Option::Some(0x4000)));
+        assert_eq!(value("fill"), Result::Ok(Option::Some(10)));
+        assert_eq!(value("bank"), Result::Ok(Option::Some(2)));
+        assert_eq!(value("stack"), Result::Ok(Option::Some(0x200)));
+        assert_eq!(value("page"), Result::Ok(Option::Some(2048)));
+        assert_eq!(value("type"), Result::Ok(Option::None));
+        assert_eq!(
+            value("last"),
+            Result::Err("last = ZP_LAST: undefined symbol \"ZP_LAST\"".to_string())
+        );
+        assert_eq!(
+            expand_kilobytes("$1K + 2K + SIZE_2K"),
+            "$1K + (2 * 1024) + SIZE_2K"
+        );
+    }
+
     #[test]
     fn test_item() {
         assert_eq!(
-            item("ZP: file = \"\", start = $0002;"),
-            Ok((
+            item("ZP: file = \"\", start = $0002;").ok(),
+            Option::Some((
                 "",
                 (
                     String::from("ZP"),
@@ -285,16 +515,39 @@ mod tests {
 
     #[test]
     fn test_arg() {
-        assert_eq!(arg("file = \"\";"), Ok((";", ("file", "\"\""))));
-        assert_eq!(arg("file=%O,"), Ok((",", ("file", "%O"))));
         assert_eq!(
-            arg("file=;"),
-            Err(NomErr::Error(VerboseError {
-                errors: vec![
-                    (";", VerboseErrorKind::Nom(ErrorKind::TakeUntil)),
-                    ("file=;", VerboseErrorKind::Context("arg")),
-                ]
-            }))
+            arg("file = \"\";").ok(),
+            Option::Some((";", ("file", "\"\"")))
+        );
+        assert_eq!(arg("file=%O,").ok(), Option::Some((",", ("file", "%O"))));
+        assert!(matches!(arg("file=;"), Result::Err(NomErr::Error(_))));
+    }
+
+    #[test]
+    fn test_syntax_error() {
+        let message = |input| match parse_linker_file(input) {
+            Result::Err(ReadLinkerFileError::ParseError(err)) => err.to_string(),
+            _ => panic!("{} parsed", input),
+        };
+        assert_eq!(
+            message("MEMORY {\n    ZP: file = \"\", start = $0002\n}\n"),
+            "at line 2, column 33 in item: expected ',' or ';'\n    \
+             \x20   ZP: file = \"\", start = $0002\n    \
+             \x20                               ^"
+        );
+        assert_eq!(
+            message("MEMORY {\n    ZP file = \"\";\n}\n"),
+            "at line 2, column 7 in item: expected ':'\n    \
+             \x20   ZP file = \"\";\n    \
+             \x20     ^"
+        );
+        assert_eq!(
+            message("MEMORY {\n    ZP: file = ;\n}\n").lines().next(),
+            Option::Some("at line 2, column 15 in arg: expected a value")
+        );
+        assert_eq!(
+            message("MEMORY\n").lines().next(),
+            Option::Some("at line 1, column 7 in category: expected '{'")
         );
     }
 }
@@ -1,22 +1,136 @@
-use clap::{Parser, Subcommand};
-use std::{fmt::Debug, path::PathBuf, process};
+#![allow(clippy::needless_return)]
 
-mod disassemble;
+use clap::{Args, Parser, Subcommand};
+use std::{collections::BTreeMap, fmt::Debug, path::PathBuf, process};
 
-use disassemble::{disassemble, DisassembleOptions};
+use sixtyfive::{
+    assemble::{assemble, hook, AssembleOptions, HookOptions},
+    disassemble::{
+        banks, clean, disassemble, display_name, header, info, list, parse_org, repl, serve,
+        AnalysisOptions, BanksOptions, CleanOptions, Cpu, CpuVariant, DiagnosticsFormat, Dialect,
+        DisassembleOptions, HeaderOptions, IllegalOpcodes, InfoOptions, JamOpcodes, ListOptions,
+        OutputFormat, Pass, ReplOptions, ServeOptions, TileFormat, DEFAULT_SERVE_PORT,
+    },
+};
 
 #[derive(Debug, Parser)]
 #[clap(name = "sixtyfive")]
 #[clap(about = "A 6502 disassembler/assembler", long_about = None)]
+#[clap(args_conflicts_with_subcommands = true, arg_required_else_help = true)]
 struct Cli {
     #[clap(subcommand)]
-    command: Commands,
+    command: Option<Commands>,
+
+    /// disassembles to stdout given no subcommand, as in `sixtyfive rom.nes`
+    #[clap(flatten)]
+    disassemble: DisassembleArgs,
 }
 
+// parsed once, the size of the disassembler's many options doesn't matter
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, Subcommand)]
 enum Commands {
-    #[clap(arg_required_else_help = true, about = "disassemble a binary")]
-    D {
+    #[clap(
+        arg_required_else_help = true,
+        visible_aliases = &["dis", "d"],
+        about = "disassemble a binary, what sixtyfive does given no subcommand"
+    )]
+    Disassemble(DisassembleArgs),
+
+    #[clap(about = "show the header of a ROM")]
+    Info {
+        #[clap(value_parser, help = "path to ROM otherwise stdin")]
+        in_file: Option<PathBuf>,
+    },
+
+    #[clap(
+        arg_required_else_help = true,
+        about = "rewrite header fields of a ROM"
+    )]
+    Header {
+        #[clap(
+            short = 'o',
+            long = "out",
+            value_parser,
+            help = "output file otherwise stdout"
+        )]
+        out: Option<PathBuf>,
+
+        #[clap(
+            long = "set",
+            value_parser,
+            help = "set a field as named in the d --json-out header, e.g. mapper=4, mirroring=vertical or prg_ram_size=8k"
+        )]
+        set: Vec<String>,
+
+        #[clap(
+            long = "nes2",
+            help = "upgrade an iNES header to NES 2.0 before setting fields"
+        )]
+        nes2: bool,
+
+        #[clap(value_parser, help = "path to ROM")]
+        in_file: PathBuf,
+    },
+
+    #[clap(
+        arg_required_else_help = true,
+        about = "list the PRG and CHR ROM banks of a ROM with their CRC-32, or extract, replace and reorder them"
+    )]
+    Banks {
+        #[clap(
+            short = 'o',
+            long = "out",
+            value_parser,
+            help = "output file otherwise stdout"
+        )]
+        out: Option<PathBuf>,
+
+        #[clap(
+            long = "extract",
+            value_parser,
+            conflicts_with_all = &["replace", "order"],
+            help = "write a single bank, e.g. prg:3"
+        )]
+        extract: Option<String>,
+
+        #[clap(
+            long = "replace",
+            value_parser,
+            help = "replace a bank with a file of the same size, e.g. chr:0=font.chr"
+        )]
+        replace: Vec<String>,
+
+        #[clap(
+            long = "order",
+            value_parser,
+            help = "rebuild the PRG or CHR ROM from banks in this order, e.g. prg:1,0,3,2"
+        )]
+        order: Vec<String>,
+
+        #[clap(value_parser, help = "path to ROM")]
+        in_file: PathBuf,
+    },
+
+    #[clap(
+        arg_required_else_help = true,
+        about = "remove what disassemble wrote into a project directory, keeping the files changed since"
+    )]
+    Clean {
+        #[clap(
+            long = "dry-run",
+            help = "print what would be removed instead of removing it"
+        )]
+        dry_run: bool,
+
+        #[clap(value_parser, help = "directory given to d with -o")]
+        dir: PathBuf,
+    },
+
+    #[clap(
+        about = "list a raw binary decoded linearly, e.g. SNES code with --cpu 65816 or a PC Engine HuCard with --cpu huc6280"
+    )]
+    List {
         #[clap(
             short = 'o',
             long = "out",
@@ -25,17 +139,575 @@ enum Commands {
         )]
         out: Option<PathBuf>,
 
-        #[clap(value_parser, help = "path to binary to disassemble otherwise stdin")]
+        #[clap(
+            long = "cpu",
+            value_parser,
+            default_value = "6502",
+            help = "instruction set: 6502, 65816 or huc6280 (PC Engine)"
+        )]
+        cpu: Cpu,
+
+        #[clap(
+            long = "org",
+            value_parser = parse_org,
+            default_value = "0",
+            help = "address of the first byte, e.g. $8000 or $c08000 with a 65816 bank"
+        )]
+        org: u32,
+
+        #[clap(
+            long = "m16",
+            help = "start in native mode with a 16-bit accumulator (65816)"
+        )]
+        m16: bool,
+
+        #[clap(
+            long = "x16",
+            help = "start in native mode with 16-bit index registers (65816)"
+        )]
+        x16: bool,
+
+        #[clap(value_parser, help = "path to binary otherwise stdin")]
         in_file: Option<PathBuf>,
     },
+
+    #[clap(
+        arg_required_else_help = true,
+        about = "interactively disassemble a ROM, reading commands from stdin"
+    )]
+    Repl {
+        #[clap(
+            short = 'o',
+            long = "out",
+            value_parser,
+            help = "file written by save otherwise the ROM path with a .s extension"
+        )]
+        out: Option<PathBuf>,
+
+        #[clap(value_parser, help = "path to ROM")]
+        in_file: PathBuf,
+    },
+
+    #[clap(about = "serve the analysis API as line-delimited JSON-RPC on localhost")]
+    Serve {
+        #[clap(
+            short = 'p',
+            long = "port",
+            value_parser,
+            default_value_t = DEFAULT_SERVE_PORT,
+            help = "TCP port to listen on"
+        )]
+        port: u16,
+    },
+
+    #[clap(
+        arg_required_else_help = true,
+        visible_aliases = &["asm", "a"],
+        about = "assemble a source file or a project directory"
+    )]
+    Assemble {
+        #[clap(
+            short = 'o',
+            long = "out",
+            value_parser,
+            help = "output file otherwise stdout"
+        )]
+        out: Option<PathBuf>,
+
+        #[clap(
+            short = 'D',
+            long = "define",
+            value_parser,
+            help = "define a symbol as NAME=value (value defaults to 1)"
+        )]
+        defines: Vec<String>,
+
+        #[clap(
+            short = 'C',
+            long = "config",
+            value_parser,
+            help = "linker config, a built-in name (nes, nes-nrom-128, nes-mmc1, c64-asm, atari2600) or path to an ld65 config"
+        )]
+        config: Option<String>,
+
+        #[clap(
+            long = "patch",
+            value_parser,
+            help = "assemble over this ROM, every segment needs an .org"
+        )]
+        patch: Option<PathBuf>,
+
+        #[clap(
+            long = "allow-grow",
+            help = "move patches that don't fit to a .free block, leaving a jmp behind"
+        )]
+        allow_grow: bool,
+
+        #[clap(
+            long = "long-branches",
+            help = "assemble branches that can't reach their target as the inverted branch over a jmp"
+        )]
+        long_branches: bool,
+
+        #[clap(
+            value_parser,
+            help = "path to source or project directory to assemble otherwise stdin"
+        )]
+        in_file: Option<PathBuf>,
+    },
+
+    #[clap(
+        arg_required_else_help = true,
+        about = "patch a ROM so a routine is called from an address via a trampoline"
+    )]
+    Hook {
+        #[clap(
+            short = 'o',
+            long = "out",
+            value_parser,
+            help = "output file otherwise stdout"
+        )]
+        out: Option<PathBuf>,
+
+        #[clap(long = "at", value_parser, help = "address to hook, e.g. $8123")]
+        at: String,
+
+        #[clap(
+            long = "free",
+            value_parser,
+            help = "unused range for the trampoline and routine, e.g. $9f00..$9fff"
+        )]
+        free: String,
+
+        #[clap(
+            short = 'D',
+            long = "define",
+            value_parser,
+            help = "define a symbol as NAME=value (value defaults to 1)"
+        )]
+        defines: Vec<String>,
+
+        #[clap(value_parser, help = "path to ROM to patch")]
+        rom: PathBuf,
+
+        #[clap(
+            value_parser,
+            help = "path to the routine, a subroutine ending in rts, otherwise stdin"
+        )]
+        routine: Option<PathBuf>,
+    },
+}
+
+#[derive(Debug, Args)]
+struct DisassembleArgs {
+    #[clap(
+        short = 'o',
+        long = "out",
+        value_parser,
+        help = "output file otherwise stdout, in a directory named after the ROM with a REPORT.md"
+    )]
+    out: Option<PathBuf>,
+
+    #[clap(
+        long = "json-out",
+        value_parser,
+        help = "also write the analysis as JSON to this file"
+    )]
+    json_out: Option<PathBuf>,
+
+    #[clap(
+        long = "symbols-out",
+        value_parser,
+        help = "also write labels, comments and variables to this file as a Mesen label file (.mlb), to load into its debugger"
+    )]
+    symbols_out: Option<PathBuf>,
+
+    #[clap(
+        long = "fceux-nl",
+        value_parser,
+        help = "also write labels, comments and variables as FCEUX name lists (.nl), to this path with .ram.nl and .<bank>.nl appended for each 16k PRG ROM bank, as FCEUX numbers them"
+    )]
+    fceux_nl: Option<PathBuf>,
+
+    #[clap(
+        long = "source-map",
+        value_parser,
+        help = "also write a JSON map from output lines to ROM offsets and addresses"
+    )]
+    source_map: Option<PathBuf>,
+
+    #[clap(
+        long = "call-graph",
+        value_parser,
+        help = "also write which subroutines call which, starting from the vectors, as Graphviz DOT if the file ends in .dot or .gv and as JSON otherwise"
+    )]
+    call_graph: Option<PathBuf>,
+
+    #[clap(
+        long = "unreached",
+        value_parser,
+        help = "also write the PRG ROM regions no vector reaches, with their sizes, to this file"
+    )]
+    unreached: Option<PathBuf>,
+
+    #[clap(
+        long = "access-map",
+        value_parser,
+        help = "also write what the decoded instructions read, write and execute at each RAM, register and ROM address to this file"
+    )]
+    access_map: Option<PathBuf>,
+
+    #[clap(
+        long = "tiles-out",
+        value_parser,
+        help = "also write the tiles of the CHR ROM and the ones copied to CHR RAM as a PGM image to this file"
+    )]
+    tiles_out: Option<PathBuf>,
+
+    #[clap(
+        long = "linker-config",
+        value_parser,
+        help = "also write an ld65 config placing the segments where they are in the ROM, so the assembly links back to the same bytes"
+    )]
+    linker_config: Option<PathBuf>,
+
+    #[clap(
+        long = "dialect",
+        value_parser,
+        help = "assembler the output is written for: ca65, asm6 (also asm6f) with .base instead of segments and .db/.dw/.dsb, dasm or acme; by default ca65 for NES ROMs, dasm for Atari 2600 ROMs (.a26) and acme for C64 programs (.prg)"
+    )]
+    dialect: Option<Dialect>,
+
+    #[clap(
+        long = "format",
+        value_parser,
+        default_value = "asm",
+        help = "what the output is written as: asm, json for every statement with its address, bytes, mnemonic, operands, label, comment and segment, or html for a page to browse with the labels linked"
+    )]
+    format: OutputFormat,
+
+    #[clap(
+        long = "listing",
+        help = "write a listing with the address and raw bytes of each line before it, for reading next to a debugger rather than assembling"
+    )]
+    listing: bool,
+
+    #[clap(
+        long = "flat-symbols",
+        help = "keep all labels in one namespace instead of writing each segment as a ca65 .scope, labels used from other segments written as prgrom0::name"
+    )]
+    flat_symbols: bool,
+
+    #[clap(
+        long = "split-banks",
+        help = "write each PRG ROM bank to a file of its own next to the output, prgrom0.s and on, and the hardware register constants to registers.inc, all included from the output, main.s when it is a directory"
+    )]
+    split_banks: bool,
+
+    #[clap(
+        long = "diagnostics-format",
+        value_parser,
+        default_value = "human",
+        help = "how warnings are printed: human or gcc (file:bank:addr: warning: ...)"
+    )]
+    diagnostics_format: DiagnosticsFormat,
+
+    #[clap(
+        long = "sweep-threshold",
+        value_parser,
+        help = "score unreached PRG ROM on how much it looks like code (0..1), regions scoring at least this are reported as likely code, e.g. 0.6"
+    )]
+    sweep_threshold: Option<f64>,
+
+    #[clap(
+        long = "sweep",
+        help = "after following code from the vectors, also disassemble unreached PRG ROM scoring as code, at --sweep-threshold or 0.6"
+    )]
+    sweep: bool,
+
+    #[clap(
+        long = "inline-tables",
+        help = "write small data tables read by a single routine right after that routine"
+    )]
+    inline_tables: bool,
+
+    #[clap(
+        long = "name-data",
+        help = "name tables and RAM by how they are used, e.g. oam_data_* for tables copied to OAM in a loop, ppu_data_* for tables written to PPUDATA and init_table_* for RAM only written while starting up"
+    )]
+    name_data: bool,
+
+    #[clap(
+        long = "text",
+        help = "write runs of unclaimed PRG ROM bytes that read as text as .byte strings"
+    )]
+    text: bool,
+
+    #[clap(
+        long = "text-min-len",
+        value_parser,
+        help = "shortest run written as a string by --text, 8 if not given"
+    )]
+    text_min_len: Option<usize>,
+
+    #[clap(
+        long = "charmap",
+        value_parser,
+        help = "read --text through a character map, one byte=char or first-last=first-last per line in hex, e.g. 0a-23=A-Z, written as .charmap"
+    )]
+    charmap: Option<PathBuf>,
+
+    #[clap(
+        long = "signatures",
+        value_parser,
+        help = "also recognize the routines in this file, one name = pattern per line, the pattern hex bytes, ?? for any byte and names for zero page addresses, e.g. update = a5 ptr 48 a5 ptr+1 48"
+    )]
+    signatures: Option<PathBuf>,
+
+    #[clap(
+        long = "template",
+        value_parser,
+        help = "write the header, footer, segment banners and labels through the Handlebars templates header.hbs, footer.hbs, segment.hbs and label.hbs in this directory, any left out written as usual; needs the templates feature"
+    )]
+    template: Option<PathBuf>,
+
+    #[clap(
+        long = "passes",
+        value_parser,
+        value_delimiter = ',',
+        help = "the analysis passes to run and their order, from trace, sweep, strings, pointers and idioms, e.g. trace,sweep,idioms; replaces trace,idioms,pointers plus sweep and strings when --sweep and --text are given"
+    )]
+    passes: Vec<Pass>,
+
+    #[clap(
+        long = "strict",
+        help = "fail when a byte is claimed by two instructions or by code and data instead of warning"
+    )]
+    strict: bool,
+
+    #[clap(
+        long = "reached-from",
+        help = "comment code with the vectors (NMI, RESET, IRQ) it is reached from"
+    )]
+    reached_from: bool,
+
+    #[clap(
+        long = "xrefs",
+        help = "comment labels with the addresses of the jumps, branches and calls to them"
+    )]
+    xrefs: bool,
+
+    #[clap(
+        long = "procs",
+        help = "write the subroutines called with jsr as .proc/.endproc blocks instead of bare labels"
+    )]
+    procs: bool,
+
+    #[clap(
+        long = "verify",
+        help = "write the original bytes of each routine as .verify, which the assemble command checks the code still assembles to (ca65 doesn't know .verify)"
+    )]
+    verify: bool,
+
+    #[clap(
+        long = "cycles",
+        help = "comment instructions with the cycles they take, as a range when crossing a page or taking a branch adds some"
+    )]
+    cycles: bool,
+
+    #[clap(
+        long = "expressions",
+        help = "comment the end of short basic blocks with what they compute into the bytes they store, e.g. score_hi = score_hi + carry"
+    )]
+    expressions: bool,
+
+    #[clap(long = "progress", help = "write how far the analysis got to stderr")]
+    progress: bool,
+
+    #[clap(
+        long = "dry-run",
+        help = "print the format, mapper, banks, entry points and outputs without disassembling"
+    )]
+    dry_run: bool,
+
+    #[clap(
+        long = "force",
+        help = "overwrite files in the output directory that were edited since they were generated"
+    )]
+    force: bool,
+
+    #[clap(
+        long = "shared-variables",
+        help = "find and name RAM written by the NMI or IRQ handler and read by the main code, listed in --json-out"
+    )]
+    shared_variables: bool,
+
+    #[clap(
+        long = "illegal-opcodes",
+        value_parser,
+        default_value = "stop",
+        help = "what to do with undocumented opcodes in code: stop following the code, decode them as ca65 6502X mnemonics or as .byte with a comment"
+    )]
+    illegal_opcodes: IllegalOpcodes,
+
+    #[clap(
+        long = "jam",
+        value_parser,
+        default_value = "stop",
+        help = "what to do with opcodes that halt the CPU ($02, $12, ...): stop following the code there, write them as .byte and go on, or leave them as data with a warning"
+    )]
+    jam: JamOpcodes,
+
+    #[clap(
+        long = "only-from",
+        value_parser,
+        help = "only write the code reachable from a vector (reset, nmi, irq), an entry point like prgrom0_reset or an address like $c123, plus the data it reads"
+    )]
+    only_from: Option<String>,
+
+    #[clap(
+        long = "cpu-variant",
+        value_parser,
+        help = "6502 variant whose quirks apply: 6502, early-6502 (no ror), 6510 (I/O port at $00/$01) or 2a03 (no decimal mode), otherwise the platform's"
+    )]
+    cpu_variant: Option<CpuVariant>,
+
+    #[clap(
+        long = "tile-format",
+        value_parser,
+        help = "how the tiles of the graphics are laid out: nes (2 bitplanes one after the other), gb, snes4 (bitplanes in pairs of rows) or mono, otherwise the platform's"
+    )]
+    tile_format: Option<TileFormat>,
+
+    #[clap(
+        long = "unnamed",
+        value_parser,
+        help = "write operands at an address or $start..$end range as numbers, not with the names of registers, variables or labels there"
+    )]
+    unnamed: Vec<String>,
+
+    #[clap(
+        long = "export",
+        value_parser,
+        help = "also .export a label or the code or data at an address, the vectors' handlers always are"
+    )]
+    export: Vec<String>,
+
+    #[clap(
+        long = "vector-table",
+        value_parser,
+        help = "follow each entry of a table of code addresses, e.g. '$ffd0:8' for the 8 words at $ffd0, like the state handlers engines jump through"
+    )]
+    vector_table: Vec<String>,
+
+    #[clap(
+        long = "tag",
+        value_parser,
+        help = "tag the routine or data table at an address, e.g. audio=$8123, tags are listed in --json-out"
+    )]
+    tag: Vec<String>,
+
+    #[clap(
+        long = "include-tag",
+        value_parser,
+        help = "only write what is tagged with this tag"
+    )]
+    include_tag: Vec<String>,
+
+    #[clap(
+        long = "exclude-tag",
+        value_parser,
+        help = "leave out what is tagged with this tag"
+    )]
+    exclude_tag: Vec<String>,
+
+    #[clap(
+        long = "mapper-plugin",
+        value_parser,
+        help = "shared library registering mappers for mapper numbers sixtyfive doesn't know, needs the plugins feature"
+    )]
+    mapper_plugin: Vec<PathBuf>,
+
+    #[clap(value_parser, help = "path to binary to disassemble otherwise stdin")]
+    in_file: Option<PathBuf>,
 }
 
 fn main() {
     let args = Cli::parse();
 
-    match args.command {
-        Commands::D { in_file, out } => {
-            if let Result::Err(err) = disassemble(DisassembleOptions {
+    match args
+        .command
+        .unwrap_or(Commands::Disassemble(args.disassemble))
+    {
+        Commands::Disassemble(args) => run_disassemble(args),
+        Commands::Info { in_file } => {
+            if let Result::Err(err) = info(InfoOptions { in_file }) {
+                eprintln!("Error reading ROM: {}", err);
+                process::exit(1);
+            }
+        }
+        Commands::Header {
+            in_file,
+            out,
+            set,
+            nes2,
+        } => {
+            if let Result::Err(err) = header(HeaderOptions {
+                in_file,
+                out_file: out,
+                set,
+                nes2,
+            }) {
+                eprintln!("Error editing header: {}", err);
+                process::exit(1);
+            }
+        }
+        Commands::Banks {
+            in_file,
+            out,
+            extract,
+            replace,
+            order,
+        } => {
+            if let Result::Err(err) = banks(BanksOptions {
+                in_file,
+                out_file: out,
+                extract,
+                replace,
+                order,
+            }) {
+                eprintln!("Error editing banks: {}", err);
+                process::exit(1);
+            }
+        }
+        Commands::Clean { dry_run, dir } => {
+            if let Result::Err(err) = clean(CleanOptions { dir, dry_run }) {
+                eprintln!("Error cleaning: {}", err);
+                process::exit(1);
+            }
+        }
+        Commands::List {
+            in_file,
+            out,
+            cpu,
+            org,
+            m16,
+            x16,
+        } => {
+            if let Result::Err(err) = list(ListOptions {
+                in_file,
+                out_file: out,
+                cpu,
+                org,
+                m16,
+                x16,
+            }) {
+                eprintln!("Error listing: {}", err);
+                process::exit(1);
+            }
+        }
+        Commands::Repl { in_file, out } => {
+            if let Result::Err(err) = repl(ReplOptions {
                 in_file,
                 out_file: out,
             }) {
@@ -43,5 +715,254 @@ fn main() {
                 process::exit(1);
             }
         }
+        Commands::Serve { port } => {
+            if let Result::Err(err) = serve(ServeOptions { port }) {
+                eprintln!("Error serving: {}", err);
+                process::exit(1);
+            }
+        }
+        Commands::Assemble {
+            in_file,
+            out,
+            defines,
+            config,
+            patch,
+            allow_grow,
+            long_branches,
+        } => {
+            if let Result::Err(err) = assemble(AssembleOptions {
+                in_file,
+                out_file: out,
+                defines,
+                config,
+                patch,
+                allow_grow,
+                long_branches,
+            }) {
+                eprintln!("Error assembling: {}", err);
+                process::exit(1);
+            }
+        }
+        Commands::Hook {
+            out,
+            at,
+            free,
+            defines,
+            rom,
+            routine,
+        } => {
+            if let Result::Err(err) = hook(HookOptions {
+                rom_file: rom,
+                routine_file: routine,
+                out_file: out,
+                at,
+                free,
+                defines,
+            }) {
+                eprintln!("Error hooking: {}", err);
+                process::exit(1);
+            }
+        }
+    }
+}
+
+/// Runs `disassemble`, the subcommand or sixtyfive without one.
+fn run_disassemble(args: DisassembleArgs) {
+    let DisassembleArgs {
+        in_file,
+        out,
+        json_out,
+        symbols_out,
+        fceux_nl,
+        source_map,
+        call_graph,
+        unreached,
+        access_map,
+        tiles_out,
+        linker_config,
+        dialect,
+        format,
+        listing,
+        flat_symbols,
+        split_banks,
+        diagnostics_format,
+        sweep_threshold,
+        sweep,
+        inline_tables,
+        name_data,
+        text,
+        text_min_len,
+        charmap,
+        signatures,
+        template,
+        passes,
+        strict,
+        reached_from,
+        xrefs,
+        procs,
+        verify,
+        cycles,
+        expressions,
+        progress,
+        dry_run,
+        force,
+        shared_variables,
+        illegal_opcodes,
+        jam,
+        only_from,
+        cpu_variant,
+        tile_format,
+        unnamed,
+        export,
+        vector_table,
+        tag,
+        include_tag,
+        exclude_tag,
+        mapper_plugin,
+    } = args;
+    let file_name = display_name(&in_file);
+    if let Result::Err(err) = disassemble(DisassembleOptions {
+        in_file,
+        out_file: out,
+        json_out_file: json_out,
+        symbols_out_file: symbols_out,
+        fceux_nl_base: fceux_nl,
+        source_map_file: source_map,
+        call_graph_file: call_graph,
+        unreached_file: unreached,
+        access_map_file: access_map,
+        tiles_file: tiles_out,
+        linker_config_file: linker_config,
+        report_file: Option::None,
+        dialect,
+        format,
+        listing,
+        flat_symbols,
+        split_banks,
+        diagnostics_format,
+        mapper_plugins: mapper_plugin,
+        charmap_file: charmap,
+        signatures_file: signatures,
+        template_dir: template,
+        progress,
+        dry_run,
+        force,
+        args: std::env::args().collect(),
+        analysis: AnalysisOptions {
+            sweep_threshold,
+            sweep,
+            inline_tables,
+            name_data,
+            text,
+            text_min_len,
+            passes,
+            charmap: BTreeMap::new(),
+            signatures: Vec::new(),
+            strict,
+            reached_from_comments: reached_from,
+            xref_comments: xrefs,
+            procs,
+            verify,
+            cycle_comments: cycles,
+            expression_comments: expressions,
+            shared_variables,
+            illegal_opcodes,
+            jam_opcodes: jam,
+            only_from,
+            cpu_variant,
+            tile_format,
+            unnamed,
+            exports: export,
+            vector_tables: vector_table,
+            tags: tag,
+            include_tags: include_tag,
+            exclude_tags: exclude_tag,
+        },
+    }) {
+        match diagnostics_format {
+            DiagnosticsFormat::Human => eprintln!("Error disassembling: {}", err),
+            DiagnosticsFormat::Gcc => eprintln!("{}: error: {}", file_name, err),
+        }
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Cli, clap::Error> {
+        return Cli::try_parse_from(std::iter::once("sixtyfive").chain(args.iter().copied()));
+    }
+
+    /// The ROM the disassembly is run on, or `None` for another subcommand.
+    fn disassembled(cli: Cli) -> Option<Option<PathBuf>> {
+        return match cli
+            .command
+            .unwrap_or(Commands::Disassemble(cli.disassemble))
+        {
+            Commands::Disassemble(args) => Option::Some(args.in_file),
+            _ => Option::None,
+        };
+    }
+
+    #[test]
+    fn test_default_disassemble() {
+        let rom = Option::Some(Option::Some(PathBuf::from("rom.nes")));
+        assert_eq!(disassembled(parse(&["rom.nes"]).unwrap()), rom);
+        let cli = parse(&["--listing", "-o", "rom.s", "rom.nes"]).unwrap();
+        assert!(cli.command.is_none());
+        assert!(cli.disassemble.listing);
+        assert_eq!(cli.disassemble.out, Option::Some(PathBuf::from("rom.s")));
+        assert_eq!(disassembled(cli), rom);
+
+        // nothing to disassemble prints the help rather than reading stdin
+        assert!(parse(&[]).is_err());
+    }
+
+    #[test]
+    fn test_disassemble_aliases() {
+        for name in ["disassemble", "dis", "d"] {
+            let cli = parse(&[name, "--listing", "rom.nes"]).unwrap();
+            match &cli.command {
+                Option::Some(Commands::Disassemble(args)) => assert!(args.listing),
+                command => panic!("{} parsed as {:?}", name, command),
+            }
+            assert_eq!(
+                disassembled(cli),
+                Option::Some(Option::Some(PathBuf::from("rom.nes")))
+            );
+        }
+    }
+
+    #[test]
+    fn test_assemble_aliases() {
+        for name in ["assemble", "asm", "a"] {
+            match parse(&[name, "-C", "nes", "game.s"]).unwrap().command {
+                Option::Some(Commands::Assemble {
+                    in_file, config, ..
+                }) => {
+                    assert_eq!(in_file, Option::Some(PathBuf::from("game.s")));
+                    assert_eq!(config, Option::Some("nes".to_string()));
+                }
+                command => panic!("{} parsed as {:?}", name, command),
+            }
+        }
+    }
+
+    #[test]
+    fn test_default_conflicts_with_subcommands() {
+        // disassembly options belong to the default, not before a subcommand
+        assert!(parse(&["--listing", "info", "rom.nes"]).is_err());
+        assert!(parse(&["-o", "rom.s", "d", "rom.nes"]).is_err());
+        // a subcommand's name is the subcommand rather than a ROM
+        match parse(&["info", "rom.nes"]).unwrap().command {
+            Option::Some(Commands::Info { in_file }) => {
+                assert_eq!(in_file, Option::Some(PathBuf::from("rom.nes")))
+            }
+            command => panic!("info parsed as {:?}", command),
+        }
+        // and the default takes no subcommand after its arguments
+        assert!(parse(&["rom.nes", "info"]).is_err());
     }
 }
@@ -0,0 +1,214 @@
+//! Everything known about each 6502 opcode: mnemonic, addressing mode, length and
+//! timing. The decoder, the assembler and the analysis passes all read this table.
+//! Like [`crate::decode`] it doesn't need `std`.
+
+use crate::decode::AddressingMode::{self, *};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Opcode {
+    pub opcode: u8,
+    pub mnemonic: &'static str,
+    pub mode: AddressingMode,
+    /// cycles taken without the page crossing penalty, and for branches when not
+    /// taken
+    pub cycles: u8,
+    /// whether crossing a page takes a cycle more: indexing across a page for
+    /// reads, a taken branch to another page for branches, which take a cycle
+    /// more when taken anyway
+    pub page_cross_penalty: bool,
+    /// one of the undocumented NMOS opcodes, named as ca65 `6502X` does
+    pub illegal: bool,
+}
+
+impl Opcode {
+    /// length in bytes including the opcode
+    pub fn length(&self) -> usize {
+        return 1 + self.mode.operand_len();
+    }
+}
+
+const fn op(mnemonic: &'static str, mode: AddressingMode, opcode: u8, cycles: u8) -> Opcode {
+    return Opcode {
+        opcode,
+        mnemonic,
+        mode,
+        cycles,
+        page_cross_penalty: false,
+        illegal: false,
+    };
+}
+
+const fn op_px(mnemonic: &'static str, mode: AddressingMode, opcode: u8, cycles: u8) -> Opcode {
+    return Opcode {
+        page_cross_penalty: true,
+        ..op(mnemonic, mode, opcode, cycles)
+    };
+}
+
+const fn illegal(mnemonic: &'static str, mode: AddressingMode, opcode: u8, cycles: u8) -> Opcode {
+    return Opcode {
+        illegal: true,
+        ..op(mnemonic, mode, opcode, cycles)
+    };
+}
+
+const fn illegal_px(
+    mnemonic: &'static str,
+    mode: AddressingMode,
+    opcode: u8,
+    cycles: u8,
+) -> Opcode {
+    return Opcode {
+        illegal: true,
+        ..op_px(mnemonic, mode, opcode, cycles)
+    };
+}
+
+/// The documented opcodes followed by the undocumented ones, the JAMs aside. Where
+/// several undocumented opcodes do the same, the first listed is the one ca65
+/// assembles.
+// https://www.masswerk.at/6502/6502_instruction_set.html
+#[rustfmt::skip]
+pub const OPCODES: &[Opcode] = &[
+    op("adc", Immediate, 0x69, 2), op("adc", ZeroPage, 0x65, 3), op("adc", ZeroPageX, 0x75, 4),
+    op("adc", Absolute, 0x6d, 4), op_px("adc", AbsoluteX, 0x7d, 4), op_px("adc", AbsoluteY, 0x79, 4),
+    op("adc", IndirectX, 0x61, 6), op_px("adc", IndirectY, 0x71, 5),
+    op("and", Immediate, 0x29, 2), op("and", ZeroPage, 0x25, 3), op("and", ZeroPageX, 0x35, 4),
+    op("and", Absolute, 0x2d, 4), op_px("and", AbsoluteX, 0x3d, 4), op_px("and", AbsoluteY, 0x39, 4),
+    op("and", IndirectX, 0x21, 6), op_px("and", IndirectY, 0x31, 5),
+    op("asl", Accumulator, 0x0a, 2), op("asl", ZeroPage, 0x06, 5), op("asl", ZeroPageX, 0x16, 6),
+    op("asl", Absolute, 0x0e, 6), op("asl", AbsoluteX, 0x1e, 7), op_px("bcc", Relative, 0x90, 2),
+    op_px("bcs", Relative, 0xb0, 2), op_px("beq", Relative, 0xf0, 2), op_px("bmi", Relative, 0x30, 2),
+    op_px("bne", Relative, 0xd0, 2), op_px("bpl", Relative, 0x10, 2), op_px("bvc", Relative, 0x50, 2),
+    op_px("bvs", Relative, 0x70, 2), op("bit", ZeroPage, 0x24, 3), op("bit", Absolute, 0x2c, 4),
+    op("brk", Implied, 0x00, 7), op("clc", Implied, 0x18, 2), op("cld", Implied, 0xd8, 2),
+    op("cli", Implied, 0x58, 2), op("clv", Implied, 0xb8, 2),
+    op("cmp", Immediate, 0xc9, 2), op("cmp", ZeroPage, 0xc5, 3), op("cmp", ZeroPageX, 0xd5, 4),
+    op("cmp", Absolute, 0xcd, 4), op_px("cmp", AbsoluteX, 0xdd, 4), op_px("cmp", AbsoluteY, 0xd9, 4),
+    op("cmp", IndirectX, 0xc1, 6), op_px("cmp", IndirectY, 0xd1, 5),
+    op("cpx", Immediate, 0xe0, 2), op("cpx", ZeroPage, 0xe4, 3), op("cpx", Absolute, 0xec, 4),
+    op("cpy", Immediate, 0xc0, 2), op("cpy", ZeroPage, 0xc4, 3), op("cpy", Absolute, 0xcc, 4),
+    op("dec", ZeroPage, 0xc6, 5), op("dec", ZeroPageX, 0xd6, 6), op("dec", Absolute, 0xce, 6),
+    op("dec", AbsoluteX, 0xde, 7), op("dex", Implied, 0xca, 2), op("dey", Implied, 0x88, 2),
+    op("eor", Immediate, 0x49, 2), op("eor", ZeroPage, 0x45, 3), op("eor", ZeroPageX, 0x55, 4),
+    op("eor", Absolute, 0x4d, 4), op_px("eor", AbsoluteX, 0x5d, 4), op_px("eor", AbsoluteY, 0x59, 4),
+    op("eor", IndirectX, 0x41, 6), op_px("eor", IndirectY, 0x51, 5),
+    op("inc", ZeroPage, 0xe6, 5), op("inc", ZeroPageX, 0xf6, 6), op("inc", Absolute, 0xee, 6),
+    op("inc", AbsoluteX, 0xfe, 7), op("inx", Implied, 0xe8, 2), op("iny", Implied, 0xc8, 2),
+    op("jmp", Absolute, 0x4c, 3), op("jmp", Indirect, 0x6c, 5), op("jsr", Absolute, 0x20, 6),
+    op("lda", Immediate, 0xa9, 2), op("lda", ZeroPage, 0xa5, 3), op("lda", ZeroPageX, 0xb5, 4),
+    op("lda", Absolute, 0xad, 4), op_px("lda", AbsoluteX, 0xbd, 4), op_px("lda", AbsoluteY, 0xb9, 4),
+    op("lda", IndirectX, 0xa1, 6), op_px("lda", IndirectY, 0xb1, 5),
+    op("ldx", Immediate, 0xa2, 2), op("ldx", ZeroPage, 0xa6, 3), op("ldx", ZeroPageY, 0xb6, 4),
+    op("ldx", Absolute, 0xae, 4), op_px("ldx", AbsoluteY, 0xbe, 4),
+    op("ldy", Immediate, 0xa0, 2), op("ldy", ZeroPage, 0xa4, 3), op("ldy", ZeroPageX, 0xb4, 4),
+    op("ldy", Absolute, 0xac, 4), op_px("ldy", AbsoluteX, 0xbc, 4),
+    op("lsr", Accumulator, 0x4a, 2), op("lsr", ZeroPage, 0x46, 5), op("lsr", ZeroPageX, 0x56, 6),
+    op("lsr", Absolute, 0x4e, 6), op("lsr", AbsoluteX, 0x5e, 7), op("nop", Implied, 0xea, 2),
+    op("ora", Immediate, 0x09, 2), op("ora", ZeroPage, 0x05, 3), op("ora", ZeroPageX, 0x15, 4),
+    op("ora", Absolute, 0x0d, 4), op_px("ora", AbsoluteX, 0x1d, 4), op_px("ora", AbsoluteY, 0x19, 4),
+    op("ora", IndirectX, 0x01, 6), op_px("ora", IndirectY, 0x11, 5), op("pha", Implied, 0x48, 3),
+    op("php", Implied, 0x08, 3), op("pla", Implied, 0x68, 4), op("plp", Implied, 0x28, 4),
+    op("rol", Accumulator, 0x2a, 2), op("rol", ZeroPage, 0x26, 5), op("rol", ZeroPageX, 0x36, 6),
+    op("rol", Absolute, 0x2e, 6), op("rol", AbsoluteX, 0x3e, 7),
+    op("ror", Accumulator, 0x6a, 2), op("ror", ZeroPage, 0x66, 5), op("ror", ZeroPageX, 0x76, 6),
+    op("ror", Absolute, 0x6e, 6), op("ror", AbsoluteX, 0x7e, 7), op("rti", Implied, 0x40, 6),
+    op("rts", Implied, 0x60, 6),
+    op("sbc", Immediate, 0xe9, 2), op("sbc", ZeroPage, 0xe5, 3), op("sbc", ZeroPageX, 0xf5, 4),
+    op("sbc", Absolute, 0xed, 4), op_px("sbc", AbsoluteX, 0xfd, 4), op_px("sbc", AbsoluteY, 0xf9, 4),
+    op("sbc", IndirectX, 0xe1, 6), op_px("sbc", IndirectY, 0xf1, 5), op("sec", Implied, 0x38, 2),
+    op("sed", Implied, 0xf8, 2), op("sei", Implied, 0x78, 2),
+    op("sta", ZeroPage, 0x85, 3), op("sta", ZeroPageX, 0x95, 4), op("sta", Absolute, 0x8d, 4),
+    op("sta", AbsoluteX, 0x9d, 5), op("sta", AbsoluteY, 0x99, 5), op("sta", IndirectX, 0x81, 6),
+    op("sta", IndirectY, 0x91, 6),
+    op("stx", ZeroPage, 0x86, 3), op("stx", ZeroPageY, 0x96, 4), op("stx", Absolute, 0x8e, 4),
+    op("sty", ZeroPage, 0x84, 3), op("sty", ZeroPageX, 0x94, 4), op("sty", Absolute, 0x8c, 4),
+    op("tax", Implied, 0xaa, 2), op("tay", Implied, 0xa8, 2), op("tsx", Implied, 0xba, 2),
+    op("txa", Implied, 0x8a, 2), op("txs", Implied, 0x9a, 2), op("tya", Implied, 0x98, 2),
+    illegal("slo", ZeroPage, 0x07, 5), illegal("slo", ZeroPageX, 0x17, 6),
+    illegal("slo", IndirectX, 0x03, 8), illegal("slo", IndirectY, 0x13, 8),
+    illegal("slo", Absolute, 0x0f, 6), illegal("slo", AbsoluteX, 0x1f, 7),
+    illegal("slo", AbsoluteY, 0x1b, 7),
+    illegal("rla", ZeroPage, 0x27, 5), illegal("rla", ZeroPageX, 0x37, 6),
+    illegal("rla", IndirectX, 0x23, 8), illegal("rla", IndirectY, 0x33, 8),
+    illegal("rla", Absolute, 0x2f, 6), illegal("rla", AbsoluteX, 0x3f, 7),
+    illegal("rla", AbsoluteY, 0x3b, 7),
+    illegal("sre", ZeroPage, 0x47, 5), illegal("sre", ZeroPageX, 0x57, 6),
+    illegal("sre", IndirectX, 0x43, 8), illegal("sre", IndirectY, 0x53, 8),
+    illegal("sre", Absolute, 0x4f, 6), illegal("sre", AbsoluteX, 0x5f, 7),
+    illegal("sre", AbsoluteY, 0x5b, 7),
+    illegal("rra", ZeroPage, 0x67, 5), illegal("rra", ZeroPageX, 0x77, 6),
+    illegal("rra", IndirectX, 0x63, 8), illegal("rra", IndirectY, 0x73, 8),
+    illegal("rra", Absolute, 0x6f, 6), illegal("rra", AbsoluteX, 0x7f, 7),
+    illegal("rra", AbsoluteY, 0x7b, 7),
+    illegal("sax", ZeroPage, 0x87, 3), illegal("sax", ZeroPageY, 0x97, 4),
+    illegal("sax", IndirectX, 0x83, 6), illegal("sax", Absolute, 0x8f, 4),
+    illegal("lax", ZeroPage, 0xa7, 3), illegal("lax", ZeroPageY, 0xb7, 4),
+    illegal("lax", IndirectX, 0xa3, 6), illegal_px("lax", IndirectY, 0xb3, 5),
+    illegal("lax", Absolute, 0xaf, 4), illegal_px("lax", AbsoluteY, 0xbf, 4),
+    illegal("lax", Immediate, 0xab, 2),
+    illegal("dcp", ZeroPage, 0xc7, 5), illegal("dcp", ZeroPageX, 0xd7, 6),
+    illegal("dcp", IndirectX, 0xc3, 8), illegal("dcp", IndirectY, 0xd3, 8),
+    illegal("dcp", Absolute, 0xcf, 6), illegal("dcp", AbsoluteX, 0xdf, 7),
+    illegal("dcp", AbsoluteY, 0xdb, 7),
+    illegal("isc", ZeroPage, 0xe7, 5), illegal("isc", ZeroPageX, 0xf7, 6),
+    illegal("isc", IndirectX, 0xe3, 8), illegal("isc", IndirectY, 0xf3, 8),
+    illegal("isc", Absolute, 0xef, 6), illegal("isc", AbsoluteX, 0xff, 7),
+    illegal("isc", AbsoluteY, 0xfb, 7),
+    illegal("anc", Immediate, 0x0b, 2), illegal("anc", Immediate, 0x2b, 2),
+    illegal("alr", Immediate, 0x4b, 2), illegal("arr", Immediate, 0x6b, 2),
+    illegal("axs", Immediate, 0xcb, 2), illegal("ane", Immediate, 0x8b, 2),
+    illegal("sbc", Immediate, 0xeb, 2),
+    illegal("sha", AbsoluteY, 0x9f, 5), illegal("sha", IndirectY, 0x93, 6),
+    illegal("shx", AbsoluteY, 0x9e, 5), illegal("shy", AbsoluteX, 0x9c, 5),
+    illegal("tas", AbsoluteY, 0x9b, 5), illegal_px("las", AbsoluteY, 0xbb, 4),
+    illegal("nop", Implied, 0x1a, 2), illegal("nop", Implied, 0x3a, 2), illegal("nop", Implied, 0x5a, 2),
+    illegal("nop", Implied, 0x7a, 2), illegal("nop", Implied, 0xda, 2), illegal("nop", Implied, 0xfa, 2),
+    illegal("nop", Immediate, 0x80, 2), illegal("nop", Immediate, 0x82, 2),
+    illegal("nop", Immediate, 0x89, 2), illegal("nop", Immediate, 0xc2, 2),
+    illegal("nop", Immediate, 0xe2, 2), illegal("nop", ZeroPage, 0x04, 3),
+    illegal("nop", ZeroPage, 0x44, 3), illegal("nop", ZeroPage, 0x64, 3),
+    illegal("nop", ZeroPageX, 0x14, 4), illegal("nop", ZeroPageX, 0x34, 4),
+    illegal("nop", ZeroPageX, 0x54, 4), illegal("nop", ZeroPageX, 0x74, 4),
+    illegal("nop", ZeroPageX, 0xd4, 4), illegal("nop", ZeroPageX, 0xf4, 4),
+    illegal("nop", Absolute, 0x0c, 4), illegal_px("nop", AbsoluteX, 0x1c, 4),
+    illegal_px("nop", AbsoluteX, 0x3c, 4), illegal_px("nop", AbsoluteX, 0x5c, 4),
+    illegal_px("nop", AbsoluteX, 0x7c, 4), illegal_px("nop", AbsoluteX, 0xdc, 4),
+    illegal_px("nop", AbsoluteX, 0xfc, 4),
+];
+
+/// The table entry of `opcode`, `None` for the JAMs.
+pub fn lookup(opcode: u8) -> Option<&'static Opcode> {
+    return OPCODES.iter().find(|o| o.opcode == opcode);
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_opcodes() {
+        let lda = lookup(0xbd).unwrap();
+        assert_eq!(
+            (lda.mnemonic, lda.mode, lda.length()),
+            ("lda", AbsoluteX, 3)
+        );
+        assert_eq!((lda.cycles, lda.page_cross_penalty), (4, true));
+        let sta = lookup(0x9d).unwrap();
+        assert_eq!((sta.cycles, sta.page_cross_penalty), (5, false));
+        assert!(lookup(0xa7).unwrap().illegal);
+        assert!(lookup(0x02).is_none());
+
+        // one entry per opcode
+        let mut seen = [false; 256];
+        for o in OPCODES {
+            assert!(
+                !seen[o.opcode as usize],
+                "${:02x} is listed twice",
+                o.opcode
+            );
+            seen[o.opcode as usize] = true;
+        }
+        assert_eq!(OPCODES.len(), 256 - 12);
+    }
+}